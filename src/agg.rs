@@ -0,0 +1,274 @@
+//! Grouped aggregation of field values over query matches (`xmz agg`).
+//!
+//! Every group is kept, unless only the most frequent are asked for: those
+//! are then found with a space-saving sketch, and distinct values counted
+//! with HyperLogLog, so memory stays bounded however many values a path has.
+//! The groups of several files are collected separately and then merged.
+
+use crate::parser::{Continue, decode_entities};
+use crate::query::{Field, Query, for_each_match};
+use crate::sketch::{DISTINCT_ERROR, HyperLogLog, SpaceSaving};
+use crate::value::{ValueParser, format_timestamp};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Groups tracked for each one of `--top` asked for.
+const TRACKED_PER_TOP: usize = 10;
+
+/// What to aggregate and how to read the values.
+pub struct AggOptions<'a> {
+    pub query: &'a Query,
+    /// Field whose values are summarised; only counts are reported without one.
+    pub field: Option<&'a Field>,
+    pub group_by: Option<&'a Field>,
+    pub parser: &'a ValueParser,
+    /// Interpret field values as dates instead of numbers.
+    pub dates: bool,
+    /// Estimate how many distinct values the field has in each group.
+    pub distinct: bool,
+    /// Report only this many groups, the most frequent, in bounded memory.
+    pub top: Option<usize>,
+}
+
+/// The summary of one group.
+#[derive(Default)]
+pub struct Accumulator {
+    count: usize,
+    values: usize,
+    invalid: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    distinct: Option<HyperLogLog>,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: f64) {
+        if self.values == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.values += 1;
+        self.sum += value;
+    }
+
+    fn merge(&mut self, other: Accumulator) {
+        if other.values > 0 {
+            if self.values == 0 {
+                self.min = other.min;
+                self.max = other.max;
+            } else {
+                self.min = self.min.min(other.min);
+                self.max = self.max.max(other.max);
+            }
+            self.values += other.values;
+            self.sum += other.sum;
+        }
+        self.count += other.count;
+        self.invalid += other.invalid;
+        match (&mut self.distinct, other.distinct) {
+            (Some(distinct), Some(other)) => distinct.merge(&other),
+            (distinct, other) => *distinct = distinct.take().or(other),
+        }
+    }
+}
+
+/// The groups as they are collected: all of them, or the most frequent.
+pub enum Groups {
+    All(BTreeMap<Option<String>, Accumulator>),
+    Top(SpaceSaving<Option<String>, Accumulator>, HyperLogLog),
+}
+
+impl Groups {
+    /// Adds the groups of another file, collected with the same options.
+    pub fn merge(&mut self, other: Groups) {
+        match (self, other) {
+            (Groups::All(groups), Groups::All(other)) => {
+                for (key, acc) in other {
+                    groups.entry(key).or_default().merge(acc);
+                }
+            }
+            (Groups::Top(groups, keys), Groups::Top(other, other_keys)) => {
+                groups.merge(other, Accumulator::merge);
+                keys.merge(&other_keys);
+            }
+            _ => unreachable!("groups collected with different options"),
+        }
+    }
+
+    /// The group of records with `key`, the group-by value, which is None
+    /// for records without one.
+    fn get(&mut self, key: Option<String>) -> &mut Accumulator {
+        match self {
+            Groups::All(groups) => groups.entry(key).or_default(),
+            Groups::Top(groups, keys) => {
+                keys.insert(&key);
+                groups.insert(&key)
+            }
+        }
+    }
+}
+
+/// Streams `xml`, aggregates every match and writes a table to `out`.
+pub fn write_aggregate<W: Write>(out: &mut W, xml: &str, options: &AggOptions) -> io::Result<()> {
+    write_groups(out, &aggregate(xml, options), options)
+}
+
+/// Streams `xml` and aggregates every match into groups.
+pub fn aggregate(xml: &str, options: &AggOptions) -> Groups {
+    let mut groups = match options.top {
+        Some(top) => Groups::Top(SpaceSaving::new(top * TRACKED_PER_TOP), HyperLogLog::default()),
+        None => Groups::All(BTreeMap::new()),
+    };
+
+    for_each_match(xml, options.query, |range| {
+        let element = &xml[range];
+        let key = options.group_by.and_then(|g| g.first(element)).map(|value| decode_entities(value.trim()).into_owned());
+        let acc = groups.get(key);
+        acc.count += 1;
+        if let Some(field) = options.field {
+            for raw in field.values(element) {
+                if options.distinct {
+                    acc.distinct.get_or_insert_with(HyperLogLog::default).insert(&decode_entities(raw.trim()));
+                }
+                let parsed = if options.dates {
+                    options.parser.date(raw).map(|secs| secs as f64)
+                } else {
+                    options.parser.number(raw)
+                };
+                match parsed {
+                    Some(value) => acc.add(value),
+                    None => acc.invalid += 1,
+                }
+            }
+        }
+        Continue(())
+    });
+    groups
+}
+
+/// Writes the groups as a table, with notes on the accuracy of estimates.
+pub fn write_groups<W: Write>(out: &mut W, groups: &Groups, options: &AggOptions) -> io::Result<()> {
+    // Groups with their counts, and what those may be over by when only the
+    // top are kept.
+    let rows_in: Vec<(Option<&str>, &Accumulator, u64, Option<u64>)> = match groups {
+        Groups::All(groups) => groups.iter().map(|(key, acc)| (key.as_deref(), acc, acc.count as u64, None)).collect(),
+        Groups::Top(sketch, _) => sketch
+            .top(options.top.unwrap_or(0))
+            .into_iter()
+            .map(|counter| (counter.key.as_deref(), &counter.payload, counter.count, Some(counter.error)))
+            .collect(),
+    };
+    let total = match groups {
+        Groups::All(_) => None,
+        Groups::Top(_, keys) => Some(keys.estimate()),
+    };
+    let format_value = |v: f64| {
+        if options.dates {
+            format_timestamp(v as i64)
+        } else {
+            format!("{}", (v * 1e6).round() / 1e6)
+        }
+    };
+    let mut header = vec!["group", "count"];
+    if total.is_some() {
+        header.push("error");
+    }
+    if options.field.is_some() {
+        header.extend(["values", "invalid", "min", "max"]);
+        if !options.dates {
+            header.extend(["sum", "mean"]);
+        }
+        if options.distinct {
+            header.push("distinct");
+        }
+    }
+    if options.group_by.is_none() {
+        header.remove(0);
+    }
+    let mut rows = vec![header.iter().map(|h| h.to_string()).collect::<Vec<_>>()];
+    for &(key, acc, count, error) in &rows_in {
+        let mut row = Vec::new();
+        if options.group_by.is_some() {
+            row.push(key.unwrap_or("(missing)").to_string());
+        }
+        row.push(count.to_string());
+        if let Some(error) = error {
+            row.push(format!("±{}", error));
+        }
+        if options.field.is_some() {
+            row.push(acc.values.to_string());
+            row.push(acc.invalid.to_string());
+            let has = acc.values > 0;
+            row.push(if has { format_value(acc.min) } else { "-".into() });
+            row.push(if has { format_value(acc.max) } else { "-".into() });
+            if !options.dates {
+                row.push(format_value(acc.sum));
+                row.push(if has { format_value(acc.sum / acc.values as f64) } else { "-".into() });
+            }
+            if options.distinct {
+                row.push(format!("~{}", acc.distinct.as_ref().map_or(0, HyperLogLog::estimate)));
+            }
+        }
+        rows.push(row);
+    }
+    if rows_in.is_empty() {
+        writeln!(out, "No matches.")?;
+        return Ok(());
+    }
+    write_table(out, &rows)?;
+    if let Some(total) = total {
+        writeln!(
+            out,
+            "top {} of ~{} groups by count; a count may be over by its error, and the other columns cover only the values seen while the group was tracked",
+            rows_in.len(),
+            total
+        )?;
+    }
+    if options.distinct {
+        writeln!(out, "distinct counts are estimates within about {:.1}%", DISTINCT_ERROR * 100.0)?;
+    }
+    Ok(())
+}
+
+/// Writes rows as left-aligned, space-separated columns.
+pub fn write_table<W: Write>(out: &mut W, rows: &[Vec<String>]) -> io::Result<()> {
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|c| rows.iter().filter_map(|r| r.get(c)).map(|s| s.chars().count()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let mut line = String::new();
+        for (c, cell) in row.iter().enumerate() {
+            if c > 0 {
+                line.push_str("  ");
+            }
+            line.push_str(cell);
+            if c + 1 < row.len() {
+                line.extend(std::iter::repeat_n(' ', widths[c] - cell.chars().count()));
+            }
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Appends one CSV record, quoting fields that hold commas, quotes or line breaks.
+pub(crate) fn push_csv_line<'s>(csv: &mut String, fields: impl Iterator<Item = &'s str>) {
+    for (n, field) in fields.enumerate() {
+        if n > 0 {
+            csv.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push('\n');
+}
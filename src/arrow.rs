@@ -0,0 +1,373 @@
+//! Arrow IPC streaming output (`xmz table --to arrow`): record batches in
+//! the Arrow columnar format that DuckDB, pandas (through pyarrow) and
+//! polars read from a pipe or socket without an intermediate file.
+//!
+//! A stream is a schema message, then a record batch message per
+//! `BATCH_ROWS` rows, then an end marker. Messages are flatbuffers, written
+//! here by a small builder rather than a generated one, since only four
+//! tables of the Arrow format are needed.
+//!
+//! Column types are inferred from the first batch with `schema::Kind`, the
+//! inference `xmz schema` uses: integers become Int64, other numbers
+//! Float64, `yyyy-mm-dd` dates Date32 and everything else Utf8. Empty
+//! values are nulls, and so are later values that do not fit the type
+//! inferred for their column.
+
+use crate::schema::Kind;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// Rows per record batch, and rows the column types are inferred from.
+pub const BATCH_ROWS: usize = 8192;
+
+/// Arrow's `MetadataVersion.V5`.
+const METADATA_VERSION: i16 = 4;
+/// Members of the `MessageHeader` union.
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+/// Members of the `Type` union.
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_UTF8: u8 = 5;
+const TYPE_DATE: u8 = 8;
+
+/// A flatbuffers object, laid out after whatever refers to it.
+enum Object {
+    /// Fields by their slot in the table's schema.
+    Table(Vec<(u16, Field)>),
+    String(String),
+    Tables(Vec<Object>),
+    /// A vector of structs of two longs (`FieldNode`, `Buffer`).
+    Pairs(Vec<[i64; 2]>),
+}
+
+enum Field {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Object(Object),
+}
+
+impl Field {
+    fn size(&self) -> usize {
+        match self {
+            Field::Bool(_) | Field::U8(_) => 1,
+            Field::I16(_) => 2,
+            Field::I32(_) | Field::Object(_) => 4,
+            Field::I64(_) => 8,
+        }
+    }
+}
+
+fn pad_to(buf: &mut Vec<u8>, align: usize) {
+    buf.resize(buf.len().next_multiple_of(align), 0);
+}
+
+/// Lays out `object` at the end of `buf`, queueing the objects it refers
+/// to, and returns where it starts.
+fn write_object(buf: &mut Vec<u8>, object: Object, queue: &mut VecDeque<(usize, Object)>) -> usize {
+    match object {
+        Object::Table(mut fields) => {
+            let slots = fields.iter().map(|(slot, _)| *slot as usize + 1).max().unwrap_or(0);
+            pad_to(buf, 2);
+            let vtable = buf.len();
+            buf.resize(vtable + 4 + 2 * slots, 0);
+            // Tables start 8-aligned, with the widest fields first.
+            pad_to(buf, 8);
+            let table = buf.len();
+            buf.extend_from_slice(&((table - vtable) as i32).to_le_bytes());
+            fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.size()));
+            for (slot, field) in fields {
+                pad_to(buf, field.size());
+                let at = buf.len();
+                let entry = vtable + 4 + 2 * slot as usize;
+                buf[entry..entry + 2].copy_from_slice(&((at - table) as u16).to_le_bytes());
+                match field {
+                    Field::Bool(value) => buf.push(value as u8),
+                    Field::U8(value) => buf.push(value),
+                    Field::I16(value) => buf.extend_from_slice(&value.to_le_bytes()),
+                    Field::I32(value) => buf.extend_from_slice(&value.to_le_bytes()),
+                    Field::I64(value) => buf.extend_from_slice(&value.to_le_bytes()),
+                    Field::Object(object) => {
+                        buf.extend_from_slice(&[0; 4]);
+                        queue.push_back((at, object));
+                    }
+                }
+            }
+            let size = buf.len() - table;
+            buf[vtable..vtable + 2].copy_from_slice(&((4 + 2 * slots) as u16).to_le_bytes());
+            buf[vtable + 2..vtable + 4].copy_from_slice(&(size as u16).to_le_bytes());
+            table
+        }
+        Object::String(string) => {
+            pad_to(buf, 4);
+            let at = buf.len();
+            buf.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            buf.extend_from_slice(string.as_bytes());
+            buf.push(0);
+            at
+        }
+        Object::Tables(tables) => {
+            pad_to(buf, 4);
+            let at = buf.len();
+            buf.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+            for table in tables {
+                queue.push_back((buf.len(), table));
+                buf.extend_from_slice(&[0; 4]);
+            }
+            at
+        }
+        Object::Pairs(pairs) => {
+            // The length comes just before the 8-aligned structs.
+            pad_to(buf, 8);
+            buf.extend_from_slice(&[0; 4]);
+            let at = buf.len();
+            buf.extend_from_slice(&(pairs.len() as u32).to_le_bytes());
+            for [a, b] in pairs {
+                buf.extend_from_slice(&a.to_le_bytes());
+                buf.extend_from_slice(&b.to_le_bytes());
+            }
+            at
+        }
+    }
+}
+
+/// Serializes a flatbuffer with `root` as its root table.
+fn finish(root: Object) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    let mut queue = VecDeque::from([(0, root)]);
+    while let Some((reference, object)) = queue.pop_front() {
+        let at = write_object(&mut buf, object, &mut queue);
+        buf[reference..reference + 4].copy_from_slice(&((at - reference) as u32).to_le_bytes());
+    }
+    buf
+}
+
+/// The Arrow type of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Int64,
+    Float64,
+    /// Days since 1970-01-01.
+    Date32,
+    Utf8,
+}
+
+impl DataType {
+    /// The type covering all non-empty `values`, Utf8 if there are none.
+    pub fn infer<'v>(values: impl Iterator<Item = &'v str>) -> Self {
+        let mut kind: Option<Kind> = None;
+        for value in values.filter(|value| !value.is_empty()) {
+            let mut this = Kind::of(value);
+            if this == Kind::Int && value.parse::<i64>().is_err() {
+                this = Kind::Float;
+            }
+            kind = Some(kind.map_or(this, |kind| kind.join(this)));
+        }
+        match kind {
+            Some(Kind::Int) => DataType::Int64,
+            Some(Kind::Float) => DataType::Float64,
+            Some(Kind::Date) => DataType::Date32,
+            _ => DataType::Utf8,
+        }
+    }
+
+    fn field_type(self) -> (u8, Object) {
+        match self {
+            DataType::Int64 => (TYPE_INT, Object::Table(vec![(0, Field::I32(64)), (1, Field::Bool(true))])),
+            DataType::Float64 => (TYPE_FLOATING_POINT, Object::Table(vec![(0, Field::I16(2))])),
+            DataType::Date32 => (TYPE_DATE, Object::Table(vec![(0, Field::I16(0))])),
+            DataType::Utf8 => (TYPE_UTF8, Object::Table(Vec::new())),
+        }
+    }
+}
+
+/// Days from 1970-01-01 to the date `yyyy-mm-dd`.
+fn days(date: &str) -> Option<i32> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Counted from March, so that the leap day ends the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    i32::try_from(era * 146_097 + day_of_era - 719_468).ok()
+}
+
+/// The buffers of one column of a batch, as they go into the body.
+struct Column {
+    nulls: usize,
+    validity: Vec<u8>,
+    /// Offsets into `data` for Utf8, nothing for the fixed-width types.
+    offsets: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Column {
+    fn encode(ty: DataType, values: &[String], mismatched: &mut u64) -> Self {
+        let mut column = Column {
+            nulls: 0,
+            validity: vec![0; values.len().div_ceil(8)],
+            offsets: Vec::new(),
+            data: Vec::new(),
+        };
+        if ty == DataType::Utf8 {
+            column.offsets.extend_from_slice(&0i32.to_le_bytes());
+        }
+        for (i, value) in values.iter().enumerate() {
+            let valid = match ty {
+                DataType::Int64 => value.parse::<i64>().ok().map(|n| column.data.extend_from_slice(&n.to_le_bytes())),
+                DataType::Float64 => value.parse::<f64>().ok().map(|n| column.data.extend_from_slice(&n.to_le_bytes())),
+                DataType::Date32 => days(value).map(|n| column.data.extend_from_slice(&n.to_le_bytes())),
+                DataType::Utf8 => (!value.is_empty()).then(|| column.data.extend_from_slice(value.as_bytes())),
+            };
+            match valid {
+                Some(()) => column.validity[i / 8] |= 1 << (i % 8),
+                None => {
+                    column.nulls += 1;
+                    if !value.is_empty() {
+                        *mismatched += 1;
+                    }
+                    // Null slots of fixed-width columns still take their width.
+                    match ty {
+                        DataType::Int64 | DataType::Float64 => column.data.extend_from_slice(&[0; 8]),
+                        DataType::Date32 => column.data.extend_from_slice(&[0; 4]),
+                        DataType::Utf8 => {}
+                    }
+                }
+            }
+            if ty == DataType::Utf8 {
+                column.offsets.extend_from_slice(&(column.data.len() as i32).to_le_bytes());
+            }
+        }
+        column
+    }
+}
+
+/// Writes rows of strings as an Arrow IPC stream.
+pub struct ArrowWriter<W: Write> {
+    out: W,
+    names: Vec<String>,
+    types: Option<Vec<DataType>>,
+    /// The rows of the batch being collected, column by column.
+    columns: Vec<Vec<String>>,
+    rows: usize,
+    mismatched: u64,
+}
+
+impl<W: Write> ArrowWriter<W> {
+    pub fn new(out: W, names: Vec<String>) -> Self {
+        let columns = vec![Vec::new(); names.len()];
+        ArrowWriter {
+            out,
+            names,
+            types: None,
+            columns,
+            rows: 0,
+            mismatched: 0,
+        }
+    }
+
+    /// Adds a row, with a value per column; empty values are nulls.
+    pub fn push(&mut self, row: Vec<String>) -> io::Result<()> {
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            column.push(value);
+        }
+        self.rows += 1;
+        if self.rows == BATCH_ROWS {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last batch and the end of the stream, returning how many
+    /// values were written as nulls because they did not fit their column.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if self.rows > 0 || self.types.is_none() {
+            self.flush_batch()?;
+        }
+        self.out.write_all(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0])?;
+        Ok(self.mismatched)
+    }
+
+    /// A message: its flatbuffer, padded to 8 bytes and length-prefixed
+    /// after the continuation marker, then its body.
+    fn write_message(&mut self, header_type: u8, header: Object, body: &[u8]) -> io::Result<()> {
+        let message = Object::Table(vec![
+            (0, Field::I16(METADATA_VERSION)),
+            (1, Field::U8(header_type)),
+            (2, Field::Object(header)),
+            (3, Field::I64(body.len() as i64)),
+        ]);
+        let mut metadata = finish(message);
+        pad_to(&mut metadata, 8);
+        self.out.write_all(&[0xff, 0xff, 0xff, 0xff])?;
+        self.out.write_all(&(metadata.len() as i32).to_le_bytes())?;
+        self.out.write_all(&metadata)?;
+        self.out.write_all(body)
+    }
+
+    fn write_schema(&mut self, types: &[DataType]) -> io::Result<()> {
+        let fields = self
+            .names
+            .iter()
+            .zip(types)
+            .map(|(name, ty)| {
+                let (type_type, ty) = ty.field_type();
+                Object::Table(vec![
+                    (0, Field::Object(Object::String(name.clone()))),
+                    (1, Field::Bool(true)),
+                    (2, Field::U8(type_type)),
+                    (3, Field::Object(ty)),
+                    (5, Field::Object(Object::Tables(Vec::new()))),
+                ])
+            })
+            .collect();
+        let schema = Object::Table(vec![(0, Field::I16(0)), (1, Field::Object(Object::Tables(fields)))]);
+        self.write_message(HEADER_SCHEMA, schema, &[])
+    }
+
+    fn flush_batch(&mut self) -> io::Result<()> {
+        let types = match &self.types {
+            Some(types) => types.clone(),
+            None => {
+                let types: Vec<DataType> = self.columns.iter().map(|values| DataType::infer(values.iter().map(String::as_str))).collect();
+                self.write_schema(&types)?;
+                self.types = Some(types.clone());
+                types
+            }
+        };
+        let mut nodes = Vec::new();
+        let mut buffers = Vec::new();
+        let mut body = Vec::new();
+        for (ty, values) in types.into_iter().zip(&mut self.columns) {
+            let column = Column::encode(ty, values, &mut self.mismatched);
+            values.clear();
+            nodes.push([self.rows as i64, column.nulls as i64]);
+            let mut parts = vec![column.validity];
+            if ty == DataType::Utf8 {
+                parts.push(column.offsets);
+            }
+            parts.push(column.data);
+            for part in parts {
+                buffers.push([body.len() as i64, part.len() as i64]);
+                body.extend_from_slice(&part);
+                pad_to(&mut body, 8);
+            }
+        }
+        let batch = Object::Table(vec![
+            (0, Field::I64(self.rows as i64)),
+            (1, Field::Object(Object::Pairs(nodes))),
+            (2, Field::Object(Object::Pairs(buffers))),
+        ]);
+        self.write_message(HEADER_RECORD_BATCH, batch, &body)?;
+        self.rows = 0;
+        Ok(())
+    }
+}
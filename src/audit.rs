@@ -0,0 +1,270 @@
+//! Security audit (`xmz audit`): finds what a parser or viewer could be made
+//! to fetch or expand while reading the document — external DTDs and
+//! entities (the XXE vectors), XInclude targets, stylesheets, and remote
+//! URIs in attributes. Findings are diagnostics, like those of `xmz check`.
+
+use crate::check::{Diagnostic, Severity, locate};
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans};
+use crate::stats::{is_uri_attribute, uri_host};
+use memchr::memmem;
+
+/// Namespaces of XInclude: the recommendation, and the 2003 draft some tools still emit.
+const XINCLUDE_NAMESPACES: [&str; 2] = ["http://www.w3.org/2001/XInclude", "http://www.w3.org/2003/XInclude"];
+
+struct Auditor {
+    diagnostics: Vec<Diagnostic>,
+    path: String,
+}
+
+impl Auditor {
+    fn report(&mut self, severity: Severity, code: &'static str, offset: usize, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            code,
+            offset,
+            line: 0,
+            column: 0,
+            path: self.path.clone(),
+            message,
+        });
+    }
+
+    /// Reports the external DTD and the entity declarations of the DOCTYPE at `start`.
+    fn audit_doctype(&mut self, xml: &str, start: usize) {
+        let mut cursor = Cursor { xml, pos: start + "<!DOCTYPE".len() };
+        cursor.skip_space();
+        let name = cursor.name();
+        cursor.skip_space();
+        let at = cursor.pos;
+        if let Some(system) = cursor.external_id() {
+            self.report(Severity::Warning, "external-dtd", at, format!("<!DOCTYPE {}> loads an external DTD from {}", name, describe(system)));
+        }
+        cursor.skip_space();
+        if !cursor.eat("[") {
+            return;
+        }
+        loop {
+            cursor.skip_space();
+            let at = cursor.pos;
+            if cursor.pos >= xml.len() || cursor.eat("]") {
+                return;
+            } else if cursor.eat("<!--") {
+                cursor.skip_past("-->");
+            } else if cursor.eat("<?") {
+                cursor.skip_past("?>");
+            } else if cursor.eat("<!ENTITY") {
+                self.audit_entity(&mut cursor, at);
+            } else if cursor.eat("%") {
+                let name = cursor.name();
+                cursor.eat(";");
+                self.report(Severity::Warning, "parameter-entity-reference", at, format!("%{}; is expanded into the DTD", name));
+            } else if cursor.eat("<!") {
+                cursor.skip_declaration();
+            } else {
+                cursor.pos += xml[cursor.pos..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+
+    /// Reports an `<!ENTITY` declaration whose start tag the cursor has just read.
+    fn audit_entity(&mut self, cursor: &mut Cursor, at: usize) {
+        cursor.skip_space();
+        let parameter = cursor.eat("%");
+        cursor.skip_space();
+        let name = cursor.name();
+        let reference = if parameter { format!("%{};", name) } else { format!("&{};", name) };
+        cursor.skip_space();
+        match cursor.external_id() {
+            Some(system) => {
+                cursor.skip_space();
+                let unparsed = cursor.eat("NDATA");
+                cursor.skip_declaration();
+                if unparsed {
+                    self.report(Severity::Warning, "unparsed-entity", at, format!("{} names external data at {}", reference, describe(system)));
+                } else {
+                    let code = if parameter { "external-parameter-entity" } else { "external-entity" };
+                    self.report(Severity::Error, code, at, format!("{} is replaced by the contents of {}", reference, describe(system)));
+                }
+            }
+            None => {
+                let value = cursor.quoted().unwrap_or("");
+                cursor.skip_declaration();
+                if value.contains('&') || value.contains('%') {
+                    self.report(Severity::Warning, "nested-entity", at, format!("{} refers to other entities; nested expansion can blow up", reference));
+                }
+            }
+        }
+    }
+
+    /// Reports an XInclude element starting at `tag`, and remote URIs among
+    /// its `attrs`, which start at `base`.
+    fn audit_element(&mut self, name: &str, attrs: &str, tag: usize, base: usize, xinclude_prefixes: &mut Vec<String>) {
+        let pairs = attribute_pairs(attrs);
+        for &(attr, value) in &pairs {
+            let prefix = if attr == "xmlns" { Some("") } else { attr.strip_prefix("xmlns:") };
+            if let Some(prefix) = prefix
+                && XINCLUDE_NAMESPACES.contains(&value)
+                && !xinclude_prefixes.iter().any(|p| p == prefix)
+            {
+                xinclude_prefixes.push(prefix.to_string());
+            }
+        }
+        let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+        if local == "include" && xinclude_prefixes.iter().any(|p| p == prefix) {
+            let get = |key: &str| pairs.iter().find(|(attr, _)| *attr == key).map(|&(_, value)| decode_entities(value));
+            let href = get("href");
+            let how = if get("parse").as_deref() == Some("text") { "as text" } else { "as XML" };
+            let message = match href.as_deref() {
+                Some("") | None => format!("<{}> includes this document's own content {}", name, how),
+                Some(href) => format!("<{}> includes {} {}", name, describe(href), how),
+            };
+            self.report(Severity::Warning, "xinclude", tag, message);
+        }
+        for &(attr, raw) in &pairs {
+            let local = attr.rsplit(':').next().unwrap_or(attr);
+            let value = decode_entities(raw);
+            let offset = raw.as_ptr() as usize - attrs.as_ptr() as usize + base;
+            if local == "schemaLocation" || local == "noNamespaceSchemaLocation" {
+                // `schemaLocation` pairs namespace names with locations; only the locations are fetched.
+                let step = if local == "schemaLocation" { 2 } else { 1 };
+                for location in value.split_whitespace().skip(step - 1).step_by(step) {
+                    self.report(Severity::Info, "schema-location", offset, format!("@{} points validators at {}", attr, describe(location)));
+                }
+            } else if is_uri_attribute(attr, &value)
+                && let Some(host) = uri_host(&value)
+            {
+                self.report(Severity::Info, "remote-uri", offset, format!("@{} refers to {} on {}", attr, value.trim(), host.to_ascii_lowercase()));
+            }
+        }
+    }
+
+    /// Reports `<?xml-stylesheet?>` instructions, which viewers fetch and run.
+    fn audit_stylesheets(&mut self, xml: &str) {
+        for start in memmem::find_iter(xml.as_bytes(), b"<?xml-stylesheet") {
+            let end = memmem::find(&xml.as_bytes()[start..], b"?>").map_or(xml.len(), |rel| start + rel);
+            let body = &xml[start + "<?xml-stylesheet".len()..end];
+            let href = attribute_pairs(body).into_iter().find(|(attr, _)| *attr == "href").map(|(_, value)| decode_entities(value));
+            let target = href.as_deref().map_or("an unnamed stylesheet".to_string(), describe);
+            self.report(Severity::Info, "stylesheet", start, format!("<?xml-stylesheet?> applies {}", target));
+        }
+    }
+}
+
+/// A URI with a note on where it leads, e.g. `'http://x/y' (remote: x)`.
+fn describe(uri: &str) -> String {
+    match uri_host(uri) {
+        Some(host) => format!("'{}' (remote: {})", uri, host.to_ascii_lowercase()),
+        None if uri.starts_with("file:") || uri.starts_with('/') => format!("'{}' (local file)", uri),
+        None => format!("'{}'", uri),
+    }
+}
+
+/// A position in the DOCTYPE, which the tokenizer skips over.
+struct Cursor<'a> {
+    xml: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn rest(&self) -> &'a str {
+        self.xml.get(self.pos..).unwrap_or("")
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str) {
+        self.pos = self.rest().find(end).map_or(self.xml.len(), |rel| self.pos + rel + end.len());
+    }
+
+    fn name(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || matches!(c, '>' | '[' | ';' | '"' | '\'')).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn quoted(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let len = rest[1..].find(quote)?;
+        self.pos += len + 2;
+        Some(&rest[1..1 + len])
+    }
+
+    /// Reads `SYSTEM "uri"` or `PUBLIC "id" "uri"`, returning the URI.
+    fn external_id(&mut self) -> Option<&'a str> {
+        if self.eat("SYSTEM") {
+            self.skip_space();
+        } else if self.eat("PUBLIC") {
+            self.skip_space();
+            self.quoted()?;
+            self.skip_space();
+        } else {
+            return None;
+        }
+        self.quoted()
+    }
+
+    /// Moves past the `>` ending the current declaration, skipping quoted strings.
+    fn skip_declaration(&mut self) {
+        while self.pos < self.xml.len() {
+            if self.quoted().is_some() {
+                continue;
+            }
+            let c = self.rest().chars().next().unwrap_or('>');
+            self.pos += c.len_utf8();
+            if c == '>' {
+                return;
+            }
+        }
+    }
+}
+
+/// Audits `xml` for external references. Diagnostics are in document order;
+/// external entities are errors, other ways of loading outside content are
+/// warnings, and remote URIs that are only referred to are infos.
+pub fn audit(xml: &str) -> Vec<Diagnostic> {
+    let mut auditor = Auditor {
+        diagnostics: Vec::new(),
+        path: String::new(),
+    };
+    if let Some(start) = memmem::find(xml.as_bytes(), b"<!DOCTYPE") {
+        auditor.audit_doctype(xml, start);
+    }
+    auditor.audit_stylesheets(xml);
+    let mut open: Vec<usize> = Vec::new();
+    let mut xinclude_prefixes = Vec::new();
+    stream_xml_spans(xml, |token, span| {
+        match token {
+            Token::StartTag(name, attrs) => {
+                open.push(auditor.path.len());
+                auditor.path.push('/');
+                auditor.path.push_str(name);
+                let base = attrs.as_ptr() as usize - xml.as_ptr() as usize;
+                auditor.audit_element(name, attrs, span.start, base, &mut xinclude_prefixes);
+            }
+            Token::EndTag(_) => {
+                if let Some(len) = open.pop() {
+                    auditor.path.truncate(len);
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    let mut diagnostics = auditor.diagnostics;
+    diagnostics.sort_by_key(|d| d.offset);
+    locate(xml, &mut diagnostics);
+    diagnostics
+}
@@ -0,0 +1,88 @@
+//! Results of expensive commands kept between runs, so repeating one on an
+//! unchanged file prints the same output at once.
+//!
+//! An entry is a command's standard output, stored under `xmz/results` in
+//! the user's cache directory (see `config::cache_path`) and named by a
+//! SHA-256 of the command line and the fingerprints of the files it read:
+//! their length, modification time and a hash of their first and last
+//! 64 KiB. A changed file has a new fingerprint, so its old entries are no longer found; they
+//! are removed with the others once there are over `MAX_ENTRIES`, the least
+//! recently written first.
+
+use crate::config::cache_path;
+use crate::hash::{Sha256, hex};
+use crate::sandbox::{self, Capability};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Entries kept before the oldest are removed.
+pub const MAX_ENTRIES: usize = 256;
+
+/// Output over this many bytes is not cached.
+pub const MAX_OUTPUT: usize = 16 << 20;
+
+/// Bytes hashed at each end of the file.
+const SAMPLE: u64 = 64 * 1024;
+
+/// The key of the result of `command` run on `files`, which names its entry.
+pub fn key(files: &[impl AsRef<Path>], command: &str) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(command.as_bytes());
+    for file in files {
+        fingerprint(&mut hasher, file.as_ref())?;
+    }
+    Ok(hex(&hasher.finish()))
+}
+
+fn fingerprint(hasher: &mut Sha256, file: &Path) -> io::Result<()> {
+    let mut input = File::open(file)?;
+    let metadata = input.metadata()?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    hasher.update(&[0]);
+    hasher.update(std::fs::canonicalize(file)?.to_string_lossy().as_bytes());
+    hasher.update(&metadata.len().to_le_bytes());
+    hasher.update(&modified.as_nanos().to_le_bytes());
+    let mut sample = Vec::with_capacity(SAMPLE as usize);
+    (&mut input).take(SAMPLE).read_to_end(&mut sample)?;
+    hasher.update(&sample);
+    if metadata.len() > SAMPLE {
+        input.seek(SeekFrom::Start(metadata.len().saturating_sub(SAMPLE).max(SAMPLE)))?;
+        sample.clear();
+        input.take(SAMPLE).read_to_end(&mut sample)?;
+        hasher.update(&sample);
+    }
+    Ok(())
+}
+
+/// The output cached under `key`, if any.
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    std::fs::read(cache_path("results")?.join(key)).ok()
+}
+
+/// Caches `output` under `key`, then removes the oldest entries over
+/// `MAX_ENTRIES`.
+pub fn put(key: &str, output: &[u8]) -> io::Result<()> {
+    sandbox::check(Capability::WriteFiles)?;
+    let dir = cache_path("results").ok_or_else(|| io::Error::other("no home directory to keep a cache in"))?;
+    std::fs::create_dir_all(&dir)?;
+    // Written aside and renamed, so a reader never sees half an entry.
+    let partial = dir.join(format!("{}.{}.tmp", key, std::process::id()));
+    std::fs::write(&partial, output)?;
+    std::fs::rename(&partial, dir.join(key))?;
+
+    let mut entries: Vec<_> = std::fs::read_dir(&dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.metadata().ok()?.modified().ok()?, entry.path()))
+        })
+        .collect();
+    if entries.len() > MAX_ENTRIES {
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - MAX_ENTRIES] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,370 @@
+//! Document checks (`xmz check`) that report problems as structured
+//! diagnostics, for the command line, JSON consumers and the TUI panel alike.
+
+use crate::json::Json;
+use crate::parser::{Continue, Token, stream_xml_spans};
+use memchr::{memchr, memmem};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Characters XML 1.0 does not allow anywhere, even as character references.
+pub fn is_forbidden(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/// One problem found in a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier of the rule, e.g. `mismatched-end-tag`.
+    pub code: &'static str,
+    /// Byte offset in the document, and the same position as 1-based line and column.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    /// Element path at the problem, e.g. `/catalog/book`.
+    pub path: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("severity".to_string(), Json::String(self.severity.as_str().to_string())),
+            ("code".to_string(), Json::String(self.code.to_string())),
+            ("offset".to_string(), Json::Number(self.offset as f64)),
+            ("line".to_string(), Json::Number(self.line as f64)),
+            ("column".to_string(), Json::Number(self.column as f64)),
+            ("path".to_string(), Json::String(self.path.clone())),
+            ("message".to_string(), Json::String(self.message.clone())),
+        ])
+    }
+}
+
+/// Collects diagnostics during a pass, tracking the open elements for paths.
+struct Checker<'a> {
+    diagnostics: Vec<Diagnostic>,
+    open: Vec<(&'a str, usize)>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, code: &'static str, offset: usize, message: String) {
+        let mut path = String::new();
+        for (name, _) in &self.open {
+            path.push('/');
+            path.push_str(name);
+        }
+        self.diagnostics.push(Diagnostic {
+            severity,
+            code,
+            offset,
+            line: 0,
+            column: 0,
+            path,
+            message,
+        });
+    }
+
+    /// Reports `&` that does not start a known entity or character reference.
+    /// With a DOCTYPE, other named entities may be declared and are allowed.
+    fn check_entities(&mut self, text: &str, base: usize, has_doctype: bool) {
+        let bytes = text.as_bytes();
+        let mut from = 0;
+        while let Some(rel) = memchr(b'&', &bytes[from..]) {
+            let at = from + rel;
+            from = at + 1;
+            let rest = &text[at + 1..];
+            let name_len = rest
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '#' | '_' | '-' | '.' | ':')))
+                .unwrap_or(rest.len());
+            if !rest[name_len..].starts_with(';') || name_len == 0 {
+                self.report(Severity::Error, "bare-ampersand", base + at, "'&' must be escaped as &amp;".to_string());
+                continue;
+            }
+            let name = &rest[..name_len];
+            let valid = match name {
+                "lt" | "gt" | "amp" | "quot" | "apos" => true,
+                _ if name.starts_with('#') => {
+                    let code = match name.strip_prefix("#x") {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => name[1..].parse().ok(),
+                    };
+                    match code.and_then(char::from_u32) {
+                        None => self.report(Severity::Error, "invalid-char-ref", base + at, format!("&{}; is not a valid character", name)),
+                        Some(c) if is_forbidden(c) => {
+                            self.report(Severity::Error, "invalid-char-ref", base + at, format!("&{}; refers to U+{:04X}, which is not allowed in XML 1.0", name, c as u32));
+                        }
+                        Some(_) => {}
+                    }
+                    true
+                }
+                _ => has_doctype,
+            };
+            if !valid {
+                self.report(Severity::Error, "undefined-entity", base + at, format!("entity &{}; is not defined", name));
+            }
+        }
+    }
+
+    /// Reports characters XML 1.0 does not allow in `xml[range]`, a run of
+    /// them at once, and U+FFFD, which usually marks undecodable input.
+    fn check_chars(&mut self, xml: &str, range: Range<usize>) {
+        let bytes = xml.as_bytes();
+        let mut pos = range.start;
+        while pos < range.end {
+            let b = bytes[pos];
+            let forbidden = |at: usize| match bytes[at] {
+                b'\t' | b'\n' | b'\r' => 0,
+                0..0x20 => 1,
+                0xef if bytes.get(at + 1) == Some(&0xbf) && matches!(bytes.get(at + 2), Some(0xbe | 0xbf)) => 3,
+                _ => 0,
+            };
+            if forbidden(pos) > 0 {
+                let start = pos;
+                let mut count = 0;
+                while pos < range.end && forbidden(pos) > 0 {
+                    pos += forbidden(pos);
+                    count += 1;
+                }
+                let first = xml[start..].chars().next().map_or(0, |c| c as u32);
+                let message = match count {
+                    1 => format!("U+{:04X} is not allowed in XML 1.0", first),
+                    n => format!("{} characters not allowed in XML 1.0, starting with U+{:04X}", n, first),
+                };
+                self.report(Severity::Error, "invalid-char", start, message);
+                continue;
+            }
+            if b == 0xef && bytes[pos..range.end].starts_with("\u{fffd}".as_bytes()) {
+                self.report(Severity::Warning, "replacement-char", pos, "U+FFFD usually stands for bytes that could not be decoded".to_string());
+                pos += 3;
+                while bytes[pos..range.end].starts_with("\u{fffd}".as_bytes()) {
+                    pos += 3;
+                }
+                continue;
+            }
+            pos += 1;
+        }
+    }
+
+    /// Checks `name="value"` syntax, duplicates and the values of a start tag's attributes.
+    fn check_attributes(&mut self, raw: &str, base: usize, has_doctype: bool) {
+        let bytes = raw.as_bytes();
+        let mut seen: Vec<&str> = Vec::new();
+        let mut pos = 0;
+        loop {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= bytes.len() {
+                return;
+            }
+            let name_start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() && bytes[pos] != b'=' {
+                pos += 1;
+            }
+            let name = &raw[name_start..pos];
+            if name.is_empty() || name.starts_with(['"', '\'']) {
+                self.report(Severity::Error, "attribute-syntax", base + name_start, "expected an attribute name".to_string());
+                return;
+            }
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if bytes.get(pos) != Some(&b'=') {
+                self.report(Severity::Error, "attribute-syntax", base + name_start, format!("attribute '{}' has no value", name));
+                return;
+            }
+            pos += 1;
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let Some(&quote @ (b'"' | b'\'')) = bytes.get(pos) else {
+                self.report(Severity::Error, "attribute-syntax", base + pos, format!("value of attribute '{}' is not quoted", name));
+                return;
+            };
+            let Some(len) = memchr(quote, &bytes[pos + 1..]) else {
+                self.report(Severity::Error, "attribute-syntax", base + pos, format!("value of attribute '{}' is not terminated", name));
+                return;
+            };
+            let value_start = pos + 1;
+            let value = &raw[value_start..value_start + len];
+            if let Some(lt) = value.find('<') {
+                self.report(Severity::Error, "lt-in-attribute", base + value_start + lt, format!("'<' in the value of attribute '{}'", name));
+            }
+            self.check_entities(value, base + value_start, has_doctype);
+            if seen.contains(&name) {
+                self.report(Severity::Error, "duplicate-attribute", base + name_start, format!("attribute '{}' appears more than once", name));
+            } else {
+                seen.push(name);
+            }
+            pos = value_start + len + 1;
+        }
+    }
+}
+
+/// Checks that `xml` is well-formed: properly nested and closed elements, a
+/// single root, valid attribute syntax and escaped `&` and `<`. Diagnostics
+/// are in document order.
+pub fn well_formedness(xml: &str) -> Vec<Diagnostic> {
+    let has_doctype = memmem::find(xml.as_bytes(), b"<!DOCTYPE").is_some();
+    let mut checker = Checker {
+        diagnostics: Vec::new(),
+        open: Vec::new(),
+    };
+    let mut root_closed = false;
+    let mut consumed = 0;
+    stream_xml_spans(xml, |token, span| {
+        // Markup the tokenizer skips (comments, declarations) is checked as part of the gap.
+        checker.check_chars(xml, consumed..span.end);
+        consumed = span.end;
+        match token {
+            Token::StartTag(name, attrs) => {
+                if checker.open.is_empty() && root_closed {
+                    checker.report(Severity::Error, "multiple-roots", span.start, format!("<{}> follows the root element", name));
+                }
+                if name.is_empty() {
+                    checker.report(Severity::Error, "tag-syntax", span.start, "missing element name".to_string());
+                }
+                let attrs_offset = attrs.as_ptr() as usize - xml.as_ptr() as usize;
+                checker.open.push((name, span.start));
+                checker.check_attributes(attrs, attrs_offset, has_doctype);
+            }
+            Token::EndTag(name) => {
+                let name = name.trim_end();
+                match checker.open.iter().rposition(|(open, _)| *open == name) {
+                    Some(depth) => {
+                        while checker.open.len() > depth + 1 {
+                            let (inner, start) = checker.open[checker.open.len() - 1];
+                            checker.report(Severity::Error, "mismatched-end-tag", span.start, format!("</{}> closes <{}> opened at byte {}", name, inner, start));
+                            checker.open.pop();
+                        }
+                        checker.open.pop();
+                    }
+                    None => checker.report(Severity::Error, "unexpected-end-tag", span.start, format!("</{}> has no matching start tag", name)),
+                }
+                root_closed |= checker.open.is_empty();
+            }
+            Token::Text(text) => {
+                if checker.open.is_empty() {
+                    checker.report(Severity::Error, "text-outside-root", span.start, "text outside the root element".to_string());
+                } else {
+                    checker.check_entities(text, span.start, has_doctype);
+                    if let Some(gt) = text.find("]]>") {
+                        checker.report(Severity::Warning, "cdata-end-in-text", span.start + gt, "']]>' is not allowed in text".to_string());
+                    }
+                }
+            }
+            Token::CData(_) if checker.open.is_empty() => {
+                checker.report(Severity::Error, "text-outside-root", span.start, "text outside the root element".to_string());
+            }
+            Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    checker.check_chars(xml, consumed..xml.len());
+    // The tokenizer stops quietly at markup cut off by the end of the document.
+    let rest = &xml[consumed..];
+    if let Some(lt) = rest.find('<')
+        && !rest[lt..].contains('>')
+    {
+        let code = if rest[lt..].starts_with("<!--") { "unterminated-comment" } else { "unterminated-tag" };
+        checker.report(Severity::Error, code, consumed + lt, "markup is cut off by the end of the document".to_string());
+    }
+    while let Some((name, start)) = checker.open.pop() {
+        checker.report(Severity::Error, "unclosed-element", start, format!("<{}> is never closed", name));
+        // Report with the element itself on the path.
+        if let Some(last) = checker.diagnostics.last_mut() {
+            last.path.push('/');
+            last.path.push_str(name);
+        }
+    }
+    if !root_closed && checker.diagnostics.is_empty() {
+        checker.report(Severity::Error, "no-root", 0, "the document has no root element".to_string());
+    }
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| d.offset);
+    locate(xml, &mut diagnostics);
+    diagnostics
+}
+
+/// Fills in the line and column of diagnostics sorted by offset.
+pub(crate) fn locate(xml: &str, diagnostics: &mut [Diagnostic]) {
+    let bytes = xml.as_bytes();
+    let (mut line, mut line_start, mut scanned) = (1, 0, 0);
+    for diagnostic in diagnostics {
+        let offset = diagnostic.offset.min(bytes.len());
+        for newline in memchr::memchr_iter(b'\n', &bytes[scanned..offset]) {
+            line += 1;
+            line_start = scanned + newline + 1;
+        }
+        scanned = scanned.max(offset);
+        diagnostic.line = line;
+        diagnostic.column = xml.get(line_start..offset).map_or(1, |s| s.chars().count() + 1);
+    }
+}
+
+/// 1-based line and column of byte `offset`.
+pub(crate) fn line_column(xml: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(xml.len());
+    let before = &xml.as_bytes()[..offset];
+    let line_start = memchr::memrchr(b'\n', before).map_or(0, |newline| newline + 1);
+    let column = xml.get(line_start..offset).map_or(1, |s| s.chars().count() + 1);
+    (memchr::memchr_iter(b'\n', before).count() + 1, column)
+}
+
+/// Byte offset where 1-based `line` starts, if the document has that many lines.
+pub(crate) fn line_start(xml: &str, line: usize) -> Option<usize> {
+    match line {
+        0 => None,
+        1 => Some(0),
+        n => memchr::memchr_iter(b'\n', xml.as_bytes()).nth(n - 2).map(|newline| newline + 1),
+    }
+}
+
+/// Prints one `file:line:column: severity[code]: message (path)` line.
+pub(crate) fn write_diagnostic<W: Write>(out: &mut W, file: &str, d: &Diagnostic) -> io::Result<()> {
+    write!(out, "{}:{}:{}: {}[{}]: {}", file, d.line, d.column, d.severity.as_str(), d.code, d.message)?;
+    if !d.path.is_empty() {
+        write!(out, " (at {})", d.path)?;
+    }
+    writeln!(out)
+}
+
+/// Prints `file:line:column: severity[code]: message (path)` lines and a summary.
+pub fn write_diagnostics<W: Write>(out: &mut W, file: &str, diagnostics: &[Diagnostic]) -> io::Result<()> {
+    for d in diagnostics {
+        write_diagnostic(out, file, d)?;
+    }
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.iter().filter(|d| d.severity == Severity::Warning).count();
+    if diagnostics.is_empty() {
+        writeln!(out, "{}: no problems found", file)
+    } else {
+        writeln!(out, "{}: {} error(s), {} warning(s)", file, errors, warnings)
+    }
+}
+
+/// The JSON report: `{"file": ..., "diagnostics": [...]}`.
+pub fn diagnostics_to_json(file: &str, diagnostics: &[Diagnostic]) -> Json {
+    Json::Object(vec![
+        ("file".to_string(), Json::String(file.to_string())),
+        ("diagnostics".to_string(), Json::Array(diagnostics.iter().map(Diagnostic::to_json).collect())),
+    ])
+}
@@ -0,0 +1,382 @@
+//! Configured checks over many files (`xmz check --config checks.toml`), for
+//! pre-commit hooks and CI jobs in repositories that keep XML assets.
+//!
+//! The config is a small subset of TOML: a `[section]` per check, each with
+//! `key = value` lines, where values are strings, integers, booleans or
+//! arrays of strings, and `#` starts a comment. Well-formedness is always
+//! checked, and a file that is not well-formed is not checked further; the
+//! other checks run when their section is present:
+//!
+//! ```toml
+//! [size]
+//! max-bytes = 5000000
+//! max-depth = 32
+//! max-elements = 100000
+//!
+//! [schema]
+//! baseline = "schema/catalog.json"   # from xmz schema --save
+//! fail-on = "drift"                  # or "new-elements"
+//!
+//! [xsd]
+//! schema = "schema/catalog.xsd"
+//!
+//! [audit]
+//! fail-on = "warning"                # or "error", the default
+//!
+//! [lint]
+//! rules = ["xml-declaration", "final-newline", "no-tabs"]
+//! severity = "error"                 # or "warning", the default
+//! ```
+//!
+//! Paths in the config are relative to the config file.
+
+use crate::audit::audit;
+use crate::check::{Diagnostic, Severity, locate, well_formedness};
+use crate::fanout::{glob, is_glob};
+use crate::json::Json;
+use crate::parser::{Continue, Token, stream_xml_spans};
+use crate::schema::{Drift, Schema};
+use crate::xsd::Xsd;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Lint rules, by the name used in the config.
+const RULES: [&str; 6] = ["xml-declaration", "no-doctype", "no-comments", "no-processing-instructions", "final-newline", "no-tabs"];
+
+#[derive(Debug, Default)]
+struct Size {
+    max_bytes: Option<u64>,
+    max_depth: Option<u64>,
+    max_elements: Option<u64>,
+}
+
+/// The checks of a config file, ready to run.
+pub struct Checks {
+    size: Option<Size>,
+    baseline: Option<Schema>,
+    /// Only new elements count as schema drift.
+    new_elements_only: bool,
+    xsd: Option<Xsd>,
+    /// The lowest severity of audit findings that fails a file.
+    audit: Option<Severity>,
+    lint: Vec<&'static str>,
+    lint_severity: Severity,
+}
+
+impl Default for Checks {
+    /// Well-formedness only.
+    fn default() -> Self {
+        Checks {
+            size: None,
+            baseline: None,
+            new_elements_only: false,
+            xsd: None,
+            audit: None,
+            lint: Vec::new(),
+            lint_severity: Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Text(String),
+    Integer(u64),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+pub(crate) fn parse_value(source: &str) -> Result<Value, String> {
+    let quoted = |source: &str| source.strip_prefix('"').and_then(|s| s.strip_suffix('"')).map(str::to_string);
+    if let Some(text) = quoted(source) {
+        return Ok(Value::Text(text));
+    }
+    if let Some(items) = source.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| quoted(item).ok_or_else(|| format!("expected a quoted string, got {}", item)))
+            .collect::<Result<_, _>>()
+            .map(Value::List);
+    }
+    match source {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => source.replace('_', "").parse().map(Value::Integer).map_err(|_| format!("cannot read value {}", source)),
+    }
+}
+
+/// Strips a `#` comment that is not inside a string.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+impl Checks {
+    /// Reads the config file at `path`, with the schemas it names.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Checks::parse(&text, dir).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+    }
+
+    /// Parses a config, resolving the paths in it against `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut checks = Checks::default();
+        let mut section = String::new();
+        for (n, line) in text.lines().enumerate() {
+            let at = |err: String| format!("line {}: {}", n + 1, err);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                section = name.trim().to_string();
+                match section.as_str() {
+                    "well-formed" => {}
+                    "size" => checks.size = Some(Size::default()),
+                    "schema" | "xsd" | "lint" => {}
+                    "audit" => checks.audit = Some(Severity::Error),
+                    _ => return Err(at(format!("unknown check [{}]", section))),
+                }
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(at("expected key = value".to_string()));
+            };
+            let (key, value) = (key.trim(), parse_value(value.trim()).map_err(at)?);
+            let path = |value: &Value| match value {
+                Value::Text(path) => Ok(dir.join(path)),
+                _ => Err(at(format!("{} must be a path in quotes", key))),
+            };
+            match (section.as_str(), key, &value) {
+                ("size", "max-bytes", Value::Integer(n)) => checks.size.get_or_insert_default().max_bytes = Some(*n),
+                ("size", "max-depth", Value::Integer(n)) => checks.size.get_or_insert_default().max_depth = Some(*n),
+                ("size", "max-elements", Value::Integer(n)) => checks.size.get_or_insert_default().max_elements = Some(*n),
+                ("schema", "baseline", _) => {
+                    let file = path(&value)?;
+                    let text = std::fs::read_to_string(&file).map_err(|err| at(format!("{}: {}", file.display(), err)))?;
+                    let baseline = Schema::from_json(&text).map_err(|err| at(format!("{}: {}", file.display(), err)))?;
+                    checks.baseline = Some(baseline);
+                }
+                ("schema", "fail-on", Value::Text(what)) => {
+                    checks.new_elements_only = match what.as_str() {
+                        "drift" => false,
+                        "new-elements" => true,
+                        _ => return Err(at(format!("unknown fail-on '{}' (expected drift or new-elements)", what))),
+                    };
+                }
+                ("xsd", "schema", _) => {
+                    let file = path(&value)?;
+                    checks.xsd = Some(Xsd::load(&file).map_err(at)?);
+                }
+                ("audit", "fail-on", Value::Text(severity)) | ("lint", "severity", Value::Text(severity)) => {
+                    let severity = match severity.as_str() {
+                        "error" => Severity::Error,
+                        "warning" => Severity::Warning,
+                        _ => return Err(at(format!("unknown severity '{}' (expected error or warning)", severity))),
+                    };
+                    if section == "audit" {
+                        checks.audit = Some(severity);
+                    } else {
+                        checks.lint_severity = severity;
+                    }
+                }
+                ("lint", "rules", Value::List(rules)) => {
+                    for rule in rules {
+                        let rule = RULES.iter().find(|known| *known == rule).ok_or_else(|| at(format!("unknown lint rule '{}' (expected one of {})", rule, RULES.join(", "))))?;
+                        checks.lint.push(rule);
+                    }
+                }
+                _ => return Err(at(format!("unexpected {} in [{}]", key, section))),
+            }
+        }
+        Ok(checks)
+    }
+
+    /// Runs the checks on one document, most severe problems first.
+    pub fn run(&self, xml: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = well_formedness(xml);
+        if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            return diagnostics;
+        }
+        if let Some(size) = &self.size {
+            diagnostics.extend(size.check(xml));
+        }
+        if let Some(baseline) = &self.baseline {
+            diagnostics.extend(drift(baseline, &Schema::infer(xml), self.new_elements_only));
+        }
+        if let Some(xsd) = &self.xsd {
+            diagnostics.extend(xsd.validate(xml));
+        }
+        if let Some(fail_on) = self.audit {
+            diagnostics.extend(audit(xml).into_iter().map(|mut d| {
+                if d.severity <= fail_on {
+                    d.severity = Severity::Error;
+                }
+                d
+            }));
+        }
+        let mut lints = lint(xml, &self.lint, self.lint_severity);
+        locate(xml, &mut lints);
+        diagnostics.extend(lints);
+        diagnostics.sort_by_key(|d| (d.severity, d.offset));
+        diagnostics
+    }
+}
+
+/// A problem with the file as a whole, or with the elements at `path`.
+fn whole_file(code: &'static str, path: &str, message: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        code,
+        offset: 0,
+        line: 1,
+        column: 1,
+        path: path.to_string(),
+        message,
+    }
+}
+
+impl Size {
+    fn check(&self, xml: &str) -> Vec<Diagnostic> {
+        let (mut depth, mut max_depth, mut elements) = (0u64, 0u64, 0u64);
+        stream_xml_spans(xml, |token, _| {
+            match token {
+                Token::StartTag(..) => {
+                    depth += 1;
+                    elements += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                Token::EndTag(_) => depth = depth.saturating_sub(1),
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            Continue(())
+        });
+        let mut diagnostics = Vec::new();
+        let mut over = |limit: Option<u64>, value: u64, what: &str| {
+            if let Some(limit) = limit
+                && value > limit
+            {
+                diagnostics.push(whole_file("size-budget", "", format!("{} {} over the budget of {}", value, what, limit)));
+            }
+        };
+        over(self.max_bytes, xml.len() as u64, "bytes");
+        over(self.max_depth, max_depth, "levels of nesting");
+        over(self.max_elements, elements, "elements");
+        diagnostics
+    }
+}
+
+fn drift(baseline: &Schema, current: &Schema, new_only: bool) -> Vec<Diagnostic> {
+    let drift = Drift::between(baseline, current);
+    let mut diagnostics: Vec<Diagnostic> = drift.new_elements.iter().map(|path| whole_file("schema-drift", path, "element not in the baseline".to_string())).collect();
+    if !new_only {
+        diagnostics.extend(drift.removed_elements.iter().map(|path| whole_file("schema-drift", path, "baseline element missing".to_string())));
+        diagnostics.extend(drift.new_attributes.iter().map(|(path, attr)| whole_file("schema-drift", path, format!("attribute {} not in the baseline", attr))));
+        diagnostics.extend(drift.removed_attributes.iter().map(|(path, attr)| whole_file("schema-drift", path, format!("baseline attribute {} missing", attr))));
+    }
+    diagnostics
+}
+
+/// Findings of the lint `rules`, with offsets but no lines yet.
+fn lint(xml: &str, rules: &[&'static str], severity: Severity) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |code: &'static str, offset: usize, message: &str| {
+        diagnostics.push(Diagnostic {
+            severity,
+            code,
+            offset,
+            line: 0,
+            column: 0,
+            path: String::new(),
+            message: message.to_string(),
+        });
+    };
+    for &rule in rules {
+        match rule {
+            "xml-declaration" if !xml.trim_start_matches('\u{feff}').starts_with("<?xml") => report(rule, 0, "the document does not start with an XML declaration"),
+            "final-newline" if !xml.is_empty() && !xml.ends_with('\n') => report(rule, xml.len(), "the file does not end with a line break"),
+            "no-tabs" => {
+                if let Some(at) = memchr::memchr(b'\t', xml.as_bytes()) {
+                    report(rule, at, "tab character; indent with spaces");
+                }
+            }
+            "no-doctype" | "no-comments" | "no-processing-instructions" => {
+                let (open, what) = match rule {
+                    "no-doctype" => ("<!DOCTYPE", "DOCTYPE declaration"),
+                    "no-comments" => ("<!--", "comment"),
+                    _ => ("<?", "processing instruction"),
+                };
+                let mut from = 0;
+                while let Some(at) = xml[from..].find(open).map(|at| from + at) {
+                    from = at + open.len();
+                    // The XML declaration looks like a processing instruction.
+                    if open == "<?" && xml[at..].starts_with("<?xml") && xml[at + 5..].starts_with(|c: char| c.is_whitespace() || c == '?') {
+                        continue;
+                    }
+                    report(rule, at, what);
+                }
+            }
+            _ => {}
+        }
+    }
+    diagnostics
+}
+
+/// The files to check for each of `paths`: a file as it is, for a
+/// directory the `.xml` files below it, in name order, and for a glob
+/// pattern such as `logs/2024-*.xml` what it matches, as either.
+pub fn collect_files(paths: &[String]) -> io::Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<_>>()?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                walk(&path, files)?;
+            } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xml")) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        let matched = if is_glob(path) && !Path::new(path).exists() {
+            let matched = glob(path)?;
+            if matched.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("no files match {}", path)));
+            }
+            matched
+        } else {
+            vec![PathBuf::from(path)]
+        };
+        for path in matched {
+            if path.is_dir() {
+                walk(&path, &mut files)?;
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The JSON report of several files: `{"files": [{"file", "diagnostics"}],
+/// "errors": n, "warnings": n}`.
+pub fn report_to_json(results: &[(String, Vec<Diagnostic>)]) -> Json {
+    let count = |severity: Severity| results.iter().flat_map(|(_, diagnostics)| diagnostics).filter(|d| d.severity == severity).count() as f64;
+    Json::Object(vec![
+        ("files".to_string(), Json::Array(results.iter().map(|(file, diagnostics)| crate::check::diagnostics_to_json(file, diagnostics)).collect())),
+        ("errors".to_string(), Json::Number(count(Severity::Error))),
+        ("warnings".to_string(), Json::Number(count(Severity::Warning))),
+    ])
+}
@@ -0,0 +1,101 @@
+//! Settings read from the user's config file, for options worth setting once
+//! instead of on every command line.
+//!
+//! The file is `$XMZ_CONFIG` if set, else `xmz/config` under
+//! `$XDG_CONFIG_HOME` (default `~/.config`), or under `%APPDATA%` on
+//! Windows. Each line is `key = value`; blank lines and lines starting with
+//! `#` are ignored, and values may be quoted.
+
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Built-in color theme of the navigator.
+    pub theme: Option<String>,
+    /// Reopen files where the last session left them, as with `--resume`.
+    pub resume: bool,
+}
+
+/// Where the config file is looked for.
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("XMZ_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("xmz").join("config"))
+}
+
+/// Where state kept between runs, such as the last session, is stored:
+/// `xmz/NAME` under `$XDG_STATE_HOME` (default `~/.local/state`), or under
+/// `%LOCALAPPDATA%` on Windows.
+pub fn state_path(name: &str) -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(windows) => PathBuf::from(std::env::var_os("LOCALAPPDATA")?),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("state"),
+    };
+    Some(base.join("xmz").join(name))
+}
+
+/// Where data that can be recomputed, such as cached results, is stored:
+/// `xmz/NAME` under `$XDG_CACHE_HOME` (default `~/.cache`), or under
+/// `%LOCALAPPDATA%` on Windows.
+pub fn cache_path(name: &str) -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(windows) => PathBuf::from(std::env::var_os("LOCALAPPDATA")?),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("xmz").join(name))
+}
+
+impl Config {
+    /// Reads the config file; a missing file gives the defaults.
+    pub fn load() -> io::Result<Config> {
+        let Some(path) = path() else {
+            return Ok(Config::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}", path.display(), err))),
+        };
+        Config::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value", n + 1));
+            };
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value)
+                .to_string();
+            match key.trim() {
+                "theme" => config.theme = Some(value),
+                "resume" => {
+                    config.resume = match value.as_str() {
+                        "true" | "yes" | "on" => true,
+                        "false" | "no" | "off" => false,
+                        _ => return Err(format!("line {}: resume must be true or false, got '{}'", n + 1, value)),
+                    }
+                }
+                other => return Err(format!("line {}: unknown setting '{}'", n + 1, other)),
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,633 @@
+//! `convert` and `split`: exporting the records a query selects as NDJSON or
+//! XML, to one output or to numbered chunk files.
+//!
+//! Exports to files write a checkpoint next to the output every so many
+//! records: the scan position after the last completed record and how much
+//! output was complete at that point. Run again with `--resume`, an interrupted
+//! export truncates its output back to the checkpoint and continues from
+//! there instead of starting over. The checkpoint is removed once the export
+//! finishes.
+
+use crate::index::fingerprint;
+use crate::interrupt;
+use crate::json::Json;
+use crate::parser::{Break, Continue, Token, decode_entities, extract_attributes, flat_text, stream_xml, stream_xml_spans, token_text};
+use crate::progress::Progress;
+use crate::query::{Predicate, Query, ScanPosition, for_each_match_from};
+use crate::sandbox::{self, Capability};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per record and line.
+    Ndjson,
+    /// The records' own markup, wrapped in the document's root element.
+    Xml,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            "xml" => Ok(Format::Xml),
+            _ => Err(format!("unknown format '{}' (expected ndjson or xml)", name)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Ndjson => "ndjson",
+            Format::Xml => "xml",
+        }
+    }
+}
+
+/// What to export and how often to checkpoint.
+pub struct ExportOptions<'a> {
+    pub query: &'a Query,
+    /// The query as given, stored in checkpoints so a resume cannot mix queries.
+    pub query_source: &'a str,
+    /// Only records this predicate holds for are exported (`--where`).
+    pub filter: Option<&'a Predicate>,
+    /// The predicate as given, stored in checkpoints along with the query.
+    pub filter_source: Option<&'a str>,
+    pub format: Format,
+    /// Records between checkpoints; 0 disables them.
+    pub checkpoint_every: u64,
+    /// Continue from the checkpoint of an earlier, interrupted run.
+    pub resume: bool,
+}
+
+impl ExportOptions<'_> {
+    /// The records exported, as one query: the query with the `--where`
+    /// predicate added to its last step.
+    fn selection(&self) -> String {
+        match self.filter_source {
+            Some(filter) => format!("{}[{}]", self.query_source, filter),
+            None => self.query_source.to_string(),
+        }
+    }
+}
+
+type Members = Vec<(String, Json)>;
+
+/// Converts one record to JSON: attributes become `@name` members, child
+/// elements are keyed by tag (an array when repeated) and text goes to
+/// `#text`, or becomes the value itself for elements with nothing else.
+/// For mixed content, such as a paragraph with inline markup, `#text` holds
+/// all of the element's text in order, that of its children included.
+pub fn record_to_json(element: &str) -> Json {
+    // Name, members, text and start of each open element, and whether it
+    // has child elements.
+    let mut stack: Vec<(&str, Members, String, usize, bool)> = Vec::new();
+    let mut record = None;
+    stream_xml_spans(element, |token, span| {
+        match token {
+            Token::StartTag(name, _) => {
+                let members = extract_attributes(element, span.start)
+                    .into_iter()
+                    .map(|(key, value)| (format!("@{}", key), Json::String(decode_entities(value).into_owned())))
+                    .collect();
+                if let Some((_, _, _, _, has_children)) = stack.last_mut() {
+                    *has_children = true;
+                }
+                stack.push((name, members, String::new(), span.start, false));
+            }
+            Token::Text(_) | Token::CData(_) => {
+                let text = token_text(&token).unwrap_or_default();
+                let text = text.trim();
+                if let Some((_, _, content, _, _)) = stack.last_mut()
+                    && !text.is_empty()
+                {
+                    if !content.is_empty() {
+                        content.push(' ');
+                    }
+                    content.push_str(text);
+                }
+            }
+            Token::EndTag(_) => {
+                let Some((name, mut members, mut text, start, has_children)) = stack.pop() else {
+                    return Continue(());
+                };
+                if has_children && !text.is_empty() {
+                    text = flat_text(&element[start..span.end], usize::MAX);
+                }
+                let Some((_, parent, _, _, _)) = stack.last_mut() else {
+                    if !text.is_empty() {
+                        members.push(("#text".to_string(), Json::String(text)));
+                    }
+                    record = Some(Json::Object(members));
+                    return Break(());
+                };
+                let value = if members.is_empty() {
+                    Json::String(text)
+                } else {
+                    if !text.is_empty() {
+                        members.push(("#text".to_string(), Json::String(text)));
+                    }
+                    Json::Object(members)
+                };
+                insert_member(parent, name, value);
+            }
+        }
+        Continue(())
+    });
+    record.unwrap_or(Json::Object(Vec::new()))
+}
+
+/// Adds `value` under `key`, turning a repeated key into an array.
+fn insert_member(members: &mut Members, key: &str, value: Json) {
+    match members.iter_mut().find(|(k, _)| k == key) {
+        Some((_, Json::Array(items))) => items.push(value),
+        Some((_, existing)) => {
+            let first = std::mem::replace(existing, Json::Null);
+            *existing = Json::Array(vec![first, value]);
+        }
+        None => members.push((key.to_string(), value)),
+    }
+}
+
+/// Progress of an export, saved so that it can be resumed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    pub fingerprint: u64,
+    pub query: String,
+    pub format: String,
+    pub position: ScanPosition,
+    pub records: u64,
+    /// Number of the output file being written (split only, from 1).
+    pub file: u64,
+    /// Records and bytes already complete in the current output file.
+    pub file_records: u64,
+    pub file_bytes: u64,
+}
+
+impl Checkpoint {
+    /// Where the checkpoint for `output` is kept.
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut name = output.as_os_str().to_owned();
+        name.push(".xmzckpt");
+        PathBuf::from(name)
+    }
+
+    pub fn to_json(&self) -> Json {
+        let number = |n: u64| Json::Number(n as f64);
+        Json::Object(vec![
+            ("fingerprint".to_string(), Json::String(format!("{:016x}", self.fingerprint))),
+            ("query".to_string(), Json::String(self.query.clone())),
+            ("format".to_string(), Json::String(self.format.clone())),
+            ("offset".to_string(), number(self.position.offset as u64)),
+            ("open".to_string(), Json::Array(self.position.open.iter().map(|&o| number(o as u64)).collect())),
+            ("records".to_string(), number(self.records)),
+            ("file".to_string(), number(self.file)),
+            ("file_records".to_string(), number(self.file_records)),
+            ("file_bytes".to_string(), number(self.file_bytes)),
+        ])
+    }
+
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        let json = Json::parse(src)?;
+        let string = |key: &str| json.get(key).and_then(Json::as_str).ok_or_else(|| format!("missing '{}'", key));
+        let number = |key: &str| {
+            json.get(key)
+                .and_then(Json::as_f64)
+                .map(|n| n as u64)
+                .ok_or_else(|| format!("missing '{}'", key))
+        };
+        let fingerprint = u64::from_str_radix(string("fingerprint")?, 16).map_err(|_| "invalid 'fingerprint'".to_string())?;
+        let open = json
+            .get("open")
+            .and_then(Json::as_array)
+            .ok_or_else(|| "missing 'open'".to_string())?
+            .iter()
+            .map(|o| o.as_f64().map(|n| n as usize).ok_or_else(|| "invalid 'open'".to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            fingerprint,
+            query: string("query")?.to_string(),
+            format: string("format")?.to_string(),
+            position: ScanPosition {
+                offset: number("offset")? as usize,
+                open,
+            },
+            records: number("records")?,
+            file: number("file")?,
+            file_records: number("file_records")?,
+            file_bytes: number("file_bytes")?,
+        })
+    }
+
+    /// Writes the checkpoint through a temporary file, so an interruption
+    /// never leaves a half-written one behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.to_json().pretty() + "\n")?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Loads the checkpoint at `path` and checks that it belongs to this export.
+    fn load(path: &Path, xml: &str, options: &ExportOptions) -> io::Result<Self> {
+        let src = std::fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("cannot resume, no checkpoint at {}: {}", path.display(), err)))?;
+        let checkpoint = Self::from_json(&src)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?;
+        let mismatch = if checkpoint.fingerprint != fingerprint(xml) {
+            Some("the input has changed")
+        } else if checkpoint.query != options.selection() {
+            Some("it was written for a different query")
+        } else if checkpoint.format != options.format.name() {
+            Some("it was written for a different format")
+        } else {
+            None
+        };
+        match mismatch {
+            Some(reason) => Err(io::Error::other(format!("cannot resume from {}: {}", path.display(), reason))),
+            None => Ok(checkpoint),
+        }
+    }
+}
+
+/// Destination of exported records.
+trait Sink {
+    fn record(&mut self, element: &str) -> io::Result<()>;
+    /// Makes everything written so far durable and notes the output state.
+    fn sync(&mut self, checkpoint: &mut Checkpoint) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Writes one record in `format`, returning the number of bytes written.
+fn write_record<W: Write>(out: &mut W, format: Format, element: &str) -> io::Result<u64> {
+    let line = match format {
+        Format::Ndjson => record_to_json(element).to_string(),
+        Format::Xml => element.to_string(),
+    };
+    out.write_all(line.as_bytes())?;
+    out.write_all(b"\n")?;
+    Ok(line.len() as u64 + 1)
+}
+
+fn xml_header(root: &str) -> String {
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<{}>\n", root)
+}
+
+/// The document's root tag, used to wrap XML output.
+fn root_name(xml: &str) -> &str {
+    let mut root = "records";
+    stream_xml(xml, |token| {
+        if let Token::StartTag(name, _) = token {
+            root = name;
+        }
+        Break(())
+    });
+    root
+}
+
+/// How much of the document `detect_record` looks at.
+const DETECT_BYTES: usize = 8 << 20;
+
+/// Deepest parent whose children are considered as records (1 = the root).
+const DETECT_DEPTH: usize = 3;
+
+/// The record element `detect_record` settled on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordGuess {
+    /// Absolute query for the record element, e.g. `/mediawiki/page`.
+    pub query: String,
+    /// Most occurrences seen directly under one parent.
+    pub count: usize,
+    /// Bytes scanned; less than the document for large files.
+    pub scanned: usize,
+}
+
+/// Child counts of one open element: its path length and occurrences per child name.
+type ChildTally<'a> = (usize, Vec<(&'a str, usize)>);
+
+/// Guesses the record element of a typical dump: the element repeated most
+/// often directly under one parent, among the children of the shallowest
+/// few levels, counted over the first few megabytes. Counting per parent
+/// keeps a few repeated fields inside every record from outvoting the
+/// records themselves. Ties go to the shallower element. Returns `None` if
+/// no element repeats.
+pub fn detect_record(xml: &str) -> Option<RecordGuess> {
+    let mut scanned = xml.len().min(DETECT_BYTES);
+    while !xml.is_char_boundary(scanned) {
+        scanned -= 1;
+    }
+    // Most siblings under one parent, per child path.
+    let mut best: HashMap<String, usize> = HashMap::new();
+    let mut path = String::new();
+    let mut stack: Vec<ChildTally> = Vec::new();
+    let mut close = |path: &mut String, (len, children): ChildTally| {
+        for (name, count) in children {
+            let most = best.entry(format!("{}/{}", path, name)).or_default();
+            *most = (*most).max(count);
+        }
+        path.truncate(len);
+    };
+    stream_xml(&xml[..scanned], |token| {
+        match token {
+            Token::StartTag(name, _) => {
+                if stack.len() <= DETECT_DEPTH
+                    && let Some((_, children)) = stack.last_mut()
+                {
+                    match children.iter_mut().find(|(seen, _)| *seen == name) {
+                        Some((_, count)) => *count += 1,
+                        None => children.push((name, 1)),
+                    }
+                }
+                stack.push((path.len(), Vec::new()));
+                path.push('/');
+                path.push_str(name);
+            }
+            Token::EndTag(_) => {
+                if let Some(tally) = stack.pop() {
+                    close(&mut path, tally);
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    // Elements still open where the scan stopped, the root at least on large files.
+    while let Some(tally) = stack.pop() {
+        close(&mut path, tally);
+    }
+    let (query, count) = best
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .max_by(|(a, a_count), (b, b_count)| {
+            let depth = |query: &str| query.matches('/').count();
+            a_count.cmp(b_count).then_with(|| depth(b).cmp(&depth(a))).then_with(|| b.cmp(a))
+        })?;
+    Some(RecordGuess { query, count, scanned })
+}
+
+/// Opens `path` for writing, truncated to `bytes` when resuming.
+fn open_output(path: &Path, resume_at: Option<u64>) -> io::Result<File> {
+    match resume_at {
+        Some(bytes) => {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            if file.metadata()?.len() < bytes {
+                return Err(io::Error::other(format!("cannot resume: {} is shorter than its checkpoint", path.display())));
+            }
+            file.set_len(bytes)?;
+            file.seek(SeekFrom::End(0))?;
+            Ok(file)
+        }
+        None => File::create(path),
+    }
+}
+
+/// All records into a single output.
+struct StreamSink<'a> {
+    out: BufWriter<Box<dyn Write + 'a>>,
+    file: Option<File>,
+    format: Format,
+    root: &'a str,
+    bytes: u64,
+}
+
+impl Sink for StreamSink<'_> {
+    fn record(&mut self, element: &str) -> io::Result<()> {
+        self.bytes += write_record(&mut self.out, self.format, element)?;
+        Ok(())
+    }
+
+    fn sync(&mut self, checkpoint: &mut Checkpoint) -> io::Result<()> {
+        self.out.flush()?;
+        if let Some(file) = &self.file {
+            file.sync_data()?;
+        }
+        checkpoint.file_bytes = self.bytes;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.format == Format::Xml {
+            writeln!(self.out, "</{}>", self.root)?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Records into numbered files of at most `per_file` records each.
+struct SplitSink<'a> {
+    dir: &'a Path,
+    prefix: &'a str,
+    format: Format,
+    root: &'a str,
+    per_file: u64,
+    file: u64,
+    file_records: u64,
+    bytes: u64,
+    out: Option<BufWriter<File>>,
+}
+
+impl SplitSink<'_> {
+    fn path(&self, file: u64) -> PathBuf {
+        let extension = self.format.name();
+        self.dir.join(format!("{}-{:05}.{}", self.prefix, file, extension))
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(mut out) = self.out.take() {
+            if self.format == Format::Xml {
+                writeln!(out, "</{}>", self.root)?;
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for SplitSink<'_> {
+    fn record(&mut self, element: &str) -> io::Result<()> {
+        if self.out.is_none() {
+            let mut out = BufWriter::new(File::create(self.path(self.file))?);
+            self.bytes = 0;
+            if self.format == Format::Xml {
+                let header = xml_header(self.root);
+                out.write_all(header.as_bytes())?;
+                self.bytes = header.len() as u64;
+            }
+            self.out = Some(out);
+        }
+        let out = self.out.as_mut().expect("opened above");
+        self.bytes += write_record(out, self.format, element)?;
+        self.file_records += 1;
+        if self.file_records == self.per_file {
+            self.close()?;
+            self.file += 1;
+            self.file_records = 0;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self, checkpoint: &mut Checkpoint) -> io::Result<()> {
+        if let Some(out) = self.out.as_mut() {
+            out.flush()?;
+            out.get_ref().sync_data()?;
+        }
+        checkpoint.file = self.file;
+        checkpoint.file_records = self.file_records;
+        checkpoint.file_bytes = if self.out.is_some() { self.bytes } else { 0 };
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+}
+
+/// Runs the scan from `checkpoint`, feeding records to `sink` and saving
+/// checkpoints to `checkpoint_path`. Returns the total number of records.
+fn export(xml: &str, options: &ExportOptions, sink: &mut dyn Sink, mut checkpoint: Checkpoint, checkpoint_path: Option<&Path>, command: &'static str) -> io::Result<u64> {
+    let mut result = Ok(());
+    let mut stopped = None;
+    let mut progress = Progress::new(command, Some(xml.len() as u64));
+    let start = checkpoint.position.clone();
+    for_each_match_from(xml, options.query, &start, |range, ancestors| {
+        if interrupt::interrupted() {
+            stopped = Some(ancestors.position_after(range.start));
+            return Break(());
+        }
+        let end = range.end;
+        progress.update(end as u64, checkpoint.records, || format!("Exporting: {} records", checkpoint.records));
+        let record = &xml[range];
+        if options.filter.is_some_and(|filter| !filter.eval(record)) {
+            return Continue(());
+        }
+        result = sink.record(record);
+        if result.is_err() {
+            return Break(());
+        }
+        checkpoint.records += 1;
+        if let Some(path) = checkpoint_path
+            && options.checkpoint_every > 0
+            && checkpoint.records.is_multiple_of(options.checkpoint_every)
+        {
+            checkpoint.position = ancestors.position_after(end);
+            result = sink.sync(&mut checkpoint).and_then(|()| checkpoint.save(path));
+            if result.is_err() {
+                return Break(());
+            }
+        }
+        Continue(())
+    });
+    let scanned = stopped.as_ref().map_or(xml.len(), |position| position.offset);
+    progress.finish(scanned as u64, checkpoint.records);
+    result?;
+    // On Ctrl-C, the output is completed and a checkpoint saved at the record
+    // that was next, so that `--resume` carries on from there.
+    if let Some(position) = stopped {
+        let offset = position.offset as u64;
+        let hint = match checkpoint_path {
+            Some(path) => {
+                checkpoint.position = position;
+                sink.sync(&mut checkpoint)?;
+                checkpoint.save(path)?;
+                format!("{} records written; run again with --resume to continue", checkpoint.records)
+            }
+            None => format!("{} records written", checkpoint.records),
+        };
+        sink.finish()?;
+        return Err(interrupt::error(offset, Some(xml.len() as u64), &hint));
+    }
+    sink.finish()?;
+    if let Some(path) = checkpoint_path {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(checkpoint.records)
+}
+
+fn fresh_checkpoint(xml: &str, options: &ExportOptions) -> Checkpoint {
+    Checkpoint {
+        fingerprint: fingerprint(xml),
+        query: options.selection(),
+        format: options.format.name().to_string(),
+        file: 1,
+        ..Checkpoint::default()
+    }
+}
+
+/// Exports the matching records to `output`, or to standard output when it is
+/// `None` (which cannot be checkpointed). Returns the number of records.
+pub fn convert(xml: &str, options: &ExportOptions, output: Option<&Path>) -> io::Result<u64> {
+    let root = root_name(xml);
+    let Some(output) = output else {
+        if options.resume {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "--resume needs an output file".to_string()));
+        }
+        let mut sink = StreamSink {
+            out: BufWriter::new(Box::new(io::stdout().lock())),
+            file: None,
+            format: options.format,
+            root,
+            bytes: 0,
+        };
+        if options.format == Format::Xml {
+            sink.out.write_all(xml_header(root).as_bytes())?;
+        }
+        return export(xml, options, &mut sink, fresh_checkpoint(xml, options), None, "convert");
+    };
+    sandbox::check(Capability::WriteFiles)?;
+    let checkpoint_path = Checkpoint::path_for(output);
+    let (checkpoint, file) = if options.resume {
+        let checkpoint = Checkpoint::load(&checkpoint_path, xml, options)?;
+        let file = open_output(output, Some(checkpoint.file_bytes))?;
+        (checkpoint, file)
+    } else {
+        let mut file = open_output(output, None)?;
+        let mut checkpoint = fresh_checkpoint(xml, options);
+        if options.format == Format::Xml {
+            let header = xml_header(root);
+            file.write_all(header.as_bytes())?;
+            checkpoint.file_bytes = header.len() as u64;
+        }
+        (checkpoint, file)
+    };
+    let mut sink = StreamSink {
+        out: BufWriter::new(Box::new(file.try_clone()?)),
+        file: Some(file),
+        format: options.format,
+        root,
+        bytes: checkpoint.file_bytes,
+    };
+    export(xml, options, &mut sink, checkpoint, Some(&checkpoint_path), "convert")
+}
+
+/// Splits the matching records into files of `per_file` records named
+/// `<prefix>-00001.<format>` in `dir`. Returns the number of records.
+pub fn split(xml: &str, options: &ExportOptions, dir: &Path, prefix: &str, per_file: u64) -> io::Result<u64> {
+    sandbox::check(Capability::WriteFiles)?;
+    std::fs::create_dir_all(dir)?;
+    let checkpoint_path = Checkpoint::path_for(&dir.join(prefix));
+    let checkpoint = if options.resume {
+        Checkpoint::load(&checkpoint_path, xml, options)?
+    } else {
+        fresh_checkpoint(xml, options)
+    };
+    let mut sink = SplitSink {
+        dir,
+        prefix,
+        format: options.format,
+        root: root_name(xml),
+        per_file: per_file.max(1),
+        file: checkpoint.file,
+        file_records: checkpoint.file_records,
+        bytes: checkpoint.file_bytes,
+        out: None,
+    };
+    if checkpoint.file_records > 0 {
+        let file = open_output(&sink.path(checkpoint.file), Some(checkpoint.file_bytes))?;
+        sink.out = Some(BufWriter::new(file));
+    }
+    export(xml, options, &mut sink, checkpoint, Some(&checkpoint_path), "split")
+}
@@ -0,0 +1,211 @@
+//! `xmz describe`: summary statistics per column of a record dump, like
+//! `pandas.describe()`, in one pass.
+//!
+//! The columns are the leaf paths of the records: their attributes
+//! (`@id`), and the elements without child elements below them (`price`,
+//! `author/name`) with the attributes of any element (`author/@id`). For
+//! each: how many values it has, how many records lack it (or have it
+//! empty), the smallest, largest and mean value when every value is a
+//! number, and an estimate of how many values are distinct.
+
+use crate::agg::write_table;
+use crate::json::Json;
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans, text_content};
+use crate::query::{Query, for_each_match};
+use crate::sketch::{DISTINCT_ERROR, HyperLogLog};
+use crate::value::ValueParser;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// The summary of one column.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnStats {
+    pub path: String,
+    /// Non-empty values.
+    pub count: u64,
+    /// Records without a non-empty value.
+    pub nulls: u64,
+    /// Values that are not numbers; min, max and mean need none.
+    pub non_numeric: u64,
+    pub min: f64,
+    pub max: f64,
+    sum: f64,
+    /// The last record with a value, to count each record once.
+    last_record: u64,
+    distinct: HyperLogLog,
+}
+
+impl ColumnStats {
+    pub fn is_numeric(&self) -> bool {
+        self.count > 0 && self.non_numeric == 0
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    pub fn distinct(&self) -> u64 {
+        self.distinct.estimate()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Description {
+    pub records: u64,
+    /// In the order first seen.
+    pub columns: Vec<ColumnStats>,
+}
+
+/// The leaf values of `record`, by path relative to it.
+fn leaves(record: &str, mut on_value: impl FnMut(&str, &str)) {
+    // Open elements below the record: the path length before each, and
+    // whether it has child elements.
+    let mut open: Vec<(usize, bool)> = Vec::new();
+    let mut path = String::new();
+    let mut depth = 0;
+    let mut content_start = 0;
+    stream_xml_spans(record, |token, span| {
+        match token {
+            Token::StartTag(name, attrs) => {
+                if depth > 0 {
+                    if let Some((_, children)) = open.last_mut() {
+                        *children = true;
+                    }
+                    open.push((path.len(), false));
+                    if !path.is_empty() {
+                        path.push('/');
+                    }
+                    path.push_str(name);
+                }
+                depth += 1;
+                for (attribute, value) in attribute_pairs(attrs) {
+                    let column = if path.is_empty() { format!("@{}", attribute) } else { format!("{}/@{}", path, attribute) };
+                    on_value(&column, &decode_entities(value));
+                }
+                content_start = span.end;
+            }
+            Token::EndTag(_) => {
+                depth -= 1;
+                if depth > 0
+                    && let Some((len, children)) = open.pop()
+                {
+                    if !children {
+                        let text = text_content(&record[content_start.min(span.start)..span.start]);
+                        on_value(&path, text.trim());
+                    }
+                    path.truncate(len);
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+}
+
+/// Streams `xml` and summarises the columns of the records `query` selects.
+pub fn describe(xml: &str, query: &Query, parser: &ValueParser) -> Description {
+    let mut columns: Vec<ColumnStats> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut records = 0;
+    for_each_match(xml, query, |range| {
+        records += 1;
+        leaves(&xml[range], |path, value| {
+            let i = *index.entry(path.to_string()).or_insert_with(|| {
+                columns.push(ColumnStats {
+                    path: path.to_string(),
+                    // Records before the column was first seen lack it.
+                    nulls: records - 1,
+                    ..ColumnStats::default()
+                });
+                columns.len() - 1
+            });
+            let column = &mut columns[i];
+            if value.is_empty() {
+                return;
+            }
+            column.count += 1;
+            column.last_record = records;
+            column.distinct.insert(value);
+            match parser.number(value) {
+                Some(n) if column.count - column.non_numeric == 1 => {
+                    column.min = n;
+                    column.max = n;
+                    column.sum = n;
+                }
+                Some(n) => {
+                    column.min = column.min.min(n);
+                    column.max = column.max.max(n);
+                    column.sum += n;
+                }
+                None => column.non_numeric += 1,
+            }
+        });
+        // Columns this record gave no value count it as a null.
+        for column in &mut columns {
+            if column.last_record != records {
+                column.nulls += 1;
+                column.last_record = records;
+            }
+        }
+        Continue(())
+    });
+    Description { records, columns }
+}
+
+fn number(value: f64) -> String {
+    format!("{}", (value * 1e6).round() / 1e6)
+}
+
+/// Writes the description as a table, a row per column.
+pub fn write_description<W: Write>(out: &mut W, description: &Description) -> io::Result<()> {
+    if description.records == 0 {
+        return writeln!(out, "No matches.");
+    }
+    let mut rows = vec![["column", "count", "nulls", "min", "max", "mean", "distinct"].map(String::from).to_vec()];
+    for column in &description.columns {
+        let numeric = column.is_numeric();
+        let stat = |value: f64| if numeric { number(value) } else { "-".to_string() };
+        rows.push(vec![
+            column.path.clone(),
+            column.count.to_string(),
+            column.nulls.to_string(),
+            stat(column.min),
+            stat(column.max),
+            stat(column.mean()),
+            format!("~{}", column.distinct()),
+        ]);
+    }
+    write_table(out, &rows)?;
+    writeln!(
+        out,
+        "{} record(s); distinct counts are estimates within about {:.1}%",
+        description.records,
+        DISTINCT_ERROR * 100.0
+    )
+}
+
+/// The description as `{"records", "columns": [{"column", "count", ...}]}`,
+/// with `null` statistics for columns that are not numeric.
+pub fn description_to_json(description: &Description) -> Json {
+    let columns = description
+        .columns
+        .iter()
+        .map(|column| {
+            let stat = |value: f64| if column.is_numeric() { Json::Number(value) } else { Json::Null };
+            Json::Object(vec![
+                ("column".to_string(), Json::String(column.path.clone())),
+                ("count".to_string(), Json::Number(column.count as f64)),
+                ("nulls".to_string(), Json::Number(column.nulls as f64)),
+                ("min".to_string(), stat(column.min)),
+                ("max".to_string(), stat(column.max)),
+                ("mean".to_string(), stat(column.mean())),
+                ("distinct".to_string(), Json::Number(column.distinct() as f64)),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("records".to_string(), Json::Number(description.records as f64)),
+        ("columns".to_string(), Json::Array(columns)),
+        ("distinct_error".to_string(), Json::Number(DISTINCT_ERROR)),
+    ])
+}
@@ -0,0 +1,479 @@
+//! `xmz diff`: a structural comparison of two documents, element by element.
+//!
+//! Children are paired by name and position among their same-named siblings,
+//! the way paths such as `/lib/book[2]` count them, so an inserted `<book>`
+//! shows as a change to every later book but an inserted `<note>` does not
+//! disturb the books around it. Paired elements are compared by their
+//! attributes, in any order, and by their own text, entities decoded and
+//! surrounding whitespace ignored.
+//!
+//! With a key attribute such as `id`, children that have it are paired by
+//! name and key instead (`/lib/book[@id='b7']`), so inserting or reordering
+//! records only reports the records themselves.
+//!
+//! What counts as equal can be tightened or loosened one concern at a time:
+//! whitespace in text (`Whitespace`), comments directly inside elements, and
+//! the order attributes are written in.
+
+use crate::json::Json;
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans, text_content, token_text};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// How children are paired, by position by default, and which differences
+/// count.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Attribute identifying children, e.g. `id`.
+    pub key: Option<String>,
+    pub whitespace: Whitespace,
+    /// Compare the comments directly inside each element, in order.
+    pub comments: bool,
+    /// Report attributes written in a different order.
+    pub attribute_order: bool,
+}
+
+/// How whitespace in text counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Whitespace {
+    /// Whitespace around each run of text is ignored, and so is text that
+    /// is only whitespace, such as indentation.
+    #[default]
+    Trim,
+    /// As `Trim`, and any run of whitespace inside text equals a single space.
+    Collapse,
+    /// Text is compared as written, indentation and line ends included.
+    Exact,
+}
+
+impl Whitespace {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "trim" => Ok(Whitespace::Trim),
+            "collapse" => Ok(Whitespace::Collapse),
+            "exact" => Ok(Whitespace::Exact),
+            _ => Err(format!("unknown whitespace mode '{}' (expected trim, collapse or exact)", name)),
+        }
+    }
+
+    /// `text` as compared in this mode.
+    fn normalize(self, text: &str) -> String {
+        match self {
+            Whitespace::Trim => text.trim().to_string(),
+            Whitespace::Collapse => text.split_whitespace().collect::<Vec<_>>().join(" "),
+            Whitespace::Exact => text.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The element is only in the new document.
+    Added,
+    /// The element is only in the old document.
+    Removed,
+    /// An attribute was added (no old value), removed (no new value) or changed.
+    Attribute { name: String, old: Option<String>, new: Option<String> },
+    Text { old: String, new: String },
+    /// The `index`th comment (from 1) was added, removed or changed.
+    Comment { index: usize, old: Option<String>, new: Option<String> },
+    /// The same attributes, written in a different order.
+    AttributeOrder { old: Vec<String>, new: Vec<String> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// Path of the element, positioned as in the document that has it.
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// Elements present in both documents, by their spans in each.
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    /// Whether the attributes or text differ.
+    pub changed: bool,
+    /// Whether anything below the element differs.
+    pub changed_inside: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// Changes in document order.
+    pub changes: Vec<Change>,
+    /// Paired elements in document order, the roots first.
+    pub pairs: Vec<Pair>,
+    /// Start offsets of the elements only in the old, and only in the new document.
+    pub removed: Vec<usize>,
+    pub added: Vec<usize>,
+}
+
+pub(crate) struct Element<'a> {
+    pub name: &'a str,
+    pub attrs: &'a str,
+    pub span: Range<usize>,
+    /// Between the start and end tag; empty at the end for `<empty/>`.
+    pub content: Range<usize>,
+    pub children: Vec<usize>,
+    pub text: String,
+    pub comments: Vec<String>,
+}
+
+/// The elements of `xml` in document order, with their children, and their
+/// text and comments as `options` compares them.
+pub(crate) fn elements<'a>(xml: &'a str, options: &DiffOptions) -> Vec<Element<'a>> {
+    let mut elements: Vec<Element> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    let mut last = 0;
+    let exact = options.whitespace == Whitespace::Exact;
+    stream_xml_spans(xml, |token, span| {
+        // Comments, and text as written, are read from between tags.
+        if !matches!(token, Token::Text(_) | Token::CData(_)) {
+            if let Some(&id) = open.last() {
+                let gap = &xml[last..span.start];
+                if exact {
+                    elements[id].text.push_str(&text_content(gap));
+                }
+                if options.comments {
+                    push_comments(gap, options.whitespace, &mut elements[id].comments);
+                }
+            }
+            last = span.end;
+        }
+        match token {
+            Token::StartTag(name, attrs) => {
+                let id = elements.len();
+                if let Some(&parent) = open.last() {
+                    elements[parent].children.push(id);
+                }
+                elements.push(Element {
+                    name,
+                    attrs,
+                    content: span.end..span.end,
+                    span,
+                    children: Vec::new(),
+                    text: String::new(),
+                    comments: Vec::new(),
+                });
+                open.push(id);
+            }
+            Token::EndTag(_) => {
+                if let Some(id) = open.pop() {
+                    elements[id].span.end = span.end;
+                    elements[id].content.end = span.start;
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {
+                if let Some(&id) = open.last()
+                    && !exact
+                {
+                    let element = &mut elements[id];
+                    if !element.text.is_empty() {
+                        element.text.push(' ');
+                    }
+                    element.text.push_str(&token_text(&token).unwrap_or_default());
+                }
+            }
+        }
+        Continue(())
+    });
+    if options.whitespace == Whitespace::Collapse {
+        for element in &mut elements {
+            element.text = options.whitespace.normalize(&element.text);
+        }
+    }
+    elements
+}
+
+/// Adds the comments in `gap`, a stretch of content between tags, to
+/// `comments`.
+fn push_comments(gap: &str, whitespace: Whitespace, comments: &mut Vec<String>) {
+    let mut rest = gap;
+    while let Some(lt) = rest.find('<') {
+        rest = &rest[lt..];
+        let end = if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<![CDATA[") {
+            "]]>"
+        } else {
+            ">"
+        };
+        let len = rest[1..].find(end).map_or(rest.len(), |at| at + 1 + end.len());
+        if let Some(body) = rest[..len].strip_prefix("<!--") {
+            comments.push(whitespace.normalize(body.strip_suffix("-->").unwrap_or(body)));
+        }
+        rest = &rest[len..];
+    }
+}
+
+/// Names children as paths step to them, one child at a time: `name[n]`,
+/// numbered among same-named siblings, or `name[@key='value']` for those
+/// with a unique `key` value.
+#[derive(Default)]
+pub(crate) struct Steps<'a> {
+    seen: HashMap<&'a str, usize>,
+    keys: HashSet<String>,
+}
+
+impl<'a> Steps<'a> {
+    pub fn next(&mut self, name: &'a str, attrs: &str, key: Option<&str>) -> String {
+        let value = key.and_then(|key| attribute_pairs(attrs).into_iter().find(|(attr, _)| *attr == key));
+        if let (Some(key), Some((_, value))) = (key, value) {
+            let value = decode_entities(value);
+            let quote = if value.contains('\'') { '"' } else { '\'' };
+            let step = format!("{}[@{}={}{}{}]", name, key, quote, value, quote);
+            if self.keys.insert(step.clone()) {
+                return step;
+            }
+        }
+        let count = self.seen.entry(name).or_default();
+        *count += 1;
+        format!("{}[{}]", name, count)
+    }
+}
+
+/// The steps to the children of `parent`.
+pub(crate) fn child_steps<'a>(elements: &[Element<'a>], parent: &Element, key: Option<&str>) -> Vec<String> {
+    let mut steps = Steps::default();
+    parent.children.iter().map(|&id| steps.next(elements[id].name, elements[id].attrs, key)).collect()
+}
+
+/// What is left to compare, in the order changes are reported.
+enum Step {
+    Pair { old: usize, new: usize, path: String, parent: Option<usize> },
+    Removed { old: usize, path: String, parent: Option<usize> },
+    Added { new: usize, path: String, parent: Option<usize> },
+}
+
+/// Compares `new` against `old`.
+pub fn diff(old: &str, new: &str, options: &DiffOptions) -> Diff {
+    let key = options.key.as_deref();
+    let a = elements(old, options);
+    let b = elements(new, options);
+    let mut result = Diff::default();
+    // Pair index of each pair's parent, to propagate `changed_inside`.
+    let mut parents: Vec<Option<usize>> = Vec::new();
+    let mut stack = Vec::new();
+    match (a.first(), b.first()) {
+        (Some(x), Some(y)) if x.name == y.name => stack.push(Step::Pair { old: 0, new: 0, path: format!("/{}", x.name), parent: None }),
+        (x, y) => {
+            if let Some(y) = y {
+                stack.push(Step::Added { new: 0, path: format!("/{}", y.name), parent: None });
+            }
+            if let Some(x) = x {
+                stack.push(Step::Removed { old: 0, path: format!("/{}", x.name), parent: None });
+            }
+        }
+    }
+
+    while let Some(step) = stack.pop() {
+        let (x, y, path, parent) = match step {
+            Step::Removed { old, path, parent } => {
+                result.removed.push(a[old].span.start);
+                result.changes.push(Change { path, kind: ChangeKind::Removed });
+                if let Some(parent) = parent {
+                    result.pairs[parent].changed_inside = true;
+                }
+                continue;
+            }
+            Step::Added { new, path, parent } => {
+                result.added.push(b[new].span.start);
+                result.changes.push(Change { path, kind: ChangeKind::Added });
+                if let Some(parent) = parent {
+                    result.pairs[parent].changed_inside = true;
+                }
+                continue;
+            }
+            Step::Pair { old, new, path, parent } => (&a[old], &b[new], path, parent),
+        };
+        let pair = result.pairs.len();
+        let before = result.changes.len();
+        compare_attributes(x.attrs, y.attrs, &path, options.attribute_order, &mut result.changes);
+        if x.text != y.text {
+            result.changes.push(Change {
+                path: path.clone(),
+                kind: ChangeKind::Text { old: x.text.clone(), new: y.text.clone() },
+            });
+        }
+        for index in 0..x.comments.len().max(y.comments.len()) {
+            let (old, new) = (x.comments.get(index), y.comments.get(index));
+            if old != new {
+                result.changes.push(Change {
+                    path: path.clone(),
+                    kind: ChangeKind::Comment { index: index + 1, old: old.cloned(), new: new.cloned() },
+                });
+            }
+        }
+        result.pairs.push(Pair {
+            old: x.span.clone(),
+            new: y.span.clone(),
+            changed: result.changes.len() > before,
+            changed_inside: false,
+        });
+        parents.push(parent);
+
+        // Children in the new document's order, with removed ones placed
+        // before the next child that follows them in the old document.
+        let old_steps = child_steps(&a, x, key);
+        let new_steps = child_steps(&b, y, key);
+        let position: HashMap<&str, usize> = old_steps.iter().enumerate().map(|(i, step)| (step.as_str(), i)).collect();
+        let kept: HashSet<&str> = new_steps.iter().map(String::as_str).collect();
+        let mut done = vec![false; x.children.len()];
+        let mut next = 0;
+        let mut steps = Vec::new();
+        for (j, step) in new_steps.iter().enumerate() {
+            let child_path = format!("{}/{}", path, step);
+            let Some(&i) = position.get(step.as_str()) else {
+                steps.push(Step::Added { new: y.children[j], path: child_path, parent: Some(pair) });
+                continue;
+            };
+            for k in next..i {
+                if !done[k] && !kept.contains(old_steps[k].as_str()) {
+                    done[k] = true;
+                    steps.push(Step::Removed { old: x.children[k], path: format!("{}/{}", path, old_steps[k]), parent: Some(pair) });
+                }
+            }
+            next = next.max(i + 1);
+            done[i] = true;
+            steps.push(Step::Pair { old: x.children[i], new: y.children[j], path: child_path, parent: Some(pair) });
+        }
+        for (k, step) in old_steps.iter().enumerate() {
+            if !done[k] {
+                steps.push(Step::Removed { old: x.children[k], path: format!("{}/{}", path, step), parent: Some(pair) });
+            }
+        }
+        stack.extend(steps.into_iter().rev());
+    }
+
+    // Children come after their parents, so walking back reaches them first.
+    for i in (0..result.pairs.len()).rev() {
+        if let Some(parent) = parents[i]
+            && (result.pairs[i].changed || result.pairs[i].changed_inside)
+        {
+            result.pairs[parent].changed_inside = true;
+        }
+    }
+    result
+}
+
+fn compare_attributes(old: &str, new: &str, path: &str, order: bool, changes: &mut Vec<Change>) {
+    let old = attribute_pairs(old);
+    let new = attribute_pairs(new);
+    if order {
+        // Only the attributes on both sides; the others are reported anyway.
+        let common = |pairs: &[(&str, &str)], other: &[(&str, &str)]| -> Vec<String> {
+            pairs.iter().filter(|(name, _)| other.iter().any(|(n, _)| n == name)).map(|(name, _)| name.to_string()).collect()
+        };
+        let (old_order, new_order) = (common(&old, &new), common(&new, &old));
+        if old_order != new_order {
+            changes.push(Change {
+                path: path.to_string(),
+                kind: ChangeKind::AttributeOrder { old: old_order, new: new_order },
+            });
+        }
+    }
+    let mut change = |name: &str, old: Option<&str>, new: Option<&str>| {
+        changes.push(Change {
+            path: path.to_string(),
+            kind: ChangeKind::Attribute {
+                name: name.to_string(),
+                old: old.map(|value| decode_entities(value).into_owned()),
+                new: new.map(|value| decode_entities(value).into_owned()),
+            },
+        });
+    };
+    for &(name, value) in &old {
+        match new.iter().find(|(n, _)| *n == name) {
+            Some(&(_, other)) if decode_entities(other) == decode_entities(value) => {}
+            Some(&(_, other)) => change(name, Some(value), Some(other)),
+            None => change(name, Some(value), None),
+        }
+    }
+    for &(name, value) in &new {
+        if !old.iter().any(|(n, _)| *n == name) {
+            change(name, None, Some(value));
+        }
+    }
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes as `{"old", "new", "changes": [...]}`, each change with
+    /// its `path`, `change` (added, removed, attribute, text, comment or
+    /// attribute-order) and for all but the first two the `old` and `new`
+    /// values, null where absent; attribute orders are arrays of names.
+    pub fn to_json(&self, old_file: &str, new_file: &str) -> Json {
+        let text = |value: Option<&str>| value.map_or(Json::Null, |value| Json::String(value.to_string()));
+        let changes = self
+            .changes
+            .iter()
+            .map(|change| {
+                let mut members = vec![("path".to_string(), Json::String(change.path.clone()))];
+                let (kind, details) = match &change.kind {
+                    ChangeKind::Added => ("added", None),
+                    ChangeKind::Removed => ("removed", None),
+                    ChangeKind::Attribute { name, old, new } => {
+                        members.push(("name".to_string(), Json::String(name.clone())));
+                        ("attribute", Some((old.as_deref(), new.as_deref())))
+                    }
+                    ChangeKind::Text { old, new } => ("text", Some((Some(old.as_str()), Some(new.as_str())))),
+                    ChangeKind::Comment { index, old, new } => {
+                        members.push(("index".to_string(), Json::Number(*index as f64)));
+                        ("comment", Some((old.as_deref(), new.as_deref())))
+                    }
+                    ChangeKind::AttributeOrder { old, new } => {
+                        let names = |names: &[String]| Json::Array(names.iter().cloned().map(Json::String).collect());
+                        members.push(("old".to_string(), names(old)));
+                        members.push(("new".to_string(), names(new)));
+                        ("attribute-order", None)
+                    }
+                };
+                members.insert(1, ("change".to_string(), Json::String(kind.to_string())));
+                if let Some((old, new)) = details {
+                    members.push(("old".to_string(), text(old)));
+                    members.push(("new".to_string(), text(new)));
+                }
+                Json::Object(members)
+            })
+            .collect();
+        Json::Object(vec![
+            ("old".to_string(), Json::String(old_file.to_string())),
+            ("new".to_string(), Json::String(new_file.to_string())),
+            ("changes".to_string(), Json::Array(changes)),
+        ])
+    }
+
+    /// Writes one line per change: `+`/`-` for elements, attributes and
+    /// comments only in the new or old document, `~` for changed attributes,
+    /// text, comments and attribute order.
+    pub fn write_report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.is_empty() {
+            return writeln!(out, "No differences.");
+        }
+        for change in &self.changes {
+            match &change.kind {
+                ChangeKind::Added => writeln!(out, "+ {}", change.path)?,
+                ChangeKind::Removed => writeln!(out, "- {}", change.path)?,
+                ChangeKind::Attribute { name, old: None, new: Some(new) } => writeln!(out, "+ {}/@{} = {:?}", change.path, name, new)?,
+                ChangeKind::Attribute { name, old: Some(old), new: None } => writeln!(out, "- {}/@{} = {:?}", change.path, name, old)?,
+                ChangeKind::Attribute { name, old, new } => {
+                    writeln!(out, "~ {}/@{}: {:?} -> {:?}", change.path, name, old.as_deref().unwrap_or(""), new.as_deref().unwrap_or(""))?
+                }
+                ChangeKind::Text { old, new } => writeln!(out, "~ {}: {:?} -> {:?}", change.path, old, new)?,
+                ChangeKind::Comment { index, old: None, new: Some(new) } => writeln!(out, "+ {}/comment()[{}] = {:?}", change.path, index, new)?,
+                ChangeKind::Comment { index, old: Some(old), new: None } => writeln!(out, "- {}/comment()[{}] = {:?}", change.path, index, old)?,
+                ChangeKind::Comment { index, old, new } => {
+                    writeln!(out, "~ {}/comment()[{}]: {:?} -> {:?}", change.path, index, old.as_deref().unwrap_or(""), new.as_deref().unwrap_or(""))?
+                }
+                ChangeKind::AttributeOrder { old, new } => writeln!(out, "~ {}/@*: order {} -> {}", change.path, old.join(" "), new.join(" "))?,
+            }
+        }
+        Ok(())
+    }
+}
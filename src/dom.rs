@@ -0,0 +1,424 @@
+//! An owned document tree (`Document::parse`), for code that needs random
+//! access and mutation. `xml::XmlExplorer` remains the zero-copy way to read
+//! a document; this is the way to change one.
+//!
+//! Nodes live in one arena and refer to each other by `NodeId`: each knows
+//! its parent, first and last child and previous and next sibling. Elements
+//! have their name and attributes, text nodes their text, both with entities
+//! decoded and CDATA sections unwrapped. Comments and processing
+//! instructions are kept; text that is only whitespace, the XML declaration
+//! and the DOCTYPE are not. A detached node stays in the arena, and can be
+//! attached again. `to_xml` writes the tree back out.
+
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans};
+use crate::transform::escape;
+
+/// A node of a `Document`, valid for that document only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// The document itself: the parent of the root element and of comments
+    /// and processing instructions outside it.
+    Document,
+    Element { name: String, attributes: Vec<(String, String)> },
+    Text(String),
+    Comment(String),
+    ProcessingInstruction { target: String, data: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<Node>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document::new()
+    }
+}
+
+impl Document {
+    /// An empty document, with only the document node.
+    pub fn new() -> Self {
+        let mut document = Document { nodes: Vec::new() };
+        document.create(NodeKind::Document);
+        document
+    }
+
+    /// Builds the tree of `xml`. Like the tokenizer, it does not fail: an
+    /// end tag closes the elements opened since the start tag it matches,
+    /// one that matches none is ignored, and elements open at the end are
+    /// closed there.
+    pub fn parse(xml: &str) -> Self {
+        let mut document = Document::new();
+        let mut open = vec![document.root()];
+        let mut last = 0;
+        stream_xml_spans(xml, |token, span| {
+            if matches!(token, Token::Text(_) | Token::CData(_)) {
+                return Continue(());
+            }
+            let parent = *open.last().expect("the document node stays open");
+            document.append_content(parent, &xml[last..span.start]);
+            last = span.end;
+            match token {
+                Token::StartTag(name, attrs) => {
+                    let attributes = attribute_pairs(attrs).into_iter().map(|(name, value)| (name.to_string(), decode_entities(value).into_owned())).collect();
+                    let element = document.create(NodeKind::Element { name: name.to_string(), attributes });
+                    document.append_child(parent, element);
+                    open.push(element);
+                }
+                Token::EndTag(name) => {
+                    let name = name.trim_end();
+                    if let Some(at) = open.iter().rposition(|&id| document.name(id) == Some(name)) {
+                        open.truncate(at);
+                    }
+                }
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            Continue(())
+        });
+        let parent = *open.last().expect("the document node stays open");
+        document.append_content(parent, &xml[last..]);
+        document
+    }
+
+    /// Appends the nodes of `raw`, content between two tags, to `parent`.
+    fn append_content(&mut self, parent: NodeId, raw: &str) {
+        let mut text = String::new();
+        let flush = |document: &mut Document, text: &mut String| {
+            if !text.trim().is_empty() {
+                let node = document.create(NodeKind::Text(std::mem::take(text)));
+                document.append_child(parent, node);
+            }
+            text.clear();
+        };
+        let mut rest = raw;
+        while let Some(lt) = rest.find('<') {
+            text.push_str(&decode_entities(&rest[..lt]));
+            rest = &rest[lt..];
+            let len = if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let end = cdata.find("]]>").unwrap_or(cdata.len());
+                text.push_str(&cdata[..end]);
+                9 + end + 3
+            } else if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").unwrap_or(comment.len());
+                flush(self, &mut text);
+                let node = self.create(NodeKind::Comment(comment[..end].to_string()));
+                self.append_child(parent, node);
+                4 + end + 3
+            } else if let Some(instruction) = rest.strip_prefix("<?") {
+                let end = instruction.find("?>").unwrap_or(instruction.len());
+                let body = &instruction[..end];
+                let (target, data) = body.split_at(body.find(char::is_whitespace).unwrap_or(body.len()));
+                if target != "xml" {
+                    flush(self, &mut text);
+                    let node = self.create(NodeKind::ProcessingInstruction {
+                        target: target.to_string(),
+                        data: data.trim_start().to_string(),
+                    });
+                    self.append_child(parent, node);
+                }
+                2 + end + 2
+            } else if rest.starts_with("<!DOCTYPE") {
+                xmz_core::doctype_len(rest.as_bytes()).unwrap_or(rest.len())
+            } else {
+                rest.find('>').map_or(rest.len(), |at| at + 1)
+            };
+            rest = rest.get(len..).unwrap_or("");
+        }
+        text.push_str(&decode_entities(rest));
+        flush(self, &mut text);
+    }
+
+    /// The document node, parent of the root element.
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// The first element below the document node.
+    pub fn root_element(&self) -> Option<NodeId> {
+        self.children(self.root()).find(|&id| self.name(id).is_some())
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn kind(&self, id: NodeId) -> &NodeKind {
+        &self.nodes[id.0].kind
+    }
+
+    pub fn kind_mut(&mut self, id: NodeId) -> &mut NodeKind {
+        &mut self.nodes[id.0].kind
+    }
+
+    /// The element's name; `None` for other nodes.
+    pub fn name(&self, id: NodeId) -> Option<&str> {
+        match self.kind(id) {
+            NodeKind::Element { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn attribute(&self, id: NodeId, name: &str) -> Option<&str> {
+        match self.kind(id) {
+            NodeKind::Element { attributes, .. } => attributes.iter().find(|(attribute, _)| attribute == name).map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Sets an attribute of an element, keeping its place if it exists and
+    /// adding it last otherwise; other nodes are left as they are.
+    pub fn set_attribute(&mut self, id: NodeId, name: &str, value: &str) {
+        if let NodeKind::Element { attributes, .. } = self.kind_mut(id) {
+            match attributes.iter_mut().find(|(attribute, _)| attribute == name) {
+                Some((_, existing)) => *existing = value.to_string(),
+                None => attributes.push((name.to_string(), value.to_string())),
+            }
+        }
+    }
+
+    /// Removes an attribute, returning its value.
+    pub fn remove_attribute(&mut self, id: NodeId, name: &str) -> Option<String> {
+        let NodeKind::Element { attributes, .. } = self.kind_mut(id) else {
+            return None;
+        };
+        let at = attributes.iter().position(|(attribute, _)| attribute == name)?;
+        Some(attributes.remove(at).1)
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).last_child
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).previous_sibling
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).next_sibling
+    }
+
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.first_child(id), |&child| self.next_sibling(child))
+    }
+
+    /// The node and every node below it, in document order.
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(Some(id), move |&node| {
+            if let Some(child) = self.first_child(node) {
+                return Some(child);
+            }
+            // The next sibling of the node or of its nearest ancestor below `id`.
+            let mut at = node;
+            while at != id {
+                if let Some(sibling) = self.next_sibling(at) {
+                    return Some(sibling);
+                }
+                at = self.parent(at)?;
+            }
+            None
+        })
+    }
+
+    /// The child elements named `name`.
+    pub fn children_named<'d>(&'d self, id: NodeId, name: &'d str) -> impl Iterator<Item = NodeId> + 'd {
+        self.children(id).filter(move |&child| self.name(child) == Some(name))
+    }
+
+    /// All the text below the node, in document order.
+    pub fn text(&self, id: NodeId) -> String {
+        let mut text = String::new();
+        for node in self.descendants(id) {
+            if let NodeKind::Text(run) = self.kind(node) {
+                text.push_str(run);
+            }
+        }
+        text
+    }
+
+    /// Replaces the children of `id` with one text node, or none for empty
+    /// text.
+    pub fn set_text(&mut self, id: NodeId, text: &str) {
+        while let Some(child) = self.first_child(id) {
+            self.detach(child);
+        }
+        if !text.is_empty() {
+            let node = self.create_text(text);
+            self.append_child(id, node);
+        }
+    }
+
+    fn create(&mut self, kind: NodeKind) -> NodeId {
+        self.nodes.push(Node {
+            kind,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// A new element without attributes, not yet attached.
+    pub fn create_element(&mut self, name: &str) -> NodeId {
+        self.create(NodeKind::Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+        })
+    }
+
+    /// A new text node, not yet attached.
+    pub fn create_text(&mut self, text: &str) -> NodeId {
+        self.create(NodeKind::Text(text.to_string()))
+    }
+
+    /// Takes the node, with everything below it, out of its parent.
+    pub fn detach(&mut self, id: NodeId) {
+        let Node { parent, previous_sibling, next_sibling, .. } = *self.node(id);
+        match previous_sibling {
+            Some(previous) => self.nodes[previous.0].next_sibling = next_sibling,
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent.0].first_child = next_sibling;
+                }
+            }
+        }
+        match next_sibling {
+            Some(next) => self.nodes[next.0].previous_sibling = previous_sibling,
+            None => {
+                if let Some(parent) = parent {
+                    self.nodes[parent.0].last_child = previous_sibling;
+                }
+            }
+        }
+        let node = &mut self.nodes[id.0];
+        node.parent = None;
+        node.previous_sibling = None;
+        node.next_sibling = None;
+    }
+
+    /// Makes `child` the last child of `parent`, detaching it from where it
+    /// was. Panics if `child` is `parent` or one of its ancestors.
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        self.check_not_ancestor(child, parent);
+        self.detach(child);
+        let previous = self.last_child(parent);
+        match previous {
+            Some(previous) => self.nodes[previous.0].next_sibling = Some(child),
+            None => self.nodes[parent.0].first_child = Some(child),
+        }
+        self.nodes[parent.0].last_child = Some(child);
+        let node = &mut self.nodes[child.0];
+        node.parent = Some(parent);
+        node.previous_sibling = previous;
+    }
+
+    /// Puts `node` just before `sibling`, under the same parent, detaching
+    /// it from where it was. Panics if `sibling` has no parent, or if `node`
+    /// is one of its ancestors.
+    pub fn insert_before(&mut self, sibling: NodeId, node: NodeId) {
+        let parent = self.parent(sibling).expect("inserting next to a node without a parent");
+        self.check_not_ancestor(node, sibling);
+        self.detach(node);
+        let previous = self.previous_sibling(sibling);
+        match previous {
+            Some(previous) => self.nodes[previous.0].next_sibling = Some(node),
+            None => self.nodes[parent.0].first_child = Some(node),
+        }
+        self.nodes[sibling.0].previous_sibling = Some(node);
+        let inserted = &mut self.nodes[node.0];
+        inserted.parent = Some(parent);
+        inserted.previous_sibling = previous;
+        inserted.next_sibling = Some(sibling);
+    }
+
+    fn check_not_ancestor(&self, node: NodeId, of: NodeId) {
+        let mut ancestors = std::iter::successors(Some(of), |&at| self.parent(at));
+        assert!(ancestors.all(|at| at != node), "a node cannot be moved below itself");
+    }
+
+    /// The document as XML, without a declaration: elements with no
+    /// children as empty-element tags, text and attribute values escaped.
+    pub fn to_xml(&self) -> String {
+        let mut out = String::new();
+        self.write_node(self.root(), &mut out);
+        out
+    }
+
+    /// The node and everything below it as XML.
+    pub fn node_to_xml(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        self.write_node(id, &mut out);
+        out
+    }
+
+    fn write_node(&self, id: NodeId, out: &mut String) {
+        match self.kind(id) {
+            NodeKind::Document => {
+                for child in self.children(id) {
+                    self.write_node(child, out);
+                    out.push('\n');
+                }
+            }
+            NodeKind::Element { name, attributes } => {
+                out.push('<');
+                out.push_str(name);
+                for (attribute, value) in attributes {
+                    out.push(' ');
+                    out.push_str(attribute);
+                    out.push_str("=\"");
+                    out.push_str(&escape(value));
+                    out.push('"');
+                }
+                if self.first_child(id).is_none() {
+                    out.push_str("/>");
+                    return;
+                }
+                out.push('>');
+                for child in self.children(id) {
+                    self.write_node(child, out);
+                }
+                out.push_str("</");
+                out.push_str(name);
+                out.push('>');
+            }
+            NodeKind::Text(text) => out.push_str(&escape(text)),
+            NodeKind::Comment(comment) => {
+                out.push_str("<!--");
+                out.push_str(comment);
+                out.push_str("-->");
+            }
+            NodeKind::ProcessingInstruction { target, data } => {
+                out.push_str("<?");
+                out.push_str(target);
+                if !data.is_empty() {
+                    out.push(' ');
+                    out.push_str(data);
+                }
+                out.push_str("?>");
+            }
+        }
+    }
+}
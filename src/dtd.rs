@@ -0,0 +1,474 @@
+//! The internal DTD subset of a document: element, attribute list and entity
+//! declarations, for validation (`xmz validate --dtd`) and for expanding the
+//! entities declared there (`--expand-entities`).
+//!
+//! Parameter entities are expanded between declarations. External subsets and
+//! external entities are never read; references to them stay as written.
+
+use crate::check::line_column;
+use crate::parser::{Continue, Token, stream_xml_spans};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Most bytes that entity references may add to a document, against
+/// "billion laughs" declarations that grow exponentially.
+const MAX_EXPANSION: usize = 64 * 1024 * 1024;
+/// Deepest nesting of entity references within entity values.
+const MAX_NESTING: usize = 16;
+
+static EXPAND: AtomicBool = AtomicBool::new(false);
+
+/// Makes loaded documents have their declared entities expanded, for the rest
+/// of the process.
+pub fn set_expand_entities(expand: bool) {
+    EXPAND.store(expand, Ordering::SeqCst);
+}
+
+pub fn expand_entities_enabled() -> bool {
+    EXPAND.load(Ordering::SeqCst)
+}
+
+/// A content model: `(a, (b | c)*, d?)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Model {
+    Name(String),
+    Sequence(Vec<Model>),
+    Choice(Vec<Model>),
+    /// A model followed by `?`, `*` or `+`.
+    Repeat(Box<Model>, char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentSpec {
+    Empty,
+    Any,
+    /// `(#PCDATA | a | b)*`, with the element names allowed among the text.
+    Mixed(Vec<String>),
+    Children(Model),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeType {
+    Cdata,
+    Id,
+    IdRef,
+    IdRefs,
+    Entity,
+    Entities,
+    NmToken,
+    NmTokens,
+    /// `(a | b | c)`, or `NOTATION (a | b)`.
+    Enumeration(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeDefault {
+    Required,
+    Implied,
+    Fixed(String),
+    Value(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDef {
+    pub element: String,
+    pub name: String,
+    pub kind: AttributeType,
+    pub default: AttributeDefault,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entity {
+    /// Replacement text, with references still in it.
+    Internal(String),
+    External(String),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Dtd {
+    /// The name after `<!DOCTYPE`, which the root element must have.
+    pub root: String,
+    /// The external subset the DOCTYPE refers to, if any; it is not read.
+    pub system: Option<String>,
+    pub elements: Vec<(String, ContentSpec)>,
+    pub attributes: Vec<AttributeDef>,
+    /// General entities, in declaration order; the first declaration of a name wins.
+    pub entities: Vec<(String, Entity)>,
+}
+
+impl Dtd {
+    /// Parses the DOCTYPE of `xml`, if it has one before the root element.
+    /// Errors give the line and column of the malformed declaration.
+    pub fn parse(xml: &str) -> Result<Option<Dtd>, String> {
+        let Some((start, len)) = find_doctype(xml) else {
+            return Ok(None);
+        };
+        let mut parser = Parser {
+            text: Cow::Borrowed(&xml[start + "<!DOCTYPE".len()..start + len - 1]),
+            pos: 0,
+            parameters: Vec::new(),
+            spliced: false,
+        };
+        let at = |parser: &Parser| match parser.spliced {
+            false => line_column(xml, start + "<!DOCTYPE".len() + parser.pos),
+            true => line_column(xml, start),
+        };
+        let mut dtd = Dtd::default();
+        parser.skip_space();
+        dtd.root = parser.name().ok_or_else(|| located(at(&parser), "DOCTYPE without a name".to_string()))?;
+        parser.skip_space();
+        if parser.eat("SYSTEM") {
+            dtd.system = Some(parser.literal().map_err(|err| located(at(&parser), err))?);
+        } else if parser.eat("PUBLIC") {
+            parser.literal().map_err(|err| located(at(&parser), err))?;
+            dtd.system = Some(parser.literal().map_err(|err| located(at(&parser), err))?);
+        }
+        parser.skip_space();
+        if parser.eat("[") {
+            parser.subset(&mut dtd).map_err(|err| located(at(&parser), err))?;
+        }
+        Ok(Some(dtd))
+    }
+
+    pub fn entity(&self, name: &str) -> Option<&Entity> {
+        self.entities.iter().find(|(declared, _)| declared == name).map(|(_, entity)| entity)
+    }
+
+    /// `xml` with references to the internal entities declared here replaced
+    /// by their text, in content and attribute values. Other references, and
+    /// comments, CDATA sections and the DOCTYPE itself, are kept as written.
+    pub fn expand(&self, xml: &str) -> Result<String, String> {
+        let mut out = String::with_capacity(xml.len());
+        let mut budget = MAX_EXPANSION;
+        let mut offset = 0;
+        // Only text and start tags are expanded; the tokenizer's gaps are copied.
+        let mut spans = Vec::new();
+        stream_xml_spans(xml, |token, span| {
+            if !matches!(token, Token::EndTag(_) | Token::CData(_)) {
+                spans.push(span);
+            }
+            Continue(())
+        });
+        for span in spans {
+            out.push_str(&xml[offset..span.start]);
+            let in_tag = xml[span.start..].starts_with('<');
+            self.expand_into(&xml[span.clone()], in_tag, &mut out, &mut budget, &mut Vec::new())?;
+            offset = span.end;
+        }
+        out.push_str(&xml[offset..]);
+        Ok(out)
+    }
+
+    fn expand_into<'d>(&'d self, text: &str, in_tag: bool, out: &mut String, budget: &mut usize, open: &mut Vec<&'d str>) -> Result<(), String> {
+        let mut rest = text;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            rest = &rest[amp..];
+            let name = rest[1..].find(';').map(|semi| &rest[1..1 + semi]).filter(|name| !name.is_empty() && !name.starts_with('#'));
+            let Some((name, Entity::Internal(value))) = name.and_then(|name| self.entities.iter().find(|(declared, _)| declared == name)) else {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            };
+            if open.contains(&name.as_str()) {
+                return Err(format!("entity &{}; refers to itself", name));
+            }
+            if open.len() >= MAX_NESTING {
+                return Err(format!("entity &{}; is nested over {} levels deep", name, MAX_NESTING));
+            }
+            *budget = budget.checked_sub(value.len()).ok_or_else(|| format!("expanding entities would add over {} MiB", MAX_EXPANSION >> 20))?;
+            open.push(name);
+            if in_tag {
+                // Inside an attribute value: markup in the replacement text is escaped.
+                let mut expanded = String::new();
+                self.expand_into(value, false, &mut expanded, budget, open)?;
+                out.push_str(&expanded.replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;"));
+            } else {
+                self.expand_into(value, false, out, budget, open)?;
+            }
+            open.pop();
+            rest = &rest[name.len() + 2..];
+        }
+        out.push_str(rest);
+        Ok(())
+    }
+}
+
+fn located((line, column): (usize, usize), err: String) -> String {
+    format!("{}:{}: {}", line, column, err)
+}
+
+/// Byte offset and length of the DOCTYPE declaration before the root element.
+fn find_doctype(xml: &str) -> Option<(usize, usize)> {
+    let mut pos = 0;
+    let bytes = xml.as_bytes();
+    loop {
+        pos += bytes[pos..].iter().position(|&b| b == b'<')?;
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!DOCTYPE") {
+            return Some((pos, xmz_core::doctype_len(rest)?));
+        }
+        let end = if rest.starts_with(b"<?") {
+            memchr::memmem::find(rest, b"?>")?
+        } else if rest.starts_with(b"<!--") {
+            memchr::memmem::find(rest, b"-->")?
+        } else {
+            return None;
+        };
+        pos += end + 1;
+    }
+}
+
+/// Reads declarations from the DOCTYPE's body.
+struct Parser<'a> {
+    text: Cow<'a, str>,
+    pos: usize,
+    /// Parameter entities declared so far.
+    parameters: Vec<(String, String)>,
+    /// Whether a parameter entity has been spliced in, so that positions no
+    /// longer match the document.
+    spliced: bool,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self.rest().starts_with(word);
+        if found {
+            self.pos += word.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        self.skip_space();
+        match self.eat(word) {
+            true => Ok(()),
+            false => Err(format!("expected '{}'", word)),
+        }
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))).unwrap_or(rest.len());
+        let name = (len > 0).then(|| rest[..len].to_string());
+        self.pos += len;
+        name
+    }
+
+    fn required_name(&mut self, what: &str) -> Result<String, String> {
+        self.skip_space();
+        self.name().ok_or_else(|| format!("expected {}", what))
+    }
+
+    fn literal(&mut self) -> Result<String, String> {
+        self.skip_space();
+        let quote = self.rest().chars().next().filter(|c| matches!(c, '"' | '\'')).ok_or("expected a quoted literal")?;
+        let len = self.rest()[1..].find(quote).ok_or("unterminated literal")?;
+        let value = self.rest()[1..1 + len].to_string();
+        self.pos += len + 2;
+        Ok(value)
+    }
+
+    /// Skips past the next unquoted `>`.
+    fn skip_declaration(&mut self) -> Result<(), String> {
+        let len = xmz_core::doctype_len(self.rest().as_bytes()).ok_or("unterminated declaration")?;
+        self.pos += len;
+        Ok(())
+    }
+
+    fn subset(&mut self, dtd: &mut Dtd) -> Result<(), String> {
+        loop {
+            self.skip_space();
+            if self.rest().starts_with(']') {
+                return Ok(());
+            } else if self.rest().is_empty() {
+                return Err("the internal subset is not closed with ']'".to_string());
+            } else if self.rest().starts_with("<!--") {
+                self.pos += self.rest().find("-->").ok_or("unterminated comment")? + 3;
+            } else if self.rest().starts_with("<?") {
+                self.pos += self.rest().find("?>").ok_or("unterminated processing instruction")? + 2;
+            } else if self.eat("<!ELEMENT") {
+                let name = self.required_name("an element name")?;
+                self.skip_space();
+                let content = if self.eat("EMPTY") {
+                    ContentSpec::Empty
+                } else if self.eat("ANY") {
+                    ContentSpec::Any
+                } else {
+                    self.content()?
+                };
+                self.expect(">")?;
+                if !dtd.elements.iter().any(|(declared, _)| *declared == name) {
+                    dtd.elements.push((name, content));
+                }
+            } else if self.eat("<!ATTLIST") {
+                let element = self.required_name("an element name")?;
+                loop {
+                    self.skip_space();
+                    if self.eat(">") {
+                        break;
+                    }
+                    let name = self.required_name("an attribute name")?;
+                    let kind = self.attribute_type()?;
+                    self.skip_space();
+                    let default = if self.eat("#REQUIRED") {
+                        AttributeDefault::Required
+                    } else if self.eat("#IMPLIED") {
+                        AttributeDefault::Implied
+                    } else if self.eat("#FIXED") {
+                        AttributeDefault::Fixed(self.literal()?)
+                    } else {
+                        AttributeDefault::Value(self.literal()?)
+                    };
+                    if !dtd.attributes.iter().any(|def| def.element == element && def.name == name) {
+                        dtd.attributes.push(AttributeDef { element: element.clone(), name, kind, default });
+                    }
+                }
+            } else if self.eat("<!ENTITY") {
+                self.skip_space();
+                let parameter = self.eat("%");
+                let name = self.required_name("an entity name")?;
+                self.skip_space();
+                let entity = if self.eat("SYSTEM") {
+                    Entity::External(self.literal()?)
+                } else if self.eat("PUBLIC") {
+                    self.literal()?;
+                    Entity::External(self.literal()?)
+                } else {
+                    Entity::Internal(self.literal()?)
+                };
+                // Unparsed entities (NDATA) are external too.
+                self.skip_declaration()?;
+                match entity {
+                    Entity::Internal(value) if parameter => self.parameters.push((name, value)),
+                    _ if parameter => {}
+                    entity => {
+                        if !dtd.entities.iter().any(|(declared, _)| *declared == name) {
+                            dtd.entities.push((name, entity));
+                        }
+                    }
+                }
+            } else if self.rest().starts_with("<!") {
+                // NOTATION, and conditional sections, which only external subsets have.
+                self.skip_declaration()?;
+            } else if let Some(reference) = self.rest().strip_prefix('%') {
+                let name = reference[..reference.find(';').ok_or("'%' without ';'")?].to_string();
+                let value = self.parameters.iter().find(|(declared, _)| *declared == name).map(|(_, value)| value.clone());
+                let end = self.pos + name.len() + 2;
+                match value {
+                    Some(value) if self.text.len() + value.len() <= MAX_EXPANSION => {
+                        self.text = Cow::Owned(format!("{}{}", value, &self.text[end..]));
+                        self.pos = 0;
+                        self.spliced = true;
+                    }
+                    Some(_) => return Err(format!("expanding %{}; would add over {} MiB", name, MAX_EXPANSION >> 20)),
+                    // Declared in an external subset, which is not read.
+                    None => self.pos = end,
+                }
+            } else {
+                return Err(format!("unexpected '{}' in the internal subset", self.rest().chars().next().unwrap_or(' ')));
+            }
+        }
+    }
+
+    /// Parses `(#PCDATA | a)*` or a children model.
+    fn content(&mut self) -> Result<ContentSpec, String> {
+        let save = self.pos;
+        self.expect("(")?;
+        self.skip_space();
+        if !self.eat("#PCDATA") {
+            self.pos = save;
+            return Ok(ContentSpec::Children(self.particle()?));
+        }
+        let mut names = Vec::new();
+        loop {
+            self.skip_space();
+            if self.eat(")") {
+                self.eat("*");
+                return Ok(ContentSpec::Mixed(names));
+            }
+            self.expect("|")?;
+            names.push(self.required_name("an element name")?);
+        }
+    }
+
+    fn particle(&mut self) -> Result<Model, String> {
+        self.skip_space();
+        let model = if self.eat("(") {
+            let mut items = vec![self.particle()?];
+            let mut separator = None;
+            loop {
+                self.skip_space();
+                if self.eat(")") {
+                    break;
+                }
+                match (self.rest().chars().next(), separator) {
+                    (Some(c @ (',' | '|')), None) => separator = Some(c),
+                    (Some(c), Some(expected)) if c == expected => {}
+                    _ => return Err("expected ',', '|' or ')' in a content model".to_string()),
+                }
+                self.pos += 1;
+                items.push(self.particle()?);
+            }
+            match separator {
+                Some('|') => Model::Choice(items),
+                Some(_) => Model::Sequence(items),
+                None => items.remove(0),
+            }
+        } else {
+            Model::Name(self.required_name("an element name")?)
+        };
+        match self.rest().chars().next() {
+            Some(c @ ('?' | '*' | '+')) => {
+                self.pos += 1;
+                Ok(Model::Repeat(Box::new(model), c))
+            }
+            _ => Ok(model),
+        }
+    }
+
+    fn attribute_type(&mut self) -> Result<AttributeType, String> {
+        self.skip_space();
+        let notation = self.eat("NOTATION");
+        self.skip_space();
+        if self.eat("(") {
+            let mut values = Vec::new();
+            loop {
+                values.push(self.required_name("a value")?);
+                self.skip_space();
+                if self.eat(")") {
+                    return Ok(AttributeType::Enumeration(values));
+                }
+                self.expect("|")?;
+            }
+        }
+        if notation {
+            return Err("expected '(' after NOTATION".to_string());
+        }
+        let kinds = [
+            ("CDATA", AttributeType::Cdata),
+            ("IDREFS", AttributeType::IdRefs),
+            ("IDREF", AttributeType::IdRef),
+            ("ID", AttributeType::Id),
+            ("ENTITIES", AttributeType::Entities),
+            ("ENTITY", AttributeType::Entity),
+            ("NMTOKENS", AttributeType::NmTokens),
+            ("NMTOKEN", AttributeType::NmToken),
+        ];
+        for (word, kind) in kinds {
+            if self.eat(word) {
+                return Ok(kind);
+            }
+        }
+        Err("expected an attribute type".to_string())
+    }
+}
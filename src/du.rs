@@ -0,0 +1,92 @@
+//! Byte usage per element path (`xmz du`), to find the branches that make a file large.
+
+use crate::agg::write_table;
+use crate::parser::{Continue, Token, stream_xml_spans};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Total size and number of elements under one path prefix.
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    pub path: String,
+    pub bytes: u64,
+    pub count: u64,
+}
+
+/// Attributes the bytes of every element down to `max_depth` (1 = root only) to its path,
+/// largest first. An element's bytes include all of its descendants.
+pub fn usage_by_path(xml: &str, max_depth: usize) -> Vec<Usage> {
+    let mut usage: Vec<Usage> = Vec::new();
+    let mut by_path: HashMap<String, usize> = HashMap::new();
+    let mut path = String::new();
+    // Start offset and previous path length of each open element.
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+
+    stream_xml_spans(xml, |token, span| {
+        match token {
+            Token::StartTag(name, _) => {
+                stack.push((span.start, path.len()));
+                if stack.len() <= max_depth {
+                    path.push('/');
+                    path.push_str(name);
+                }
+            }
+            Token::EndTag(_) => {
+                if let Some((start, len)) = stack.pop() {
+                    if stack.len() < max_depth {
+                        let i = *by_path.entry(path.clone()).or_insert_with(|| {
+                            usage.push(Usage {
+                                path: path.clone(),
+                                ..Default::default()
+                            });
+                            usage.len() - 1
+                        });
+                        usage[i].bytes += (span.end - start) as u64;
+                        usage[i].count += 1;
+                    }
+                    path.truncate(len);
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+
+    usage.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    usage
+}
+
+/// Writes the breakdown as a table of size, share of the file, element count and path.
+pub fn write_usage<W: Write>(out: &mut W, xml: &str, max_depth: usize) -> io::Result<()> {
+    let usage = usage_by_path(xml, max_depth);
+    if usage.is_empty() {
+        return writeln!(out, "No elements.");
+    }
+    let total = xml.len().max(1) as f64;
+    let mut rows = vec![vec!["size".to_string(), "share".into(), "count".into(), "path".into()]];
+    for entry in &usage {
+        rows.push(vec![
+            format_bytes(entry.bytes),
+            format!("{:.1}%", entry.bytes as f64 * 100.0 / total),
+            entry.count.to_string(),
+            entry.path.clone(),
+        ]);
+    }
+    write_table(out, &rows)
+}
+
+/// Formats a byte count with a binary unit, e.g. `12.3 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,343 @@
+//! Character encoding detection and transcoding to UTF-8.
+//!
+//! The parser works on UTF-8 text. Documents in UTF-16 (recognised by their
+//! byte order mark or by the zero bytes around the leading `<`) or in a legacy
+//! single-byte encoding named by the `encoding=` pseudo-attribute of the XML
+//! declaration are converted first. UTF-8 input, the common case, is used in
+//! place without copying.
+//!
+//! Malformed input is an error, unless decoding is lossy: then each invalid
+//! sequence becomes U+FFFD and is counted in `Replacements`, so a slightly
+//! corrupted file can still be inspected.
+
+use crate::sniff::sniff;
+use std::borrow::Cow;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+/// Characters for bytes 0x80..=0x9F in windows-1252 (the rest match Latin-1).
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—',
+    '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Number of invalid sequences replaced during lossy decoding, shared with
+/// the reader doing the replacing.
+#[derive(Debug, Clone, Default)]
+pub struct Replacements(Arc<AtomicUsize>);
+
+impl Replacements {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, n: usize) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Encoding {
+    /// Looks up an encoding label as used in `encoding="..."`.
+    pub fn for_label(label: &str) -> Option<Self> {
+        let encoding = match label.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" => Encoding::Utf8,
+            "utf-16le" => Encoding::Utf16Le,
+            "utf-16be" => Encoding::Utf16Be,
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => Encoding::Latin1,
+            "windows-1252" | "cp1252" | "x-cp1252" => Encoding::Windows1252,
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    /// Detects the encoding from the start of a document, returning it with
+    /// the length of the byte order mark to skip.
+    pub fn detect(prefix: &[u8]) -> io::Result<(Self, usize)> {
+        match prefix {
+            [0xef, 0xbb, 0xbf, ..] => return Ok((Encoding::Utf8, 3)),
+            [0xff, 0xfe, ..] => return Ok((Encoding::Utf16Le, 2)),
+            [0xfe, 0xff, ..] => return Ok((Encoding::Utf16Be, 2)),
+            [b'<', 0, b'?', 0, ..] => return Ok((Encoding::Utf16Le, 0)),
+            [0, b'<', 0, b'?', ..] => return Ok((Encoding::Utf16Be, 0)),
+            _ => {}
+        }
+        match declared_encoding(prefix) {
+            None => Ok((Encoding::Utf8, 0)),
+            // A UTF-16 declaration without a BOM or zero bytes cannot be right.
+            Some(label) if label.eq_ignore_ascii_case("utf-16") => Ok((Encoding::Utf8, 0)),
+            Some(label) => Self::for_label(label)
+                .map(|encoding| (encoding, 0))
+                .ok_or_else(|| invalid(format!("unsupported encoding '{}'", label))),
+        }
+    }
+}
+
+/// The `encoding` pseudo-attribute of a leading `<?xml ...?>` declaration.
+fn declared_encoding(prefix: &[u8]) -> Option<&str> {
+    let end = memchr::memmem::find(prefix, b"?>")?;
+    let decl = std::str::from_utf8(prefix.strip_prefix(b"<?xml")?.get(..end - 5)?).ok()?;
+    let rest = &decl[decl.find("encoding")? + "encoding".len()..];
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest.chars().next().filter(|q| *q == '"' || *q == '\'')?;
+    let rest = &rest[1..];
+    Some(&rest[..rest.find(quote)?])
+}
+
+/// Decodes a whole document to UTF-8, borrowing when it already is UTF-8.
+pub fn decode(bytes: &[u8]) -> io::Result<Cow<'_, str>> {
+    decode_with(bytes, None)
+}
+
+/// Like `decode`, but replaces invalid sequences instead of failing; returns
+/// the text and the number of replacements.
+pub fn decode_lossy(bytes: &[u8]) -> io::Result<(Cow<'_, str>, usize)> {
+    let replacements = Replacements::default();
+    let text = decode_with(bytes, Some(&replacements))?;
+    Ok((text, replacements.count()))
+}
+
+/// Fails with an explanation if UTF-8 input is clearly something other than XML.
+fn refuse_non_xml(encoding: Encoding, prefix: &[u8], lossy: bool) -> io::Result<()> {
+    match sniff(prefix, lossy) {
+        Some(format) if encoding == Encoding::Utf8 && format.is_fatal() => Err(invalid(format.describe())),
+        _ => Ok(()),
+    }
+}
+
+fn decode_with<'a>(bytes: &'a [u8], lossy: Option<&Replacements>) -> io::Result<Cow<'a, str>> {
+    let (encoding, bom) = Encoding::detect(&bytes[..bytes.len().min(1024)])?;
+    let body = &bytes[bom..];
+    refuse_non_xml(encoding, &body[..body.len().min(1024)], lossy.is_some())?;
+    match (encoding, lossy) {
+        (Encoding::Utf8, None) => std::str::from_utf8(body)
+            .map(Cow::Borrowed)
+            .map_err(|err| invalid(format!("invalid UTF-8 at byte {}", bom + err.valid_up_to()))),
+        (Encoding::Utf8, Some(replacements)) => {
+            replacements.add(body.utf8_chunks().filter(|chunk| !chunk.invalid().is_empty()).count());
+            Ok(String::from_utf8_lossy(body))
+        }
+        _ => {
+            let mut decoder = Decoder::new(encoding, lossy.cloned());
+            let mut out = String::with_capacity(body.len());
+            decoder.push(body, &mut out)?;
+            decoder.finish(&mut out)?;
+            Ok(Cow::Owned(out))
+        }
+    }
+}
+
+/// Incremental decoder for the non-UTF-8 encodings.
+struct Decoder {
+    encoding: Encoding,
+    /// A trailing byte, or high surrogate, carried over between chunks.
+    pending: Vec<u8>,
+    high_surrogate: Option<u16>,
+    /// Set for lossy decoding, which replaces malformed input.
+    lossy: Option<Replacements>,
+}
+
+impl Decoder {
+    fn new(encoding: Encoding, lossy: Option<Replacements>) -> Self {
+        Self {
+            encoding,
+            pending: Vec::new(),
+            high_surrogate: None,
+            lossy,
+        }
+    }
+
+    /// Replaces a malformed sequence when lossy, and fails otherwise.
+    fn malformed(&self, message: &str, out: &mut String) -> io::Result<()> {
+        match &self.lossy {
+            Some(replacements) => {
+                replacements.add(1);
+                out.push(char::REPLACEMENT_CHARACTER);
+                Ok(())
+            }
+            None => Err(invalid(message.to_string())),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8], out: &mut String) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Utf8 => unreachable!("UTF-8 is passed through"),
+            Encoding::Latin1 => out.extend(bytes.iter().map(|&b| b as char)),
+            Encoding::Windows1252 => out.extend(bytes.iter().map(|&b| match b {
+                0x80..=0x9f => WINDOWS_1252[(b - 0x80) as usize],
+                _ => b as char,
+            })),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut input = bytes;
+                if let Some(first) = self.pending.pop() {
+                    let Some((&second, rest)) = input.split_first() else {
+                        self.pending.push(first);
+                        return Ok(());
+                    };
+                    self.unit([first, second], out)?;
+                    input = rest;
+                }
+                let mut units = input.chunks_exact(2);
+                for pair in &mut units {
+                    self.unit([pair[0], pair[1]], out)?;
+                }
+                self.pending.extend_from_slice(units.remainder());
+            }
+        }
+        Ok(())
+    }
+
+    fn unit(&mut self, pair: [u8; 2], out: &mut String) -> io::Result<()> {
+        let unit = match self.encoding {
+            Encoding::Utf16Le => u16::from_le_bytes(pair),
+            _ => u16::from_be_bytes(pair),
+        };
+        if let Some(high) = self.high_surrogate.take() {
+            if let 0xdc00..=0xdfff = unit {
+                let code = 0x10000 + (((high - 0xd800) as u32) << 10) + (unit - 0xdc00) as u32;
+                out.push(char::from_u32(code).expect("valid surrogate pair"));
+                return Ok(());
+            }
+            self.malformed("unpaired UTF-16 surrogate", out)?;
+        }
+        match unit {
+            0xd800..=0xdbff => self.high_surrogate = Some(unit),
+            _ => match char::from_u32(unit as u32) {
+                Some(c) => out.push(c),
+                None => self.malformed("unpaired UTF-16 surrogate", out)?,
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut String) -> io::Result<()> {
+        if !self.pending.is_empty() || self.high_surrogate.is_some() {
+            self.pending.clear();
+            self.high_surrogate = None;
+            self.malformed("truncated UTF-16 input", out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `inner` so that it yields UTF-8, detecting the encoding from the
+/// first kilobyte. With `lossy`, malformed input is replaced and counted
+/// instead of failing the read.
+pub fn decoding_reader(mut inner: Box<dyn Read + Send>, lossy: Option<&Replacements>) -> io::Result<Box<dyn Read + Send>> {
+    let mut prefix = Vec::with_capacity(1024);
+    (&mut inner).take(1024).read_to_end(&mut prefix)?;
+    let (encoding, bom) = Encoding::detect(&prefix)?;
+    refuse_non_xml(encoding, &prefix[bom..], lossy.is_some())?;
+    let prefix = io::Cursor::new(prefix.split_off(bom));
+    let chained = Box::new(prefix.chain(inner));
+    match (encoding, lossy) {
+        (Encoding::Utf8, None) => Ok(chained),
+        (Encoding::Utf8, Some(replacements)) => Ok(Box::new(LossyUtf8Reader {
+            inner: chained,
+            pending: Vec::new(),
+            out: String::new(),
+            pos: 0,
+            replacements: replacements.clone(),
+        })),
+        _ => Ok(Box::new(DecodingReader {
+            inner: chained,
+            decoder: Decoder::new(encoding, lossy.cloned()),
+            out: String::new(),
+            pos: 0,
+        })),
+    }
+}
+
+/// Copies as much of `out[*pos..]` into `buf` as fits.
+fn drain_into(out: &str, pos: &mut usize, buf: &mut [u8]) -> usize {
+    let n = buf.len().min(out.len() - *pos);
+    buf[..n].copy_from_slice(&out.as_bytes()[*pos..*pos + n]);
+    *pos += n;
+    n
+}
+
+struct DecodingReader {
+    inner: Box<dyn Read + Send>,
+    decoder: Decoder,
+    out: String,
+    pos: usize,
+}
+
+impl Read for DecodingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() {
+            self.out.clear();
+            self.pos = 0;
+            let mut chunk = [0u8; 32 * 1024];
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                self.decoder.finish(&mut self.out)?;
+                if self.out.is_empty() {
+                    return Ok(0);
+                }
+                break;
+            }
+            self.decoder.push(&chunk[..n], &mut self.out)?;
+        }
+        Ok(drain_into(&self.out, &mut self.pos, buf))
+    }
+}
+
+/// UTF-8 passed through with invalid sequences replaced.
+struct LossyUtf8Reader {
+    inner: Box<dyn Read + Send>,
+    /// An incomplete sequence at the end of the last read.
+    pending: Vec<u8>,
+    out: String,
+    pos: usize,
+    replacements: Replacements,
+}
+
+impl Read for LossyUtf8Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() {
+            self.out.clear();
+            self.pos = 0;
+            let mut chunk = [0u8; 32 * 1024];
+            let n = self.inner.read(&mut chunk)?;
+            self.pending.extend_from_slice(&chunk[..n]);
+            if self.pending.is_empty() {
+                return Ok(0);
+            }
+            let mut keep = 0;
+            let mut pieces = self.pending.utf8_chunks().peekable();
+            while let Some(piece) = pieces.next() {
+                self.out.push_str(piece.valid());
+                let bad = piece.invalid();
+                if bad.is_empty() {
+                    continue;
+                }
+                // A sequence cut off by the end of this read may still complete.
+                let incomplete = n > 0
+                    && pieces.peek().is_none()
+                    && std::str::from_utf8(bad).err().is_some_and(|err| err.error_len().is_none());
+                if incomplete {
+                    keep = bad.len();
+                } else {
+                    self.replacements.add(1);
+                    self.out.push(char::REPLACEMENT_CHARACTER);
+                }
+            }
+            self.pending.drain(..self.pending.len() - keep);
+        }
+        Ok(drain_into(&self.out, &mut self.pos, buf))
+    }
+}
@@ -0,0 +1,147 @@
+//! Running a command over many files, such as a dataset split into a file
+//! per day: glob patterns to name them, and threads that read and process
+//! several at once while their results are passed on in order.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+/// Whether `path` is a glob pattern rather than a plain path.
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// The paths matching `pattern`, in name order. Within a name, `*` matches
+/// any characters, `?` one character and `[a-z]` (or `[!a-z]`) one of a set;
+/// a `**` component matches any number of directories. Names starting with
+/// `.` are only matched by a pattern that starts with `.` too.
+pub fn glob(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut found = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !is_glob(&part) {
+            for path in &mut found {
+                path.push(component);
+            }
+            continue;
+        }
+        let part: Vec<char> = part.chars().collect();
+        let mut next = Vec::new();
+        for base in &found {
+            if part == ['*', '*'] {
+                directories(base, &mut next)?;
+                continue;
+            }
+            let dir = if base.as_os_str().is_empty() { Path::new(".") } else { base.as_path() };
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries {
+                let name = entry?.file_name();
+                let chars: Vec<char> = name.to_string_lossy().chars().collect();
+                if (chars.first() != Some(&'.') || part.first() == Some(&'.')) && matches(&part, &chars) {
+                    next.push(base.join(name));
+                }
+            }
+        }
+        next.sort();
+        found = next;
+    }
+    found.retain(|path| path.exists());
+    Ok(found)
+}
+
+/// `dir` and every directory below it that is not hidden.
+fn directories(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    found.push(dir.to_path_buf());
+    let Ok(entries) = std::fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            directories(&dir.join(entry.file_name()), found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the name `name` matches the pattern `pattern`.
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+        Some(('[', rest)) => {
+            // A `]` right after the `[` is part of the set; without a
+            // closing `]`, the `[` is an ordinary character.
+            let Some(close) = rest.iter().skip(1).position(|&c| c == ']').map(|at| at + 1) else {
+                return name.first() == Some(&'[') && matches(rest, &name[1..]);
+            };
+            let Some((&c, name)) = name.split_first() else {
+                return false;
+            };
+            let (negated, set) = match rest[..close].split_first() {
+                Some(('!' | '^', set)) => (true, set),
+                _ => (false, &rest[..close]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && matches(&rest[close + 1..], name)
+        }
+        Some((p, rest)) => name.first() == Some(p) && matches(rest, &name[1..]),
+    }
+}
+
+/// Calls `work` on each of `files` on up to `jobs` threads, and `on_result`
+/// with each result in the order of `files` as soon as it and those before
+/// it are done. An error from `on_result` stops the work and is returned.
+pub fn for_each_file<T, W, R>(files: &[PathBuf], jobs: usize, work: W, mut on_result: R) -> io::Result<()>
+where
+    T: Send,
+    W: Fn(&Path) -> T + Sync,
+    R: FnMut(&Path, T) -> io::Result<()>,
+{
+    let next = AtomicUsize::new(0);
+    let (send, receive) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, files.len().max(1)) {
+            let send = send.clone();
+            let (next, work) = (&next, &work);
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= files.len() || send.send((i, work(&files[i]))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(send);
+        // Results that came before those of earlier files.
+        let mut early = BTreeMap::new();
+        let mut done = 0;
+        for (i, result) in receive.iter() {
+            early.insert(i, result);
+            while let Some(result) = early.remove(&done) {
+                if let Err(err) = on_result(&files[done], result) {
+                    next.store(files.len(), Ordering::Relaxed);
+                    return Err(err);
+                }
+                done += 1;
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,226 @@
+//! `xmz format`: pretty-prints the root element, or only the elements around
+//! changed regions so that a diff of an edited, otherwise unformatted file
+//! against version control shows the edit and nothing else.
+//!
+//! Elements with only element children (and comments or processing
+//! instructions) are broken into one child per line. Leaves and elements with
+//! text or CDATA are copied as they are, since whitespace in them may matter.
+//! Tags keep their attributes byte for byte, and everything outside the
+//! reformatted elements is copied unchanged.
+
+use crate::check::line_start;
+use crate::parser::{Continue, Token, stream_xml_spans};
+use crate::xml::XmlExplorer;
+use std::ops::Range;
+
+pub struct FormatOptions {
+    /// Spaces per nesting level.
+    pub indent: usize,
+}
+
+pub struct Formatted {
+    pub output: String,
+    /// Input byte ranges of the elements that were reformatted, in order.
+    pub elements: Vec<Range<usize>>,
+}
+
+/// One piece of a reformatted element, with its byte range in the element.
+enum Item {
+    Start(Range<usize>),
+    End(Range<usize>),
+    Text,
+    /// A comment, processing instruction or CDATA section between tokens.
+    Markup(Range<usize>),
+}
+
+/// Pretty-prints the root element; the prolog and anything after the root
+/// are left as they are.
+pub fn format(xml: &str, options: &FormatOptions) -> Formatted {
+    let targets = XmlExplorer::new(xml).root().map(|root| root.span).into_iter().collect();
+    reformat(xml, targets, options)
+}
+
+/// Pretty-prints only the elements touching `regions` (byte ranges of `xml`):
+/// for each region, the children of the innermost element containing it that
+/// overlap it, or the root if no element contains it.
+pub fn format_regions(xml: &str, regions: &[Range<usize>], options: &FormatOptions) -> Formatted {
+    // Per region: depth of the innermost element containing it, and the
+    // overlapping elements that do not contain it, with their depths.
+    let mut containers: Vec<Option<usize>> = vec![None; regions.len()];
+    let mut overlapping: Vec<Vec<(usize, Range<usize>)>> = vec![Vec::new(); regions.len()];
+    let mut open = Vec::new();
+    stream_xml_spans(xml, |token, span| {
+        match token {
+            Token::StartTag(..) => open.push(span.start),
+            Token::EndTag(_) => {
+                let Some(start) = open.pop() else {
+                    return Continue(());
+                };
+                let element = start..span.end;
+                let depth = open.len();
+                for (i, region) in regions.iter().enumerate() {
+                    if element.start <= region.start && region.end <= element.end {
+                        // Children close first, so the first container is the innermost.
+                        containers[i].get_or_insert(depth);
+                    } else if element.start < region.end && region.start < element.end {
+                        overlapping[i].push((depth, element.clone()));
+                    }
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    let mut targets: Vec<Range<usize>> = containers
+        .iter()
+        .zip(overlapping)
+        .flat_map(|(container, elements)| {
+            let depth = container.map_or(0, |depth| depth + 1);
+            elements.into_iter().filter(move |(d, _)| *d == depth).map(|(_, span)| span)
+        })
+        .collect();
+    targets.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end)));
+    // Regions touching nested elements may select both; keep the outer one.
+    let mut end = 0;
+    targets.retain(|span| {
+        let keep = span.start >= end;
+        end = end.max(span.end);
+        keep
+    });
+    reformat(xml, targets, options)
+}
+
+/// The region of `xml` that differs from `base`, between their common prefix
+/// and suffix, or None if they are the same.
+pub fn changed_region(base: &str, xml: &str) -> Option<Range<usize>> {
+    if base == xml {
+        return None;
+    }
+    let (a, b) = (base.as_bytes(), xml.as_bytes());
+    let mut prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    while !xml.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let limit = a.len().min(b.len()) - prefix;
+    let suffix = a.iter().rev().zip(b.iter().rev()).take(limit).take_while(|(x, y)| x == y).count();
+    let mut end = b.len() - suffix;
+    while !xml.is_char_boundary(end) {
+        end += 1;
+    }
+    Some(prefix..end)
+}
+
+/// Byte range of 1-based lines `first..=last`, or None if `first` is past the
+/// end of the document.
+pub fn line_range(xml: &str, first: usize, last: usize) -> Option<Range<usize>> {
+    let start = line_start(xml, first)?;
+    let end = line_start(xml, last.max(first) + 1).unwrap_or(xml.len());
+    Some(start..end)
+}
+
+/// Copies `xml` with each of the disjoint, sorted `targets` pretty-printed.
+fn reformat(xml: &str, targets: Vec<Range<usize>>, options: &FormatOptions) -> Formatted {
+    let mut output = String::with_capacity(xml.len() + xml.len() / 8);
+    let mut copied = 0;
+    for span in &targets {
+        output.push_str(&xml[copied..span.start]);
+        // Nested lines are indented from the line the element starts on.
+        let line = xml[..span.start].rfind('\n').map_or(0, |newline| newline + 1);
+        let base = &xml[line..line + xml[line..span.start].bytes().take_while(|b| matches!(b, b' ' | b'\t')).count()];
+        format_element(&xml[span.clone()], base, options.indent, &mut output);
+        copied = span.end;
+    }
+    output.push_str(&xml[copied..]);
+    Formatted { output, elements: targets }
+}
+
+/// Appends the pretty-printed `element` (a whole, well-formed element) to `out`.
+fn format_element(element: &str, base: &str, indent: usize, out: &mut String) {
+    let mut items = Vec::new();
+    // Per start tag: the index of its end tag, and whether it must be copied
+    // as it is (a leaf, or text or CDATA among its children).
+    let mut close = Vec::new();
+    let mut inline = Vec::new();
+    let mut has_child = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    let mut consumed = 0;
+    stream_xml_spans(element, |token, span| {
+        let gap = &element[consumed..span.start];
+        let trimmed = gap.trim();
+        if !trimmed.is_empty() {
+            let at = consumed + gap.find(trimmed).unwrap_or(0);
+            if trimmed.starts_with("<![CDATA[")
+                && let Some(&parent) = open.last()
+            {
+                inline[parent] = true;
+            }
+            items.push(Item::Markup(at..at + trimmed.len()));
+        }
+        consumed = span.end;
+        match token {
+            Token::StartTag(..) => {
+                if let Some(&parent) = open.last() {
+                    has_child[parent] = true;
+                }
+                open.push(items.len());
+                items.push(Item::Start(span));
+            }
+            Token::EndTag(_) => {
+                if let Some(start) = open.pop() {
+                    close[start] = items.len();
+                }
+                items.push(Item::End(span));
+            }
+            Token::Text(_) => {
+                if let Some(&parent) = open.last() {
+                    inline[parent] = true;
+                }
+                items.push(Item::Text);
+            }
+            Token::CData(_) => {
+                if let Some(&parent) = open.last() {
+                    inline[parent] = true;
+                }
+                items.push(Item::Markup(span));
+            }
+        }
+        close.resize(items.len(), 0);
+        inline.resize(items.len(), false);
+        has_child.resize(items.len(), false);
+        Continue(())
+    });
+    let mut depth = 0;
+    let mut i = 0;
+    while i < items.len() {
+        if i > 0 {
+            let level = match items[i] {
+                Item::End(_) => depth - 1,
+                _ => depth,
+            };
+            out.push('\n');
+            out.push_str(base);
+            out.extend(std::iter::repeat_n(' ', level * indent));
+        }
+        match &items[i] {
+            Item::Start(span) if inline[i] || !has_child[i] => {
+                let end = match &items[close[i]] {
+                    Item::End(end) => end.end,
+                    _ => span.end,
+                };
+                out.push_str(&element[span.start..end]);
+                i = close[i].max(i);
+            }
+            Item::Start(span) => {
+                out.push_str(&element[span.clone()]);
+                depth += 1;
+            }
+            Item::End(span) => {
+                out.push_str(&element[span.clone()]);
+                depth -= 1;
+            }
+            Item::Markup(span) => out.push_str(&element[span.clone()]),
+            Item::Text => {}
+        }
+        i += 1;
+    }
+}
@@ -0,0 +1,184 @@
+//! `xmz grep`: the elements whose tag, an attribute value or own text match
+//! a regular expression, found in one pass over the document.
+//!
+//! Own text is the text directly inside an element, not that of its
+//! children, so a match in a `<title>` reports the title and not every
+//! element around it. Each hit is printed as its path, numbered among
+//! same-named siblings as `xmz diff` does, and the element on one line.
+//! Context is counted in sibling elements, the way `grep -A/-B` counts
+//! lines.
+
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, flat_text, stream_xml_spans, text_content};
+use crate::regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Characters of text shown for each element before it is cut off.
+const PREVIEW: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrepOptions {
+    /// Siblings to show before and after each match.
+    pub before: usize,
+    pub after: usize,
+}
+
+/// A matching element, or a sibling shown as context.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub path: String,
+    pub span: Range<usize>,
+    pub matched: bool,
+    /// Which element the hit is a child of, and its place among the
+    /// children, to tell runs of neighbouring siblings apart.
+    parent: usize,
+    index: usize,
+}
+
+struct Open<'a> {
+    /// A number unique to this element, for `Hit::parent`.
+    id: usize,
+    path: String,
+    start: usize,
+    matched: bool,
+    text: String,
+    children: usize,
+    /// Child elements seen by name, for their positions.
+    seen: HashMap<&'a str, usize>,
+    /// The last `before` children closed, with their paths.
+    recent: VecDeque<(String, Range<usize>)>,
+    /// Children still to show after a match.
+    after: usize,
+}
+
+/// The elements of `xml` matching `regex`, and with context their
+/// siblings, in document order.
+pub fn grep(xml: &str, regex: &Regex, options: GrepOptions) -> Vec<Hit> {
+    let mut hits = Vec::new();
+    let mut open: Vec<Open> = Vec::new();
+    let mut ids = 0;
+    let mut last = 0;
+    stream_xml_spans(xml, |token, span| {
+        if matches!(token, Token::Text(_) | Token::CData(_)) {
+            return Continue(());
+        }
+        if let Some(element) = open.last_mut() {
+            element.text.push_str(&text_content(&xml[last..span.start]));
+        }
+        last = span.end;
+        match token {
+            Token::StartTag(name, attrs) => {
+                let path = match open.last_mut() {
+                    Some(parent) => {
+                        let count = parent.seen.entry(name).or_default();
+                        *count += 1;
+                        format!("{}/{}[{}]", parent.path, name, count)
+                    }
+                    None => format!("/{}", name),
+                };
+                let matched = regex.is_match(name) || attribute_pairs(attrs).iter().any(|(_, value)| regex.is_match(&decode_entities(value)));
+                ids += 1;
+                open.push(Open {
+                    id: ids,
+                    path,
+                    start: span.start,
+                    matched,
+                    text: String::new(),
+                    children: 0,
+                    seen: HashMap::new(),
+                    recent: VecDeque::new(),
+                    after: 0,
+                });
+            }
+            Token::EndTag(_) => {
+                let Some(element) = open.pop() else {
+                    return Continue(());
+                };
+                let text = element.text.split_whitespace().collect::<Vec<_>>().join(" ");
+                let matched = element.matched || (!text.is_empty() && regex.is_match(&text));
+                let (parent, index) = match open.last_mut() {
+                    Some(parent) => {
+                        parent.children += 1;
+                        (parent.id, parent.children)
+                    }
+                    None => (0, 1),
+                };
+                let range = element.start..span.end;
+                let hit = |path: String, span: Range<usize>, matched: bool, index: usize| Hit { path, span, matched, parent, index };
+                if matched {
+                    if let Some(parent) = open.last_mut() {
+                        let first = index - parent.recent.len();
+                        for (i, (path, span)) in parent.recent.drain(..).enumerate() {
+                            hits.push(hit(path, span, false, first + i));
+                        }
+                        parent.after = options.after;
+                    }
+                    hits.push(hit(element.path, range, true, index));
+                } else if let Some(parent) = open.last_mut() {
+                    if parent.after > 0 {
+                        parent.after -= 1;
+                        hits.push(hit(element.path, range, false, index));
+                    } else if options.before > 0 {
+                        if parent.recent.len() == options.before {
+                            parent.recent.pop_front();
+                        }
+                        parent.recent.push_back((element.path, range));
+                    }
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    // Matches are found as their elements close, after the matches inside
+    // them.
+    hits.sort_by_key(|hit| hit.span.start);
+    hits
+}
+
+/// `element` on one line: its start tag as written, with whitespace
+/// collapsed, and the beginning of its text.
+fn preview(element: &str) -> String {
+    let tag_end = element.find('>').map_or(element.len(), |at| at + 1);
+    let mut line = element[..tag_end].split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.ends_with("/>") {
+        return line;
+    }
+    let text = flat_text(element, PREVIEW + 1);
+    if text.chars().count() > PREVIEW {
+        line.extend(text.chars().take(PREVIEW));
+        line.push('…');
+    } else {
+        line.push_str(&text);
+    }
+    let name = element[1..tag_end].split(|c: char| c.is_whitespace() || c == '>' || c == '/').next().unwrap_or("");
+    line.push_str("</");
+    line.push_str(name);
+    line.push('>');
+    line
+}
+
+/// Writes `path: element` for each match and `path- element` for context,
+/// like `grep`, with `--` between runs of siblings that are not adjacent.
+/// With a `file`, each line starts with it, as `file:path: element`.
+pub fn write_hits<W: Write>(out: &mut W, file: Option<&str>, xml: &str, hits: &[Hit], options: GrepOptions) -> io::Result<()> {
+    let context = options.before > 0 || options.after > 0;
+    let mut previous: Option<&Hit> = None;
+    for hit in hits {
+        if context
+            && let Some(previous) = previous
+            && !(previous.parent == hit.parent && previous.index + 1 == hit.index)
+            && !previous.span.contains(&hit.span.start)
+        {
+            writeln!(out, "--")?;
+        }
+        let separator = if hit.matched { ':' } else { '-' };
+        if let Some(file) = file {
+            write!(out, "{}{}", file, separator)?;
+        }
+        writeln!(out, "{}{} {}", hit.path, separator, preview(&xml[hit.span.clone()]))?;
+        previous = Some(hit);
+    }
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Content hashes of documents and subtrees (`xmz hash`). Elements are
+//! hashed in a canonical form, so two copies of a record hash alike however
+//! they were written:
+//!
+//! - every element is written as a start and an end tag, empty or not;
+//! - attributes are sorted by name, their values with entities decoded,
+//!   whitespace normalised to spaces and `& < "` escaped as `&amp; &lt; &quot;`;
+//! - text has entities decoded, CDATA sections unwrapped, line ends as `\n`
+//!   and `& < >` escaped, and text that is only whitespace is left out;
+//! - comments and processing instructions are left out.
+//!
+//! Namespace declarations count as attributes and prefixes are kept as
+//! written. The digest is SHA-256 of that form.
+
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans, text_content};
+use crate::query::{Query, for_each_match};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// SHA-256 (FIPS 180-4).
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    len: u64,
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be,
+    0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa,
+    0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85,
+    0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3,
+    0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f,
+    0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+            block: [0; 64],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let n = (64 - self.filled).min(bytes.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&bytes[..n]);
+            self.filled += n;
+            bytes = &bytes[n..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bits = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, add) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+}
+
+/// Lower-case hex of `digest`.
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Escapes `text` for the canonical form; `quote` also escapes `"` and
+/// turns whitespace into spaces, for attribute values.
+fn escape(text: &str, quote: bool, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' if !quote => out.push_str("&gt;"),
+            '"' if quote => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' if quote => out.push(' '),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Passes the canonical form of the element or document `xml` to `sink`,
+/// a piece at a time.
+pub fn canonicalize(xml: &str, mut sink: impl FnMut(&str)) {
+    let mut piece = String::new();
+    let mut last = None;
+    stream_xml_spans(xml, |token, span| {
+        if matches!(token, Token::Text(_) | Token::CData(_)) {
+            return Continue(());
+        }
+        piece.clear();
+        // Text is taken from between tags, where CDATA sections and
+        // comments are left to `text_content`. Nothing before the first tag
+        // is part of an element.
+        if let Some(last) = last
+            && span.start > last
+        {
+            let text = text_content(&xml[last..span.start]);
+            if !text.trim().is_empty() {
+                escape(&text.replace("\r\n", "\n").replace('\r', "\n"), false, &mut piece);
+            }
+        }
+        last = Some(span.end);
+        match token {
+            Token::StartTag(name, attrs) => {
+                piece.push('<');
+                piece.push_str(name);
+                let mut pairs = attribute_pairs(attrs);
+                pairs.sort_by_key(|&(name, _)| name);
+                for (name, value) in pairs {
+                    piece.push(' ');
+                    piece.push_str(name);
+                    piece.push_str("=\"");
+                    let value = value.replace(['\t', '\n', '\r'], " ");
+                    escape(&decode_entities(&value), true, &mut piece);
+                    piece.push('"');
+                }
+                piece.push('>');
+            }
+            Token::EndTag(name) => {
+                piece.push_str("</");
+                piece.push_str(name.trim_end());
+                piece.push('>');
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        sink(&piece);
+        Continue(())
+    });
+}
+
+/// The canonical form of `xml` as a string.
+pub fn canonical(xml: &str) -> String {
+    let mut out = String::new();
+    canonicalize(xml, |piece| out.push_str(piece));
+    out
+}
+
+/// SHA-256 of the canonical form of `xml`.
+pub fn digest(xml: &str) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    canonicalize(xml, |piece| sha.update(piece.as_bytes()));
+    sha.finish()
+}
+
+/// The digest of every element matching `query`, in document order.
+pub fn digest_matches(xml: &str, query: &Query) -> Vec<(Range<usize>, [u8; 32])> {
+    let mut matches = Vec::new();
+    for_each_match(xml, query, |range| {
+        matches.push((range.clone(), digest(&xml[range])));
+        Continue(())
+    });
+    matches.sort_by_key(|(range, _)| range.start);
+    matches
+}
+
+/// 1-based line of each offset in `offsets`, which must be ascending.
+fn lines(xml: &str, offsets: impl Iterator<Item = usize>) -> Vec<usize> {
+    let bytes = xml.as_bytes();
+    let (mut line, mut scanned) = (1, 0);
+    offsets
+        .map(|offset| {
+            let offset = offset.min(bytes.len()).max(scanned);
+            line += memchr::memchr_iter(b'\n', &bytes[scanned..offset]).count();
+            scanned = offset;
+            line
+        })
+        .collect()
+}
+
+/// Writes one `digest  name:line` line per match, like `sha256sum`, or with
+/// `duplicates` only the digests shared by several matches, most frequent
+/// first, with the lines of each.
+pub fn write_digests<W: Write>(out: &mut W, xml: &str, name: &str, matches: &[(Range<usize>, [u8; 32])], duplicates: bool) -> io::Result<()> {
+    let lines = lines(xml, matches.iter().map(|(range, _)| range.start));
+    if !duplicates {
+        for ((_, digest), line) in matches.iter().zip(lines) {
+            writeln!(out, "{}  {}:{}", hex(digest), name, line)?;
+        }
+        return Ok(());
+    }
+    let mut groups: HashMap<&[u8; 32], Vec<usize>> = HashMap::new();
+    for ((_, digest), line) in matches.iter().zip(lines) {
+        groups.entry(digest).or_default().push(line);
+    }
+    let mut groups: Vec<_> = groups.into_iter().filter(|(_, lines)| lines.len() > 1).collect();
+    groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.1[0].cmp(&b.1[0])));
+    for (digest, lines) in &groups {
+        let lines: Vec<String> = lines.iter().map(usize::to_string).collect();
+        writeln!(out, "{}  {} copies, lines {}", hex(*digest), lines.len(), lines.join(", "))?;
+    }
+    let copies: usize = groups.iter().map(|(_, lines)| lines.len() - 1).sum();
+    writeln!(out, "{} of {} matches are duplicates", copies, matches.len())
+}
@@ -0,0 +1,122 @@
+//! Tag frequencies (`xmz stats --histogram`) and, optionally, which of the
+//! most common tags occur directly inside which, to get to know the schema of
+//! an undocumented dump.
+
+use crate::agg::write_table;
+use crate::interrupt;
+use crate::parser::{Break, Continue, Token, stream_xml_reader_spans, stream_xml_spans};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ops::ControlFlow;
+
+/// Width of the longest bar.
+const BAR_WIDTH: usize = 30;
+/// Tag names in matrix column headers are cut to this many characters.
+const COLUMN_WIDTH: usize = 10;
+
+#[derive(Default)]
+pub struct Histogram {
+    ids: HashMap<String, usize>,
+    names: Vec<String>,
+    counts: Vec<u64>,
+    /// Child elements by parent and child tag.
+    edges: HashMap<(usize, usize), u64>,
+    open: Vec<usize>,
+    /// Bytes scanned, less than the input when stopped with Ctrl-C.
+    pub scanned: u64,
+}
+
+impl Histogram {
+    fn on_token(&mut self, token: Token, end: u64) -> ControlFlow<()> {
+        if interrupt::interrupted() {
+            return Break(());
+        }
+        self.scanned = end;
+        match token {
+            Token::StartTag(name, _) => {
+                let id = match self.ids.get(name) {
+                    Some(&id) => id,
+                    None => {
+                        self.ids.insert(name.to_string(), self.names.len());
+                        self.names.push(name.to_string());
+                        self.counts.push(0);
+                        self.names.len() - 1
+                    }
+                };
+                self.counts[id] += 1;
+                if let Some(&parent) = self.open.last() {
+                    *self.edges.entry((parent, id)).or_default() += 1;
+                }
+                self.open.push(id);
+            }
+            Token::EndTag(_) => {
+                self.open.pop();
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    }
+
+    /// Tag ids, most frequent first.
+    fn ranked(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = (0..self.names.len()).collect();
+        ids.sort_by(|&a, &b| self.counts[b].cmp(&self.counts[a]).then_with(|| self.names[a].cmp(&self.names[b])));
+        ids
+    }
+
+    /// Writes a row per tag name with its count, a bar and its share of all
+    /// elements; with `matrix`, also how often each of that many most
+    /// frequent tags has each of them as a child.
+    pub fn write<W: Write>(&self, out: &mut W, matrix: Option<usize>) -> io::Result<()> {
+        if self.names.is_empty() {
+            return writeln!(out, "No elements.");
+        }
+        let ranked = self.ranked();
+        let total: u64 = self.counts.iter().sum();
+        let widest = self.counts[ranked[0]];
+        let mut rows = vec![vec!["tag".to_string(), "count".into(), "share".into()]];
+        for &id in &ranked {
+            let count = self.counts[id];
+            rows.push(vec![
+                self.names[id].clone(),
+                count.to_string(),
+                format!("{:.1}%", count as f64 * 100.0 / total as f64),
+                "█".repeat((count as usize * BAR_WIDTH).div_ceil(widest as usize)),
+            ]);
+        }
+        write_table(out, &rows)?;
+
+        let Some(top) = matrix else {
+            return Ok(());
+        };
+        let top = &ranked[..top.min(ranked.len())];
+        writeln!(out)?;
+        writeln!(out, "Children of the {} most frequent tags (rows are parents, columns children):", top.len())?;
+        let mut header = vec![String::new()];
+        header.extend(top.iter().map(|&id| self.names[id].chars().take(COLUMN_WIDTH).collect::<String>()));
+        let mut rows = vec![header];
+        for &parent in top {
+            let mut row = vec![self.names[parent].clone()];
+            row.extend(top.iter().map(|&child| match self.edges.get(&(parent, child)) {
+                Some(count) => count.to_string(),
+                None => "·".to_string(),
+            }));
+            rows.push(row);
+        }
+        write_table(out, &rows)
+    }
+}
+
+/// Counts the tags of `xml`.
+pub fn histogram(xml: &str) -> Histogram {
+    let mut histogram = Histogram::default();
+    stream_xml_spans(xml, |token, span| histogram.on_token(token, span.end as u64));
+    histogram
+}
+
+/// Like `histogram`, reading the document incrementally from `reader`.
+pub fn histogram_reader<R: Read>(reader: R) -> io::Result<Histogram> {
+    let mut histogram = Histogram::default();
+    stream_xml_reader_spans(reader, |token, span| histogram.on_token(token, span.end))?;
+    Ok(histogram)
+}
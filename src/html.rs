@@ -0,0 +1,295 @@
+//! HTML as XML (`--html`): rewrites tag soup into a well-formed document the
+//! tokenizer reads like any other, so scraped pages can be explored and
+//! queried with the same tools.
+//!
+//! Void elements (`<br>`, `<img>`) become empty elements and stray end tags
+//! for them are dropped. Attributes without quotes or without a value get
+//! quoted (`<input disabled>` reads as `disabled=""`). The contents of
+//! `<script>` and `<style>` are raw text, kept in CDATA sections. End tags
+//! HTML lets authors leave out (`</p>`, `</li>`, `</td>`...) are implied
+//! where the next element starts, and elements still open at the end are
+//! closed. Tag and attribute names are lower-cased, bare `&` and `<` in text
+//! are escaped, and the common HTML entities become character references.
+//! Offsets then refer to the rewritten text, not the file.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HTML: AtomicBool = AtomicBool::new(false);
+
+/// Makes loaded documents be read as HTML, for the rest of the process.
+pub fn set_html(html: bool) {
+    HTML.store(html, Ordering::SeqCst);
+}
+
+pub fn html_enabled() -> bool {
+    HTML.load(Ordering::SeqCst)
+}
+
+const VOID: [&str; 14] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
+const RAW_TEXT: [&str; 2] = ["script", "style"];
+
+/// Elements whose start closes an open `p`.
+const CLOSES_P: [&str; 24] = [
+    "address", "article", "aside", "blockquote", "details", "div", "dl", "fieldset", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "main",
+    "nav", "ol", "p", "ul",
+];
+
+/// Named entities beyond XML's five, as the characters they stand for.
+const ENTITIES: [(&str, char); 24] = [
+    ("nbsp", '\u{a0}'),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+    ("mdash", '—'),
+    ("ndash", '–'),
+    ("hellip", '…'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("bull", '•'),
+    ("middot", '·'),
+    ("deg", '°'),
+    ("times", '×'),
+    ("euro", '€'),
+    ("pound", '£'),
+    ("yen", '¥'),
+    ("sect", '§'),
+    ("para", '¶'),
+    ("shy", '\u{ad}'),
+    ("zwj", '\u{200d}'),
+];
+
+/// The open elements an element's start implicitly ends, up to the element
+/// that scopes it: `li` ends an open `li` of the same list, and so on.
+fn implied_end(name: &str, open: &[String]) -> Option<usize> {
+    let (ends, scope): (&[&str], &[&str]) = match name {
+        "li" => (&["li"], &["ul", "ol"]),
+        "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+        "tr" => (&["tr", "td", "th"], &["table", "thead", "tbody", "tfoot"]),
+        "td" | "th" => (&["td", "th"], &["tr", "table"]),
+        "thead" | "tbody" | "tfoot" => (&["thead", "tbody", "tfoot", "tr", "td", "th"], &["table"]),
+        "option" => (&["option"], &["select", "datalist", "optgroup"]),
+        _ if CLOSES_P.contains(&name) => (&["p"], &["div", "td", "th", "li", "body", "blockquote", "section", "article"]),
+        _ => return None,
+    };
+    // The outermost one within the scope, so `<tr>` in a cell ends the row.
+    let mut end = None;
+    for (i, element) in open.iter().enumerate().rev() {
+        if ends.contains(&element.as_str()) {
+            end = Some(i);
+        } else if scope.contains(&element.as_str()) {
+            break;
+        }
+    }
+    end
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || (!c.is_ascii() && c.is_alphabetic())
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || matches!(c, '-' | '.' | ':')
+}
+
+/// Appends `text` escaped for XML, keeping references that XML knows and
+/// turning HTML's named entities into characters.
+fn push_text(out: &mut String, text: &str, quote: bool) {
+    let mut rest = text;
+    while let Some(at) = rest.find(['&', '<', '>', '"']) {
+        out.push_str(&rest[..at]);
+        let c = rest.as_bytes()[at];
+        rest = &rest[at + 1..];
+        match c {
+            b'<' => out.push_str("&lt;"),
+            b'>' => out.push_str("&gt;"),
+            b'"' if quote => out.push_str("&quot;"),
+            b'"' => out.push('"'),
+            _ => {
+                let name_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '#')).unwrap_or(rest.len());
+                let name = &rest[..name_len];
+                let terminated = rest[name_len..].starts_with(';');
+                if terminated && (matches!(name, "amp" | "lt" | "gt" | "quot" | "apos") || (name.starts_with('#') && name.len() > 1)) {
+                    out.push('&');
+                } else if let Some(&(_, c)) = terminated.then(|| ENTITIES.iter().find(|(entity, _)| *entity == name)).flatten() {
+                    out.push(c);
+                    rest = &rest[name_len + 1..];
+                } else {
+                    out.push_str("&amp;");
+                }
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+/// A start tag's attributes from `source`, which ends at the tag's `>`.
+fn attributes(source: &str) -> Vec<(String, &str)> {
+    let mut attributes: Vec<(String, &str)> = Vec::new();
+    let mut rest = source;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return attributes;
+        }
+        let name_len = rest.find(|c: char| c.is_whitespace() || matches!(c, '=' | '/' | '>')).unwrap_or(rest.len()).max(1);
+        let name = rest[..name_len].to_ascii_lowercase();
+        rest = rest[name_len..].trim_start();
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (found, remainder) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let body = &after[1..];
+                    let end = body.find(quote).unwrap_or(body.len());
+                    (&body[..end], body.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = found;
+            rest = remainder;
+        }
+        // Names XML cannot have, such as Vue's `@click`, are left out, and
+        // so are repeats.
+        let valid = name.starts_with(is_name_start) && name.chars().all(is_name_char) && name != "xmlns" && !name.starts_with("xmlns:");
+        if valid && !attributes.iter().any(|(existing, _)| *existing == name) {
+            attributes.push((name, value));
+        }
+    }
+}
+
+/// Where the end tag of the raw text element `name` starts in `text`, which
+/// follows its start tag; names are compared ignoring case.
+fn find_end_tag(text: &str, name: &str) -> usize {
+    let mut from = 0;
+    while let Some(at) = text[from..].find("</").map(|at| from + at) {
+        if text[at + 2..].get(..name.len()).is_some_and(|found| found.eq_ignore_ascii_case(name)) {
+            return at;
+        }
+        from = at + 2;
+    }
+    text.len()
+}
+
+/// Rewrites the HTML `html` as well-formed XML.
+pub fn to_xml(html: &str) -> String {
+    let mut out = String::with_capacity(html.len() + html.len() / 8);
+    let mut open: Vec<String> = Vec::new();
+    // Where the content after the prolog starts, and how many elements
+    // are at the top level, to wrap fragments in one root.
+    let mut body_start = None;
+    let mut roots = 0;
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            push_text(&mut out, rest, false);
+            break;
+        };
+        let (text, tag) = rest.split_at(lt);
+        if open.is_empty() && !text.trim().is_empty() {
+            body_start.get_or_insert(out.len());
+            roots += 1;
+        }
+        push_text(&mut out, text, false);
+        rest = tag;
+        // Comments, CDATA sections, declarations and processing instructions
+        // pass through as they are.
+        let passthrough = [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<!", ">"), ("<?", ">")].into_iter().find(|(open, _)| rest.starts_with(open));
+        if let Some((start, end)) = passthrough {
+            let len = rest[start.len()..].find(end).map_or(rest.len(), |at| start.len() + at + end.len());
+            if start == "<!--" {
+                // `--` is not allowed inside XML comments.
+                let body = rest[4..len].strip_suffix("-->").unwrap_or(&rest[4..len]);
+                out.push_str("<!--");
+                out.push_str(&body.replace("--", "- -"));
+                out.push_str("-->");
+            } else {
+                out.push_str(&rest[..len]);
+            }
+            rest = &rest[len..];
+            continue;
+        }
+        let closing = rest.starts_with("</");
+        let name_from = if closing { 2 } else { 1 };
+        if !rest[name_from..].starts_with(is_name_start) {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+        let end = rest.find('>').map_or(rest.len(), |at| at + 1);
+        let inside = rest[name_from..end].trim_end_matches('>');
+        rest = &rest[end..];
+        let name_len = inside.find(|c: char| !is_name_char(c)).unwrap_or(inside.len());
+        let name = inside[..name_len].to_ascii_lowercase();
+        if closing {
+            // An end tag closes its element and any left open inside it; one
+            // with nothing to close is dropped.
+            if let Some(at) = open.iter().rposition(|element| *element == name) {
+                for element in open.drain(at..).rev() {
+                    out.push_str("</");
+                    out.push_str(&element);
+                    out.push('>');
+                }
+            }
+            continue;
+        }
+        if let Some(at) = implied_end(&name, &open) {
+            for element in open.drain(at..).rev() {
+                out.push_str("</");
+                out.push_str(&element);
+                out.push('>');
+            }
+        }
+        if open.is_empty() {
+            body_start.get_or_insert(out.len());
+            roots += 1;
+        }
+        out.push('<');
+        out.push_str(&name);
+        for (attribute, value) in attributes(&inside[name_len..]) {
+            out.push(' ');
+            out.push_str(&attribute);
+            out.push_str("=\"");
+            push_text(&mut out, value, true);
+            out.push('"');
+        }
+        if VOID.contains(&name.as_str()) || inside.ends_with('/') {
+            out.push_str("/>");
+            continue;
+        }
+        out.push('>');
+        if RAW_TEXT.contains(&name.as_str()) {
+            let len = find_end_tag(rest, &name);
+            if !rest[..len].is_empty() {
+                out.push_str("<![CDATA[");
+                out.push_str(&rest[..len].replace("]]>", "]]]]><![CDATA[>"));
+                out.push_str("]]>");
+            }
+            rest = &rest[len..];
+            rest = rest.find('>').map_or("", |at| &rest[at + 1..]);
+            out.push_str("</");
+            out.push_str(&name);
+            out.push('>');
+            continue;
+        }
+        open.push(name);
+    }
+    for element in open.into_iter().rev() {
+        out.push_str("</");
+        out.push_str(&element);
+        out.push('>');
+    }
+    if roots > 1
+        && let Some(start) = body_start
+    {
+        out.insert_str(start, "<html>");
+        out.push_str("</html>");
+    }
+    out
+}
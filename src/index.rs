@@ -0,0 +1,244 @@
+//! One-pass structural index of a document for constant-time navigation.
+//!
+//! Elements are numbered in document (pre-)order. For each element the index
+//! stores its byte span, parent, first child, next sibling and the span of its
+//! first direct text, as flat `u64`/`u32` arrays. The index can be saved next
+//! to the document and reloaded as long as the document is unchanged: the
+//! same length, first and last 64 KiB, and modification time.
+
+use crate::parser::{Continue, Token, stream_xml_spans};
+use crate::sandbox::{self, Capability};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Marks a missing parent, child or sibling link.
+pub const NONE: u32 = u32::MAX;
+
+const MAGIC: &[u8; 8] = b"XMZIDX02";
+
+/// Structural index of one document.
+#[derive(Debug, Default, Clone)]
+pub struct Index {
+    pub start: Vec<u64>,
+    pub end: Vec<u64>,
+    pub parent: Vec<u32>,
+    pub first_child: Vec<u32>,
+    pub next_sibling: Vec<u32>,
+    /// Start of the first direct text run, or `u64::MAX` when there is none.
+    pub text_start: Vec<u64>,
+    pub text_len: Vec<u32>,
+    fingerprint: u64,
+}
+
+impl Index {
+    /// Builds the index with a single streaming pass over `xml`.
+    pub fn build(xml: &str) -> Self {
+        let mut index = Index {
+            fingerprint: fingerprint(xml),
+            ..Default::default()
+        };
+        // Open elements and, for each, the last child seen so far.
+        let mut stack: Vec<(u32, u32)> = Vec::new();
+        let mut last_top_level = NONE;
+
+        stream_xml_spans(xml, |token, span| {
+            match token {
+                Token::StartTag(..) => {
+                    let id = index.start.len() as u32;
+                    let parent = stack.last().map_or(NONE, |&(p, _)| p);
+                    index.start.push(span.start as u64);
+                    index.end.push(xml.len() as u64);
+                    index.parent.push(parent);
+                    index.first_child.push(NONE);
+                    index.next_sibling.push(NONE);
+                    index.text_start.push(u64::MAX);
+                    index.text_len.push(0);
+
+                    let previous = match stack.last_mut() {
+                        Some((p, last)) => {
+                            if *last == NONE {
+                                index.first_child[*p as usize] = id;
+                            }
+                            std::mem::replace(last, id)
+                        }
+                        None => std::mem::replace(&mut last_top_level, id),
+                    };
+                    if previous != NONE {
+                        index.next_sibling[previous as usize] = id;
+                    }
+                    stack.push((id, NONE));
+                }
+                Token::EndTag(_) => {
+                    if let Some((id, _)) = stack.pop() {
+                        index.end[id as usize] = span.end as u64;
+                    }
+                }
+                Token::Text(text) | Token::CData(text) => {
+                    if let Some(&(id, _)) = stack.last()
+                        && index.text_start[id as usize] == u64::MAX
+                    {
+                        index.text_start[id as usize] = (text.as_ptr() as usize - xml.as_ptr() as usize) as u64;
+                        index.text_len[id as usize] = text.len() as u32;
+                    }
+                }
+            }
+            Continue(())
+        });
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.start.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.is_empty()
+    }
+
+    /// Finds the element whose start tag begins at `offset`.
+    pub fn id_at(&self, offset: usize) -> Option<u32> {
+        self.start.binary_search(&(offset as u64)).ok().map(|i| i as u32)
+    }
+
+    /// Number of elements nested in element `id` at any depth. Elements are
+    /// numbered in document order, so they are the ones starting before it ends.
+    pub fn descendant_count(&self, id: u32) -> usize {
+        let end = self.end[id as usize];
+        self.start.partition_point(|&start| start < end) - id as usize - 1
+    }
+
+    /// Iterates the direct children of element `id`.
+    pub fn children(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        let mut next = self.first_child.get(id as usize).copied().unwrap_or(NONE);
+        std::iter::from_fn(move || {
+            if next == NONE {
+                return None;
+            }
+            let current = next;
+            next = self.next_sibling[current as usize];
+            Some(current)
+        })
+    }
+
+    /// Whether this index was built from a document identical to `xml`.
+    pub fn matches(&self, xml: &str) -> bool {
+        self.fingerprint == fingerprint(xml)
+    }
+
+    /// Path of the sidecar file used to persist the index of `document`.
+    pub fn sidecar_path(document: &Path) -> PathBuf {
+        let mut name = document.as_os_str().to_owned();
+        name.push(".xmzidx");
+        PathBuf::from(name)
+    }
+
+    /// Saves the index of a document last modified at `modified`, as given
+    /// by [`modified`].
+    pub fn save(&self, path: &Path, modified: u64) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&self.fingerprint.to_le_bytes())?;
+        out.write_all(&modified.to_le_bytes())?;
+        out.write_all(&(self.len() as u64).to_le_bytes())?;
+        for array in [&self.start, &self.end, &self.text_start] {
+            for v in array.iter() {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        for array in [&self.parent, &self.first_child, &self.next_sibling, &self.text_len] {
+            for v in array.iter() {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Loads a saved index, returning `None` if it is missing, corrupt or stale
+    /// for `xml`, last modified at `modified`.
+    pub fn load(path: &Path, xml: &str, modified: u64) -> Option<Self> {
+        let bytes = std::fs::read(path).ok()?;
+        let (magic, rest) = bytes.split_at_checked(MAGIC.len())?;
+        if magic != MAGIC {
+            return None;
+        }
+        let mut reader = rest;
+        let fingerprint = read_u64(&mut reader)?;
+        if fingerprint != self::fingerprint(xml) || read_u64(&mut reader)? != modified {
+            return None;
+        }
+        let len = read_u64(&mut reader)? as usize;
+        if reader.len() != len.checked_mul(3 * 8 + 4 * 4)? {
+            return None;
+        }
+        let (wide, narrow) = reader.split_at(len * 3 * 8);
+        let mut wide = wide
+            .chunks_exact(len * 8)
+            .map(|c| c.chunks_exact(8).map(|b| u64::from_le_bytes(b.try_into().unwrap())).collect());
+        let mut narrow = narrow
+            .chunks_exact(len * 4)
+            .map(|c| c.chunks_exact(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect());
+        Some(Index {
+            start: wide.next().unwrap_or_default(),
+            end: wide.next().unwrap_or_default(),
+            text_start: wide.next().unwrap_or_default(),
+            parent: narrow.next().unwrap_or_default(),
+            first_child: narrow.next().unwrap_or_default(),
+            next_sibling: narrow.next().unwrap_or_default(),
+            text_len: narrow.next().unwrap_or_default(),
+            fingerprint,
+        })
+    }
+
+    /// Loads the sidecar index of `document` if it is current, otherwise builds
+    /// a fresh one and tries to save it for next time.
+    pub fn load_or_build(document: &Path, xml: &str) -> Self {
+        let sidecar = Self::sidecar_path(document);
+        // An edit that keeps the length and both ends of the document still
+        // changes its modification time.
+        let modified = self::modified(document);
+        if let Some(index) = Self::load(&sidecar, xml, modified) {
+            return index;
+        }
+        let index = Self::build(xml);
+        // A read-only directory (or the sandbox) just means the index is not persisted.
+        if sandbox::check(Capability::WriteFiles).is_ok() {
+            let _ = index.save(&sidecar, modified);
+        }
+        index
+    }
+}
+
+/// When `document` was last modified, in nanoseconds since the epoch, or 0
+/// when that is not known.
+pub fn modified(document: &Path) -> u64 {
+    std::fs::metadata(document)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+fn read_u64<R: Read>(input: &mut R) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf).ok()?;
+    Some(u64::from_le_bytes(buf))
+}
+
+/// Cheap document fingerprint: length plus FNV-1a over the first and last 64 KiB.
+pub(crate) fn fingerprint(xml: &str) -> u64 {
+    const SAMPLE: usize = 64 * 1024;
+    let bytes = xml.as_bytes();
+    let mut hash = fnv1a(0xcbf2_9ce4_8422_2325, &(bytes.len() as u64).to_le_bytes());
+    hash = fnv1a(hash, &bytes[..bytes.len().min(SAMPLE)]);
+    fnv1a(hash, &bytes[bytes.len().saturating_sub(SAMPLE)..])
+}
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
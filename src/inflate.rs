@@ -0,0 +1,369 @@
+//! Streaming gzip (RFC 1952) / DEFLATE (RFC 1951) decompression.
+//!
+//! `GzDecoder` wraps any reader and yields the decompressed bytes, so the
+//! chunked parser can consume `.gz` dumps without unpacking them to disk.
+//! Concatenated gzip members are decoded one after another, like `zcat`.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+const WINDOW: usize = 32 * 1024;
+
+/// Length base values and extra bits for symbols 257..=285.
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which code length code lengths are stored in a dynamic block header.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt gzip data: {}", message))
+}
+
+struct BitReader<R> {
+    inner: BufReader<R>,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn need(&mut self, n: u32) -> io::Result<()> {
+        while self.count < n {
+            let byte = {
+                let buf = self.inner.fill_buf()?;
+                if buf.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated gzip data"));
+                }
+                buf[0]
+            };
+            self.inner.consume(1);
+            self.bits |= (byte as u64) << self.count;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.need(n)?;
+        let value = (self.bits & ((1u64 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        self.bits(8).map(|b| b as u8)
+    }
+
+    fn align(&mut self) {
+        let drop = self.count % 8;
+        self.bits >>= drop;
+        self.count -= drop;
+    }
+
+    fn at_eof(&mut self) -> io::Result<bool> {
+        Ok(self.count == 0 && self.inner.fill_buf()?.is_empty())
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+enum State {
+    MemberHeader,
+    BlockHeader,
+    Stored(usize),
+    Codes { lit: Huffman, dist: Huffman },
+    MemberTrailer,
+    Done,
+}
+
+/// Decompresses a gzip stream, implementing `Read` over the plain bytes.
+pub struct GzDecoder<R> {
+    input: BitReader<R>,
+    state: State,
+    last_block: bool,
+    /// Recent output (the back-reference window) followed by bytes not yet returned.
+    buf: Vec<u8>,
+    delivered: usize,
+    crc: u32,
+    size: u32,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            input: BitReader {
+                inner: BufReader::with_capacity(64 * 1024, inner),
+                bits: 0,
+                count: 0,
+            },
+            state: State::MemberHeader,
+            last_block: false,
+            buf: Vec::with_capacity(4 * WINDOW),
+            delivered: 0,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    /// Decodes the next piece of the stream, checksumming what it produces.
+    fn step(&mut self) -> io::Result<()> {
+        let before = self.buf.len();
+        self.decode()?;
+        self.crc = crc32(self.crc, &self.buf[before..]);
+        self.size = self.size.wrapping_add((self.buf.len() - before) as u32);
+        Ok(())
+    }
+
+    fn decode(&mut self) -> io::Result<()> {
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::MemberHeader => {
+                self.read_member_header()?;
+                self.crc = 0;
+                self.size = 0;
+                self.last_block = false;
+                self.state = State::BlockHeader;
+            }
+            State::BlockHeader => {
+                if self.last_block {
+                    self.state = State::MemberTrailer;
+                    return Ok(());
+                }
+                self.last_block = self.input.bits(1)? == 1;
+                self.state = match self.input.bits(2)? {
+                    0 => {
+                        self.input.align();
+                        let len = self.input.bits(16)?;
+                        let nlen = self.input.bits(16)?;
+                        if len != !nlen & 0xffff {
+                            return Err(corrupt("stored block length mismatch"));
+                        }
+                        State::Stored(len as usize)
+                    }
+                    1 => {
+                        let mut lengths = [0u8; 288];
+                        lengths[..144].fill(8);
+                        lengths[144..256].fill(9);
+                        lengths[256..280].fill(7);
+                        lengths[280..].fill(8);
+                        State::Codes {
+                            lit: Huffman::new(&lengths)?,
+                            dist: Huffman::new(&[5u8; 30])?,
+                        }
+                    }
+                    2 => self.read_dynamic_tables()?,
+                    _ => return Err(corrupt("invalid block type")),
+                };
+            }
+            State::Stored(remaining) => {
+                let n = remaining.min(WINDOW);
+                for _ in 0..n {
+                    let byte = self.input.byte()?;
+                    self.buf.push(byte);
+                }
+                self.state = if remaining > n { State::Stored(remaining - n) } else { State::BlockHeader };
+            }
+            State::Codes { lit, dist } => {
+                let target = self.buf.len() + WINDOW;
+                while self.buf.len() < target {
+                    let symbol = lit.decode(&mut self.input)?;
+                    match symbol {
+                        0..=255 => self.buf.push(symbol as u8),
+                        256 => {
+                            self.state = State::BlockHeader;
+                            return Ok(());
+                        }
+                        257..=285 => {
+                            let i = (symbol - 257) as usize;
+                            let len = LENGTH_BASE[i] as usize + self.input.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                            let d = dist.decode(&mut self.input)? as usize;
+                            if d >= 30 {
+                                return Err(corrupt("invalid distance symbol"));
+                            }
+                            let distance = DIST_BASE[d] as usize + self.input.bits(DIST_EXTRA[d] as u32)? as usize;
+                            if distance > self.buf.len() {
+                                return Err(corrupt("distance too far back"));
+                            }
+                            let start = self.buf.len() - distance;
+                            for k in 0..len {
+                                let byte = self.buf[start + k];
+                                self.buf.push(byte);
+                            }
+                        }
+                        _ => return Err(corrupt("invalid literal/length symbol")),
+                    }
+                }
+                self.state = State::Codes { lit, dist };
+            }
+            State::MemberTrailer => {
+                self.input.align();
+                let mut trailer = [0u8; 8];
+                for byte in trailer.iter_mut() {
+                    *byte = self.input.byte()?;
+                }
+                if u32::from_le_bytes(trailer[..4].try_into().unwrap()) != self.crc {
+                    return Err(corrupt("CRC mismatch"));
+                }
+                if u32::from_le_bytes(trailer[4..].try_into().unwrap()) != self.size {
+                    return Err(corrupt("length mismatch"));
+                }
+                self.state = if self.input.at_eof()? { State::Done } else { State::MemberHeader };
+            }
+            State::Done => {}
+        }
+        Ok(())
+    }
+
+    fn read_member_header(&mut self) -> io::Result<()> {
+        let mut header = [0u8; 10];
+        for byte in header.iter_mut() {
+            *byte = self.input.byte()?;
+        }
+        if header[0] != 0x1f || header[1] != 0x8b {
+            return Err(corrupt("not a gzip stream"));
+        }
+        if header[2] != 8 {
+            return Err(corrupt("unsupported compression method"));
+        }
+        let flags = header[3];
+        if flags & 0x04 != 0 {
+            let len = self.input.bits(16)?;
+            for _ in 0..len {
+                self.input.byte()?;
+            }
+        }
+        // File name and comment are zero-terminated.
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while self.input.byte()? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            self.input.bits(16)?;
+        }
+        Ok(())
+    }
+
+    fn read_dynamic_tables(&mut self) -> io::Result<State> {
+        let hlit = self.input.bits(5)? as usize + 257;
+        let hdist = self.input.bits(5)? as usize + 1;
+        let hclen = self.input.bits(4)? as usize + 4;
+        let mut clen_lengths = [0u8; 19];
+        for &i in CLEN_ORDER.iter().take(hclen) {
+            clen_lengths[i] = self.input.bits(3)? as u8;
+        }
+        let clen = Huffman::new(&clen_lengths)?;
+
+        let mut lengths = vec![0u8; hlit + hdist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = clen.decode(&mut self.input)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    let previous = *lengths[..i].last().ok_or_else(|| corrupt("repeat with no previous length"))?;
+                    (previous, 3 + self.input.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.input.bits(3)? as usize),
+                _ => (0, 11 + self.input.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(corrupt("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        Ok(State::Codes {
+            lit: Huffman::new(&lengths[..hlit])?,
+            dist: Huffman::new(&lengths[hlit..])?,
+        })
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.delivered == self.buf.len() {
+            if matches!(self.state, State::Done) {
+                return Ok(0);
+            }
+            // Keep only the window once everything before it has been delivered.
+            if self.buf.len() > 4 * WINDOW {
+                let keep_from = self.buf.len() - WINDOW;
+                self.buf.drain(..keep_from);
+                self.delivered -= keep_from;
+            }
+            self.step()?;
+        }
+        let n = out.len().min(self.buf.len() - self.delivered);
+        out[..n].copy_from_slice(&self.buf[self.delivered..self.delivered + n]);
+        self.delivered += n;
+        Ok(n)
+    }
+}
+
+/// CRC-32 (IEEE) continuing from `crc`.
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    });
+    let mut c = !crc;
+    for &b in bytes {
+        c = table[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
@@ -0,0 +1,49 @@
+//! Ctrl-C for long-running commands. Once `install` has been called, the
+//! first Ctrl-C only raises a flag that streaming passes check between
+//! tokens or records, so they can stop cleanly and report what they have;
+//! a second Ctrl-C exits at once. Without signal support (outside Linux),
+//! Ctrl-C ends the process as usual.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+extern "C" fn on_sigint(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Catches Ctrl-C for the rest of the process.
+pub fn install() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::signal(libc::SIGINT, on_sigint as *const () as libc::sighandler_t);
+    }
+}
+
+/// Whether Ctrl-C has been pressed since `install`.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// "interrupted at byte N (x%)", the percentage when the input size is known.
+pub fn describe(offset: u64, total: Option<u64>) -> String {
+    match total {
+        Some(total) if total > 0 => format!("interrupted at byte {} ({:.0}%)", offset, offset as f64 * 100.0 / total as f64),
+        _ => format!("interrupted at byte {}", offset),
+    }
+}
+
+/// An `Interrupted` error for a pass stopped at `offset`, with `hint` (e.g.
+/// how to continue) appended.
+pub fn error(offset: u64, total: Option<u64>, hint: &str) -> io::Error {
+    let mut message = describe(offset, total);
+    if !hint.is_empty() {
+        message.push_str("; ");
+        message.push_str(hint);
+    }
+    io::Error::new(io::ErrorKind::Interrupted, message)
+}
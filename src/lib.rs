@@ -1,4 +1,52 @@
+pub mod agg;
+pub mod arrow;
+pub mod audit;
+pub mod cache;
+pub mod check;
+pub mod checks;
+pub mod config;
+pub mod convert;
+pub mod describe;
+pub mod diff;
+pub mod dom;
+pub mod dtd;
+pub mod du;
+pub mod encoding;
+pub mod fanout;
+pub mod format;
+pub mod grep;
+pub mod hash;
+pub mod histogram;
+pub mod html;
+pub mod index;
+pub mod inflate;
+pub mod interrupt;
+pub mod json;
+pub mod lookup;
+pub mod merge;
+pub mod pager;
 pub mod parser;
+pub mod patch;
+pub mod pipeline;
+pub mod profile;
+pub mod progress;
+pub mod query;
+pub mod regex;
+pub mod repair;
+pub mod sandbox;
+pub mod schema;
+pub mod sketch;
+pub mod sniff;
+pub mod source;
+pub mod splice;
 pub mod stats;
+pub mod table;
+pub mod tokens;
+pub mod transform;
 pub mod tui;
+pub mod validate;
+pub mod value;
+pub mod watch;
 pub mod xml;
+pub mod xbrl;
+pub mod xsd;
@@ -0,0 +1,104 @@
+//! Lookups by key in records sorted by it (`xmz get --sorted-by`): a binary
+//! search over byte offsets, which reads the record after each probe
+//! instead of scanning the whole document, so a key is found in a dump of
+//! millions of records after a few dozen reads.
+//!
+//! A probe jumps to an offset and reads the first record starting at or
+//! after it, found by its start tag, `<item` for records `//item`. That
+//! relies on the records' tag not occurring inside records, and on the
+//! records being in ascending order of the key (compared as numbers when
+//! both values are, else as text; a record without the key sorts first).
+
+use crate::query::{Field, order};
+use memchr::memmem;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// The records found for a key.
+#[derive(Debug, Clone, Default)]
+pub struct Found {
+    /// Byte ranges of the records with the key, in document order.
+    pub records: Vec<Range<usize>>,
+    /// Records read to find them.
+    pub probes: usize,
+}
+
+/// The first record named `tag` starting at or after `from`. Only its own
+/// bytes are read: its end is the first end tag of its name.
+fn record_from(xml: &[u8], tag: &str, from: usize) -> Option<Range<usize>> {
+    let open = format!("<{}", tag);
+    let start = next_tag(xml, &open, from)?;
+    let mut quote = None;
+    let mut tag_end = None;
+    for (i, &byte) in xml.iter().enumerate().skip(start + open.len()) {
+        match (quote, byte) {
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (Some(q), _) if byte == q => quote = None,
+            (None, b'>') => {
+                tag_end = Some(i + 1);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let tag_end = tag_end?;
+    if xml[tag_end - 2] == b'/' {
+        return Some(start..tag_end);
+    }
+    let close = next_tag(xml, &format!("</{}", tag), tag_end)?;
+    let end = close + memchr::memchr(b'>', &xml[close..])? + 1;
+    Some(start..end)
+}
+
+/// Where `open` (`<item` or `</item`) next starts a tag at or after `from`,
+/// followed by whitespace, `>` or `/` rather than more of a longer name.
+fn next_tag(xml: &[u8], open: &str, from: usize) -> Option<usize> {
+    let finder = memmem::Finder::new(open.as_bytes());
+    let mut at = from;
+    loop {
+        let start = at + finder.find(xml.get(at..)?)?;
+        if matches!(xml.get(start + open.len()), Some(b' ' | b'\t' | b'\r' | b'\n' | b'>' | b'/')) {
+            return Some(start);
+        }
+        at = start + open.len();
+    }
+}
+
+/// The key of the record at `range`, empty when it has none.
+fn key_of<'a>(xml: &'a [u8], range: &Range<usize>, field: &Field) -> &'a str {
+    std::str::from_utf8(&xml[range.clone()]).ok().and_then(|record| field.first(record)).unwrap_or("")
+}
+
+/// Finds the records named `tag` whose `field` is `key`, in a document
+/// whose records are sorted by `field`.
+pub fn find_sorted(xml: &[u8], tag: &str, field: &Field, key: &str) -> Found {
+    let mut found = Found::default();
+    // Records starting before `low` have smaller keys; none starting at or
+    // after `high` has a smaller one.
+    let (mut low, mut high) = (0, xml.len());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let record = record_from(xml, tag, middle).filter(|record| record.start < high);
+        let Some(record) = record else {
+            high = middle;
+            continue;
+        };
+        found.probes += 1;
+        if order(key_of(xml, &record, field), key) == Ordering::Less {
+            low = record.end;
+        } else {
+            // No record starts between the probe and this one.
+            high = middle;
+        }
+    }
+    let mut at = low;
+    while let Some(record) = record_from(xml, tag, at) {
+        found.probes += 1;
+        if order(key_of(xml, &record, field), key) != Ordering::Equal {
+            break;
+        }
+        at = record.end;
+        found.records.push(record);
+    }
+    found
+}
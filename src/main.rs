@@ -1,32 +1,2002 @@
-use memmap2::Mmap;
-use std::fs::File;
-use xmz::stats::print_stats;
-use xmz::tui::run_tui;
-use clap::Parser;
+use xmz::agg::{AggOptions, Groups, aggregate, write_groups};
+use xmz::audit::audit;
+use xmz::check::{Severity, diagnostics_to_json, well_formedness, write_diagnostics};
+use xmz::checks::{Checks, collect_files, report_to_json};
+use xmz::config::Config;
+use xmz::convert::{ExportOptions, Format, convert, detect_record, split};
+use xmz::describe::{describe, description_to_json, write_description};
+use xmz::diff::{DiffOptions, Whitespace, diff};
+use xmz::dtd::Dtd;
+use xmz::du::write_usage;
+use xmz::format::{FormatOptions, changed_region, format, format_regions, line_range};
+use xmz::grep::{GrepOptions, grep, write_hits};
+use xmz::hash::{canonical, digest, digest_matches, hex, write_digests};
+use xmz::histogram::{histogram, histogram_reader};
+use xmz::index::Index;
+use xmz::merge::{MergeOptions, merge3};
+use xmz::parser::{Limits, check_limits, limits, set_limits};
+use xmz::patch::{Patch, write_patched};
+use xmz::pipeline::Pipeline;
+use xmz::query::{Field, Predicate, Query, select};
+use xmz::regex::Regex;
+use xmz::repair::{RepairOptions, repair, write_changes, write_plan};
+use xmz::sandbox::{self, Capability};
+use xmz::schema::{Draft, Drift, Schema};
+use xmz::sniff::sniff;
+use xmz::encoding::Replacements;
+use xmz::fanout::for_each_file;
+use xmz::source::{Compression, Document, load};
+use xmz::stats::{print_stats, print_stats_reader};
+use xmz::table::{Column, TableOptions, write_rows};
+use xmz::tokens::write_tokens;
+use xmz::transform::{Case, Conversion, DefaultValue, TransformOptions, parse_rename, transform};
+use xmz::validate::{RecordOptions, Rule, summary_to_json, validate_records, write_summary};
+use xmz::value::ValueParser;
+use xmz::xsd::Xsd;
+use xmz::tui::{Claim, Exit, SessionKey, Theme, TuiOptions, claim, offer_handover, run_directory, run_follow, run_tui, set_theme};
+use clap::{Args, Parser, Subcommand};
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// Path to the XML file
-    file_path: String,
+    file_path: Option<String>,
 
     /// Run in TUI mode
     #[arg(long)]
     tui: bool,
+
+    #[command(flatten)]
+    tui_args: TuiArgs,
+
+    /// Refuse to write files, run external commands or use the network
+    #[arg(long, global = true)]
+    sandbox: bool,
+
+    /// Read input files no faster than this many megabytes per second
+    #[arg(long, global = true, value_name = "MBPS")]
+    max_mbps: Option<f64>,
+
+    /// Replace invalid UTF-8 (or UTF-16) sequences with U+FFFD instead of failing
+    #[arg(long, global = true)]
+    lossy: bool,
+
+    /// Refuse documents nested over 256 levels, with tokens over 1 MiB or
+    /// over 256 attributes on an element, and any network access
+    #[arg(long, global = true)]
+    untrusted: bool,
+
+    /// Replace references to the entities declared in a document's internal
+    /// DTD with their text when loading it (never external entities)
+    #[arg(long, global = true, conflicts_with = "untrusted")]
+    expand_entities: bool,
+
+    /// Read documents as HTML: void elements need no end tag, attributes no
+    /// quotes, and script and style contents are raw text
+    #[arg(long, global = true)]
+    html: bool,
+
+    /// Keep reading past mismatched and unclosed tags, closing elements
+    /// where their parent ends, and report each recovery
+    #[arg(long, global = true)]
+    lenient: bool,
+
+    /// Print stats, schema and du reports straight to the terminal instead
+    /// of through $XMZ_PAGER, $PAGER or less
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// Neither use nor save cached results of agg, describe, grep, du and hash
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Run the command even if its result is cached, and cache the new one
+    #[arg(long, global = true, conflicts_with = "no_cache")]
+    refresh: bool,
+
+    /// How stats, convert and split show progress on stderr: auto (a line
+    /// on a terminal), json (one JSON object per line) or none
+    #[arg(long, global = true, value_name = "MODE", default_value = "auto")]
+    progress: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> std::io::Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Explore the document interactively (or headlessly with --script)
+    Tui {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Further XML files to open in tabs (Tab/Shift-Tab to switch)
+        files: Vec<String>,
+
+        #[command(flatten)]
+        args: TuiArgs,
+    },
+    /// Print statistics about the document
+    Stats {
+        /// Path to the XML file, or - to read standard input
+        file_path: String,
+
+        /// Read the file in chunks instead of mapping it into memory
+        #[arg(long)]
+        stream: bool,
+
+        /// Keep reading what is appended to the file and update the report, like tail -f
+        #[arg(long, conflicts_with = "stream")]
+        follow: bool,
+
+        /// Print a table of every tag name with its count instead of the report
+        #[arg(long, conflicts_with = "follow")]
+        histogram: bool,
+
+        /// With --histogram, also show which of the N (default 10) most
+        /// frequent tags occur as children of which
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10", requires = "histogram")]
+        matrix: Option<usize>,
+    },
+    /// Count matches of a query and summarise a numeric or date field
+    #[command(override_usage = "xmz agg [OPTIONS] <FILE>... <QUERY>")]
+    Agg {
+        /// Paths to XML files, directories or glob patterns such as
+        /// "logs/*.xml", whose results are combined, then the query selecting
+        /// the records to aggregate, e.g. "//item"
+        #[arg(required = true, value_name = "FILE")]
+        args: Vec<String>,
+
+        /// Field to summarise, relative to each record ("price", "@amount")
+        #[arg(long)]
+        field: Option<String>,
+
+        /// Field whose value groups the records
+        #[arg(long)]
+        group_by: Option<String>,
+
+        /// Report only the K most frequent groups, in bounded memory; their
+        /// counts may be over by the error shown
+        #[arg(long, value_name = "K", requires = "group_by", value_parser = clap::value_parser!(u64).range(1..))]
+        top: Option<u64>,
+
+        /// Also estimate how many distinct values the field has
+        #[arg(long, requires = "field")]
+        distinct: bool,
+
+        /// Threads reading files, when there are several (default: one per CPU)
+        #[arg(long, value_name = "N")]
+        jobs: Option<usize>,
+
+        #[command(flatten)]
+        values: ValueArgs,
+    },
+    /// Summarise each column of the records a query selects: count, nulls,
+    /// min, max, mean and distinct values
+    Describe {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Query selecting the records, e.g. "item"; detected from the document if omitted
+        #[arg(long, value_name = "QUERY")]
+        record: Option<String>,
+
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        values: ValueArgs,
+    },
+    /// Print a content hash of the document, or of each element a query
+    /// matches, computed over a canonical form
+    Hash {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Query selecting the elements to hash, e.g. "//record"; the whole document without one
+        query: Option<String>,
+
+        /// List only the digests several matches share, with their lines
+        #[arg(long, requires = "query")]
+        duplicates: bool,
+
+        /// Print the canonical form instead of its digest
+        #[arg(long, conflicts_with = "duplicates")]
+        canonical: bool,
+    },
+    /// Print the elements whose tag, an attribute value or own text match a
+    /// regular expression, with their paths
+    #[command(override_usage = "xmz grep [OPTIONS] <FILE>... <PATTERN>")]
+    Grep {
+        /// Paths to XML files, directories or glob patterns such as
+        /// "logs/*.xml", each hit prefixed with its file when there are
+        /// several, then the regular expression, e.g. "^Dune" or "(?i)overdue"
+        #[arg(required = true, value_name = "FILE")]
+        args: Vec<String>,
+
+        /// Match case-insensitively
+        #[arg(short = 'i', long)]
+        ignore_case: bool,
+
+        /// Print only the number of matching elements
+        #[arg(short, long, conflicts_with_all = ["after", "before", "context"])]
+        count: bool,
+
+        /// Also show N sibling elements after each match
+        #[arg(short = 'A', long, value_name = "N", default_value_t = 0)]
+        after: usize,
+
+        /// Also show N sibling elements before each match
+        #[arg(short = 'B', long, value_name = "N", default_value_t = 0)]
+        before: usize,
+
+        /// Also show N sibling elements before and after each match
+        #[arg(short = 'C', long, value_name = "N")]
+        context: Option<usize>,
+
+        /// Threads reading files, when there are several (default: one per CPU)
+        #[arg(long, value_name = "N")]
+        jobs: Option<usize>,
+    },
+    /// Show how many bytes each element path takes up, largest first
+    Du {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Deepest level to break down (1 = root element only)
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
+        depth: u16,
+    },
+    /// Infer the element structure, or compare it against a saved baseline
+    Schema {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Save the inferred structure as a JSON baseline
+        #[arg(long, value_name = "FILE")]
+        save: Option<String>,
+
+        /// Report elements and attributes added or removed since this baseline
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<String>,
+
+        /// Exit with an error if elements appear that the baseline lacks
+        #[arg(long, requires = "baseline")]
+        fail_on_new_elements: bool,
+
+        /// Exit with an error on any difference from the baseline
+        #[arg(long, requires = "baseline")]
+        fail_on_drift: bool,
+
+        /// Print a draft schema instead of the outline: xsd or rng (RELAX NG)
+        #[arg(long, value_name = "LANGUAGE", conflicts_with = "baseline")]
+        draft: Option<String>,
+    },
+    /// Check that the document is well-formed and report every problem found
+    Check {
+        /// Paths to XML files, or directories to check the .xml files in
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Run the checks configured in FILE (size budgets, schema drift,
+        /// XSD, audit, lint rules) besides well-formedness
+        #[arg(long, value_name = "FILE")]
+        config: Option<String>,
+
+        /// Print the diagnostics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Validate the document against an XML Schema (the commonly used subset
+    /// of XSD 1.0) or the DTD in its DOCTYPE
+    Validate {
+        /// Path to the XML file
+        file_path: String,
+
+        /// The schema to validate against; schemas it includes or imports from
+        /// local files are read too
+        #[arg(long, value_name = "SCHEMA", required_unless_present_any = ["dtd", "rules"])]
+        xsd: Option<String>,
+
+        /// Validate against the element and attribute declarations in the
+        /// document's internal DTD subset
+        #[arg(long, conflicts_with = "xsd")]
+        dtd: bool,
+
+        /// Validate each record this query selects on its own, in parallel,
+        /// e.g. "//record"; prints counts and the first failures
+        #[arg(long, value_name = "QUERY")]
+        records: Option<String>,
+
+        /// A predicate every record must satisfy, e.g. "price>0 and @id"
+        /// (repeatable)
+        #[arg(long = "rule", value_name = "PREDICATE", requires = "records")]
+        rules: Vec<String>,
+
+        /// Threads validating records (default: one per CPU)
+        #[arg(long, value_name = "N", requires = "records")]
+        jobs: Option<usize>,
+
+        /// Invalid records to report in full
+        #[arg(long, value_name = "N", default_value_t = 10, requires = "records")]
+        max_failures: usize,
+
+        /// Print the diagnostics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Flag external DTDs and entities, XIncludes and remote URIs (XXE vectors)
+    Audit {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Print the findings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a well-formed copy of a damaged document, reporting each fix
+    Repair {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Write the repaired document to FILE instead of standard output; the
+        /// report then goes to standard output instead of standard error
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        /// Put TEXT in place of each character XML does not allow instead of
+        /// removing it (e.g. --replace-invalid '?' or --replace-invalid '&#xFFFD;')
+        #[arg(long, value_name = "TEXT", default_value = "")]
+        replace_invalid: String,
+    },
+    /// Find unclosed, stray and misnested tags and print a plan to fix them,
+    /// or write a copy with them fixed
+    Fix {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Write the fixed document to FILE instead of printing the plan
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Pretty-print the document, or only the elements around changed regions
+    Format {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Reformat only the elements that differ from BASE (e.g. the version
+        /// in version control), leaving every other byte as it is
+        #[arg(long, value_name = "BASE", conflicts_with = "lines")]
+        against: Option<String>,
+
+        /// Reformat only the elements on lines FIRST:LAST (repeatable)
+        #[arg(long, value_name = "FIRST:LAST")]
+        lines: Vec<String>,
+
+        /// Spaces per nesting level
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        indent: usize,
+
+        /// Write the result to FILE (which may be the input) instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Copy the document with its tags rewritten by the given rules
+    Transform {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Normalize element names and namespace prefixes: lower, upper or preserve-first
+        #[arg(long, value_name = "CASE")]
+        tag_case: Option<String>,
+
+        /// Normalize attribute names: lower, upper or preserve-first
+        #[arg(long, value_name = "CASE")]
+        attribute_case: Option<String>,
+
+        /// Remove namespace declarations that nothing in their scope uses
+        #[arg(long)]
+        prune_namespaces: bool,
+
+        /// Rename a namespace prefix everywhere, e.g. ns2=order (repeatable)
+        #[arg(long, value_name = "OLD=NEW")]
+        rename_prefix: Vec<String>,
+
+        /// Move the elements and attributes of namespace URI to no namespace (repeatable)
+        #[arg(long, value_name = "URI")]
+        drop_namespace: Vec<String>,
+
+        /// Turn an attribute into a child element, e.g. //book/@isbn (repeatable)
+        #[arg(long, value_name = "PATH")]
+        attr_to_elem: Vec<String>,
+
+        /// Turn a text-only child element into an attribute, e.g. //book/isbn (repeatable)
+        #[arg(long, value_name = "PATH")]
+        elem_to_attr: Vec<String>,
+
+        /// Add an attribute or child element where it is missing, e.g.
+        /// //book/@lang=en or //book/currency=EUR (repeatable)
+        #[arg(long, value_name = "PATH=VALUE")]
+        default: Vec<String>,
+
+        /// Write the result to FILE instead of standard output; the summary
+        /// then goes to standard output instead of standard error
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Compare two documents element by element
+    Diff {
+        /// The old version
+        old: String,
+
+        /// The new version
+        new: String,
+
+        /// Pair children by this attribute (e.g. id) instead of by position
+        #[arg(long, value_name = "NAME")]
+        key: Option<String>,
+
+        /// How whitespace in text counts: trim (ignored around text and between elements), collapse (runs also equal one space) or exact
+        #[arg(long, value_name = "MODE", default_value = "trim")]
+        whitespace: String,
+
+        /// Also compare the comments inside each element
+        #[arg(long)]
+        comments: bool,
+
+        /// Report attributes written in a different order
+        #[arg(long)]
+        attribute_order: bool,
+
+        /// Print the changes as JSON
+        #[arg(long, conflicts_with = "tui")]
+        json: bool,
+
+        /// Show the documents side by side in the TUI, differences marked
+        #[arg(long)]
+        tui: bool,
+    },
+    /// Print the document canonicalized and indented, one element per line,
+    /// for `git diff` to compare (a textconv filter)
+    GitTextconv {
+        /// Path to the XML file
+        file_path: String,
+    },
+    /// Show two versions side by side in the TUI with differences marked,
+    /// for `git difftool`
+    GitDifftool {
+        /// The old version ($LOCAL)
+        old: String,
+
+        /// The new version ($REMOTE)
+        new: String,
+
+        /// Pair children by this attribute (e.g. id) instead of by position
+        #[arg(long, value_name = "NAME")]
+        key: Option<String>,
+    },
+    /// Merge two edited versions of a document with their common base,
+    /// element by element, marking conflicts with XML comments
+    Merge3 {
+        /// The common ancestor
+        base: String,
+
+        /// Our version; what is outside the root element is taken from it
+        ours: String,
+
+        /// Their version
+        theirs: String,
+
+        /// Pair children by this attribute (e.g. id) instead of by position
+        #[arg(long, value_name = "NAME")]
+        key: Option<String>,
+
+        /// Write the merge to FILE instead of standard output (it may be ours)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Write the changes from one version of a document to another as a
+    /// JSON patch, for `xmz apply`
+    Patch {
+        /// The original version
+        old: String,
+
+        /// The edited version, e.g. the copy the TUI's edits went to
+        new: String,
+
+        /// Find children by this attribute (e.g. id) instead of by position
+        #[arg(long, value_name = "NAME")]
+        key: Option<String>,
+
+        /// Write the patch to FILE instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Make the changes of a patch from `xmz patch`, splicing each into the
+    /// document as it is copied out
+    Apply {
+        /// The patch (JSON)
+        patch: String,
+
+        /// Path to the XML file
+        file_path: String,
+
+        /// Write the patched document to FILE instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Print the raw token stream with offsets, for debugging the parser
+    Tokens {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Stop after N tokens
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Skip tokens that end before this byte offset
+        #[arg(long, value_name = "OFFSET", default_value_t = 0)]
+        from: usize,
+    },
+    /// Export the records a query selects as NDJSON or XML
+    #[command(visible_alias = "to-ndjson")]
+    Convert {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Write to FILE (checkpointed) instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Split the records a query selects into files of N records each
+    Split {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Records per output file
+        #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+        per_file: u64,
+
+        /// Directory for the chunk files
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        out_dir: String,
+
+        /// Chunk file names: PREFIX-00001.xml, PREFIX-00002.xml, ...
+        #[arg(long, default_value = "part")]
+        prefix: String,
+
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Print the records a predicate selects; with --sorted-by, find them by
+    /// binary search instead of reading the whole document
+    Get {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Query selecting the records, e.g. "//item"; detected from the document if omitted
+        #[arg(long, value_name = "QUERY")]
+        record: Option<String>,
+
+        /// The records to print, e.g. "id=42" or "price>10 and @lang='en'"
+        #[arg(long = "where", value_name = "PREDICATE")]
+        filter: String,
+
+        /// The records are in ascending order of FIELD (e.g. "id" or "@sku"),
+        /// and --where is FIELD=VALUE
+        #[arg(long, value_name = "FIELD")]
+        sorted_by: Option<String>,
+
+        /// Output format: xml or ndjson
+        #[arg(long, value_name = "FORMAT", default_value = "xml")]
+        to: String,
+    },
+    /// Run the steps of a pipeline manifest (check, transform, split,
+    /// convert) over one input, reading it once, and report each step
+    Run {
+        /// The manifest, e.g. pipeline.toml
+        manifest: String,
+
+        /// Input file, instead of the manifest's `input`
+        #[arg(long, value_name = "FILE")]
+        input: Option<String>,
+    },
+    /// Write one CSV or TSV row per record a query selects, with chosen
+    /// fields as the columns
+    Table {
+        /// Path to the XML file
+        file_path: String,
+
+        /// Query selecting the records, e.g. "//record"
+        #[arg(long, value_name = "QUERY")]
+        rows: String,
+
+        /// Fields of each record, comma-separated, e.g. "name,price,@id"
+        #[arg(long, value_name = "FIELDS")]
+        cols: String,
+
+        /// Write only the records matching a predicate, e.g. "price>10"
+        #[arg(long = "where", value_name = "PREDICATE")]
+        filter: Option<String>,
+
+        /// Output format: csv, tsv or arrow (an Arrow IPC stream)
+        #[arg(long, value_name = "FORMAT", default_value = "csv")]
+        to: String,
+
+        /// Leave out the line of column names
+        #[arg(long)]
+        no_header: bool,
+
+        /// Write to FILE instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        /// Send the rows to a TCP socket instead of standard output, e.g.
+        /// "localhost:9000"
+        #[arg(long, value_name = "HOST:PORT", conflicts_with = "output")]
+        connect: Option<String>,
+    },
+    /// Extract the facts of an XBRL instance with their contexts and units
+    /// resolved, as CSV or NDJSON
+    Xbrl {
+        /// Path to the XBRL instance
+        file_path: String,
+
+        /// Output format: csv or ndjson
+        #[arg(long, value_name = "FORMAT", default_value = "csv")]
+        to: String,
+
+        /// Write the facts to FILE instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ExportArgs {
+    /// Query selecting the records, e.g. "//item"; detected from the document if omitted
+    #[arg(long, value_name = "QUERY")]
+    record: Option<String>,
+
+    /// Export only the records matching a predicate, e.g. "price>10 and @lang='en'"
+    #[arg(long = "where", value_name = "PREDICATE")]
+    filter: Option<String>,
+
+    /// Output format: ndjson or xml
+    #[arg(long, value_name = "FORMAT", default_value = "ndjson")]
+    to: String,
+
+    /// Records between checkpoints (0 = never)
+    #[arg(long, value_name = "N", default_value_t = 10_000)]
+    checkpoint_every: u64,
+
+    /// Continue an interrupted export from its last checkpoint
+    #[arg(long)]
+    resume: bool,
+}
+
+#[derive(Args, Debug)]
+struct ValueArgs {
+    /// Number convention for parsing values: c, en, de, fr, ch (or a tag like de-AT)
+    #[arg(long)]
+    locale: Option<String>,
 
-    let file = File::open(&cli.file_path)?;
-    let mmap = unsafe { Mmap::map(&file)? };
-    let xml = std::str::from_utf8(&mmap).expect("Invalid UTF-8 XML");
+    /// Parse values as dates in this strftime-style format (repeatable), e.g. "%d.%m.%Y"
+    #[arg(long, value_name = "FORMAT")]
+    date_format: Vec<String>,
 
-    if cli.tui {
-        run_tui(xml)?;
+    /// Parse values as dates using ISO 8601 formats
+    #[arg(long)]
+    dates: bool,
+}
+
+impl ValueArgs {
+    fn parser(&self) -> std::io::Result<ValueParser> {
+        ValueParser::new(self.locale.as_deref(), &self.date_format).map_err(invalid_input)
+    }
+
+    fn dates(&self) -> bool {
+        self.dates || !self.date_format.is_empty()
+    }
+}
+
+impl ExportArgs {
+    /// Fails on an invalid `--record` or `--where` before the document is loaded.
+    fn check_record(&self) -> std::io::Result<()> {
+        if let Some(record) = &self.record {
+            Query::parse(record).map_err(invalid_input)?;
+        }
+        self.filter().map(drop)
+    }
+
+    fn filter(&self) -> std::io::Result<Option<Predicate>> {
+        self.filter
+            .as_deref()
+            .map(Predicate::parse)
+            .transpose()
+            .map_err(|err| invalid_input(format!("--where: {}", err)))
+    }
+
+    /// The `--record` query, or the record element detected in `xml`, which
+    /// is reported so the guess can be checked.
+    fn record(&self, xml: &str) -> std::io::Result<String> {
+        if let Some(record) = &self.record {
+            return Ok(record.clone());
+        }
+        let guess = detect_record(xml)
+            .ok_or_else(|| invalid_input("no repeated element found to export; pass --record".to_string()))?;
+        let scope = if guess.scanned < xml.len() {
+            format!("in the first {}", xmz::du::format_bytes(guess.scanned as u64))
+        } else {
+            "in the document".to_string()
+        };
+        eprintln!(
+            "xmz: exporting --record {}, repeated {} times under one element {}; pass --record to choose another",
+            guess.query, guess.count, scope
+        );
+        Ok(guess.query)
+    }
+
+    fn options<'a>(&'a self, query: &'a Query, query_source: &'a str, filter: Option<&'a Predicate>) -> std::io::Result<ExportOptions<'a>> {
+        Ok(ExportOptions {
+            query,
+            query_source,
+            filter,
+            filter_source: self.filter.as_deref(),
+            format: Format::parse(&self.to).map_err(invalid_input)?,
+            checkpoint_every: self.checkpoint_every,
+            resume: self.resume,
+        })
+    }
+}
+
+/// Runs `report` through a pager when standard output is a terminal, unless
+/// `no_pager`.
+fn paged(no_pager: bool, report: impl FnOnce() -> std::io::Result<()>) -> std::io::Result<()> {
+    let pager = if no_pager { None } else { xmz::pager::Pager::start() };
+    let result = report();
+    if let Some(pager) = pager {
+        pager.finish();
+        // Quitting the pager before the end is not an error.
+        if matches!(&result, Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe) {
+            return Ok(());
+        }
+    }
+    result
+}
+
+/// Whether and how `cached` uses the result cache, with the command line
+/// results are cached under.
+struct ResultCache {
+    command: String,
+    enabled: bool,
+    refresh: bool,
+}
+
+/// Runs `command` with standard output, unless its result on `files` is
+/// cached, in which case that is printed instead; a new result is cached.
+/// Only standard output is kept, not warnings on standard error.
+fn cached(cache: &ResultCache, files: &[impl AsRef<Path>], command: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) -> std::io::Result<()> {
+    let key = if cache.enabled { xmz::cache::key(files, &cache.command).ok() } else { None };
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    if let Some(key) = &key
+        && !cache.refresh
+        && let Some(output) = xmz::cache::get(key)
+    {
+        stdout.write_all(&output)?;
+        return stdout.flush();
+    }
+    let mut out = Capture {
+        inner: stdout,
+        captured: key.as_ref().map(|_| Vec::new()),
+    };
+    command(&mut out)?;
+    out.flush()?;
+    if let (Some(key), Some(output)) = (key, out.captured) {
+        // Failing to cache is no reason to fail the command.
+        let _ = xmz::cache::put(&key, &output);
+    }
+    Ok(())
+}
+
+/// Writes through to `inner`, keeping a copy of up to `cache::MAX_OUTPUT`
+/// bytes; `captured` is dropped when there is more.
+struct Capture<W: Write> {
+    inner: W,
+    captured: Option<Vec<u8>>,
+}
+
+impl<W: Write> Write for Capture<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(captured) = &mut self.captured {
+            if captured.len() + n > xmz::cache::MAX_OUTPUT {
+                self.captured = None;
+            } else {
+                captured.extend_from_slice(&buf[..n]);
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Splits `args` into the files named by all but the last, with
+/// directories and glob patterns expanded, and the last, which is `what`.
+fn files_then<'a>(args: &'a [String], what: &str) -> std::io::Result<(Vec<PathBuf>, &'a str)> {
+    let [paths @ .., last] = args else {
+        return Err(invalid_input(format!("expected files, then {}", what)));
+    };
+    if paths.is_empty() {
+        return Err(invalid_input(format!("expected files, then {}; got only '{}'", what, last)));
+    }
+    let files = collect_files(paths)?;
+    if files.is_empty() {
+        return Err(invalid_input(format!("no XML files in {}", paths.join(" "))));
+    }
+    Ok((files, last))
+}
+
+/// Threads to use when not told: one per CPU.
+fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+#[derive(Args, Debug, Default)]
+struct TuiArgs {
+    /// Open a second TUI pane, on FILE or on the same document if omitted
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    split: Option<Option<String>>,
+
+    /// Query to run when the TUI opens; matches are listed for marking
+    #[arg(long, value_name = "QUERY")]
+    on_open: Option<String>,
+
+    /// Command to run when the TUI quits, e.g. "export marked.xml"
+    #[arg(long, value_name = "COMMAND")]
+    on_exit: Option<String>,
+
+    /// Run the actions in FILE headlessly instead of reading the keyboard
+    #[arg(long, value_name = "FILE")]
+    script: Option<String>,
+
+    /// Write headless snapshots to FILE instead of stdout
+    #[arg(long, value_name = "FILE", requires = "script")]
+    snapshot: Option<String>,
+
+    /// Navigate through a structural index, reusing or writing FILE.xmzidx
+    #[arg(long)]
+    index: bool,
+
+    /// Open the file even if another xmz already has it open
+    #[arg(long)]
+    new_instance: bool,
+
+    /// Color theme: dark, light, solarized or monochrome (default: the
+    /// config file's `theme`, else dark)
+    #[arg(long, value_name = "NAME")]
+    theme: Option<String>,
+
+    /// Reopen the file where the last session left it (always, with
+    /// `resume = true` in the config file)
+    #[arg(long)]
+    resume: bool,
+
+    /// Reload the file whenever it grows, like tail -f
+    #[arg(long)]
+    follow: bool,
+
+    /// Reload the file as soon as it changes on disk, instead of offering to
+    #[arg(long, conflicts_with = "follow")]
+    watch: bool,
+
+    /// Write edits (`=`) back to the file instead of to FILE.edited.xml
+    #[arg(long)]
+    write: bool,
+}
+
+fn main() {
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("xmz: {}", err);
+        // 130 is what shells report for a command ended by Ctrl-C.
+        std::process::exit(if err.kind() == std::io::ErrorKind::Interrupted { 130 } else { 1 });
+    }
+}
+
+fn run(cli: Cli) -> std::io::Result<()> {
+    if cli.sandbox {
+        xmz::sandbox::enable();
+    }
+    if let Some(mbps) = cli.max_mbps {
+        if !(mbps > 0.0 && mbps.is_finite()) {
+            return Err(invalid_input(format!("--max-mbps must be a positive number, got {}", mbps)));
+        }
+        xmz::source::set_max_rate(((mbps * 1_000_000.0) as u64).max(1));
+    }
+    if cli.untrusted {
+        set_limits(Limits::UNTRUSTED);
+    }
+    xmz::dtd::set_expand_entities(cli.expand_entities);
+    xmz::html::set_html(cli.html);
+    xmz::repair::set_lenient(cli.lenient);
+    xmz::progress::set_mode(xmz::progress::Mode::parse(&cli.progress).map_err(invalid_input)?);
+    let lossy = cli.lossy;
+    let no_pager = cli.no_pager;
+    // Everything that may change the output, bar the file itself.
+    let cache = ResultCache {
+        command: format!("{:?}", (&cli.command, lossy, cli.untrusted, cli.expand_entities, cli.html, cli.lenient)),
+        enabled: !cli.no_cache,
+        refresh: cli.refresh,
+    };
+    match cli.command {
+        Some(Command::Tui { file_path, files, args }) => tui(&file_path, &files, &args, lossy),
+        Some(Command::Stats {
+            file_path,
+            stream,
+            follow,
+            histogram,
+            matrix,
+        }) => {
+            if follow {
+                follow_stats(&file_path)
+            } else if histogram {
+                paged(no_pager, || tag_histogram(&file_path, stream, matrix, lossy))
+            } else {
+                paged(no_pager, || stats(&file_path, stream, lossy))
+            }
+        }
+        Some(Command::Agg {
+            args,
+            field,
+            group_by,
+            top,
+            distinct,
+            jobs,
+            values,
+        }) => {
+            let (files, query) = files_then(&args, "a query")?;
+            let query = Query::parse(query).map_err(invalid_input)?;
+            let field = field.as_deref().map(Field::parse).transpose().map_err(invalid_input)?;
+            let group_by = group_by.as_deref().map(Field::parse).transpose().map_err(invalid_input)?;
+            let parser = values.parser()?;
+            let options = AggOptions {
+                query: &query,
+                field: field.as_ref(),
+                group_by: group_by.as_ref(),
+                parser: &parser,
+                dates: values.dates(),
+                distinct,
+                top: top.map(|k| k as usize),
+            };
+            cached(&cache, &files, |mut out| {
+                let mut groups: Option<Groups> = None;
+                let work = |file: &Path| {
+                    let name = file.display().to_string();
+                    let document = load(&name)?;
+                    let (text, _) = decode_text(&document, &name, lossy)?;
+                    Ok(aggregate(&text, &options))
+                };
+                for_each_file(&files, jobs.unwrap_or_else(default_jobs), work, |_, found: std::io::Result<Groups>| {
+                    match &mut groups {
+                        Some(groups) => groups.merge(found?),
+                        None => groups = Some(found?),
+                    }
+                    Ok(())
+                })?;
+                write_groups(&mut out, &groups.expect("at least one file"), &options)?;
+                if files.len() > 1 {
+                    writeln!(out, "{} file(s)", files.len())?;
+                }
+                Ok(())
+            })
+        }
+        Some(Command::Describe { file_path, record, json, values }) => {
+            let parser = values.parser()?;
+            cached(&cache, &[&file_path], |mut out| {
+                let document = load(&file_path)?;
+                let (text, _) = decode_text(&document, &file_path, lossy)?;
+                let xml: &str = &text;
+                let record = match record {
+                    Some(record) => record,
+                    None => {
+                        let guess = detect_record(xml).ok_or_else(|| invalid_input("no repeated element found to describe; pass --record".to_string()))?;
+                        eprintln!("xmz: describing --record {}, repeated {} times under one element; pass --record to choose another", guess.query, guess.count);
+                        guess.query
+                    }
+                };
+                let query = Query::parse(&record).map_err(invalid_input)?;
+                let description = describe(xml, &query, &parser);
+                if json {
+                    writeln!(out, "{}", description_to_json(&description).pretty())
+                } else {
+                    write_description(&mut out, &description)
+                }
+            })
+        }
+        Some(Command::Hash {
+            file_path,
+            query,
+            duplicates,
+            canonical: print_canonical,
+        }) => {
+            let query = query.as_deref().map(Query::parse).transpose().map_err(invalid_input)?;
+            cached(&cache, &[&file_path], |mut out| {
+                let document = load(&file_path)?;
+                let (text, _) = decode_text(&document, &file_path, lossy)?;
+                let xml: &str = &text;
+                match (query, print_canonical) {
+                    (None, false) => writeln!(out, "{}  {}", hex(&digest(xml)), file_path),
+                    (None, true) => writeln!(out, "{}", canonical(xml)),
+                    (Some(query), false) => write_digests(&mut out, xml, &file_path, &digest_matches(xml, &query), duplicates),
+                    (Some(query), true) => {
+                        for range in select(xml, &query) {
+                            writeln!(out, "{}", canonical(&xml[range]))?;
+                        }
+                        Ok(())
+                    }
+                }
+            })
+        }
+        Some(Command::Grep {
+            args,
+            ignore_case,
+            count,
+            after,
+            before,
+            context,
+            jobs,
+        }) => {
+            let (files, pattern) = files_then(&args, "a pattern")?;
+            let pattern = if ignore_case { format!("(?i){}", pattern) } else { pattern.to_string() };
+            let regex = Regex::new(&pattern).map_err(invalid_input)?;
+            let options = GrepOptions {
+                before: context.unwrap_or(before),
+                after: context.unwrap_or(after),
+            };
+            let several = files.len() > 1;
+            cached(&cache, &files, |out| {
+                // Each file's output is written aside, to be printed in order.
+                let work = |file: &Path| {
+                    let name = file.display().to_string();
+                    let document = load(&name)?;
+                    let (text, _) = decode_text(&document, &name, lossy)?;
+                    let hits = grep(&text, &regex, options);
+                    let mut found = Vec::new();
+                    if count {
+                        if several {
+                            writeln!(found, "{}:{}", name, hits.len())?;
+                        } else {
+                            writeln!(found, "{}", hits.len())?;
+                        }
+                    } else {
+                        write_hits(&mut found, several.then_some(name.as_str()), &text, &hits, options)?;
+                    }
+                    Ok((hits.len(), found))
+                };
+                let mut total = 0;
+                for_each_file(&files, jobs.unwrap_or_else(default_jobs), work, |_, found: std::io::Result<(usize, Vec<u8>)>| {
+                    let (hits, found) = found?;
+                    total += hits;
+                    out.write_all(&found)
+                })?;
+                if count && several {
+                    writeln!(out, "total:{}", total)?;
+                }
+                Ok(())
+            })
+        }
+        Some(Command::Du { file_path, depth }) => paged(no_pager, || {
+            cached(&cache, &[&file_path], |mut out| {
+                let document = load(&file_path)?;
+                let (text, _) = decode_text(&document, &file_path, lossy)?;
+                let xml: &str = &text;
+                write_usage(&mut out, xml, depth as usize)
+            })
+        }),
+        Some(Command::Schema {
+            file_path,
+            save,
+            baseline,
+            fail_on_new_elements,
+            fail_on_drift,
+            draft,
+        }) => {
+            let draft = draft.as_deref().map(Draft::parse).transpose().map_err(invalid_input)?;
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let xml: &str = &text;
+            let schema = Schema::infer(xml);
+            if let Some(path) = &save {
+                sandbox::check(Capability::WriteFiles)?;
+                std::fs::write(path, schema.to_json().pretty() + "\n")?;
+            }
+            let Some(baseline) = baseline else {
+                if let Some(draft) = draft {
+                    paged(no_pager, || schema.write_draft(&mut std::io::stdout().lock(), draft))?;
+                } else if save.is_none() {
+                    paged(no_pager, || schema.write_outline(&mut std::io::stdout().lock()))?;
+                }
+                return Ok(());
+            };
+            let baseline = Schema::from_json(&std::fs::read_to_string(&baseline)?)
+                .map_err(|err| invalid_input(format!("{}: {}", baseline, err)))?;
+            let drift = Drift::between(&baseline, &schema);
+            paged(no_pager, || drift.write_report(&mut std::io::stdout().lock()))?;
+            if (fail_on_drift && !drift.is_empty()) || (fail_on_new_elements && !drift.new_elements.is_empty()) {
+                return Err(std::io::Error::other("schema drift detected"));
+            }
+            Ok(())
+        }
+        Some(Command::Check { paths, config, json }) if paths.len() > 1 || config.is_some() || Path::new(&paths[0]).is_dir() => {
+            let checks = match &config {
+                Some(path) => Checks::load(Path::new(path))?,
+                None => Checks::default(),
+            };
+            let mut results = Vec::new();
+            for file in collect_files(&paths)? {
+                let name = file.display().to_string();
+                let document = load(&name)?;
+                let (text, _) = decode_text(&document, &name, lossy)?;
+                results.push((name, checks.run(&text)));
+            }
+            let mut out = std::io::stdout().lock();
+            if json {
+                writeln!(out, "{}", report_to_json(&results).pretty())?;
+            } else {
+                for (file, diagnostics) in &results {
+                    write_diagnostics(&mut out, file, diagnostics)?;
+                }
+            }
+            let failed = results.iter().filter(|(_, diagnostics)| diagnostics.iter().any(|d| d.severity == Severity::Error)).count();
+            if failed > 0 {
+                return Err(std::io::Error::other(format!("{} of {} file(s) failed the checks", failed, results.len())));
+            }
+            Ok(())
+        }
+        Some(Command::Check { paths, config: _, json }) => {
+            let file_path = &paths[0];
+            let document = load(file_path)?;
+            let (text, _) = decode_text(&document, file_path, lossy)?;
+            let diagnostics = well_formedness(&text);
+            let mut out = std::io::stdout().lock();
+            if json {
+                writeln!(out, "{}", diagnostics_to_json(file_path, &diagnostics).pretty())?;
+            } else {
+                write_diagnostics(&mut out, file_path, &diagnostics)?;
+            }
+            if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+                return Err(std::io::Error::other(format!("{} is not well-formed", file_path)));
+            }
+            Ok(())
+        }
+        Some(Command::Validate {
+            file_path,
+            xsd,
+            dtd,
+            records,
+            rules,
+            jobs,
+            max_failures,
+            json,
+        }) => {
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let (schema, against) = match xsd {
+                Some(xsd) => (Some(Xsd::load(Path::new(&xsd)).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?), xsd),
+                None if !dtd => (None, "its rules".to_string()),
+                None => {
+                    let dtd = parse_dtd(&text, &file_path)?.ok_or_else(|| invalid_input(format!("{} has no DOCTYPE", file_path)))?;
+                    if let Some(system) = &dtd.system {
+                        eprintln!("xmz: warning: {}: the external DTD subset {} is not read", file_path, system);
+                    }
+                    (Some(Xsd::from_dtd(&dtd)), "its DTD".to_string())
+                }
+            };
+            for warning in schema.iter().flat_map(|schema| &schema.warnings) {
+                eprintln!("xmz: warning: {}: {}", against, warning);
+            }
+            if let Some(records) = records {
+                let query = Query::parse(&records).map_err(invalid_input)?;
+                let rules = rules.iter().map(|rule| Rule::parse(rule)).collect::<Result<Vec<_>, _>>().map_err(invalid_input)?;
+                let options = RecordOptions {
+                    query: &query,
+                    schema: schema.as_ref(),
+                    rules: &rules,
+                    jobs: jobs.unwrap_or_else(default_jobs),
+                    max_failures,
+                };
+                let summary = validate_records(&text, &options);
+                let mut out = std::io::stdout().lock();
+                if json {
+                    writeln!(out, "{}", summary_to_json(&file_path, &summary).pretty())?;
+                } else {
+                    write_summary(&mut out, &file_path, &summary)?;
+                }
+                if summary.invalid > 0 {
+                    return Err(std::io::Error::other(format!("{} of {} records are not valid against {}", summary.invalid, summary.records, against)));
+                }
+                return Ok(());
+            }
+            let Some(schema) = schema else {
+                return Err(invalid_input("--rule needs --records".to_string()));
+            };
+            let diagnostics = schema.validate(&text);
+            let mut out = std::io::stdout().lock();
+            if json {
+                writeln!(out, "{}", diagnostics_to_json(&file_path, &diagnostics).pretty())?;
+            } else {
+                write_diagnostics(&mut out, &file_path, &diagnostics)?;
+            }
+            if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+                return Err(std::io::Error::other(format!("{} is not valid against {}", file_path, against)));
+            }
+            Ok(())
+        }
+        Some(Command::Audit { file_path, json }) => {
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let findings = audit(&text);
+            let mut out = std::io::stdout().lock();
+            if json {
+                writeln!(out, "{}", diagnostics_to_json(&file_path, &findings).pretty())?;
+            } else {
+                write_diagnostics(&mut out, &file_path, &findings)?;
+            }
+            if findings.iter().any(|d| d.severity == Severity::Error) {
+                return Err(std::io::Error::other(format!("{} declares external entities", file_path)));
+            }
+            Ok(())
+        }
+        Some(Command::Repair { file_path, output, replace_invalid }) => {
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let repaired = repair(
+                &text,
+                &RepairOptions {
+                    invalid_chars: &replace_invalid,
+                    structure_only: false,
+                },
+            );
+            match &output {
+                Some(path) => {
+                    std::fs::write(path, &repaired.output)?;
+                    write_changes(&mut std::io::stdout().lock(), &file_path, &repaired.changes)?;
+                }
+                None => {
+                    std::io::stdout().lock().write_all(repaired.output.as_bytes())?;
+                    write_changes(&mut std::io::stderr().lock(), &file_path, &repaired.changes)?;
+                }
+            }
+            let remaining = well_formedness(&repaired.output).iter().filter(|d| d.severity == Severity::Error).count();
+            if remaining > 0 {
+                let name = output.as_deref().unwrap_or("the output");
+                return Err(std::io::Error::other(format!("{} still has {} problem(s) repair cannot fix; run xmz check on it", name, remaining)));
+            }
+            Ok(())
+        }
+        Some(Command::Fix { file_path, output }) => {
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let fixed = repair(
+                &text,
+                &RepairOptions {
+                    invalid_chars: "",
+                    structure_only: true,
+                },
+            );
+            let mut out = std::io::stdout().lock();
+            match &output {
+                Some(path) => {
+                    std::fs::write(path, &fixed.output)?;
+                    write_changes(&mut out, &file_path, &fixed.changes)?;
+                }
+                None => write_plan(&mut out, &file_path, &fixed.changes)?,
+            }
+            let remaining = well_formedness(&fixed.output).iter().filter(|d| d.severity == Severity::Error).count();
+            if remaining > 0 {
+                let problems = match &output {
+                    Some(path) => format!("{} still has {} problem(s)", path, remaining),
+                    None => format!("the fixed copy would still have {} problem(s)", remaining),
+                };
+                return Err(std::io::Error::other(format!("{} outside the tags; xmz repair fixes those too", problems)));
+            }
+            if output.is_none() && !fixed.changes.is_empty() {
+                return Err(std::io::Error::other(format!("{} has {} tag problem(s)", file_path, fixed.changes.len())));
+            }
+            Ok(())
+        }
+        Some(Command::Format { file_path, against, lines, indent, output }) => {
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let errors = well_formedness(&text).iter().filter(|d| d.severity == Severity::Error).count();
+            if errors > 0 {
+                return Err(std::io::Error::other(format!("{} has {} well-formedness error(s); run xmz check or xmz repair first", file_path, errors)));
+            }
+            let options = FormatOptions { indent };
+            let formatted = if let Some(base_path) = &against {
+                let base = load(base_path)?;
+                let (base_text, _) = decode_text(&base, base_path, lossy)?;
+                let regions: Vec<_> = changed_region(&base_text, &text).into_iter().collect();
+                format_regions(&text, &regions, &options)
+            } else if !lines.is_empty() {
+                let regions = lines.iter().map(|range| parse_lines(&text, range)).collect::<std::io::Result<Vec<_>>>()?;
+                format_regions(&text, &regions, &options)
+            } else {
+                format(&text, &options)
+            };
+            let summary = format!("{}: reformatted {} element(s)", file_path, formatted.elements.len());
+            match &output {
+                Some(path) => {
+                    std::fs::write(path, &formatted.output)?;
+                    println!("{}", summary);
+                }
+                None => {
+                    std::io::stdout().lock().write_all(formatted.output.as_bytes())?;
+                    eprintln!("{}", summary);
+                }
+            }
+            Ok(())
+        }
+        Some(Command::Transform {
+            file_path,
+            tag_case,
+            attribute_case,
+            prune_namespaces,
+            rename_prefix,
+            drop_namespace,
+            attr_to_elem,
+            elem_to_attr,
+            default,
+            output,
+        }) => {
+            let options = TransformOptions {
+                tag_case: tag_case.as_deref().map(Case::parse).transpose().map_err(invalid_input)?,
+                attribute_case: attribute_case.as_deref().map(Case::parse).transpose().map_err(invalid_input)?,
+                prune_namespaces,
+                rename_prefixes: rename_prefix.iter().map(|arg| parse_rename(arg)).collect::<Result<_, _>>().map_err(invalid_input)?,
+                drop_namespaces: drop_namespace,
+                attributes_to_elements: attr_to_elem.iter().map(|path| Conversion::parse(path, true)).collect::<Result<_, _>>().map_err(invalid_input)?,
+                elements_to_attributes: elem_to_attr.iter().map(|path| Conversion::parse(path, false)).collect::<Result<_, _>>().map_err(invalid_input)?,
+                defaults: default.iter().map(|rule| DefaultValue::parse(rule)).collect::<Result<_, _>>().map_err(invalid_input)?,
+            };
+            if let Some(path) = &output {
+                sandbox::check(Capability::WriteFiles)?;
+                // The input is read as it is written, so it cannot be the output.
+                if Path::new(path).exists() && std::fs::canonicalize(path)? == std::fs::canonicalize(&file_path)? {
+                    return Err(invalid_input(format!("{} is the input; write to another file", path)));
+                }
+            }
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let sink: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::fs::File::create(path)?),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut out = std::io::BufWriter::new(sink);
+            let stats = transform(&text, &options, &mut out)?;
+            out.flush()?;
+            let summary = format!("{}: {}", file_path, stats.describe());
+            match output {
+                Some(_) => println!("{}", summary),
+                None => eprintln!("{}", summary),
+            }
+            Ok(())
+        }
+        Some(Command::Diff { old, new, key, whitespace, comments, attribute_order, json, tui }) => {
+            let options = DiffOptions {
+                key,
+                whitespace: Whitespace::parse(&whitespace).map_err(invalid_input)?,
+                comments,
+                attribute_order,
+            };
+            if tui {
+                return compare(&old, &new, &options, lossy);
+            }
+            let old_document = load(&old)?;
+            let new_document = load(&new)?;
+            let (old_text, _) = decode_text(&old_document, &old, lossy)?;
+            let (new_text, _) = decode_text(&new_document, &new, lossy)?;
+            let changes = diff(&old_text, &new_text, &options);
+            let mut out = std::io::stdout().lock();
+            if json {
+                writeln!(out, "{}", changes.to_json(&old, &new).pretty())?;
+            } else {
+                changes.write_report(&mut out)?;
+            }
+            // Like diff(1), differences make the exit status 1.
+            if !changes.is_empty() {
+                return Err(std::io::Error::other(format!("{} and {} differ", old, new)));
+            }
+            Ok(())
+        }
+        Some(Command::GitTextconv { file_path }) => {
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, true)?;
+            // A file that is not well-formed, or not XML at all, is shown as
+            // it is rather than failing the diff.
+            let errors = well_formedness(&text).iter().any(|d| d.severity == Severity::Error);
+            let mut out = std::io::stdout().lock();
+            if errors {
+                out.write_all(text.as_bytes())
+            } else {
+                let formatted = format(&canonical(&text), &FormatOptions { indent: 2 });
+                out.write_all(formatted.output.as_bytes())?;
+                writeln!(out)
+            }
+        }
+        Some(Command::GitDifftool { old, new, key }) => compare(&old, &new, &DiffOptions { key, ..DiffOptions::default() }, lossy),
+        Some(Command::Merge3 { base, ours, theirs, key, output }) => {
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            let mut texts = Vec::new();
+            for path in [&base, &ours, &theirs] {
+                let document = load(path)?;
+                texts.push(decode_text(&document, path, lossy)?.0.into_owned());
+            }
+            let options = MergeOptions { key, ours, theirs };
+            let merged = merge3(&texts[0], &texts[1], &texts[2], &options);
+            match &output {
+                Some(path) => std::fs::write(path, &merged.xml)?,
+                None => std::io::stdout().lock().write_all(merged.xml.as_bytes())?,
+            }
+            if merged.conflicts > 0 {
+                return Err(std::io::Error::other(format!("{} conflict(s) between <!-- <<<<<<< --> and <!-- >>>>>>> --> markers", merged.conflicts)));
+            }
+            Ok(())
+        }
+        Some(Command::Patch { old, new, key, output }) => {
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            let old_document = load(&old)?;
+            let new_document = load(&new)?;
+            let (old_text, _) = decode_text(&old_document, &old, lossy)?;
+            let (new_text, _) = decode_text(&new_document, &new, lossy)?;
+            let patch = Patch::between(&old_text, &new_text, key);
+            let json = patch.to_json().pretty();
+            match &output {
+                Some(path) => std::fs::write(path, format!("{}\n", json))?,
+                None => writeln!(std::io::stdout().lock(), "{}", json)?,
+            }
+            eprintln!("{} operation(s)", patch.operations.len());
+            Ok(())
+        }
+        Some(Command::Apply { patch, file_path, output }) => {
+            if let Some(path) = &output {
+                sandbox::check(Capability::WriteFiles)?;
+                // The input is read as it is written, so it cannot be the output.
+                if Path::new(path).exists() && std::fs::canonicalize(path)? == std::fs::canonicalize(&file_path)? {
+                    return Err(invalid_input(format!("{} is the input; write to another file", path)));
+                }
+            }
+            let source = std::fs::read_to_string(&patch).map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", patch, err)))?;
+            let patch = Patch::parse(&source).map_err(|err| invalid_input(format!("{}: {}", patch, err)))?;
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let splices = patch.splices(&text).map_err(|err| std::io::Error::other(format!("{}: {}", file_path, err)))?;
+            let sink: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::fs::File::create(path)?),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut out = std::io::BufWriter::new(sink);
+            write_patched(&text, &splices, &mut out)?;
+            out.flush()?;
+            let summary = format!("{}: {} operation(s) applied", file_path, patch.operations.len());
+            match output {
+                Some(_) => println!("{}", summary),
+                None => eprintln!("{}", summary),
+            }
+            Ok(())
+        }
+        Some(Command::Tokens { file_path, limit, from }) => {
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            write_tokens(&mut out, &text, from, limit)?;
+            out.flush()
+        }
+        Some(Command::Convert { file_path, output, export }) => {
+            export.check_record()?;
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let source = export.record(&text)?;
+            let query = Query::parse(&source).map_err(invalid_input)?;
+            let filter = export.filter()?;
+            let options = export.options(&query, &source, filter.as_ref())?;
+            xmz::interrupt::install();
+            convert(&text, &options, output.as_deref().map(Path::new))?;
+            Ok(())
+        }
+        Some(Command::Split {
+            file_path,
+            per_file,
+            out_dir,
+            prefix,
+            export,
+        }) => {
+            export.check_record()?;
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let source = export.record(&text)?;
+            let query = Query::parse(&source).map_err(invalid_input)?;
+            let filter = export.filter()?;
+            let options = export.options(&query, &source, filter.as_ref())?;
+            xmz::interrupt::install();
+            let records = split(&text, &options, Path::new(&out_dir), &prefix, per_file)?;
+            eprintln!("{} records in {} file(s)", records, records.div_ceil(per_file));
+            Ok(())
+        }
+        Some(Command::Get { file_path, record, filter, sorted_by, to }) => {
+            let predicate = Predicate::parse(&filter).map_err(|err| invalid_input(format!("--where: {}", err)))?;
+            let format = Format::parse(&to).map_err(invalid_input)?;
+            let sorted_by = sorted_by.as_deref().map(Field::parse).transpose().map_err(|err| invalid_input(format!("--sorted-by: {}", err)))?;
+            let key = match &sorted_by {
+                Some(field) => match predicate.equality() {
+                    Some((equal, key)) if equal == *field => Some(key.to_string()),
+                    _ => return Err(invalid_input("--sorted-by FIELD needs --where FIELD=VALUE".to_string())),
+                },
+                None => None,
+            };
+            if let Some(record) = &record {
+                Query::parse(record).map_err(invalid_input)?;
+            }
+            let document = load(&file_path)?;
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            let mut write_record = |element: &str| -> std::io::Result<()> {
+                match format {
+                    Format::Xml => writeln!(out, "{}", element),
+                    Format::Ndjson => writeln!(out, "{}", xmz::convert::record_to_json(element)),
+                }
+            };
+            let mut count = 0;
+            match (sorted_by, key) {
+                (Some(field), Some(key)) => {
+                    let record = record.ok_or_else(|| invalid_input("--sorted-by needs --record".to_string()))?;
+                    let query = Query::parse(&record).map_err(invalid_input)?;
+                    let tag = query.target_name().ok_or_else(|| invalid_input(format!("--record {} must end in an element name", record)))?;
+                    // UTF-8 is searched in place, reading only the probed
+                    // records; other encodings are decoded first.
+                    let (encoding, _) = xmz::encoding::Encoding::detect(&document[..document.len().min(1024)])?;
+                    let decoded;
+                    let bytes: &[u8] = if encoding == xmz::encoding::Encoding::Utf8 {
+                        &document
+                    } else {
+                        decoded = decode_text(&document, &file_path, lossy)?.0;
+                        decoded.as_bytes()
+                    };
+                    let found = xmz::lookup::find_sorted(bytes, tag, &field, &key);
+                    for range in found.records {
+                        let element = std::str::from_utf8(&bytes[range.clone()])
+                            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: invalid UTF-8 at byte {}", file_path, range.start + err.valid_up_to())))?;
+                        write_record(element)?;
+                        count += 1;
+                    }
+                }
+                _ => {
+                    let (text, _) = decode_text(&document, &file_path, lossy)?;
+                    let source = match record {
+                        Some(record) => record,
+                        None => detect_record(&text).ok_or_else(|| invalid_input("no repeated element found; pass --record".to_string()))?.query,
+                    };
+                    let query = Query::parse(&source).map_err(invalid_input)?;
+                    let mut result = Ok(());
+                    xmz::query::for_each_match(&text, &query, |range| {
+                        let element = &text[range];
+                        if predicate.eval(element) {
+                            count += 1;
+                            result = write_record(element);
+                        }
+                        if result.is_ok() { std::ops::ControlFlow::Continue(()) } else { std::ops::ControlFlow::Break(()) }
+                    });
+                    result?;
+                }
+            }
+            out.flush()?;
+            if count == 0 {
+                return Err(std::io::Error::other(format!("no record matches {}", filter)));
+            }
+            Ok(())
+        }
+        Some(Command::Run { manifest, input }) => {
+            let pipeline = Pipeline::load(Path::new(&manifest))?;
+            let file_path = match (input, &pipeline.input) {
+                (Some(input), _) => input,
+                (None, Some(input)) => input.display().to_string(),
+                (None, None) => return Err(invalid_input(format!("{} names no input; set input or pass --input", manifest))),
+            };
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            xmz::interrupt::install();
+            let mut out = std::io::stdout().lock();
+            pipeline.run(&text, &file_path, &mut out)
+        }
+        Some(Command::Table {
+            file_path,
+            rows,
+            cols,
+            filter,
+            to,
+            no_header,
+            output,
+            connect,
+        }) => {
+            let query = Query::parse(&rows).map_err(invalid_input)?;
+            let filter = filter.as_deref().map(Predicate::parse).transpose().map_err(invalid_input)?;
+            let columns = Column::parse_list(&cols).map_err(invalid_input)?;
+            let format = xmz::table::Format::parse(&to).map_err(invalid_input)?;
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            if connect.is_some() {
+                sandbox::check(Capability::Network)?;
+            }
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let sink: Box<dyn Write> = match (&output, &connect) {
+                (Some(path), _) => Box::new(std::fs::File::create(path)?),
+                (None, Some(address)) => Box::new(std::net::TcpStream::connect(address).map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", address, err)))?),
+                (None, None) => Box::new(std::io::stdout().lock()),
+            };
+            let mut out = std::io::BufWriter::new(sink);
+            let options = TableOptions {
+                query: &query,
+                filter: filter.as_ref(),
+                columns: &columns,
+                format,
+                header: !no_header,
+            };
+            let written = write_rows(&mut out, &text, &options)?;
+            out.flush()?;
+            if written.mismatched > 0 {
+                eprintln!("xmz: warning: {} value(s) did not fit the type of their column and were written as nulls", written.mismatched);
+            }
+            eprintln!("{} rows", written.rows);
+            Ok(())
+        }
+        Some(Command::Xbrl { file_path, to, output }) => {
+            let format = xmz::xbrl::Format::parse(&to).map_err(invalid_input)?;
+            if output.is_some() {
+                sandbox::check(Capability::WriteFiles)?;
+            }
+            let document = load(&file_path)?;
+            let (text, _) = decode_text(&document, &file_path, lossy)?;
+            let facts = xmz::xbrl::facts(&text);
+            let sink: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::fs::File::create(path)?),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut out = std::io::BufWriter::new(sink);
+            xmz::xbrl::write_facts(&mut out, &facts, format)?;
+            out.flush()?;
+            eprintln!("{} facts", facts.len());
+            Ok(())
+        }
+        None => {
+            let Some(file_path) = cli.file_path else {
+                use clap::CommandFactory;
+                Cli::command().print_help()?;
+                std::process::exit(2);
+            };
+            if cli.tui {
+                tui(&file_path, &[], &cli.tui_args, lossy)
+            } else {
+                stats(&file_path, false, lossy)
+            }
+        }
+    }
+}
+
+fn stats(file_path: &str, stream: bool, lossy: bool) -> std::io::Result<()> {
+    xmz::interrupt::install();
+    // Compressed input is decoded on the fly rather than unpacked into memory.
+    if file_path == "-" || stream || xmz::source::compression(file_path)? != Compression::None {
+        let replacements = lossy.then(Replacements::default);
+        print_stats_reader(xmz::source::open_reader(file_path, replacements.as_ref())?, replacements.as_ref())?;
+    } else {
+        let document = load(file_path)?;
+        let (text, damage) = decode_text(&document, file_path, lossy)?;
+        let xml: &str = &text;
+        print_stats(xml, lossy.then_some(damage.replaced))?;
+    }
+    // The partial report is out; exit as a command ended by Ctrl-C would.
+    if xmz::interrupt::interrupted() {
+        std::process::exit(130);
+    }
+    Ok(())
+}
+
+fn tag_histogram(file_path: &str, stream: bool, matrix: Option<usize>, lossy: bool) -> std::io::Result<()> {
+    xmz::interrupt::install();
+    let (histogram, size) = if file_path == "-" || stream || xmz::source::compression(file_path)? != Compression::None {
+        let replacements = lossy.then(Replacements::default);
+        (histogram_reader(xmz::source::open_reader(file_path, replacements.as_ref())?)?, None)
     } else {
-        print_stats(xml);
+        let document = load(file_path)?;
+        let (text, _) = decode_text(&document, file_path, lossy)?;
+        (histogram(&text), Some(text.len() as u64))
+    };
+    histogram.write(&mut std::io::stdout().lock(), matrix)?;
+    if xmz::interrupt::interrupted() {
+        return Err(xmz::interrupt::error(histogram.scanned, size, "the counts cover only the part scanned"));
     }
+    Ok(())
+}
+
+fn follow_stats(file_path: &str) -> std::io::Result<()> {
+    check_followable(file_path)?;
+    xmz::interrupt::install();
+    xmz::stats::follow_stats(Path::new(file_path))
+}
 
+/// `--follow` rereads the file from disk, so it needs a plain file.
+fn check_followable(file_path: &str) -> std::io::Result<()> {
+    if file_path == "-" {
+        return Err(invalid_input("--follow needs a file, not standard input".to_string()));
+    }
+    if xmz::source::compression(file_path)? != Compression::None {
+        return Err(invalid_input("--follow cannot read compressed files".to_string()));
+    }
     Ok(())
 }
+
+/// Parses a `--lines FIRST:LAST` range (or a single line) into a byte range of `xml`.
+fn parse_lines(xml: &str, range: &str) -> std::io::Result<std::ops::Range<usize>> {
+    let (first, last) = range.split_once(':').unwrap_or((range, range));
+    let (Ok(first), Ok(last)) = (first.trim().parse(), last.trim().parse()) else {
+        return Err(invalid_input(format!("--lines expects FIRST:LAST, got '{}'", range)));
+    };
+    line_range(xml, first, last).ok_or_else(|| invalid_input(format!("--lines {}: the document has no line {}", range, first)))
+}
+
+/// The DOCTYPE of `text`, with errors located in `file_path`.
+fn parse_dtd(text: &str, file_path: &str) -> std::io::Result<Option<Dtd>> {
+    Dtd::parse(text).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}:{}", file_path, err)))
+}
+
+/// `text` with the entities declared in its internal DTD expanded.
+fn expand_entities<'a>(text: Cow<'a, str>, file_path: &str) -> std::io::Result<Cow<'a, str>> {
+    match parse_dtd(&text, file_path)? {
+        Some(dtd) if !dtd.entities.is_empty() => {
+            let expanded = dtd.expand(&text).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", file_path, err)))?;
+            Ok(Cow::Owned(expanded))
+        }
+        _ => Ok(text),
+    }
+}
+
+/// Recoveries reported on stderr for each document read with `--lenient`;
+/// `xmz repair` lists them all.
+const MAX_RECOVERIES_SHOWN: usize = 20;
+
+/// `text` repaired as `xmz repair` would, with each change reported as a
+/// warning, and the number of changes.
+fn recover<'a>(text: Cow<'a, str>, file_path: &str) -> (Cow<'a, str>, usize) {
+    let repaired = repair(
+        &text,
+        &RepairOptions {
+            invalid_chars: "\u{FFFD}",
+            structure_only: false,
+        },
+    );
+    if repaired.changes.is_empty() {
+        return (text, 0);
+    }
+    for d in repaired.changes.iter().take(MAX_RECOVERIES_SHOWN) {
+        let at = if d.path.is_empty() { String::new() } else { format!(" (at {})", d.path) };
+        eprintln!("xmz: warning: {}:{}:{}: recovered[{}]: {}{}", file_path, d.line, d.column, d.code, d.message, at);
+    }
+    let count = repaired.changes.len();
+    if count > MAX_RECOVERIES_SHOWN {
+        eprintln!("xmz: warning: {}: {} more recoveries; xmz repair lists them all", file_path, count - MAX_RECOVERIES_SHOWN);
+    }
+    (Cow::Owned(repaired.output), count)
+}
+
+/// How much of a document had to be patched up to read it.
+#[derive(Debug, Clone, Copy, Default)]
+struct Damage {
+    /// Invalid sequences replaced with `--lossy`.
+    replaced: usize,
+    /// Changes made to read it with `--lenient`.
+    recovered: usize,
+}
+
+impl Damage {
+    /// The damage in words, e.g. for the TUI banner.
+    fn describe(self, file_path: &str) -> Vec<String> {
+        let mut parts = Vec::new();
+        if self.replaced > 0 {
+            parts.push(format!("{} invalid sequence(s) in {} replaced with U+FFFD", self.replaced, file_path));
+        }
+        if self.recovered > 0 {
+            parts.push(format!("{} recovered from {} problem(s)", file_path, self.recovered));
+        }
+        parts
+    }
+}
+
+/// Decodes `document`; with `--lossy`, invalid sequences are replaced and
+/// counted, with a warning, instead of failing.
+fn decode_text<'a>(document: &'a Document, file_path: &str, lossy: bool) -> std::io::Result<(Cow<'a, str>, Damage)> {
+    let decoded = if lossy { document.text_lossy() } else { document.text().map(|text| (text, 0)) };
+    let (text, replaced) = decoded.map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", file_path, err)))?;
+    if replaced > 0 {
+        eprintln!("xmz: warning: {}: replaced {} invalid sequence(s) with U+FFFD", file_path, replaced);
+    }
+    let text = if xmz::html::html_enabled() {
+        Cow::Owned(xmz::html::to_xml(&text))
+    } else {
+        if let Some(format @ xmz::sniff::Format::Html) = sniff(text.as_bytes().get(..1024).unwrap_or(text.as_bytes()), lossy) {
+            eprintln!("xmz: warning: {}: {}", file_path, format.describe());
+        }
+        text
+    };
+    let (text, recovered) = match xmz::repair::lenient_enabled() {
+        true => recover(text, file_path),
+        false => (text, 0),
+    };
+    let text = match xmz::dtd::expand_entities_enabled() {
+        true => expand_entities(text, file_path)?,
+        false => text,
+    };
+    check_limits(&text, limits()).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", file_path, err)))?;
+    Ok((text, Damage { replaced, recovered }))
+}
+
+/// `xmz diff --tui`: the two documents side by side, with differences marked.
+fn compare(old_path: &str, new_path: &str, options: &DiffOptions, lossy: bool) -> std::io::Result<()> {
+    if let Some(name) = Config::load()?.theme {
+        set_theme(Theme::named(&name).map_err(invalid_input)?);
+    }
+    let old_document = load(old_path)?;
+    let new_document = load(new_path)?;
+    let (old, _) = decode_text(&old_document, old_path, lossy)?;
+    let (new, _) = decode_text(&new_document, new_path, lossy)?;
+    let options = TuiOptions {
+        split: Some(&new),
+        name: old_path,
+        diff: Some(diff(&old, &new, options)),
+        ..TuiOptions::default()
+    };
+    run_tui(&old, options).map(|_| ())
+}
+
+fn tui(file_path: &str, files: &[String], args: &TuiArgs, lossy: bool) -> std::io::Result<()> {
+    let config = Config::load()?;
+    let theme = args.theme.clone().or(config.theme);
+    if let Some(name) = theme {
+        set_theme(Theme::named(&name).map_err(invalid_input)?);
+    }
+    if Path::new(file_path).is_dir() {
+        if args.script.is_some() || args.split.is_some() || args.on_open.is_some() || args.on_exit.is_some() || !files.is_empty() {
+            return Err(invalid_input("directory mode is interactive and takes no pane options".to_string()));
+        }
+        return run_directory(Path::new(file_path));
+    }
+    if args.follow {
+        if args.script.is_some() || args.split.is_some() || args.on_open.is_some() || args.on_exit.is_some() || args.index || !files.is_empty() {
+            return Err(invalid_input("--follow is interactive and takes no pane options".to_string()));
+        }
+        check_followable(file_path)?;
+        return run_follow(Path::new(file_path));
+    }
+    // Look for an instance that already has the file open before loading or
+    // indexing it again. Scripts and several files always run on their own.
+    let mut guard = None;
+    if args.script.is_none() && files.is_empty() && !args.new_instance && sandbox::check(Capability::WriteFiles).is_ok() {
+        match claim(Path::new(file_path)) {
+            Claim::Owner(listener) => guard = Some(listener),
+            Claim::Running(peer) => {
+                let actions: Vec<String> = args.on_open.iter().map(|query| format!("query {}", query)).collect();
+                if !offer_handover(file_path, peer, &actions)? {
+                    return Ok(());
+                }
+            }
+            Claim::Unguarded => {}
+        }
+    }
+    if args.watch && file_path == "-" {
+        return Err(invalid_input("--watch needs a file, not standard input".to_string()));
+    }
+    let script = args.script.as_deref().map(std::fs::read_to_string).transpose()?;
+    if args.snapshot.is_some() {
+        sandbox::check(Capability::WriteFiles)?;
+    }
+
+    // Reloading reads every document again and reopens the first one where it was.
+    // After an edit to a copy, the copy is what is reopened.
+    let mut location = None;
+    let mut source = file_path.to_string();
+    let edit_target = if args.write { PathBuf::from(file_path) } else { xmz::splice::copy_path(Path::new(file_path)) };
+    loop {
+        let file_path = source.as_str();
+        let document = load(file_path)?;
+        let (text, damage) = decode_text(&document, file_path, lossy)?;
+        let xml: &str = &text;
+        // Edits are spliced into the text, so they can only be written back
+        // to a file whose bytes it is.
+        let editable = file_path != "-" && xmz::source::compression(file_path)? == Compression::None && *document == *xml.as_bytes();
+
+        let split_document = match &args.split {
+            Some(Some(path)) => Some(load(path)?),
+            _ => None,
+        };
+        let split_text = match (&args.split, &split_document) {
+            (Some(Some(path)), Some(document)) => Some(decode_text(document, path, lossy)?),
+            _ => None,
+        };
+        let tab_documents = files.iter().map(|path| load(path)).collect::<std::io::Result<Vec<_>>>()?;
+        let tab_texts = files
+            .iter()
+            .zip(&tab_documents)
+            .map(|(path, document)| decode_text(document, path, lossy))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let mut damaged = damage.describe(file_path);
+        if let (Some(Some(path)), Some((_, split_damage))) = (&args.split, &split_text) {
+            damaged.extend(split_damage.describe(path));
+        }
+        damaged.extend(files.iter().zip(&tab_texts).flat_map(|(path, (_, damage))| damage.describe(path)));
+        let warning = (!damaged.is_empty()).then(|| damaged.join(", "));
+        let split = match (&args.split, &split_text) {
+            (Some(_), Some((text, _))) => Some(&**text),
+            (Some(None), None) => Some(xml),
+            _ => None,
+        };
+
+        let options = TuiOptions {
+            split,
+            name: file_path,
+            tabs: files.iter().zip(&tab_texts).map(|(path, (text, _))| (path.as_str(), &**text)).collect(),
+            on_open: args.on_open.as_deref().filter(|_| location.is_none()),
+            on_exit: args.on_exit.as_deref(),
+            script: script.as_deref(),
+            snapshot_path: args.snapshot.as_deref(),
+            index: args.index.then(|| Index::load_or_build(std::path::Path::new(file_path), xml)),
+            warning,
+            instance: guard.take(),
+            session: (file_path != "-").then(|| SessionKey::for_file(Path::new(file_path))).flatten(),
+            resume: location.is_none() && (args.resume || config.resume),
+            diff: None,
+            watch: (file_path != "-").then(|| Path::new(file_path)),
+            auto_reload: args.watch,
+            location: location.take(),
+            edit_target: editable.then(|| edit_target.clone()),
+        };
+        match run_tui(xml, options)? {
+            Exit::Quit => return Ok(()),
+            Exit::Reload(steps, instance) => {
+                location = Some(steps);
+                guard = instance;
+            }
+            Exit::Edited(steps, instance) => {
+                location = Some(steps);
+                guard = instance;
+                source = edit_target.to_string_lossy().into_owned();
+            }
+        }
+    }
+}
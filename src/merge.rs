@@ -0,0 +1,298 @@
+//! `xmz merge3`: a three-way merge of two versions of a document that were
+//! both edited from a common base, element by element rather than line by
+//! line.
+//!
+//! Elements are paired across the three versions the way `xmz diff` pairs
+//! them, by name and position among same-named siblings or by a key
+//! attribute. An element that only one side changed (by its canonical form,
+//! see `hash`) is taken from that side; one both sides changed is merged
+//! attribute by attribute and child by child. Children added on either side
+//! are kept, in our order with theirs placed after the sibling they follow
+//! there, and children deleted on one side are dropped if the other left
+//! them alone.
+//!
+//! What cannot be reconciled — an attribute or text changed differently on
+//! each side, a child added differently on both, or changed on one side and
+//! deleted on the other — is a conflict: both versions of the element are
+//! written one after the other between markers in XML comments, so the
+//! result stays well-formed (unless the root itself conflicts) and opens in
+//! any XML tool for resolving.
+
+use crate::diff::{DiffOptions, Element, child_steps, elements};
+use crate::hash::digest;
+use crate::parser::{attribute_pairs, decode_entities};
+use std::collections::{HashMap, HashSet};
+
+/// How children are paired, and how the sides are named in conflict markers.
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    /// Attribute identifying children, e.g. `id`.
+    pub key: Option<String>,
+    pub ours: String,
+    pub theirs: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub xml: String,
+    pub conflicts: usize,
+}
+
+struct Version<'a> {
+    xml: &'a str,
+    elements: Vec<Element<'a>>,
+}
+
+impl<'a> Version<'a> {
+    fn new(xml: &'a str) -> Self {
+        Version { xml, elements: elements(xml, &DiffOptions::default()) }
+    }
+
+    fn raw(&self, id: usize) -> &'a str {
+        &self.xml[self.elements[id].span.clone()]
+    }
+
+    fn start_tag(&self, id: usize) -> &'a str {
+        let element = &self.elements[id];
+        &self.xml[element.span.start..element.content.start]
+    }
+
+    /// Attribute names and decoded values, in the order written.
+    fn attributes(&self, id: usize) -> Vec<(&'a str, String)> {
+        attribute_pairs(self.elements[id].attrs).into_iter().map(|(name, value)| (name, decode_entities(value).into_owned())).collect()
+    }
+
+    /// The children of `id` by their paired steps (`book[2]`, `book[@id='b7']`).
+    fn steps(&self, id: usize, key: Option<&str>) -> Vec<(String, usize)> {
+        let element = &self.elements[id];
+        child_steps(&self.elements, element, key).into_iter().zip(element.children.iter().copied()).collect()
+    }
+}
+
+/// An element in each version, where it is present.
+#[derive(Debug, Clone, Copy, Default)]
+struct Triple {
+    base: Option<usize>,
+    ours: Option<usize>,
+    theirs: Option<usize>,
+}
+
+struct Merger<'a> {
+    base: Version<'a>,
+    ours: Version<'a>,
+    theirs: Version<'a>,
+    options: &'a MergeOptions,
+    conflicts: usize,
+}
+
+impl<'a> Merger<'a> {
+    fn digest(version: &Version, id: Option<usize>) -> Option<[u8; 32]> {
+        id.map(|id| digest(version.raw(id)))
+    }
+
+    /// Writes the merge of one element, nothing if it was deleted.
+    /// `indent` is what precedes it on its line, for conflict markers.
+    fn element(&mut self, triple: Triple, indent: &str, out: &mut String) {
+        let base = Self::digest(&self.base, triple.base);
+        let ours = Self::digest(&self.ours, triple.ours);
+        let theirs = Self::digest(&self.theirs, triple.theirs);
+        if ours == theirs || base == theirs {
+            if let Some(id) = triple.ours {
+                out.push_str(self.ours.raw(id));
+            }
+        } else if base == ours {
+            if let Some(id) = triple.theirs {
+                out.push_str(self.theirs.raw(id));
+            }
+        } else if let Triple { base: Some(b), ours: Some(o), theirs: Some(t) } = triple
+            && self.ours.elements[o].name == self.theirs.elements[t].name
+            && let Some(merged) = self.inside(b, o, t)
+        {
+            out.push_str(&merged);
+        } else {
+            self.conflict(triple, indent, out);
+        }
+    }
+
+    fn conflict(&mut self, triple: Triple, indent: &str, out: &mut String) {
+        self.conflicts += 1;
+        let newline = format!("\n{}", indent);
+        let label = |name: &str| name.replace("--", "- -");
+        out.push_str(&format!("<!-- <<<<<<< {} -->", label(&self.options.ours)));
+        if let Some(id) = triple.ours {
+            out.push_str(&newline);
+            out.push_str(self.ours.raw(id));
+        }
+        out.push_str(&newline);
+        out.push_str("<!-- ======= -->");
+        if let Some(id) = triple.theirs {
+            out.push_str(&newline);
+            out.push_str(self.theirs.raw(id));
+        }
+        out.push_str(&newline);
+        out.push_str(&format!("<!-- >>>>>>> {} -->", label(&self.options.theirs)));
+    }
+
+    /// Merges an element both sides changed, or `None` if its attributes or
+    /// own text conflict.
+    fn inside(&mut self, b: usize, o: usize, t: usize) -> Option<String> {
+        let attributes = merge_attributes(&self.base.attributes(b), &self.ours.attributes(o), &self.theirs.attributes(t))?;
+        // The text between children, and the layout around them, comes from
+        // the side that changed the text.
+        let (base_text, our_text, their_text) = (&self.base.elements[b].text, &self.ours.elements[o].text, &self.theirs.elements[t].text);
+        let ours_lead = if our_text == their_text || base_text == their_text {
+            true
+        } else if base_text == our_text {
+            false
+        } else {
+            return None;
+        };
+        let children = self.children(b, o, t, self.options.key.as_deref());
+        let (lead, lead_id) = if ours_lead { (&self.ours, o) } else { (&self.theirs, t) };
+        let element = &lead.elements[lead_id];
+        let name = element.name;
+        let mut out = if attributes == self.ours.attributes(o) {
+            self.ours.start_tag(o).to_string()
+        } else if attributes == self.theirs.attributes(t) {
+            self.theirs.start_tag(t).to_string()
+        } else {
+            let mut tag = format!("<{}", name);
+            for (name, value) in &attributes {
+                tag.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+            }
+            tag.push('>');
+            tag
+        };
+        // Each child follows the whitespace or text before it on the leading
+        // side, or on the side it comes from.
+        let gap_before = |version: &Version<'a>, parent: usize, id: usize| -> &'a str {
+            let element = &version.elements[parent];
+            let at = element.children.iter().position(|&child| child == id).unwrap_or(0);
+            let from = if at == 0 { element.content.start } else { version.elements[element.children[at - 1]].span.end };
+            &version.xml[from..version.elements[id].span.start]
+        };
+        let gaps: Vec<&'a str> = children
+            .iter()
+            .map(|triple| match (if ours_lead { triple.ours } else { triple.theirs }, triple.ours, triple.theirs) {
+                (Some(id), _, _) => gap_before(lead, lead_id, id),
+                (None, Some(id), _) => gap_before(&self.ours, o, id),
+                (None, None, Some(id)) => gap_before(&self.theirs, t, id),
+                (None, None, None) => "",
+            })
+            .collect();
+        let last = element.children.last().map_or(element.content.start, |&id| lead.elements[id].span.end);
+        let trailing = &lead.xml[last..element.content.end];
+
+        let mut content = String::new();
+        for (triple, gap) in children.into_iter().zip(gaps) {
+            let indent = gap.rsplit('\n').next().filter(|indent| indent.trim().is_empty()).unwrap_or("");
+            let mut child = String::new();
+            self.element(triple, indent, &mut child);
+            if !child.is_empty() {
+                content.push_str(gap);
+                content.push_str(&child);
+            }
+        }
+        content.push_str(trailing);
+        if let Some(tag) = out.strip_suffix("/>") {
+            if content.is_empty() {
+                return Some(out);
+            }
+            // An empty element gained content.
+            out = format!("{}>", tag.trim_end());
+        }
+        out.push_str(&content);
+        out.push_str("</");
+        out.push_str(name);
+        out.push('>');
+        Some(out)
+    }
+
+    /// The children of the three versions of an element, paired, in our
+    /// order with children only they have after the sibling they follow.
+    fn children(&self, b: usize, o: usize, t: usize, key: Option<&str>) -> Vec<Triple> {
+        let base: HashMap<String, usize> = self.base.steps(b, key).into_iter().collect();
+        let ours = self.ours.steps(o, key);
+        let theirs = self.theirs.steps(t, key);
+        let their_ids: HashMap<&str, usize> = theirs.iter().map(|(step, id)| (step.as_str(), *id)).collect();
+        let our_steps: HashSet<&str> = ours.iter().map(|(step, _)| step.as_str()).collect();
+        let mut merged: Vec<Triple> = ours
+            .iter()
+            .map(|(step, id)| Triple {
+                base: base.get(step).copied(),
+                ours: Some(*id),
+                theirs: their_ids.get(step.as_str()).copied(),
+            })
+            .collect();
+        // Place each child only they have after the last shared one before it.
+        let mut at = 0;
+        for (step, id) in &theirs {
+            if our_steps.contains(step.as_str()) {
+                at = merged.iter().position(|triple| triple.theirs == Some(*id)).map_or(at, |i| i + 1);
+                continue;
+            }
+            merged.insert(
+                at,
+                Triple {
+                    base: base.get(step).copied(),
+                    ours: None,
+                    theirs: Some(*id),
+                },
+            );
+            at += 1;
+        }
+        merged
+    }
+}
+
+/// Merges attributes by name, or `None` if one was changed differently on
+/// each side. The result keeps our order, with their new attributes last.
+fn merge_attributes<'a>(base: &[(&'a str, String)], ours: &[(&'a str, String)], theirs: &[(&'a str, String)]) -> Option<Vec<(&'a str, String)>> {
+    let get = |list: &[(&'a str, String)], name: &str| list.iter().find(|(n, _)| *n == name).map(|(_, value)| value.clone());
+    let mut names: Vec<&str> = ours.iter().map(|(name, _)| *name).collect();
+    names.extend(theirs.iter().map(|(name, _)| *name).filter(|name| !ours.iter().any(|(n, _)| n == name)));
+    names.extend(base.iter().map(|(name, _)| *name).filter(|name| !names.contains(name)).collect::<Vec<_>>());
+    let mut merged = Vec::new();
+    for name in names {
+        let (b, o, t) = (get(base, name), get(ours, name), get(theirs, name));
+        let value = if o == t || b == t {
+            o
+        } else if b == o {
+            t
+        } else {
+            return None;
+        };
+        if let Some(value) = value {
+            merged.push((name, value));
+        }
+    }
+    Some(merged)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;")
+}
+
+/// Merges `ours` and `theirs`, both edited from `base`. Everything outside
+/// the root element is taken from ours.
+pub fn merge3(base: &str, ours: &str, theirs: &str, options: &MergeOptions) -> Merged {
+    let mut merger = Merger {
+        base: Version::new(base),
+        ours: Version::new(ours),
+        theirs: Version::new(theirs),
+        options,
+        conflicts: 0,
+    };
+    let root = |version: &Version| (!version.elements.is_empty()).then_some(0);
+    let triple = Triple {
+        base: root(&merger.base),
+        ours: root(&merger.ours),
+        theirs: root(&merger.theirs),
+    };
+    let mut xml = String::new();
+    let span = merger.ours.elements.first().map_or(ours.len()..ours.len(), |root| root.span.clone());
+    xml.push_str(&ours[..span.start]);
+    merger.element(triple, "", &mut xml);
+    xml.push_str(&ours[span.end..]);
+    Merged { xml, conflicts: merger.conflicts }
+}
@@ -0,0 +1,80 @@
+//! Paging of long reports on a terminal. While a report is written, standard
+//! output is handed to `$XMZ_PAGER`, `$PAGER` or `less`, which shows it a
+//! screen at a time and searches it with `/`. `less` is run as `less -FRX`
+//! unless `LESS` says otherwise, so a report that fits on one screen is
+//! printed as usual, colours included. An empty pager, or `cat`, turns
+//! paging off, and so do `--no-pager` and `--sandbox`; output that is not a
+//! terminal is never paged. Paging is only done on Linux.
+
+use crate::sandbox::{self, Capability};
+use std::io::{IsTerminal, Write};
+use std::process::Child;
+
+/// A pager reading what is written to standard output, until `finish`.
+pub struct Pager {
+    child: Child,
+    /// The descriptor standard output had before.
+    #[cfg(target_os = "linux")]
+    saved: libc::c_int,
+    /// How `SIGPIPE` was handled before.
+    #[cfg(target_os = "linux")]
+    sigpipe: libc::sighandler_t,
+}
+
+impl Pager {
+    /// Starts the pager and points standard output at it, or returns None
+    /// when output is not to be paged or the pager cannot be run.
+    pub fn start() -> Option<Pager> {
+        if !std::io::stdout().is_terminal() || sandbox::check(Capability::RunCommands).is_err() {
+            return None;
+        }
+        let command = std::env::var("XMZ_PAGER").or_else(|_| std::env::var("PAGER")).unwrap_or_else(|_| "less".to_string());
+        if command.trim().is_empty() || command.trim() == "cat" {
+            return None;
+        }
+        Self::spawn(&command)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn spawn(command: &str) -> Option<Pager> {
+        use std::os::fd::AsRawFd;
+        use std::process::{Command, Stdio};
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .env("LESS", std::env::var("LESS").unwrap_or_else(|_| "FRX".to_string()))
+            .stdin(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let stdin = child.stdin.take()?;
+        let _ = std::io::stdout().flush();
+        // Standard output becomes the pager's input; the pipe's own
+        // descriptor is closed when `stdin` is dropped.
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved < 0 || unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+            let _ = child.kill();
+            return None;
+        }
+        // Writing after the pager quits fails with `BrokenPipe`, which the
+        // report ends on, rather than killing xmz before it cleans up.
+        let sigpipe = unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) };
+        Some(Pager { child, saved, sigpipe })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn spawn(_: &str) -> Option<Pager> {
+        None
+    }
+
+    /// Ends the report: the pager sees the end of its input, and is waited
+    /// for until the user quits it.
+    pub fn finish(mut self) {
+        let _ = std::io::stdout().flush();
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::dup2(self.saved, libc::STDOUT_FILENO);
+            libc::close(self.saved);
+            libc::signal(libc::SIGPIPE, self.sigpipe);
+        }
+        let _ = self.child.wait();
+    }
+}
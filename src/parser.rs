@@ -1,13 +1,124 @@
 use memchr::memchr;
-use std::ops::ControlFlow;
+use xmz_core::{decode_entity, tokenize};
+use std::io::{self, Read};
+use std::ops::{ControlFlow, Range};
+use std::sync::OnceLock;
 
-#[derive(Debug)]
-pub enum Token<'a> {
-    StartTag(&'a str, &'a str), // name, attributes
-    EndTag(&'a str),
-    Text(&'a str),
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Bounds on what a document may contain before xmz refuses it, so a file
+/// from an unknown source cannot exhaust memory or stack. One set applies to
+/// the whole process (`--untrusted` chooses `Limits::UNTRUSTED`): the reader
+/// enforces it as it streams, and `check_limits` before a loaded document is
+/// used.
+///
+/// Entities declared in a DOCTYPE are not expanded; their references stay as
+/// written, and only the predefined entities and character references are
+/// decoded. `--expand-entities` (see `dtd`), which cannot be combined with
+/// `--untrusted`, expands those of the internal subset when a document is
+/// loaded, within bounds of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Deepest element nesting.
+    pub max_depth: Option<usize>,
+    /// Longest tag or text run, in bytes.
+    pub max_token_len: Option<usize>,
+    /// Most attributes on one element.
+    pub max_attributes: Option<usize>,
+    /// Whether anything may be fetched over the network; when not,
+    /// `sandbox::check(Capability::Network)` fails as under `--sandbox`.
+    pub external_fetches: bool,
+}
+
+impl Limits {
+    pub const NONE: Limits = Limits {
+        max_depth: None,
+        max_token_len: None,
+        max_attributes: None,
+        external_fetches: true,
+    };
+
+    pub const UNTRUSTED: Limits = Limits {
+        max_depth: Some(256),
+        max_token_len: Some(1024 * 1024),
+        max_attributes: Some(256),
+        external_fetches: false,
+    };
+
+    pub fn is_none(&self) -> bool {
+        *self == Limits::NONE
+    }
+}
+
+/// Chooses the limits for the rest of the process; later calls have no effect.
+pub fn set_limits(limits: Limits) {
+    let _ = LIMITS.set(limits);
+}
+
+/// The chosen limits, or none if none were chosen.
+pub fn limits() -> &'static Limits {
+    LIMITS.get_or_init(|| Limits::NONE)
+}
+
+/// Tracks the nesting of a pass to check each token against `Limits`.
+struct LimitGuard {
+    limits: Limits,
+    depth: usize,
 }
 
+impl LimitGuard {
+    /// Checks `token`, which is `len` bytes long at byte `offset`.
+    fn check(&mut self, token: &Token, len: usize, offset: u64) -> Result<(), String> {
+        let limits = &self.limits;
+        if let Some(max) = limits.max_token_len
+            && len > max
+        {
+            return Err(format!("a token of {} bytes at byte {} exceeds the limit of {}", len, offset, max));
+        }
+        match token {
+            Token::StartTag(name, attrs) => {
+                self.depth += 1;
+                if let Some(max) = limits.max_depth
+                    && self.depth > max
+                {
+                    return Err(format!("<{}> at byte {} is nested deeper than the limit of {} levels", name, offset, max));
+                }
+                // Counting '=' is a cheap upper bound; only parse when it is over.
+                if let Some(max) = limits.max_attributes
+                    && memchr::memchr_iter(b'=', attrs.as_bytes()).count() > max
+                {
+                    let count = attribute_pairs(attrs).len();
+                    if count > max {
+                        return Err(format!("<{}> at byte {} has {} attributes, over the limit of {}", name, offset, count, max));
+                    }
+                }
+            }
+            Token::EndTag(_) => self.depth = self.depth.saturating_sub(1),
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Fails with the first place `xml` goes over `limits`.
+pub fn check_limits(xml: &str, limits: &Limits) -> Result<(), String> {
+    if limits.is_none() {
+        return Ok(());
+    }
+    let mut guard = LimitGuard { limits: *limits, depth: 0 };
+    let mut result = Ok(());
+    stream_xml_spans(xml, |token, span| match guard.check(&token, span.len(), span.start as u64) {
+        Ok(()) => Continue(()),
+        Err(err) => {
+            result = Err(err);
+            Break(())
+        }
+    });
+    result
+}
+
+pub use xmz_core::{Attributes, Handler, Token, parse_with_handler};
+
 pub use std::ops::ControlFlow::{Break, Continue};
 
 /// Streams tokens from XML without allocations.
@@ -16,94 +127,95 @@ pub fn stream_xml<'a, F>(xml: &'a str, mut on_token: F)
 where
     F: FnMut(Token<'a>) -> ControlFlow<()>, 
 {
-    let bytes = xml.as_bytes();
-    let len = bytes.len();
-    let mut pos = 0;
+    stream_xml_spans(xml, |token, _| on_token(token));
+}
 
-    while pos < len {
-        // Fast skip whitespace using memchr
-        while pos < len {
-            let b = unsafe { *bytes.get_unchecked(pos) };
-            if !b.is_ascii_whitespace() {
-                break;
-            }
-            pos += 1;
-        }
+/// Like `stream_xml`, but also passes the byte range each token occupies in `xml`.
+/// A self-closing tag yields a `StartTag` spanning the whole tag followed by an
+/// `EndTag` with an empty range at its end.
+pub fn stream_xml_spans<'a, F>(xml: &'a str, mut on_token: F)
+where
+    F: FnMut(Token<'a>, Range<usize>) -> ControlFlow<()>,
+{
+    let _ = tokenize(xml, true, &mut on_token);
+}
 
-        if pos >= len {
-            break;
-        }
+/// Streams tokens from any reader, e.g. a pipe, without mapping the whole input.
+/// Input is read in chunks; a token cut by a chunk boundary is completed with
+/// the next read. Tokens only borrow the internal buffer, so they are valid for
+/// the duration of the callback. Returns the number of bytes read. Input over
+/// the process's `limits()` fails with `InvalidData`.
+pub fn stream_xml_reader<R, F>(reader: R, mut on_token: F) -> io::Result<u64>
+where
+    R: Read,
+    F: FnMut(Token<'_>) -> ControlFlow<()>,
+{
+    stream_xml_reader_spans(reader, |token, _| on_token(token))
+}
 
-        let current_byte = unsafe { *bytes.get_unchecked(pos) };
-        if current_byte == b'<' {
-            if pos + 1 < len && unsafe { *bytes.get_unchecked(pos + 1) } == b'/' {
-                let start = pos + 2;
-                if let Some(rel) = memchr(b'>', &bytes[start..]) {
-                    let end_pos = start + rel;
-                    let name = unsafe { xml.get_unchecked(start..end_pos) };
-                    if on_token(Token::EndTag(name)).is_break() {
-                        return;
-                    }
-                    pos = end_pos + 1;
-                } else {
-                    break;
-                }
-            } else if pos + 3 < len && unsafe { *bytes.get_unchecked(pos + 1) } == b'!' {
-                let mut end_pos = pos + 2;
-                while end_pos < len && unsafe { *bytes.get_unchecked(end_pos) } != b'>' {
-                    end_pos += 1;
-                }
-                pos = if end_pos < len { end_pos + 1 } else { len };
-            } else {
-                let start = pos + 1;
-                if let Some(rel) = memchr(b'>', &bytes[start..]) {
-                    let end_pos = start + rel;
-                    let is_self_closing = end_pos > start && unsafe { *bytes.get_unchecked(end_pos - 1) } == b'/';
-                    let mut name_end = start;
-                    while name_end < end_pos {
-                        let byte = unsafe { *bytes.get_unchecked(name_end) };
-                        if byte <= b' ' || byte == b'/' {
-                            break;
-                        }
-                        name_end += 1;
-                    }
-                    let name = unsafe { xml.get_unchecked(start..name_end) };
-                    
-                    let attrs_start = name_end;
-                    let attrs_end = if is_self_closing { end_pos - 1 } else { end_pos };
-                    let attrs = unsafe { xml.get_unchecked(attrs_start..attrs_end) };
-
-                    if on_token(Token::StartTag(name, attrs)).is_break() {
-                        return;
-                    }
-                    if is_self_closing && on_token(Token::EndTag(name)).is_break() {
-                        return;
-                    }
-                    pos = end_pos + 1;
-                } else {
-                    break;
-                }
+/// Like `stream_xml_reader`, but also passes the byte range each token
+/// occupies in the whole input.
+pub fn stream_xml_reader_spans<R, F>(mut reader: R, mut on_token: F) -> io::Result<u64>
+where
+    R: Read,
+    F: FnMut(Token<'_>, Range<u64>) -> ControlFlow<()>,
+{
+    const CHUNK: usize = 64 * 1024;
+    let mut buf: Vec<u8> = Vec::with_capacity(CHUNK);
+    let mut total = 0u64;
+    let mut guard = LimitGuard { limits: *limits(), depth: 0 };
+    let mut exceeded = None;
+    loop {
+        // Read at least as much as is buffered so a huge token is not rescanned once per chunk.
+        let filled = buf.len();
+        buf.resize(filled + CHUNK.max(filled), 0);
+        let n = loop {
+            match reader.read(&mut buf[filled..]) {
+                Ok(n) => break n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
             }
-        } else {
-            let start = pos;
-            let end_pos = memchr(b'<', &bytes[start..]).map_or(len, |rel| start + rel);
-            if end_pos > start {
-                let mut t_start = start;
-                let mut t_end = end_pos;
-                while t_start < t_end && unsafe { *bytes.get_unchecked(t_start) }.is_ascii_whitespace() {
-                    t_start += 1;
-                }
-                while t_end > t_start && unsafe { *bytes.get_unchecked(t_end - 1) }.is_ascii_whitespace() {
-                    t_end -= 1;
-                }
-                if t_end > t_start {
-                    let text = unsafe { xml.get_unchecked(t_start..t_end) };
-                    if on_token(Token::Text(text)).is_break() {
-                        return;
-                    }
-                }
+        };
+        buf.truncate(filled + n);
+        total += n as u64;
+        let eof = n == 0;
+
+        // A multi-byte character may be split across reads; keep its start for later.
+        let valid = match std::str::from_utf8(&buf) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() && !eof => err.valid_up_to(),
+            Err(err) => {
+                let at = total - (buf.len() - err.valid_up_to()) as u64;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid UTF-8 at byte {}", at)));
+            }
+        };
+        let text = unsafe { std::str::from_utf8_unchecked(&buf[..valid]) };
+        let base = total - buf.len() as u64;
+        let step = tokenize(text, eof, &mut |token, span| {
+            if let Err(err) = guard.check(&token, span.len(), base + span.start as u64) {
+                exceeded = Some(err);
+                return Break(());
             }
-            pos = end_pos;
+            on_token(token, base + span.start as u64..base + span.end as u64)
+        });
+        if let Some(err) = exceeded.take() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        match step {
+            Continue(consumed) => {
+                buf.drain(..consumed);
+            }
+            Break(()) => return Ok(total),
+        }
+        // An unfinished token is buffered whole; refuse to buffer one over the limit.
+        if let Some(max) = guard.limits.max_token_len
+            && buf.len() > max
+        {
+            let message = format!("a token of over {} bytes at byte {} exceeds the limit", max, total - buf.len() as u64);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        if eof {
+            return Ok(total);
         }
     }
 }
@@ -195,4 +307,114 @@ pub fn extract_attributes(xml: &str, mut offset: usize) -> Vec<(&str, &str)> {
         }
     }
     attrs
-}
\ No newline at end of file
+}
+
+/// Parses a raw attribute string (as found in `Token::StartTag`) into pairs.
+pub fn attribute_pairs(attrs: &str) -> Vec<(&str, &str)> {
+    xmz_core::attributes(attrs).collect()
+}
+
+/// Replaces the predefined entities and character references in `text`.
+/// Unknown entities are kept as written.
+pub fn decode_entities(text: &str) -> std::borrow::Cow<'_, str> {
+    if memchr(b'&', text.as_bytes()).is_none() {
+        return std::borrow::Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').and_then(|semi| Some((decode_entity(&rest[1..semi])?, semi + 1)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    std::borrow::Cow::Owned(out)
+}
+
+/// The text a `Text` token holds, with its entities decoded, or a `CData`
+/// token, as written; None for tags.
+pub fn token_text<'a>(token: &Token<'a>) -> Option<std::borrow::Cow<'a, str>> {
+    match *token {
+        Token::Text(text) => Some(decode_entities(text)),
+        Token::CData(text) => Some(std::borrow::Cow::Borrowed(text)),
+        Token::StartTag(..) | Token::EndTag(_) => None,
+    }
+}
+
+/// The text of an element's content as written between its tags: entities
+/// decoded, CDATA sections unwrapped, and comments, processing instructions
+/// and the tags of any child elements dropped.
+pub fn text_content(raw: &str) -> String {
+    let mut text = String::new();
+    let mut rest = raw;
+    while let Some(lt) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..lt]));
+        rest = &rest[lt..];
+        let (body, end) = if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let len = cdata.find("]]>").unwrap_or(cdata.len());
+            (&cdata[..len], "]]>")
+        } else if rest.starts_with("<!--") {
+            ("", "-->")
+        } else {
+            ("", ">")
+        };
+        text.push_str(body);
+        rest = rest.find(end).map_or("", |at| &rest[at + end.len()..]);
+    }
+    text.push_str(&decode_entities(rest));
+    text
+}
+
+/// The text of the element `raw` as read, for display and export: entities
+/// decoded, CDATA sections unwrapped and whitespace runs turned into single
+/// spaces. Text of sibling elements that touch (`<b>x</b><c>y</c>`) is
+/// separated by a space, while markup inside a word (`wo<b>rd</b>s`) leaves
+/// it whole. Stops after about `limit` characters.
+pub fn flat_text(raw: &str, limit: usize) -> String {
+    fn push(out: &mut String, count: &mut usize, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if out.is_empty() || out.ends_with(' ') {
+                    continue;
+                }
+                out.push(' ');
+            } else {
+                out.push(c);
+            }
+            *count += 1;
+        }
+    }
+    let mut out = String::new();
+    let mut count = 0;
+    let mut last = 0;
+    let mut after_end = false;
+    stream_xml_spans(raw, |token, span| {
+        if matches!(token, Token::Text(_) | Token::CData(_)) {
+            return Continue(());
+        }
+        let gap = &raw[last..span.start];
+        if !gap.is_empty() {
+            push(&mut out, &mut count, &text_content(gap));
+        } else if after_end && matches!(token, Token::StartTag(..)) {
+            push(&mut out, &mut count, " ");
+        }
+        last = span.end;
+        after_end = matches!(token, Token::EndTag(_));
+        if count >= limit { Break(()) } else { Continue(()) }
+    });
+    if count < limit {
+        push(&mut out, &mut count, &text_content(&raw[last..]));
+    }
+    out.truncate(out.trim_end().len());
+    out
+}
@@ -0,0 +1,408 @@
+//! Patches: changes to a document as a list of operations on elements found
+//! by path, written as JSON. `xmz patch` writes one from two versions of a
+//! document, such as a file and the copy the TUI's edits went to, and
+//! `xmz apply` makes one: every path is found in a single streaming pass,
+//! and each change is spliced into the bytes as they are copied out, so a
+//! multi-gigabyte file is patched without building a tree and everything
+//! else stays as it was written.
+//!
+//! ```json
+//! {
+//!   "key": "id",
+//!   "operations": [
+//!     { "op": "set-attribute", "path": "/lib/book[@id='b7']", "name": "year", "value": "2001" },
+//!     { "op": "remove-attribute", "path": "/lib/book[@id='b7']", "name": "draft" },
+//!     { "op": "set-text", "path": "/lib/book[@id='b7']/title[1]", "text": "Dune" },
+//!     { "op": "delete", "path": "/lib/book[@id='b9']" },
+//!     { "op": "insert", "path": "/lib/book[@id='b7']", "position": "after", "xml": "<book id='b8'/>" },
+//!     { "op": "replace", "path": "/lib/note[1]", "xml": "<note>Revised</note>" }
+//!   ]
+//! }
+//! ```
+//!
+//! Paths step to elements as `xmz diff` names them, by position among
+//! same-named siblings or, with a `key`, by that attribute's value, and all
+//! are found in the document before it is patched. An insertion goes
+//! `before` or `after` the element, or inside it as its `first` or `last`
+//! child. Operations may not change the same bytes, such as an attribute
+//! of an element another one deletes.
+
+use crate::diff::{ChangeKind, DiffOptions, Steps, child_steps, diff, elements};
+use crate::json::Json;
+use crate::parser::{Continue, Token, stream_xml_spans, text_content};
+use crate::splice::{self, Splice};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Before,
+    After,
+    First,
+    Last,
+}
+
+impl Position {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "before" => Ok(Position::Before),
+            "after" => Ok(Position::After),
+            "first" => Ok(Position::First),
+            "last" => Ok(Position::Last),
+            _ => Err(format!("unknown position '{}' (expected before, after, first or last)", name)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Position::Before => "before",
+            Position::After => "after",
+            Position::First => "first",
+            Position::Last => "last",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    SetAttribute { path: String, name: String, value: String },
+    RemoveAttribute { path: String, name: String },
+    /// Replaces the text of an element without child elements.
+    SetText { path: String, text: String },
+    Delete { path: String },
+    Insert { path: String, position: Position, xml: String },
+    /// Replaces the whole element, tags included.
+    Replace { path: String, xml: String },
+}
+
+impl Operation {
+    pub fn path(&self) -> &str {
+        match self {
+            Operation::SetAttribute { path, .. }
+            | Operation::RemoveAttribute { path, .. }
+            | Operation::SetText { path, .. }
+            | Operation::Delete { path }
+            | Operation::Insert { path, .. }
+            | Operation::Replace { path, .. } => path,
+        }
+    }
+
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let field = |name: &str| -> Result<String, String> {
+            json.get(name).and_then(Json::as_str).map(str::to_string).ok_or_else(|| format!("needs a \"{}\" string", name))
+        };
+        let path = field("path")?;
+        Ok(match field("op")?.as_str() {
+            "set-attribute" => Operation::SetAttribute { path, name: field("name")?, value: field("value")? },
+            "remove-attribute" => Operation::RemoveAttribute { path, name: field("name")? },
+            "set-text" => Operation::SetText { path, text: field("text")? },
+            "delete" => Operation::Delete { path },
+            "insert" => Operation::Insert { path, position: Position::parse(&field("position")?)?, xml: field("xml")? },
+            "replace" => Operation::Replace { path, xml: field("xml")? },
+            op => return Err(format!("unknown op '{}'", op)),
+        })
+    }
+
+    fn to_json(&self) -> Json {
+        let string = |value: &str| Json::String(value.to_string());
+        let (op, mut members) = match self {
+            Operation::SetAttribute { name, value, .. } => ("set-attribute", vec![("name", string(name)), ("value", string(value))]),
+            Operation::RemoveAttribute { name, .. } => ("remove-attribute", vec![("name", string(name))]),
+            Operation::SetText { text, .. } => ("set-text", vec![("text", string(text))]),
+            Operation::Delete { .. } => ("delete", vec![]),
+            Operation::Insert { position, xml, .. } => ("insert", vec![("position", string(position.name())), ("xml", string(xml))]),
+            Operation::Replace { xml, .. } => ("replace", vec![("xml", string(xml))]),
+        };
+        members.splice(0..0, [("op", string(op)), ("path", string(self.path()))]);
+        Json::Object(members.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patch {
+    /// The attribute paths step to children by, as with `xmz diff --key`.
+    pub key: Option<String>,
+    pub operations: Vec<Operation>,
+}
+
+/// An element a patch changes, as found in the document.
+#[derive(Debug, Clone)]
+struct Located {
+    name: String,
+    span: Range<usize>,
+    /// Between the start and end tag; empty at the end for `<empty/>`.
+    content: Range<usize>,
+    first_child: Option<Range<usize>>,
+    last_child: Option<Range<usize>>,
+}
+
+/// An open element while finding paths; only those on the way to one have
+/// a path and count their children.
+struct Open<'a> {
+    path: Option<String>,
+    steps: Steps<'a>,
+    start: usize,
+    content_start: usize,
+    first_child: Option<Range<usize>>,
+    last_child: Option<Range<usize>>,
+}
+
+/// The steps of `path`, `/lib/book[2]` being `lib` and `book[2]`; a slash
+/// in a quoted key value does not split it.
+fn steps(path: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let (mut quote, mut from) = (None, 1);
+    for (i, c) in path.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '/') => {
+                steps.push(&path[from..i]);
+                from = i + 1;
+            }
+            _ => {}
+        }
+    }
+    steps.push(&path[from..]);
+    steps
+}
+
+/// Finds the elements at `paths` in one pass over `xml`.
+fn locate(xml: &str, paths: &HashSet<&str>, key: Option<&str>) -> HashMap<String, Located> {
+    // Paths of the elements on the way to those wanted, which are counted.
+    let mut ways: HashSet<String> = HashSet::new();
+    for path in paths {
+        let mut way = String::new();
+        for step in steps(path) {
+            way.push('/');
+            way.push_str(step);
+            ways.insert(way.clone());
+        }
+    }
+    let mut found = HashMap::new();
+    let mut open: Vec<Open> = Vec::new();
+    let mut root = true;
+    stream_xml_spans(xml, |token, span| {
+        match token {
+            Token::StartTag(name, attrs) => {
+                let path = match open.last_mut() {
+                    Some(parent) => match &parent.path {
+                        Some(parent_path) => Some(format!("{}/{}", parent_path, parent.steps.next(name, attrs, key))),
+                        None => None,
+                    },
+                    None if root => Some(format!("/{}", name)),
+                    None => None,
+                };
+                root = false;
+                open.push(Open {
+                    path: path.filter(|path| ways.contains(path)),
+                    steps: Steps::default(),
+                    start: span.start,
+                    content_start: span.end,
+                    first_child: None,
+                    last_child: None,
+                });
+            }
+            Token::EndTag(name) => {
+                let Some(element) = open.pop() else {
+                    return Continue(());
+                };
+                let whole = element.start..span.end;
+                if let Some(path) = element.path.filter(|path| paths.contains(path.as_str())) {
+                    // A self-closing tag's end has no name of its own.
+                    let name = if span.is_empty() {
+                        xml[element.start + 1..].split(|c: char| c.is_whitespace() || c == '/' || c == '>').next().unwrap_or("")
+                    } else {
+                        name.trim_end()
+                    };
+                    found.insert(path, Located {
+                        name: name.to_string(),
+                        span: whole.clone(),
+                        content: if span.is_empty() { span.end..span.end } else { element.content_start..span.start },
+                        first_child: element.first_child,
+                        last_child: element.last_child,
+                    });
+                }
+                if let Some(parent) = open.last_mut()
+                    && parent.path.is_some()
+                {
+                    parent.first_child.get_or_insert(whole.clone());
+                    parent.last_child = Some(whole);
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    found
+}
+
+/// The splice adding `fragment` inside `element`, which has no child
+/// elements; `<empty/>` is opened up.
+fn insert_into(element: &Located, fragment: &str) -> Splice {
+    if element.content.start == element.span.end {
+        let tag_end = element.span.end;
+        return Splice {
+            span: tag_end - 2..tag_end,
+            replacement: format!(">{}</{}>", fragment, element.name),
+        };
+    }
+    Splice { span: element.content.end..element.content.end, replacement: fragment.to_string() }
+}
+
+impl Patch {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let json = Json::parse(src)?;
+        let key = match json.get("key") {
+            None | Some(Json::Null) => None,
+            Some(key) => Some(key.as_str().ok_or("\"key\" must be a string")?.to_string()),
+        };
+        let operations = json
+            .get("operations")
+            .and_then(Json::as_array)
+            .ok_or("a patch needs an \"operations\" array")?
+            .iter()
+            .enumerate()
+            .map(|(i, op)| Operation::from_json(op).map_err(|err| format!("operation {}: {}", i + 1, err)))
+            .collect::<Result<_, _>>()?;
+        Ok(Patch { key, operations })
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mut members = Vec::new();
+        if let Some(key) = &self.key {
+            members.push(("key".to_string(), Json::String(key.clone())));
+        }
+        members.push(("operations".to_string(), Json::Array(self.operations.iter().map(Operation::to_json).collect())));
+        Json::Object(members)
+    }
+
+    /// The patch turning `old` into `new`, from their differences as
+    /// `xmz diff` pairs elements. Text changed beside child elements, and
+    /// a root renamed, replace the whole element.
+    pub fn between(old: &str, new: &str, key: Option<String>) -> Self {
+        let options = DiffOptions { key: key.clone(), ..DiffOptions::default() };
+        let changes = diff(old, new, &options);
+        let elements = elements(new, &options);
+        let mut patch = Patch { key, operations: Vec::new() };
+        let Some(root) = elements.first() else {
+            return patch;
+        };
+        if changes.pairs.is_empty() {
+            if let Some(removed) = changes.changes.iter().find(|change| change.kind == ChangeKind::Removed) {
+                patch.operations.push(Operation::Replace { path: removed.path.clone(), xml: new[root.span.clone()].to_string() });
+            }
+            return patch;
+        }
+
+        // Paths and parents of the new document's elements.
+        let mut paths = vec![format!("/{}", root.name)];
+        paths.resize(elements.len(), String::new());
+        let mut parents = vec![None; elements.len()];
+        for (id, element) in elements.iter().enumerate() {
+            for (&child, step) in element.children.iter().zip(child_steps(&elements, element, patch.key.as_deref())) {
+                paths[child] = format!("{}/{}", paths[id], step);
+                parents[child] = Some(id);
+            }
+        }
+        let ids: HashMap<&str, usize> = paths.iter().enumerate().map(|(id, path)| (path.as_str(), id)).collect();
+        let paired: HashSet<usize> = changes.pairs.iter().map(|pair| pair.new.start).collect();
+
+        let mut replaced: Vec<String> = Vec::new();
+        for change in changes.changes {
+            let path = change.path;
+            if replaced.iter().any(|done| path == *done || path.strip_prefix(done.as_str()).is_some_and(|rest| rest.starts_with('/'))) {
+                continue;
+            }
+            let id = ids.get(path.as_str()).copied();
+            let operation = match change.kind {
+                ChangeKind::Removed => Operation::Delete { path },
+                ChangeKind::Attribute { name, new: Some(value), .. } => Operation::SetAttribute { path, name, value },
+                ChangeKind::Attribute { name, new: None, .. } => Operation::RemoveAttribute { path, name },
+                ChangeKind::Added => {
+                    let Some((id, parent)) = id.and_then(|id| Some((id, parents[id]?))) else {
+                        continue;
+                    };
+                    // After the nearest earlier sibling in both versions,
+                    // whose path is the same in each, else first.
+                    let siblings = &elements[parent].children;
+                    let before = &siblings[..siblings.iter().position(|&sibling| sibling == id).unwrap_or(0)];
+                    let xml = new[elements[id].span.clone()].to_string();
+                    match before.iter().rev().find(|&&sibling| paired.contains(&elements[sibling].span.start)) {
+                        Some(&sibling) => Operation::Insert { path: paths[sibling].clone(), position: Position::After, xml },
+                        None => Operation::Insert { path: paths[parent].clone(), position: Position::First, xml },
+                    }
+                }
+                kind @ (ChangeKind::Text { .. } | ChangeKind::Comment { .. } | ChangeKind::AttributeOrder { .. }) => {
+                    let Some(id) = id else {
+                        continue;
+                    };
+                    if matches!(kind, ChangeKind::Text { .. }) && elements[id].children.is_empty() {
+                        Operation::SetText { path, text: text_content(&new[elements[id].content.clone()]) }
+                    } else {
+                        patch.operations.retain(|operation| operation.path() != path);
+                        replaced.push(path.clone());
+                        Operation::Replace { path, xml: new[elements[id].span.clone()].to_string() }
+                    }
+                }
+            };
+            patch.operations.push(operation);
+        }
+        patch
+    }
+
+    /// The splices making the patch to `xml`, in document order. Fails on a
+    /// path with no element, an operation that does not fit its element,
+    /// or two changing the same bytes.
+    pub fn splices(&self, xml: &str) -> Result<Vec<Splice>, String> {
+        let paths: HashSet<&str> = self.operations.iter().map(Operation::path).collect();
+        let found = locate(xml, &paths, self.key.as_deref());
+        let mut splices = Vec::new();
+        for (i, operation) in self.operations.iter().enumerate() {
+            let fail = |what: String| format!("operation {} ({}): {}", i + 1, operation.path(), what);
+            let element = found.get(operation.path()).ok_or_else(|| fail("no element at this path".to_string()))?;
+            let (start, span) = (element.span.start, element.span.clone());
+            let splice = match operation {
+                Operation::SetAttribute { name, value, .. } => splice::set_attribute(xml, start, name, value),
+                Operation::RemoveAttribute { name, .. } => {
+                    Some(splice::remove_attribute(xml, start, name).ok_or_else(|| fail(format!("<{}> has no attribute {}", element.name, name)))?)
+                }
+                Operation::SetText { text, .. } => {
+                    Some(splice::set_text(xml, start, text).ok_or_else(|| fail(format!("<{}> has child elements", element.name)))?)
+                }
+                Operation::Delete { .. } => Some(splice::delete(xml, span)),
+                Operation::Insert { position, xml: fragment, .. } => Some(match (position, &element.first_child, &element.last_child) {
+                    (Position::Before, _, _) => splice::insert_before(xml, span, fragment),
+                    (Position::After, _, _) => splice::insert_after(xml, span, fragment),
+                    (Position::First, Some(child), _) => splice::insert_before(xml, child.clone(), fragment),
+                    (Position::Last, _, Some(child)) => splice::insert_after(xml, child.clone(), fragment),
+                    (Position::First | Position::Last, _, _) => insert_into(element, fragment),
+                }),
+                Operation::Replace { xml: replacement, .. } => Some(Splice { span, replacement: replacement.clone() }),
+            };
+            let splice = splice.ok_or_else(|| fail(format!("<{}> could not be read", element.name)))?;
+            splices.push((splice, i));
+        }
+        // Insertions come before what is replaced at the same place.
+        splices.sort_by_key(|(splice, _)| (splice.span.start, splice.span.end));
+        for pair in splices.windows(2) {
+            let ((first, a), (second, b)) = (&pair[0], &pair[1]);
+            if second.span.start < first.span.end {
+                return Err(format!("operations {} and {} change the same bytes", a.min(b) + 1, a.max(b) + 1));
+            }
+        }
+        Ok(splices.into_iter().map(|(splice, _)| splice).collect())
+    }
+}
+
+/// Writes `xml` with `splices`, in document order and not overlapping, made.
+pub fn write_patched<W: Write>(xml: &str, splices: &[Splice], out: &mut W) -> io::Result<()> {
+    let mut at = 0;
+    for splice in splices {
+        out.write_all(&xml.as_bytes()[at..splice.span.start])?;
+        out.write_all(splice.replacement.as_bytes())?;
+        at = splice.span.end;
+    }
+    out.write_all(&xml.as_bytes()[at..])
+}
@@ -0,0 +1,333 @@
+//! Batch pipelines (`xmz run pipeline.toml`): a manifest of steps run over
+//! one input in order, such as check, transform, split and convert, so that
+//! processing a dump in several steps is one command that can be run again.
+//!
+//! The manifest is the TOML subset of `checks`, with a `[[step]]` table per
+//! step:
+//!
+//! ```toml
+//! input = "dump.xml"
+//!
+//! [[step]]
+//! run = "check"
+//! config = "checks.toml"             # as xmz check --config; optional
+//!
+//! [[step]]
+//! run = "transform"
+//! tag-case = "lower"                 # the options of xmz transform
+//! default = ["//book/@lang=en"]
+//! output = "clean.xml"               # optional
+//!
+//! [[step]]
+//! run = "split"
+//! record = "//book"                  # detected if omitted
+//! where = "price>10"
+//! to = "xml"
+//! per-file = 1000
+//! out-dir = "parts"
+//! prefix = "books"
+//!
+//! [[step]]
+//! run = "convert"
+//! record = "//book"
+//! output = "books.ndjson"
+//! ```
+//!
+//! The input is read and decoded once. Each step reads the document as the
+//! steps before it left it: a transform's result is kept in memory for the
+//! next ones, and written out only if it has an `output`. A check that finds
+//! errors stops the pipeline. Paths are relative to the manifest.
+
+use crate::agg::write_table;
+use crate::check::{Severity, write_diagnostics};
+use crate::checks::{Checks, Value, parse_value, strip_comment};
+use crate::convert::{ExportOptions, Format, convert, detect_record, split};
+use crate::query::{Predicate, Query};
+use crate::sandbox::{self, Capability};
+use crate::transform::{Case, Conversion, DefaultValue, TransformOptions, parse_rename, transform};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Which records a split or convert step exports, and as what.
+struct Export {
+    record: Option<String>,
+    filter: Option<(String, Predicate)>,
+    format: Format,
+}
+
+enum Step {
+    Check(Checks),
+    Transform {
+        options: TransformOptions,
+        output: Option<PathBuf>,
+    },
+    Split {
+        export: Export,
+        per_file: u64,
+        out_dir: PathBuf,
+        prefix: String,
+    },
+    Convert {
+        export: Export,
+        output: PathBuf,
+    },
+}
+
+impl Step {
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Check(_) => "check",
+            Step::Transform { .. } => "transform",
+            Step::Split { .. } => "split",
+            Step::Convert { .. } => "convert",
+        }
+    }
+}
+
+/// A parsed manifest, with its checks' configs and schemas loaded.
+pub struct Pipeline {
+    /// The manifest's `input`, if it names one.
+    pub input: Option<PathBuf>,
+    steps: Vec<Step>,
+}
+
+/// The keys of one `[[step]]` table, in order.
+type Table = Vec<(usize, String, Value)>;
+
+impl Pipeline {
+    /// Reads the manifest at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Pipeline::parse(&text, dir).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+    }
+
+    /// Parses a manifest, resolving the paths in it against `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut input = None;
+        let mut tables: Vec<(usize, Table)> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let at = |err: String| format!("line {}: {}", n + 1, err);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("[[").and_then(|s| s.strip_suffix("]]")) {
+                if name.trim() != "step" {
+                    return Err(at(format!("unknown table [[{}]] (expected [[step]])", name.trim())));
+                }
+                tables.push((n + 1, Table::new()));
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(at("expected key = value or [[step]]".to_string()));
+            };
+            let (key, value) = (key.trim(), parse_value(value.trim()).map_err(at)?);
+            match (tables.last_mut(), key, value) {
+                (Some((_, table)), _, value) => table.push((n + 1, key.to_string(), value)),
+                (None, "input", Value::Text(path)) => input = Some(dir.join(path)),
+                (None, _, _) => return Err(at(format!("unexpected {} before the first [[step]]", key))),
+            }
+        }
+        if tables.is_empty() {
+            return Err("no [[step]] to run".to_string());
+        }
+        let steps = tables.into_iter().map(|(line, table)| parse_step(line, table, dir)).collect::<Result<_, _>>()?;
+        Ok(Pipeline { input, steps })
+    }
+
+    /// Runs the steps over `xml`, the document read from `file`, and writes
+    /// the problems found by checks, then a table with each step's result
+    /// and time. Stops at the first step that fails, returning its error.
+    pub fn run<W: Write>(&self, xml: &str, file: &str, out: &mut W) -> io::Result<()> {
+        let mut rows = vec![["step", "run", "result", "time"].map(String::from).to_vec()];
+        let mut transformed: Option<String> = None;
+        let mut failure = None;
+        let started = Instant::now();
+        for (i, step) in self.steps.iter().enumerate() {
+            let xml = transformed.as_deref().unwrap_or(xml);
+            let start = Instant::now();
+            let mut next = None;
+            let result = match step {
+                Step::Check(checks) => {
+                    let diagnostics = checks.run(xml);
+                    if !diagnostics.is_empty() {
+                        write_diagnostics(out, file, &diagnostics)?;
+                    }
+                    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+                    match (errors, diagnostics.len()) {
+                        (0, 0) => Ok("no problems".to_string()),
+                        (0, warnings) => Ok(format!("{} warning(s)", warnings)),
+                        (errors, all) => Err(io::Error::other(format!("{} error(s), {} warning(s)", errors, all - errors))),
+                    }
+                }
+                Step::Transform { options, output } => run_transform(xml, options, output.as_deref()).map(|(text, summary)| {
+                    next = Some(text);
+                    summary
+                }),
+                Step::Split { export, per_file, out_dir, prefix } => with_export(xml, export, |options| {
+                    let records = split(xml, options, out_dir, prefix, *per_file)?;
+                    Ok(format!("{} record(s) in {} file(s) in {}", records, records.div_ceil(*per_file), out_dir.display()))
+                }),
+                Step::Convert { export, output } => with_export(xml, export, |options| {
+                    let records = convert(xml, options, Some(output))?;
+                    Ok(format!("{} record(s) to {}", records, output.display()))
+                }),
+            };
+            let summary = match result {
+                Ok(summary) => summary,
+                Err(err) => {
+                    let summary = format!("failed: {}", err);
+                    failure = Some(io::Error::new(err.kind(), format!("step {} ({}) failed: {}", i + 1, step.name(), err)));
+                    summary
+                }
+            };
+            if next.is_some() {
+                transformed = next;
+            }
+            rows.push(vec![(i + 1).to_string(), step.name().to_string(), summary, seconds(start)]);
+            if failure.is_some() {
+                break;
+            }
+        }
+        write_table(out, &rows)?;
+        match failure {
+            Some(err) => Err(err),
+            None => writeln!(out, "{} step(s) in {}", self.steps.len(), seconds(started)),
+        }
+    }
+}
+
+fn seconds(start: Instant) -> String {
+    format!("{:.2}s", start.elapsed().as_secs_f64())
+}
+
+/// Transforms `xml`, writing the result to `output` if given; returns it
+/// with a summary.
+fn run_transform(xml: &str, options: &TransformOptions, output: Option<&Path>) -> io::Result<(String, String)> {
+    let mut buffer = Vec::with_capacity(xml.len());
+    let stats = transform(xml, options, &mut buffer)?;
+    let text = String::from_utf8(buffer).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let mut summary = stats.describe();
+    if let Some(path) = output {
+        sandbox::check(Capability::WriteFiles)?;
+        std::fs::write(path, &text).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        summary.push_str(&format!("; wrote {}", path.display()));
+    }
+    Ok((text, summary))
+}
+
+/// Runs an export step with its options, the record detected in `xml` when
+/// the step names none.
+fn with_export(xml: &str, export: &Export, run: impl FnOnce(&ExportOptions) -> io::Result<String>) -> io::Result<String> {
+    let (source, detected) = match &export.record {
+        Some(record) => (record.clone(), false),
+        None => {
+            let guess = detect_record(xml).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no repeated element found to export; set record"))?;
+            (guess.query, true)
+        }
+    };
+    let query = Query::parse(&source).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let options = ExportOptions {
+        query: &query,
+        query_source: &source,
+        filter: export.filter.as_ref().map(|(_, predicate)| predicate),
+        filter_source: export.filter.as_ref().map(|(source, _)| source.as_str()),
+        format: export.format,
+        // A pipeline is run again from the start rather than resumed.
+        checkpoint_every: 0,
+        resume: false,
+    };
+    let summary = run(&options)?;
+    Ok(if detected { format!("{} (record {}, detected)", summary, source) } else { summary })
+}
+
+fn parse_step(line: usize, table: Table, dir: &Path) -> Result<Step, String> {
+    let name = table.iter().find_map(|(_, key, value)| match (key.as_str(), value) {
+        ("run", Value::Text(name)) => Some(name.clone()),
+        _ => None,
+    });
+    let name = name.ok_or_else(|| format!("line {}: the step has no run = \"...\"", line))?;
+    let mut step = match name.as_str() {
+        "check" => Step::Check(Checks::default()),
+        "transform" => Step::Transform {
+            options: TransformOptions::default(),
+            output: None,
+        },
+        "split" => Step::Split {
+            export: Export { record: None, filter: None, format: Format::Ndjson },
+            per_file: 10_000,
+            out_dir: dir.to_path_buf(),
+            prefix: "part".to_string(),
+        },
+        "convert" => Step::Convert {
+            export: Export { record: None, filter: None, format: Format::Ndjson },
+            output: PathBuf::new(),
+        },
+        _ => return Err(format!("line {}: unknown step '{}' (expected check, transform, split or convert)", line, name)),
+    };
+    let mut has_output = false;
+    for (n, key, value) in table {
+        let at = |err: String| format!("line {}: {}", n, err);
+        let text = |value: &Value| match value {
+            Value::Text(text) => Ok(text.clone()),
+            _ => Err(at(format!("{} must be a string in quotes", key))),
+        };
+        let list = |value: &Value| match value {
+            Value::List(items) => Ok(items.clone()),
+            Value::Text(item) => Ok(vec![item.clone()]),
+            _ => Err(at(format!("{} must be a list of strings", key))),
+        };
+        match (&mut step, key.as_str()) {
+            (_, "run") => {}
+            (Step::Check(checks), "config") => *checks = Checks::load(&dir.join(text(&value)?)).map_err(|err| at(err.to_string()))?,
+            (Step::Transform { options, .. }, "tag-case") => options.tag_case = Some(Case::parse(&text(&value)?).map_err(at)?),
+            (Step::Transform { options, .. }, "attribute-case") => options.attribute_case = Some(Case::parse(&text(&value)?).map_err(at)?),
+            (Step::Transform { options, .. }, "prune-namespaces") => match value {
+                Value::Bool(prune) => options.prune_namespaces = prune,
+                _ => return Err(at("prune-namespaces must be true or false".to_string())),
+            },
+            (Step::Transform { options, .. }, "rename-prefix") => {
+                options.rename_prefixes = list(&value)?.iter().map(|arg| parse_rename(arg)).collect::<Result<_, _>>().map_err(at)?;
+            }
+            (Step::Transform { options, .. }, "drop-namespace") => options.drop_namespaces = list(&value)?,
+            (Step::Transform { options, .. }, "attr-to-elem") => {
+                options.attributes_to_elements = list(&value)?.iter().map(|path| Conversion::parse(path, true)).collect::<Result<_, _>>().map_err(at)?;
+            }
+            (Step::Transform { options, .. }, "elem-to-attr") => {
+                options.elements_to_attributes = list(&value)?.iter().map(|path| Conversion::parse(path, false)).collect::<Result<_, _>>().map_err(at)?;
+            }
+            (Step::Transform { options, .. }, "default") => {
+                options.defaults = list(&value)?.iter().map(|rule| DefaultValue::parse(rule)).collect::<Result<_, _>>().map_err(at)?;
+            }
+            (Step::Transform { output, .. }, "output") => *output = Some(dir.join(text(&value)?)),
+            (Step::Split { export, .. } | Step::Convert { export, .. }, "record") => {
+                let record = text(&value)?;
+                Query::parse(&record).map_err(at)?;
+                export.record = Some(record);
+            }
+            (Step::Split { export, .. } | Step::Convert { export, .. }, "where") => {
+                let source = text(&value)?;
+                let predicate = Predicate::parse(&source).map_err(at)?;
+                export.filter = Some((source, predicate));
+            }
+            (Step::Split { export, .. } | Step::Convert { export, .. }, "to") => export.format = Format::parse(&text(&value)?).map_err(at)?,
+            (Step::Split { per_file, .. }, "per-file") => match value {
+                Value::Integer(n) if n > 0 => *per_file = n,
+                _ => return Err(at("per-file must be a positive number".to_string())),
+            },
+            (Step::Split { out_dir, .. }, "out-dir") => *out_dir = dir.join(text(&value)?),
+            (Step::Split { prefix, .. }, "prefix") => *prefix = text(&value)?,
+            (Step::Convert { output, .. }, "output") => {
+                *output = dir.join(text(&value)?);
+                has_output = true;
+            }
+            _ => return Err(at(format!("unexpected {} in a {} step", key, name))),
+        }
+    }
+    if matches!(step, Step::Convert { .. }) && !has_output {
+        return Err(format!("line {}: a convert step needs an output", line));
+    }
+    Ok(step)
+}
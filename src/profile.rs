@@ -0,0 +1,431 @@
+//! Document profiles: formats xmz recognises from the root element and knows
+//! more about than their structure. For GPX track logs and KML map exports,
+//! `xmz stats` adds what the file holds (tracks, waypoints, bounding box,
+//! distance, time span) and the navigator labels points, tracks and
+//! placemarks by name and coordinates.
+
+use crate::parser::{Continue, Token, stream_xml, token_text};
+use crate::value::{ValueParser, format_timestamp};
+use crate::xml::Node;
+
+/// Mean Earth radius in metres, for great-circle distances.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+/// How far into an element labels look for its name, coordinates and time.
+const LABEL_WINDOW: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Gpx,
+    Kml,
+}
+
+impl Profile {
+    /// The profile of a document whose root element is named `root`.
+    pub fn detect(root: &str) -> Option<Self> {
+        match local_name(root) {
+            "gpx" => Some(Profile::Gpx),
+            "kml" => Some(Profile::Kml),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Gpx => "GPX",
+            Profile::Kml => "KML",
+        }
+    }
+
+    /// What the navigator shows beside `node`: the name, coordinates and
+    /// time of a point, or the name of a track, route, folder or placemark.
+    pub fn label(self, xml: &str, node: &Node) -> Option<String> {
+        let tag = local_name(node.tag);
+        let point = match (self, tag) {
+            (Profile::Gpx, "wpt" | "rtept" | "trkpt") => true,
+            (Profile::Gpx, "trk" | "rte") | (Profile::Kml, "Document" | "Folder") => false,
+            (Profile::Kml, "Placemark") => true,
+            _ => return None,
+        };
+        let fields = Fields::of(xml, node);
+        let mut parts = Vec::new();
+        if let Some(name) = fields.name {
+            parts.push(name);
+        }
+        if point {
+            let coordinates = match self {
+                Profile::Gpx => coordinate(&fields.lat?, &fields.lon?),
+                Profile::Kml => fields.coordinates.as_deref().and_then(|text| kml_points(text).next()),
+            };
+            if let Some((lat, lon)) = coordinates {
+                parts.push(format!("{:.5}, {:.5}", lat, lon));
+            }
+            if let Some(time) = fields.time.as_deref().and_then(timestamp) {
+                parts.push(format_timestamp(time));
+            }
+        }
+        (!parts.is_empty()).then(|| parts.join("  "))
+    }
+}
+
+/// The name and time among the children of an element, its `lat`/`lon`
+/// attributes, and the first KML coordinates inside it, looking at the
+/// first `LABEL_WINDOW` bytes of the element only.
+#[derive(Default)]
+struct Fields {
+    name: Option<String>,
+    time: Option<String>,
+    coordinates: Option<String>,
+    lat: Option<String>,
+    lon: Option<String>,
+}
+
+impl Fields {
+    fn of(xml: &str, node: &Node) -> Self {
+        let mut end = node.span.end.min(node.span.start + LABEL_WINDOW);
+        while !xml.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut fields = Fields::default();
+        let mut depth = 0;
+        let mut capture: Option<&str> = None;
+        stream_xml(&xml[node.span.start..end], |token| {
+            match token {
+                Token::StartTag(name, attrs) => {
+                    if depth == 0 {
+                        for (attr, value) in xmz_core::attributes(attrs) {
+                            match attr {
+                                "lat" => fields.lat = Some(value.to_string()),
+                                "lon" => fields.lon = Some(value.to_string()),
+                                _ => {}
+                            }
+                        }
+                    }
+                    depth += 1;
+                    capture = match local_name(name) {
+                        "name" if depth == 2 => Some("name"),
+                        "time" | "when" if depth <= 3 => Some("time"),
+                        "coordinates" => Some("coordinates"),
+                        "coord" => Some("coord"),
+                        _ => None,
+                    };
+                }
+                Token::EndTag(_) => {
+                    depth -= 1;
+                    capture = None;
+                }
+                Token::Text(text) | Token::CData(text) => {
+                    let (field, text) = match capture.take() {
+                        Some("name") => (&mut fields.name, token_text(&token).unwrap_or_default().into_owned()),
+                        Some("time") => (&mut fields.time, text.to_string()),
+                        Some("coordinates") => (&mut fields.coordinates, text.to_string()),
+                        // `gx:coord` separates with spaces what `coordinates` does with commas.
+                        Some("coord") => (&mut fields.coordinates, text.split_ascii_whitespace().collect::<Vec<_>>().join(",")),
+                        _ => return Continue(()),
+                    };
+                    field.get_or_insert(text);
+                }
+            }
+            Continue(())
+        });
+        fields
+    }
+}
+
+/// What a GPX or KML document holds, gathered token by token.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub profile: Profile,
+    /// GPX tracks and KML `gx:Track`s, and the points on them.
+    pub tracks: u64,
+    pub track_points: u64,
+    /// GPX track segments.
+    pub segments: u64,
+    /// GPX routes and KML line strings, and the points on them.
+    pub routes: u64,
+    pub route_points: u64,
+    /// GPX waypoints and KML points.
+    pub waypoints: u64,
+    pub placemarks: u64,
+    pub polygons: u64,
+    /// South-west and north-east corners, as (latitude, longitude).
+    pub bounds: Option<((f64, f64), (f64, f64))>,
+    /// Along tracks and routes, in metres.
+    pub distance: f64,
+    /// Earliest and latest timestamp, in seconds since the epoch.
+    pub time_span: Option<(i64, i64)>,
+    /// Enclosing elements that matter, innermost last.
+    open: Vec<Part>,
+    /// The previous point on the current segment, route or line.
+    last: Option<(f64, f64)>,
+}
+
+/// Elements the summary keeps track of while inside them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    /// A GPX point: `wpt`, `rtept` or `trkpt`.
+    Point,
+    /// A KML geometry.
+    Geometry(Geometry),
+    /// KML `coordinates`, inside the geometry they belong to.
+    Coordinates(Geometry),
+    Time,
+    /// A `gx:coord` inside a `gx:Track`.
+    Coord,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Geometry {
+    Point,
+    Line,
+    Polygon,
+}
+
+impl Summary {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            profile,
+            tracks: 0,
+            track_points: 0,
+            segments: 0,
+            routes: 0,
+            route_points: 0,
+            waypoints: 0,
+            placemarks: 0,
+            polygons: 0,
+            bounds: None,
+            distance: 0.0,
+            time_span: None,
+            open: Vec::new(),
+            last: None,
+        }
+    }
+
+    pub fn on_token(&mut self, token: &Token) {
+        match *token {
+            Token::StartTag(name, attrs) => {
+                let part = match self.profile {
+                    Profile::Gpx => self.on_gpx_start(local_name(name), attrs),
+                    Profile::Kml => self.on_kml_start(local_name(name)),
+                };
+                self.open.push(part);
+            }
+            Token::EndTag(_) => {
+                self.open.pop();
+            }
+            Token::Text(text) | Token::CData(text) => match self.open.last() {
+                Some(Part::Time) => {
+                    // GPX times count on points only, not on the file's metadata.
+                    let on_point = self.profile == Profile::Kml || self.open.iter().rev().nth(1) == Some(&Part::Point);
+                    if on_point && let Some(time) = timestamp(text) {
+                        self.time_span = Some(match self.time_span {
+                            Some((first, last)) => (first.min(time), last.max(time)),
+                            None => (time, time),
+                        });
+                    }
+                }
+                Some(Part::Coord) => {
+                    let mut values = text.split_ascii_whitespace();
+                    if let (Some(lon), Some(lat)) = (values.next(), values.next())
+                        && let Some(point) = coordinate(lat, lon)
+                    {
+                        self.track_points += 1;
+                        self.add_point(point, true);
+                    }
+                }
+                Some(&Part::Coordinates(geometry)) => {
+                    for point in kml_points(text) {
+                        match geometry {
+                            Geometry::Line => {
+                                self.route_points += 1;
+                                self.add_point(point, true);
+                            }
+                            _ => self.add_point(point, false),
+                        }
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn on_gpx_start(&mut self, name: &str, attrs: &str) -> Part {
+        match name {
+            "trk" => self.tracks += 1,
+            "rte" => {
+                self.routes += 1;
+                self.last = None;
+            }
+            "trkseg" => {
+                self.segments += 1;
+                self.last = None;
+            }
+            "wpt" | "rtept" | "trkpt" => {
+                let mut lat = None;
+                let mut lon = None;
+                for (attr, value) in xmz_core::attributes(attrs) {
+                    match attr {
+                        "lat" => lat = Some(value),
+                        "lon" => lon = Some(value),
+                        _ => {}
+                    }
+                }
+                let point = lat.zip(lon).and_then(|(lat, lon)| coordinate(lat, lon));
+                match name {
+                    "wpt" => self.waypoints += 1,
+                    "rtept" => self.route_points += 1,
+                    _ => self.track_points += 1,
+                }
+                if let Some(point) = point {
+                    self.add_point(point, name != "wpt");
+                }
+                return Part::Point;
+            }
+            "time" => return Part::Time,
+            _ => {}
+        }
+        Part::Other
+    }
+
+    fn on_kml_start(&mut self, name: &str) -> Part {
+        match name {
+            "Placemark" => self.placemarks += 1,
+            "Point" => self.waypoints += 1,
+            "LineString" => {
+                self.routes += 1;
+                self.last = None;
+            }
+            "Polygon" => self.polygons += 1,
+            "Track" => {
+                self.tracks += 1;
+                self.last = None;
+            }
+            "when" | "begin" | "end" => return Part::Time,
+            "coord" => return Part::Coord,
+            "coordinates" => {
+                // The geometry is the nearest enclosing one: a ring's
+                // coordinates belong to its polygon.
+                let geometry = self.open.iter().rev().find_map(|part| match part {
+                    Part::Geometry(geometry) => Some(*geometry),
+                    _ => None,
+                });
+                return Part::Coordinates(geometry.unwrap_or(Geometry::Point));
+            }
+            _ => {}
+        }
+        match name {
+            "Point" => Part::Geometry(Geometry::Point),
+            "LineString" => Part::Geometry(Geometry::Line),
+            "Polygon" => Part::Geometry(Geometry::Polygon),
+            _ => Part::Other,
+        }
+    }
+
+    /// Extends the bounding box, and with `along`, the distance from the
+    /// previous point.
+    fn add_point(&mut self, (lat, lon): (f64, f64), along: bool) {
+        self.bounds = Some(match self.bounds {
+            Some(((south, west), (north, east))) => ((south.min(lat), west.min(lon)), (north.max(lat), east.max(lon))),
+            None => ((lat, lon), (lat, lon)),
+        });
+        if along {
+            if let Some(last) = self.last {
+                self.distance += haversine(last, (lat, lon));
+            }
+            self.last = Some((lat, lon));
+        }
+    }
+
+    /// The figures for the report, as label and value, e.g. `("Tracks", "2
+    /// (3 segments, 1,204 points)")`.
+    pub fn figures(&self) -> Vec<(&'static str, String)> {
+        let mut figures = Vec::new();
+        match self.profile {
+            Profile::Gpx => {
+                figures.push(("Tracks", format!("{} ({} segments, {} points)", self.tracks, self.segments, self.track_points)));
+                figures.push(("Routes", format!("{} ({} points)", self.routes, self.route_points)));
+                figures.push(("Waypoints", self.waypoints.to_string()));
+            }
+            Profile::Kml => {
+                figures.push(("Placemarks", self.placemarks.to_string()));
+                figures.push(("Points", self.waypoints.to_string()));
+                figures.push(("Lines", format!("{} ({} points)", self.routes, self.route_points)));
+                figures.push(("Polygons", self.polygons.to_string()));
+                if self.tracks > 0 {
+                    figures.push(("Tracks", format!("{} ({} points)", self.tracks, self.track_points)));
+                }
+            }
+        }
+        if let Some(((south, west), (north, east))) = self.bounds {
+            figures.push(("Bounding box", format!("{:.5}, {:.5} to {:.5}, {:.5} (south-west to north-east)", south, west, north, east)));
+        }
+        if self.distance > 0.0 {
+            figures.push(("Distance", format!("{:.2} km", self.distance / 1000.0)));
+        }
+        if let Some((first, last)) = self.time_span {
+            figures.push(("Time span", format!("{} to {} UTC ({})", format_timestamp(first), format_timestamp(last), format_duration(last - first))));
+        }
+        figures
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+/// A point from latitude and longitude text, if both are numbers in range.
+fn coordinate(lat: &str, lon: &str) -> Option<(f64, f64)> {
+    let lat: f64 = lat.trim().parse().ok()?;
+    let lon: f64 = lon.trim().parse().ok()?;
+    ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some((lat, lon))
+}
+
+/// The points in KML `coordinates` text: `lon,lat[,alt]` tuples separated by
+/// whitespace.
+fn kml_points(text: &str) -> impl Iterator<Item = (f64, f64)> + '_ {
+    text.split_ascii_whitespace().filter_map(|tuple| {
+        let mut values = tuple.split(',');
+        let lon = values.next()?;
+        coordinate(values.next()?, lon)
+    })
+}
+
+/// Great-circle distance in metres.
+fn haversine((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let a = ((phi2 - phi1) / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * ((lon2 - lon1).to_radians() / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Seconds since the epoch for an ISO 8601 timestamp, with optional
+/// fractional seconds and UTC offset.
+fn timestamp(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (text, offset) = match text.len().checked_sub(6).filter(|&at| at > 10 && text.is_char_boundary(at)) {
+        Some(at) if matches!(text.as_bytes()[at], b'+' | b'-') && text.as_bytes()[at + 3] == b':' => {
+            let hours: i64 = text[at + 1..at + 3].parse().ok()?;
+            let minutes: i64 = text[at + 4..].parse().ok()?;
+            let sign = if text.as_bytes()[at] == b'-' { -1 } else { 1 };
+            (&text[..at], sign * (hours * 3600 + minutes * 60))
+        }
+        _ => (text, 0),
+    };
+    let whole = match text.find('.') {
+        Some(dot) => {
+            let digits = text[dot + 1..].bytes().take_while(u8::is_ascii_digit).count();
+            format!("{}{}", &text[..dot], &text[dot + 1 + digits..])
+        }
+        None => text.to_string(),
+    };
+    Some(ValueParser::default().date(&whole)? - offset)
+}
+
+/// `8m 30s`, `2h 05m` or `3d 4h`.
+fn format_duration(secs: i64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {:02}s", s / 60, s % 60),
+        s if s < 86_400 => format!("{}h {:02}m", s / 3600, s % 3600 / 60),
+        s => format!("{}d {}h", s / 86_400, s % 86_400 / 3600),
+    }
+}
@@ -0,0 +1,153 @@
+//! Progress of long passes over a document. By default a one-line summary is
+//! redrawn on stderr when it is a terminal; with `--progress json`, wrapping
+//! tools get one JSON object per line instead:
+//!
+//! ```text
+//! {"event":"progress","command":"convert","bytes":1048576,"total_bytes":8388608,"records":5120,"elapsed":0.5,"eta":3.5}
+//! {"event":"done","command":"convert","bytes":8388608,"total_bytes":8388608,"records":40960,"elapsed":4.1,"eta":0}
+//! ```
+//!
+//! `total_bytes` and `eta` are null when the input size is unknown (a pipe or
+//! compressed input). Records are the tags scanned for `stats` and the
+//! records written for `convert` and `split`.
+
+use crate::du::format_bytes;
+use crate::json::Json;
+use crossterm::{
+    cursor::MoveToColumn,
+    execute,
+    style::Print,
+    terminal::{Clear, ClearType},
+};
+use std::io::{IsTerminal, Write, stderr};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A line on stderr, redrawn in place, when stderr is a terminal.
+    Auto,
+    /// JSON lines on stderr.
+    Json,
+    None,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "auto" => Ok(Mode::Auto),
+            "json" => Ok(Mode::Json),
+            "none" => Ok(Mode::None),
+            _ => Err(format!("unknown progress mode '{}' (expected auto, json or none)", name)),
+        }
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(0);
+
+/// Sets how progress is shown for the rest of the process.
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::SeqCst);
+}
+
+fn mode() -> Mode {
+    match MODE.load(Ordering::SeqCst) {
+        1 => Mode::Json,
+        2 => Mode::None,
+        _ => Mode::Auto,
+    }
+}
+
+/// How often progress is reported.
+const INTERVAL: Duration = Duration::from_millis(250);
+/// Steps between clock checks, so that checking stays cheap.
+const STEPS: usize = 4096;
+
+/// Progress of one pass, reported at most every `INTERVAL`.
+pub struct Progress {
+    command: &'static str,
+    start: Instant,
+    last: Instant,
+    /// Size of the whole input, when known.
+    total: Option<u64>,
+    steps: usize,
+    mode: Mode,
+    shown: bool,
+}
+
+impl Progress {
+    pub fn new(command: &'static str, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        let mode = match mode() {
+            Mode::Auto if !stderr().is_terminal() => Mode::None,
+            mode => mode,
+        };
+        Self {
+            command,
+            start: now,
+            last: now,
+            total,
+            steps: 0,
+            mode,
+            shown: false,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Called for every token or record, with the bytes scanned and records
+    /// seen so far. `line` describes them for the terminal, e.g. `Scanning:
+    /// 120 tags, depth 3`; the bytes and speed are appended.
+    pub fn update(&mut self, scanned: u64, records: u64, line: impl FnOnce() -> String) {
+        self.steps += 1;
+        if self.mode == Mode::None || !self.steps.is_multiple_of(STEPS) {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last) < INTERVAL {
+            return;
+        }
+        self.last = now;
+        if self.mode == Mode::Json {
+            self.emit("progress", scanned, records);
+            return;
+        }
+        let speed = scanned as f64 / now.duration_since(self.start).as_secs_f64() / 1_000_000.0;
+        let done = match self.total {
+            Some(total) if total > 0 => format!("{} of {} ({:.0}%)", format_bytes(scanned), format_bytes(total), scanned as f64 * 100.0 / total as f64),
+            _ => format_bytes(scanned),
+        };
+        let line = format!("{}, {}, {:.2} MB/s", line(), done, speed);
+        self.shown = execute!(stderr(), MoveToColumn(0), Clear(ClearType::CurrentLine), Print(line)).is_ok();
+    }
+
+    /// Ends the pass: clears the terminal line, or emits a `done` event.
+    pub fn finish(&self, scanned: u64, records: u64) {
+        if self.shown {
+            let _ = execute!(stderr(), MoveToColumn(0), Clear(ClearType::CurrentLine));
+        }
+        if self.mode == Mode::Json {
+            self.emit("done", scanned, records);
+        }
+    }
+
+    fn emit(&self, event: &str, scanned: u64, records: u64) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let eta = match self.total {
+            Some(total) if scanned > 0 => Json::Number((elapsed * total.saturating_sub(scanned) as f64 / scanned as f64 * 10.0).round() / 10.0),
+            _ => Json::Null,
+        };
+        let record = Json::Object(vec![
+            ("event".to_string(), Json::String(event.to_string())),
+            ("command".to_string(), Json::String(self.command.to_string())),
+            ("bytes".to_string(), Json::Number(scanned as f64)),
+            ("total_bytes".to_string(), self.total.map_or(Json::Null, |total| Json::Number(total as f64))),
+            ("records".to_string(), Json::Number(records as f64)),
+            ("elapsed".to_string(), Json::Number((elapsed * 10.0).round() / 10.0)),
+            ("eta".to_string(), eta),
+        ]);
+        let _ = writeln!(stderr(), "{}", record);
+    }
+}
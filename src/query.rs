@@ -0,0 +1,641 @@
+//! Path queries over the token stream, e.g. `//book[@lang='en' and price>10]/title`.
+//!
+//! A query is a sequence of steps separated by `/` (child) or `//` (descendant).
+//! A query that does not start with `/` matches at any depth. Each step is a tag
+//! name or `*`, optionally followed by `[predicate]` filters. Predicates compare
+//! `@attr`, `.` (the element's text) or a relative child path (`author/name`,
+//! `author/@id`) against a quoted string or a number with `= != < <= > >=`, or
+//! against a regex with `~=`, and combine with `and`, `or`, `not(...)`.
+//! A bare operand tests for existence.
+//!
+//! Predicates on the last step see the whole element; predicates on earlier
+//! steps only see attributes, since their content has not been read yet.
+
+use crate::parser::{Break, Continue, Token, attribute_pairs, extract_attributes, stream_xml_spans};
+use crate::regex::Regex;
+use std::ops::{ControlFlow, Range};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Child,
+    Descendant,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    axis: Axis,
+    name: Option<String>,
+    predicates: Vec<Predicate>,
+}
+
+/// A compiled path expression.
+#[derive(Debug, Clone)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+/// A boolean filter evaluated against a single element.
+#[derive(Debug, Clone)]
+pub struct Predicate(Expr);
+
+/// A value extracted relative to an element: `@attr`, `.`, `child/path` or `child/@attr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field(Operand);
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Operand),
+    Compare(Operand, Op, Literal),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Text,
+    Attr(String),
+    Child { path: Vec<String>, attr: Option<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Matches,
+}
+
+#[derive(Debug, Clone)]
+struct Literal {
+    text: String,
+    number: Option<f64>,
+    regex: Option<Regex>,
+}
+
+struct Frame<'a> {
+    name: &'a str,
+    attrs: &'a str,
+    start: usize,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let source = source.trim();
+        if source.is_empty() {
+            return Err("empty query".to_string());
+        }
+        let mut rest = source;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            let axis = if let Some(r) = rest.strip_prefix("//") {
+                rest = r;
+                Axis::Descendant
+            } else if let Some(r) = rest.strip_prefix('/') {
+                rest = r;
+                Axis::Child
+            } else if steps.is_empty() {
+                Axis::Descendant
+            } else {
+                return Err(format!("expected '/' before '{}'", rest));
+            };
+            let name_len = rest.find(['/', '[']).unwrap_or(rest.len());
+            let name = rest[..name_len].trim();
+            if name.is_empty() {
+                return Err(format!("missing element name in '{}'", source));
+            }
+            rest = &rest[name_len..];
+            let mut predicates = Vec::new();
+            while rest.starts_with('[') {
+                let close = find_closing_bracket(rest).ok_or_else(|| format!("missing ']' in '{}'", source))?;
+                predicates.push(Predicate::parse(&rest[1..close])?);
+                rest = &rest[close + 1..];
+            }
+            steps.push(Step {
+                axis,
+                name: (name != "*").then(|| name.to_string()),
+                predicates,
+            });
+        }
+        Ok(Self { steps })
+    }
+
+    /// The tag name of the last step, if it is not a wildcard.
+    pub fn target_name(&self) -> Option<&str> {
+        self.steps.last().and_then(|s| s.name.as_deref())
+    }
+
+    fn matches(&self, xml: &str, stack: &[Frame], end: usize) -> bool {
+        let Some((last, rest)) = self.steps.split_last() else {
+            return false;
+        };
+        let Some((frame, ancestors)) = stack.split_last() else {
+            return false;
+        };
+        if !name_matches(last, frame.name) {
+            return false;
+        }
+        let outer_ok = if rest.is_empty() {
+            last.axis == Axis::Descendant || ancestors.is_empty()
+        } else {
+            match last.axis {
+                Axis::Child => match_steps(rest, ancestors),
+                Axis::Descendant => (0..ancestors.len()).rev().any(|k| match_steps(rest, &ancestors[..=k])),
+            }
+        };
+        outer_ok && {
+            let element = &xml[frame.start..end];
+            last.predicates.iter().all(|p| p.eval(element))
+        }
+    }
+}
+
+fn name_matches(step: &Step, name: &str) -> bool {
+    step.name.as_deref().is_none_or(|n| n == name)
+}
+
+/// Matches `steps` so that the last one is `stack`'s innermost frame, using attributes only.
+fn match_steps(steps: &[Step], stack: &[Frame]) -> bool {
+    let (Some((step, rest)), Some((frame, ancestors))) = (steps.split_last(), stack.split_last()) else {
+        return false;
+    };
+    if !name_matches(step, frame.name) || !step.predicates.iter().all(|p| p.0.eval_attrs(frame.attrs)) {
+        return false;
+    }
+    if rest.is_empty() {
+        return step.axis == Axis::Descendant || ancestors.is_empty();
+    }
+    match step.axis {
+        Axis::Child => match_steps(rest, ancestors),
+        Axis::Descendant => (0..ancestors.len()).rev().any(|k| match_steps(rest, &ancestors[..=k])),
+    }
+}
+
+/// Calls `on_match` with the byte range of every element matching `query`.
+/// Matches are reported when the element closes, so nested matches come
+/// before their enclosing match.
+pub fn for_each_match<F>(xml: &str, query: &Query, mut on_match: F)
+where
+    F: FnMut(Range<usize>) -> ControlFlow<()>,
+{
+    for_each_match_from(xml, query, &ScanPosition::default(), |range, _| on_match(range));
+}
+
+/// A point in a scan from which it can be resumed: a byte offset and the
+/// start offsets of the elements still open there.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanPosition {
+    pub offset: usize,
+    pub open: Vec<usize>,
+}
+
+/// The elements enclosing a match, as passed to `for_each_match_from`.
+pub struct Ancestors<'s, 'a>(&'s [Frame<'a>]);
+
+impl Ancestors<'_, '_> {
+    /// The position just after the match that was reported with these ancestors.
+    pub fn position_after(&self, match_end: usize) -> ScanPosition {
+        ScanPosition {
+            offset: match_end,
+            open: self.0.iter().map(|frame| frame.start).collect(),
+        }
+    }
+}
+
+/// Like `for_each_match`, but starts at `from` instead of the beginning of
+/// the document, e.g. to continue an interrupted export.
+pub fn for_each_match_from<F>(xml: &str, query: &Query, from: &ScanPosition, mut on_match: F)
+where
+    F: FnMut(Range<usize>, &Ancestors) -> ControlFlow<()>,
+{
+    let mut stack: Vec<Frame> = from.open.iter().filter_map(|&start| open_frame(xml, start)).collect();
+    let base = from.offset.min(xml.len());
+    stream_xml_spans(&xml[base..], |token, span| {
+        let span = base + span.start..base + span.end;
+        match token {
+            Token::StartTag(name, attrs) => stack.push(Frame {
+                name,
+                attrs,
+                start: span.start,
+            }),
+            Token::EndTag(_) => {
+                let matched = !stack.is_empty() && query.matches(xml, &stack, span.end);
+                if let Some(frame) = stack.pop()
+                    && matched
+                    && on_match(frame.start..span.end, &Ancestors(&stack)).is_break()
+                {
+                    return Break(());
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+}
+
+/// Re-reads the start tag at `start` to restore a frame of a resumed scan.
+fn open_frame(xml: &str, start: usize) -> Option<Frame<'_>> {
+    let mut frame = None;
+    stream_xml_spans(xml.get(start..)?, |token, _| {
+        if let Token::StartTag(name, attrs) = token {
+            frame = Some(Frame { name, attrs, start });
+        }
+        Break(())
+    });
+    frame
+}
+
+/// Returns the byte ranges of all elements matching `query`, in document order.
+pub fn select(xml: &str, query: &Query) -> Vec<Range<usize>> {
+    let mut matches = Vec::new();
+    for_each_match(xml, query, |range| {
+        matches.push(range);
+        Continue(())
+    });
+    matches.sort_by_key(|r| r.start);
+    matches
+}
+
+fn find_closing_bracket(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+impl Predicate {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut parser = ExprParser { chars: &chars, pos: 0 };
+        let expr = parser.parse_or()?;
+        parser.skip_ws();
+        if parser.pos < chars.len() {
+            let rest: String = chars[parser.pos..].iter().collect();
+            return Err(format!("unexpected '{}' in predicate", rest));
+        }
+        Ok(Self(expr))
+    }
+
+    /// Evaluates the predicate against `element`, the raw source of one element.
+    pub fn eval(&self, element: &str) -> bool {
+        self.0.eval(element)
+    }
+
+    /// The field and value of a predicate that is a single equality, such
+    /// as `id=42` or `@sku='A-1'`.
+    pub fn equality(&self) -> Option<(Field, &str)> {
+        match &self.0 {
+            Expr::Compare(operand, Op::Eq, literal) => Some((Field(operand.clone()), &literal.text)),
+            _ => None,
+        }
+    }
+}
+
+/// Orders `value` before or after `key` as predicates compare them: as
+/// numbers when both are, else as text.
+pub fn order(value: &str, key: &str) -> std::cmp::Ordering {
+    match (value.trim().parse::<f64>(), key.trim().parse::<f64>()) {
+        (Ok(value), Ok(key)) => value.total_cmp(&key),
+        _ => value.cmp(key),
+    }
+}
+
+impl Field {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let chars: Vec<char> = source.trim().chars().collect();
+        let mut parser = ExprParser { chars: &chars, pos: 0 };
+        let operand = parser.parse_operand()?;
+        if parser.pos < chars.len() {
+            return Err(format!("unexpected trailing input in field '{}'", source));
+        }
+        Ok(Self(operand))
+    }
+
+    /// All values of the field within `element`, the raw source of one element.
+    pub fn values<'e>(&self, element: &'e str) -> Vec<&'e str> {
+        self.0.values(element)
+    }
+
+    /// The first value of the field within `element`, if any.
+    pub fn first<'e>(&self, element: &'e str) -> Option<&'e str> {
+        self.values(element).into_iter().next()
+    }
+}
+
+impl Expr {
+    fn eval(&self, element: &str) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(element) && b.eval(element),
+            Expr::Or(a, b) => a.eval(element) || b.eval(element),
+            Expr::Not(a) => !a.eval(element),
+            Expr::Exists(operand) => !operand.values(element).is_empty(),
+            Expr::Compare(operand, op, literal) => operand
+                .values(element)
+                .iter()
+                .any(|v| compare(v, *op, literal)),
+        }
+    }
+
+    /// Evaluates using only the start tag's attributes; content tests are false.
+    fn eval_attrs(&self, attrs: &str) -> bool {
+        let attr_values = |name: &str| -> Vec<&str> {
+            attribute_pairs(attrs)
+                .into_iter()
+                .filter(|(k, _)| *k == name)
+                .map(|(_, v)| v)
+                .collect()
+        };
+        match self {
+            Expr::And(a, b) => a.eval_attrs(attrs) && b.eval_attrs(attrs),
+            Expr::Or(a, b) => a.eval_attrs(attrs) || b.eval_attrs(attrs),
+            Expr::Not(a) => !a.eval_attrs(attrs),
+            Expr::Exists(Operand::Attr(name)) => !attr_values(name).is_empty(),
+            Expr::Compare(Operand::Attr(name), op, literal) => {
+                attr_values(name).iter().any(|v| compare(v, *op, literal))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Operand {
+    fn values<'e>(&self, element: &'e str) -> Vec<&'e str> {
+        match self {
+            Operand::Attr(name) => extract_attributes(element, 0)
+                .into_iter()
+                .filter(|(k, _)| k == name)
+                .map(|(_, v)| v)
+                .collect(),
+            Operand::Text => child_values(element, &[], None),
+            Operand::Child { path, attr } => child_values(element, path, attr.as_deref()),
+        }
+    }
+}
+
+/// Collects the text (or `attr` value) of every descendant reached by `path`
+/// from the element at the start of `element`: its first run of text or
+/// CDATA. An empty path means the element itself.
+fn child_values<'e>(element: &'e str, path: &[String], attr: Option<&str>) -> Vec<&'e str> {
+    let mut values = Vec::new();
+    // Names of open elements below the context element.
+    let mut stack: Vec<&str> = Vec::new();
+    let mut depth = 0usize;
+    // Depth of an element on `path` whose first text we still want.
+    let mut want_text_at: Option<usize> = None;
+    stream_xml_spans(element, |token, span| {
+        match token {
+            Token::StartTag(name, _) => {
+                if depth > 0 {
+                    stack.push(name);
+                }
+                depth += 1;
+                let on_path = stack.len() == path.len() && stack.iter().zip(path).all(|(a, b)| a == b);
+                if on_path {
+                    match attr {
+                        Some(attr) => values.extend(
+                            extract_attributes(element, span.start)
+                                .into_iter()
+                                .filter(|(k, _)| *k == attr)
+                                .map(|(_, v)| v),
+                        ),
+                        None => want_text_at = Some(depth),
+                    }
+                }
+            }
+            Token::EndTag(_) => {
+                if want_text_at == Some(depth) {
+                    want_text_at = None;
+                }
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return Break(());
+                }
+                stack.pop();
+            }
+            // CDATA content is text as written too, with no entities to decode.
+            Token::Text(text) | Token::CData(text) => {
+                if want_text_at == Some(depth) {
+                    values.push(text);
+                    want_text_at = None;
+                }
+            }
+        }
+        Continue(())
+    });
+    values
+}
+
+fn compare(value: &str, op: Op, literal: &Literal) -> bool {
+    if op == Op::Matches {
+        return literal.regex.as_ref().is_some_and(|r| r.is_match(value));
+    }
+    let ordering = match (literal.number, value.trim().parse::<f64>().ok()) {
+        (Some(expected), Some(actual)) => actual.partial_cmp(&expected),
+        _ => Some(value.cmp(literal.text.as_str())),
+    };
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+        Op::Matches => unreachable!(),
+    }
+}
+
+struct ExprParser<'p> {
+    chars: &'p [char],
+    pos: usize,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')
+}
+
+impl ExprParser<'_> {
+    fn skip_ws(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Consumes `word` if it appears next as a whole keyword.
+    fn eat_keyword(&mut self, word: &str) -> bool {
+        self.skip_ws();
+        let end = self.pos + word.chars().count();
+        let matches = self.chars.get(self.pos..end).is_some_and(|s| s.iter().copied().eq(word.chars()));
+        if matches && !self.chars.get(end).is_some_and(|&c| is_name_char(c)) {
+            self.pos = end;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_keyword("or") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        while self.eat_keyword("and") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        self.skip_ws();
+        if self.eat_keyword("not") {
+            self.skip_ws();
+            if self.peek() != Some('(') {
+                return Err("expected '(' after not".to_string());
+            }
+            return Ok(Expr::Not(Box::new(self.parse_group()?)));
+        }
+        if self.peek() == Some('(') {
+            return self.parse_group();
+        }
+        let operand = self.parse_operand()?;
+        self.skip_ws();
+        let op = match (self.peek(), self.chars.get(self.pos + 1).copied()) {
+            (Some('!'), Some('=')) => Some((Op::Ne, 2)),
+            (Some('<'), Some('=')) => Some((Op::Le, 2)),
+            (Some('>'), Some('=')) => Some((Op::Ge, 2)),
+            (Some('~'), Some('=')) => Some((Op::Matches, 2)),
+            (Some('='), _) => Some((Op::Eq, 1)),
+            (Some('<'), _) => Some((Op::Lt, 1)),
+            (Some('>'), _) => Some((Op::Gt, 1)),
+            _ => None,
+        };
+        let Some((op, len)) = op else {
+            return Ok(Expr::Exists(operand));
+        };
+        self.pos += len;
+        let literal = self.parse_literal(op)?;
+        Ok(Expr::Compare(operand, op, literal))
+    }
+
+    fn parse_group(&mut self) -> Result<Expr, String> {
+        self.pos += 1;
+        let inner = self.parse_or()?;
+        self.skip_ws();
+        if self.peek() != Some(')') {
+            return Err("missing ')' in predicate".to_string());
+        }
+        self.pos += 1;
+        Ok(inner)
+    }
+
+    fn parse_name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(is_name_char) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        self.skip_ws();
+        if self.peek() == Some('@') {
+            self.pos += 1;
+            let name = self.parse_name();
+            if name.is_empty() {
+                return Err("missing attribute name after '@'".to_string());
+            }
+            return Ok(Operand::Attr(name));
+        }
+        if self.eat_keyword("text()") {
+            return Ok(Operand::Text);
+        }
+        let mut path = Vec::new();
+        let mut attr = None;
+        loop {
+            if self.peek() == Some('@') {
+                self.pos += 1;
+                attr = Some(self.parse_name());
+                break;
+            }
+            let name = self.parse_name();
+            if name.is_empty() {
+                return Err(format!("expected an operand at position {}", self.pos));
+            }
+            path.push(name);
+            if self.peek() != Some('/') {
+                break;
+            }
+            self.pos += 1;
+        }
+        if path.len() == 1 && path[0] == "." && attr.is_none() {
+            return Ok(Operand::Text);
+        }
+        Ok(Operand::Child { path, attr })
+    }
+
+    fn parse_literal(&mut self, op: Op) -> Result<Literal, String> {
+        self.skip_ws();
+        let text = match self.peek() {
+            Some(q @ ('\'' | '"')) => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != q) {
+                    self.pos += 1;
+                }
+                if self.peek().is_none() {
+                    return Err("unterminated string in predicate".to_string());
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                self.pos += 1;
+                text
+            }
+            _ => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| is_name_char(c) || c == '+') {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Err("expected a value after the operator".to_string());
+                }
+                self.chars[start..self.pos].iter().collect()
+            }
+        };
+        let regex = if op == Op::Matches {
+            Some(Regex::new(&text)?)
+        } else {
+            None
+        };
+        Ok(Literal {
+            number: text.trim().parse().ok(),
+            text,
+            regex,
+        })
+    }
+}
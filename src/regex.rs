@@ -0,0 +1,434 @@
+//! A small regular expression engine (Pike VM) used for selection and search.
+//!
+//! Supports literals, `.`, character classes (`[a-z]`, `[^...]`), the escapes
+//! `\d \w \s \D \W \S`, anchors `^ $`, groups with alternation, the
+//! quantifiers `* + ? {m} {m,} {m,n}` and a leading `(?i)` for case-insensitive
+//! matching. Matching runs in linear time in the length of the haystack.
+//!
+//! Repetitions are compiled by copying what they repeat, so nested ones
+//! multiply; patterns that would compile past `MAX_PROGRAM` instructions, or
+//! nest groups past `MAX_NESTING`, are refused.
+
+const MAX_REPEAT: u32 = 1000;
+/// Most instructions a pattern may compile to.
+const MAX_PROGRAM: usize = 100_000;
+/// Deepest nesting of groups.
+const MAX_NESTING: usize = 250;
+
+#[derive(Debug, Clone)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char, icase: bool) -> bool {
+        let hit = |c: char| self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        let found = if icase {
+            hit(c) || c.to_lowercase().any(hit) || c.to_uppercase().any(hit)
+        } else {
+            hit(c)
+        };
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Ast {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Ast>),
+    Alt(Vec<Ast>),
+    Repeat(Box<Ast>, u32, Option<u32>),
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Split(usize, usize),
+    Jmp(usize),
+    Start,
+    End,
+    Match,
+}
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Vec<Inst>,
+    icase: bool,
+}
+
+impl Regex {
+    /// Compiles `pattern`, returning a description of the problem on failure.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let (icase, pattern) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut parser = Parser { chars: &chars, pos: 0, depth: 0 };
+        let ast = parser.parse_alt()?;
+        if parser.pos < chars.len() {
+            return Err(format!("unexpected '{}' at position {}", chars[parser.pos], parser.pos));
+        }
+        if program_size(&ast).is_none() {
+            return Err(format!("pattern too large: its repetitions expand past {} instructions", MAX_PROGRAM));
+        }
+        let mut prog = Vec::new();
+        compile(&ast, &mut prog);
+        prog.push(Inst::Match);
+        Ok(Self { prog, icase })
+    }
+
+    /// Returns true if the expression matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let len = text.len();
+        let mut closure = Closure { seen: vec![usize::MAX; self.prog.len()], stack: Vec::new() };
+        let mut clist = Vec::new();
+        let mut nlist = Vec::new();
+        let mut step = 0;
+        let mut chars = text.char_indices().peekable();
+
+        loop {
+            let pos = chars.peek().map_or(len, |&(i, _)| i);
+            // Unanchored search: start a new thread at every position.
+            if self.add_thread(&mut clist, &mut closure, step, 0, pos == 0, pos == len) {
+                return true;
+            }
+            let Some((_, c)) = chars.next() else {
+                return false;
+            };
+            step += 1;
+            let next_pos = chars.peek().map_or(len, |&(i, _)| i);
+            for &pc in clist.iter() {
+                let advance = match &self.prog[pc] {
+                    Inst::Char(x) => self.char_eq(c, *x),
+                    Inst::Any => true,
+                    Inst::Class(class) => class.matches(c, self.icase),
+                    _ => false,
+                };
+                if advance && self.add_thread(&mut nlist, &mut closure, step, pc + 1, false, next_pos == len) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut clist, &mut nlist);
+            nlist.clear();
+        }
+    }
+
+    fn char_eq(&self, c: char, x: char) -> bool {
+        c == x || (self.icase && c.to_lowercase().eq(x.to_lowercase()))
+    }
+
+    /// Follows epsilon transitions from `pc`, returning true when `Match` is
+    /// reached.
+    fn add_thread(&self, list: &mut Vec<usize>, closure: &mut Closure, step: usize, pc: usize, at_start: bool, at_end: bool) -> bool {
+        let Closure { seen, stack } = closure;
+        stack.clear();
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if seen[pc] == step {
+                continue;
+            }
+            seen[pc] = step;
+            match self.prog[pc] {
+                Inst::Match => return true,
+                Inst::Jmp(target) => stack.push(target),
+                Inst::Split(a, b) => {
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Inst::Start if at_start => stack.push(pc + 1),
+                Inst::End if at_end => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                _ => list.push(pc),
+            }
+        }
+        false
+    }
+}
+
+/// What `add_thread` keeps between calls: the step each instruction was
+/// last reached in, and the instructions still to follow, on a stack of its
+/// own so that a long chain of them does not recurse.
+struct Closure {
+    seen: Vec<usize>,
+    stack: Vec<usize>,
+}
+
+struct Parser<'p> {
+    chars: &'p [char],
+    pos: usize,
+    /// Groups open at `pos`.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn parse_alt(&mut self) -> Result<Ast, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Ast::Alt(branches) })
+    }
+
+    fn parse_concat(&mut self) -> Result<Ast, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_quantifiers(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Ast::Empty,
+            1 => items.pop().unwrap(),
+            _ => Ast::Concat(items),
+        })
+    }
+
+    fn parse_quantifiers(&mut self, mut atom: Ast) -> Result<Ast, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.parse_braces()? {
+                    Some(bounds) => bounds,
+                    None => return Ok(atom),
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(atom),
+            };
+            // Lazy modifiers make no difference for a yes/no match.
+            if self.peek() == Some('?') {
+                self.pos += 1;
+            }
+            atom = Ast::Repeat(Box::new(atom), min, max);
+        }
+    }
+
+    /// Parses `{m}`, `{m,}` or `{m,n}`. A brace that does not form a valid
+    /// quantifier is left in place and treated as a literal.
+    fn parse_braces(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let Some(close) = rest.find('}') else {
+            return Ok(None);
+        };
+        let body = &rest[..close];
+        let parse = |s: &str| s.trim().parse::<u32>().ok();
+        let bounds = match body.split_once(',') {
+            None => parse(body).map(|n| (n, Some(n))),
+            Some((lo, "")) => parse(lo).map(|n| (n, None)),
+            Some((lo, hi)) => parse(lo).zip(parse(hi)).map(|(a, b)| (a, Some(b))),
+        };
+        let Some((min, max)) = bounds else {
+            return Ok(None);
+        };
+        if max.is_some_and(|m| m < min) || min > MAX_REPEAT || max.is_some_and(|m| m > MAX_REPEAT) {
+            return Err(format!("invalid repetition {{{}}}", body));
+        }
+        self.pos += body.chars().count() + 2;
+        Ok(Some((min, max)))
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, String> {
+        let c = self.peek().ok_or("unexpected end of pattern")?;
+        self.pos += 1;
+        Ok(match c {
+            '.' => Ast::Any,
+            '^' => Ast::Start,
+            '$' => Ast::End,
+            '(' => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                if self.depth == MAX_NESTING {
+                    return Err(format!("groups nested over {} deep", MAX_NESTING));
+                }
+                self.depth += 1;
+                let inner = self.parse_alt()?;
+                self.depth -= 1;
+                if self.peek() != Some(')') {
+                    return Err("missing ')'".to_string());
+                }
+                self.pos += 1;
+                inner
+            }
+            '[' => Ast::Class(self.parse_class()?),
+            '\\' => self.parse_escape()?,
+            '*' | '+' | '?' => return Err(format!("nothing to repeat before '{}'", c)),
+            _ => Ast::Char(c),
+        })
+    }
+
+    fn parse_escape(&mut self) -> Result<Ast, String> {
+        let c = self.peek().ok_or("trailing '\\'")?;
+        self.pos += 1;
+        Ok(match shorthand_class(c) {
+            Some(class) => Ast::Class(class),
+            None => Ast::Char(escaped_char(c)),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Class, String> {
+        let mut class = Class { ranges: Vec::new(), negated: false };
+        if self.peek() == Some('^') {
+            class.negated = true;
+            self.pos += 1;
+        }
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or("missing ']'")?;
+            self.pos += 1;
+            if c == ']' && !first {
+                return Ok(class);
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.peek().ok_or("trailing '\\'")?;
+                self.pos += 1;
+                if let Some(short) = shorthand_class(e) {
+                    if short.negated {
+                        return Err(format!("\\{} is not supported inside a class", e));
+                    }
+                    class.ranges.extend(short.ranges);
+                    continue;
+                }
+                escaped_char(e)
+            } else {
+                c
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let mut hi = self.peek().ok_or("missing ']'")?;
+                self.pos += 1;
+                if hi == '\\' {
+                    hi = escaped_char(self.peek().ok_or("trailing '\\'")?);
+                    self.pos += 1;
+                }
+                if hi < lo {
+                    return Err(format!("invalid class range {}-{}", lo, hi));
+                }
+                class.ranges.push((lo, hi));
+            } else {
+                class.ranges.push((lo, lo));
+            }
+        }
+    }
+}
+
+fn shorthand_class(c: char) -> Option<Class> {
+    let (ranges, negated) = match c {
+        'd' => (vec![('0', '9')], false),
+        'D' => (vec![('0', '9')], true),
+        'w' => (vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false),
+        'W' => (vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], true),
+        's' => (vec![(' ', ' '), ('\t', '\r')], false),
+        'S' => (vec![(' ', ' '), ('\t', '\r')], true),
+        _ => return None,
+    };
+    Some(Class { ranges, negated })
+}
+
+fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        _ => c,
+    }
+}
+
+/// How many instructions `ast` compiles to at most, or None past
+/// `MAX_PROGRAM`. Each copy of a repetition counts at least one, so that
+/// repeating an empty group is not free either.
+fn program_size(ast: &Ast) -> Option<usize> {
+    let size = match ast {
+        Ast::Empty => 0,
+        Ast::Char(_) | Ast::Any | Ast::Class(_) | Ast::Start | Ast::End => 1,
+        Ast::Concat(items) => items.iter().try_fold(0usize, |total, item| total.checked_add(program_size(item)?))?,
+        Ast::Alt(branches) => branches.iter().try_fold(0usize, |total, branch| total.checked_add(program_size(branch)? + 2))?,
+        Ast::Repeat(inner, min, max) => {
+            let copies = max.unwrap_or(min + 1) as usize;
+            copies.checked_mul(program_size(inner)?.max(1) + 1)?
+        }
+    };
+    (size <= MAX_PROGRAM).then_some(size)
+}
+
+fn compile(ast: &Ast, prog: &mut Vec<Inst>) {
+    match ast {
+        Ast::Empty => {}
+        Ast::Char(c) => prog.push(Inst::Char(*c)),
+        Ast::Any => prog.push(Inst::Any),
+        Ast::Class(class) => prog.push(Inst::Class(class.clone())),
+        Ast::Start => prog.push(Inst::Start),
+        Ast::End => prog.push(Inst::End),
+        Ast::Concat(items) => items.iter().for_each(|item| compile(item, prog)),
+        Ast::Alt(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 < branches.len() {
+                    let split = prog.len();
+                    prog.push(Inst::Split(split + 1, 0));
+                    compile(branch, prog);
+                    jumps.push(prog.len());
+                    prog.push(Inst::Jmp(0));
+                    let next = prog.len();
+                    prog[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(branch, prog);
+                }
+            }
+            let end = prog.len();
+            for j in jumps {
+                prog[j] = Inst::Jmp(end);
+            }
+        }
+        Ast::Repeat(inner, min, max) => {
+            for _ in 0..*min {
+                compile(inner, prog);
+            }
+            match max {
+                None => {
+                    let split = prog.len();
+                    prog.push(Inst::Split(split + 1, 0));
+                    compile(inner, prog);
+                    prog.push(Inst::Jmp(split));
+                    let end = prog.len();
+                    prog[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Split(0, 0));
+                        compile(inner, prog);
+                    }
+                    let end = prog.len();
+                    for s in splits {
+                        prog[s] = Inst::Split(s + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
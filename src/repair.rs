@@ -0,0 +1,453 @@
+//! `xmz repair`: rewrites a nearly well-formed document into a well-formed
+//! one, recording each change as a diagnostic at the input position it
+//! concerns. Everything else is copied byte for byte.
+//!
+//! Fixes: end tags are added for elements left open (where their parent
+//! closes, or at the end of the document), end tags without a start tag are
+//! removed, bare `&` and stray `<` in text and attribute values are escaped
+//! (and `>` in attribute values), and characters XML does not allow (most C0
+//! controls), or references to them, are dropped or replaced. End tags
+//! written in the wrong order (`<b><i>x</b></i>`) are swapped.
+//!
+//! `xmz fix` makes only the changes to tags, leaving text and attribute
+//! values alone, and shows them as a plan before writing anything.
+//!
+//! With `--lenient`, documents are repaired this way as they are loaded, so
+//! a truncated or damaged dump can still be explored; each change is then
+//! reported as a recovery.
+
+use crate::check::{Diagnostic, Severity, is_forbidden, locate};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+/// Makes loaded documents be repaired instead of read as they are, for the
+/// rest of the process.
+pub fn set_lenient(lenient: bool) {
+    LENIENT.store(lenient, Ordering::SeqCst);
+}
+
+pub fn lenient_enabled() -> bool {
+    LENIENT.load(Ordering::SeqCst)
+}
+
+pub struct RepairOptions<'a> {
+    /// Put in place of each character XML does not allow; empty removes them.
+    pub invalid_chars: &'a str,
+    /// Fix only the tags (`xmz fix`), copying text, attribute values and
+    /// other markup as they are.
+    pub structure_only: bool,
+}
+
+pub struct Repair {
+    pub output: String,
+    /// One diagnostic per change, in input order.
+    pub changes: Vec<Diagnostic>,
+}
+
+struct Repairer<'a> {
+    xml: &'a str,
+    out: String,
+    changes: Vec<Diagnostic>,
+    /// Open elements with the offsets of their start tags.
+    open: Vec<(&'a str, usize)>,
+    has_doctype: bool,
+    replacement: &'a str,
+    structure_only: bool,
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+impl<'a> Repairer<'a> {
+    fn change(&mut self, code: &'static str, offset: usize, message: String) {
+        let mut path = String::new();
+        for (name, _) in &self.open {
+            path.push('/');
+            path.push_str(name);
+        }
+        self.changes.push(Diagnostic {
+            severity: Severity::Info,
+            code,
+            offset,
+            line: 0,
+            column: 0,
+            path,
+            message,
+        });
+    }
+
+    /// Replaces (or removes) `count` forbidden characters starting with `first` at `offset`.
+    fn replace_forbidden(&mut self, offset: usize, first: u32, count: usize) {
+        for _ in 0..count {
+            self.out.push_str(self.replacement);
+        }
+        let verb = if self.replacement.is_empty() { "removed" } else { "replaced" };
+        let message = match count {
+            1 => format!("{} U+{:04X}, which XML does not allow", verb, first),
+            n => format!("{} {} characters XML does not allow, starting with U+{:04X}", verb, n, first),
+        };
+        self.change("invalid-char", offset, message);
+    }
+
+    /// The code point a reference at `xml[at..]` refers to, and the length of
+    /// the reference, if it is a character reference.
+    fn char_reference(&self, at: usize) -> Option<(u32, usize)> {
+        let rest = self.xml[at..].strip_prefix("&#")?;
+        let end = rest.find(';')?;
+        let code = match rest[..end].strip_prefix('x') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => rest[..end].parse().ok()?,
+        };
+        Some((code, end + 3))
+    }
+
+    /// Length of the reference at `xml[at..]` (which starts with `&`) if it
+    /// is a valid one: a predefined entity, a character reference, or with a
+    /// DOCTYPE any named entity, which may be declared there.
+    fn reference_len(&self, at: usize) -> Option<usize> {
+        let rest = &self.xml[at + 1..];
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || matches!(c, '#' | '_' | '-' | '.' | ':')))?;
+        if len == 0 || !rest[len..].starts_with(';') {
+            return None;
+        }
+        let name = &rest[..len];
+        let valid = match name {
+            "lt" | "gt" | "amp" | "quot" | "apos" => true,
+            _ if name.starts_with('#') => {
+                let code = match name.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => name[1..].parse().ok(),
+                };
+                code.and_then(char::from_u32).is_some_and(|c| !is_forbidden(c))
+            }
+            _ => self.has_doctype && !name.contains('#'),
+        };
+        valid.then_some(len + 2)
+    }
+
+    /// Copies character data (text, or an attribute value when `quote` is
+    /// given) from `start..end`, escaping `&` and `<` that cannot stay and
+    /// dropping forbidden characters.
+    fn copy_data(&mut self, start: usize, end: usize, quote: Option<char>) {
+        if self.structure_only {
+            self.out.push_str(&self.xml[start..end]);
+            return;
+        }
+        let mut pos = start;
+        while pos < end {
+            let c = self.xml[pos..].chars().next().unwrap_or(' ');
+            match c {
+                '&' => match self.reference_len(pos) {
+                    None if let Some((referred, len)) = self.char_reference(pos).filter(|&(code, _)| char::from_u32(code).is_none_or(is_forbidden)) => {
+                        self.replace_forbidden(pos, referred, 1);
+                        pos += len;
+                        continue;
+                    }
+                    Some(len) => {
+                        self.out.push_str(&self.xml[pos..pos + len]);
+                        pos += len;
+                        continue;
+                    }
+                    None => {
+                        self.out.push_str("&amp;");
+                        self.change("escaped-ampersand", pos, "escaped '&' as &amp;".to_string());
+                    }
+                },
+                '<' => {
+                    self.out.push_str("&lt;");
+                    let place = if quote.is_some() { "in an attribute value" } else { "in text" };
+                    self.change("escaped-lt", pos, format!("escaped '<' {} as &lt;", place));
+                }
+                // Allowed, but ends the tag for tokenizers that do not track quotes.
+                '>' if quote.is_some() => {
+                    self.out.push_str("&gt;");
+                    self.change("escaped-gt", pos, "escaped '>' in an attribute value as &gt;".to_string());
+                }
+                '>' if self.xml[..pos].ends_with("]]") => {
+                    self.out.push_str("&gt;");
+                    self.change("escaped-cdata-end", pos, "escaped ']]>' in text as ]]&gt;".to_string());
+                }
+                c if is_forbidden(c) => {
+                    let run = self.xml[pos..end].chars().take_while(|&c| is_forbidden(c)).count();
+                    self.replace_forbidden(pos, c as u32, run);
+                    pos += self.xml[pos..end].chars().take(run).map(char::len_utf8).sum::<usize>();
+                    continue;
+                }
+                c => self.out.push(c),
+            }
+            pos += c.len_utf8();
+        }
+    }
+
+    /// Copies markup other than elements verbatim, except forbidden characters.
+    fn copy_markup(&mut self, start: usize, end: usize) {
+        if self.structure_only {
+            self.out.push_str(&self.xml[start..end]);
+            return;
+        }
+        let raw = self.xml[start..end].char_indices();
+        for (at, c) in raw {
+            if is_forbidden(c) {
+                self.replace_forbidden(start + at, c as u32, 1);
+            } else {
+                self.out.push(c);
+            }
+        }
+    }
+
+    /// Copies a comment, CDATA section or processing instruction at `at`
+    /// ending with `terminator`; one cut off by the end of the document is closed.
+    fn copy_delimited(&mut self, at: usize, opener: usize, terminator: &str) -> usize {
+        match self.xml[at + opener..].find(terminator) {
+            Some(rel) => {
+                let end = at + opener + rel + terminator.len();
+                self.copy_markup(at, end);
+                end
+            }
+            None => {
+                self.copy_markup(at, self.xml.len());
+                self.out.push_str(terminator);
+                self.change("closed-markup", at, format!("added '{}' to close markup cut off by the end of the document", terminator));
+                self.xml.len()
+            }
+        }
+    }
+
+    /// Copies a DOCTYPE or other `<!` declaration, which may hold an internal subset.
+    fn copy_declaration(&mut self, at: usize) -> usize {
+        let bytes = self.xml.as_bytes();
+        let (mut pos, mut depth, mut quote) = (at + 2, 0usize, None);
+        while pos < bytes.len() {
+            match (bytes[pos], quote) {
+                (q, Some(open)) if q == open => quote = None,
+                (_, Some(_)) => {}
+                (q @ (b'"' | b'\''), None) => quote = Some(q),
+                (b'[', None) => depth += 1,
+                (b']', None) => depth = depth.saturating_sub(1),
+                (b'>', None) if depth == 0 => {
+                    self.copy_markup(at, pos + 1);
+                    return pos + 1;
+                }
+                _ => {}
+            }
+            pos += 1;
+        }
+        self.change("removed-markup", at, "removed a declaration cut off by the end of the document".to_string());
+        bytes.len()
+    }
+
+    /// Handles the start tag at `at`, returning where it ends.
+    fn start_tag(&mut self, at: usize) -> usize {
+        let bytes = self.xml.as_bytes();
+        let name_end = self.xml[at + 1..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '<'))
+            .map_or(bytes.len(), |rel| at + 1 + rel);
+        let name = &self.xml[at + 1..name_end];
+        let mut pos = name_end;
+        let mut quote = None;
+        let body_start = pos;
+        // Find the closing '>' outside quotes; a '<' outside quotes means it is missing.
+        let end = loop {
+            let Some(&b) = bytes.get(pos) else {
+                self.change("removed-markup", at, format!("removed <{} cut off by the end of the document", name));
+                return bytes.len();
+            };
+            match (b, quote) {
+                (q, Some(open)) if q == open => quote = None,
+                (_, Some(_)) => {}
+                (q @ (b'"' | b'\''), None) => quote = Some(q),
+                (b'>', None) => break Some(pos),
+                (b'<', None) => break None,
+                _ => {}
+            }
+            pos += 1;
+        };
+        let body_end = end.unwrap_or(pos);
+        let self_closing = self.xml[body_start..body_end].trim_end().ends_with('/');
+        self.out.push('<');
+        self.out.push_str(name);
+        self.copy_attributes(body_start, body_end);
+        self.out.push('>');
+        if end.is_none() {
+            self.change("closed-tag", pos, format!("added '>' to close the start tag <{}>", name));
+        }
+        if !self_closing {
+            self.open.push((name, at));
+        }
+        end.map_or(pos, |end| end + 1)
+    }
+
+    /// Copies the attributes of a start tag, repairing their values.
+    fn copy_attributes(&mut self, start: usize, end: usize) {
+        let bytes = self.xml.as_bytes();
+        let mut pos = start;
+        while pos < end {
+            let b = bytes[pos];
+            if b == b'"' || b == b'\'' {
+                let close = self.xml[pos + 1..end].find(b as char).map_or(end, |rel| pos + 1 + rel);
+                self.out.push(b as char);
+                self.copy_data(pos + 1, close, Some(b as char));
+                self.out.push(b as char);
+                if close == end {
+                    self.change("closed-attribute", pos, "added the missing closing quote of an attribute value".to_string());
+                }
+                pos = close + 1;
+            } else {
+                let c = self.xml[pos..].chars().next().unwrap_or(' ');
+                if is_forbidden(c) && !self.structure_only {
+                    self.replace_forbidden(pos, c as u32, 1);
+                } else {
+                    self.out.push(c);
+                }
+                pos += c.len_utf8();
+            }
+        }
+    }
+
+    /// Handles the end tag at `at`, returning where it ends.
+    fn end_tag(&mut self, at: usize) -> usize {
+        let bytes = self.xml.as_bytes();
+        let close = self.xml[at + 2..].find(['>', '<']).map(|rel| at + 2 + rel);
+        let terminated = close.is_some_and(|close| bytes[close] == b'>');
+        let name_end = close.unwrap_or(bytes.len());
+        let name = self.xml[at + 2..name_end].trim();
+        let next = if terminated { name_end + 1 } else { name_end };
+        let Some(depth) = self.open.iter().rposition(|(open, _)| *open == name) else {
+            self.change("removed-end-tag", at, format!("removed </{}>, which has no start tag", name));
+            return next;
+        };
+        // `<b><i>x</b></i>`: end tags written in the wrong order are put
+        // in the right one, rather than closing `i` early and dropping `</i>`.
+        let inner: Vec<&str> = self.open[depth + 1..].iter().rev().map(|(inner, _)| *inner).collect();
+        if terminated
+            && !inner.is_empty()
+            && let Some(after) = self.following_end_tags(next, &inner)
+        {
+            let moved: String = inner.iter().map(|inner| format!("</{}>", inner)).collect();
+            self.change("misnested", at, format!("moved </{}> after {}, which close inside it", name, moved));
+            self.out.push_str(&moved);
+            self.out.push_str(&self.xml[at..next]);
+            self.open.truncate(depth);
+            return after;
+        }
+        while self.open.len() > depth + 1 {
+            let (inner, start) = self.open[self.open.len() - 1];
+            self.out.push_str(&format!("</{}>", inner));
+            self.change("closed-element", at, format!("added </{}> for the element opened at byte {}, before </{}>", inner, start, name));
+            self.open.pop();
+        }
+        self.open.pop();
+        if terminated {
+            self.out.push_str(&self.xml[at..next]);
+        } else {
+            self.out.push_str(&format!("</{}>", name));
+            self.change("closed-tag", at, format!("added '>' to close the end tag </{}>", name));
+        }
+        next
+    }
+
+    /// Where the end tags of `names`, in order, end if they come right
+    /// after `at`.
+    fn following_end_tags(&self, at: usize, names: &[&str]) -> Option<usize> {
+        let mut pos = at;
+        for name in names {
+            let rest = self.xml[pos..].strip_prefix("</")?.strip_prefix(name)?;
+            let len = rest.len() - rest.trim_start().len();
+            rest[len..].starts_with('>').then_some(())?;
+            pos += 2 + name.len() + len + 1;
+        }
+        Some(pos)
+    }
+
+    fn run(&mut self) {
+        let xml = self.xml;
+        let mut pos = 0;
+        while pos < xml.len() {
+            let Some(rel) = xml[pos..].find('<') else {
+                self.copy_data(pos, xml.len(), None);
+                break;
+            };
+            let at = pos + rel;
+            self.copy_data(pos, at, None);
+            let rest = &xml[at..];
+            pos = if rest.starts_with("<!--") {
+                self.copy_delimited(at, 4, "-->")
+            } else if rest.starts_with("<![CDATA[") {
+                self.copy_delimited(at, 9, "]]>")
+            } else if rest.starts_with("<?") {
+                self.copy_delimited(at, 2, "?>")
+            } else if rest.starts_with("<!") {
+                self.copy_declaration(at)
+            } else if rest.starts_with("</") {
+                self.end_tag(at)
+            } else if rest[1..].starts_with(is_name_start) {
+                self.start_tag(at)
+            } else if self.structure_only {
+                self.out.push('<');
+                at + 1
+            } else {
+                self.out.push_str("&lt;");
+                self.change("escaped-lt", at, "escaped '<' in text as &lt;".to_string());
+                at + 1
+            };
+        }
+        while let Some((name, start)) = self.open.pop() {
+            self.out.push_str(&format!("</{}>", name));
+            self.change("closed-element", xml.len(), format!("added </{}> at the end for the element opened at byte {}", name, start));
+        }
+    }
+}
+
+/// Repairs `xml`; the output is a plain copy if there was nothing to fix.
+pub fn repair(xml: &str, options: &RepairOptions) -> Repair {
+    let mut repairer = Repairer {
+        xml,
+        out: String::with_capacity(xml.len() + xml.len() / 64),
+        changes: Vec::new(),
+        open: Vec::new(),
+        has_doctype: memchr::memmem::find(xml.as_bytes(), b"<!DOCTYPE").is_some(),
+        replacement: options.invalid_chars,
+        structure_only: options.structure_only,
+    };
+    repairer.run();
+    let mut changes = repairer.changes;
+    changes.sort_by_key(|d| d.offset);
+    locate(xml, &mut changes);
+    Repair {
+        output: repairer.out,
+        changes,
+    }
+}
+
+fn write_change<W: Write>(out: &mut W, file: &str, label: &str, d: &Diagnostic) -> io::Result<()> {
+    write!(out, "{}:{}:{}: {}[{}]: {}", file, d.line, d.column, label, d.code, d.message)?;
+    if !d.path.is_empty() {
+        write!(out, " (at {})", d.path)?;
+    }
+    writeln!(out)
+}
+
+/// Prints `file:line:column: fixed[code]: message (at path)` lines and a summary.
+pub fn write_changes<W: Write>(out: &mut W, file: &str, changes: &[Diagnostic]) -> io::Result<()> {
+    for d in changes {
+        write_change(out, file, "fixed", d)?;
+    }
+    match changes.len() {
+        0 => writeln!(out, "{}: nothing to repair", file),
+        n => writeln!(out, "{}: {} change(s)", file, n),
+    }
+}
+
+/// Prints the changes `xmz fix` would make, as `plan[code]` lines.
+pub fn write_plan<W: Write>(out: &mut W, file: &str, changes: &[Diagnostic]) -> io::Result<()> {
+    for d in changes {
+        write_change(out, file, "plan", d)?;
+    }
+    match changes.len() {
+        0 => writeln!(out, "{}: the tags are balanced", file),
+        n => writeln!(out, "{}: {} change(s) planned; pass -o FILE to write the fixed copy", file, n),
+    }
+}
@@ -0,0 +1,55 @@
+//! `--sandbox`: a process-wide switch that refuses every operation able to
+//! change the system or reach outside it, so xmz can be run on production
+//! hosts with read-only guarantees.
+//!
+//! Dispatch layers (CLI argument handling, TUI actions, decompressors) call
+//! `check` before acting instead of deciding on their own.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Operations that `--sandbox` disables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    WriteFiles,
+    RunCommands,
+    Network,
+}
+
+impl Capability {
+    fn describe(self) -> &'static str {
+        match self {
+            Capability::WriteFiles => "writing files",
+            Capability::RunCommands => "running external commands",
+            Capability::Network => "network access",
+        }
+    }
+}
+
+/// Turns the sandbox on for the rest of the process; it cannot be turned off.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Fails with `PermissionDenied` if `capability` is disabled.
+pub fn check(capability: Capability) -> io::Result<()> {
+    if is_enabled() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is disabled by --sandbox", capability.describe()),
+        ));
+    }
+    if capability == Capability::Network && !crate::parser::limits().external_fetches {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is disabled by --untrusted", capability.describe()),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,724 @@
+//! Structure inference (`xmz schema`) and drift detection against a saved baseline.
+//!
+//! The inferred structure is the set of element paths in the document with,
+//! for each, how often it occurs inside its parent, the attributes seen on it
+//! and the apparent type of its text and attribute values. From it, `xmz
+//! schema --draft` writes an XSD or RELAX NG starting point. A baseline is the
+//! paths and attribute names saved as JSON, so a CI job can notice when an
+//! upstream feed starts sending new fields.
+
+use crate::json::Json;
+use crate::parser::{Continue, Token, extract_attributes, stream_xml_spans};
+use crate::transform::escape;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+
+/// The apparent type of text or attribute values, from the values seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Int,
+    Float,
+    /// `2024-05-04`
+    Date,
+    /// `2024-05-04T06:12:00Z`, with optional fractional seconds and offset.
+    DateTime,
+    String,
+}
+
+impl Kind {
+    pub fn of(value: &str) -> Self {
+        let bytes = value.as_bytes();
+        let unsigned = bytes.strip_prefix(b"-").or_else(|| bytes.strip_prefix(b"+")).unwrap_or(bytes);
+        if !unsigned.is_empty() && unsigned.iter().all(u8::is_ascii_digit) {
+            Kind::Int
+        } else if unsigned.first().is_some_and(|&b| b.is_ascii_digit() || b == b'.')
+            && unsigned.iter().all(|&b| b.is_ascii_digit() || b".eE+-".contains(&b))
+            && value.parse::<f64>().is_ok()
+        {
+            Kind::Float
+        } else if is_date(bytes) {
+            Kind::Date
+        } else if bytes.len() > 10 && is_date(&bytes[..10]) && bytes[10] == b'T' && is_time(&bytes[11..]) {
+            Kind::DateTime
+        } else {
+            Kind::String
+        }
+    }
+
+    /// The narrowest kind covering values of both kinds.
+    pub(crate) fn join(self, other: Kind) -> Kind {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Kind::Int, Kind::Float) | (Kind::Float, Kind::Int) => Kind::Float,
+            _ => Kind::String,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Int => "int",
+            Kind::Float => "float",
+            Kind::Date => "date",
+            Kind::DateTime => "datetime",
+            Kind::String => "string",
+        }
+    }
+
+    /// The XML Schema datatype, also used by RELAX NG.
+    fn datatype(self) -> &'static str {
+        match self {
+            Kind::Int => "integer",
+            Kind::Float => "double",
+            Kind::Date => "date",
+            Kind::DateTime => "dateTime",
+            Kind::String => "string",
+        }
+    }
+}
+
+/// Whether `bytes` matches `pattern`, where `9` stands for any digit.
+fn shaped(bytes: &[u8], pattern: &[u8]) -> bool {
+    bytes.len() == pattern.len() && bytes.iter().zip(pattern).all(|(&b, &p)| if p == b'9' { b.is_ascii_digit() } else { b == p })
+}
+
+fn is_date(bytes: &[u8]) -> bool {
+    shaped(bytes, b"9999-99-99")
+}
+
+/// `hh:mm:ss`, then optional fractional seconds, then `Z` or `±hh:mm`, if any.
+fn is_time(bytes: &[u8]) -> bool {
+    if bytes.len() < 8 || !shaped(&bytes[..8], b"99:99:99") {
+        return false;
+    }
+    let mut rest = &bytes[8..];
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        let digits = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return false;
+        }
+        rest = &fraction[digits..];
+    }
+    rest.is_empty() || rest == b"Z" || shaped(rest, b"+99:99") || shaped(rest, b"-99:99")
+}
+
+/// How many values were seen and their kind, `None` until there is one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Values {
+    pub count: u64,
+    pub kind: Option<Kind>,
+}
+
+impl Values {
+    fn add(&mut self, value: &str) {
+        self.count += 1;
+        let kind = Kind::of(value);
+        self.kind = Some(self.kind.map_or(kind, |k| k.join(kind)));
+    }
+}
+
+/// One distinct element path, e.g. `/catalog/book`.
+#[derive(Debug, Clone, Default)]
+pub struct ElementInfo {
+    pub path: String,
+    pub count: u64,
+    pub attributes: BTreeMap<String, Values>,
+    /// Text directly inside the element.
+    pub text: Values,
+    /// Indexes of child element paths, in the order they come in, or in
+    /// first-seen order when that varies.
+    pub children: Vec<usize>,
+    /// Whether the order of children varies, e.g. a child comes back after
+    /// another.
+    pub unordered: bool,
+    /// Pairs of child paths seen one right after the other.
+    follows: HashSet<(usize, usize)>,
+    /// Parent elements containing this one, and the most it occurs in one.
+    pub parents: u64,
+    pub max_per_parent: u64,
+}
+
+impl ElementInfo {
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Attributes other than namespace declarations.
+    fn data_attributes(&self) -> impl Iterator<Item = (&String, &Values)> {
+        self.attributes.iter().filter(|(name, _)| !is_declaration(name))
+    }
+
+    fn depth(&self) -> usize {
+        self.path.matches('/').count() - 1
+    }
+
+    /// Sorts children so that each comes after those it followed, keeping
+    /// first-seen order where that leaves a choice; when the pairs contradict
+    /// each other, the children are unordered.
+    fn order_children(&mut self) {
+        let mut remaining = self.children.clone();
+        let mut ordered = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let next = remaining
+                .iter()
+                .position(|&child| !remaining.iter().any(|&before| before != child && self.follows.contains(&(before, child))));
+            match next {
+                Some(i) => ordered.push(remaining.remove(i)),
+                None => {
+                    self.unordered = true;
+                    return;
+                }
+            }
+        }
+        self.children = ordered;
+    }
+}
+
+/// Element paths of a document in first-seen order.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    pub elements: Vec<ElementInfo>,
+    by_path: HashMap<String, usize>,
+    /// Namespace URIs by prefix (empty for the default namespace), as first declared.
+    pub namespaces: Vec<(String, String)>,
+}
+
+/// An element being read: its path index, and its children so far.
+struct Open {
+    element: usize,
+    children: Vec<(usize, u64)>,
+}
+
+/// Schema drafts `xmz schema --draft` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Draft {
+    Xsd,
+    RelaxNg,
+}
+
+impl Draft {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "xsd" => Ok(Draft::Xsd),
+            "rng" | "relaxng" => Ok(Draft::RelaxNg),
+            _ => Err(format!("unknown schema language '{}' (expected xsd or rng)", name)),
+        }
+    }
+}
+
+impl Schema {
+    /// Infers the structure of `xml` in one streaming pass.
+    pub fn infer(xml: &str) -> Self {
+        let mut schema = Schema::default();
+        let mut path = String::new();
+        let mut lengths: Vec<usize> = Vec::new();
+        let mut open: Vec<Open> = Vec::new();
+        stream_xml_spans(xml, |token, span| {
+            match token {
+                Token::StartTag(name, _) => {
+                    lengths.push(path.len());
+                    path.push('/');
+                    path.push_str(name);
+                    let id = schema.index(&path);
+                    let element = &mut schema.elements[id];
+                    element.count += 1;
+                    for (attr, value) in extract_attributes(xml, span.start) {
+                        match element.attributes.get_mut(attr) {
+                            Some(values) => values.add(value),
+                            None => element.attributes.entry(attr.to_string()).or_default().add(value),
+                        }
+                        let prefix = if attr == "xmlns" { Some("") } else { attr.strip_prefix("xmlns:") };
+                        if let Some(prefix) = prefix
+                            && !schema.namespaces.iter().any(|(p, _)| p == prefix)
+                        {
+                            schema.namespaces.push((prefix.to_string(), value.to_string()));
+                        }
+                    }
+                    if let Some(parent) = open.last_mut() {
+                        let siblings = &mut schema.elements[parent.element];
+                        if !siblings.children.contains(&id) {
+                            siblings.children.push(id);
+                        }
+                        match parent.children.last_mut() {
+                            Some((last, n)) if *last == id => *n += 1,
+                            last => {
+                                if let Some(&mut (last, _)) = last {
+                                    siblings.follows.insert((last, id));
+                                }
+                                parent.children.push((id, 1));
+                            }
+                        }
+                    }
+                    open.push(Open {
+                        element: id,
+                        children: Vec::new(),
+                    });
+                }
+                Token::EndTag(_) => {
+                    if let Some(len) = lengths.pop() {
+                        path.truncate(len);
+                    }
+                    if let Some(closed) = open.pop() {
+                        let mut counts: Vec<(usize, u64)> = Vec::new();
+                        for (child, n) in closed.children {
+                            match counts.iter_mut().find(|(c, _)| *c == child) {
+                                Some((_, total)) => *total += n,
+                                None => counts.push((child, n)),
+                            }
+                        }
+                        for (child, n) in counts {
+                            let child = &mut schema.elements[child];
+                            child.parents += 1;
+                            child.max_per_parent = child.max_per_parent.max(n);
+                        }
+                    }
+                }
+                Token::Text(text) | Token::CData(text) => {
+                    if let Some(current) = open.last() {
+                        schema.elements[current.element].text.add(text);
+                    }
+                }
+            }
+            Continue(())
+        });
+        for element in &mut schema.elements {
+            element.order_children();
+        }
+        schema
+    }
+
+    fn index(&mut self, path: &str) -> usize {
+        match self.by_path.get(path) {
+            Some(&i) => i,
+            None => {
+                self.by_path.insert(path.to_string(), self.elements.len());
+                self.elements.push(ElementInfo {
+                    path: path.to_string(),
+                    ..Default::default()
+                });
+                self.elements.len() - 1
+            }
+        }
+    }
+
+    fn entry(&mut self, path: &str) -> &mut ElementInfo {
+        let i = self.index(path);
+        &mut self.elements[i]
+    }
+
+    pub fn get(&self, path: &str) -> Option<&ElementInfo> {
+        self.by_path.get(path).map(|&i| &self.elements[i])
+    }
+
+    /// How often `element` occurs inside one of its parents, at least (0 or
+    /// 1) and at most; once for the root.
+    pub fn occurs(&self, element: &ElementInfo) -> (u64, u64) {
+        let parent = element.path.rsplit_once('/').and_then(|(parent, _)| self.get(parent));
+        match parent {
+            Some(parent) => ((element.parents >= parent.count) as u64, element.max_per_parent),
+            None => (1, 1),
+        }
+    }
+
+    /// The baseline representation: paths and attribute names, without counts,
+    /// so it only changes when the structure does.
+    pub fn to_json(&self) -> Json {
+        let elements = self
+            .elements
+            .iter()
+            .map(|e| {
+                let attributes = e.attributes.keys().map(|a| Json::String(a.clone())).collect();
+                (e.path.clone(), Json::Object(vec![("attributes".to_string(), Json::Array(attributes))]))
+            })
+            .collect();
+        Json::Object(vec![("elements".to_string(), Json::Object(elements))])
+    }
+
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        let json = Json::parse(src)?;
+        let elements = json
+            .get("elements")
+            .and_then(Json::as_object)
+            .ok_or("baseline has no \"elements\" object")?;
+        let mut schema = Schema::default();
+        for (path, info) in elements {
+            let element = schema.entry(path);
+            for attr in info.get("attributes").and_then(Json::as_array).unwrap_or_default() {
+                let name = attr.as_str().ok_or_else(|| format!("non-string attribute name under '{}'", path))?;
+                element.attributes.entry(name.to_string()).or_default();
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Paths of root elements; one in a well-formed document.
+    fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.elements.len()).filter(|&i| self.elements[i].depth() == 0)
+    }
+
+    /// Writes an indented outline, one line per path: its name, marked `?`
+    /// when optional, `+` when repeated or `*` for both, its count, the kind
+    /// of its text, and its attributes with their kinds (marked `?` when
+    /// optional).
+    pub fn write_outline<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for root in self.roots() {
+            self.write_outline_element(out, root)?;
+        }
+        Ok(())
+    }
+
+    fn write_outline_element<W: Write>(&self, out: &mut W, id: usize) -> io::Result<()> {
+        let element = &self.elements[id];
+        let marker = match self.occurs(element) {
+            (0, 1) => "?",
+            (0, _) => "*",
+            (_, 1) => "",
+            _ => "+",
+        };
+        write!(out, "{}{}{} ({})", "  ".repeat(element.depth()), element.name(), marker, element.count)?;
+        if let Some(kind) = element.text.kind {
+            write!(out, ": {}", if element.children.is_empty() { kind.name() } else { "mixed" })?;
+        }
+        for (name, values) in &element.attributes {
+            let optional = if values.count < element.count { "?" } else { "" };
+            match values.kind.filter(|_| !is_declaration(name)) {
+                Some(kind) => write!(out, " @{}{}: {}", name, optional, kind.name())?,
+                None => write!(out, " @{}{}", name, optional)?,
+            }
+        }
+        writeln!(out)?;
+        for &child in &element.children {
+            self.write_outline_element(out, child)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a schema in `draft`'s language that the document would pass:
+    /// a starting point, since one document shows only some of what its
+    /// format allows.
+    pub fn write_draft<W: Write>(&self, out: &mut W, draft: Draft) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(out, "<!-- Inferred by xmz from one document: review types, occurrences and order before use. -->")?;
+        let default_namespace = self.namespaces.iter().find(|(prefix, _)| prefix.is_empty()).map(|(_, uri)| escape(uri));
+        match draft {
+            Draft::Xsd => {
+                // One schema covers one namespace, the root's: elements and
+                // attributes from others are allowed rather than declared.
+                writeln!(out, "<!-- Elements and attributes from other namespaces than the root's are allowed, not declared. -->")?;
+                let target = self.roots().next().and_then(|root| self.namespace(self.elements[root].name()));
+                write!(out, r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" elementFormDefault="qualified""#)?;
+                if let Some(uri) = target {
+                    write!(out, r#" targetNamespace="{0}" xmlns="{0}""#, escape(uri))?;
+                }
+                writeln!(out, ">")?;
+                for root in self.roots() {
+                    self.write_xsd_element(out, root, 1, false, target)?;
+                }
+                writeln!(out, "</xs:schema>")
+            }
+            Draft::RelaxNg => {
+                write!(out, r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0" datatypeLibrary="http://www.w3.org/2001/XMLSchema-datatypes""#)?;
+                if let Some(uri) = &default_namespace {
+                    write!(out, r#" ns="{}""#, uri)?;
+                }
+                for (prefix, uri) in &self.namespaces {
+                    if !prefix.is_empty() && prefix != "xml" {
+                        write!(out, r#" xmlns:{}="{}""#, prefix, escape(uri))?;
+                    }
+                }
+                writeln!(out, ">")?;
+                writeln!(out, "  <start>")?;
+                for root in self.roots() {
+                    self.write_rng_element(out, root, 2)?;
+                }
+                writeln!(out, "  </start>")?;
+                writeln!(out, "</grammar>")
+            }
+        }
+    }
+
+    /// Declares element `id` at `depth`; inside a repeated choice, its own
+    /// occurrence is left to the choice.
+    /// The namespace URI of a prefixed or unprefixed name, as declared
+    /// somewhere in the document.
+    fn namespace(&self, name: &str) -> Option<&str> {
+        let prefix = name.split_once(':').map_or("", |(prefix, _)| prefix);
+        if prefix == "xml" {
+            return Some("http://www.w3.org/XML/1998/namespace");
+        }
+        self.namespaces.iter().find(|(p, _)| p == prefix).map(|(_, uri)| uri.as_str())
+    }
+
+    fn write_xsd_element<W: Write>(&self, out: &mut W, id: usize, depth: usize, in_choice: bool, target: Option<&str>) -> io::Result<()> {
+        let element = &self.elements[id];
+        let pad = "  ".repeat(depth);
+        let name = local_name(element.name());
+        let mut occurs = String::new();
+        if !in_choice && depth > 1 {
+            let (min, max) = self.occurs(element);
+            if min == 0 {
+                occurs.push_str(r#" minOccurs="0""#);
+            }
+            if max > 1 {
+                occurs.push_str(r#" maxOccurs="unbounded""#);
+            }
+        }
+        if depth > 1 && self.namespace(element.name()) != target {
+            let namespace = self.namespace(element.name()).map_or_else(|| "##local".to_string(), escape);
+            return writeln!(out, r#"{}<xs:any namespace="{}" processContents="lax"{}/>"#, pad, namespace, occurs);
+        }
+        if element.children.is_empty() && element.data_attributes().next().is_none() {
+            return match element.text.kind {
+                Some(kind) => writeln!(out, r#"{}<xs:element name="{}" type="xs:{}"{}/>"#, pad, name, kind.datatype(), occurs),
+                None => writeln!(out, r#"{0}<xs:element name="{1}"{2}><xs:complexType/></xs:element>"#, pad, name, occurs),
+            };
+        }
+        writeln!(out, r#"{}<xs:element name="{}"{}>"#, pad, name, occurs)?;
+        match element.text.kind {
+            Some(kind) if element.children.is_empty() => {
+                writeln!(out, "{}  <xs:complexType>", pad)?;
+                writeln!(out, "{}    <xs:simpleContent>", pad)?;
+                writeln!(out, r#"{}      <xs:extension base="xs:{}">"#, pad, kind.datatype())?;
+                self.write_xsd_attributes(out, element, depth + 4, target)?;
+                writeln!(out, "{}      </xs:extension>", pad)?;
+                writeln!(out, "{}    </xs:simpleContent>", pad)?;
+            }
+            text => {
+                let mixed = if text.is_some() { r#" mixed="true""# } else { "" };
+                writeln!(out, "{}  <xs:complexType{}>", pad, mixed)?;
+                if !element.children.is_empty() {
+                    let group = if element.unordered { r#"xs:choice minOccurs="0" maxOccurs="unbounded""# } else { "xs:sequence" };
+                    writeln!(out, "{}    <{}>", pad, group)?;
+                    for &child in &element.children {
+                        self.write_xsd_element(out, child, depth + 3, element.unordered, target)?;
+                    }
+                    writeln!(out, "{}    </{}>", pad, group.split(' ').next().unwrap_or_default())?;
+                }
+                self.write_xsd_attributes(out, element, depth + 2, target)?;
+            }
+        }
+        writeln!(out, "{}  </xs:complexType>", pad)?;
+        writeln!(out, "{}</xs:element>", pad)
+    }
+
+    /// Declares unprefixed attributes and those in `target`, then allows the
+    /// namespaces of the rest.
+    fn write_xsd_attributes<W: Write>(&self, out: &mut W, element: &ElementInfo, depth: usize, target: Option<&str>) -> io::Result<()> {
+        let pad = "  ".repeat(depth);
+        let mut others: Vec<String> = Vec::new();
+        for (name, values) in element.data_attributes() {
+            let form = if !name.contains(':') {
+                ""
+            } else if target.is_some() && self.namespace(name) == target {
+                r#" form="qualified""#
+            } else {
+                let namespace = self.namespace(name).map_or_else(|| "##any".to_string(), escape);
+                if !others.contains(&namespace) {
+                    others.push(namespace);
+                }
+                continue;
+            };
+            let kind = values.kind.unwrap_or(Kind::String);
+            let required = if values.count < element.count { "" } else { r#" use="required""# };
+            writeln!(out, r#"{}<xs:attribute name="{}" type="xs:{}"{}{}/>"#, pad, local_name(name), kind.datatype(), form, required)?;
+        }
+        if !others.is_empty() {
+            writeln!(out, r#"{}<xs:anyAttribute namespace="{}" processContents="lax"/>"#, pad, others.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn write_rng_element<W: Write>(&self, out: &mut W, id: usize, depth: usize) -> io::Result<()> {
+        let element = &self.elements[id];
+        let wrapper = match self.occurs(element) {
+            (0, 1) => Some("optional"),
+            (0, _) => Some("zeroOrMore"),
+            (_, 1) => None,
+            _ => Some("oneOrMore"),
+        };
+        let depth = depth + wrapper.is_some() as usize;
+        let pad = "  ".repeat(depth);
+        if let Some(wrapper) = wrapper {
+            writeln!(out, "{}<{}>", &pad[2..], wrapper)?;
+        }
+        let attributes: Vec<_> = element.data_attributes().collect();
+        let content = match element.text.kind {
+            _ if !element.children.is_empty() => None,
+            Some(Kind::String) => Some("<text/>".to_string()),
+            Some(kind) => Some(format!(r#"<data type="{}"/>"#, kind.datatype())),
+            None if attributes.is_empty() => Some("<empty/>".to_string()),
+            None => None,
+        };
+        if let Some(content) = &content
+            && attributes.is_empty()
+        {
+            writeln!(out, r#"{}<element name="{}">{}</element>"#, pad, element.name(), content)?;
+        } else {
+            self.write_rng_content(out, element, &attributes, content, depth)?;
+        }
+        if let Some(wrapper) = wrapper {
+            writeln!(out, "{}</{}>", &pad[2..], wrapper)?;
+        }
+        Ok(())
+    }
+
+    fn write_rng_content<W: Write>(&self, out: &mut W, element: &ElementInfo, attributes: &[(&String, &Values)], content: Option<String>, depth: usize) -> io::Result<()> {
+        let pad = "  ".repeat(depth);
+        writeln!(out, r#"{}<element name="{}">"#, pad, element.name())?;
+        for &(name, values) in attributes {
+            let attribute = match values.kind {
+                Some(Kind::String) | None => format!(r#"<attribute name="{}"/>"#, name),
+                Some(kind) => format!(r#"<attribute name="{}"><data type="{}"/></attribute>"#, name, kind.datatype()),
+            };
+            if values.count < element.count {
+                writeln!(out, "{}  <optional>{}</optional>", pad, attribute)?;
+            } else {
+                writeln!(out, "{}  {}", pad, attribute)?;
+            }
+        }
+        if let Some(content) = content {
+            writeln!(out, "{}  {}", pad, content)?;
+        } else if !element.children.is_empty() {
+            let mut groups = Vec::new();
+            if element.text.kind.is_some() {
+                groups.push("mixed");
+            }
+            if element.unordered {
+                groups.push("interleave");
+            }
+            for (i, group) in groups.iter().enumerate() {
+                writeln!(out, "{}  {}<{}>", pad, "  ".repeat(i), group)?;
+            }
+            for &child in &element.children {
+                self.write_rng_element(out, child, depth + 1 + groups.len())?;
+            }
+            for (i, group) in groups.iter().enumerate().rev() {
+                writeln!(out, "{}  {}</{}>", pad, "  ".repeat(i), group)?;
+            }
+        }
+        writeln!(out, "{}</element>", pad)
+    }
+}
+
+/// Whether attribute `name` declares a namespace rather than carrying data.
+fn is_declaration(name: &str) -> bool {
+    name == "xmlns" || name.starts_with("xmlns:")
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+/// Structural differences between a baseline and the current document.
+#[derive(Debug, Default)]
+pub struct Drift {
+    pub new_elements: Vec<String>,
+    pub removed_elements: Vec<String>,
+    /// `(element path, attribute name)` pairs.
+    pub new_attributes: Vec<(String, String)>,
+    pub removed_attributes: Vec<(String, String)>,
+}
+
+impl Drift {
+    pub fn between(baseline: &Schema, current: &Schema) -> Self {
+        let mut drift = Drift::default();
+        for element in &current.elements {
+            match baseline.get(&element.path) {
+                None => drift.new_elements.push(element.path.clone()),
+                Some(old) => {
+                    for attr in element.attributes.keys().filter(|a| !old.attributes.contains_key(*a)) {
+                        drift.new_attributes.push((element.path.clone(), attr.clone()));
+                    }
+                    for attr in old.attributes.keys().filter(|a| !element.attributes.contains_key(*a)) {
+                        drift.removed_attributes.push((element.path.clone(), attr.clone()));
+                    }
+                }
+            }
+        }
+        for element in &baseline.elements {
+            if current.get(&element.path).is_none() {
+                drift.removed_elements.push(element.path.clone());
+            }
+        }
+        drift
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.new_elements.is_empty()
+            && self.removed_elements.is_empty()
+            && self.new_attributes.is_empty()
+            && self.removed_attributes.is_empty()
+    }
+
+    /// Writes one `+`/`-` line per change.
+    pub fn write_report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.is_empty() {
+            return writeln!(out, "No schema drift.");
+        }
+        for path in &self.new_elements {
+            writeln!(out, "+ element   {}", path)?;
+        }
+        for path in &self.removed_elements {
+            writeln!(out, "- element   {}", path)?;
+        }
+        for (path, attr) in &self.new_attributes {
+            writeln!(out, "+ attribute {}/@{}", path, attr)?;
+        }
+        for (path, attr) in &self.removed_attributes {
+            writeln!(out, "- attribute {}/@{}", path, attr)?;
+        }
+        Ok(())
+    }
+}
+
+/// How often elements named `child` occur directly inside elements named `parent`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cardinality {
+    pub parent: String,
+    pub child: String,
+    /// Number of `parent` elements seen.
+    pub parents: u64,
+    /// Parents containing no, exactly one, and several `child` elements.
+    pub zero: u64,
+    pub one: u64,
+    pub many: u64,
+    pub max: u64,
+}
+
+impl Cardinality {
+    /// Counts with one streaming pass over `xml`.
+    pub fn count(xml: &str, parent: &str, child: &str) -> Self {
+        let mut result = Cardinality {
+            parent: parent.to_string(),
+            child: child.to_string(),
+            ..Default::default()
+        };
+        // Per open element: its `child` count if it is a `parent`.
+        let mut stack: Vec<Option<u64>> = Vec::new();
+        stream_xml_spans(xml, |token, _| {
+            match token {
+                Token::StartTag(name, _) => {
+                    if name == child
+                        && let Some(Some(n)) = stack.last_mut()
+                    {
+                        *n += 1;
+                    }
+                    stack.push((name == parent).then_some(0));
+                }
+                Token::EndTag(_) => {
+                    if let Some(Some(n)) = stack.pop() {
+                        result.parents += 1;
+                        result.max = result.max.max(n);
+                        match n {
+                            0 => result.zero += 1,
+                            1 => result.one += 1,
+                            _ => result.many += 1,
+                        }
+                    }
+                }
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            Continue(())
+        });
+        result
+    }
+}
@@ -0,0 +1,169 @@
+//! Sketches: summaries of value streams in fixed memory, for counts that
+//! would otherwise need every value kept.
+
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash};
+
+/// Bits of the hash choosing a register.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// The standard error of `HyperLogLog::estimate`, as a fraction.
+pub const DISTINCT_ERROR: f64 = 1.04 / 64.0;
+
+/// A HyperLogLog estimate of the number of distinct values, in 4 KiB
+/// whatever the number of values, within about 1.6% (`DISTINCT_ERROR`).
+/// Small counts are close to exact.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog { registers: vec![0; REGISTERS] }
+    }
+}
+
+impl HyperLogLog {
+    pub fn insert(&mut self, value: &(impl Hash + ?Sized)) {
+        // The default hasher with its fixed keys, so estimates are the same
+        // from run to run.
+        let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(value);
+        let register = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+        let raw = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        // Linear counting is more accurate while many registers are empty.
+        let estimate = if raw <= 2.5 * m && empty > 0 { m * (m / empty as f64).ln() } else { raw };
+        estimate.round() as u64
+    }
+
+    /// Adds the values `other` has seen, as if inserted here.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (rank, &other) in self.registers.iter_mut().zip(&other.registers) {
+            *rank = (*rank).max(other);
+        }
+    }
+}
+
+/// A tracked key of `SpaceSaving`.
+#[derive(Debug, Clone)]
+pub struct Counter<K, T> {
+    pub key: K,
+    /// Occurrences counted, over by at most `error`.
+    pub count: u64,
+    /// The count of the key this one replaced, which it inherited.
+    pub error: u64,
+    pub payload: T,
+}
+
+/// The space-saving sketch of the most frequent keys: a fixed number of
+/// counters, where a new key replaces the least counted one and inherits
+/// its count as a possible overcount. Any key occurring more than n/capacity
+/// times in n insertions is kept, each with a payload for what else is
+/// summarised about it.
+#[derive(Debug, Clone)]
+pub struct SpaceSaving<K, T> {
+    capacity: usize,
+    counters: Vec<Counter<K, T>>,
+    slots: HashMap<K, usize>,
+    /// Counters by count, for the least counted one.
+    by_count: BTreeSet<(u64, usize)>,
+}
+
+impl<K: Hash + Eq + Clone, T: Default> SpaceSaving<K, T> {
+    pub fn new(capacity: usize) -> Self {
+        SpaceSaving {
+            capacity: capacity.max(1),
+            counters: Vec::new(),
+            slots: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    /// Counts an occurrence of `key`, returning its payload; a key that
+    /// replaced another starts with a default payload.
+    pub fn insert(&mut self, key: &K) -> &mut T {
+        let slot = match self.slots.get(key) {
+            Some(&slot) => slot,
+            None if self.counters.len() < self.capacity => {
+                self.counters.push(Counter {
+                    key: key.clone(),
+                    count: 0,
+                    error: 0,
+                    payload: T::default(),
+                });
+                self.by_count.insert((0, self.counters.len() - 1));
+                self.slots.insert(key.clone(), self.counters.len() - 1);
+                self.counters.len() - 1
+            }
+            None => {
+                let &(count, slot) = self.by_count.first().expect("a full sketch has counters");
+                let counter = &mut self.counters[slot];
+                self.slots.remove(&counter.key);
+                counter.key = key.clone();
+                counter.error = count;
+                counter.payload = T::default();
+                self.slots.insert(key.clone(), slot);
+                slot
+            }
+        };
+        let counter = &mut self.counters[slot];
+        self.by_count.remove(&(counter.count, slot));
+        counter.count += 1;
+        self.by_count.insert((counter.count, slot));
+        &mut counter.payload
+    }
+
+    /// The count a key not tracked may have at most.
+    fn untracked(&self) -> u64 {
+        if self.counters.len() < self.capacity { 0 } else { self.by_count.first().map_or(0, |&(count, _)| count) }
+    }
+
+    /// Adds the keys `other` has counted, combining the payloads of keys
+    /// both track with `merge_payload`. A key only one tracks may have
+    /// occurred as often as the other's least counted key, which is added to
+    /// its count and error; the most counted keys are then kept.
+    pub fn merge(&mut self, other: SpaceSaving<K, T>, mut merge_payload: impl FnMut(&mut T, T)) {
+        let (self_untracked, other_untracked) = (self.untracked(), other.untracked());
+        let mut counters = std::mem::take(&mut self.counters);
+        for counter in &mut counters {
+            counter.count += other_untracked;
+            counter.error += other_untracked;
+        }
+        let mut slots = std::mem::take(&mut self.slots);
+        for mut counter in other.counters {
+            match slots.get(&counter.key) {
+                Some(&slot) => {
+                    let existing = &mut counters[slot];
+                    existing.count = existing.count - other_untracked + counter.count;
+                    existing.error = existing.error - other_untracked + counter.error;
+                    merge_payload(&mut existing.payload, counter.payload);
+                }
+                None => {
+                    counter.count += self_untracked;
+                    counter.error += self_untracked;
+                    slots.insert(counter.key.clone(), counters.len());
+                    counters.push(counter);
+                }
+            }
+        }
+        counters.sort_by_key(|counter| std::cmp::Reverse(counter.count));
+        counters.truncate(self.capacity);
+        self.slots = counters.iter().enumerate().map(|(slot, counter)| (counter.key.clone(), slot)).collect();
+        self.by_count = counters.iter().enumerate().map(|(slot, counter)| (counter.count, slot)).collect();
+        self.counters = counters;
+    }
+
+    /// The `k` most counted keys, most first.
+    pub fn top(&self, k: usize) -> Vec<&Counter<K, T>> {
+        self.by_count.iter().rev().take(k).map(|&(_, slot)| &self.counters[slot]).collect()
+    }
+}
@@ -0,0 +1,91 @@
+//! Recognises input that is not XML from its first bytes, so that a JSON
+//! file, an archive or an image is reported as such instead of failing with
+//! an empty tree or an encoding error deep into the file.
+//!
+//! Compressed input is unpacked before it is sniffed, so what is sniffed is
+//! always the document itself.
+
+use memchr::memchr;
+
+/// What non-XML input looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// HTML that is not XHTML: usually parses, but seldom well-formed (void
+    /// elements, unquoted attributes), so it is a warning rather than an error.
+    Html,
+    /// A known binary format, by name, with a hint on getting XML out of it.
+    Binary(&'static str, &'static str),
+    /// Other bytes that are not text.
+    UnknownBinary,
+    /// Text without any markup.
+    PlainText,
+}
+
+/// Magic numbers of binary formats people open by mistake.
+const MAGIC: [(&[u8], &str, &str); 9] = [
+    (b"PK\x03\x04", "a ZIP archive", "Office documents and EPUBs are ZIP files of XML parts; extract one, e.g. `unzip -p file.docx word/document.xml | xmz stats -`"),
+    (b"\xfd7zXZ\x00", "xz-compressed data", "decompress it first: `xz -dc file.xz | xmz stats -`"),
+    (b"7z\xbc\xaf\x27\x1c", "a 7-Zip archive", "extract the XML file from it first"),
+    (b"Rar!\x1a\x07", "a RAR archive", "extract the XML file from it first"),
+    (b"%PDF-", "a PDF document", ""),
+    (b"\x89PNG\r\n\x1a\n", "a PNG image", ""),
+    (b"\xff\xd8\xff", "a JPEG image", ""),
+    (b"\x7fELF", "an executable", ""),
+    (b"SQLite format 3\x00", "an SQLite database", ""),
+];
+
+impl Format {
+    /// The explanation shown to the user.
+    pub fn describe(&self) -> String {
+        match self {
+            Format::Json => "this looks like JSON, not XML".to_string(),
+            Format::Html => "this looks like HTML rather than XML; elements without end tags such as <br> will look misnested (--html reads it leniently)".to_string(),
+            Format::Binary(name, "") => format!("this is {}, not XML", name),
+            Format::Binary(name, hint) => format!("this is {}, not XML; {}", name, hint),
+            Format::UnknownBinary => "this looks like binary data, not XML (use --lossy to read it as damaged text anyway)".to_string(),
+            Format::PlainText => "this is plain text without any XML markup".to_string(),
+        }
+    }
+
+    /// Whether the input should be refused; HTML is only warned about.
+    pub fn is_fatal(&self) -> bool {
+        *self != Format::Html
+    }
+}
+
+/// Looks at the start of UTF-8 input (after any byte order mark) and says
+/// what it is if it is clearly not XML. With `lossy`, stray control bytes are
+/// allowed, as damaged text is expected.
+pub fn sniff(prefix: &[u8], lossy: bool) -> Option<Format> {
+    if prefix.is_empty() {
+        return None;
+    }
+    if let Some(&(_, name, hint)) = MAGIC.iter().find(|(magic, _, _)| prefix.starts_with(magic)) {
+        return Some(Format::Binary(name, hint));
+    }
+    if prefix.len() > 262 && &prefix[257..262] == b"ustar" {
+        return Some(Format::Binary("a tar archive", "extract the XML file first: `tar -xOf file.tar member.xml | xmz stats -`"));
+    }
+    // A stray invalid byte is damage, reported when decoding; many, or
+    // control bytes, mean the input is not text at all.
+    let invalid = prefix.utf8_chunks().filter(|chunk| !chunk.invalid().is_empty()).count();
+    let controls = prefix.iter().filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r')).count();
+    if !lossy && (memchr(0, prefix).is_some() || (invalid + controls) * 10 > prefix.len()) {
+        return Some(Format::UnknownBinary);
+    }
+    let text = prefix.utf8_chunks().next().map_or("", |chunk| chunk.valid());
+    let start = text.trim_start();
+    if start.starts_with(['{', '[']) {
+        return Some(Format::Json);
+    }
+    let lower = start.get(..start.len().min(512)).unwrap_or(start).to_ascii_lowercase();
+    let html = lower.starts_with("<!doctype html") || lower.starts_with("<html") || lower.contains("<head>");
+    if html && !lower.contains("http://www.w3.org/1999/xhtml") {
+        return Some(Format::Html);
+    }
+    if !text.is_empty() && !text.contains('<') {
+        return Some(Format::PlainText);
+    }
+    None
+}
@@ -0,0 +1,240 @@
+//! Opening input documents.
+//!
+//! Plain files are memory-mapped. Compressed files are recognised by their
+//! magic bytes, so a mislabelled extension does not matter: gzip is decoded
+//! in-process, zstd and bzip2 through the `zstd`/`bzip2` tools. `-` means
+//! standard input. Text is converted to UTF-8 by `crate::encoding`.
+//!
+//! `set_max_rate` limits how fast files are read, so scans and index builds
+//! over shared network storage leave bandwidth for others. Throttled files
+//! are read rather than mapped, since page faults on a mapping cannot be
+//! paced.
+
+use crate::encoding::{Replacements, decode, decode_lossy, decoding_reader};
+use crate::inflate::GzDecoder;
+use crate::sandbox::{self, Capability};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Deref;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Read limit in bytes per second; 0 means unlimited.
+static MAX_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Limits reads of input files to `bytes_per_second` for the rest of the
+/// process (0 lifts the limit).
+pub fn set_max_rate(bytes_per_second: u64) {
+    MAX_RATE.store(bytes_per_second, Ordering::SeqCst);
+}
+
+fn max_rate() -> Option<u64> {
+    Some(MAX_RATE.load(Ordering::SeqCst)).filter(|&rate| rate > 0)
+}
+
+/// A reader that sleeps as needed to stay under `rate` bytes per second.
+struct Throttled<R> {
+    inner: R,
+    rate: u64,
+    start: Instant,
+    read: u64,
+}
+
+impl<R: Read> Throttled<R> {
+    fn new(inner: R, rate: u64) -> Self {
+        Self {
+            inner,
+            rate,
+            start: Instant::now(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Small reads keep the pace even instead of bursting and sleeping long.
+        let slice = (self.rate / 20).clamp(512, buf.len().max(512) as u64) as usize;
+        let len = buf.len().min(slice);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.read += n as u64;
+        let due = Duration::from_secs_f64(self.read as f64 / self.rate as f64);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        Ok(n)
+    }
+}
+
+/// Opens `path` for reading, throttled when a rate limit is set.
+fn open_file(path: &str) -> io::Result<Box<dyn Read + Send>> {
+    let file = File::open(path)?;
+    Ok(match max_rate() {
+        Some(rate) => Box::new(Throttled::new(file, rate)),
+        None => Box::new(file),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    pub fn from_magic(bytes: &[u8]) -> Self {
+        match bytes {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+
+    /// External decompressor for formats not decoded in-process.
+    fn command(self) -> Option<&'static str> {
+        match self {
+            Compression::Zstd => Some("zstd"),
+            Compression::Bzip2 => Some("bzip2"),
+            Compression::None | Compression::Gzip => None,
+        }
+    }
+}
+
+/// The bytes of a document: mapped from disk, or decompressed/read into memory.
+pub enum Document {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Document {
+    /// The document as UTF-8 text, transcoded from UTF-16 or a legacy
+    /// encoding when necessary.
+    pub fn text(&self) -> io::Result<Cow<'_, str>> {
+        decode(self)
+    }
+
+    /// Like `text`, but replaces invalid sequences instead of failing; also
+    /// returns how many were replaced.
+    pub fn text_lossy(&self) -> io::Result<(Cow<'_, str>, usize)> {
+        decode_lossy(self)
+    }
+}
+
+impl Deref for Document {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Document::Mapped(mmap) => mmap,
+            Document::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Reads the compression magic at the start of `file` without consuming it.
+fn sniff_file(file: &mut File) -> io::Result<Compression> {
+    use std::io::{Seek, SeekFrom};
+    let mut magic = [0u8; 4];
+    let mut len = 0;
+    while len < magic.len() {
+        match file.read(&mut magic[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(Compression::from_magic(&magic[..len]))
+}
+
+/// Returns the compression of the file at `path` (`None` for standard input).
+pub fn compression(path: &str) -> io::Result<Compression> {
+    if path == "-" {
+        return Ok(Compression::None);
+    }
+    sniff_file(&mut File::open(path)?)
+}
+
+/// Opens `path` (or standard input for `-`) as a stream of decompressed UTF-8
+/// text, replacing and counting invalid sequences if `lossy` is given.
+pub fn open_reader(path: &str, lossy: Option<&Replacements>) -> io::Result<Box<dyn Read + Send>> {
+    decoding_reader(open_decompressed(path)?, lossy)
+}
+
+/// Opens `path` as a stream of decompressed bytes in the document's own encoding.
+fn open_decompressed(path: &str) -> io::Result<Box<dyn Read + Send>> {
+    if path == "-" {
+        let mut input = BufReader::new(io::stdin());
+        return match Compression::from_magic(input.fill_buf()?) {
+            Compression::None => Ok(Box::new(input)),
+            Compression::Gzip => Ok(Box::new(GzDecoder::new(input))),
+            other => spawn_decompressor(other, Stdio::piped(), Some(Box::new(input))),
+        };
+    }
+    let compression = sniff_file(&mut File::open(path)?)?;
+    match compression {
+        Compression::None => open_file(path),
+        Compression::Gzip => Ok(Box::new(GzDecoder::new(open_file(path)?))),
+        // Throttled input is fed through a pipe so the limit applies to the tool too.
+        other if max_rate().is_some() => spawn_decompressor(other, Stdio::piped(), Some(open_file(path)?)),
+        other => spawn_decompressor(other, Stdio::from(File::open(path)?), None),
+    }
+}
+
+/// Runs the external tool for `compression` on `stdin`, copying `feed` into it
+/// from a background thread when the input is not a file.
+fn spawn_decompressor(compression: Compression, stdin: Stdio, feed: Option<Box<dyn Read + Send>>) -> io::Result<Box<dyn Read + Send>> {
+    let program = compression.command().expect("decoded in-process");
+    sandbox::check(Capability::RunCommands)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}-compressed input: {}", program, err)))?;
+    let mut child = Command::new(program)
+        .arg("-dc")
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| io::Error::new(err.kind(), format!("{}-compressed input needs the `{}` tool: {}", program, program, err)))?;
+    if let (Some(mut feed), Some(mut child_stdin)) = (feed, child.stdin.take()) {
+        std::thread::spawn(move || io::copy(&mut feed, &mut child_stdin));
+    }
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Box::new(ChildReader { program, child, stdout }))
+}
+
+/// Output of an external decompressor; its exit status is checked at the end.
+struct ChildReader {
+    program: &'static str,
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("{} failed to decompress the input ({})", self.program, status)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Loads a whole document: plain files are mapped, anything else (and any
+/// throttled file) is read and decompressed into memory.
+pub fn load(path: &str) -> io::Result<Document> {
+    if path != "-" && max_rate().is_none() {
+        let mut file = File::open(path)?;
+        if sniff_file(&mut file)? == Compression::None {
+            return unsafe { Mmap::map(&file) }.map(Document::Mapped);
+        }
+    }
+    let mut bytes = Vec::new();
+    open_decompressed(path)?.read_to_end(&mut bytes)?;
+    Ok(Document::Owned(bytes))
+}
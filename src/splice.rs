@@ -0,0 +1,219 @@
+//! Byte-span edits: new values spliced into a document in place of the old
+//! ones, with every other byte kept as written, so changing one value does
+//! not reformat the file around it.
+
+use crate::parser::{Break, Continue, Token, attribute_pairs, stream_xml_spans};
+use crate::sandbox::{self, Capability};
+use crate::transform::escape;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Replaces the bytes in `span` with `replacement`; an empty span inserts it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Splice {
+    pub span: Range<usize>,
+    pub replacement: String,
+}
+
+/// A document with edits spliced in, which still finds the bytes of the text
+/// it was loaded with.
+#[derive(Debug, Clone)]
+pub struct Edited {
+    text: String,
+    /// The edits made, in order, each by offsets in the text before it.
+    splices: Vec<Splice>,
+}
+
+impl Edited {
+    pub fn new(text: String) -> Self {
+        Edited { text, splices: Vec::new() }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// How many edits were made.
+    pub fn edits(&self) -> usize {
+        self.splices.len()
+    }
+
+    /// Makes `splice`, by offsets in `text()`.
+    pub fn apply(&mut self, splice: Splice) {
+        self.text.replace_range(splice.span.clone(), &splice.replacement);
+        self.splices.push(splice);
+    }
+
+    /// Where byte `offset` of the text as loaded is now. Bytes an edit
+    /// replaced are at its start.
+    pub fn offset(&self, mut offset: usize) -> usize {
+        for splice in &self.splices {
+            if offset >= splice.span.end {
+                offset = offset - splice.span.len() + splice.replacement.len();
+            } else if offset > splice.span.start {
+                offset = splice.span.start;
+            }
+        }
+        offset
+    }
+}
+
+/// The splice setting attribute `name` of the element whose start tag is at
+/// `start` to `value`: the value between its quotes, or a new attribute
+/// after the others when the element has none of that name.
+pub fn set_attribute(xml: &str, start: usize, name: &str, value: &str) -> Option<Splice> {
+    let mut splice = None;
+    stream_xml_spans(&xml[start..], |token, _| {
+        if let Token::StartTag(tag, attrs) = token {
+            splice = Some(match attribute_pairs(attrs).into_iter().find(|(key, _)| *key == name) {
+                Some((_, raw)) => {
+                    let from = raw.as_ptr() as usize - xml.as_ptr() as usize;
+                    // The quote before the value is the one that needs escaping.
+                    let quote = xml.as_bytes()[from - 1];
+                    Splice {
+                        span: from..from + raw.len(),
+                        replacement: escape_attribute(value, quote),
+                    }
+                }
+                None => {
+                    // After the last attribute, or the tag name.
+                    let written = attrs.trim_end_matches(|c: char| c.is_whitespace() || c == '/');
+                    let at = if written.is_empty() { start + 1 + tag.len() } else { written.as_ptr() as usize - xml.as_ptr() as usize + written.len() };
+                    Splice {
+                        span: at..at,
+                        replacement: format!(" {}=\"{}\"", name, escape_attribute(value, b'"')),
+                    }
+                }
+            });
+        }
+        Break(())
+    });
+    splice
+}
+
+fn escape_attribute(value: &str, quote: u8) -> String {
+    let escaped = escape(value);
+    if quote == b'\'' { escaped.replace('\'', "&apos;") } else { escaped }
+}
+
+/// The splice setting the text of the element at `start` to `text`, or None
+/// when it has child elements. An empty element such as `<price/>` is
+/// opened up into `<price>text</price>`.
+pub fn set_text(xml: &str, start: usize, text: &str) -> Option<Splice> {
+    let mut depth = 0;
+    let mut tag = "";
+    let mut content_start = 0;
+    let mut splice = None;
+    stream_xml_spans(&xml[start..], |token, span| {
+        match token {
+            Token::StartTag(name, _) if depth == 0 => {
+                depth = 1;
+                tag = name;
+                content_start = span.end;
+            }
+            Token::StartTag(..) => return Break(()),
+            Token::EndTag(_) if span.is_empty() => {
+                // Self-closing: the start tag ends in "/>".
+                splice = Some(Splice {
+                    span: start + content_start - 2..start + content_start,
+                    replacement: format!(">{}</{}>", escape(text), tag),
+                });
+                return Break(());
+            }
+            Token::EndTag(_) => {
+                splice = Some(Splice {
+                    span: start + content_start..start + span.start,
+                    replacement: escape(text),
+                });
+                return Break(());
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    splice
+}
+
+/// The line break and indentation before `start` when nothing else comes
+/// before it on its line, else the empty range at `start`.
+fn line_lead(xml: &str, start: usize) -> Range<usize> {
+    let indent = xml[..start].trim_end_matches([' ', '\t']).len();
+    match xml[..indent].strip_suffix('\n') {
+        Some(rest) => rest.strip_suffix('\r').unwrap_or(rest).len()..start,
+        None => start..start,
+    }
+}
+
+/// The splice removing the element at `span`, with its line when it is on
+/// one of its own.
+pub fn delete(xml: &str, span: Range<usize>) -> Splice {
+    Splice { span: line_lead(xml, span.start).start..span.end, replacement: String::new() }
+}
+
+/// The splice adding `copies` copies of the element at `span` after it,
+/// indented like it when it starts a line.
+pub fn duplicate(xml: &str, span: Range<usize>, copies: usize) -> Splice {
+    let copy = format!("{}{}", &xml[line_lead(xml, span.start)], &xml[span.clone()]);
+    Splice { span: span.end..span.end, replacement: copy.repeat(copies) }
+}
+
+/// The splice adding `fragment` before the element at `span`, on a line of
+/// its own when the element starts one.
+pub fn insert_before(xml: &str, span: Range<usize>, fragment: &str) -> Splice {
+    let lead = &xml[line_lead(xml, span.start)];
+    Splice { span: span.start..span.start, replacement: format!("{}{}", fragment, lead) }
+}
+
+/// The splice adding `fragment` after the element at `span`, on a line of
+/// its own when the element starts one.
+pub fn insert_after(xml: &str, span: Range<usize>, fragment: &str) -> Splice {
+    let lead = &xml[line_lead(xml, span.start)];
+    Splice { span: span.end..span.end, replacement: format!("{}{}", lead, fragment) }
+}
+
+/// The splice removing attribute `name`, and the whitespace before it, from
+/// the element whose start tag is at `start`, or None when it has none of
+/// that name.
+pub fn remove_attribute(xml: &str, start: usize, name: &str) -> Option<Splice> {
+    let mut splice = None;
+    stream_xml_spans(&xml[start..], |token, _| {
+        if let Token::StartTag(_, attrs) = token
+            && let Some((key, raw)) = attribute_pairs(attrs).into_iter().find(|(key, _)| *key == name)
+        {
+            let from = key.as_ptr() as usize - xml.as_ptr() as usize;
+            let from = xml[..from].trim_end().len();
+            // Past the closing quote.
+            let to = raw.as_ptr() as usize - xml.as_ptr() as usize + raw.len() + 1;
+            splice = Some(Splice { span: from..to, replacement: String::new() });
+        }
+        Break(())
+    });
+    splice
+}
+
+/// Where edits go unless they are written back: `catalog.xml` becomes
+/// `catalog.edited.xml`.
+pub fn copy_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.edited.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.edited", stem),
+    };
+    path.with_file_name(name)
+}
+
+/// Writes `text` to `path` through a temporary file beside it, so the file
+/// is never seen half written, keeping the permissions of the file it
+/// replaces.
+pub fn write(path: &Path, text: &str) -> io::Result<()> {
+    sandbox::check(Capability::WriteFiles)?;
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(".{}.tmp", std::process::id()));
+    let partial = PathBuf::from(partial);
+    std::fs::write(&partial, text)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        std::fs::set_permissions(&partial, metadata.permissions())?;
+    }
+    std::fs::rename(&partial, path)
+}
@@ -1,95 +1,580 @@
-use crate::parser::{stream_xml, Token, Continue};
+use crate::du::format_bytes;
+use crate::profile::{Profile, Summary};
+use crate::progress::Progress;
+use crate::encoding::Replacements;
+use crate::interrupt;
+use memchr::memchr;
+use crate::parser::{decode_entities, stream_xml_reader_spans, stream_xml_spans, Token, Break, Continue};
 use crossterm::{
+    cursor::MoveTo,
     execute,
     style::{Color, Print, ResetColor, SetForegroundColor, Attribute, SetAttribute},
+    terminal::{Clear, ClearType},
 };
-use std::io::stdout;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::File;
+use std::cell::Cell;
+use std::io::{self, stdout, IsTerminal, Read, Seek, SeekFrom};
+use std::ops::{ControlFlow, Range};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-const MAX_DEPTH: usize = 32;
-const MAX_UNIQUE_TAGS: usize = 128;
+/// Nesting levels and tag names per level that earlier versions counted;
+/// documents beyond them are pointed out, since older reports were cut short.
+const FORMER_MAX_DEPTH: usize = 32;
+const FORMER_MAX_UNIQUE_TAGS: usize = 128;
+/// Tag names listed per level; the rest are only counted.
+const SHOWN_TAGS: usize = 128;
+const MAX_UNIQUE_ATTRIBUTES: usize = 256;
+const MAX_UNIQUE_HOSTS: usize = 1024;
+/// Hosts listed in the report, most referenced first.
+const SHOWN_HOSTS: usize = 20;
+/// Attribute names listed as the most frequent.
+const SHOWN_ATTRIBUTES: usize = 5;
+/// Largest subtrees listed, below the root.
+const LARGEST_SUBTREES: usize = 10;
 
-pub fn print_stats(xml: &str) {
-    let mut depth: usize = 0;
-    let mut max_depth: usize = 0;
-    let mut tag_count = 0;
-    let mut elements_per_level = [0usize; MAX_DEPTH];
-    let mut unique_tags_per_level: [[Option<&str>; MAX_UNIQUE_TAGS]; MAX_DEPTH] = [[None; MAX_UNIQUE_TAGS]; MAX_DEPTH];
-    let mut unique_tag_counts = [0usize; MAX_DEPTH];
+/// Upper bounds (inclusive) of the value length buckets; longer values go
+/// in a last bucket.
+const LENGTH_BUCKETS: [usize; 6] = [0, 8, 32, 128, 1024, 8192];
 
-    let start_time = std::time::Instant::now();
+/// Local attribute names whose values are URI references in common
+/// vocabularies (HTML, SVG, XLink, Atom, RSS enclosures).
+const URI_ATTRIBUTES: [&str; 13] = [
+    "href", "src", "action", "cite", "data", "poster", "background", "codebase", "longdesc", "usemap", "formaction", "icon", "url",
+];
 
-    stream_xml(xml, |token| {
+/// Value lengths (in characters) seen for one attribute name.
+#[derive(Default)]
+struct AttributeStats {
+    count: usize,
+    min_len: usize,
+    max_len: usize,
+    total_len: usize,
+    /// Values that are URI references: absolute with a host, and others.
+    uris_with_host: usize,
+    uris_without_host: usize,
+}
+
+impl AttributeStats {
+    /// Counts a value of `len` characters, and `host` when it is a URI
+    /// reference: `Some(None)` for one without a host.
+    fn add(&mut self, len: usize, host: Option<Option<&str>>) {
+        self.min_len = if self.count == 0 { len } else { self.min_len.min(len) };
+        self.max_len = self.max_len.max(len);
+        self.total_len += len;
+        self.count += 1;
+        match host {
+            Some(Some(_)) => self.uris_with_host += 1,
+            Some(None) => self.uris_without_host += 1,
+            None => {}
+        }
+    }
+}
+
+/// Whether the attribute `name` holding `value` refers to a resource: it is
+/// named like one (`href`, `xlink:href`, `src`, ...) or holds a `scheme://` URL.
+/// Namespace declarations are names, not references, and are left out.
+pub(crate) fn is_uri_attribute(name: &str, value: &str) -> bool {
+    !is_namespace(name) && (named_like_uri(name) || uri_host(value).is_some())
+}
+
+fn is_namespace(name: &str) -> bool {
+    name == "xmlns" || name.starts_with("xmlns:")
+}
+
+fn named_like_uri(name: &str) -> bool {
+    let local = name.rsplit(':').next().unwrap_or(name);
+    URI_ATTRIBUTES.contains(&local) || name.starts_with("xlink:")
+}
+
+/// The host of an absolute (`https://host/...`) or protocol-relative
+/// (`//host/...`) URL, as written; hosts are the same in any case.
+pub(crate) fn uri_host(value: &str) -> Option<&str> {
+    let value = value.trim();
+    // A scheme ends at the first colon.
+    let rest = match memchr(b':', value.as_bytes()) {
+        Some(colon)
+            if colon > 0
+                && value[colon + 1..].starts_with("//")
+                && value[..colon].bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'+' | b'-' | b'.')) =>
+        {
+            &value[colon + 3..]
+        }
+        _ => value.strip_prefix("//")?,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit('@').next().unwrap_or(authority);
+    let host = match host_port.find(']') {
+        // IPv6 literal: keep the brackets, drop the port.
+        Some(end) if host_port.starts_with('[') => &host_port[..=end],
+        _ => host_port.split(':').next().unwrap_or(host_port),
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+/// Counters gathered while streaming a document.
+struct Collector {
+    depth: usize,
+    max_depth: usize,
+    tag_count: usize,
+    /// Counts per nesting level, the root's first.
+    levels: Vec<Level>,
+    attributes: HashMap<String, AttributeStats>,
+    /// Attribute values seen, by length bucket.
+    value_lengths: [usize; LENGTH_BUCKETS.len() + 1],
+    hosts: HashMap<String, usize>,
+    /// References to hosts beyond `MAX_UNIQUE_HOSTS` distinct ones.
+    other_host_refs: usize,
+    /// A host lowercased, reused from one attribute to the next.
+    lowercase: String,
+    /// Attributes on all elements, including names beyond `MAX_UNIQUE_ATTRIBUTES`.
+    attribute_count: usize,
+    /// Bytes of text, surrounding whitespace excluded, and the text nodes holding it.
+    text_bytes: u64,
+    text_nodes: usize,
+    /// Elements with neither children nor text.
+    empty_elements: usize,
+    /// Elements with children, and the children they have between them.
+    parents: usize,
+    children: usize,
+    /// The element with the most children: how many, its name and offset.
+    most_children: (usize, String, u64),
+    /// The largest subtrees below the root by size in bytes, smallest on top.
+    largest: BinaryHeap<Reverse<(u64, u64, String)>>,
+    open: Vec<OpenElement>,
+    /// What a GPX or KML document holds, when the root is one of those.
+    profile: Option<Summary>,
+}
+
+/// Elements and distinct tag names at one nesting level.
+#[derive(Default)]
+struct Level {
+    elements: usize,
+    tags: HashSet<String>,
+}
+
+/// An element whose end tag is still to come.
+struct OpenElement {
+    start: u64,
+    children: usize,
+    text: bool,
+}
+
+impl Collector {
+    fn new() -> Self {
+        Self {
+            depth: 0,
+            max_depth: 0,
+            tag_count: 0,
+            levels: Vec::new(),
+            attributes: HashMap::new(),
+            value_lengths: [0; LENGTH_BUCKETS.len() + 1],
+            hosts: HashMap::new(),
+            other_host_refs: 0,
+            lowercase: String::new(),
+            attribute_count: 0,
+            text_bytes: 0,
+            text_nodes: 0,
+            empty_elements: 0,
+            parents: 0,
+            children: 0,
+            most_children: (0, String::new(), 0),
+            largest: BinaryHeap::new(),
+            open: Vec::new(),
+            profile: None,
+        }
+    }
+
+    fn on_attribute(&mut self, name: &str, raw: &str) {
+        self.attribute_count += 1;
+        let value = decode_entities(raw);
+        let len = value.chars().count();
+        let bucket = LENGTH_BUCKETS.iter().position(|&max| len <= max).unwrap_or(LENGTH_BUCKETS.len());
+        self.value_lengths[bucket] += 1;
+        // The host, when the value is a URI reference.
+        let host = if is_namespace(name) {
+            None
+        } else {
+            let host = uri_host(&value);
+            (host.is_some() || named_like_uri(name)).then_some(host)
+        };
+        // Only a name not seen before is copied.
+        let full = self.attributes.len() >= MAX_UNIQUE_ATTRIBUTES;
+        match self.attributes.get_mut(name) {
+            Some(stats) => stats.add(len, host),
+            None if !full => {
+                let mut stats = AttributeStats::default();
+                stats.add(len, host);
+                self.attributes.insert(name.to_string(), stats);
+            }
+            None => return,
+        }
+        let Some(Some(host)) = host else {
+            return;
+        };
+        let host = if host.bytes().any(|b| b.is_ascii_uppercase()) {
+            self.lowercase.clear();
+            self.lowercase.extend(host.chars().map(|c| c.to_ascii_lowercase()));
+            self.lowercase.as_str()
+        } else {
+            host
+        };
+        // Likewise only a host not seen before.
+        if let Some(refs) = self.hosts.get_mut(host) {
+            *refs += 1;
+        } else if self.hosts.len() < MAX_UNIQUE_HOSTS {
+            self.hosts.insert(host.to_string(), 1);
+        } else {
+            self.other_host_refs += 1;
+        }
+    }
+
+    /// Counts `token`, which spans `span` in the input.
+    fn on_token(&mut self, token: Token, span: Range<u64>) -> ControlFlow<()> {
+        if let Token::StartTag(name, _) = token
+            && self.tag_count == 0
+        {
+            self.profile = Profile::detect(name).map(Summary::new);
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.on_token(&token);
+        }
         match token {
-            Token::StartTag(name, _) => {
-                if depth < MAX_DEPTH {
-                    elements_per_level[depth] += 1;
-                    let tags = unsafe { unique_tags_per_level.get_unchecked_mut(depth) };
-                    let count = unsafe { unique_tag_counts.get_unchecked_mut(depth) };
-                    let mut found = false;
-                    let name_ptr = name.as_ptr();
-                    let name_len = name.len();
-                    for i in 0..*count {
-                        if let Some(existing) = unsafe { *tags.get_unchecked(i) } {
-                            if existing.as_ptr() == name_ptr && existing.len() == name_len {
-                                found = true;
-                                break;
-                            }
-                            if existing == name {
-                                found = true;
-                                break;
-                            }
-                        }
-                    }
-                    if !found && *count < MAX_UNIQUE_TAGS {
-                        unsafe {
-                            *tags.get_unchecked_mut(*count) = Some(name);
-                        }
-                        *count += 1;
-                    }
+            Token::StartTag(name, attrs) => {
+                for (attr, value) in xmz_core::attributes(attrs) {
+                    self.on_attribute(attr, value);
                 }
-                depth += 1;
-                max_depth = max_depth.max(depth);
-                tag_count += 1;
+                if let Some(parent) = self.open.last_mut() {
+                    parent.children += 1;
+                }
+                self.open.push(OpenElement { start: span.start, children: 0, text: false });
+                if self.depth == self.levels.len() {
+                    self.levels.push(Level::default());
+                }
+                let level = &mut self.levels[self.depth];
+                level.elements += 1;
+                // Only a name not seen before at this level is copied.
+                if !level.tags.contains(name) {
+                    level.tags.insert(name.to_string());
+                }
+                self.depth += 1;
+                self.max_depth = self.max_depth.max(self.depth);
+                self.tag_count += 1;
             }
-            Token::EndTag(_) => {
-                depth = depth.saturating_sub(1);
-                tag_count += 1;
+            Token::EndTag(name) => {
+                self.depth = self.depth.saturating_sub(1);
+                self.tag_count += 1;
+                if let Some(element) = self.open.pop() {
+                    self.on_element_end(name, element, span.end);
+                }
+            }
+            Token::Text(text) | Token::CData(text) => {
+                self.text_bytes += text.len() as u64;
+                self.text_nodes += 1;
+                if let Some(element) = self.open.last_mut() {
+                    element.text = true;
+                }
             }
-            Token::Text(_) => {}
         }
         Continue(())
+    }
+
+    fn on_element_end(&mut self, name: &str, element: OpenElement, end: u64) {
+        if element.children == 0 {
+            if !element.text {
+                self.empty_elements += 1;
+            }
+        } else {
+            self.parents += 1;
+            self.children += element.children;
+            if element.children > self.most_children.0 {
+                self.most_children = (element.children, name.to_string(), element.start);
+            }
+        }
+        if self.open.is_empty() {
+            return;
+        }
+        let size = end - element.start;
+        if self.largest.len() < LARGEST_SUBTREES {
+            self.largest.push(Reverse((size, element.start, name.to_string())));
+        } else if self.largest.peek().is_some_and(|Reverse((smallest, _, _))| size > *smallest) {
+            self.largest.pop();
+            self.largest.push(Reverse((size, element.start, name.to_string())));
+        }
+    }
+}
+
+/// The live summary of a scan, e.g. `Scanning: 120 tags, depth 3`.
+fn scanning(collector: &Collector) -> String {
+    format!("Scanning: {} tags, depth {}", collector.tag_count, collector.depth)
+}
+
+/// `replaced` is the number of invalid sequences replaced while decoding the
+/// document, reported when it was decoded lossily.
+pub fn print_stats(xml: &str, replaced: Option<usize>) -> io::Result<()> {
+    let mut progress = Progress::new("stats", Some(xml.len() as u64));
+    let mut collector = Collector::new();
+    let mut scanned = xml.len();
+    stream_xml_spans(xml, |token, span| {
+        if interrupt::interrupted() {
+            scanned = span.start;
+            return Break(());
+        }
+        let span = span.start as u64..span.end as u64;
+        let end = span.end;
+        let flow = collector.on_token(token, span);
+        progress.update(end, collector.tag_count as u64, || scanning(&collector));
+        flow
     });
+    progress.finish(scanned as u64, collector.tag_count as u64);
+    print_report(&collector, scanned as u64, progress.elapsed(), replaced)?;
+    if scanned < xml.len() {
+        print_interrupted(scanned as u64, Some(xml.len() as u64))?;
+    }
+    Ok(())
+}
 
-    let elapsed = start_time.elapsed();
-    let mut stdout = stdout();
+/// Notes under a report that the scan was stopped with Ctrl-C.
+fn print_interrupted(scanned: u64, total: Option<u64>) -> io::Result<()> {
+    let mut note = interrupt::describe(scanned, total);
+    note[..1].make_ascii_uppercase();
+    let note = format!("\n{}: the figures above cover only the part scanned\n", note);
+    execute!(stdout(), SetForegroundColor(Color::Red), Print(note), ResetColor)
+}
+
+/// Counts the bytes read through it, for the live summary.
+struct Counted<'c, R> {
+    inner: R,
+    read: &'c Cell<u64>,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Like `print_stats`, but reads the document incrementally from `reader`
+/// (a pipe, socket or file too large to map).
+pub fn print_stats_reader<R: Read>(reader: R, replacements: Option<&Replacements>) -> io::Result<()> {
+    let mut progress = Progress::new("stats", None);
+    let mut collector = Collector::new();
+    let read = Cell::new(0);
+    let result = stream_xml_reader_spans(Counted { inner: reader, read: &read }, |token, span| {
+        if interrupt::interrupted() {
+            return Break(());
+        }
+        let flow = collector.on_token(token, span);
+        progress.update(read.get(), collector.tag_count as u64, || scanning(&collector));
+        flow
+    });
+    progress.finish(read.get(), collector.tag_count as u64);
+    let size = result?;
+    print_report(&collector, size, progress.elapsed(), replacements.map(Replacements::count))?;
+    if interrupt::interrupted() {
+        print_interrupted(size, None)?;
+    }
+    Ok(())
+}
 
-    execute!(stdout, SetAttribute(Attribute::Bold), Print("--- XML Statistics ---\n"), ResetColor).unwrap();
-    execute!(stdout, Print("Processed "), SetForegroundColor(Color::Yellow), Print(tag_count), ResetColor, Print(" tags in "), SetForegroundColor(Color::Green), Print(format!("{:?}\n", elapsed)), ResetColor).unwrap();
-    execute!(stdout, Print("Max depth: "), SetForegroundColor(Color::Yellow), Print(max_depth), ResetColor, Print("\n")).unwrap();
-    execute!(stdout, Print("File size: "), SetForegroundColor(Color::Yellow), Print(xml.len()), ResetColor, Print(" bytes\n")).unwrap();
-    execute!(stdout, Print("Processing speed: "), SetForegroundColor(Color::Green), Print(format!("{:.2} MB/s\n", xml.len() as f64 / elapsed.as_secs_f64() / 1_000_000.0)), ResetColor).unwrap();
-
-    execute!(stdout, Print("\n"), SetAttribute(Attribute::Bold), Print("--- Elements and unique tag names per depth level ---\n"), ResetColor).unwrap();
-    for level in 0..MAX_DEPTH {
-        let count = elements_per_level[level];
-        if count > 0 {
-            let level_name = if level == 0 {
-                "Root level".to_string()
-            } else {
-                format!("Depth {}", level)
+/// Scans the UTF-8 file at `path` like `print_stats`, then keeps reading what
+/// is appended to it, like `tail -f`, and reprints the report after each
+/// addition until Ctrl-C. Each addition is scanned from the end of the
+/// last complete tag, so a record still being written is counted once it is
+/// whole. A file that shrinks was replaced, and is scanned from the start.
+pub fn follow_stats(path: &Path) -> io::Result<()> {
+    let mut collector = Collector::new();
+    // End of the last complete tag, and time spent scanning.
+    let mut offset = 0u64;
+    let mut busy = Duration::ZERO;
+    let mut scanned_to = None;
+    loop {
+        let len = std::fs::metadata(path)?.len();
+        if len < offset {
+            collector = Collector::new();
+            offset = 0;
+            busy = Duration::ZERO;
+        }
+        if scanned_to != Some(len) {
+            let start_time = Instant::now();
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut bytes = Vec::new();
+            file.take(len - offset).read_to_end(&mut bytes)?;
+            let text = match std::str::from_utf8(&bytes) {
+                Ok(text) => text,
+                // A character cut off at the end is read again next time.
+                Err(err) if err.error_len().is_none() => std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default(),
+                Err(err) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid UTF-8 at byte {}", offset + err.valid_up_to() as u64),
+                    ));
+                }
             };
-            execute!(stdout, Print("  "), SetForegroundColor(Color::Cyan), Print(format!("{}: ", level_name)), ResetColor, SetForegroundColor(Color::Yellow), Print(count), ResetColor, Print(" elements\n")).unwrap();
-            let tag_count = unique_tag_counts[level];
-            if tag_count > 0 {
-                let mut tag_list: [&str; MAX_UNIQUE_TAGS] = [""; MAX_UNIQUE_TAGS];
-                let mut n = 0;
-                for tag in unique_tags_per_level[level].iter().take(tag_count).flatten() {
-                    tag_list[n] = tag;
-                    n += 1;
+            // Text after the last tag may be cut off, so it waits as well.
+            let mut tokens = Vec::new();
+            stream_xml_spans(text, |token, span| {
+                tokens.push((token, span));
+                Continue(())
+            });
+            let complete = tokens.iter().rposition(|(token, _)| !matches!(token, Token::Text(_))).map_or(0, |last| last + 1);
+            let consumed = complete.checked_sub(1).map_or(0, |last| tokens[last].1.end);
+            for (token, span) in tokens.drain(..complete) {
+                if collector.on_token(token, offset + span.start as u64..offset + span.end as u64).is_break() {
+                    break;
                 }
-                tag_list[..n].sort_unstable();
-                execute!(stdout, Print("    Unique tags: "), SetForegroundColor(Color::Magenta), Print(format!("{}\n", tag_list[..n].join(", "))), ResetColor).unwrap();
             }
+            offset += consumed as u64;
+            busy += start_time.elapsed();
+            scanned_to = Some(len);
+
+            let mut stdout = stdout();
+            if stdout.is_terminal() {
+                execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+            }
+            print_report(&collector, offset, busy, None)?;
+            execute!(stdout, Print(format!("\nFollowing {} (Ctrl-C to stop)\n", path.display())))?;
+        }
+        if interrupt::interrupted() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn print_report(collector: &Collector, size: u64, elapsed: std::time::Duration, replaced: Option<usize>) -> io::Result<()> {
+    let Collector {
+        max_depth,
+        tag_count,
+        levels,
+        attributes,
+        value_lengths,
+        hosts,
+        other_host_refs,
+        attribute_count,
+        text_bytes,
+        text_nodes,
+        empty_elements,
+        parents,
+        children,
+        most_children,
+        largest,
+        profile,
+        ..
+    } = collector;
+    let mut stdout = stdout();
+
+    execute!(stdout, SetAttribute(Attribute::Bold), Print("--- XML Statistics ---\n"), ResetColor)?;
+    execute!(stdout, Print("Processed "), SetForegroundColor(Color::Yellow), Print(tag_count), ResetColor, Print(" tags in "), SetForegroundColor(Color::Green), Print(format!("{:?}\n", elapsed)), ResetColor)?;
+    execute!(stdout, Print("Max depth: "), SetForegroundColor(Color::Yellow), Print(max_depth), ResetColor, Print("\n"))?;
+    execute!(stdout, Print("File size: "), SetForegroundColor(Color::Yellow), Print(size), ResetColor, Print(" bytes\n"))?;
+    if let Some(replaced) = replaced {
+        let color = if replaced > 0 { Color::Red } else { Color::Yellow };
+        execute!(stdout, Print("Invalid sequences replaced: "), SetForegroundColor(color), Print(replaced), ResetColor, Print("\n"))?;
+    }
+    execute!(stdout, Print("Processing speed: "), SetForegroundColor(Color::Green), Print(format!("{:.2} MB/s\n", size as f64 / elapsed.as_secs_f64() / 1_000_000.0)), ResetColor)?;
+
+    execute!(stdout, Print("Text: "), SetForegroundColor(Color::Yellow), Print(text_bytes), ResetColor, Print(" bytes in "), SetForegroundColor(Color::Yellow), Print(text_nodes), ResetColor, Print(" text nodes\n"))?;
+    execute!(stdout, Print("Attributes: "), SetForegroundColor(Color::Yellow), Print(attribute_count), ResetColor, Print(format!(" ({} distinct names)\n", attributes.len())))?;
+
+    if let Some(profile) = profile {
+        execute!(stdout, Print("\n"), SetAttribute(Attribute::Bold), Print(format!("--- {} ---\n", profile.profile.name())), ResetColor)?;
+        for (label, value) in profile.figures() {
+            execute!(stdout, Print(format!("  {}: ", label)), SetForegroundColor(Color::Yellow), Print(value), ResetColor, Print("\n"))?;
+        }
+    }
+
+    execute!(stdout, Print("\n"), SetAttribute(Attribute::Bold), Print("--- Element structure ---\n"), ResetColor)?;
+    execute!(stdout, Print("  Empty elements: "), SetForegroundColor(Color::Yellow), Print(empty_elements), ResetColor, Print("\n"))?;
+    if *parents > 0 {
+        let average = *children as f64 / *parents as f64;
+        let (count, name, offset) = most_children;
+        execute!(stdout, Print("  Children per element: "), SetForegroundColor(Color::Yellow), Print(format!("{:.1}", average)), ResetColor, Print(format!(" on average over {} elements with children, at most ", parents)), SetForegroundColor(Color::Yellow), Print(count), ResetColor, Print(" in "), SetForegroundColor(Color::Magenta), Print(format!("<{}>", name)), ResetColor, Print(format!(" at byte {}\n", offset)))?;
+    }
+    if !largest.is_empty() {
+        execute!(stdout, Print("  Largest subtrees below the root:\n"))?;
+        let mut subtrees: Vec<&(u64, u64, String)> = largest.iter().map(|Reverse(subtree)| subtree).collect();
+        subtrees.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        for (size, offset, name) in subtrees {
+            execute!(stdout, Print("    "), SetForegroundColor(Color::Yellow), Print(format!("{:>10}", format_bytes(*size))), ResetColor, Print("  "), SetForegroundColor(Color::Magenta), Print(format!("<{}>", name)), ResetColor, Print(format!(" at byte {}\n", offset)))?;
+        }
+    }
+
+    execute!(stdout, Print("\n"), SetAttribute(Attribute::Bold), Print("--- Elements and unique tag names per depth level ---\n"), ResetColor)?;
+    for (depth, level) in levels.iter().enumerate() {
+        let level_name = if depth == 0 {
+            "Root level".to_string()
+        } else {
+            format!("Depth {}", depth)
+        };
+        execute!(stdout, Print("  "), SetForegroundColor(Color::Cyan), Print(format!("{}: ", level_name)), ResetColor, SetForegroundColor(Color::Yellow), Print(level.elements), ResetColor, Print(" elements\n"))?;
+        let mut tag_list: Vec<&str> = level.tags.iter().map(String::as_str).collect();
+        tag_list.sort_unstable();
+        let more = if tag_list.len() > SHOWN_TAGS { format!(" … and {} more", tag_list.len() - SHOWN_TAGS) } else { String::new() };
+        tag_list.truncate(SHOWN_TAGS);
+        execute!(stdout, Print("    Unique tags: "), SetForegroundColor(Color::Magenta), Print(tag_list.join(", ")), ResetColor, Print(format!("{}\n", more)))?;
+    }
+    let crowded: Vec<String> = levels.iter().enumerate().filter(|(_, level)| level.tags.len() > FORMER_MAX_UNIQUE_TAGS).map(|(depth, _)| depth.to_string()).collect();
+    if levels.len() > FORMER_MAX_DEPTH || !crowded.is_empty() {
+        let mut beyond = Vec::new();
+        if levels.len() > FORMER_MAX_DEPTH {
+            beyond.push(format!("{} levels deep (over {})", levels.len(), FORMER_MAX_DEPTH));
+        }
+        if !crowded.is_empty() {
+            beyond.push(format!("over {} tag names at depth {}", FORMER_MAX_UNIQUE_TAGS, crowded.join(", ")));
         }
+        execute!(stdout, SetForegroundColor(Color::Yellow), Print(format!("  Note: this document is {}; earlier versions of xmz left part of it out of these counts.\n", beyond.join(" and "))), ResetColor)?;
+    }
+
+    if attributes.is_empty() {
+        return Ok(());
+    }
+    let mut names: Vec<(&String, &AttributeStats)> = attributes.iter().collect();
+    names.sort_unstable_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+    let frequent: Vec<String> = names.iter().take(SHOWN_ATTRIBUTES).map(|(name, stats)| format!("@{} ({})", name, stats.count)).collect();
+    execute!(stdout, Print("\nMost frequent attributes: "), SetForegroundColor(Color::Magenta), Print(frequent.join(", ")), ResetColor, Print("\n"))?;
+    execute!(stdout, Print("\n"), SetAttribute(Attribute::Bold), Print("--- Attribute value lengths ---\n"), ResetColor)?;
+    for (name, stats) in &names {
+        let average = stats.total_len as f64 / stats.count as f64;
+        execute!(stdout, Print("  "), SetForegroundColor(Color::Magenta), Print(format!("@{}", name)), ResetColor, Print(": "), SetForegroundColor(Color::Yellow), Print(stats.count), ResetColor, Print(format!(" values, {}–{} characters (average {:.1})\n", stats.min_len, stats.max_len, average)))?;
+    }
+    let total: usize = value_lengths.iter().sum();
+    let widest = value_lengths.iter().copied().max().unwrap_or(0).max(1);
+    for (bucket, &count) in value_lengths.iter().enumerate() {
+        let label = match bucket {
+            0 => "empty".to_string(),
+            b if b == LENGTH_BUCKETS.len() => format!("> {}", LENGTH_BUCKETS[b - 1]),
+            b => format!("{}–{}", LENGTH_BUCKETS[b - 1] + 1, LENGTH_BUCKETS[b]),
+        };
+        let bar = "█".repeat((count * 40).div_ceil(widest));
+        execute!(stdout, Print(format!("  {:>11} ", label)), SetForegroundColor(Color::Yellow), Print(format!("{:>10} ", count)), SetForegroundColor(Color::Green), Print(bar), ResetColor, Print(format!(" {:.1}%\n", count as f64 * 100.0 / total as f64)))?;
+    }
+
+    let uri_names: Vec<&(&String, &AttributeStats)> = names.iter().filter(|(_, stats)| stats.uris_with_host + stats.uris_without_host > 0).collect();
+    if uri_names.is_empty() {
+        return Ok(());
+    }
+    execute!(stdout, Print("\n"), SetAttribute(Attribute::Bold), Print("--- URI-valued attributes ---\n"), ResetColor)?;
+    for (name, stats) in uri_names {
+        execute!(stdout, Print("  "), SetForegroundColor(Color::Magenta), Print(format!("@{}", name)), ResetColor, Print(": "), SetForegroundColor(Color::Yellow), Print(stats.uris_with_host), ResetColor, Print(" with a host, "), SetForegroundColor(Color::Yellow), Print(stats.uris_without_host), ResetColor, Print(" relative or local\n"))?;
+    }
+    if hosts.is_empty() {
+        return Ok(());
+    }
+    let mut by_count: Vec<(&String, &usize)> = hosts.iter().collect();
+    by_count.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    let capped = if *other_host_refs > 0 { format!(", plus {} references beyond the first {}", other_host_refs, MAX_UNIQUE_HOSTS) } else { String::new() };
+    execute!(stdout, Print("  Hosts referenced: "), SetForegroundColor(Color::Yellow), Print(hosts.len()), ResetColor, Print(format!(" distinct{}\n", capped)))?;
+    for (host, count) in by_count.iter().take(SHOWN_HOSTS) {
+        execute!(stdout, Print("    "), SetForegroundColor(Color::Cyan), Print(format!("{:<40}", host)), ResetColor, SetForegroundColor(Color::Yellow), Print(format!("{:>10}\n", count)), ResetColor)?;
+    }
+    if by_count.len() > SHOWN_HOSTS {
+        execute!(stdout, Print(format!("    … and {} more\n", by_count.len() - SHOWN_HOSTS)))?;
     }
+    Ok(())
 }
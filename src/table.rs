@@ -0,0 +1,136 @@
+//! `xmz table`: one CSV or TSV row per record a query selects, with chosen
+//! fields of each record as the columns, or the same rows as an Arrow IPC
+//! stream.
+
+use crate::agg::push_csv_line;
+use crate::arrow::ArrowWriter;
+use crate::parser::{Break, Continue, decode_entities};
+use crate::query::{Field, Predicate, Query, for_each_match};
+use std::io::{self, Write};
+
+/// Output formats of `xmz table`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// Tab-separated, with tabs and line breaks in values turned into spaces.
+    Tsv,
+    /// Arrow IPC record batches, see `arrow`.
+    Arrow,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "arrow" => Ok(Format::Arrow),
+            _ => Err(format!("unknown format '{}' (expected csv, tsv or arrow)", name)),
+        }
+    }
+}
+
+/// A column: a field of each record, headed by the field as written.
+pub struct Column {
+    pub name: String,
+    field: Field,
+}
+
+impl Column {
+    /// Parses a comma-separated list of fields, e.g. `name,price,@id`.
+    pub fn parse_list(source: &str) -> Result<Vec<Self>, String> {
+        let columns: Vec<Self> = source
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Ok(Column { name: name.to_string(), field: Field::parse(name)? }))
+            .collect::<Result<_, String>>()?;
+        if columns.is_empty() {
+            return Err("no columns given".to_string());
+        }
+        Ok(columns)
+    }
+
+    /// The field's values in `element`, entities decoded, joined with "; "
+    /// when it has several.
+    fn value(&self, element: &str) -> String {
+        let values: Vec<_> = self.field.values(element).into_iter().map(|value| decode_entities(value.trim())).collect();
+        values.join("; ")
+    }
+}
+
+pub struct TableOptions<'a> {
+    pub query: &'a Query,
+    /// Only records this predicate holds for become rows (`--where`).
+    pub filter: Option<&'a Predicate>,
+    pub columns: &'a [Column],
+    pub format: Format,
+    /// Start with a line of column names.
+    pub header: bool,
+}
+
+/// What `write_rows` wrote.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Written {
+    pub rows: u64,
+    /// Values written as Arrow nulls because they did not fit the type
+    /// inferred for their column.
+    pub mismatched: u64,
+}
+
+fn push_line<'s>(line: &mut String, format: Format, fields: impl Iterator<Item = &'s str>) {
+    match format {
+        Format::Csv => push_csv_line(line, fields),
+        Format::Tsv => {
+            for (n, field) in fields.enumerate() {
+                if n > 0 {
+                    line.push('\t');
+                }
+                line.extend(field.chars().map(|c| if matches!(c, '\t' | '\n' | '\r') { ' ' } else { c }));
+            }
+            line.push('\n');
+        }
+        Format::Arrow => unreachable!("Arrow rows are not lines"),
+    }
+}
+
+/// Calls `on_row` with the values of every matching record, returning the
+/// number of rows.
+fn for_each_row(xml: &str, options: &TableOptions, mut on_row: impl FnMut(Vec<String>) -> io::Result<()>) -> io::Result<u64> {
+    let mut rows = 0;
+    let mut result = Ok(());
+    for_each_match(xml, options.query, |range| {
+        let record = &xml[range];
+        if options.filter.is_some_and(|filter| !filter.eval(record)) {
+            return Continue(());
+        }
+        result = on_row(options.columns.iter().map(|column| column.value(record)).collect());
+        if result.is_err() {
+            return Break(());
+        }
+        rows += 1;
+        Continue(())
+    });
+    result.map(|()| rows)
+}
+
+/// Streams `xml` and writes a row for every matching record. A record
+/// inside another is written before it.
+pub fn write_rows<W: Write>(out: &mut W, xml: &str, options: &TableOptions) -> io::Result<Written> {
+    if options.format == Format::Arrow {
+        let mut arrow = ArrowWriter::new(out, options.columns.iter().map(|column| column.name.clone()).collect());
+        let rows = for_each_row(xml, options, |values| arrow.push(values))?;
+        let mismatched = arrow.finish()?;
+        return Ok(Written { rows, mismatched });
+    }
+    let mut line = String::new();
+    if options.header {
+        push_line(&mut line, options.format, options.columns.iter().map(|column| column.name.as_str()));
+        out.write_all(line.as_bytes())?;
+    }
+    let rows = for_each_row(xml, options, |values| {
+        line.clear();
+        push_line(&mut line, options.format, values.iter().map(String::as_str));
+        out.write_all(line.as_bytes())
+    })?;
+    Ok(Written { rows, mismatched: 0 })
+}
@@ -0,0 +1,63 @@
+//! `xmz tokens`: the raw token stream, for sharing exactly what the
+//! tokenizer produced around a problem.
+
+use crate::parser::{Break, Continue, Token, stream_xml_spans};
+use std::io::{self, Write};
+
+/// Longest text shown per token; the length column still gives the full size.
+const MAX_TEXT: usize = 60;
+
+/// Prints one line per token ending after byte `from`, up to `limit` tokens:
+/// start offset, length, kind and the token's name, attributes or text.
+/// Offsets are bytes into `xml`. Ends with a note if the limit is reached or
+/// markup cut off by the end of the document was skipped.
+pub fn write_tokens<W: Write>(out: &mut W, xml: &str, from: usize, limit: Option<usize>) -> io::Result<()> {
+    let mut printed = 0;
+    let mut consumed = 0;
+    let mut next_from = None;
+    writeln!(out, "#   offset  length kind  detail")?;
+    let mut result = Ok(());
+    stream_xml_spans(xml, |token, span| {
+        if span.end <= from && !(span.is_empty() && span.start == from) {
+            consumed = span.end;
+            return Continue(());
+        }
+        if limit.is_some_and(|limit| printed >= limit) {
+            next_from = Some(span.start);
+            return Break(());
+        }
+        consumed = span.end;
+        let detail = match token {
+            Token::StartTag(name, attrs) if attrs.trim().is_empty() => name.to_string(),
+            Token::StartTag(name, attrs) => format!("{} {}", name, quoted(attrs.trim())),
+            Token::EndTag(name) => name.to_string(),
+            Token::Text(text) | Token::CData(text) => quoted(text),
+        };
+        let kind = match token {
+            Token::StartTag(..) => "start",
+            Token::EndTag(_) => "end",
+            Token::Text(_) => "text",
+            Token::CData(_) => "cdata",
+        };
+        result = writeln!(out, "{:>10} {:>7} {:<5} {}", span.start, span.len(), kind, detail);
+        printed += 1;
+        if result.is_err() { Break(()) } else { Continue(()) }
+    });
+    result?;
+    if let Some(offset) = next_from {
+        writeln!(out, "# limit reached; continue with --from {}", offset)?;
+    } else if let Some(lt) = xml[consumed..].find('<')
+        && !xml[consumed + lt..].contains('>')
+    {
+        writeln!(out, "# unterminated markup at byte {} was skipped", consumed + lt)?;
+    }
+    Ok(())
+}
+
+/// `text` as a quoted, escaped string, cut after `MAX_TEXT` characters.
+fn quoted(text: &str) -> String {
+    match text.char_indices().nth(MAX_TEXT) {
+        Some((cut, _)) => format!("{:?}…", &text[..cut]),
+        None => format!("{:?}", text),
+    }
+}
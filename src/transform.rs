@@ -0,0 +1,722 @@
+//! `xmz transform`: a streaming copy of the document with rewriting rules
+//! applied to its tags. Text, comments and everything else the rules do not
+//! touch are copied byte for byte.
+//!
+//! Rules: element and attribute names can be normalized to lower or upper
+//! case, or to the first spelling seen, for documents from tools that treat
+//! names case-insensitively. Namespace prefixes follow the element name rule
+//! wherever they appear, so declarations keep matching their uses, and the
+//! reserved `xml` and `xmlns` prefixes are always lower case.
+//!
+//! Namespaces can be tidied up: declarations nothing in their scope uses are
+//! removed, prefixes renamed, and a namespace dropped, which moves its
+//! elements and attributes to no namespace. Attribute values that look like
+//! prefixed names (`xsi:type="ns:Item"`) count as uses and are renamed along.
+//!
+//! Attributes of the elements a query selects can become child elements
+//! (`//book/@isbn`), inserted first, and text-only child elements can become
+//! attributes (`//book/isbn`). Where those go is found in a pass before the
+//! copy, since a child is read after its parent's start tag is written.
+//! Defaults add an attribute or a first child element with a given value to
+//! the selected elements that lack one.
+
+use crate::parser::{Break, Continue, Token, attribute_pairs, extract_attributes, stream_xml_spans};
+use crate::query::{Query, select};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::ops::Range;
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    Lower,
+    Upper,
+    /// The first spelling seen, compared case-insensitively, is used throughout.
+    PreserveFirst,
+}
+
+impl Case {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "lower" => Ok(Case::Lower),
+            "upper" => Ok(Case::Upper),
+            "preserve-first" | "first" => Ok(Case::PreserveFirst),
+            _ => Err(format!("unknown case '{}' (expected lower, upper or preserve-first)", name)),
+        }
+    }
+}
+
+/// Which rules to apply; the default copies the document unchanged.
+#[derive(Debug, Default)]
+pub struct TransformOptions {
+    pub tag_case: Option<Case>,
+    pub attribute_case: Option<Case>,
+    /// Remove namespace declarations nothing in their scope uses.
+    pub prune_namespaces: bool,
+    /// Prefixes to rename, old to new.
+    pub rename_prefixes: HashMap<String, String>,
+    /// Namespace URIs whose elements and attributes move to no namespace.
+    pub drop_namespaces: Vec<String>,
+    /// Attributes to turn into child elements.
+    pub attributes_to_elements: Vec<Conversion>,
+    /// Text-only child elements to turn into attributes.
+    pub elements_to_attributes: Vec<Conversion>,
+    /// Attributes and child elements to add where they are missing.
+    pub defaults: Vec<DefaultValue>,
+}
+
+/// An attribute or child element of the elements `parent` selects, as given
+/// by `//book/@isbn` or `//book/isbn`.
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub parent: Query,
+    pub name: String,
+}
+
+impl Conversion {
+    /// Parses a path ending in `/@name` if `attribute`, else in `/name`.
+    pub fn parse(path: &str, attribute: bool) -> Result<Self, String> {
+        let (parent, last) = path.rsplit_once('/').ok_or_else(|| format!("expected PARENT/NAME, got '{}'", path))?;
+        let name = match (attribute, last.strip_prefix('@')) {
+            (true, Some(name)) => name,
+            (true, None) => return Err(format!("expected a path ending in /@attribute, got '{}'", path)),
+            (false, Some(_)) => return Err(format!("expected a path ending in /element, got '{}'", path)),
+            (false, None) => last,
+        };
+        if !name.split(':').all(is_name) || name.split(':').count() > 2 {
+            return Err(format!("'{}' is not a name", name));
+        }
+        let parent = parent.strip_suffix('/').unwrap_or(parent);
+        Ok(Self {
+            parent: Query::parse(parent)?,
+            name: name.to_string(),
+        })
+    }
+}
+
+/// An attribute (`//book/@lang=en`) or child element (`//book/currency=EUR`)
+/// to add, with its value, to the selected elements that lack it.
+#[derive(Debug, Clone)]
+pub struct DefaultValue {
+    /// The path as given, for the summary.
+    pub path: String,
+    pub target: Conversion,
+    pub attribute: bool,
+    pub value: String,
+}
+
+impl DefaultValue {
+    /// Parses `PATH=VALUE`; the `=` is the first one outside predicates.
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let mut depth = 0;
+        let mut quote = None;
+        let split = rule.char_indices().find(|&(_, c)| {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '[') => depth += 1,
+                (None, ']') => depth -= 1,
+                (None, '=') if depth == 0 => return true,
+                _ => {}
+            }
+            false
+        });
+        let Some((at, _)) = split else {
+            return Err(format!("expected PATH=VALUE, got '{}'", rule));
+        };
+        let path = &rule[..at];
+        let attribute = path.rsplit('/').next().is_some_and(|last| last.starts_with('@'));
+        Ok(Self {
+            path: path.to_string(),
+            target: Conversion::parse(path, attribute)?,
+            attribute,
+            value: rule[at + 1..].to_string(),
+        })
+    }
+}
+
+/// How much the rules changed.
+#[derive(Debug, Default)]
+pub struct TransformStats {
+    /// Start and end tags whose name was changed.
+    pub renamed_tags: usize,
+    pub renamed_attributes: usize,
+    pub removed_declarations: usize,
+    /// Attributes that became elements, and elements that became attributes.
+    pub attributes_to_elements: usize,
+    pub elements_to_attributes: usize,
+    /// Per default rule: its path and how many values it added.
+    pub defaults_added: Vec<(String, usize)>,
+}
+
+impl TransformStats {
+    pub fn describe(&self) -> String {
+        let mut summary = format!("renamed {} tag(s) and {} attribute(s)", self.renamed_tags, self.renamed_attributes);
+        if self.removed_declarations > 0 {
+            summary.push_str(&format!(", removed {} namespace declaration(s)", self.removed_declarations));
+        }
+        if self.attributes_to_elements > 0 {
+            summary.push_str(&format!(", turned {} attribute(s) into elements", self.attributes_to_elements));
+        }
+        if self.elements_to_attributes > 0 {
+            summary.push_str(&format!(", turned {} element(s) into attributes", self.elements_to_attributes));
+        }
+        for (path, count) in &self.defaults_added {
+            summary.push_str(&format!(", added {} default(s) for {}", count, path));
+        }
+        summary
+    }
+}
+
+/// Parses a `--rename-prefix OLD=NEW` argument.
+pub fn parse_rename(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((old, new)) if is_name(old) && is_name(new) => Ok((old.to_string(), new.to_string())),
+        _ => Err(format!("expected OLD=NEW with two prefixes, got '{}'", arg)),
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The prefix and local part of an attribute value that is a prefixed name.
+fn qname_value(value: &str) -> Option<(&str, &str)> {
+    value.split_once(':').filter(|(prefix, local)| is_name(prefix) && is_name(local))
+}
+
+/// The namespace declarations among a start tag's attributes, as (prefix,
+/// URI) pairs; the default namespace has the empty prefix.
+fn declarations<'a>(pairs: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+    pairs
+        .iter()
+        .filter_map(|&(key, value)| match key {
+            "xmlns" => Some(("", value)),
+            _ => key.strip_prefix("xmlns:").map(|prefix| (prefix, value)),
+        })
+        .collect()
+}
+
+/// Offset of `part` (a slice of `whole`) in `whole`.
+fn offset_in(whole: &str, part: &str) -> usize {
+    part.as_ptr() as usize - whole.as_ptr() as usize
+}
+
+/// Offsets of the namespace declarations that something in their scope uses:
+/// an element or attribute name, or a prefixed-name attribute value.
+fn used_declarations(xml: &str) -> HashSet<usize> {
+    let mut used = HashSet::new();
+    // Per open element: its declarations as (prefix, offset of the attribute).
+    let mut scopes: Vec<Vec<(&str, usize)>> = Vec::new();
+    stream_xml_spans(xml, |token, _| {
+        match token {
+            Token::StartTag(name, attrs) => {
+                let pairs = attribute_pairs(attrs);
+                let bindings = pairs
+                    .iter()
+                    .filter_map(|&(key, _)| match key {
+                        "xmlns" => Some(("", offset_in(xml, key))),
+                        _ => key.strip_prefix("xmlns:").map(|prefix| (prefix, offset_in(xml, key))),
+                    })
+                    .collect();
+                scopes.push(bindings);
+                let mut mark = |prefix: &str| {
+                    if let Some(&(_, at)) = scopes.iter().rev().flatten().find(|(p, _)| *p == prefix) {
+                        used.insert(at);
+                    }
+                };
+                mark(name.split_once(':').map_or("", |(prefix, _)| prefix));
+                for (key, value) in pairs {
+                    if let Some((prefix, _)) = key.split_once(':')
+                        && prefix != "xmlns"
+                    {
+                        mark(prefix);
+                    }
+                    if let Some((prefix, _)) = qname_value(value) {
+                        mark(prefix);
+                    }
+                }
+            }
+            Token::EndTag(_) => {
+                scopes.pop();
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    used
+}
+
+/// Where the conversions apply, keyed by the start offset of the element
+/// whose start tag changes.
+#[derive(Default)]
+struct Conversions {
+    /// Names of the attributes to turn into child elements.
+    to_elements: HashMap<usize, Vec<String>>,
+    /// Attributes to add, with values escaped, from child elements.
+    to_attributes: HashMap<usize, Vec<(String, String)>>,
+    /// Child elements that became attributes, start offset to end offset.
+    removed: HashMap<usize, usize>,
+    /// Default attributes and child elements to add, with escaped values.
+    default_attributes: HashMap<usize, Vec<(String, String)>>,
+    default_children: HashMap<usize, Vec<(String, String)>>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Escapes raw element text for a double-quoted attribute value; entity and
+/// character references stay as they are.
+fn attribute_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' => escaped.push_str("&#9;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes `value` for element text or a double-quoted attribute value.
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether `element` (the source of one element) has a child named `name`.
+fn has_child(element: &str, name: &str) -> bool {
+    let mut depth = 0;
+    let mut found = false;
+    stream_xml_spans(element, |token, _| {
+        match token {
+            Token::StartTag(tag, _) => {
+                depth += 1;
+                found = depth == 2 && tag == name;
+            }
+            Token::EndTag(_) => depth -= 1,
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        if found { Break(()) } else { Continue(()) }
+    });
+    found
+}
+
+/// Finds the elements the conversions and defaults in `options` apply to,
+/// counting the defaults into `stats`.
+fn find_conversions(xml: &str, options: &TransformOptions, stats: &mut TransformStats) -> io::Result<Conversions> {
+    let mut conversions = Conversions::default();
+    for default in &options.defaults {
+        let name = &default.target.name;
+        let mut count = 0;
+        for element in select(xml, &default.target.parent) {
+            let missing = match default.attribute {
+                true => extract_attributes(xml, element.start).iter().all(|(key, _)| key != name),
+                false => !has_child(&xml[element.clone()], name),
+            };
+            if missing {
+                let additions = match default.attribute {
+                    true => &mut conversions.default_attributes,
+                    false => &mut conversions.default_children,
+                };
+                additions.entry(element.start).or_default().push((name.clone(), escape(&default.value)));
+                count += 1;
+            }
+        }
+        stats.defaults_added.push((default.path.clone(), count));
+    }
+    for conversion in &options.attributes_to_elements {
+        for element in select(xml, &conversion.parent) {
+            conversions.to_elements.entry(element.start).or_default().push(conversion.name.clone());
+        }
+    }
+    for conversion in &options.elements_to_attributes {
+        for element in select(xml, &conversion.parent) {
+            let source = &xml[element.clone()];
+            // The matching child: its start tag and whether it holds markup.
+            let mut child: Option<(Range<usize>, bool)> = None;
+            let mut found: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+            let mut depth = 0;
+            let mut consumed = 0;
+            let mut error = None;
+            stream_xml_spans(source, |token, span| {
+                if let Some((_, markup)) = child.as_mut() {
+                    *markup |= !source[consumed..span.start].trim().is_empty() || matches!(token, Token::StartTag(..) | Token::CData(_));
+                }
+                consumed = span.end;
+                match token {
+                    Token::StartTag(name, _) => {
+                        depth += 1;
+                        if depth == 2 && name == conversion.name {
+                            child = Some((span, false));
+                        }
+                    }
+                    Token::EndTag(_) => {
+                        if depth == 2
+                            && let Some((start, markup)) = child.take()
+                        {
+                            if markup {
+                                let at = element.start + start.start;
+                                error = Some(format!("element '{}' at byte {} holds markup; only text can become an attribute", conversion.name, at));
+                                return Break(());
+                            }
+                            found.push((start, span));
+                        }
+                        depth -= 1;
+                    }
+                    Token::Text(_) | Token::CData(_) => {}
+                }
+                Continue(())
+            });
+            if let Some(message) = error {
+                return Err(invalid(message));
+            }
+            match found.as_slice() {
+                [] => {}
+                [(start, end)] => {
+                    let text = if end.is_empty() { "" } else { &source[start.end..end.start] };
+                    conversions.to_attributes.entry(element.start).or_default().push((conversion.name.clone(), attribute_text(text)));
+                    conversions.removed.insert(element.start + start.start, element.start + end.end.max(start.end));
+                }
+                [_, (second, _), ..] => {
+                    return Err(invalid(format!(
+                        "element '{}' at byte {} is the second in its parent; only one can become an attribute",
+                        conversion.name,
+                        element.start + second.start
+                    )));
+                }
+            }
+        }
+    }
+    Ok(conversions)
+}
+
+/// First spellings seen by `Case::PreserveFirst`, keyed by lower case name.
+#[derive(Default)]
+struct Spellings {
+    tags: HashMap<String, String>,
+    attributes: HashMap<String, String>,
+    prefixes: HashMap<String, String>,
+}
+
+/// The namespaces an open element declares, and the default namespace in
+/// effect in the output inside it.
+struct Scope<'a> {
+    bindings: Vec<(&'a str, &'a str)>,
+    out_default: &'a str,
+}
+
+struct Transformer<'a, 'o> {
+    xml: &'a str,
+    options: &'o TransformOptions,
+    /// Declarations in use, when pruning.
+    used: Option<HashSet<usize>>,
+    scopes: Vec<Scope<'a>>,
+    conversions: Conversions,
+    spellings: Spellings,
+    stats: TransformStats,
+}
+
+/// `name` in `case`, remembering first spellings in `seen`.
+fn apply_case(name: &str, case: Case, seen: &mut HashMap<String, String>) -> String {
+    match case {
+        Case::Lower => name.to_lowercase(),
+        Case::Upper => name.to_uppercase(),
+        Case::PreserveFirst => seen.entry(name.to_lowercase()).or_insert_with(|| name.to_string()).clone(),
+    }
+}
+
+impl<'a> Transformer<'a, '_> {
+    /// The namespace `prefix` (empty for the default) is bound to at this point.
+    fn resolve(&self, prefix: &str) -> Option<&'a str> {
+        if prefix == "xml" {
+            return Some(XML_NAMESPACE);
+        }
+        let uri = self.scopes.iter().rev().flat_map(|scope| &scope.bindings).find(|(p, _)| *p == prefix)?.1;
+        (!uri.is_empty()).then_some(uri)
+    }
+
+    fn dropped(&self, uri: Option<&str>) -> bool {
+        uri.is_some_and(|uri| self.options.drop_namespaces.iter().any(|dropped| dropped == uri))
+    }
+
+    fn prefix(&mut self, prefix: &str) -> String {
+        if prefix.eq_ignore_ascii_case("xml") || prefix.eq_ignore_ascii_case("xmlns") {
+            return prefix.to_ascii_lowercase();
+        }
+        let prefix = self.options.rename_prefixes.get(prefix).map_or(prefix, String::as_str);
+        match self.options.tag_case {
+            Some(case) => apply_case(prefix, case, &mut self.spellings.prefixes),
+            None => prefix.to_string(),
+        }
+    }
+
+    /// The output name of element `name`, whose prefix is resolved in the current scope.
+    fn tag_name(&mut self, name: &str) -> String {
+        let (prefix, local) = match name.split_once(':') {
+            Some((prefix, local)) => (Some(prefix), local),
+            None => (None, name),
+        };
+        let local = match self.options.tag_case {
+            Some(case) => apply_case(local, case, &mut self.spellings.tags),
+            None => local.to_string(),
+        };
+        match prefix {
+            Some(prefix) if !self.dropped(self.resolve(prefix)) => format!("{}:{}", self.prefix(prefix), local),
+            _ => local,
+        }
+    }
+
+    fn attribute_name(&mut self, name: &str) -> String {
+        if name.eq_ignore_ascii_case("xmlns") {
+            return "xmlns".to_string();
+        }
+        match name.split_once(':') {
+            // The local part of a namespace declaration is a prefix.
+            Some((xmlns, declared)) if xmlns.eq_ignore_ascii_case("xmlns") => format!("xmlns:{}", self.prefix(declared)),
+            Some((prefix, local)) if !self.dropped(self.resolve(prefix)) => {
+                let prefix = self.prefix(prefix);
+                format!("{}:{}", prefix, self.attribute_local(local))
+            }
+            Some((_, local)) => self.attribute_local(local),
+            None => self.attribute_local(name),
+        }
+    }
+
+    fn attribute_local(&mut self, local: &str) -> String {
+        match self.options.attribute_case {
+            Some(case) => apply_case(local, case, &mut self.spellings.attributes),
+            None => local.to_string(),
+        }
+    }
+
+    /// A prefixed-name attribute value with its prefix renamed like element
+    /// prefixes, or None if it is not one or stays the same.
+    fn attribute_value(&mut self, value: &str) -> Option<String> {
+        let (prefix, local) = qname_value(value)?;
+        let uri = self.resolve(prefix)?;
+        let renamed = match self.dropped(Some(uri)) {
+            true => local.to_string(),
+            false => format!("{}:{}", self.prefix(prefix), local),
+        };
+        (renamed != value).then_some(renamed)
+    }
+
+    /// Writes the start tag `raw` (from `<` to `>`) named `name` with raw
+    /// attributes `attrs`, rewritten as the rules say, and opens its scope.
+    fn start_tag<W: Write>(&mut self, out: &mut W, raw: &str, name: &str, attrs: &'a str) -> io::Result<()> {
+        let pairs = attribute_pairs(attrs);
+        let start = offset_in(self.xml, raw);
+        let converting = self.conversions.to_elements.remove(&start).unwrap_or_default();
+        let mut moved: Vec<(String, String)> = Vec::new();
+        let inherited = self.scopes.last().map_or("", |scope| scope.out_default);
+        self.scopes.push(Scope {
+            bindings: declarations(&pairs),
+            out_default: inherited,
+        });
+        let prefix = name.split_once(':').map(|(prefix, _)| prefix);
+        let namespace = self.resolve(prefix.unwrap_or(""));
+        let unprefixed = prefix.is_none() || self.dropped(namespace);
+
+        // The default namespace in the output here: what the element itself
+        // needs if it ends up unprefixed, else what it declares, if anything.
+        let declared = pairs.iter().find(|(key, _)| *key == "xmlns");
+        let mut out_default = match declared {
+            _ if unprefixed => if self.dropped(namespace) { "" } else { namespace.unwrap_or("") },
+            Some(&(_, uri)) if self.dropped(Some(uri).filter(|uri| !uri.is_empty())) => "",
+            Some(&(_, uri)) => uri,
+            None => inherited,
+        };
+        if let Some(&(key, uri)) = declared
+            && !unprefixed
+            && uri == out_default
+            && self.used.as_ref().is_some_and(|used| !used.contains(&offset_in(self.xml, key)))
+        {
+            out_default = inherited;
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.out_default = out_default;
+        }
+
+        // Byte ranges of `attrs` to replace, in order.
+        let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+        if declared.is_none() && out_default != inherited {
+            edits.push((0..0, format!(" xmlns=\"{}\"", out_default)));
+        }
+        let mut names = HashSet::new();
+        for &(key, value) in &pairs {
+            let at = offset_in(attrs, key);
+            let value_at = offset_in(attrs, value);
+            // From the whitespace before the attribute to its closing quote.
+            let whole = attrs[..at].trim_end().len()..(value_at + value.len() + 1).min(attrs.len());
+            if converting.iter().any(|name| name == key) {
+                edits.push((whole, String::new()));
+                moved.push((key.to_string(), value.to_string()));
+                continue;
+            }
+            let remove = match key.split_once(':') {
+                _ if key == "xmlns" => out_default != value && out_default == inherited,
+                Some(("xmlns", declared)) => {
+                    self.dropped(self.resolve(declared))
+                        || self.used.as_ref().is_some_and(|used| !used.contains(&offset_in(self.xml, key)))
+                }
+                _ => false,
+            };
+            if remove {
+                edits.push((whole, String::new()));
+                self.stats.removed_declarations += 1;
+                continue;
+            }
+            if key == "xmlns" && value != out_default {
+                edits.push((value_at..value_at + value.len(), out_default.to_string()));
+            }
+            let new_key = self.attribute_name(key);
+            if !names.insert(new_key.clone()) {
+                let offset = offset_in(self.xml, key);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("attribute '{}' at byte {} would become a second '{}'", key, offset, new_key),
+                ));
+            }
+            if new_key != key {
+                edits.push((at..at + key.len(), new_key));
+                self.stats.renamed_attributes += 1;
+            }
+            if !key.starts_with("xmlns")
+                && let Some(new_value) = self.attribute_value(value)
+            {
+                edits.push((value_at..value_at + value.len(), new_value));
+            }
+        }
+        let end = attrs.trim_end().len();
+        let converted = self.conversions.to_attributes.remove(&start).unwrap_or_default();
+        self.stats.elements_to_attributes += converted.len();
+        let defaults = self.conversions.default_attributes.remove(&start).unwrap_or_default();
+        for (key, value) in converted.into_iter().chain(defaults) {
+            let new_key = self.attribute_name(&key);
+            if !names.insert(new_key.clone()) {
+                return Err(invalid(format!("element at byte {} already has an attribute '{}'", start, new_key)));
+            }
+            edits.push((end..end, format!(" {}=\"{}\"", new_key, value)));
+        }
+        self.stats.attributes_to_elements += moved.len();
+        moved.extend(self.conversions.default_children.remove(&start).unwrap_or_default());
+
+        let new_name = self.tag_name(name);
+        if new_name != name {
+            self.stats.renamed_tags += 1;
+        }
+        write!(out, "<{}", new_name)?;
+        let mut copied = 0;
+        for (range, replacement) in edits {
+            out.write_all(&attrs.as_bytes()[copied..range.start])?;
+            out.write_all(replacement.as_bytes())?;
+            copied = range.end;
+        }
+        out.write_all(&attrs.as_bytes()[copied..])?;
+        let self_closing = raw.ends_with("/>");
+        if moved.is_empty() {
+            return out.write_all(if self_closing { b"/>" } else { b">" });
+        }
+        out.write_all(b">")?;
+        // New children go first, each on its own line if the next one is.
+        let after = &self.xml[start + raw.len()..];
+        let space = &after[..after.len() - after.trim_start().len()];
+        let space = if space.contains('\n') && !self_closing { space } else { "" };
+        for (key, value) in moved {
+            let child = self.tag_name(&key);
+            write!(out, "{}<{}>{}</{}>", space, child, value, child)?;
+        }
+        if self_closing {
+            write!(out, "</{}>", new_name)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the end tag for `name`, which may include trailing whitespace,
+    /// and closes its scope.
+    fn end_tag<W: Write>(&mut self, out: &mut W, name: &str) -> io::Result<()> {
+        let trimmed = name.trim_end();
+        let new_name = self.tag_name(trimmed);
+        self.scopes.pop();
+        if new_name != trimmed {
+            self.stats.renamed_tags += 1;
+        }
+        write!(out, "</{}{}>", new_name, &name[trimmed.len()..])
+    }
+}
+
+/// Copies `xml` to `out` with the rules in `options` applied.
+pub fn transform<W: Write>(xml: &str, options: &TransformOptions, out: &mut W) -> io::Result<TransformStats> {
+    let mut stats = TransformStats::default();
+    let conversions = find_conversions(xml, options, &mut stats)?;
+    let mut transformer = Transformer {
+        xml,
+        options,
+        used: options.prune_namespaces.then(|| used_declarations(xml)),
+        scopes: Vec::new(),
+        conversions,
+        spellings: Spellings::default(),
+        stats,
+    };
+    let mut copied = 0;
+    let mut result = Ok(());
+    // Depth inside a child element that became an attribute.
+    let mut skipping = 0;
+    stream_xml_spans(xml, |token, span| {
+        if skipping > 0 {
+            match token {
+                Token::StartTag(..) => skipping += 1,
+                Token::EndTag(_) => skipping -= 1,
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            if skipping == 0 {
+                copied = span.end;
+            }
+            return Continue(());
+        }
+        if let Token::StartTag(..) = token
+            && transformer.conversions.removed.contains_key(&span.start)
+        {
+            // Drop the element with the indentation before it.
+            result = out.write_all(xml[copied..span.start].trim_end().as_bytes());
+            skipping = 1;
+            return if result.is_err() { Break(()) } else { Continue(()) };
+        }
+        let written = match token {
+            // The empty end tag after a self-closing tag has nothing to write.
+            Token::EndTag(_) if span.is_empty() => {
+                transformer.scopes.pop();
+                return Continue(());
+            }
+            Token::Text(_) | Token::CData(_) => return Continue(()),
+            Token::StartTag(name, attrs) => out
+                .write_all(&xml.as_bytes()[copied..span.start])
+                .and_then(|_| transformer.start_tag(out, &xml[span.clone()], name, attrs)),
+            Token::EndTag(name) => out.write_all(&xml.as_bytes()[copied..span.start]).and_then(|_| transformer.end_tag(out, name)),
+        };
+        copied = span.end;
+        result = written;
+        if result.is_err() { Break(()) } else { Continue(()) }
+    });
+    result?;
+    out.write_all(&xml.as_bytes()[copied..])?;
+    Ok(transformer.stats)
+}
@@ -0,0 +1,174 @@
+use super::state::{PromptKind, SortKey, YankKind};
+use crate::sandbox::Capability;
+
+/// Everything the navigator can do, whether triggered by a key or a script line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Down,
+    Up,
+    Enter,
+    Back,
+    /// Goes up this many levels at once, stopping at the root.
+    Ascend(usize),
+    Root,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    ToggleInfo,
+    ToggleCardinality,
+    ToggleSource,
+    ToggleTree,
+    ToggleTable,
+    ToggleGrouping,
+    /// Moves the table column under the cursor right (true) or left.
+    MoveColumn(bool),
+    HideColumn,
+    FreezeColumn,
+    /// Shows only the named table columns, in that order; empty shows all.
+    TableColumns(String),
+    /// Writes the table as shown (columns and row order) to a CSV file.
+    ExportTable(String),
+    /// Adds the table's cursor column to the sort keys, or flips or drops it.
+    AddSortColumn,
+    /// Filters the table's cursor column, e.g. `>=10`, `1..5` or `!draft`.
+    FilterColumn(String),
+    ToggleHexdump,
+    /// Shows the selected element's full text, wrapped and scrollable.
+    ToggleText,
+    /// Shows the selected element's prose as wrapped paragraphs under headings.
+    ToggleReading,
+    /// Shows or hides the tags of inline elements in the reading view.
+    ToggleMarkers,
+    NextSection,
+    PrevSection,
+    ToggleDiagnostics,
+    NextDiagnostic,
+    PrevDiagnostic,
+    CycleAttributes,
+    /// Shows only the named attributes (comma or space separated); empty shows all.
+    FilterAttributes(String),
+    Sort(SortKey),
+    CycleSort,
+    /// Lists only the children matching the text; empty lists all.
+    FilterChildren(String),
+    ToggleMark,
+    ClearMarks,
+    SwitchFocus,
+    FlipSplit,
+    /// Shows the next or previous open document in the first pane.
+    NextTab,
+    PrevTab,
+    /// Opens an input prompt; the submitted text becomes another action.
+    Prompt(PromptKind),
+    SelectRegex(String),
+    ExportSelected(String),
+    /// Sets the selected leaf element's text, or an attribute with `@name=value`.
+    Edit(String),
+    /// Stages deleting the selected element, or drops its staged change.
+    Delete,
+    /// Stages another copy of the selected element after it.
+    Duplicate,
+    /// Copies the selected element's path, text or XML to the clipboard.
+    Yank(YankKind),
+    /// A hook-style command handled by `TuiState::run_command`.
+    Command(String),
+    RecordMacro(char),
+    StopRecording,
+    ReplayMacro(char),
+    /// Reopens the primary document from disk, keeping the location.
+    Reload,
+}
+
+impl Action {
+    /// The sandboxed capability this action needs, if any.
+    pub fn capability(&self) -> Option<Capability> {
+        match self {
+            Action::Prompt(PromptKind::ExportFile | PromptKind::ExportTable | PromptKind::Edit)
+            | Action::ExportSelected(_)
+            | Action::ExportTable(_)
+            | Action::Edit(_) => Some(Capability::WriteFiles),
+            Action::Command(command) if matches!(command.split_whitespace().next(), Some("export" | "w")) => {
+                Some(Capability::WriteFiles)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses one script line, e.g. `down`, `select ^item$` or `export out.xml`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        let action = match name {
+            "quit" => Action::Quit,
+            "down" => Action::Down,
+            "up" => Action::Up,
+            "enter" => Action::Enter,
+            "back" => Action::Back,
+            "ascend" => Action::Ascend(arg.parse().map_err(|_| format!("expected a number of levels, got '{}'", arg))?),
+            "root" => Action::Root,
+            "page-up" => Action::PageUp,
+            "page-down" => Action::PageDown,
+            "home" => Action::Home,
+            "end" => Action::End,
+            "info" => Action::ToggleInfo,
+            "cardinality" => Action::ToggleCardinality,
+            "source" => Action::ToggleSource,
+            "tree" => Action::ToggleTree,
+            "table" => Action::ToggleTable,
+            "group" => Action::ToggleGrouping,
+            "move-column-left" => Action::MoveColumn(false),
+            "move-column-right" => Action::MoveColumn(true),
+            "hide-column" => Action::HideColumn,
+            "freeze-column" => Action::FreezeColumn,
+            "columns" => Action::TableColumns(arg.to_string()),
+            "export-table" => Action::ExportTable(arg.to_string()),
+            "column-filter" => Action::FilterColumn(arg.to_string()),
+            "add-sort" => Action::AddSortColumn,
+            "hexdump" => Action::ToggleHexdump,
+            "text" => Action::ToggleText,
+            "reading" => Action::ToggleReading,
+            "markers" => Action::ToggleMarkers,
+            "next-section" => Action::NextSection,
+            "prev-section" => Action::PrevSection,
+            "diagnostics" => Action::ToggleDiagnostics,
+            "next-diagnostic" => Action::NextDiagnostic,
+            "prev-diagnostic" => Action::PrevDiagnostic,
+            "attributes" => Action::CycleAttributes,
+            "attr-filter" => Action::FilterAttributes(arg.to_string()),
+            "sort" => Action::Sort(SortKey::parse(arg)?),
+            "cycle-sort" => Action::CycleSort,
+            "filter" => Action::FilterChildren(arg.to_string()),
+            "mark" => Action::ToggleMark,
+            "clear-marks" => Action::ClearMarks,
+            "focus" => Action::SwitchFocus,
+            "flip" => Action::FlipSplit,
+            "next-tab" => Action::NextTab,
+            "prev-tab" => Action::PrevTab,
+            "select" => Action::SelectRegex(arg.to_string()),
+            "yank-path" => Action::Yank(YankKind::Path),
+            "yank-text" => Action::Yank(YankKind::Text),
+            "yank-xml" => Action::Yank(YankKind::Xml),
+            "export-selected" => Action::ExportSelected(arg.to_string()),
+            "edit" => Action::Edit(arg.to_string()),
+            "delete" => Action::Delete,
+            "duplicate" => Action::Duplicate,
+            "export" | "query" | "offset" | "line" | "w" => Action::Command(line.to_string()),
+            "record" => Action::RecordMacro(register(arg)?),
+            "stop-recording" => Action::StopRecording,
+            "replay" => Action::ReplayMacro(register(arg)?),
+            _ => return Err(format!("unknown action '{}'", name)),
+        };
+        Ok(action)
+    }
+}
+
+fn register(arg: &str) -> Result<char, String> {
+    let mut chars = arg.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("expected a single-character register, got '{}'", arg)),
+    }
+}
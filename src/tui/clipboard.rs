@@ -0,0 +1,77 @@
+//! Copying yanked text out of the navigator.
+//!
+//! The text is sent to the terminal as an OSC 52 sequence, which most
+//! terminal emulators forward to the system clipboard, also over SSH. With
+//! the `clipboard` feature the platform's clipboard tool (`pbcopy`,
+//! `wl-copy`, `xclip`, `xsel` or `clip.exe`) is tried first.
+
+use std::io::{self, Write};
+
+/// Copies `text`, returning how it was delivered.
+pub fn copy(text: &str) -> io::Result<&'static str> {
+    #[cfg(feature = "clipboard")]
+    if crate::sandbox::check(crate::sandbox::Capability::RunCommands).is_ok()
+        && let Some(tool) = system::copy(text)
+    {
+        return Ok(tool);
+    }
+    osc52(text)?;
+    Ok("OSC 52")
+}
+
+/// Writes the OSC 52 "set clipboard" sequence, wrapped for tmux when inside it.
+fn osc52(text: &str) -> io::Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    let mut out = io::stdout().lock();
+    if std::env::var_os("TMUX").is_some() {
+        write!(out, "\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))?;
+    } else {
+        out.write_all(sequence.as_bytes())?;
+    }
+    out.flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(feature = "clipboard")]
+mod system {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    const TOOLS: &[(&str, &[&str])] = &[
+        ("pbcopy", &[]),
+        ("wl-copy", &[]),
+        ("xclip", &["-selection", "clipboard"]),
+        ("xsel", &["--clipboard", "--input"]),
+        ("clip.exe", &[]),
+    ];
+
+    /// Pipes `text` into the first clipboard tool that runs successfully.
+    pub fn copy(text: &str) -> Option<&'static str> {
+        TOOLS.iter().find_map(|(tool, args)| {
+            let mut child = Command::new(tool)
+                .args(*args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let written = child.stdin.take()?.write_all(text.as_bytes()).is_ok();
+            (child.wait().ok()?.success() && written).then_some(*tool)
+        })
+    }
+}
@@ -0,0 +1,57 @@
+//! Side-by-side comparison (`xmz diff --tui`): each pane marks the elements
+//! that differ from the other document and knows their counterparts there,
+//! so that the other pane can follow the selection.
+
+use crate::diff::Diff;
+use std::collections::HashMap;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    /// Not in the other document.
+    OnlyHere,
+    /// Attributes or text differ.
+    Changed,
+    /// Something below the element differs.
+    ChangedInside,
+}
+
+pub struct DiffMarks {
+    /// Whether this pane shows the new document.
+    pub new: bool,
+    marks: HashMap<usize, Mark>,
+    /// Spans of the paired elements here, in document order, with the start
+    /// offsets of their counterparts.
+    pairs: Vec<(Range<usize>, usize)>,
+}
+
+impl DiffMarks {
+    /// Marks for the pane showing the old (`new` false) or new document.
+    pub fn build(diff: &Diff, new: bool) -> Self {
+        let only_here = if new { &diff.added } else { &diff.removed };
+        let mut marks: HashMap<usize, Mark> = only_here.iter().map(|&offset| (offset, Mark::OnlyHere)).collect();
+        let mut pairs = Vec::with_capacity(diff.pairs.len());
+        for pair in &diff.pairs {
+            let (here, there) = if new { (&pair.new, &pair.old) } else { (&pair.old, &pair.new) };
+            if pair.changed {
+                marks.insert(here.start, Mark::Changed);
+            } else if pair.changed_inside {
+                marks.insert(here.start, Mark::ChangedInside);
+            }
+            pairs.push((here.clone(), there.start));
+        }
+        pairs.sort_by_key(|(span, _)| span.start);
+        Self { new, marks, pairs }
+    }
+
+    pub fn mark(&self, offset: usize) -> Option<Mark> {
+        self.marks.get(&offset).copied()
+    }
+
+    /// The start offset in the other document of the element starting at
+    /// `offset`, or of its innermost ancestor that is in both.
+    pub fn counterpart(&self, offset: usize) -> Option<usize> {
+        let end = self.pairs.partition_point(|(span, _)| span.start <= offset);
+        self.pairs[..end].iter().rev().find(|(span, _)| span.contains(&offset)).map(|&(_, there)| there)
+    }
+}
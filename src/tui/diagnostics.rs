@@ -0,0 +1,98 @@
+use super::action::Action;
+use super::theme;
+use crate::check::{Diagnostic, Severity, well_formedness};
+use ratatui::prelude::*;
+use ratatui::widgets::{ListItem, ListState};
+
+/// Problems found by `check::well_formedness` (`!`), listed below the
+/// navigator. While open it takes the movement keys; Enter jumps to the
+/// selected problem.
+pub struct DiagnosticsPanel {
+    pub open: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    pub list_state: ListState,
+    /// Index of the problem last jumped to, where `]d` and `[d` continue from.
+    pub cursor: Option<usize>,
+}
+
+impl DiagnosticsPanel {
+    pub fn check(xml: &str) -> Self {
+        let diagnostics = well_formedness(xml);
+        let mut list_state = ListState::default();
+        list_state.select((!diagnostics.is_empty()).then_some(0));
+        Self {
+            open: true,
+            diagnostics,
+            list_state,
+            cursor: None,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Diagnostic> {
+        self.diagnostics.get(self.list_state.selected()?)
+    }
+
+    /// The problem after (or before) the last one jumped to; without one,
+    /// the first at or after (or before) byte `from`. Selects it.
+    pub fn step(&mut self, forward: bool, from: usize) -> Option<&Diagnostic> {
+        let next = match (self.cursor, forward) {
+            (Some(i), true) => i + 1,
+            (Some(i), false) => i.checked_sub(1)?,
+            (None, true) => self.diagnostics.iter().position(|d| d.offset >= from)?,
+            (None, false) => self.diagnostics.iter().rposition(|d| d.offset < from)?,
+        };
+        let diagnostic = self.diagnostics.get(next)?;
+        self.cursor = Some(next);
+        self.list_state.select(Some(next));
+        Some(diagnostic)
+    }
+
+    /// Moves the selection for navigation keys. Returns whether `action` was used.
+    pub fn move_action(&mut self, action: &Action) -> bool {
+        let last = self.diagnostics.len().saturating_sub(1);
+        let current = self.list_state.selected().unwrap_or(0);
+        let next = match action {
+            Action::Down => current.saturating_add(1),
+            Action::Up => current.saturating_sub(1),
+            Action::PageDown => current.saturating_add(10),
+            Action::PageUp => current.saturating_sub(10),
+            Action::Home => 0,
+            Action::End => last,
+            _ => return false,
+        };
+        if !self.diagnostics.is_empty() {
+            self.list_state.select(Some(next.min(last)));
+        }
+        true
+    }
+
+    /// "2 errors, 1 warning" style summary.
+    pub fn summary(&self) -> String {
+        if self.diagnostics.is_empty() {
+            return "no problems found".to_string();
+        }
+        let count = |severity| self.diagnostics.iter().filter(|d| d.severity == severity).count();
+        let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+        format!("{}, {}", plural(count(Severity::Error), "error"), plural(count(Severity::Warning), "warning"))
+    }
+
+    pub fn items(&self) -> Vec<ListItem<'static>> {
+        let theme = theme::current();
+        self.diagnostics
+            .iter()
+            .map(|d| {
+                let color = match d.severity {
+                    Severity::Error => theme.error,
+                    Severity::Warning => theme.warning,
+                    Severity::Info => theme.accent,
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{:>10}  ", format!("{}:{}", d.line, d.column)), Style::default().fg(theme.muted)),
+                    Span::styled(format!("{}[{}] ", d.severity.as_str(), d.code), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+                    Span::styled(d.message.clone(), Style::default().fg(theme.foreground)),
+                    Span::styled(format!("  {}", d.path), Style::default().fg(theme.tag)),
+                ]))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,287 @@
+use super::terminal::{restore_terminal, setup_terminal};
+use super::theme;
+use super::{Screen, navigate};
+use crate::du::format_bytes;
+use crate::index::Index;
+use crate::parser::{check_limits, limits};
+use crate::source::load;
+use crate::watch::DirWatcher;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::{Duration, SystemTime};
+
+/// Files modified this recently are highlighted as fresh.
+const FRESH: Duration = Duration::from_secs(15 * 60);
+
+enum IndexStatus {
+    Queued,
+    Indexed(usize),
+    Failed(String),
+}
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    modified: SystemTime,
+    /// Appeared or changed while the directory was being watched.
+    new: bool,
+    status: IndexStatus,
+    index: Option<Index>,
+}
+
+/// XML files by name, including compressed ones.
+fn is_xml(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    let name = [".gz", ".zst", ".bz2"].iter().find_map(|ext| name.strip_suffix(ext)).unwrap_or(&name);
+    name.ends_with(".xml")
+}
+
+fn index_file(path: &Path) -> Result<Index, String> {
+    let name = path.to_str().ok_or("file name is not valid UTF-8")?;
+    let document = load(name).map_err(|err| err.to_string())?;
+    let text = document.text().map_err(|err| err.to_string())?;
+    check_limits(&text, limits())?;
+    Ok(Index::load_or_build(path, &text))
+}
+
+/// The watched directory's XML files, newest first.
+struct Listing {
+    entries: Vec<Entry>,
+    list_state: ListState,
+    jobs: Sender<PathBuf>,
+    message: Option<String>,
+}
+
+impl Listing {
+    /// Adds, refreshes or drops the entry for `path` and queues it for indexing.
+    fn update(&mut self, path: &Path, new: bool) {
+        if !is_xml(path) {
+            return;
+        }
+        let selected = self.selected().map(|e| e.path.clone());
+        self.entries.retain(|e| e.path != path);
+        if let Ok(metadata) = std::fs::metadata(path)
+            && metadata.is_file()
+        {
+            self.entries.push(Entry {
+                path: path.to_path_buf(),
+                name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                new,
+                status: IndexStatus::Queued,
+                index: None,
+            });
+            let _ = self.jobs.send(path.to_path_buf());
+        }
+        self.entries.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.name.cmp(&b.name)));
+        let position = selected.and_then(|path| self.entries.iter().position(|e| e.path == path));
+        self.list_state.select(position.or((!self.entries.is_empty()).then_some(0)));
+    }
+
+    fn indexed(&mut self, path: &Path, result: Result<Index, String>) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.path == path) {
+            match result {
+                Ok(index) => {
+                    entry.status = IndexStatus::Indexed(index.len());
+                    entry.index = Some(index);
+                }
+                Err(err) => entry.status = IndexStatus::Failed(err),
+            }
+        }
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.list_state.selected()?)
+    }
+
+    fn select_by(&mut self, delta: isize) {
+        let last = self.entries.len().saturating_sub(1);
+        let current = self.list_state.selected().unwrap_or(0);
+        self.list_state.select(Some(current.saturating_add_signed(delta).min(last)));
+    }
+}
+
+/// Lists the XML files in `dir`, watching for new ones and indexing them in
+/// the background; Enter opens a file in the navigator, `q` there returns.
+pub fn run_directory(dir: &Path) -> io::Result<()> {
+    let mut watcher = DirWatcher::new(dir)?;
+    let (jobs, queue) = channel::<PathBuf>();
+    let (finished, results) = channel();
+    std::thread::spawn(move || {
+        for path in queue {
+            let result = index_file(&path);
+            if finished.send((path, result)).is_err() {
+                break;
+            }
+        }
+    });
+    let mut listing = Listing {
+        entries: Vec::new(),
+        list_state: ListState::default(),
+        jobs,
+        message: None,
+    };
+    let mut existing: Vec<_> = watcher.scan()?.into_iter().collect();
+    existing.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+    for (path, _) in existing {
+        listing.update(&path, false);
+    }
+
+    let mut terminal = setup_terminal()?;
+    let result = watch_loop(&mut terminal, &mut watcher, &mut listing, &results);
+    restore_terminal()?;
+    result
+}
+
+fn watch_loop(terminal: &mut Screen, watcher: &mut DirWatcher, listing: &mut Listing, results: &Receiver<(PathBuf, Result<Index, String>)>) -> io::Result<()> {
+    loop {
+        for path in watcher.changes()? {
+            listing.update(&path, true);
+        }
+        while let Ok((path, result)) = results.try_recv() {
+            listing.indexed(&path, result);
+        }
+        terminal.draw(|f| draw_listing(f, watcher.dir(), listing))?;
+
+        if !event::poll(Duration::from_millis(500))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        listing.message = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Down => listing.select_by(1),
+            KeyCode::Up => listing.select_by(-1),
+            KeyCode::PageDown => listing.select_by(10),
+            KeyCode::PageUp => listing.select_by(-10),
+            KeyCode::Home => listing.list_state.select(Some(0)),
+            KeyCode::End => listing.select_by(isize::MAX),
+            KeyCode::Enter | KeyCode::Right => {
+                if let Err(err) = open_selected(terminal, listing) {
+                    listing.message = Some(err.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn open_selected(terminal: &mut Screen, listing: &mut Listing) -> io::Result<()> {
+    let Some(position) = listing.list_state.selected() else {
+        return Ok(());
+    };
+    let entry = &mut listing.entries[position];
+    let name = entry.path.to_str().ok_or_else(|| io::Error::other("file name is not valid UTF-8"))?;
+    let document = load(name)?;
+    let text = document.text()?;
+    check_limits(&text, limits()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, err)))?;
+    let index = match entry.index.take() {
+        Some(index) if index.matches(&text) => index,
+        _ => Index::load_or_build(&entry.path, &text),
+    };
+    entry.new = false;
+    navigate(terminal, &text, Some(index))?;
+    terminal.clear()
+}
+
+/// "3m ago" style age of `time`.
+fn age(time: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(time).unwrap_or_default().as_secs();
+    match secs {
+        0..60 => format!("{}s ago", secs),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn draw_listing(f: &mut Frame, dir: &Path, listing: &mut Listing) {
+    let theme = theme::current();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+        .split(f.size());
+
+    let now = SystemTime::now();
+    let items: Vec<ListItem> = listing
+        .entries
+        .iter()
+        .map(|entry| {
+            let fresh = now.duration_since(entry.modified).is_ok_and(|age| age < FRESH);
+            let marker = match (entry.new, fresh) {
+                (true, _) => Span::styled("NEW ", Style::default().fg(theme.background).bg(theme.mark).add_modifier(Modifier::BOLD)),
+                (false, true) => Span::styled(" ●  ", Style::default().fg(theme.mark)),
+                (false, false) => Span::raw("    "),
+            };
+            let status = match &entry.status {
+                IndexStatus::Queued => Span::styled("indexing…", Style::default().fg(theme.muted)),
+                IndexStatus::Indexed(n) => Span::styled(format!("{} elements", n), Style::default().fg(theme.count)),
+                IndexStatus::Failed(err) => Span::styled(err.clone(), Style::default().fg(theme.error)),
+            };
+            ListItem::new(Line::from(vec![
+                marker,
+                Span::styled(format!("{:<40} ", entry.name), Style::default().fg(theme.tag).add_modifier(Modifier::BOLD)),
+                Span::styled(format!("{:>10}  ", format_bytes(entry.size)), Style::default().fg(theme.count)),
+                Span::styled(format!("{:>8}  ", age(entry.modified)), Style::default().fg(if fresh { theme.mark } else { theme.border })),
+                status,
+            ]))
+        })
+        .collect();
+    let items = if items.is_empty() {
+        vec![ListItem::new(Span::styled(
+            "(No XML files yet; waiting for new ones)",
+            Style::default().fg(theme.muted).add_modifier(Modifier::ITALIC),
+        ))]
+    } else {
+        items
+    };
+
+    let block = Block::default()
+        .title(Line::from(vec![
+            Span::styled(" XML Tree Navigator ", theme.title),
+            Span::raw("  "),
+            Span::styled(
+                format!("Watching {}  [{} files]", dir.display(), listing.entries.len()),
+                Style::default().fg(theme.accent).add_modifier(Modifier::BOLD),
+            ),
+        ]))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.border))
+        .bg(theme.background);
+    let list = List::new(items)
+        .block(block)
+        .highlight_symbol("→ ")
+        .highlight_style(theme.highlight)
+        .bg(theme.background);
+    f.render_stateful_widget(list, chunks[0], &mut listing.list_state);
+
+    let bottom = match &listing.message {
+        Some(message) => Line::from(Span::styled(message.clone(), Style::default().fg(theme.warning))),
+        None => {
+            let key_style = Style::default().fg(theme.accent).add_modifier(Modifier::BOLD);
+            Line::from(vec![
+                Span::raw("Use "),
+                Span::styled("↑/↓", key_style),
+                Span::raw(" to move, "),
+                Span::styled("Enter", key_style),
+                Span::raw(" to open (q there returns), "),
+                Span::styled("q", key_style),
+                Span::raw(" to quit."),
+            ])
+        }
+    };
+    f.render_widget(Paragraph::new(bottom.alignment(Alignment::Center)), chunks[1]);
+}
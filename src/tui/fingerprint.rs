@@ -0,0 +1,127 @@
+//! Fingerprints: the shape of the selected element at a glance, shown in the
+//! status bar as the cursor moves. A large element is counted a piece at a
+//! time between key presses, so moving onto the root of a huge document
+//! does not freeze the navigator, and each count is kept for the next visit.
+
+use crate::parser::attribute_pairs;
+use crate::xml::Node;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use xmz_core::{Continue, Token, tokenize};
+
+/// Bytes tokenized per step of a count.
+const CHUNK: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub attributes: usize,
+    pub children: usize,
+    pub bytes: usize,
+    /// Levels of elements below the element, its height; 0 for a leaf.
+    pub height: usize,
+}
+
+/// A fingerprint being counted, up to `position` so far.
+struct Count {
+    offset: usize,
+    span: Range<usize>,
+    position: usize,
+    /// Nesting at `position`, the element itself being 1.
+    nesting: usize,
+    chunk: usize,
+    fingerprint: Fingerprint,
+}
+
+impl Count {
+    fn new(node: &Node) -> Self {
+        Count {
+            offset: node.offset,
+            span: node.span.clone(),
+            position: node.span.start,
+            nesting: 0,
+            chunk: CHUNK,
+            fingerprint: Fingerprint {
+                attributes: attribute_pairs(node.attributes_raw).len(),
+                bytes: node.span.len(),
+                ..Fingerprint::default()
+            },
+        }
+    }
+
+    /// Tokenizes the next chunk; returns whether the element is done.
+    fn step(&mut self, xml: &str) -> bool {
+        let mut end = (self.position + self.chunk).min(self.span.end);
+        while !xml.is_char_boundary(end) {
+            end += 1;
+        }
+        let last = end == self.span.end;
+        let (mut nesting, fingerprint) = (self.nesting, &mut self.fingerprint);
+        let consumed = tokenize(&xml[self.position..end], last, &mut |token, _| {
+            match token {
+                Token::StartTag(..) => {
+                    nesting += 1;
+                    if nesting == 2 {
+                        fingerprint.children += 1;
+                    }
+                    fingerprint.height = fingerprint.height.max(nesting - 1);
+                }
+                Token::EndTag(_) => nesting = nesting.saturating_sub(1),
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            Continue(())
+        });
+        self.nesting = nesting;
+        match consumed {
+            _ if last => return true,
+            // A token longer than the chunk: read more at once.
+            Continue(0) => self.chunk *= 2,
+            Continue(consumed) => self.position += consumed,
+            _ => return true,
+        }
+        false
+    }
+}
+
+/// The fingerprints counted in one document, by element offset, and the
+/// one being counted.
+#[derive(Default)]
+pub struct Fingerprints {
+    counted: HashMap<usize, Fingerprint>,
+    count: Option<Count>,
+}
+
+impl Fingerprints {
+    /// The fingerprint of the element at `offset`, and whether it is still
+    /// being counted (its numbers so far).
+    pub fn get(&self, offset: usize) -> Option<(Fingerprint, bool)> {
+        if let Some(fingerprint) = self.counted.get(&offset) {
+            return Some((*fingerprint, false));
+        }
+        self.count.as_ref().filter(|count| count.offset == offset).map(|count| (count.fingerprint, true))
+    }
+
+    /// Counts `node`, dropping the count of an element no longer selected,
+    /// for up to `budget`, or to the end without one. Returns whether it is
+    /// still being counted.
+    pub fn update(&mut self, xml: &str, node: &Node, budget: Option<Duration>) -> bool {
+        if self.counted.contains_key(&node.offset) {
+            return false;
+        }
+        if self.count.as_ref().is_none_or(|count| count.offset != node.offset) {
+            self.count = Some(Count::new(node));
+        }
+        let started = Instant::now();
+        let Some(count) = self.count.as_mut() else {
+            return false;
+        };
+        while !count.step(xml) {
+            if budget.is_some_and(|budget| started.elapsed() >= budget) {
+                return true;
+            }
+        }
+        self.counted.insert(count.offset, count.fingerprint);
+        self.count = None;
+        false
+    }
+}
@@ -0,0 +1,68 @@
+//! `xmz tui FILE --follow`: like `tail -f`, reopens the document whenever the
+//! file grows, keeping the navigation where it was.
+
+use super::input::handle_input;
+use super::panes::PaneManager;
+use super::state::TuiState;
+use super::terminal::{restore_terminal, setup_terminal};
+use super::ui::draw_ui;
+use super::Screen;
+use crate::source::load;
+use crossterm::event;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Navigates the file at `path`, reloading it as it grows, until `q`.
+pub fn run_follow(path: &Path) -> io::Result<()> {
+    let name = path.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "file name is not valid UTF-8"))?;
+    let text = load(name)?.text()?.into_owned();
+    let mut terminal = setup_terminal()?;
+    let result = follow_loop(&mut terminal, name, text);
+    restore_terminal()?;
+    result
+}
+
+fn follow_loop(terminal: &mut Screen, name: &str, mut text: String) -> io::Result<()> {
+    let mut location = Vec::new();
+    let mut message = None;
+    loop {
+        let mut panes = PaneManager::new(TuiState::new(&text));
+        let state = &mut panes.panes[0];
+        state.restore_location(&location);
+        state.message = message.take();
+        let Some(grown) = watch(terminal, &mut panes, name, text.len())? else {
+            return Ok(());
+        };
+        location = panes.panes[0].location();
+        message = Some(if grown.len() >= text.len() {
+            format!("{} grew by {} bytes", name, grown.len() - text.len())
+        } else {
+            format!("{} shrank by {} bytes", name, text.len() - grown.len())
+        });
+        text = grown;
+    }
+}
+
+/// Runs the navigator until the user quits (`None`) or the file no longer
+/// has `len` bytes, returning its new text. A file caught halfway through
+/// writing a character is read again on the next tick; while a prompt is
+/// open, the reload waits for it to close.
+fn watch(terminal: &mut Screen, panes: &mut PaneManager, name: &str, len: usize) -> io::Result<Option<String>> {
+    loop {
+        terminal.draw(|f| draw_ui(f, panes))?;
+
+        if panes.panes[0].prompt.is_none()
+            && std::fs::metadata(name)?.len() != len as u64
+            && let Ok(text) = load(name)?.text()
+        {
+            return Ok(Some(text.into_owned()));
+        }
+
+        if event::poll(Duration::from_millis(200))?
+            && !handle_input(event::read()?, panes)
+        {
+            return Ok(None);
+        }
+    }
+}
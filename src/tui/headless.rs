@@ -0,0 +1,75 @@
+use super::action::Action;
+use super::panes::PaneManager;
+use super::ui::draw_ui;
+use ratatui::{Terminal, backend::TestBackend, buffer::Buffer};
+use std::io;
+
+/// Screen size used when rendering headless snapshots.
+pub const SNAPSHOT_SIZE: (u16, u16) = (100, 30);
+
+/// Executes a script of actions (one per line, `#` starts a comment) against
+/// `panes` without a terminal. A `snapshot` line captures the rendered screen;
+/// if the script has none, the final screen is captured. Returns all snapshots.
+pub fn run_script(panes: &mut PaneManager, script: &str) -> io::Result<String> {
+    let (width, height) = SNAPSHOT_SIZE;
+    let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+    let mut snapshots = Vec::new();
+
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "snapshot" {
+            settle(panes);
+            terminal.draw(|f| draw_ui(f, panes))?;
+            snapshots.push(buffer_to_text(terminal.backend().buffer()));
+            continue;
+        }
+        let action = Action::parse(line).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("script line {}: {}", number + 1, err))
+        })?;
+        // Render between steps so list offsets behave as they do interactively.
+        settle(panes);
+        terminal.draw(|f| draw_ui(f, panes))?;
+        if !panes.apply(action) {
+            break;
+        }
+    }
+
+    if snapshots.is_empty() {
+        settle(panes);
+        terminal.draw(|f| draw_ui(f, panes))?;
+        snapshots.push(buffer_to_text(terminal.backend().buffer()));
+    }
+
+    let mut out = String::new();
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        out.push_str(&format!("--- snapshot {} ---\n", i + 1));
+        out.push_str(snapshot);
+    }
+    Ok(out)
+}
+
+/// Waits for background table sorts and counts the selection's fingerprint
+/// to the end, so snapshots do not depend on timing.
+fn settle(panes: &mut PaneManager) {
+    for table in panes.panes.iter_mut().filter_map(|state| state.table.as_mut()) {
+        table.poll(true);
+    }
+    panes.focused_mut().update_fingerprint(None);
+}
+
+fn buffer_to_text(buffer: &Buffer) -> String {
+    let area = buffer.area;
+    let mut text = String::new();
+    for y in area.top()..area.bottom() {
+        let mut line = String::new();
+        for x in area.left()..area.right() {
+            line.push_str(buffer.get(x, y).symbol());
+        }
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
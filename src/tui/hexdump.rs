@@ -0,0 +1,96 @@
+use super::action::Action;
+use super::theme;
+use ratatui::prelude::*;
+
+/// Hexdump of the bytes around an offset (`x`), for spotting encoding
+/// damage and invisible characters. The byte at the offset is highlighted.
+pub struct HexView {
+    pub offset: usize,
+    /// First byte shown, a multiple of the row width once drawn.
+    top: Option<usize>,
+    row_width: usize,
+    rows: usize,
+}
+
+impl HexView {
+    pub fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            top: None,
+            row_width: 16,
+            rows: 16,
+        }
+    }
+
+    /// Sets the rows on screen and bytes per row; the first fit centres the offset.
+    pub fn fit(&mut self, rows: usize, row_width: usize, len: usize) {
+        self.rows = rows.max(1);
+        if self.row_width != row_width {
+            self.row_width = row_width;
+            self.top = None;
+        }
+        let top = self.top.unwrap_or_else(|| {
+            let row = self.offset / row_width;
+            row.saturating_sub(self.rows / 2) * row_width
+        });
+        let last_top = len.saturating_sub(1) / row_width * row_width;
+        self.top = Some(top.min(last_top));
+    }
+
+    /// Scrolls by rows for navigation keys. Returns whether `action` was used.
+    pub fn move_action(&mut self, action: &Action, len: usize) -> bool {
+        let rows = match action {
+            Action::Down => 1,
+            Action::Up => -1,
+            Action::PageDown => self.rows as isize,
+            Action::PageUp => -(self.rows as isize),
+            Action::Home => isize::MIN / 2,
+            Action::End => isize::MAX / 2,
+            _ => return false,
+        };
+        let top = self.top.unwrap_or(0) as isize + rows.saturating_mul(self.row_width as isize);
+        let last_top = (len.saturating_sub(1) / self.row_width * self.row_width) as isize;
+        self.top = Some(top.clamp(0, last_top.max(0)) as usize);
+        true
+    }
+
+    /// `offset  hex bytes  |ascii|` lines for the visible rows of `bytes`.
+    pub fn lines(&self, bytes: &[u8]) -> Vec<Line<'static>> {
+        let theme = theme::current();
+        let top = self.top.unwrap_or(0);
+        (0..self.rows)
+            .map(|row| top + row * self.row_width)
+            .take_while(|&start| start < bytes.len())
+            .map(|start| {
+                let end = (start + self.row_width).min(bytes.len());
+                let mut spans = vec![Span::styled(format!("{:08x}  ", start), Style::default().fg(theme.muted))];
+                for i in start..start + self.row_width {
+                    match bytes.get(i) {
+                        Some(&b) if i < end => spans.push(Span::styled(format!("{:02x} ", b), self.style(i, b))),
+                        _ => spans.push(Span::raw("   ")),
+                    }
+                }
+                spans.push(Span::styled("|", Style::default().fg(theme.muted)));
+                for (i, &b) in bytes[start..end].iter().enumerate() {
+                    let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
+                    spans.push(Span::styled(c.to_string(), self.style(start + i, b)));
+                }
+                spans.push(Span::styled("|", Style::default().fg(theme.muted)));
+                Line::from(spans)
+            })
+            .collect()
+    }
+
+    /// Highlights the offset, non-ASCII bytes and control characters.
+    fn style(&self, at: usize, b: u8) -> Style {
+        let theme = theme::current();
+        let style = match b {
+            0x80.. => Style::default().fg(theme.warning),
+            b'\n' | b'\r' | b'\t' => Style::default().fg(theme.muted),
+            0..0x20 | 0x7f => Style::default().fg(theme.error).add_modifier(Modifier::BOLD),
+            b'<' | b'>' => Style::default().fg(theme.tag),
+            _ => Style::default().fg(theme.foreground),
+        };
+        if at == self.offset { style.patch(theme.title) } else { style }
+    }
+}
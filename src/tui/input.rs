@@ -1,28 +1,136 @@
-use super::state::TuiState;
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use super::action::Action;
+use super::macros::RegisterUse;
+use super::panes::PaneManager;
+use super::state::{PromptKind, TuiState, YankKind};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 
-pub fn handle_input(event: Event, state: &mut TuiState) -> bool {
+pub fn handle_input(event: Event, panes: &mut PaneManager) -> bool {
     if let Event::Key(key_event) = event
         && key_event.kind == KeyEventKind::Press
     {
-        return handle_key_press(key_event, state);
+        if let Some(register_use) = panes.macros.pending.take() {
+            return match (key_event.code, register_use) {
+                (KeyCode::Char(c), RegisterUse::Record) => panes.apply(Action::RecordMacro(c)),
+                (KeyCode::Char(c), RegisterUse::Replay) => panes.apply(Action::ReplayMacro(c)),
+                _ => true,
+            };
+        }
+        if let Some(prefix) = panes.pending_prefix.take() {
+            let action = match (prefix, key_event.code) {
+                ('y', KeyCode::Char('p')) => Action::Yank(YankKind::Path),
+                ('y', KeyCode::Char('t')) => Action::Yank(YankKind::Text),
+                ('y', KeyCode::Char('x')) => Action::Yank(YankKind::Xml),
+                (']', KeyCode::Char('d')) => Action::NextDiagnostic,
+                ('[', KeyCode::Char('d')) => Action::PrevDiagnostic,
+                (']', KeyCode::Char('s')) => Action::NextSection,
+                ('[', KeyCode::Char('s')) => Action::PrevSection,
+                _ => return true,
+            };
+            return panes.apply(action);
+        }
+        let recording = panes.macros.recording().is_some();
+        let state = panes.focused_mut();
+        state.message = None;
+        let action = if state.prompt.is_some() {
+            handle_prompt_key(key_event, state)
+        } else {
+            match key_event.code {
+                KeyCode::Char('Q') if recording => Some(Action::StopRecording),
+                KeyCode::Char('Q') => {
+                    panes.macros.pending = Some(RegisterUse::Record);
+                    None
+                }
+                KeyCode::Char(prefix @ ('y' | ']' | '[')) => {
+                    panes.pending_prefix = Some(prefix);
+                    None
+                }
+                KeyCode::Char('@') => {
+                    panes.macros.pending = Some(RegisterUse::Replay);
+                    None
+                }
+                _ => key_action(key_event),
+            }
+        };
+        if let Some(action) = action {
+            return panes.apply(action);
+        }
     }
     true
 }
 
-fn handle_key_press(key_event: KeyEvent, state: &mut TuiState) -> bool {
+/// Edits the open prompt; returns the resulting action once it is submitted.
+fn handle_prompt_key(key_event: KeyEvent, state: &mut TuiState) -> Option<Action> {
     match key_event.code {
-        KeyCode::Char('q') => return false, // Signal to quit
-        KeyCode::Down => state.go_down(),
-        KeyCode::Up => state.go_up(),
-        KeyCode::Enter | KeyCode::Right => state.enter(),
-        KeyCode::Backspace | KeyCode::Left => state.back(),
-        KeyCode::PageUp => state.page_up(),
-        KeyCode::PageDown => state.page_down(),
-        KeyCode::Home => state.home(),
-        KeyCode::End => state.end(),
-        KeyCode::Char(' ') => state.toggle_info(),
+        KeyCode::Esc => state.cancel_prompt(),
+        KeyCode::Enter => return state.take_prompt_action(),
+        KeyCode::Backspace => {
+            if let Some(prompt) = state.prompt.as_mut() {
+                prompt.input.pop();
+            }
+        }
+        KeyCode::Char(c) => {
+            if let Some(prompt) = state.prompt.as_mut() {
+                prompt.input.push(c);
+            }
+        }
         _ => {}
     }
-    true
+    None
+}
+
+fn key_action(key_event: KeyEvent) -> Option<Action> {
+    // Raw mode turns Ctrl-C into a key press rather than an interrupt.
+    if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+        return (key_event.code == KeyCode::Char('c')).then_some(Action::Quit);
+    }
+    let action = match key_event.code {
+        KeyCode::Char('q') => Action::Quit,
+        KeyCode::Char('R') => Action::Reload,
+        KeyCode::Down => Action::Down,
+        KeyCode::Up => Action::Up,
+        KeyCode::Enter | KeyCode::Right => Action::Enter,
+        KeyCode::Backspace | KeyCode::Left => Action::Back,
+        KeyCode::Char(digit @ '1'..='9') => Action::Ascend(digit as usize - '0' as usize),
+        KeyCode::Char('g') => Action::Root,
+        KeyCode::PageUp => Action::PageUp,
+        KeyCode::PageDown => Action::PageDown,
+        KeyCode::Home => Action::Home,
+        KeyCode::End => Action::End,
+        KeyCode::Char(' ') => Action::ToggleInfo,
+        KeyCode::Char('c') => Action::ToggleCardinality,
+        KeyCode::Char('v') => Action::ToggleSource,
+        KeyCode::Char('T') => Action::ToggleTree,
+        KeyCode::Char('t') => Action::ToggleTable,
+        KeyCode::Char('G') => Action::ToggleGrouping,
+        KeyCode::Char('<') => Action::MoveColumn(false),
+        KeyCode::Char('>') => Action::MoveColumn(true),
+        KeyCode::Char('-') => Action::HideColumn,
+        KeyCode::Char('f') => Action::FreezeColumn,
+        KeyCode::Char('C') => Action::Prompt(PromptKind::TableColumns),
+        KeyCode::Char('E') => Action::Prompt(PromptKind::ExportTable),
+        KeyCode::Char('x') => Action::ToggleHexdump,
+        KeyCode::Char('o') => Action::ToggleText,
+        KeyCode::Char('r') => Action::ToggleReading,
+        KeyCode::Char('i') => Action::ToggleMarkers,
+        KeyCode::Char('!') => Action::ToggleDiagnostics,
+        KeyCode::Char('a') => Action::CycleAttributes,
+        KeyCode::Char('A') => Action::Prompt(PromptKind::AttributeFilter),
+        KeyCode::Char('s') => Action::CycleSort,
+        KeyCode::Char('S') => Action::Prompt(PromptKind::SortAttribute),
+        KeyCode::Char('/') => Action::Prompt(PromptKind::FilterChildren),
+        KeyCode::Char('m') => Action::ToggleMark,
+        KeyCode::Char('u') => Action::ClearMarks,
+        KeyCode::Char('*') => Action::Prompt(PromptKind::SelectRegex),
+        KeyCode::Char('=') => Action::Prompt(PromptKind::Edit),
+        KeyCode::Char('d') => Action::Delete,
+        KeyCode::Char('p') => Action::Duplicate,
+        KeyCode::Char('e') => Action::Prompt(PromptKind::ExportFile),
+        KeyCode::Char(':') => Action::Prompt(PromptKind::Command),
+        KeyCode::Char('w') => Action::SwitchFocus,
+        KeyCode::Char('W') => Action::FlipSplit,
+        KeyCode::Tab => Action::NextTab,
+        KeyCode::BackTab => Action::PrevTab,
+        _ => return None,
+    };
+    Some(action)
 }
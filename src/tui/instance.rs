@@ -0,0 +1,239 @@
+//! Single-instance guard for the navigator.
+//!
+//! The first `xmz` to open a file listens on a socket named after it in the
+//! temporary directory. A second one opening the same file connects to it and
+//! can hand over instead of loading and indexing the document again: it sends
+//! script action lines (such as its `--on-open` query), which the running
+//! instance applies before ringing the terminal bell. Unix only; elsewhere
+//! every run opens its own window.
+
+use super::action::Action;
+use std::io::{self, BufRead, Write};
+
+/// Outcome of looking for a running instance on a file.
+pub enum Claim {
+    /// No other instance has the file open; this one now answers for it.
+    Owner(Listener),
+    /// Another instance has the file open.
+    Running(Peer),
+    /// No guard could be set up (unsupported platform, or the socket is unusable).
+    Unguarded,
+}
+
+/// What a later instance handed over.
+pub struct Handover {
+    pub pid: u32,
+    pub actions: Vec<Action>,
+}
+
+/// Asks what to do about the instance `peer` that already has `file` open:
+/// hand `actions` over to it (the default), open another copy, or quit.
+/// Returns whether this process should go on and open the file.
+pub fn offer_handover(file: &str, peer: Peer, actions: &[String]) -> io::Result<bool> {
+    use std::io::IsTerminal;
+    let mut answer = String::new();
+    if io::stdin().is_terminal() {
+        eprint!(
+            "xmz: {} is already open in another xmz (pid {}).\n[Enter] switch to it, [o] open another copy, [q] quit: ",
+            file, peer.pid
+        );
+        io::stderr().flush()?;
+        io::stdin().lock().read_line(&mut answer)?;
+    }
+    match answer.trim() {
+        "o" | "O" => Ok(true),
+        "q" | "Q" => Ok(false),
+        _ => {
+            let pid = peer.pid;
+            peer.hand_over(actions)?;
+            eprintln!("xmz: handed over to the xmz showing {} (pid {})", file, pid);
+            Ok(false)
+        }
+    }
+}
+
+/// Parses a handover: `hello <pid>` followed by one action per line.
+/// Lines that are not actions, and actions that write files or quit, are ignored.
+fn parse_handover(text: &str) -> Option<Handover> {
+    let mut lines = text.lines();
+    let pid = lines.next()?.strip_prefix("hello ")?.trim().parse().ok()?;
+    let actions = lines
+        .filter_map(|line| Action::parse(line).ok())
+        .filter(|action| action.capability().is_none() && *action != Action::Quit)
+        .collect();
+    Some(Handover { pid, actions })
+}
+
+#[cfg(unix)]
+pub use unix::{Listener, Peer, claim};
+
+#[cfg(unix)]
+mod unix {
+    use super::{Claim, Handover, parse_handover};
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    /// How long to wait for a running instance to answer before giving up on it.
+    const GREETING_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// The running instance's end: accepts later instances without blocking.
+    pub struct Listener {
+        listener: UnixListener,
+        path: PathBuf,
+        /// Connections still sending, with what they have sent so far.
+        pending: Vec<(UnixStream, Vec<u8>)>,
+    }
+
+    /// A connection to the instance that already has the file open.
+    pub struct Peer {
+        pub pid: u32,
+        stream: UnixStream,
+    }
+
+    /// Socket for `file`: per user and per canonical path, so renamed or
+    /// relative spellings of the same file meet at the same socket.
+    fn socket_path(file: &Path) -> PathBuf {
+        let canonical = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        // FNV-1a, stable across builds unlike the std hasher.
+        let hash = canonical
+            .to_string_lossy()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+        let user = std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).unwrap_or_default();
+        std::env::temp_dir().join(format!("xmz-{}-{:016x}.sock", user, hash))
+    }
+
+    /// Connects to the instance that has `file` open, or starts answering for it.
+    pub fn claim(file: &Path) -> Claim {
+        let path = socket_path(file);
+        match UnixStream::connect(&path) {
+            Ok(stream) => match greeting(&stream) {
+                Some(pid) => return Claim::Running(Peer { pid, stream }),
+                None => return Claim::Unguarded,
+            },
+            // Nothing listens there any more: the owner died without cleaning up.
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                let _ = std::fs::remove_file(&path);
+            }
+            Err(_) => {}
+        }
+        let Ok(listener) = UnixListener::bind(&path) else {
+            return Claim::Unguarded;
+        };
+        if listener.set_nonblocking(true).is_err() {
+            let _ = std::fs::remove_file(&path);
+            return Claim::Unguarded;
+        }
+        Claim::Owner(Listener {
+            listener,
+            path,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Reads the running instance's `xmz <pid>` greeting.
+    fn greeting(stream: &UnixStream) -> Option<u32> {
+        stream.set_read_timeout(Some(GREETING_TIMEOUT)).ok()?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).ok()?;
+        line.strip_prefix("xmz ")?.trim().parse().ok()
+    }
+
+    impl Peer {
+        /// Sends `actions` to the running instance, which applies them.
+        pub fn hand_over(mut self, actions: &[String]) -> io::Result<()> {
+            let mut message = format!("hello {}\n", std::process::id());
+            for action in actions {
+                message.push_str(action);
+                message.push('\n');
+            }
+            self.stream.write_all(message.as_bytes())?;
+            self.stream.shutdown(std::net::Shutdown::Write)
+        }
+    }
+
+    impl Listener {
+        /// Accepts new connections and collects the handovers that are complete.
+        pub fn poll(&mut self) -> Vec<Handover> {
+            while let Ok((mut stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() && writeln!(stream, "xmz {}", std::process::id()).is_ok() {
+                    self.pending.push((stream, Vec::new()));
+                }
+            }
+            let mut handovers = Vec::new();
+            self.pending.retain_mut(|(stream, received)| {
+                let mut chunk = [0; 4096];
+                loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => {
+                            handovers.extend(parse_handover(&String::from_utf8_lossy(received)));
+                            return false;
+                        }
+                        Ok(n) => received.extend_from_slice(&chunk[..n]),
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                        Err(_) => return false,
+                    }
+                }
+            });
+            handovers
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub use fallback::{Listener, Peer, claim};
+
+#[cfg(not(unix))]
+mod fallback {
+    use super::{Claim, Handover};
+    use std::io;
+    use std::path::Path;
+
+    pub struct Listener;
+
+    pub struct Peer {
+        pub pid: u32,
+    }
+
+    pub fn claim(_file: &Path) -> Claim {
+        Claim::Unguarded
+    }
+
+    impl Peer {
+        pub fn hand_over(self, _actions: &[String]) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Listener {
+        pub fn poll(&mut self) -> Vec<Handover> {
+            Vec::new()
+        }
+    }
+}
+
+/// Applies `handover`'s actions to `panes` and draws attention to the window.
+pub(super) fn receive(panes: &mut super::PaneManager, handover: Handover) -> bool {
+    let count = handover.actions.len();
+    for action in handover.actions {
+        if !panes.apply(action) {
+            return false;
+        }
+    }
+    panes.focused_mut().message = Some(match count {
+        0 => format!("This file was opened again from another terminal (pid {})", handover.pid),
+        _ => format!("Opened again from another terminal (pid {}); ran {} action(s)", handover.pid, count),
+    });
+    let mut out = io::stdout();
+    let _ = out.write_all(b"\x07").and_then(|()| out.flush());
+    true
+}
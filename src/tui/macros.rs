@@ -0,0 +1,60 @@
+use super::action::Action;
+use std::collections::HashMap;
+
+/// What the next key names a register for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterUse {
+    Record,
+    Replay,
+}
+
+/// Keyboard macros: `Q<register>` records actions until the next `Q`,
+/// `@<register>` replays them and `@@` repeats the last replayed register.
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<Action>>,
+    recording: Option<(char, Vec<Action>)>,
+    last_replayed: Option<char>,
+    /// Set after `Q` or `@` while waiting for the register key.
+    pub pending: Option<RegisterUse>,
+}
+
+impl Macros {
+    pub fn start(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+    }
+
+    /// Stores the recording, returning its register and length.
+    pub fn stop(&mut self) -> Option<(char, usize)> {
+        let (register, actions) = self.recording.take()?;
+        let len = actions.len();
+        self.registers.insert(register, actions);
+        Some((register, len))
+    }
+
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    /// Appends `action` to the running recording. Quitting, reloading, macro
+    /// control and opening a prompt are left out; a submitted prompt is recorded as the
+    /// action it produced, so replays do not stop for input.
+    pub fn record(&mut self, action: &Action) {
+        if let Some((_, actions)) = self.recording.as_mut()
+            && !matches!(
+                action,
+                Action::Quit | Action::Reload | Action::Prompt(_) | Action::RecordMacro(_) | Action::StopRecording | Action::ReplayMacro(_)
+            )
+        {
+            actions.push(action.clone());
+        }
+    }
+
+    /// Actions stored in `register` (`@` meaning the last replayed one).
+    pub fn replay(&mut self, register: char) -> Option<(char, Vec<Action>)> {
+        let register = if register == '@' { self.last_replayed? } else { register };
+        let actions = self.registers.get(&register)?.clone();
+        self.last_replayed = Some(register);
+        Some((register, actions))
+    }
+}
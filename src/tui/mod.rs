@@ -1,29 +1,238 @@
-use std::io;
+use std::io::{self, Stdout};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use crossterm::event;
+use ratatui::{Terminal, backend::CrosstermBackend};
+use crate::diff::Diff;
+use crate::index::Index;
+use crate::sandbox;
+use crate::watch::FileWatcher;
+use crate::xml::XmlExplorer;
 
+mod action;
+mod clipboard;
+mod compare;
+mod diagnostics;
+mod directory;
+mod fingerprint;
+mod follow;
+mod headless;
+mod hexdump;
+mod instance;
+mod macros;
+mod panes;
+mod reading;
+mod session;
+mod source;
 mod state;
+mod table;
+mod text;
+mod theme;
+mod tree;
 mod ui;
 mod input;
 mod terminal;
 
+use action::Action;
+pub use directory::run_directory;
+pub use follow::run_follow;
+pub use instance::{Claim, Listener, claim, offer_handover};
+pub use session::{SessionKey, Step};
+pub use theme::{Theme, set as set_theme};
+use panes::PaneManager;
 use state::TuiState;
 use terminal::{setup_terminal, restore_terminal};
 use ui::draw_ui;
 use input::handle_input;
 
-pub fn run_tui(xml: &str) -> io::Result<()> {
-    let mut state = TuiState::new(xml);
-    let mut terminal = setup_terminal()?;
+/// Startup options for the interactive navigator.
+#[derive(Default)]
+pub struct TuiOptions<'a> {
+    /// Second document to open in a split pane (may be the same document).
+    pub split: Option<&'a str>,
+    /// Name of the primary document, shown in the tab bar.
+    pub name: &'a str,
+    /// Further documents (name and text) to open in tabs, switched with Tab.
+    pub tabs: Vec<(&'a str, &'a str)>,
+    /// Query run in the primary pane on startup.
+    pub on_open: Option<&'a str>,
+    /// Command run against the primary pane on quit, e.g. `export out.xml`.
+    pub on_exit: Option<&'a str>,
+    /// Action script to run headlessly instead of reading the keyboard.
+    pub script: Option<&'a str>,
+    /// Where headless snapshots are written; stdout when unset.
+    pub snapshot_path: Option<&'a str>,
+    /// Prebuilt index of the primary document for fast navigation.
+    pub index: Option<Index>,
+    /// Shown in a banner above the panes, e.g. after a lossy decode.
+    pub warning: Option<String>,
+    /// Single-instance guard for the primary document, answering later instances.
+    pub instance: Option<Listener>,
+    /// The primary document on disk; its location is saved on quit.
+    pub session: Option<SessionKey>,
+    /// Reopen the primary document where its last session left it.
+    pub resume: bool,
+    /// Differences between the primary and split documents, marked in both
+    /// panes, which then follow each other's selection.
+    pub diff: Option<Diff>,
+    /// The primary document's file, watched for changes so it can be reloaded.
+    pub watch: Option<&'a Path>,
+    /// Reload as soon as the watched file changes instead of offering to.
+    pub auto_reload: bool,
+    /// Where to reopen the primary document after a reload.
+    pub location: Option<Vec<Step>>,
+    /// Where edits to the primary document are written, if it can be edited.
+    pub edit_target: Option<PathBuf>,
+}
+
+/// How a navigator session ended.
+pub enum Exit {
+    Quit,
+    /// The primary document is to be reopened from disk at this location,
+    /// keeping the single-instance guard.
+    Reload(Vec<Step>, Option<Listener>),
+    /// The primary document was edited, and is to be reopened from where the
+    /// edits were written.
+    Edited(Vec<Step>, Option<Listener>),
+}
 
+/// Runs the interactive navigator on `xml` until the user quits or asks to
+/// reload the document.
+pub fn run_tui(xml: &str, options: TuiOptions) -> io::Result<Exit> {
+    let explorer = match options.index {
+        Some(index) => XmlExplorer::with_index(xml, index),
+        None => XmlExplorer::new(xml),
+    };
+    let mut panes = PaneManager::new(TuiState::with_explorer(explorer));
+    panes.warning = options.warning;
+    panes.edit_target = options.edit_target;
+    panes.open_tabs(options.name, options.tabs);
+    if let Some(other) = options.split {
+        panes.split(other);
+        if let Some(diff) = &options.diff {
+            panes.compare(diff);
+        }
+    }
+    if options.resume
+        && let Some(key) = &options.session
+    {
+        let state = &mut panes.panes[0];
+        match key.lookup() {
+            session::Saved::At(steps) => state.restore_location(&steps),
+            session::Saved::Changed => state.message = Some("The file changed since the last session; starting at the root".to_string()),
+            session::Saved::None => {}
+        }
+    }
+    if let Some(steps) = &options.location {
+        let state = &mut panes.panes[0];
+        state.restore_location(steps);
+        state.message = Some(format!("Reloaded {}", options.name));
+    }
+    if let Some(query) = options.on_open
+        && let Err(err) = panes.panes[0].apply_query(query)
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("--on-open: {}", err)));
+    }
+
+    if let Some(script) = options.script {
+        panes.clipboard = false;
+        let snapshots = headless::run_script(&mut panes, script)?;
+        match options.snapshot_path {
+            Some(path) => std::fs::write(path, snapshots)?,
+            None => print!("{}", snapshots),
+        }
+    } else {
+        let mut watcher = match options.watch {
+            Some(path) => Some(FileWatcher::new(path)?),
+            None => None,
+        };
+        panes.reloadable = watcher.is_some();
+        let mut guard = options.instance;
+        let mut terminal = setup_terminal()?;
+        let result = event_loop(&mut terminal, &mut panes, guard.as_mut(), watcher.as_mut().map(|watcher| (watcher, options.auto_reload)));
+        restore_terminal()?;
+        result?;
+        if panes.reload && panes.edited.is_some() {
+            return Ok(Exit::Edited(panes.panes[0].location(), guard));
+        }
+        if panes.reload {
+            return Ok(Exit::Reload(panes.panes[0].location(), guard));
+        }
+        if let Some(key) = &options.session
+            && sandbox::check(sandbox::Capability::WriteFiles).is_ok()
+            && let Err(err) = key.save(&panes.primary_mut().location())
+        {
+            eprintln!("xmz: could not save the session: {}", err);
+        }
+    }
+
+    if let Some(command) = options.on_exit {
+        if let Some(capability) = Action::Command(command.to_string()).capability() {
+            sandbox::check(capability)?;
+        }
+        match panes.primary_mut().run_command(command) {
+            Ok(summary) => println!("{}", summary),
+            Err(err) => return Err(io::Error::other(format!("--on-exit: {}", err))),
+        }
+    }
+    Ok(Exit::Quit)
+}
+
+type Screen = Terminal<CrosstermBackend<Stdout>>;
+
+/// How long a fingerprint is counted before the screen is redrawn and keys
+/// are read.
+const FINGERPRINT_BUDGET: Duration = Duration::from_millis(30);
+
+/// Runs until the user quits or reloads. A change to the file being watched
+/// is offered for reloading, or reloads at once when asked to (`true`).
+fn event_loop(
+    terminal: &mut Screen,
+    panes: &mut PaneManager,
+    mut guard: Option<&mut Listener>,
+    mut watch: Option<(&mut FileWatcher, bool)>,
+) -> io::Result<()> {
+    // An automatic reload waits for an open prompt to close.
+    let mut changed = false;
     loop {
-        terminal.draw(|f| draw_ui(f, &mut state))?;
+        if let Some((watcher, automatic)) = watch.as_mut() {
+            if watcher.changed()? {
+                changed = true;
+                if !*automatic {
+                    panes.panes[0].message = Some("The file changed on disk; press R to reload".to_string());
+                }
+            }
+            if changed && *automatic && panes.panes[0].prompt.is_none() {
+                panes.reload = true;
+                return Ok(());
+            }
+        }
+
+        let counting = panes.focused_mut().update_fingerprint(Some(FINGERPRINT_BUDGET));
+        terminal.draw(|f| draw_ui(f, panes))?;
 
-        if event::poll(std::time::Duration::from_millis(200))?
-            && !handle_input(event::read()?, &mut state)
+        for handover in guard.as_mut().map_or_else(Vec::new, |listener| listener.poll()) {
+            if !instance::receive(panes, handover) {
+                return Ok(());
+            }
+        }
+
+        // An edit reloads once all the actions of its key (or macro) are done.
+        // While a fingerprint is counted, keys are checked between pieces.
+        let wait = if counting { Duration::ZERO } else { Duration::from_millis(200) };
+        if event::poll(wait)?
+            && (!handle_input(event::read()?, panes) || panes.reload)
         {
-            break;
+            return Ok(());
         }
     }
+}
 
-    restore_terminal()
+/// Runs the navigator on `xml` in an already set up terminal until `q`.
+fn navigate(terminal: &mut Screen, xml: &str, index: Option<Index>) -> io::Result<()> {
+    let explorer = match index {
+        Some(index) => XmlExplorer::with_index(xml, index),
+        None => XmlExplorer::new(xml),
+    };
+    event_loop(terminal, &mut PaneManager::new(TuiState::with_explorer(explorer)), None, None)
 }
@@ -0,0 +1,403 @@
+use super::action::Action;
+use super::clipboard;
+use super::compare::DiffMarks;
+use crate::diff::Diff;
+use super::macros::Macros;
+use super::state::TuiState;
+use crate::sandbox;
+use crate::splice::{self, Edited};
+use ratatui::layout::Direction;
+use std::path::{Path, PathBuf};
+
+/// An open document. The active tab's state is shown in the first pane; the
+/// others keep theirs here, with their own navigation and caches.
+pub struct Tab<'a> {
+    pub name: String,
+    parked: Option<TuiState<'a>>,
+}
+
+/// Owns one or two independent explorer panes and tracks which one has focus.
+pub struct PaneManager<'a> {
+    pub panes: Vec<TuiState<'a>>,
+    pub focus: usize,
+    pub direction: Direction,
+    /// Documents open in the first pane, in order; empty with a single document.
+    pub tabs: Vec<Tab<'a>>,
+    pub active_tab: usize,
+    pub macros: Macros,
+    /// Banner shown above the panes.
+    pub warning: Option<String>,
+    /// The first key of a two-key command (`y`, `]` or `[`) while waiting for the second.
+    pub pending_prefix: Option<char>,
+    /// Whether yanks reach the clipboard; off for headless scripts.
+    pub clipboard: bool,
+    /// Whether the primary document can be reopened from disk.
+    pub reloadable: bool,
+    /// Set when the user asked to reload, which ends the event loop.
+    pub reload: bool,
+    /// Where edits to the primary document are written; None when it cannot
+    /// be edited because its text is not the file's bytes as they are.
+    pub edit_target: Option<PathBuf>,
+    /// The primary document with the edits made since it was loaded.
+    pub edited: Option<Edited>,
+}
+
+impl<'a> PaneManager<'a> {
+    pub fn new(first: TuiState<'a>) -> Self {
+        Self {
+            panes: vec![first],
+            focus: 0,
+            direction: Direction::Horizontal,
+            tabs: Vec::new(),
+            active_tab: 0,
+            macros: Macros::default(),
+            warning: None,
+            pending_prefix: None,
+            clipboard: true,
+            reloadable: false,
+            reload: false,
+            edit_target: None,
+            edited: None,
+        }
+    }
+
+    /// Opens a second pane on `xml`, which may be the same document as the first.
+    pub fn split(&mut self, xml: &'a str) {
+        self.panes.push(TuiState::new(xml));
+    }
+
+    /// Marks `diff` between the two panes' documents and links their selections.
+    pub fn compare(&mut self, diff: &Diff) {
+        if self.panes.len() < 2 {
+            return;
+        }
+        self.panes[0].diff = Some(DiffMarks::build(diff, false));
+        self.panes[1].diff = Some(DiffMarks::build(diff, true));
+        self.panes[0].message = Some(match diff.changes.len() {
+            0 => "No differences".to_string(),
+            n => format!("{} change(s): - only on the left, + only on the right, ~ changed, · changed inside", n),
+        });
+    }
+
+    /// Selects the counterpart of the focused pane's selection in the other
+    /// pane, when comparing.
+    fn follow_selection(&mut self) {
+        if self.panes.len() != 2 {
+            return;
+        }
+        let (focused, other) = (self.focus, 1 - self.focus);
+        let Some(offset) = self.panes[focused].selected_node().map(|node| node.offset) else {
+            return;
+        };
+        let Some(target) = self.panes[focused].diff.as_ref().and_then(|diff| diff.counterpart(offset)) else {
+            return;
+        };
+        let state = &mut self.panes[other];
+        if state.selected_node().map(|node| node.offset) != Some(target) {
+            state.reveal(target);
+        }
+    }
+
+    /// Opens `documents` (name and text) in tabs after the first pane's
+    /// document, which is named `first`.
+    pub fn open_tabs(&mut self, first: &str, documents: Vec<(&str, &'a str)>) {
+        if documents.is_empty() {
+            return;
+        }
+        self.tabs.push(Tab { name: first.to_string(), parked: None });
+        for (name, xml) in documents {
+            self.tabs.push(Tab { name: name.to_string(), parked: Some(TuiState::new(xml)) });
+        }
+    }
+
+    /// Shows the next (or previous) tab's document in the first pane.
+    pub fn switch_tab(&mut self, forward: bool) {
+        let count = self.tabs.len();
+        if count < 2 {
+            self.focused_mut().message = Some("Only one document is open".to_string());
+            return;
+        }
+        let next = (self.active_tab + if forward { 1 } else { count - 1 }) % count;
+        let Some(shown) = self.tabs[next].parked.take() else {
+            return;
+        };
+        let previous = std::mem::replace(&mut self.panes[0], shown);
+        self.tabs[self.active_tab].parked = Some(previous);
+        self.active_tab = next;
+        self.focus = 0;
+    }
+
+    /// The state of the first document given, whichever tab is active.
+    pub fn primary_mut(&mut self) -> &mut TuiState<'a> {
+        match self.tabs.first_mut().and_then(|tab| tab.parked.as_mut()) {
+            Some(state) => state,
+            None => &mut self.panes[0],
+        }
+    }
+
+    pub fn focused(&self) -> &TuiState<'a> {
+        &self.panes[self.focus]
+    }
+
+    pub fn focused_mut(&mut self) -> &mut TuiState<'a> {
+        &mut self.panes[self.focus]
+    }
+
+    pub fn switch_focus(&mut self) {
+        self.focus = (self.focus + 1) % self.panes.len();
+    }
+
+    pub fn toggle_direction(&mut self) {
+        self.direction = match self.direction {
+            Direction::Horizontal => Direction::Vertical,
+            Direction::Vertical => Direction::Horizontal,
+        };
+    }
+
+    /// Applies `action` to the focused pane. Returns false when the user quits.
+    pub fn apply(&mut self, action: Action) -> bool {
+        if let Some(capability) = action.capability()
+            && let Err(err) = sandbox::check(capability)
+        {
+            self.focused_mut().message = Some(err.to_string());
+            return true;
+        }
+        match action {
+            Action::RecordMacro(register) => {
+                self.macros.start(register);
+                return true;
+            }
+            Action::StopRecording => {
+                if let Some((register, len)) = self.macros.stop() {
+                    self.focused_mut().message = Some(format!("Recorded {} actions into @{}", len, register));
+                }
+                return true;
+            }
+            Action::ReplayMacro(register) => {
+                let Some((_, actions)) = self.macros.replay(register) else {
+                    self.focused_mut().message = Some(format!("Register @{} is empty", register));
+                    return true;
+                };
+                return actions.into_iter().all(|action| self.apply(action));
+            }
+            _ => self.macros.record(&action),
+        }
+        if let Action::NextTab | Action::PrevTab = action {
+            self.switch_tab(action == Action::NextTab);
+            return true;
+        }
+        if action == Action::Reload {
+            if !self.reloadable {
+                self.focused_mut().message = Some("Only a document opened from a file can be reloaded".to_string());
+                return true;
+            }
+            self.reload = true;
+            return false;
+        }
+        match &action {
+            Action::Edit(input) => {
+                self.edit(input);
+                return true;
+            }
+            Action::Delete | Action::Duplicate => {
+                if self.editable().is_some() {
+                    if action == Action::Delete {
+                        self.panes[0].stage_delete();
+                    } else {
+                        self.panes[0].stage_duplicate();
+                    }
+                }
+                return true;
+            }
+            Action::Command(command) if command.split_whitespace().next() == Some("w") => {
+                self.save(command.trim()[1..].trim());
+                return true;
+            }
+            _ => {}
+        }
+        let running = self.apply_to_focused(action);
+        self.follow_selection();
+        running
+    }
+
+    /// Where edits to the focused document are written, or None with a
+    /// message when it is not the first document or cannot be edited.
+    fn editable(&mut self) -> Option<PathBuf> {
+        let Some(target) = self.edit_target.clone() else {
+            self.focused_mut().message = Some("Only an uncompressed UTF-8 file, read as it is, can be edited".to_string());
+            return None;
+        };
+        if self.focus != 0 || self.active_tab != 0 {
+            self.focused_mut().message = Some("Only the first document can be edited".to_string());
+            return None;
+        }
+        Some(target)
+    }
+
+    /// Splices `input` into the selected element of the primary document and
+    /// writes the document with all its edits so far, staged changes
+    /// included, to the edit target. A reloadable document is then reloaded,
+    /// from the target, once the current key's actions are done; a script
+    /// goes on with the document it loaded, whose elements are found in the
+    /// edited text.
+    fn edit(&mut self, input: &str) {
+        let Some(target) = self.editable() else {
+            return;
+        };
+        let state = &mut self.panes[0];
+        let edited = self.edited.get_or_insert_with(|| Edited::new(state.explorer.xml().to_string()));
+        match state.edit_splice(edited, input) {
+            Ok(splice) => edited.apply(splice),
+            Err(err) => {
+                state.message = Some(format!("Edit failed: {}", err));
+                return;
+            }
+        }
+        state.apply_pending(edited);
+        state.pending.clear();
+        match splice::write(&target, edited.text()) {
+            Ok(()) => {
+                state.message = Some(format!("Wrote {} edit(s) to {}", edited.edits(), target.display()));
+                self.reload |= self.reloadable;
+            }
+            Err(err) => state.message = Some(format!("Edit failed: {}: {}", target.display(), err)),
+        }
+    }
+
+    /// `:w`: makes the staged changes and writes the document with all its
+    /// edits to the edit target, reloading it as after an edit. `:w path`
+    /// writes a copy with the changes to `path` instead, leaving them staged.
+    fn save(&mut self, path: &str) {
+        let Some(target) = self.editable() else {
+            return;
+        };
+        let state = &mut self.panes[0];
+        let edited = self.edited.get_or_insert_with(|| Edited::new(state.explorer.xml().to_string()));
+        if !path.is_empty() {
+            let mut copy = edited.clone();
+            state.apply_pending(&mut copy);
+            state.message = Some(match splice::write(Path::new(path), copy.text()) {
+                Ok(()) => format!("Wrote {} edit(s) to {}", copy.edits(), path),
+                Err(err) => format!("Write failed: {}: {}", path, err),
+            });
+            return;
+        }
+        if state.pending.is_empty() {
+            state.message = Some("No changes are staged (d to delete, p to duplicate)".to_string());
+            return;
+        }
+        let staged = state.pending.len();
+        state.apply_pending(edited);
+        state.pending.clear();
+        match splice::write(&target, edited.text()) {
+            Ok(()) => {
+                state.message = Some(format!("Wrote {} change(s) to {}", staged, target.display()));
+                self.reload |= self.reloadable;
+            }
+            Err(err) => state.message = Some(format!("Write failed: {}: {}", target.display(), err)),
+        }
+    }
+
+    fn apply_to_focused(&mut self, action: Action) -> bool {
+        let clipboard = self.clipboard;
+        let state = self.focused_mut();
+        if state.text_action(&action) || state.reading_action(&action) {
+            return true;
+        }
+        if let Some(view) = state.hexdump.as_mut()
+            && view.move_action(&action, state.explorer.xml().len())
+        {
+            return true;
+        }
+        if state.diagnostics_action(&action) {
+            return true;
+        }
+        if let Some(view) = state.source.as_mut()
+            && view.scroll_action(&action)
+        {
+            return true;
+        }
+        if state.tree_action(&action) || state.table_action(&action) {
+            return true;
+        }
+        match action {
+            Action::Quit => return false,
+            Action::Down => state.go_down(),
+            Action::Up => state.go_up(),
+            Action::Enter => state.enter(),
+            Action::Back => state.back(),
+            Action::Ascend(levels) => state.ascend(levels),
+            Action::Root => state.ascend(usize::MAX),
+            Action::PageUp => state.page_up(),
+            Action::PageDown => state.page_down(),
+            Action::Home => state.home(),
+            Action::End => state.end(),
+            Action::ToggleInfo => state.toggle_info(),
+            Action::ToggleCardinality => state.toggle_cardinality(),
+            Action::ToggleSource => state.toggle_source(),
+            Action::ToggleTree => state.toggle_tree(),
+            Action::ToggleTable => state.toggle_table(),
+            Action::ToggleGrouping => state.toggle_grouping(),
+            Action::MoveColumn(_) | Action::HideColumn | Action::FreezeColumn | Action::AddSortColumn => {
+                state.message = Some("Columns can be arranged in the table view (t)".to_string());
+            }
+            Action::TableColumns(names) => state.choose_table_columns(&names),
+            Action::ExportTable(path) => state.export_table(&path),
+            Action::FilterColumn(expr) => state.filter_table_column(&expr),
+            Action::ToggleHexdump => state.toggle_hexdump(),
+            Action::ToggleText => state.toggle_text(),
+            Action::ToggleReading => state.toggle_reading(),
+            Action::ToggleMarkers | Action::NextSection | Action::PrevSection => {
+                state.message = Some("Markers and sections are in the reading view (r)".to_string());
+            }
+            Action::ToggleDiagnostics => state.toggle_diagnostics(),
+            Action::NextDiagnostic => state.jump_diagnostic(true),
+            Action::PrevDiagnostic => state.jump_diagnostic(false),
+            Action::CycleAttributes => state.attributes.cycle(),
+            Action::FilterAttributes(names) => {
+                state.attributes.set_filter(&names);
+                state.message = Some(if state.attributes.filter.is_empty() {
+                    "Showing all attributes".to_string()
+                } else {
+                    format!("Showing attributes {}", state.attributes.filter.join(", "))
+                });
+            }
+            Action::Sort(key) => state.sort_children(key),
+            Action::CycleSort => state.cycle_sort(),
+            Action::FilterChildren(filter) => state.filter_children(&filter),
+            Action::ToggleMark => state.toggle_mark(),
+            Action::ClearMarks => state.clear_marks(),
+            Action::SwitchFocus => self.switch_focus(),
+            Action::FlipSplit => self.toggle_direction(),
+            Action::Prompt(kind) => state.start_prompt(kind),
+            Action::SelectRegex(pattern) => state.select_matching(&pattern),
+            Action::ExportSelected(path) => state.export_selected(&path),
+            Action::Yank(kind) => {
+                state.message = Some(match state.yank(kind) {
+                    None => "Nothing to yank".to_string(),
+                    Some((_, description)) if !clipboard => format!("Yanked {}", description),
+                    Some((text, description)) => match clipboard::copy(&text) {
+                        Ok(via) => format!("Yanked {} (via {})", description, via),
+                        Err(err) => format!("Yank failed: {}", err),
+                    },
+                });
+            }
+            Action::Command(command) => {
+                state.message = Some(match state.run_command(&command) {
+                    Ok(summary) => summary,
+                    Err(err) => err,
+                });
+            }
+            Action::RecordMacro(_)
+            | Action::StopRecording
+            | Action::ReplayMacro(_)
+            | Action::NextTab
+            | Action::PrevTab
+            | Action::Reload
+            | Action::Edit(_)
+            | Action::Delete
+            | Action::Duplicate => {} // handled in apply
+        }
+        true
+    }
+}
@@ -0,0 +1,595 @@
+use super::action::Action;
+use super::theme;
+use crate::parser::{Continue, Token, attribute_pairs, stream_xml_spans, text_content};
+use ratatui::prelude::*;
+use std::collections::HashMap;
+
+/// Reading view of document-centric XML such as TEI or DocBook (`r`): the
+/// selected element's prose as wrapped paragraphs under section headings,
+/// with inline elements styled, or marked by their tags with `i`. `]s` and
+/// `[s` go to the next and previous heading, and Enter lists the contents.
+pub struct ReadingView {
+    pub tag: String,
+    blocks: Vec<Block>,
+    /// Whether inline elements show their start and end tags.
+    pub markers: bool,
+    pub scroll: usize,
+    /// The selected entry while the contents are listed.
+    pub contents: Option<usize>,
+    /// Lines that fit on screen and their width, as last laid out.
+    page: usize,
+    width: usize,
+    lines: Vec<Line<'static>>,
+    /// First line of each block, and the blocks that are headings.
+    starts: Vec<usize>,
+    headings: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A section title, by nesting depth from 1.
+    Heading(usize),
+    /// The title of a figure, table or list.
+    Title,
+    Paragraph,
+    /// A verse line or speaker, set without a gap to the one before.
+    Line,
+    Item,
+    /// Program listings and the like, with their line breaks.
+    Verbatim,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Inline {
+    Plain,
+    Italic,
+    Bold,
+    Code,
+    /// A cross-reference such as DocBook's `xref`, by the id it points to
+    /// until resolved to the title of its section.
+    Link,
+    /// A start or end tag, shown with markers on.
+    Marker,
+}
+
+struct Block {
+    kind: Kind,
+    runs: Vec<(String, Inline)>,
+}
+
+impl Block {
+    fn has_text(&self) -> bool {
+        self.runs.iter().any(|(text, style)| *style != Inline::Marker && !text.trim().is_empty())
+    }
+
+    fn text(&self) -> String {
+        let text: String = self.runs.iter().filter(|(_, style)| *style != Inline::Marker).map(|(text, _)| text.as_str()).collect();
+        text.trim().to_string()
+    }
+}
+
+/// What an element is to the reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Section,
+    /// Front matter such as DocBook's `info`, read only for its title.
+    Meta,
+    Skip,
+    Block(Kind),
+    /// A table cell, separated from the one before.
+    Cell,
+    Break,
+    Inline(Inline),
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+/// Whether `name` is `prefix` followed by a number, like `sect2` or `div3`.
+fn numbered(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix).is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The role of an element of TEI, DocBook or XHTML named `name`; elements
+/// these do not define read as plain inline text.
+fn role(name: &str, attrs: &str) -> Role {
+    let bold = || {
+        attribute_pairs(attrs)
+            .into_iter()
+            .any(|(key, value)| matches!(key, "role" | "rend") && matches!(value, "bold" | "strong" | "b"))
+    };
+    match name {
+        "book" | "part" | "chapter" | "appendix" | "preface" | "article" | "section" | "simplesect" | "glossary" | "bibliography"
+        | "colophon" | "dedication" | "acknowledgements" | "refentry" | "div" => Role::Section,
+        _ if numbered(name, "sect") || numbered(name, "refsect") || numbered(name, "div") => Role::Section,
+        "info" | "bookinfo" | "articleinfo" | "chapterinfo" | "sectioninfo" | "prefaceinfo" | "appendixinfo" => Role::Meta,
+        "teiHeader" | "indexterm" | "remark" | "titleabbrev" | "fw" | "facsimile" | "egXML" => Role::Skip,
+        "title" | "head" | "subtitle" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Role::Block(Kind::Title),
+        "p" | "para" | "simpara" | "ab" | "stage" | "term" | "caption" | "row" | "tr" | "attribution" | "bibl" | "dt" | "dd" => {
+            Role::Block(Kind::Paragraph)
+        }
+        "l" | "speaker" => Role::Block(Kind::Line),
+        "listitem" | "item" | "li" => Role::Block(Kind::Item),
+        "programlisting" | "screen" | "literallayout" | "synopsis" | "address" | "eg" | "pre" => Role::Block(Kind::Verbatim),
+        "entry" | "cell" | "td" | "th" => Role::Cell,
+        "lb" | "sbr" | "br" => Role::Break,
+        "emphasis" | "hi" if bold() => Role::Inline(Inline::Bold),
+        "emphasis" | "emph" | "hi" | "em" | "i" | "foreign" | "citetitle" | "firstterm" => Role::Inline(Inline::Italic),
+        "strong" | "b" => Role::Inline(Inline::Bold),
+        "code" | "literal" | "command" | "filename" | "option" | "varname" | "function" | "tag" | "userinput"
+        | "computeroutput" | "replaceable" | "envar" | "classname" => Role::Inline(Inline::Code),
+        _ => Role::Inline(Inline::Plain),
+    }
+}
+
+/// Reads the element `raw` into blocks of text.
+struct Reader {
+    blocks: Vec<Block>,
+    current: Option<Block>,
+    /// Roles of the open elements.
+    open: Vec<Role>,
+    /// Open sections.
+    depth: usize,
+    /// The ids of the open sections, and the titles of those read.
+    section_ids: Vec<Option<String>>,
+    titles: HashMap<String, String>,
+}
+
+impl Reader {
+    /// Whether text here is read: not in a skipped element, and in front
+    /// matter only within its title.
+    fn reading(&self) -> bool {
+        if self.open.contains(&Role::Skip) {
+            return false;
+        }
+        match self.open.iter().rposition(|role| *role == Role::Meta) {
+            Some(meta) => self.open[meta..].contains(&Role::Block(Kind::Title)),
+            None => true,
+        }
+    }
+
+    fn verbatim(&self) -> bool {
+        self.open.contains(&Role::Block(Kind::Verbatim))
+    }
+
+    fn flush(&mut self) {
+        if let Some(block) = self.current.take()
+            && block.has_text()
+        {
+            if let Kind::Heading(_) = block.kind
+                && let Some(Some(id)) = self.section_ids.last()
+            {
+                self.titles.entry(id.clone()).or_insert_with(|| block.text());
+            }
+            self.blocks.push(block);
+        }
+    }
+
+    fn begin(&mut self, kind: Kind) {
+        // The first paragraph of a list item carries its bullet.
+        if let Some(block) = &self.current
+            && block.kind == Kind::Item
+            && !block.has_text()
+        {
+            return;
+        }
+        self.flush();
+        self.current = Some(Block { kind, runs: Vec::new() });
+    }
+
+    fn push(&mut self, text: &str, style: Inline) {
+        if text.is_empty() || (self.current.is_none() && text.trim().is_empty()) {
+            return;
+        }
+        let block = self.current.get_or_insert_with(|| Block { kind: Kind::Paragraph, runs: Vec::new() });
+        let text = match block.runs.iter().rev().find(|(_, style)| *style != Inline::Marker) {
+            Some((last, _)) if last.ends_with(' ') => text.strip_prefix(' ').unwrap_or(text),
+            None => text.strip_prefix(' ').unwrap_or(text),
+            _ => text,
+        };
+        if !text.is_empty() {
+            block.runs.push((text.to_string(), style));
+        }
+    }
+
+    /// The style of text here, from the innermost styled element.
+    fn style(&self) -> Inline {
+        self.open
+            .iter()
+            .rev()
+            .find_map(|role| match role {
+                Role::Inline(Inline::Plain) => None,
+                Role::Inline(style) => Some(*style),
+                _ => None,
+            })
+            .unwrap_or(Inline::Plain)
+    }
+
+    fn start(&mut self, name: &str, attrs: &str) {
+        let local = local_name(name);
+        let mut role = role(local, attrs);
+        if self.open.contains(&Role::Skip) || (!self.reading() && role != Role::Block(Kind::Title)) {
+            role = Role::Skip;
+        }
+        let attr = |keys: &[&str]| attribute_pairs(attrs).into_iter().find(|(key, _)| keys.contains(key)).map(|(_, value)| value.to_string());
+        match role {
+            Role::Section => {
+                self.flush();
+                self.depth += 1;
+                self.section_ids.push(attr(&["id", "xml:id"]));
+            }
+            Role::Block(Kind::Title) => {
+                let heading = match self.open.iter().rev().find(|role| **role != Role::Meta) {
+                    Some(role) => *role == Role::Section,
+                    None => true,
+                };
+                self.flush();
+                let kind = if heading { Kind::Heading(self.depth.max(1)) } else { Kind::Title };
+                self.current = Some(Block { kind, runs: Vec::new() });
+            }
+            Role::Block(kind) => self.begin(kind),
+            Role::Cell if self.current.as_ref().is_some_and(Block::has_text) => self.push(" | ", Inline::Plain),
+            Role::Break => self.push("\n", Inline::Plain),
+            Role::Inline(_) => {
+                self.push(&format!("<{}>", local), Inline::Marker);
+                if matches!(local, "xref" | "ptr")
+                    && let Some(id) = attr(&["linkend", "target"])
+                {
+                    self.push(id.trim_start_matches('#'), Inline::Link);
+                }
+            }
+            _ => {}
+        }
+        self.open.push(role);
+    }
+
+    fn end(&mut self, name: &str) {
+        match self.open.pop() {
+            Some(Role::Section) => {
+                self.flush();
+                self.depth = self.depth.saturating_sub(1);
+                self.section_ids.pop();
+            }
+            Some(Role::Block(_)) => self.flush(),
+            Some(Role::Inline(_)) if self.current.is_some() => self.push(&format!("</{}>", local_name(name)), Inline::Marker),
+            _ => {}
+        }
+    }
+}
+
+fn read(raw: &str) -> Vec<Block> {
+    let mut reader = Reader {
+        blocks: Vec::new(),
+        current: None,
+        open: Vec::new(),
+        depth: 0,
+        section_ids: Vec::new(),
+        titles: HashMap::new(),
+    };
+    let mut last = 0;
+    stream_xml_spans(raw, |token, span| {
+        // Text is taken from between tags, with CDATA sections unwrapped,
+        // comments dropped and its whitespace as written.
+        if matches!(token, Token::Text(_) | Token::CData(_)) {
+            return Continue(());
+        }
+        if span.start > last && reader.reading() {
+            let text = text_content(&raw[last..span.start]);
+            let text = if reader.verbatim() { text } else { collapse(&text) };
+            reader.push(&text, reader.style());
+        }
+        last = span.end;
+        match token {
+            Token::StartTag(name, attrs) => reader.start(name, attrs),
+            Token::EndTag(name) => reader.end(name.trim_end()),
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    reader.flush();
+    for block in &mut reader.blocks {
+        for (text, style) in &mut block.runs {
+            if *style == Inline::Link
+                && let Some(title) = reader.titles.get(text.as_str())
+            {
+                *text = title.clone();
+            }
+        }
+    }
+    reader.blocks
+}
+
+/// `text` with each whitespace run turned into one space.
+fn collapse(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            space = true;
+        } else {
+            if space {
+                out.push(' ');
+                space = false;
+            }
+            out.push(c);
+        }
+    }
+    if space {
+        out.push(' ');
+    }
+    out
+}
+
+/// Fills lines of `width` characters with styled words.
+struct Filler {
+    width: usize,
+    /// Put before every line after the first, e.g. to indent list items.
+    indent: &'static str,
+    lines: Vec<Line<'static>>,
+    spans: Vec<Span<'static>>,
+    used: usize,
+}
+
+impl Filler {
+    fn new(width: usize, first: &'static str, indent: &'static str) -> Self {
+        Self {
+            width,
+            indent,
+            lines: Vec::new(),
+            spans: vec![Span::raw(first)],
+            used: first.chars().count(),
+        }
+    }
+
+    fn at_start(&self) -> bool {
+        self.spans.iter().all(|span| span.content.trim().is_empty())
+    }
+
+    fn break_line(&mut self) {
+        let spans = std::mem::replace(&mut self.spans, vec![Span::raw(self.indent)]);
+        self.lines.push(Line::from(spans));
+        self.used = self.indent.chars().count();
+    }
+
+    /// Appends `text`, breaking within it only if a line cannot hold it.
+    fn append(&mut self, text: &str, style: Style) {
+        for c in text.chars() {
+            if self.used >= self.width && !self.at_start() {
+                self.break_line();
+            }
+            match self.spans.last_mut() {
+                Some(span) if span.style == style => span.content.to_mut().push(c),
+                _ => self.spans.push(Span::styled(c.to_string(), style)),
+            }
+            self.used += 1;
+        }
+    }
+
+    /// Appends a word, on a new line if it does not fit on this one.
+    fn word(&mut self, word: &[(String, Style)], space: bool) {
+        let len: usize = word.iter().map(|(text, _)| text.chars().count()).sum();
+        if len == 0 {
+            return;
+        }
+        let space = space && !self.at_start();
+        if self.used + usize::from(space) + len > self.width && !self.at_start() {
+            self.break_line();
+        } else if space {
+            self.append(" ", Style::default());
+        }
+        for (text, style) in word {
+            self.append(text, *style);
+        }
+    }
+
+    fn finish(mut self) -> Vec<Line<'static>> {
+        if !self.at_start() {
+            self.break_line();
+        }
+        self.lines
+    }
+}
+
+impl ReadingView {
+    pub fn new(tag: &str, raw: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+            blocks: read(raw),
+            markers: false,
+            scroll: 0,
+            contents: None,
+            page: 20,
+            width: 0,
+            lines: Vec::new(),
+            starts: Vec::new(),
+            headings: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Sets the size on screen, laying the text out again if the width
+    /// changed, with the block at the top kept there.
+    pub fn fit(&mut self, page: usize, width: usize) {
+        self.page = page.max(1);
+        let width = width.max(1);
+        if width != self.width {
+            self.width = width;
+            self.relayout();
+        }
+        self.scroll_by(0);
+    }
+
+    fn relayout(&mut self) {
+        let top = self.starts.iter().rposition(|&start| start <= self.scroll).unwrap_or(0);
+        self.layout();
+        self.scroll = self.starts.get(top).copied().unwrap_or(0);
+    }
+
+    fn layout(&mut self) {
+        let theme = theme::current();
+        self.lines.clear();
+        self.starts.clear();
+        self.headings.clear();
+        let mut previous = None;
+        for (i, block) in self.blocks.iter().enumerate() {
+            let close = matches!((previous, block.kind), (Some(Kind::Line), Kind::Line) | (Some(Kind::Item), Kind::Item));
+            if previous.is_some() && !close {
+                self.lines.push(Line::default());
+            }
+            previous = Some(block.kind);
+            self.starts.push(self.lines.len());
+            let base = Style::default().fg(theme.foreground);
+            let (base, first, indent) = match block.kind {
+                Kind::Heading(level) => {
+                    self.headings.push(i);
+                    let style = base.fg(theme.accent).add_modifier(Modifier::BOLD);
+                    (if level == 1 { style.add_modifier(Modifier::UNDERLINED) } else { style }, "", "")
+                }
+                Kind::Title => (base.add_modifier(Modifier::BOLD), "", ""),
+                Kind::Paragraph => (base, "", ""),
+                Kind::Line => (base, "  ", "    "),
+                Kind::Item => (base, "• ", "  "),
+                Kind::Verbatim => {
+                    let text: String = block.runs.iter().filter(|(_, style)| self.markers || *style != Inline::Marker).map(|(text, _)| text.as_str()).collect();
+                    let text = text.trim_matches('\n');
+                    self.lines.extend(text.lines().map(|line| Line::styled(format!("  {}", line), Style::default().fg(theme.text))));
+                    continue;
+                }
+            };
+            let mut filler = Filler::new(self.width, first, indent);
+            let mut word: Vec<(String, Style)> = Vec::new();
+            let mut space = false;
+            for (text, inline) in &block.runs {
+                let style = match inline {
+                    Inline::Plain => base,
+                    Inline::Italic => base.add_modifier(Modifier::ITALIC),
+                    Inline::Bold => base.add_modifier(Modifier::BOLD),
+                    Inline::Code => base.fg(theme.tag),
+                    Inline::Link => base.add_modifier(Modifier::UNDERLINED),
+                    Inline::Marker if self.markers => Style::default().fg(theme.muted),
+                    Inline::Marker => continue,
+                };
+                for c in text.chars() {
+                    if c == ' ' || c == '\n' {
+                        filler.word(&word, space);
+                        word.clear();
+                        space = c == ' ';
+                        if c == '\n' {
+                            filler.break_line();
+                        }
+                        continue;
+                    }
+                    match word.last_mut() {
+                        Some((text, last)) if *last == style => text.push(c),
+                        _ => word.push((c.to_string(), style)),
+                    }
+                }
+            }
+            filler.word(&word, space);
+            self.lines.extend(filler.finish());
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        let max = self.lines.len().saturating_sub(self.page);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max);
+    }
+
+    /// The heading of the section at the top of the page.
+    fn current_heading(&self) -> Option<usize> {
+        self.headings.iter().rposition(|&block| self.starts[block] <= self.scroll)
+    }
+
+    /// The title of the section at the top of the page.
+    pub fn section(&self) -> Option<String> {
+        self.current_heading().map(|heading| self.blocks[self.headings[heading]].text())
+    }
+
+    /// The headings, as depth and title.
+    pub fn entries(&self) -> Vec<(usize, String)> {
+        self.headings
+            .iter()
+            .map(|&block| match self.blocks[block].kind {
+                Kind::Heading(level) => (level, self.blocks[block].text()),
+                _ => (1, self.blocks[block].text()),
+            })
+            .collect()
+    }
+
+    fn go_to_heading(&mut self, heading: usize) {
+        if let Some(&block) = self.headings.get(heading) {
+            self.scroll = self.starts[block];
+            self.scroll_by(0);
+        }
+    }
+
+    /// Scrolls, goes to headings, shows markers or moves in the contents.
+    /// Returns whether `action` was used.
+    pub fn action(&mut self, action: &Action) -> bool {
+        if let Some(selected) = self.contents {
+            let last = self.headings.len().saturating_sub(1);
+            let selected = match action {
+                Action::Down => (selected + 1).min(last),
+                Action::Up => selected.saturating_sub(1),
+                Action::PageDown => (selected + self.page).min(last),
+                Action::PageUp => selected.saturating_sub(self.page),
+                Action::Home => 0,
+                Action::End => last,
+                Action::Enter => {
+                    self.contents = None;
+                    self.go_to_heading(selected);
+                    return true;
+                }
+                Action::Back => {
+                    self.contents = None;
+                    return true;
+                }
+                _ => return false,
+            };
+            self.contents = Some(selected);
+            return true;
+        }
+        match action {
+            Action::Down => self.scroll_by(1),
+            Action::Up => self.scroll_by(-1),
+            Action::PageDown => self.scroll_by(self.page as isize),
+            Action::PageUp => self.scroll_by(-(self.page as isize)),
+            Action::Home => self.scroll = 0,
+            Action::End => self.scroll_by(isize::MAX),
+            Action::NextSection => {
+                let next = self.headings.iter().position(|&block| self.starts[block] > self.scroll);
+                if let Some(next) = next {
+                    self.go_to_heading(next);
+                }
+            }
+            Action::PrevSection => {
+                let previous = self.headings.iter().rposition(|&block| self.starts[block] < self.scroll);
+                if let Some(previous) = previous {
+                    self.go_to_heading(previous);
+                }
+            }
+            Action::ToggleMarkers => {
+                self.markers = !self.markers;
+                self.relayout();
+                self.scroll_by(0);
+            }
+            Action::Enter if !self.headings.is_empty() => self.contents = Some(self.current_heading().unwrap_or(0)),
+            _ => return false,
+        }
+        true
+    }
+
+    /// One page of lines from the scroll position.
+    pub fn visible_lines(&self) -> Vec<Line<'static>> {
+        let end = (self.scroll + self.page).min(self.lines.len());
+        self.lines[self.scroll.min(end)..end].to_vec()
+    }
+}
@@ -0,0 +1,98 @@
+//! Where the navigator was left in each file, so that `--resume` (or
+//! `resume = true` in the config file) can reopen it there.
+//!
+//! Sessions are kept in one `sessions` file in the state directory, most
+//! recent first, one line per document: its modification time, its canonical
+//! path and the location as `index:tag` steps from the root. A document
+//! modified since is opened at the root, as the indexes may no longer match.
+
+use crate::config::state_path;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Documents remembered; older sessions are forgotten.
+const MAX_SESSIONS: usize = 200;
+
+/// One level of a location: the selected child's position among its
+/// siblings, and its tag, which is checked when the location is restored.
+pub type Step = (usize, String);
+
+/// Identifies a document on disk as it was when its session was saved.
+pub struct SessionKey {
+    path: String,
+    modified: u128,
+}
+
+/// What is known about a document's last session.
+pub enum Saved {
+    At(Vec<Step>),
+    /// The document was modified after the session was saved.
+    Changed,
+    None,
+}
+
+impl SessionKey {
+    /// The key of the file at `path`, unless it has no usable path or time.
+    pub fn for_file(path: &Path) -> Option<SessionKey> {
+        let path = path.canonicalize().ok()?;
+        let modified = path.metadata().ok()?.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+        let path = path.to_str().filter(|path| !path.contains(['\t', '\n']))?.to_string();
+        Some(SessionKey { path, modified })
+    }
+
+    pub fn lookup(&self) -> Saved {
+        let text = state_path("sessions").and_then(|path| std::fs::read_to_string(path).ok()).unwrap_or_default();
+        match text.lines().filter_map(parse_line).find(|(_, path, _)| *path == self.path) {
+            Some((modified, _, steps)) if modified == self.modified => Saved::At(steps),
+            Some(_) => Saved::Changed,
+            None => Saved::None,
+        }
+    }
+
+    /// Records `steps` as the document's location, replacing its previous session.
+    pub fn save(&self, steps: &[Step]) -> io::Result<()> {
+        let path = state_path("sessions").ok_or_else(|| io::Error::other("no home directory to keep sessions in"))?;
+        let old = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut text = format!("{}\t{}\t", self.modified, self.path);
+        for (n, (index, tag)) in steps.iter().enumerate() {
+            if n > 0 {
+                text.push(' ');
+            }
+            text.push_str(&format!("{}:{}", index, tag));
+        }
+        text.push('\n');
+        let others = old.lines().filter(|line| parse_line(line).is_some_and(|(_, path, _)| path != self.path));
+        for line in others.take(MAX_SESSIONS - 1) {
+            text.push_str(line);
+            text.push('\n');
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write a copy and rename it, so two quitting instances cannot interleave.
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&partial, text)?;
+        std::fs::rename(&partial, &path)
+    }
+}
+
+/// Parses `modified<TAB>path<TAB>index:tag ...`.
+fn parse_line(line: &str) -> Option<(u128, &str, Vec<Step>)> {
+    let mut fields = line.splitn(3, '\t');
+    let modified = fields.next()?.parse().ok()?;
+    let path = fields.next()?;
+    let steps = fields
+        .next()?
+        .split_whitespace()
+        .map(|step| {
+            let (index, tag) = step.split_once(':')?;
+            Some((index.parse().ok()?, tag.to_string()))
+        })
+        .collect::<Option<Vec<Step>>>()?;
+    Some((modified, path, steps))
+}
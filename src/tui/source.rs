@@ -0,0 +1,234 @@
+use super::action::Action;
+use super::theme;
+use crate::check::{Diagnostic, Severity};
+use ratatui::prelude::*;
+use std::ops::Range;
+
+/// Lexer state at a line boundary of the highlighted source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lex {
+    Text,
+    TagName,
+    /// Inside a tag, after its name.
+    Markup,
+    Quoted(u8),
+    Comment,
+    Cdata,
+    /// `<?...?>`, `<!DOCTYPE ...>` and other declarations.
+    Declaration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Text,
+    Tag,
+    AttrName,
+    AttrValue,
+    Comment,
+    Cdata,
+    Declaration,
+    Plain,
+}
+
+impl Class {
+    fn style(self) -> Style {
+        let theme = theme::current();
+        match self {
+            Class::Text => Style::default().fg(theme.foreground),
+            Class::Tag => Style::default().fg(theme.tag).add_modifier(Modifier::BOLD),
+            Class::AttrName => Style::default().fg(theme.text),
+            Class::AttrValue => Style::default().fg(theme.value),
+            Class::Comment => Style::default().fg(theme.muted).add_modifier(Modifier::ITALIC),
+            Class::Cdata => Style::default().fg(theme.accent),
+            Class::Declaration => Style::default().fg(theme.muted),
+            Class::Plain => Style::default().fg(theme.border),
+        }
+    }
+}
+
+/// Highlights one line starting in `state`, calling `emit` for each run of
+/// equally classified bytes. Returns the state at the end of the line.
+fn lex(line: &str, mut state: Lex, mut emit: impl FnMut(Range<usize>, Class)) -> Lex {
+    let bytes = line.as_bytes();
+    let mut run: Option<(usize, Class)> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        let (class, len, next) = match state {
+            Lex::Text if rest.starts_with(b"<!--") => (Class::Comment, 4, Lex::Comment),
+            Lex::Text if rest.starts_with(b"<![CDATA[") => (Class::Cdata, 9, Lex::Cdata),
+            Lex::Text if rest.starts_with(b"<!") || rest.starts_with(b"<?") => (Class::Declaration, 2, Lex::Declaration),
+            Lex::Text if rest.starts_with(b"</") => (Class::Tag, 2, Lex::TagName),
+            Lex::Text if rest[0] == b'<' => (Class::Tag, 1, Lex::TagName),
+            Lex::Text => (Class::Text, 1, Lex::Text),
+            Lex::TagName | Lex::Markup if rest[0] == b'>' => (Class::Tag, 1, Lex::Text),
+            Lex::TagName | Lex::Markup if rest.starts_with(b"/>") => (Class::Tag, 2, Lex::Text),
+            Lex::TagName if rest[0].is_ascii_whitespace() => (Class::Plain, 1, Lex::Markup),
+            Lex::TagName => (Class::Tag, 1, Lex::TagName),
+            Lex::Markup => match rest[0] {
+                q @ (b'"' | b'\'') => (Class::AttrValue, 1, Lex::Quoted(q)),
+                b'=' | b'/' => (Class::Plain, 1, Lex::Markup),
+                b if b.is_ascii_whitespace() => (Class::Plain, 1, Lex::Markup),
+                _ => (Class::AttrName, 1, Lex::Markup),
+            },
+            Lex::Quoted(q) if rest[0] == q => (Class::AttrValue, 1, Lex::Markup),
+            Lex::Quoted(q) => (Class::AttrValue, 1, Lex::Quoted(q)),
+            Lex::Comment if rest.starts_with(b"-->") => (Class::Comment, 3, Lex::Text),
+            Lex::Comment => (Class::Comment, 1, Lex::Comment),
+            Lex::Cdata if rest.starts_with(b"]]>") => (Class::Cdata, 3, Lex::Text),
+            Lex::Cdata => (Class::Cdata, 1, Lex::Cdata),
+            Lex::Declaration if rest[0] == b'>' => (Class::Declaration, 1, Lex::Text),
+            Lex::Declaration => (Class::Declaration, 1, Lex::Declaration),
+        };
+        match run {
+            Some((_, current)) if current == class => {}
+            Some((start, current)) => {
+                emit(start..i, current);
+                run = Some((i, class));
+            }
+            None => run = Some((i, class)),
+        }
+        state = next;
+        i += len;
+    }
+    if let Some((start, class)) = run {
+        emit(start..bytes.len(), class);
+    }
+    state
+}
+
+/// Scrollable, syntax-highlighted raw source of the selected element (`v`),
+/// next to the list or in place of it.
+pub struct SourceView {
+    pub full_screen: bool,
+    pub scroll: usize,
+    /// Lines that fit on screen, as last drawn.
+    page: usize,
+    /// Byte range of the element shown.
+    pub span: Range<usize>,
+    /// Start offset (relative to `span`) and lexer state of every line.
+    lines: Vec<(usize, Lex)>,
+    /// Lines with problems and the most severe one on each, once the document
+    /// has been checked.
+    pub markers: Option<Vec<(usize, Severity)>>,
+}
+
+impl SourceView {
+    pub fn new(full_screen: bool) -> Self {
+        Self {
+            full_screen,
+            scroll: 0,
+            page: 20,
+            span: 0..0,
+            lines: Vec::new(),
+            markers: None,
+        }
+    }
+
+    /// Shows the element at `span` of `xml`, unless it already is.
+    pub fn show(&mut self, xml: &str, span: Range<usize>) {
+        if span == self.span && !self.lines.is_empty() {
+            return;
+        }
+        let source = &xml[span.clone()];
+        self.span = span;
+        self.scroll = 0;
+        self.lines.clear();
+        self.markers = None;
+        let mut state = Lex::Text;
+        let mut start = 0;
+        for line in source.split('\n') {
+            self.lines.push((start, state));
+            state = lex(line, state, |_, _| {});
+            start += line.len() + 1;
+        }
+    }
+
+    /// Index of the line containing byte `offset` of the document.
+    fn line_of(&self, offset: usize) -> usize {
+        let relative = offset.saturating_sub(self.span.start);
+        self.lines.partition_point(|(start, _)| *start <= relative).saturating_sub(1)
+    }
+
+    /// Records gutter markers for the `diagnostics` inside the element shown.
+    pub fn mark(&mut self, diagnostics: &[Diagnostic]) {
+        let mut markers: Vec<(usize, Severity)> = diagnostics
+            .iter()
+            .filter(|d| self.span.contains(&d.offset))
+            .map(|d| (self.line_of(d.offset), d.severity))
+            .collect();
+        // Keep the most severe marker per line; Error sorts first.
+        markers.sort();
+        markers.dedup_by_key(|(line, _)| *line);
+        self.markers = Some(markers);
+    }
+
+    /// Scrolls so that the line with byte `offset` is near the top.
+    pub fn scroll_to(&mut self, offset: usize) {
+        self.scroll = self.line_of(offset).saturating_sub(2);
+        self.scroll_by(0);
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Sets the number of lines on screen, keeping the last page full.
+    pub fn fit(&mut self, page: usize) {
+        self.page = page.max(1);
+        self.scroll_by(0);
+    }
+
+    pub fn scroll_by(&mut self, delta: isize) {
+        let max = self.lines.len().saturating_sub(self.page);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max);
+    }
+
+    /// Scrolls for navigation keys: always for paging, and for every key
+    /// when the list is hidden. Returns whether `action` was used.
+    pub fn scroll_action(&mut self, action: &Action) -> bool {
+        match action {
+            Action::PageDown => self.scroll_by(self.page as isize),
+            Action::PageUp => self.scroll_by(-(self.page as isize)),
+            Action::Down if self.full_screen => self.scroll_by(1),
+            Action::Up if self.full_screen => self.scroll_by(-1),
+            Action::Home if self.full_screen => self.scroll = 0,
+            Action::End if self.full_screen => self.scroll_by(isize::MAX),
+            _ => return false,
+        }
+        true
+    }
+
+    /// One page of highlighted lines from the scroll position, numbered from
+    /// the element's first line.
+    pub fn visible_lines(&self, xml: &str) -> Vec<Line<'static>> {
+        let theme = theme::current();
+        let source = &xml[self.span.clone()];
+        let width = self.lines.len().to_string().len();
+        let end = (self.scroll + self.page).min(self.lines.len());
+        (self.scroll..end)
+            .map(|n| {
+                let (start, state) = self.lines[n];
+                let stop = self.lines.get(n + 1).map_or(source.len(), |(next, _)| next - 1);
+                let line = &source[start..stop];
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                let marker = self
+                    .markers
+                    .as_ref()
+                    .and_then(|markers| markers.binary_search_by_key(&n, |(line, _)| *line).ok().map(|i| markers[i].1));
+                let gutter = match marker {
+                    Some(Severity::Error) => Span::styled("● ", Style::default().fg(theme.error)),
+                    Some(Severity::Warning) => Span::styled("● ", Style::default().fg(theme.warning)),
+                    Some(Severity::Info) => Span::styled("● ", Style::default().fg(theme.accent)),
+                    None if self.markers.as_ref().is_some_and(|m| !m.is_empty()) => Span::raw("  "),
+                    None => Span::raw(""),
+                };
+                let mut spans = vec![gutter, Span::styled(format!("{:>width$} ", n + 1), Style::default().fg(theme.muted))];
+                lex(line, state, |range, class| {
+                    spans.push(Span::styled(line[range].replace('\t', "    "), class.style()));
+                });
+                Line::from(spans)
+            })
+            .collect()
+    }
+}
@@ -1,15 +1,388 @@
+use super::action::Action;
+use super::compare::DiffMarks;
+use super::diagnostics::DiagnosticsPanel;
+use super::fingerprint::{Fingerprint, Fingerprints};
+use super::hexdump::HexView;
+use super::session::Step;
+use super::source::SourceView;
+use super::table::TableView;
+use super::reading::ReadingView;
+use super::text::{TextView, leaf_text};
+use super::tree::TreeView;
+use crate::check::{line_column, line_start};
+use crate::query::{Query, select};
+use crate::regex::Regex;
+use crate::profile::Profile;
+use crate::schema::Cardinality;
+use crate::parser::{attribute_pairs, decode_entities, extract_attributes};
+use crate::splice::{self, Edited, Splice};
 use crate::xml::{Node, XmlExplorer};
 use ratatui::widgets::ListState;
 use ratatui::widgets::ScrollbarState;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Children read at a time when entering an element, and shown per page.
+const WINDOW: usize = 500;
+
+/// How far past the selection children are kept read.
+const LOOKAHEAD: usize = 100;
 
 /// Info data: (attributes, children_count)
-pub type InfoData<'a> = (Vec<(&'a str, &'a str)>, usize);
+/// What the info popup (Space) shows about the selected element.
+pub struct InfoData<'a> {
+    pub attributes: Vec<(&'a str, &'a str)>,
+    pub child_count: usize,
+    /// Elements nested at any depth.
+    pub descendant_count: usize,
+    /// Byte offset of the start tag, and the same position as 1-based line and column.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    /// Bytes from the start tag to the end of the end tag.
+    pub size: usize,
+}
 
 /// A level in the XML tree navigation.
 pub struct Level<'a> {
     pub tag: Option<&'a str>,
+    /// Title override for synthetic levels such as query results.
+    pub label: Option<String>,
+    /// Children read so far; elements with many children are read in windows
+    /// as the selection approaches the end of what has been read.
     pub children: Vec<Node<'a>>,
+    /// Start of the next unread child, while some are left.
+    pub more: Option<usize>,
+    /// Number of children, including those not read yet.
+    pub total: usize,
     pub last_selected: usize,
+    /// Indexes of marked children, used by batch operations.
+    pub marked: BTreeSet<usize>,
+    /// Sorted or filtered list shown instead of the children in document order.
+    pub view: Option<LevelView>,
+}
+
+/// Order of the children in the list (`s`, `S`).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Document,
+    Tag,
+    /// The element's text, numerically when both values are numbers.
+    Text,
+    Attribute(String),
+}
+
+impl SortKey {
+    /// Parses a script argument: `document`, `tag`, `text` or `@name`.
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        match arg {
+            "" | "document" => Ok(SortKey::Document),
+            "tag" => Ok(SortKey::Tag),
+            "text" => Ok(SortKey::Text),
+            _ => match arg.strip_prefix('@') {
+                Some(name) if !name.is_empty() => Ok(SortKey::Attribute(name.to_string())),
+                _ => Err(format!("unknown sort key '{}' (expected document, tag, text or @name)", arg)),
+            },
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            SortKey::Document => "document order".to_string(),
+            SortKey::Tag => "tag".to_string(),
+            SortKey::Text => "text".to_string(),
+            SortKey::Attribute(name) => format!("@{}", name),
+        }
+    }
+}
+
+/// A level's children as listed after sorting, filtering (`/`) and
+/// grouping (`G`). A filter starting with `~` matches fuzzily: its
+/// characters in order, not necessarily together.
+#[derive(Debug, Clone, Default)]
+pub struct LevelView {
+    pub sort: SortKey,
+    pub filter: String,
+    /// Indexes into the level's children, in list order.
+    pub rows: Vec<usize>,
+    /// Whether children sharing a tag are folded into one row.
+    pub grouped: bool,
+    /// Tags whose groups are unfolded.
+    pub expanded: BTreeSet<String>,
+    /// The list while grouped: `rows` gathered by tag behind a row per group.
+    pub lines: Vec<ListRow>,
+}
+
+/// A row of a grouped list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListRow {
+    Child(usize),
+    /// `count` children with the tag of child `first`; only tags that are
+    /// repeated are grouped.
+    Group { first: usize, count: usize },
+}
+
+impl LevelView {
+    /// Rebuilds `lines` from `rows`: each repeated tag gets a row where it
+    /// first occurs, followed by all its children if it is expanded.
+    fn group(&mut self, children: &[Node]) {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut by_tag: HashMap<&str, usize> = HashMap::new();
+        for &i in &self.rows {
+            let next = groups.len();
+            let group = *by_tag.entry(children[i].tag).or_insert(next);
+            if group == next {
+                groups.push(Vec::new());
+            }
+            groups[group].push(i);
+        }
+        self.lines.clear();
+        for members in groups {
+            let first = members[0];
+            if members.len() == 1 {
+                self.lines.push(ListRow::Child(first));
+                continue;
+            }
+            self.lines.push(ListRow::Group { first, count: members.len() });
+            if self.expanded.contains(children[first].tag) {
+                self.lines.extend(members.into_iter().map(ListRow::Child));
+            }
+        }
+    }
+
+
+    fn matches(&self, node: &Node) -> bool {
+        let filter = self.filter.to_lowercase();
+        let haystacks = [node.tag, node.attributes_raw, node.text.unwrap_or("")];
+        match filter.strip_prefix('~') {
+            Some(pattern) => haystacks.iter().any(|h| fuzzy_match(&h.to_lowercase(), pattern)),
+            None => haystacks.iter().any(|h| h.to_lowercase().contains(&filter)),
+        }
+    }
+}
+
+/// Whether the characters of `pattern` appear in `text` in order.
+fn fuzzy_match(text: &str, pattern: &str) -> bool {
+    let mut chars = text.chars();
+    pattern.chars().all(|p| chars.any(|c| c == p))
+}
+
+/// Compares two values numerically when both are numbers, else as text.
+fn compare_values(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y),
+        _ => a.cmp(b),
+    }
+}
+
+impl<'a> Level<'a> {
+    /// Rows in the list: the view's, or all children read so far.
+    pub fn len(&self) -> usize {
+        match &self.view {
+            Some(view) if view.grouped => view.lines.len(),
+            Some(view) => view.rows.len(),
+            None => self.children.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rows the list will have once every child is read.
+    pub fn total_rows(&self) -> usize {
+        match &self.view {
+            Some(_) => self.len(),
+            None => self.total,
+        }
+    }
+
+    /// Index into `children` of list row `row`, unless it is a group's row.
+    pub fn child_index(&self, row: usize) -> Option<usize> {
+        match &self.view {
+            Some(view) if view.grouped => match view.lines.get(row)? {
+                ListRow::Child(i) => Some(*i),
+                ListRow::Group { .. } => None,
+            },
+            Some(view) => view.rows.get(row).copied(),
+            None => (row < self.children.len()).then_some(row),
+        }
+    }
+
+    /// The first child and size of the group on list row `row`, if it is one.
+    pub fn group_at(&self, row: usize) -> Option<(usize, usize)> {
+        match self.view.as_ref()?.lines.get(row)? {
+            ListRow::Group { first, count } => Some((*first, *count)),
+            ListRow::Child(_) => None,
+        }
+    }
+
+    /// Whether the group holding child `index` is unfolded.
+    pub fn is_expanded(&self, index: usize) -> bool {
+        self.view.as_ref().is_some_and(|view| view.expanded.contains(self.children[index].tag))
+    }
+
+    /// Indexes of the children the view lets through, in list order and
+    /// including those folded into groups.
+    pub fn shown(&self) -> Vec<usize> {
+        match &self.view {
+            Some(view) => view.rows.clone(),
+            None => (0..self.children.len()).collect(),
+        }
+    }
+
+    /// The node on list row `row`.
+    pub fn node(&self, row: usize) -> Option<&Node<'a>> {
+        self.children.get(self.child_index(row)?)
+    }
+
+    /// List row showing child `index`, if the view shows it.
+    pub fn row_of(&self, index: usize) -> Option<usize> {
+        match &self.view {
+            Some(view) if view.grouped => view.lines.iter().position(|&row| row == ListRow::Child(index)),
+            Some(view) => view.rows.iter().position(|&i| i == index),
+            None => Some(index),
+        }
+    }
+
+    /// Rows of the page holding row `selected`.
+    pub fn page(&self, selected: usize) -> Range<usize> {
+        let len = self.len();
+        let start = (selected / WINDOW * WINDOW).min(len);
+        start..(start + WINDOW).min(len)
+    }
+
+    /// Folds or unfolds the group of child `index`.
+    fn toggle_group(&mut self, index: usize) {
+        let Some(view) = self.view.as_mut() else {
+            return;
+        };
+        let tag = self.children[index].tag;
+        if !view.expanded.remove(tag) {
+            view.expanded.insert(tag.to_string());
+        }
+        view.group(&self.children);
+    }
+
+    /// Sorts, filters and groups the children into `view`, or drops the view
+    /// when it would list them all in document order. All children must be read.
+    fn apply_view(&mut self, xml: &str, mut view: LevelView) {
+        if view.sort == SortKey::Document && view.filter.is_empty() && !view.grouped {
+            self.view = None;
+            return;
+        }
+        let children = &self.children;
+        view.rows = (0..children.len()).filter(|&i| view.matches(&children[i])).collect();
+        match &view.sort {
+            SortKey::Document => {}
+            SortKey::Tag => view.rows.sort_by_key(|&i| children[i].tag),
+            SortKey::Text => view.rows.sort_by(|&a, &b| match (children[a].text, children[b].text) {
+                (Some(x), Some(y)) => compare_values(x, y),
+                // Elements without text go last.
+                (x, y) => y.is_some().cmp(&x.is_some()),
+            }),
+            SortKey::Attribute(name) => {
+                let values: Vec<Option<&str>> = children
+                    .iter()
+                    .map(|node| extract_attributes(xml, node.offset).into_iter().find(|(key, _)| key == name).map(|(_, value)| value))
+                    .collect();
+                view.rows.sort_by(|&a, &b| match (values[a], values[b]) {
+                    (Some(x), Some(y)) => compare_values(x, y),
+                    (x, y) => y.is_some().cmp(&x.is_some()),
+                });
+            }
+        }
+        if view.grouped {
+            view.group(children);
+        }
+        self.view = Some(view);
+    }
+}
+
+/// How much of each element's attributes the list shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeDisplay {
+    Hidden,
+    /// One line, cut after 40 characters.
+    #[default]
+    Summary,
+    /// Every attribute, wrapped onto extra lines.
+    Full,
+}
+
+/// The list's attribute display mode and name filter (`a` and `A`).
+#[derive(Debug, Clone, Default)]
+pub struct AttributeView {
+    pub display: AttributeDisplay,
+    /// Attribute names to show; empty shows all.
+    pub filter: Vec<String>,
+}
+
+impl AttributeView {
+    pub fn cycle(&mut self) {
+        self.display = match self.display {
+            AttributeDisplay::Hidden => AttributeDisplay::Summary,
+            AttributeDisplay::Summary => AttributeDisplay::Full,
+            AttributeDisplay::Full => AttributeDisplay::Hidden,
+        };
+    }
+
+    /// Sets the filter from a list of names separated by commas or spaces.
+    pub fn set_filter(&mut self, names: &str) {
+        self.filter = names
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+
+    pub fn shows(&self, name: &str) -> bool {
+        self.filter.is_empty() || self.filter.iter().any(|f| f == name)
+    }
+}
+
+/// What a submitted prompt line is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    SelectRegex,
+    ExportFile,
+    /// A new value for the selected element's text or one of its attributes.
+    Edit,
+    AttributeFilter,
+    FilterChildren,
+    SortAttribute,
+    TableColumns,
+    ExportTable,
+    ColumnFilter,
+    /// A `:` command line such as `offset 123456` or `line 98765`.
+    Command,
+}
+
+/// What `y` copies from the selected element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YankKind {
+    Path,
+    Text,
+    Xml,
+}
+
+/// A change to an element staged with `d` or `p`, made when it is saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pending {
+    Delete,
+    /// Adds this many copies after the element.
+    Duplicate(usize),
+}
+
+/// A single-line input shown in place of the help bar.
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
 }
 
 pub struct TuiState<'a> {
@@ -21,23 +394,59 @@ pub struct TuiState<'a> {
     pub items_len: usize,
     pub show_info_popup: bool,
     pub info_popup_data: Option<InfoData<'a>>,
+    /// Cardinality histogram of the selected tag within its parent type, while shown.
+    pub cardinality: Option<Cardinality>,
+    /// Raw source viewer for the selected element, while open.
+    pub source: Option<SourceView>,
+    /// Hexdump popup around the selected element or problem, while shown.
+    pub hexdump: Option<HexView>,
+    /// Full text of the selected leaf, while shown.
+    pub text: Option<TextView>,
+    /// Reading view of the selected element, while open.
+    pub reading: Option<ReadingView>,
+    /// Tree view of the current level, shown instead of the list while set.
+    pub tree: Option<TreeView<'a>>,
+    /// Table view of the current level, shown instead of the list while set.
+    pub table: Option<TableView<'a>>,
+    /// Well-formedness problems, checked the first time the panel is opened.
+    pub diagnostics: Option<DiagnosticsPanel>,
+    pub attributes: AttributeView,
+    pub prompt: Option<Prompt>,
+    /// One-shot status message, cleared on the next key press.
+    pub message: Option<String>,
+    /// Differences from the document in the other pane, when comparing.
+    pub diff: Option<DiffMarks>,
+    /// The document's profile, which labels rows of formats like GPX.
+    pub profile: Option<Profile>,
+    /// Changes staged for elements, by offset, with the element's bytes.
+    pub pending: BTreeMap<usize, (Range<usize>, Pending)>,
+    /// Fingerprints of the elements selected so far, for the status bar.
+    pub fingerprints: Fingerprints,
 }
 
 impl<'a> TuiState<'a> {
     pub fn new(xml: &'a str) -> Self {
-        let explorer = XmlExplorer::new(xml);
+        Self::with_explorer(XmlExplorer::new(xml))
+    }
 
+    pub fn with_explorer(explorer: XmlExplorer<'a>) -> Self {
         let children = match explorer.root() {
             Some(node) => vec![node],
             None => vec![],
         };
+        let profile = children.first().and_then(|root| Profile::detect(root.tag));
         let items_len = children.len();
 
         Self {
             stack: vec![Level {
                 tag: None,
+                label: None,
                 children,
+                more: None,
+                total: items_len,
                 last_selected: 0,
+                marked: BTreeSet::new(),
+                view: None,
             }],
             selected: 0,
             list_state: ListState::default(),
@@ -46,6 +455,21 @@ impl<'a> TuiState<'a> {
             items_len,
             show_info_popup: false,
             info_popup_data: None,
+            cardinality: None,
+            source: None,
+            hexdump: None,
+            text: None,
+            reading: None,
+            tree: None,
+            table: None,
+            diagnostics: None,
+            attributes: AttributeView::default(),
+            prompt: None,
+            message: None,
+            diff: None,
+            profile,
+            pending: BTreeMap::new(),
+            fingerprints: Fingerprints::default(),
         }
     }
 
@@ -55,10 +479,34 @@ impl<'a> TuiState<'a> {
 
     /// Returns the number of children at the current level
     fn current_children_len(&self) -> usize {
-        self.stack.last().map_or(0, |l| l.children.len())
+        self.stack.last().map_or(0, Level::len)
+    }
+
+    /// Reads more children of the current level until at least `len` are
+    /// read or none are left. Whole pages are read, so the page holding the
+    /// selection is always complete.
+    fn load_children(&mut self, len: usize) {
+        let Some(level) = self.stack.last_mut() else {
+            return;
+        };
+        let len = len.div_ceil(WINDOW).saturating_mul(WINDOW);
+        while level.children.len() < len
+            && let Some(resume) = level.more
+        {
+            let (children, more) = self.explorer.more_children(resume, WINDOW.max(len - level.children.len()));
+            level.children.extend(children);
+            level.more = more;
+        }
+        self.items_len = level.len();
+    }
+
+    /// Reads every remaining child of the current level.
+    fn load_all_children(&mut self) {
+        self.load_children(usize::MAX);
     }
 
     pub fn go_down(&mut self) {
+        self.load_children(self.selected + 2 + LOOKAHEAD);
         let len = self.current_children_len();
         if self.selected + 1 < len {
             self.selected += 1;
@@ -75,15 +523,23 @@ impl<'a> TuiState<'a> {
         self.scrollbar_state = self.scrollbar_state.position(self.selected);
     }
 
+    /// Rows moved by PageUp and PageDown: a whole page in lists longer than
+    /// one, else ten.
+    fn page_step(&self) -> usize {
+        if self.get_current_level().total_rows() > WINDOW { WINDOW } else { 10 }
+    }
+
     pub fn page_down(&mut self) {
+        let step = self.page_step();
+        self.load_children(self.selected + step + 1 + LOOKAHEAD);
         let len = self.current_children_len();
-        self.selected = (self.selected + 10).min(len.saturating_sub(1));
+        self.selected = (self.selected + step).min(len.saturating_sub(1));
         self.list_state.select(Some(self.selected));
         self.scrollbar_state = self.scrollbar_state.position(self.selected);
     }
 
     pub fn page_up(&mut self) {
-        self.selected = self.selected.saturating_sub(10);
+        self.selected = self.selected.saturating_sub(self.page_step());
         self.list_state.select(Some(self.selected));
         self.scrollbar_state = self.scrollbar_state.position(self.selected);
     }
@@ -95,20 +551,39 @@ impl<'a> TuiState<'a> {
     }
 
     pub fn end(&mut self) {
+        self.load_all_children();
         let len = self.current_children_len();
         self.selected = len.saturating_sub(1);
         self.list_state.select(Some(self.selected));
         self.scrollbar_state = self.scrollbar_state.position(self.selected);
     }
 
+    /// Goes into the selected element, or shows the text of a leaf.
     pub fn enter(&mut self) {
+        if !self.descend()
+            && let Some(node) = self.selected_node()
+        {
+            self.text = Some(TextView::new(node.tag, leaf_text(self.explorer.raw(&node))));
+        }
+    }
+
+    /// Goes into the selected element, or unfolds the selected group. Returns
+    /// false, leaving the level as it is, for a leaf.
+    fn descend(&mut self) -> bool {
+        if let Some(level) = self.stack.last_mut()
+            && let Some((first, _)) = level.group_at(self.selected)
+        {
+            level.toggle_group(first);
+            self.items_len = level.len();
+            return true;
+        }
         // Get the selected node without holding a borrow on self
         // Note: we need to clone the node structure (it's just references and usize)
         // to pass it to the explorer which needs a fresh borrow of self.xml via self.explorer
         let selected_node = self
             .stack
             .last()
-            .and_then(|level| level.children.get(self.selected))
+            .and_then(|level| level.node(self.selected))
             .cloned();
 
         if let Some(node) = selected_node {
@@ -117,16 +592,27 @@ impl<'a> TuiState<'a> {
                 current.last_selected = self.selected;
             }
 
-            let children = self.explorer.children(&node);
+            let (children, more) = self.explorer.children_window(&node, WINDOW);
+            if children.is_empty() && more.is_none() {
+                return false;
+            }
+            let total = if more.is_some() { self.explorer.child_count(&node) } else { children.len() };
             self.items_len = children.len();
             self.stack.push(Level {
                 tag: Some(node.tag),
+                label: None,
                 children,
+                more,
+                total,
                 last_selected: 0,
+                marked: BTreeSet::new(),
+                view: None,
             });
             self.selected = 0;
             self.list_state.select(Some(self.selected));
+            return true;
         }
+        false
     }
 
     pub fn back(&mut self) {
@@ -139,6 +625,22 @@ impl<'a> TuiState<'a> {
         }
     }
 
+    /// Goes up `levels` levels at once (`1`–`9`, or `g` for the root),
+    /// closing the tree or table shown for the level left.
+    pub fn ascend(&mut self, levels: usize) {
+        let levels = levels.min(self.stack.len() - 1);
+        if levels == 0 {
+            self.message = Some("Already at the root".to_string());
+            return;
+        }
+        self.tree = None;
+        self.table = None;
+        self.stack.truncate(self.stack.len() - levels);
+        self.selected = self.stack.last().map_or(0, |l| l.last_selected);
+        self.list_state.select(Some(self.selected));
+        self.items_len = self.current_children_len();
+    }
+
     pub fn toggle_info(&mut self) {
         if self.show_info_popup {
             self.show_info_popup = false;
@@ -149,18 +651,825 @@ impl<'a> TuiState<'a> {
         let selected_node = self
             .stack
             .last()
-            .and_then(|level| level.children.get(self.selected))
+            .and_then(|level| level.node(self.selected))
             .cloned();
 
         if let Some(node) = selected_node {
-            let attributes = self.explorer.attributes(&node);
+            let (line, column) = line_column(self.explorer.xml(), node.offset);
+            self.info_popup_data = Some(InfoData {
+                attributes: self.explorer.attributes(&node),
+                child_count: self.explorer.child_count(&node),
+                descendant_count: self.explorer.descendant_count(&node),
+                offset: node.offset,
+                line,
+                column,
+                size: node.span.len(),
+            });
+            self.show_info_popup = true;
+        }
+    }
 
-            // Get children count.
-            let children = self.explorer.children(&node);
-            let child_count = children.len();
+    /// Shows how many elements of the current level's type contain 0, 1 or
+    /// several of the selected tag, counted over the whole document.
+    pub fn toggle_cardinality(&mut self) {
+        if self.cardinality.take().is_some() {
+            return;
+        }
+        let Some(node) = self.selected_node() else {
+            return;
+        };
+        let parent = match &self.tree {
+            Some(tree) => tree.parent_tag(),
+            None => self.get_current_level().tag,
+        };
+        match parent {
+            Some(parent) => self.cardinality = Some(Cardinality::count(self.explorer.xml(), parent, node.tag)),
+            None => self.message = Some("Cardinality needs a parent element; enter one first".to_string()),
+        }
+    }
 
-            self.info_popup_data = Some((attributes, child_count));
-            self.show_info_popup = true;
+    /// The element the status bar fingerprints: the selected one, unless a
+    /// view without the selection (text, reading, hexdump) fills the pane.
+    fn fingerprinted(&self) -> Option<Node<'a>> {
+        if self.text.is_some() || self.reading.is_some() || self.hexdump.is_some() {
+            return None;
+        }
+        self.selected_node()
+    }
+
+    /// Counts the selected element's fingerprint for up to `budget`, or to
+    /// the end without one; returns whether counting goes on.
+    pub fn update_fingerprint(&mut self, budget: Option<Duration>) -> bool {
+        let Some(node) = self.fingerprinted() else {
+            return false;
+        };
+        self.fingerprints.update(self.explorer.xml(), &node, budget)
+    }
+
+    /// The selected element's tag and fingerprint, with whether it is still
+    /// being counted.
+    pub fn fingerprint(&self) -> Option<(&'a str, Fingerprint, bool)> {
+        let node = self.fingerprinted()?;
+        let (fingerprint, counting) = self.fingerprints.get(node.offset)?;
+        Some((node.tag, fingerprint, counting))
+    }
+
+    /// Cycles the source viewer: beside the list, full screen, closed.
+    pub fn toggle_source(&mut self) {
+        match self.source.as_mut() {
+            None => self.source = Some(SourceView::new(false)),
+            Some(view) if !view.full_screen => view.full_screen = true,
+            Some(_) => self.source = None,
+        }
+    }
+
+    /// Points the source viewer at the selected element.
+    pub fn sync_source(&mut self) {
+        let span = self.selected_node().map_or(0..0, |node| node.span);
+        if let Some(view) = self.source.as_mut() {
+            view.show(self.explorer.xml(), span);
+            if let Some(panel) = &self.diagnostics
+                && view.markers.is_none()
+            {
+                view.mark(&panel.diagnostics);
+            }
+        }
+    }
+
+    /// Shows the bytes around the problem selected in the open diagnostics
+    /// panel, or else around the selected element's start tag.
+    /// Shows or hides the full text of the selected element (`o`).
+    pub fn toggle_text(&mut self) {
+        if self.text.take().is_some() {
+            return;
+        }
+        if let Some(node) = self.selected_node() {
+            let text = match self.explorer.child_count(&node) {
+                0 => leaf_text(self.explorer.raw(&node)),
+                _ => self.explorer.text_content(&node),
+            };
+            self.text = Some(TextView::new(node.tag, text));
+        }
+    }
+
+    /// Handles `action` in the open text view: `x` switches to the hexdump,
+    /// Enter, Backspace and `o` close it and movement keys scroll. Returns
+    /// whether `action` was used.
+    pub fn text_action(&mut self, action: &Action) -> bool {
+        let Some(view) = self.text.as_mut() else {
+            return false;
+        };
+        match action {
+            Action::ToggleHexdump => view.toggle_hex(),
+            Action::Enter | Action::Back | Action::ToggleText => self.text = None,
+            _ => return view.scroll_action(action),
+        }
+        true
+    }
+
+    /// Shows or hides the selected element as prose (`r`).
+    pub fn toggle_reading(&mut self) {
+        if self.reading.take().is_some() {
+            return;
+        }
+        if let Some(node) = self.selected_node() {
+            let view = ReadingView::new(node.tag, self.explorer.raw(&node));
+            if view.is_empty() {
+                self.message = Some(format!("<{}> has no text to read", node.tag));
+            } else {
+                self.reading = Some(view);
+            }
+        }
+    }
+
+    /// Handles `action` in the open reading view: Backspace and `r` close
+    /// it, and the rest scroll, go to headings or list them. Returns whether
+    /// `action` was used.
+    pub fn reading_action(&mut self, action: &Action) -> bool {
+        let Some(view) = self.reading.as_mut() else {
+            return false;
+        };
+        if view.action(action) {
+            return true;
+        }
+        match action {
+            Action::Back | Action::ToggleReading => self.reading = None,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn toggle_hexdump(&mut self) {
+        if self.hexdump.take().is_some() {
+            return;
+        }
+        let problem = self.diagnostics.as_ref().filter(|panel| panel.open).and_then(|panel| panel.selected());
+        let offset = match problem {
+            Some(diagnostic) => Some(diagnostic.offset),
+            None => self.selected_node().map(|node| node.offset),
+        };
+        self.hexdump = offset.map(HexView::new);
+    }
+
+    /// Switches between the list and a tree of the current level.
+    pub fn toggle_tree(&mut self) {
+        if self.tree.take().is_some() {
+            return;
+        }
+        self.table = None;
+        self.load_all_children();
+        let level = self.get_current_level();
+        let shown = level.shown();
+        let selected = level.child_index(self.selected).and_then(|i| shown.iter().position(|&j| j == i)).unwrap_or(0);
+        let nodes: Vec<Node<'a>> = shown.into_iter().map(|i| level.children[i].clone()).collect();
+        self.tree = Some(TreeView::new(self.explorer.xml(), &nodes, selected, level.tag));
+    }
+
+    /// Switches between the list and a table of the current level's
+    /// children, which must share one tag. Closing the table selects its
+    /// selected record in the list.
+    pub fn toggle_table(&mut self) {
+        if let Some(table) = self.table.take() {
+            let offset = table.selected_node().map(|node| node.offset);
+            let level = self.get_current_level();
+            if let Some(row) = offset.and_then(|offset| level.children.iter().position(|node| node.offset == offset)).and_then(|i| level.row_of(i)) {
+                self.selected = row;
+                self.list_state.select(Some(row));
+                self.scrollbar_state = self.scrollbar_state.position(row);
+            }
+            return;
+        }
+        self.tree = None;
+        self.load_all_children();
+        let level = self.get_current_level();
+        let shown = level.shown();
+        let selected = level.child_index(self.selected).and_then(|i| shown.iter().position(|&j| j == i)).unwrap_or(0);
+        let nodes: Vec<Node<'a>> = shown.into_iter().map(|i| level.children[i].clone()).collect();
+        match TableView::new(self.explorer.xml(), &nodes) {
+            Ok(mut table) => {
+                table.selected = selected;
+                self.table = Some(table);
+            }
+            Err(message) => self.message = Some(message),
+        }
+    }
+
+    /// Shows the named columns of the open table, in that order (`C`).
+    pub fn choose_table_columns(&mut self, names: &str) {
+        let Some(table) = self.table.as_mut() else {
+            self.message = Some("Columns can be chosen in the table view (t)".to_string());
+            return;
+        };
+        self.message = Some(match table.choose_columns(names) {
+            Ok(()) => format!("Showing {} of {} columns", table.order.len(), table.columns.len()),
+            Err(err) => err,
+        });
+    }
+
+    /// Writes the open table, with its shown columns in their order and its
+    /// rows as sorted, to `path` as CSV (`E`).
+    pub fn export_table(&mut self, path: &str) {
+        let Some(table) = self.table.as_ref() else {
+            self.message = Some("Nothing to export: open the table view (t) first".to_string());
+            return;
+        };
+        if path.is_empty() {
+            self.message = Some("Export cancelled: no file name".to_string());
+            return;
+        }
+        self.message = Some(match std::fs::write(path, table.to_csv()) {
+            Ok(()) => format!("Wrote {} rows × {} columns to {}", table.rows.len(), table.order.len(), path),
+            Err(err) => format!("Export failed: {}: {}", path, err),
+        });
+    }
+
+    /// Filters the open table's cursor column with `expr` (`/` in the table).
+    pub fn filter_table_column(&mut self, expr: &str) {
+        let Some(table) = self.table.as_mut() else {
+            self.message = Some("Columns can be filtered in the table view (t)".to_string());
+            return;
+        };
+        if let Err(err) = table.filter_column(expr) {
+            self.message = Some(format!("Invalid filter: {}", err));
+        }
+    }
+
+    /// Handles `action` in the table view, if it is shown. There `/`
+    /// filters the cursor column instead of the list, and `S` adds it to
+    /// the sort instead of asking for an attribute.
+    pub fn table_action(&mut self, action: &Action) -> bool {
+        match self.table.as_mut() {
+            Some(_) if *action == Action::Prompt(PromptKind::FilterChildren) => {
+                self.start_prompt(PromptKind::ColumnFilter);
+                true
+            }
+            Some(table) if *action == Action::Prompt(PromptKind::SortAttribute) => table.apply(&Action::AddSortColumn),
+            Some(table) => table.apply(action),
+            None => false,
+        }
+    }
+
+    /// Handles `action` in the tree view, if it is shown.
+    pub fn tree_action(&mut self, action: &Action) -> bool {
+        match self.tree.as_mut() {
+            Some(tree) => tree.apply(action, &mut self.explorer),
+            None => false,
+        }
+    }
+
+    /// Opens or closes the diagnostics panel, checking the document on first use.
+    pub fn toggle_diagnostics(&mut self) {
+        match self.diagnostics.as_mut() {
+            Some(panel) => panel.open = !panel.open,
+            None => self.diagnostics = Some(DiagnosticsPanel::check(self.explorer.xml())),
+        }
+    }
+
+    /// Handles `action` in the open diagnostics panel: movement keys select a
+    /// problem and Enter reveals it. Returns whether `action` was used.
+    pub fn diagnostics_action(&mut self, action: &Action) -> bool {
+        let Some(panel) = self.diagnostics.as_mut().filter(|panel| panel.open) else {
+            return false;
+        };
+        if *action != Action::Enter {
+            return panel.move_action(action);
+        }
+        let Some(diagnostic) = panel.selected() else {
+            return true;
+        };
+        let (offset, line, column) = (diagnostic.offset, diagnostic.line, diagnostic.column);
+        panel.cursor = panel.list_state.selected();
+        self.message = Some(if self.reveal(offset) {
+            format!("Line {}, column {}", line, column)
+        } else {
+            format!("Line {}, column {} is outside the root element", line, column)
+        });
+        true
+    }
+
+    /// Jumps to the next (or previous) problem, checking the document first if
+    /// needed, and scrolls an open source viewer to its line (`]d`, `[d`).
+    pub fn jump_diagnostic(&mut self, forward: bool) {
+        let xml = self.explorer.xml();
+        let from = self.selected_node().map_or(0, |node| node.offset);
+        let panel = self.diagnostics.get_or_insert_with(|| {
+            let mut panel = DiagnosticsPanel::check(xml);
+            panel.open = false;
+            panel
+        });
+        let total = panel.diagnostics.len();
+        let Some(diagnostic) = panel.step(forward, from) else {
+            self.message = Some(match (total, forward) {
+                (0, _) => "No problems found".to_string(),
+                (_, true) => "No more problems below".to_string(),
+                (_, false) => "No more problems above".to_string(),
+            });
+            return;
+        };
+        let (offset, message) = (diagnostic.offset, diagnostic.message.clone());
+        let position = format!("{}:{}", diagnostic.line, diagnostic.column);
+        let number = panel.cursor.map_or(0, |i| i + 1);
+        self.reveal(offset);
+        self.sync_source();
+        if let Some(view) = self.source.as_mut() {
+            view.scroll_to(offset);
+        }
+        self.message = Some(format!("Problem {} of {} at {}: {}", number, total, position, message));
+    }
+
+    /// Where the navigation is, from the root: the child selected at each
+    /// level down to the selection. Query result levels are left out.
+    pub fn location(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for (depth, level) in self.stack.iter().enumerate() {
+            let row = if depth + 1 == self.stack.len() { self.selected } else { level.last_selected };
+            let Some(index) = level.child_index(row).filter(|_| level.label.is_none()) else {
+                break;
+            };
+            steps.push((index, level.children[index].tag.to_string()));
+        }
+        steps
+    }
+
+    /// Goes back to a `location`, as far as the document still matches it.
+    pub fn restore_location(&mut self, steps: &[Step]) {
+        let mut path = String::new();
+        for (depth, (index, tag)) in steps.iter().enumerate() {
+            self.load_children(index + 1);
+            let level = self.get_current_level();
+            if level.children.get(*index).is_none_or(|node| node.tag != tag) {
+                break;
+            }
+            let row = level.row_of(*index).unwrap_or(*index);
+            self.selected = row;
+            self.list_state.select(Some(row));
+            self.scrollbar_state = self.scrollbar_state.position(row);
+            path.push('/');
+            path.push_str(tag);
+            if depth + 1 < steps.len() && !self.descend() {
+                break;
+            }
+        }
+        if !path.is_empty() {
+            self.message = Some(format!("Resumed at {}", path));
+        }
+    }
+
+    /// Navigates from the root to the innermost element containing byte
+    /// `offset` and selects it. Returns false if no element contains it.
+    pub fn reveal(&mut self, offset: usize) -> bool {
+        self.tree = None;
+        self.table = None;
+        while self.stack.len() > 1 {
+            self.back();
+        }
+        let Some(mut i) = self.child_containing(offset) else {
+            return false;
+        };
+        loop {
+            self.selected = i;
+            self.list_state.select(Some(i));
+            self.scrollbar_state = self.scrollbar_state.position(i);
+            if !self.descend() {
+                return true;
+            }
+            match self.child_containing(offset) {
+                Some(child) => i = child,
+                None => {
+                    self.back();
+                    return true;
+                }
+            }
+        }
+    }
+
+    /// Selects the innermost element containing byte `offset` and scrolls an
+    /// open source viewer to it (`:offset`, `:line`).
+    fn go_to(&mut self, offset: usize) -> Result<String, String> {
+        let xml = self.explorer.xml();
+        if offset > xml.len() {
+            return Err(format!("Offset {} is past the end of the document ({} bytes)", offset, xml.len()));
+        }
+        let (line, column) = line_column(xml, offset);
+        if !self.reveal(offset) {
+            return Err(format!("Line {}, column {} (offset {}) is outside the root element", line, column, offset));
+        }
+        self.sync_source();
+        if let Some(view) = self.source.as_mut() {
+            view.scroll_to(offset);
+        }
+        Ok(format!("Line {}, column {} (offset {})", line, column, offset))
+    }
+
+    /// List row of the current level's child containing byte `offset`,
+    /// reading further children as needed. A folded group holding the child
+    /// is unfolded, and a filter hiding it is dropped.
+    fn child_containing(&mut self, offset: usize) -> Option<usize> {
+        let contains = |node: &Node| node.span.contains(&offset) || node.span.start == offset;
+        loop {
+            let level = self.get_current_level();
+            if let Some(i) = level.children.iter().position(contains) {
+                let level = self.stack.last_mut()?;
+                if level.row_of(i).is_none() && level.view.as_ref().is_some_and(|view| view.rows.contains(&i)) {
+                    level.toggle_group(i);
+                }
+                let row = level.row_of(i);
+                if row.is_none() {
+                    level.view = None;
+                }
+                self.items_len = level.len();
+                return Some(row.unwrap_or(i));
+            }
+            // Children are in document order, so stop once past the offset.
+            if level.more.is_none() || level.children.last().is_some_and(|child| child.span.start > offset) {
+                return None;
+            }
+            self.load_children(level.children.len() + WINDOW);
+        }
+    }
+
+    /// Sorts the current level's children by `key`, keeping the filter.
+    pub fn sort_children(&mut self, key: SortKey) {
+        self.update_view(|view| view.sort = key);
+    }
+
+    /// Steps the sort order through document order, tag and text (`s`).
+    pub fn cycle_sort(&mut self) {
+        let key = match self.get_current_level().view.as_ref().map(|view| &view.sort) {
+            None | Some(SortKey::Document) => SortKey::Tag,
+            Some(SortKey::Tag) => SortKey::Text,
+            Some(_) => SortKey::Document,
+        };
+        self.sort_children(key);
+    }
+
+    /// Lists only the children whose tag, attributes or text contain `filter`
+    /// (fuzzily with a leading `~`), keeping the sort order; empty shows all.
+    pub fn filter_children(&mut self, filter: &str) {
+        let filter = filter.to_string();
+        self.update_view(|view| view.filter = filter);
+    }
+
+    /// Folds the current level's children into a row per repeated tag, or
+    /// lists them one by one again (`G`). Enter unfolds a group.
+    pub fn toggle_grouping(&mut self) {
+        self.update_view(|view| view.grouped = !view.grouped);
+    }
+
+    /// Reads all children and rebuilds the current level's view after
+    /// `change`, keeping the selected child selected if it is still listed.
+    fn update_view(&mut self, change: impl FnOnce(&mut LevelView)) {
+        self.tree = None;
+        self.table = None;
+        self.load_all_children();
+        let xml = self.explorer.xml();
+        let Some(level) = self.stack.last_mut() else {
+            return;
+        };
+        let selected = level.child_index(self.selected);
+        let mut view = level.view.take().unwrap_or_default();
+        change(&mut view);
+        level.apply_view(xml, view);
+        self.selected = selected.and_then(|i| level.row_of(i)).unwrap_or(0);
+        self.items_len = level.len();
+        self.list_state.select(Some(self.selected));
+        self.scrollbar_state = self.scrollbar_state.position(self.selected);
+        self.message = Some(match &level.view {
+            None => format!("{} children in document order", level.children.len()),
+            Some(view) if view.grouped => format!(
+                "{} children in {} rows, grouped by tag",
+                view.rows.len(),
+                view.lines.len()
+            ),
+            Some(view) if view.filter.is_empty() => format!("Sorted by {}", view.sort.describe()),
+            Some(view) => format!(
+                "{} of {} match \"{}\", sorted by {}",
+                view.rows.len(),
+                level.children.len(),
+                view.filter,
+                view.sort.describe()
+            ),
+        });
+    }
+
+    /// Toggles the mark on the selected child and moves to the next one. On
+    /// a group's row, marks all of the group, or unmarks it if all are marked.
+    pub fn toggle_mark(&mut self) {
+        if let Some(level) = self.stack.last_mut() {
+            if let Some((first, _)) = level.group_at(self.selected) {
+                let tag = level.children[first].tag;
+                let members: Vec<usize> = level.shown().into_iter().filter(|&i| level.children[i].tag == tag).collect();
+                if members.iter().all(|i| level.marked.contains(i)) {
+                    for i in members {
+                        level.marked.remove(&i);
+                    }
+                } else {
+                    level.marked.extend(members);
+                }
+            } else if let Some(i) = level.child_index(self.selected)
+                && !level.marked.remove(&i)
+            {
+                level.marked.insert(i);
+            }
+        }
+        self.go_down();
+    }
+
+    pub fn clear_marks(&mut self) {
+        if let Some(level) = self.stack.last_mut() {
+            level.marked.clear();
+        }
+    }
+
+    /// The selected element, unless it is the root, which cannot be
+    /// deleted or duplicated.
+    fn stageable(&mut self) -> Option<Node<'a>> {
+        let node = self.selected_node();
+        let root = self.stack.first().and_then(|level| level.children.first()).map(|root| root.offset);
+        match node {
+            None => self.message = Some("Nothing selected".to_string()),
+            Some(node) if Some(node.offset) == root => {
+                self.message = Some("The root element cannot be deleted or duplicated".to_string());
+            }
+            Some(node) => return Some(node),
+        }
+        None
+    }
+
+    /// Stages deleting the selected element, or drops the change staged
+    /// for it.
+    pub fn stage_delete(&mut self) {
+        let Some(node) = self.stageable() else {
+            return;
+        };
+        self.message = Some(match self.pending.remove(&node.offset) {
+            Some(_) => format!("Dropped the change to <{}>", node.tag),
+            None => {
+                self.pending.insert(node.offset, (node.span.clone(), Pending::Delete));
+                format!("<{}> will be deleted; :w to save, d again to keep it", node.tag)
+            }
+        });
+    }
+
+    /// Stages another copy of the selected element after it.
+    pub fn stage_duplicate(&mut self) {
+        let Some(node) = self.stageable() else {
+            return;
+        };
+        let copies = match self.pending.get(&node.offset) {
+            Some((_, Pending::Delete)) => {
+                self.message = Some(format!("<{}> will be deleted; d to keep it", node.tag));
+                return;
+            }
+            Some((_, Pending::Duplicate(copies))) => copies + 1,
+            None => 1,
+        };
+        self.pending.insert(node.offset, (node.span.clone(), Pending::Duplicate(copies)));
+        self.message = Some(format!("<{}> will be copied {} time(s); :w to save", node.tag, copies));
+    }
+
+    /// Makes the staged changes to `edited`, from the last element to the
+    /// first, so an element's span still covers what was staged inside it.
+    pub fn apply_pending(&self, edited: &mut Edited) {
+        for (span, pending) in self.pending.values().rev() {
+            let span = edited.offset(span.start)..edited.offset(span.end);
+            edited.apply(match pending {
+                Pending::Delete => splice::delete(edited.text(), span),
+                Pending::Duplicate(copies) => splice::duplicate(edited.text(), span, *copies),
+            });
+        }
+    }
+
+    pub fn start_prompt(&mut self, kind: PromptKind) {
+        let input = match kind {
+            PromptKind::Edit => self.edit_default(),
+            _ => String::new(),
+        };
+        self.prompt = Some(Prompt { kind, input });
+    }
+
+    /// What the edit prompt starts with: the selected element's text when it
+    /// has no child elements (and the text fits on a line), else its first
+    /// attribute as `@name=value`.
+    fn edit_default(&self) -> String {
+        let Some(node) = self.selected_node() else {
+            return String::new();
+        };
+        let xml = self.explorer.xml();
+        if splice::set_text(xml, node.offset, "").is_some() {
+            let text = leaf_text(self.explorer.raw(&node));
+            return if text.contains('\n') { String::new() } else { text };
+        }
+        match attribute_pairs(node.attributes_raw).first() {
+            Some((name, value)) => format!("@{}={}", name, decode_entities(value)),
+            None => String::new(),
+        }
+    }
+
+    /// The splice for an edit prompt's `input` to `edited`: `@name=value`
+    /// sets an attribute of the selected element, anything else its text.
+    pub fn edit_splice(&self, edited: &Edited, input: &str) -> Result<Splice, String> {
+        let node = self.selected_node().ok_or("Nothing to edit")?;
+        let (xml, start) = (edited.text(), edited.offset(node.offset));
+        match input.strip_prefix('@') {
+            Some(assignment) => {
+                let (name, value) = assignment.split_once('=').ok_or("Attribute edits are written @name=value")?;
+                let name = name.trim();
+                if name.is_empty() || name.contains(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\'' | '=' | '/')) {
+                    return Err(format!("'{}' is not an attribute name", name));
+                }
+                splice::set_attribute(xml, start, name, value).ok_or_else(|| format!("<{}> could not be read", node.tag))
+            }
+            None => splice::set_text(xml, start, input)
+                .ok_or_else(|| format!("<{}> has child elements; edit an attribute with @name=value", node.tag)),
+        }
+    }
+
+    pub fn cancel_prompt(&mut self) {
+        self.prompt = None;
+    }
+
+    /// Closes the prompt and turns its input into the action it was opened for.
+    pub fn take_prompt_action(&mut self) -> Option<Action> {
+        let prompt = self.prompt.take()?;
+        Some(match prompt.kind {
+            PromptKind::SelectRegex => Action::SelectRegex(prompt.input),
+            PromptKind::ExportFile => Action::ExportSelected(prompt.input.trim().to_string()),
+            PromptKind::Edit => Action::Edit(prompt.input),
+            PromptKind::AttributeFilter => Action::FilterAttributes(prompt.input.trim().to_string()),
+            PromptKind::FilterChildren => Action::FilterChildren(prompt.input.trim().to_string()),
+            PromptKind::SortAttribute => match prompt.input.trim().trim_start_matches('@') {
+                "" => Action::Sort(SortKey::Document),
+                name => Action::Sort(SortKey::Attribute(name.to_string())),
+            },
+            PromptKind::TableColumns => Action::TableColumns(prompt.input.trim().to_string()),
+            PromptKind::ExportTable => Action::ExportTable(prompt.input.trim().to_string()),
+            PromptKind::ColumnFilter => Action::FilterColumn(prompt.input.trim().to_string()),
+            PromptKind::Command => Action::Command(prompt.input.trim().to_string()),
+        })
+    }
+
+    /// Marks every child of the current level whose tag, raw attributes or
+    /// text matches `pattern`, keeping existing marks.
+    pub fn select_matching(&mut self, pattern: &str) {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(err) => {
+                self.message = Some(format!("Invalid regex: {}", err));
+                return;
+            }
+        };
+        self.load_all_children();
+        let Some(level) = self.stack.last_mut() else {
+            return;
+        };
+        let mut matched = 0;
+        let shown = level.shown();
+        let total = shown.len();
+        for i in shown {
+            let node = &level.children[i];
+            if regex.is_match(node.tag)
+                || regex.is_match(node.attributes_raw)
+                || node.text.is_some_and(|t| regex.is_match(t))
+            {
+                level.marked.insert(i);
+                matched += 1;
+            }
+        }
+        self.message = Some(format!(
+            "Selected {} of {} ({} marked)",
+            matched,
+            total,
+            level.marked.len()
+        ));
+    }
+
+    /// Runs `source` as a query and pushes a level listing every match.
+    pub fn apply_query(&mut self, source: &str) -> Result<usize, String> {
+        let query = Query::parse(source)?;
+        let children: Vec<Node<'a>> = select(self.explorer.xml(), &query)
+            .into_iter()
+            .filter_map(|range| self.explorer.node_at(range.start))
+            .collect();
+        let count = children.len();
+        if let Some(current) = self.stack.last_mut() {
+            current.last_selected = self.selected;
+        }
+        self.items_len = count;
+        self.stack.push(Level {
+            tag: None,
+            label: Some(format!("Query {}", source)),
+            children,
+            more: None,
+            total: count,
+            last_selected: 0,
+            marked: BTreeSet::new(),
+            view: None,
+        });
+        self.selected = 0;
+        self.list_state.select(Some(self.selected));
+        self.message = Some(format!("{} matches for {}", count, source));
+        Ok(count)
+    }
+
+    /// Marked nodes across the whole navigation stack, in document order.
+    pub fn marked_nodes(&self) -> Vec<Node<'a>> {
+        let mut nodes: Vec<Node<'a>> = self
+            .stack
+            .iter()
+            .flat_map(|level| level.marked.iter().filter_map(|&i| level.children.get(i).cloned()))
+            .collect();
+        nodes.sort_by_key(|n| n.offset);
+        nodes.dedup_by_key(|n| n.offset);
+        nodes
+    }
+
+    /// Writes the selected element's full subtree, byte for byte, to `path`.
+    pub fn export_selected(&mut self, path: &str) {
+        let Some(node) = self.selected_node() else {
+            self.message = Some("Nothing to export".to_string());
+            return;
+        };
+        if path.is_empty() {
+            self.message = Some("Export cancelled: no file name".to_string());
+            return;
+        }
+        let raw = self.explorer.raw(&node).as_bytes();
+        self.message = Some(match std::fs::write(path, raw) {
+            Ok(()) => format!("Wrote <{}> ({} bytes) to {}", node.tag, raw.len(), path),
+            Err(err) => format!("Export failed: {}: {}", path, err),
+        });
+    }
+
+    /// The selected element's path, text or raw XML, with a short description.
+    pub fn yank(&self, kind: YankKind) -> Option<(String, String)> {
+        let node = self.selected_node()?;
+        Some(match kind {
+            YankKind::Path => {
+                let path = self.explorer.path(&node);
+                (path.clone(), format!("path {}", path))
+            }
+            YankKind::Text => {
+                let text = self.explorer.text_content(&node);
+                let description = format!("text of <{}> ({} chars)", node.tag, text.chars().count());
+                (text, description)
+            }
+            YankKind::Xml => {
+                let raw = self.explorer.raw(&node);
+                (raw.to_string(), format!("<{}> ({} bytes)", node.tag, raw.len()))
+            }
+        })
+    }
+
+    pub fn selected_node(&self) -> Option<Node<'a>> {
+        if let Some(tree) = &self.tree {
+            return tree.selected().map(|row| row.node.clone());
+        }
+        if let Some(table) = &self.table {
+            return table.selected_node().cloned();
+        }
+        self.stack
+            .last()
+            .and_then(|level| level.node(self.selected))
+            .cloned()
+    }
+
+    /// Writes the raw source of every marked element to `path`, one per line.
+    pub fn export_marked(&self, path: &str) -> io::Result<usize> {
+        let nodes = self.marked_nodes();
+        let mut out = io::BufWriter::new(File::create(path)?);
+        for node in &nodes {
+            out.write_all(self.explorer.raw(node).as_bytes())?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        Ok(nodes.len())
+    }
+
+    /// Executes a hook command such as `export out.xml`, returning a summary.
+    pub fn run_command(&mut self, command: &str) -> Result<String, String> {
+        let (name, arg) = command.trim().split_once(char::is_whitespace).unwrap_or((command.trim(), ""));
+        let arg = arg.trim();
+        match name {
+            "export" if !arg.is_empty() => {
+                let count = self.export_marked(arg).map_err(|e| format!("{}: {}", arg, e))?;
+                Ok(format!("Exported {} marked elements to {}", count, arg))
+            }
+            "export" => Err("export needs a file name".to_string()),
+            "query" => self.apply_query(arg).map(|n| format!("{} matches", n)),
+            "offset" => {
+                let offset = arg.parse().map_err(|_| format!("offset needs a byte offset, got '{}'", arg))?;
+                self.go_to(offset)
+            }
+            "line" => {
+                let line = arg.parse().map_err(|_| format!("line needs a line number, got '{}'", arg))?;
+                let xml = self.explorer.xml();
+                let start = line_start(xml, line).ok_or_else(|| format!("There is no line {}", line))?;
+                // Land on the line's first markup rather than its indentation.
+                let indent = xml[start..].bytes().take_while(|b| matches!(b, b' ' | b'\t')).count();
+                self.go_to(start + indent)
+            }
+            _ => Err(format!("Unknown command: {}", name)),
         }
     }
 }
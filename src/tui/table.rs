@@ -0,0 +1,509 @@
+use super::action::Action;
+use crate::agg::push_csv_line;
+use crate::parser::{Continue, Token, decode_entities, extract_attributes, flat_text, stream_xml_spans, token_text};
+use crate::xml::Node;
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, channel};
+
+/// Widest a column is drawn; longer cells are cut.
+const MAX_WIDTH: usize = 30;
+
+/// Tables with more records than this are filtered and sorted on a
+/// background thread, keeping the previous rows on screen meanwhile.
+const BACKGROUND_RECORDS: usize = 20_000;
+
+/// Space between columns.
+pub const GAP: usize = 2;
+
+/// One column: an attribute (`@id`), a child element (`title`) or the
+/// records' own text (`#text`).
+pub struct Column {
+    pub name: String,
+    /// Widest cell or header, up to `MAX_WIDTH` characters.
+    pub width: usize,
+}
+
+/// A column filter (`/` in the table): `text` keeps cells containing it
+/// (ignoring case), `=text` cells equal to it, `>n`, `>=n`, `<n`, `<=n` and
+/// `a..b` (either end optional) numeric cells in range; `!` negates.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Contains(String),
+    Equals(String),
+    Range { min: Option<(f64, bool)>, max: Option<(f64, bool)> },
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(expr: &str) -> Result<Filter, String> {
+        let number = |text: &str| {
+            text.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("'{}' is not a number", text.trim()))
+        };
+        if let Some(rest) = expr.strip_prefix('!') {
+            return Ok(Filter::Not(Box::new(Filter::parse(rest)?)));
+        }
+        let filter = if let Some(n) = expr.strip_prefix(">=") {
+            Filter::Range { min: Some((number(n)?, true)), max: None }
+        } else if let Some(n) = expr.strip_prefix("<=") {
+            Filter::Range { min: None, max: Some((number(n)?, true)) }
+        } else if let Some(n) = expr.strip_prefix('>') {
+            Filter::Range { min: Some((number(n)?, false)), max: None }
+        } else if let Some(n) = expr.strip_prefix('<') {
+            Filter::Range { min: None, max: Some((number(n)?, false)) }
+        } else if let Some(text) = expr.strip_prefix('=') {
+            Filter::Equals(text.to_string())
+        } else if let Some((low, high)) = expr.split_once("..")
+            && (!low.trim().is_empty() || !high.trim().is_empty())
+            && let (Ok(min), Ok(max)) = (
+                (!low.trim().is_empty()).then(|| number(low)).transpose(),
+                (!high.trim().is_empty()).then(|| number(high)).transpose(),
+            )
+        {
+            Filter::Range { min: min.map(|n| (n, true)), max: max.map(|n| (n, true)) }
+        } else {
+            Filter::Contains(expr.to_lowercase())
+        };
+        Ok(filter)
+    }
+
+    fn matches(&self, cell: &str) -> bool {
+        match self {
+            Filter::Contains(text) => cell.to_lowercase().contains(text),
+            Filter::Equals(text) => cell == text,
+            Filter::Range { min, max } => {
+                let Ok(value) = cell.trim().parse::<f64>() else {
+                    return false;
+                };
+                let above = min.is_none_or(|(min, inclusive)| if inclusive { value >= min } else { value > min });
+                let below = max.is_none_or(|(max, inclusive)| if inclusive { value <= max } else { value < max });
+                above && below
+            }
+            Filter::Not(filter) => !filter.matches(cell),
+        }
+    }
+}
+
+/// A filter on one column (an index into `columns`), with the text it was
+/// parsed from.
+#[derive(Debug, Clone)]
+pub struct ColumnFilter {
+    pub column: usize,
+    pub expr: String,
+    filter: Filter,
+}
+
+/// Spreadsheet-like view of a level whose children are records of one tag
+/// (`t`): a row per child, a column per attribute and child element.
+pub struct TableView<'a> {
+    pub tag: &'a str,
+    pub columns: Vec<Column>,
+    /// The records, in document order.
+    pub nodes: Vec<Node<'a>>,
+    /// One cell per column for each record; shared with background sorts.
+    pub cells: Arc<Vec<Vec<String>>>,
+    /// Records passing the filters, in table order.
+    pub rows: Vec<usize>,
+    pub selected: usize,
+    /// First row on screen.
+    pub top: usize,
+    /// Indexes into `columns` of the columns shown, in display order.
+    pub order: Vec<usize>,
+    /// Position in `order` of the column under the cursor, which `s` sorts by.
+    pub column: usize,
+    /// Position in `order` of the first column on screen after the frozen
+    /// one; follows the cursor when it moves off screen.
+    pub first_column: usize,
+    /// Whether the first shown column stays on screen when scrolling sideways.
+    pub frozen: bool,
+    /// Sort columns (indexes into `columns`) by priority, each with whether
+    /// its order is descending.
+    pub sort: Vec<(usize, bool)>,
+    pub filters: Vec<ColumnFilter>,
+    /// Rows being computed in the background, with the record to select then.
+    job: Option<(Receiver<Vec<usize>>, Option<usize>)>,
+    height: usize,
+}
+
+/// The records of `cells` passing `filters`, in the order of `sort`. Empty
+/// cells sort last; values compare numerically when both are numbers.
+fn compute_rows(cells: &[Vec<String>], filters: &[ColumnFilter], sort: &[(usize, bool)]) -> Vec<usize> {
+    let mut rows: Vec<usize> = (0..cells.len())
+        .filter(|&r| filters.iter().all(|f| f.filter.matches(&cells[r][f.column])))
+        .collect();
+    if sort.is_empty() {
+        return rows;
+    }
+    // Numbers are parsed once per sort column instead of per comparison.
+    let numbers: Vec<Vec<Option<f64>>> = sort
+        .iter()
+        .map(|&(column, _)| cells.iter().map(|row| row[column].trim().parse().ok()).collect())
+        .collect();
+    rows.sort_by(|&a, &b| {
+        for (k, &(column, descending)) in sort.iter().enumerate() {
+            let (x, y) = (&cells[a][column], &cells[b][column]);
+            let order = match (x.is_empty(), y.is_empty()) {
+                (false, false) => match (numbers[k][a], numbers[k][b]) {
+                    (Some(x), Some(y)) => x.total_cmp(&y),
+                    _ => x.cmp(y),
+                },
+                // Empty cells go last either way.
+                (x, y) => return x.cmp(&y),
+            };
+            let order = if descending { order.reverse() } else { order };
+            if order != Ordering::Equal {
+                return order;
+            }
+        }
+        Ordering::Equal
+    });
+    rows
+}
+
+/// Cells of one record by column name: attributes, then the text of each
+/// child element (repeated children joined with "; "), then its own text,
+/// which for mixed content is all of its text in order.
+fn record_cells<'a>(xml: &'a str, node: &Node<'a>) -> Vec<(String, String)> {
+    let mut cells: Vec<(String, String)> = extract_attributes(xml, node.offset)
+        .into_iter()
+        .map(|(name, value)| (format!("@{}", name), decode_entities(value).into_owned()))
+        .collect();
+    let raw = &xml[node.span.clone()];
+    let mut depth = 0;
+    let mut own_text = String::new();
+    stream_xml_spans(raw, |token, _| {
+        match token {
+            Token::StartTag(name, _) => {
+                depth += 1;
+                if depth == 2 {
+                    cells.push((name.to_string(), String::new()));
+                }
+            }
+            Token::EndTag(_) => depth -= 1,
+            Token::Text(_) | Token::CData(_) => {
+                let text = token_text(&token).unwrap_or_default();
+                let text = text.trim();
+                let cell = match depth {
+                    1 => &mut own_text,
+                    d if d >= 2 => match cells.last_mut() {
+                        Some((_, cell)) => cell,
+                        None => return Continue(()),
+                    },
+                    _ => return Continue(()),
+                };
+                if !text.is_empty() {
+                    if !cell.is_empty() {
+                        cell.push(' ');
+                    }
+                    cell.push_str(text);
+                }
+            }
+        }
+        Continue(())
+    });
+    if !own_text.is_empty() {
+        // Text around child elements reads as one run, children included.
+        let text = if node.mixed { flat_text(raw, usize::MAX) } else { own_text };
+        cells.push(("#text".to_string(), text));
+    }
+    cells
+}
+
+impl<'a> TableView<'a> {
+    /// Tabulates `nodes`, which must all have the same tag.
+    pub fn new(xml: &'a str, nodes: &[Node<'a>]) -> Result<Self, String> {
+        let Some(first) = nodes.first() else {
+            return Err("No children to show as a table".to_string());
+        };
+        if let Some(other) = nodes.iter().find(|node| node.tag != first.tag) {
+            return Err(format!(
+                "The table view needs children with one tag; this level has <{}> and <{}>",
+                first.tag, other.tag
+            ));
+        }
+        let mut columns: Vec<Column> = Vec::new();
+        let mut records = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let mut cells: Vec<(usize, String)> = Vec::new();
+            for (name, value) in record_cells(xml, node) {
+                let i = match columns.iter().position(|column| column.name == name) {
+                    Some(i) => i,
+                    None => {
+                        // Room for the sort arrow and its rank after the name.
+                        columns.push(Column {
+                            width: name.chars().count() + 3,
+                            name,
+                        });
+                        columns.len() - 1
+                    }
+                };
+                match cells.iter_mut().find(|(column, _)| *column == i) {
+                    Some((_, cell)) => {
+                        cell.push_str("; ");
+                        cell.push_str(&value);
+                    }
+                    None => cells.push((i, value)),
+                }
+            }
+            records.push((node.clone(), cells));
+        }
+        // Attributes first, each group in order of first appearance.
+        let mut order: Vec<usize> = (0..columns.len()).collect();
+        order.sort_by_key(|&i| !columns[i].name.starts_with('@'));
+        let mut position = vec![0; columns.len()];
+        for (to, &from) in order.iter().enumerate() {
+            position[from] = to;
+        }
+        let (nodes, cells): (Vec<Node<'a>>, Vec<Vec<String>>) = records
+            .into_iter()
+            .map(|(node, cells)| {
+                let mut row = vec![String::new(); columns.len()];
+                for (i, value) in cells {
+                    row[position[i]] = value;
+                }
+                (node, row)
+            })
+            .unzip();
+        let mut columns: Vec<Column> = order
+            .into_iter()
+            .map(|i| Column {
+                name: std::mem::take(&mut columns[i].name),
+                width: columns[i].width,
+            })
+            .collect();
+        for row in &cells {
+            for (column, cell) in columns.iter_mut().zip(row) {
+                column.width = column.width.max(cell.chars().count()).min(MAX_WIDTH);
+            }
+        }
+        Ok(Self {
+            tag: first.tag,
+            order: (0..columns.len()).collect(),
+            columns,
+            rows: (0..nodes.len()).collect(),
+            nodes,
+            cells: Arc::new(cells),
+            selected: 0,
+            top: 0,
+            column: 0,
+            first_column: 0,
+            frozen: false,
+            sort: Vec::new(),
+            filters: Vec::new(),
+            job: None,
+            height: 1,
+        })
+    }
+
+    /// Shows the columns named in `names` (comma or space separated, with or
+    /// without the `@` of attributes) in that order; empty shows them all.
+    pub fn choose_columns(&mut self, names: &str) -> Result<(), String> {
+        let mut order = Vec::new();
+        let mut unknown = Vec::new();
+        for name in names.split(|c: char| c == ',' || c.is_whitespace()).filter(|name| !name.is_empty()) {
+            let found = self.columns.iter().position(|column| column.name == name).or_else(|| {
+                let attribute = format!("@{}", name);
+                self.columns.iter().position(|column| column.name == attribute)
+            });
+            match found {
+                Some(i) if !order.contains(&i) => order.push(i),
+                Some(_) => {}
+                None => unknown.push(name),
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(format!("No column named {}", unknown.join(", ")));
+        }
+        if order.is_empty() {
+            order = (0..self.columns.len()).collect();
+            self.frozen = false;
+        }
+        self.order = order;
+        self.column = 0;
+        self.first_column = 0;
+        Ok(())
+    }
+
+    /// The shown columns and every row, as CSV with a header line.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        let names = self.order.iter().map(|&i| self.columns[i].name.as_str());
+        push_csv_line(&mut csv, names);
+        for &r in &self.rows {
+            push_csv_line(&mut csv, self.order.iter().map(|&i| self.cells[r][i].as_str()));
+        }
+        csv
+    }
+
+    pub fn selected_node(&self) -> Option<&Node<'a>> {
+        self.nodes.get(*self.rows.get(self.selected)?)
+    }
+
+    /// Whether rows are still being filtered or sorted in the background.
+    pub fn is_busy(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Filters the cursor column with `expr`, replacing its previous filter;
+    /// empty removes it.
+    pub fn filter_column(&mut self, expr: &str) -> Result<(), String> {
+        let Some(&column) = self.order.get(self.column) else {
+            return Ok(());
+        };
+        self.filters.retain(|f| f.column != column);
+        if !expr.is_empty() {
+            let filter = Filter::parse(expr)?;
+            self.filters.push(ColumnFilter {
+                column,
+                expr: expr.to_string(),
+                filter,
+            });
+        }
+        self.refresh();
+        Ok(())
+    }
+
+    /// Recomputes the rows after a filter or sort change: inline for small
+    /// tables, else on a background thread picked up by `poll`.
+    fn refresh(&mut self) {
+        let selected = self.rows.get(self.selected).copied();
+        if self.nodes.len() <= BACKGROUND_RECORDS {
+            self.rows = compute_rows(&self.cells, &self.filters, &self.sort);
+            self.finish(selected);
+            return;
+        }
+        let (cells, filters, sort) = (Arc::clone(&self.cells), self.filters.clone(), self.sort.clone());
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(compute_rows(&cells, &filters, &sort));
+        });
+        // A newer job replaces an unfinished one, whose result is dropped.
+        self.job = Some((receiver, selected));
+    }
+
+    /// Selects record `selected` again in the new rows, or the first row.
+    fn finish(&mut self, selected: Option<usize>) {
+        self.selected = selected.and_then(|r| self.rows.iter().position(|&row| row == r)).unwrap_or(0);
+        self.top = 0;
+    }
+
+    /// Takes the rows of a finished background job; with `wait`, blocks
+    /// until it finishes.
+    pub fn poll(&mut self, wait: bool) {
+        let Some((receiver, selected)) = &self.job else {
+            return;
+        };
+        let rows = if wait { receiver.recv().ok() } else { receiver.try_recv().ok() };
+        let selected = *selected;
+        if let Some(rows) = rows {
+            self.rows = rows;
+            self.job = None;
+            self.finish(selected);
+        }
+    }
+
+    /// Adapts scrolling to `height` rows and `width` columns of screen, and
+    /// returns the positions in `order` of the columns that fit: the frozen
+    /// column, if any, then those from `first_column` on.
+    pub fn fit(&mut self, height: usize, width: usize) -> Vec<usize> {
+        self.height = height.max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + self.height {
+            self.top = self.selected + 1 - self.height;
+        }
+        let frozen = usize::from(self.frozen && !self.order.is_empty());
+        let width = width.saturating_sub(self.span_width(0..frozen));
+        self.first_column = self.first_column.min(self.column).max(frozen);
+        while self.first_column < self.column && self.span_width(self.first_column..self.column + 1) > width {
+            self.first_column += 1;
+        }
+        let mut end = self.first_column;
+        while end < self.order.len() && (end == self.first_column || self.span_width(self.first_column..end + 1) <= width) {
+            end += 1;
+        }
+        (0..frozen).chain(self.first_column..end).collect()
+    }
+
+    fn span_width(&self, positions: Range<usize>) -> usize {
+        self.order[positions].iter().map(|&i| self.columns[i].width + GAP).sum()
+    }
+
+    /// Moves the cursor column one place left or right in the display order.
+    fn move_column(&mut self, right: bool) {
+        let to = if right { self.column + 1 } else { self.column.wrapping_sub(1) };
+        if to < self.order.len() {
+            self.order.swap(self.column, to);
+            self.column = to;
+        }
+    }
+
+    /// Hides the cursor column, keeping at least one.
+    fn hide_column(&mut self) {
+        if self.order.len() > 1 {
+            self.order.remove(self.column);
+            self.column = self.column.min(self.order.len() - 1);
+        }
+    }
+
+    /// Freezes the cursor column at the left edge, or unfreezes it.
+    fn toggle_frozen(&mut self) {
+        if self.frozen || self.order.is_empty() {
+            self.frozen = false;
+            return;
+        }
+        let i = self.order.remove(self.column);
+        self.order.insert(0, i);
+        self.column = 0;
+        self.frozen = true;
+    }
+
+    /// Sorts by the cursor column alone, ascending first and then descending (`s`).
+    fn sort_by_column(&mut self) {
+        let column = self.order[self.column];
+        let descending = self.sort == [(column, false)];
+        self.sort = vec![(column, descending)];
+        self.refresh();
+    }
+
+    /// Adds the cursor column as the next sort key, or steps it from
+    /// ascending to descending to unsorted if it already is one (`S`).
+    fn add_sort_column(&mut self) {
+        let column = self.order[self.column];
+        match self.sort.iter().position(|&(sorted, _)| sorted == column) {
+            Some(k) if !self.sort[k].1 => self.sort[k].1 = true,
+            Some(k) => {
+                self.sort.remove(k);
+            }
+            None => self.sort.push((column, false)),
+        }
+        self.refresh();
+    }
+
+    /// Handles table navigation: the arrows move between rows and columns,
+    /// `s` sorts by the current column and `S` by it next, `<` and `>` move
+    /// it, `-` hides it and `f` freezes it. Returns whether `action` was used.
+    pub fn apply(&mut self, action: &Action) -> bool {
+        let last = self.rows.len().saturating_sub(1);
+        match action {
+            Action::Down => self.selected = (self.selected + 1).min(last),
+            Action::Up => self.selected = self.selected.saturating_sub(1),
+            Action::PageDown => self.selected = (self.selected + self.height).min(last),
+            Action::PageUp => self.selected = self.selected.saturating_sub(self.height),
+            Action::Home => self.selected = 0,
+            Action::End => self.selected = last,
+            Action::Enter => self.column = (self.column + 1).min(self.order.len().saturating_sub(1)),
+            Action::Back => self.column = self.column.saturating_sub(1),
+            Action::CycleSort if !self.order.is_empty() => self.sort_by_column(),
+            Action::AddSortColumn if !self.order.is_empty() => self.add_sort_column(),
+            Action::MoveColumn(right) => self.move_column(*right),
+            Action::HideColumn => self.hide_column(),
+            Action::FreezeColumn => self.toggle_frozen(),
+            _ => return false,
+        }
+        true
+    }
+}
@@ -4,9 +4,27 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{prelude::*, Terminal};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+/// Whether the terminal is set up, so that a panic knows to restore it.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Switches to raw mode on the alternate screen. A panic until
+/// `restore_terminal` puts the terminal back before its message is printed.
 pub fn setup_terminal() -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if ACTIVE.load(Ordering::SeqCst) {
+                let _ = restore_terminal();
+            }
+            previous(info);
+        }));
+    });
     enable_raw_mode()?;
+    ACTIVE.store(true, Ordering::SeqCst);
     let mut stdout = stdout();
     execute!(stdout, EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(stdout);
@@ -14,6 +32,7 @@ pub fn setup_terminal() -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
 }
 
 pub fn restore_terminal() -> io::Result<()> {
+    ACTIVE.store(false, Ordering::SeqCst);
     disable_raw_mode()?;
     execute!(io::stdout(), LeaveAlternateScreen)?;
     Ok(())
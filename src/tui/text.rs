@@ -0,0 +1,167 @@
+use super::action::Action;
+use super::hexdump::HexView;
+use crate::parser::decode_entities;
+use memchr::{memchr, memmem};
+use ratatui::prelude::*;
+use std::ops::Range;
+
+/// Full text of a leaf element (Enter on a leaf, or `o`), decoded and
+/// word-wrapped, or as a hexdump (`x`), which binary-looking text opens in.
+pub struct TextView {
+    pub tag: String,
+    pub text: String,
+    pub hex: Option<HexView>,
+    pub scroll: usize,
+    /// Lines that fit on screen and their width, as last drawn.
+    page: usize,
+    width: usize,
+    /// Byte ranges of `text` per wrapped line, for `width`.
+    lines: Vec<Range<usize>>,
+}
+
+/// The text of the leaf element `raw`, with entities decoded, CDATA sections
+/// unwrapped and comments left out.
+pub fn leaf_text(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let Some(open_end) = memchr(b'>', bytes) else {
+        return String::new();
+    };
+    if raw[..open_end].ends_with('/') {
+        return String::new();
+    }
+    let content = &raw[open_end + 1..raw.rfind("</").unwrap_or(raw.len()).max(open_end + 1)];
+    let mut text = String::new();
+    let mut rest = content;
+    while let Some(lt) = memchr(b'<', rest.as_bytes()) {
+        text.push_str(&decode_entities(&rest[..lt]));
+        let markup = &rest[lt..];
+        let (inner, terminator) = if let Some(cdata) = markup.strip_prefix("<![CDATA[") {
+            (Some(cdata), "]]>")
+        } else if markup.starts_with("<!--") {
+            (None, "-->")
+        } else {
+            (None, ">")
+        };
+        let end = memmem::find(markup.as_bytes(), terminator.as_bytes()).map_or(markup.len(), |at| at + terminator.len());
+        if let Some(cdata) = inner {
+            text.push_str(&cdata[..cdata.find("]]>").unwrap_or(cdata.len())]);
+        }
+        rest = &markup[end..];
+    }
+    text.push_str(&decode_entities(rest));
+    text
+}
+
+/// Whether `text` holds control characters or U+FFFD, as bytes from an
+/// embedded file or a wrong encoding would.
+fn looks_binary(text: &str) -> bool {
+    text.chars().any(|c| (c.is_control() && !matches!(c, '\t' | '\n' | '\r')) || c == '\u{fffd}')
+}
+
+/// Greedy word wrap of `text` at `width` characters, breaking long words.
+fn wrap(text: &str, width: usize) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut paragraph_start = 0;
+    for raw in text.split('\n') {
+        let paragraph = raw.strip_suffix('\r').unwrap_or(raw);
+        let mut start = paragraph_start;
+        let mut count = 0;
+        let mut last_space = None;
+        for (i, c) in paragraph.char_indices() {
+            let at = paragraph_start + i;
+            if count == width {
+                let cut = last_space.filter(|&space| space > start).unwrap_or(at);
+                lines.push(start..cut);
+                start = if text[cut..].starts_with(' ') { cut + 1 } else { cut };
+                count = text[start..at].chars().count();
+                last_space = None;
+            }
+            if c == ' ' {
+                last_space = Some(at);
+            }
+            count += 1;
+        }
+        lines.push(start..paragraph_start + paragraph.len());
+        paragraph_start += raw.len() + 1;
+    }
+    lines
+}
+
+impl TextView {
+    pub fn new(tag: &str, text: String) -> Self {
+        let hex = looks_binary(&text).then(|| HexView::new(0));
+        Self {
+            tag: tag.to_string(),
+            text,
+            hex,
+            scroll: 0,
+            page: 20,
+            width: 0,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Switches between the wrapped text and its hexdump.
+    pub fn toggle_hex(&mut self) {
+        self.hex = match self.hex {
+            Some(_) => None,
+            None => Some(HexView::new(0)),
+        };
+    }
+
+    /// Sets the size on screen, rewrapping the text if the width changed.
+    pub fn fit(&mut self, page: usize, width: usize) {
+        self.page = page.max(1);
+        let width = width.max(1);
+        if width != self.width {
+            self.width = width;
+            self.lines = wrap(&self.text, width);
+        }
+        self.scroll_by(0);
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        let max = self.lines.len().saturating_sub(self.page);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max);
+    }
+
+    /// Scrolls the text or the hexdump. Returns whether `action` was used.
+    pub fn scroll_action(&mut self, action: &Action) -> bool {
+        if let Some(hex) = self.hex.as_mut() {
+            return hex.move_action(action, self.text.len());
+        }
+        match action {
+            Action::Down => self.scroll_by(1),
+            Action::Up => self.scroll_by(-1),
+            Action::PageDown => self.scroll_by(self.page as isize),
+            Action::PageUp => self.scroll_by(-(self.page as isize)),
+            Action::Home => self.scroll = 0,
+            Action::End => self.scroll_by(isize::MAX),
+            _ => return false,
+        }
+        true
+    }
+
+    /// One page of wrapped lines from the scroll position.
+    pub fn visible_lines(&self) -> Vec<Line<'static>> {
+        let end = (self.scroll + self.page).min(self.lines.len());
+        self.lines[self.scroll..end]
+            .iter()
+            .map(|range| {
+                let line: String = self.text[range.clone()]
+                    .chars()
+                    .map(|c| match c {
+                        '\t' => ' ',
+                        c if c.is_control() => '·',
+                        c => c,
+                    })
+                    .collect();
+                Line::raw(line)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,157 @@
+//! Colors of the navigator. One theme is chosen at startup (`--theme`, or
+//! `theme` in the config file) and read by every drawing function.
+
+use ratatui::style::{Color, Modifier, Style};
+use std::sync::OnceLock;
+
+static CURRENT: OnceLock<Theme> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    /// Behind the lists, tables and source.
+    pub background: Color,
+    /// Behind popups drawn over the lists.
+    pub popup: Color,
+    /// Drop shadow under the main list.
+    pub shadow: Color,
+    /// Ordinary text, popup borders and problem messages.
+    pub foreground: Color,
+    pub border: Color,
+    /// Titles, key names and the focused pane's border.
+    pub accent: Color,
+    /// The " XML Tree Navigator " label.
+    pub title: Style,
+    pub tag: Color,
+    /// Element text in the list and tree.
+    pub text: Color,
+    /// Attribute summaries in the list and tree.
+    pub attribute: Color,
+    /// Attribute values in popups and the source, and bars in histograms.
+    pub value: Color,
+    /// Secondary details: line numbers, placeholders, offsets.
+    pub muted: Color,
+    /// Counts and sizes.
+    pub count: Color,
+    /// The selected row.
+    pub highlight: Style,
+    /// A selected row that does not have the focus, such as in the problems panel.
+    pub highlight_dim: Style,
+    /// Marks and other confirmations.
+    pub mark: Color,
+    pub warning: Color,
+    pub error: Color,
+}
+
+const DARK: Theme = Theme {
+    name: "dark",
+    background: Color::Rgb(30, 30, 40),
+    popup: Color::Rgb(40, 40, 50),
+    shadow: Color::Rgb(20, 20, 28),
+    foreground: Color::White,
+    border: Color::Gray,
+    accent: Color::Cyan,
+    title: Style::new().fg(Color::White).bg(Color::Blue).add_modifier(Modifier::BOLD),
+    tag: Color::Magenta,
+    text: Color::Cyan,
+    attribute: Color::DarkGray,
+    value: Color::Green,
+    muted: Color::DarkGray,
+    count: Color::Yellow,
+    highlight: Style::new().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD),
+    highlight_dim: Style::new().bg(Color::Rgb(60, 60, 80)).add_modifier(Modifier::BOLD),
+    mark: Color::Green,
+    warning: Color::Yellow,
+    error: Color::Red,
+};
+
+const LIGHT: Theme = Theme {
+    name: "light",
+    background: Color::Rgb(250, 250, 245),
+    popup: Color::Rgb(235, 235, 228),
+    shadow: Color::Rgb(215, 215, 208),
+    foreground: Color::Black,
+    border: Color::Rgb(150, 150, 150),
+    accent: Color::Rgb(0, 90, 170),
+    title: Style::new().fg(Color::White).bg(Color::Rgb(0, 90, 170)).add_modifier(Modifier::BOLD),
+    tag: Color::Rgb(150, 0, 120),
+    text: Color::Rgb(0, 110, 130),
+    attribute: Color::Rgb(110, 110, 110),
+    value: Color::Rgb(0, 120, 0),
+    muted: Color::Rgb(130, 130, 130),
+    count: Color::Rgb(170, 95, 0),
+    highlight: Style::new().fg(Color::Black).bg(Color::Rgb(255, 215, 90)).add_modifier(Modifier::BOLD),
+    highlight_dim: Style::new().bg(Color::Rgb(215, 220, 235)).add_modifier(Modifier::BOLD),
+    mark: Color::Rgb(0, 130, 0),
+    warning: Color::Rgb(170, 95, 0),
+    error: Color::Rgb(190, 0, 0),
+};
+
+/// Solarized dark, after Ethan Schoonover's palette.
+const SOLARIZED: Theme = Theme {
+    name: "solarized",
+    background: Color::Rgb(0, 43, 54),
+    popup: Color::Rgb(7, 54, 66),
+    shadow: Color::Rgb(0, 33, 43),
+    foreground: Color::Rgb(147, 161, 161),
+    border: Color::Rgb(88, 110, 117),
+    accent: Color::Rgb(38, 139, 210),
+    title: Style::new().fg(Color::Rgb(253, 246, 227)).bg(Color::Rgb(38, 139, 210)).add_modifier(Modifier::BOLD),
+    tag: Color::Rgb(211, 54, 130),
+    text: Color::Rgb(42, 161, 152),
+    attribute: Color::Rgb(101, 123, 131),
+    value: Color::Rgb(133, 153, 0),
+    muted: Color::Rgb(88, 110, 117),
+    count: Color::Rgb(181, 137, 0),
+    highlight: Style::new().fg(Color::Rgb(0, 43, 54)).bg(Color::Rgb(181, 137, 0)).add_modifier(Modifier::BOLD),
+    highlight_dim: Style::new().bg(Color::Rgb(7, 54, 66)).add_modifier(Modifier::BOLD),
+    mark: Color::Rgb(133, 153, 0),
+    warning: Color::Rgb(203, 75, 22),
+    error: Color::Rgb(220, 50, 47),
+};
+
+/// The terminal's own colors only, with reverse video for the selection,
+/// for terminals without color support.
+const MONOCHROME: Theme = Theme {
+    name: "monochrome",
+    background: Color::Reset,
+    popup: Color::Reset,
+    shadow: Color::Reset,
+    foreground: Color::Reset,
+    border: Color::Reset,
+    accent: Color::Reset,
+    title: Style::new().add_modifier(Modifier::REVERSED.union(Modifier::BOLD)),
+    tag: Color::Reset,
+    text: Color::Reset,
+    attribute: Color::Reset,
+    value: Color::Reset,
+    muted: Color::Reset,
+    count: Color::Reset,
+    highlight: Style::new().add_modifier(Modifier::REVERSED.union(Modifier::BOLD)),
+    highlight_dim: Style::new().add_modifier(Modifier::UNDERLINED),
+    mark: Color::Reset,
+    warning: Color::Reset,
+    error: Color::Reset,
+};
+
+/// Every built-in theme; the first is the default.
+pub const THEMES: [Theme; 4] = [DARK, LIGHT, SOLARIZED, MONOCHROME];
+
+impl Theme {
+    pub fn named(name: &str) -> Result<Theme, String> {
+        THEMES.into_iter().find(|theme| theme.name == name).ok_or_else(|| {
+            let names: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+            format!("unknown theme '{}' (expected {})", name, names.join(", "))
+        })
+    }
+}
+
+/// Chooses the theme for the rest of the process; later calls have no effect.
+pub fn set(theme: Theme) {
+    let _ = CURRENT.set(theme);
+}
+
+/// The chosen theme, or the default if none was chosen.
+pub fn current() -> &'static Theme {
+    CURRENT.get_or_init(|| THEMES[0])
+}
@@ -0,0 +1,141 @@
+use super::action::Action;
+use crate::xml::{Content, Node, XmlExplorer};
+use ratatui::widgets::ListState;
+
+/// One visible line of the tree: an element, or a run of text among the
+/// child elements of a mixed one.
+pub struct TreeRow<'a> {
+    /// The element, or for a text run the element it is in.
+    pub node: Node<'a>,
+    /// The text run, as written.
+    pub text: Option<&'a str>,
+    pub depth: usize,
+    pub expanded: bool,
+    /// Whether the element seems to contain markup; cleared if expanding it
+    /// finds no child elements.
+    pub expandable: bool,
+}
+
+/// Expandable tree of the level the tree view was opened on (`T`), showing
+/// several levels at once. Children are read when a node is first expanded.
+pub struct TreeView<'a> {
+    pub rows: Vec<TreeRow<'a>>,
+    pub list_state: ListState,
+    /// Tag of the element whose children are the top-level rows.
+    base_tag: Option<&'a str>,
+}
+
+/// Cheap check for child markup between the start and end tag of `node`.
+fn has_markup(xml: &str, node: &Node) -> bool {
+    let raw = &xml[node.span.clone()];
+    match (raw.find('>'), raw.rfind("</")) {
+        (Some(gt), Some(end)) if gt < end => raw[gt + 1..end].contains('<'),
+        _ => false,
+    }
+}
+
+impl<'a> TreeView<'a> {
+    pub fn new(xml: &str, nodes: &[Node<'a>], selected: usize, base_tag: Option<&'a str>) -> Self {
+        let rows = nodes
+            .iter()
+            .map(|node| TreeRow {
+                node: node.clone(),
+                text: None,
+                depth: 0,
+                expanded: false,
+                expandable: has_markup(xml, node),
+            })
+            .collect::<Vec<_>>();
+        let mut list_state = ListState::default();
+        list_state.select((!rows.is_empty()).then(|| selected.min(rows.len() - 1)));
+        Self { rows, list_state, base_tag }
+    }
+
+    pub fn selected(&self) -> Option<&TreeRow<'a>> {
+        self.rows.get(self.list_state.selected()?)
+    }
+
+    /// Tag of the selected row's parent element.
+    pub fn parent_tag(&self) -> Option<&'a str> {
+        let at = self.list_state.selected()?;
+        let depth = self.rows.get(at)?.depth;
+        match self.rows[..at].iter().rposition(|row| row.depth < depth) {
+            Some(parent) => Some(self.rows[parent].node.tag),
+            None => self.base_tag,
+        }
+    }
+
+    fn select(&mut self, row: usize) {
+        if !self.rows.is_empty() {
+            self.list_state.select(Some(row.min(self.rows.len() - 1)));
+        }
+    }
+
+    fn expand(&mut self, at: usize, explorer: &mut XmlExplorer<'a>) {
+        let row = &mut self.rows[at];
+        if row.expanded || !row.expandable {
+            return;
+        }
+        let node = row.node.clone();
+        // Mixed content is listed in order, text runs between the elements.
+        let content = match node.mixed {
+            true => explorer.content(&node),
+            false => explorer.children(&node).into_iter().map(Content::Element).collect(),
+        };
+        row.expanded = true;
+        row.expandable = !content.is_empty();
+        let depth = row.depth + 1;
+        let xml = explorer.xml();
+        let new_rows = content.into_iter().map(|content| match content {
+            Content::Element(child) => TreeRow {
+                expandable: has_markup(xml, &child),
+                node: child,
+                text: None,
+                depth,
+                expanded: false,
+            },
+            Content::Text(run) => TreeRow {
+                node: node.clone(),
+                text: Some(run),
+                depth,
+                expanded: false,
+                expandable: false,
+            },
+        });
+        self.rows.splice(at + 1..at + 1, new_rows);
+    }
+
+    fn collapse(&mut self, at: usize) {
+        let depth = self.rows[at].depth;
+        let end = self.rows[at + 1..].iter().position(|row| row.depth <= depth).map_or(self.rows.len(), |n| at + 1 + n);
+        self.rows.drain(at + 1..end);
+        self.rows[at].expanded = false;
+    }
+
+    /// Handles tree navigation: Enter and Space expand or collapse, Back
+    /// collapses or moves to the parent. Returns whether `action` was used.
+    pub fn apply(&mut self, action: &Action, explorer: &mut XmlExplorer<'a>) -> bool {
+        let Some(at) = self.list_state.selected() else {
+            return matches!(action, Action::Enter | Action::Back | Action::ToggleInfo);
+        };
+        match action {
+            Action::Down => self.select(at + 1),
+            Action::Up => self.select(at.saturating_sub(1)),
+            Action::PageDown => self.select(at + 10),
+            Action::PageUp => self.select(at.saturating_sub(10)),
+            Action::Home => self.select(0),
+            Action::End => self.select(usize::MAX),
+            Action::Enter | Action::ToggleInfo if self.rows[at].expanded => self.collapse(at),
+            Action::Enter | Action::ToggleInfo => self.expand(at, explorer),
+            Action::Back if self.rows[at].expanded => self.collapse(at),
+            Action::Back => {
+                let depth = self.rows[at].depth;
+                if let Some(parent) = self.rows[..at].iter().rposition(|row| row.depth < depth) {
+                    self.select(parent);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
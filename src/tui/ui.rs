@@ -1,18 +1,171 @@
-use super::state::{Level, TuiState};
+use super::compare::{DiffMarks, Mark};
+use super::diagnostics::DiagnosticsPanel;
+use super::fingerprint::Fingerprint;
+use super::hexdump::HexView;
+use super::panes::PaneManager;
+use super::reading::ReadingView;
+use super::source::SourceView;
+use super::state::{AttributeDisplay, AttributeView, Level, Pending, Prompt, PromptKind, SortKey, TuiState};
+use super::table::{GAP, TableView};
+use super::theme;
+use super::text::TextView;
+use super::tree::TreeView;
+use crate::du::format_bytes;
+use crate::parser::{extract_attributes, flat_text};
+use crate::schema::Cardinality;
+use crate::xml::Node;
 use ratatui::{
     prelude::*,
-    widgets::{Block, Borders, List, ListItem, Paragraph, Scrollbar},
+    widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Scrollbar, Table, TableState},
 };
+use std::borrow::Cow;
+use std::ops::Range;
 
-pub fn draw_ui(f: &mut Frame, state: &mut TuiState) {
+/// Characters of mixed content read for the text beside an element.
+const TEXT_PREVIEW: usize = 200;
+
+pub fn draw_ui(f: &mut Frame, panes: &mut PaneManager) {
+    let theme = theme::current();
+    let banner_height = if panes.warning.is_some() { 1 } else { 0 };
+    let tabs_height = if panes.tabs.is_empty() { 0 } else { 1 };
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+        .constraints(
+            [
+                Constraint::Length(banner_height),
+                Constraint::Length(tabs_height),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
         .split(f.size());
 
-    let main_area = chunks[0];
-    let help_area = chunks[1];
+    let main_area = chunks[2];
+    let help_area = chunks[3];
+    if let Some(warning) = &panes.warning {
+        let banner = Paragraph::new(format!(" Warning: {}", warning)).style(Style::default().fg(theme.background).bg(theme.warning));
+        f.render_widget(banner, chunks[0]);
+    }
+    if !panes.tabs.is_empty() {
+        f.render_widget(create_tab_bar(panes), chunks[1]);
+    }
+
+    let pane_count = panes.panes.len() as u32;
+    let pane_areas = Layout::default()
+        .direction(panes.direction)
+        .constraints(vec![Constraint::Ratio(1, pane_count); pane_count as usize])
+        .split(main_area);
 
+    let focus = panes.focus;
+    let split = panes.panes.len() > 1;
+    for (i, state) in panes.panes.iter_mut().enumerate() {
+        draw_pane(f, pane_areas[i], state, split && i == focus);
+    }
+
+    let state = panes.focused();
+    let help = match (&state.prompt, &state.message, panes.macros.recording()) {
+        (Some(prompt), _, _) => create_prompt_paragraph(prompt),
+        (None, Some(message), _) => create_message_paragraph(message),
+        (None, None, Some(register)) => create_message_paragraph(&format!("Recording @{}  (Q to stop)", register)),
+        (None, None, None) => create_help_paragraph(),
+    };
+    let help_area = match state.fingerprint() {
+        Some((tag, fingerprint, counting)) => {
+            let status = fingerprint_line(tag, fingerprint, counting);
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(0), Constraint::Length(status.width() as u16)].as_ref())
+                .split(help_area);
+            f.render_widget(Paragraph::new(status), columns[1]);
+            columns[0]
+        }
+        None => help_area,
+    };
+    f.render_widget(help, help_area);
+}
+
+/// The status bar's summary of the selected element, e.g. ` │ <item>  3 attrs
+/// · 120 children · 14.2 KiB · 5 levels below`, with counts so far marked
+/// `…`. The leading bar keeps it apart from the key help on narrow terminals.
+fn fingerprint_line(tag: &str, fingerprint: Fingerprint, counting: bool) -> Line<'static> {
+    let theme = theme::current();
+    let mut summary = format!(
+        "{} attr{} · {} child{} · {} · {} level{} below",
+        fingerprint.attributes,
+        if fingerprint.attributes == 1 { "" } else { "s" },
+        thousands(fingerprint.children),
+        if fingerprint.children == 1 { "" } else { "ren" },
+        format_bytes(fingerprint.bytes as u64),
+        fingerprint.height,
+        if fingerprint.height == 1 { "" } else { "s" }
+    );
+    if counting {
+        summary.push('…');
+    }
+    Line::from(vec![
+        Span::styled(" │ ", Style::default().fg(theme.muted)),
+        Span::styled(format!("<{}>  ", tag), Style::default().fg(theme.tag)),
+        Span::styled(summary, Style::default().fg(if counting { theme.muted } else { theme.text })),
+    ])
+}
+
+fn draw_pane(f: &mut Frame, area: Rect, state: &mut TuiState, focused: bool) {
+    let theme = theme::current();
+    let area = match state.diagnostics.as_mut().filter(|panel| panel.open) {
+        Some(panel) => {
+            let height = panel.diagnostics.len().clamp(1, 8) as u16 + 2;
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(height)].as_ref())
+                .split(area);
+            draw_diagnostics(f, rows[1], panel);
+            rows[0]
+        }
+        None => area,
+    };
+    if let Some(view) = state.text.as_mut() {
+        draw_text(f, area, view);
+        return;
+    }
+    if let Some(view) = state.reading.as_mut() {
+        draw_reading(f, area, view, focused);
+        return;
+    }
+    if let Some(view) = state.hexdump.as_mut() {
+        draw_hexdump(f, area, view, state.explorer.xml().as_bytes());
+        return;
+    }
+    state.sync_source();
+    let (main_area, source_area) = match &state.source {
+        Some(view) if view.full_screen => (None, area),
+        Some(_) => {
+            let halves = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(area);
+            (Some(halves[0]), halves[1])
+        }
+        None => (Some(area), area),
+    };
+    if let Some(view) = state.source.as_mut() {
+        view.fit(source_area.height.saturating_sub(2) as usize);
+        draw_source(f, source_area, view, state.explorer.xml(), focused && main_area.is_none());
+    }
+    let Some(main_area) = main_area else {
+        return;
+    };
+    if let Some(table) = state.table.as_mut() {
+        draw_table(f, main_area, table, focused);
+        return;
+    }
+    if let Some(tree) = state.tree.as_mut() {
+        draw_tree(f, main_area, tree, state.explorer.xml(), &state.attributes, focused);
+        if let Some(cardinality) = &state.cardinality {
+            draw_cardinality_popup(f, main_area, cardinality);
+        }
+        return;
+    }
     let main_chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
@@ -25,34 +178,36 @@ pub fn draw_ui(f: &mut Frame, state: &mut TuiState) {
     let items_len = state.items_len;
     if items_len == 0 {
         state.selected = 0;
-        state.list_state.select(None);
-    } else {
-        if state.selected >= items_len {
-            state.selected = items_len.saturating_sub(1);
-        }
-        state.list_state.select(Some(state.selected));
+    } else if state.selected >= items_len {
+        state.selected = items_len.saturating_sub(1);
     }
+    // Only the page holding the selection is drawn, so the list state is relative to it.
+    let page = state.get_current_level().page(state.selected);
+    state.list_state.select((items_len > 0).then(|| state.selected - page.start));
 
     // Extract data from level without holding borrow across the mutable operations
     let current_level = state.get_current_level();
-    let block = create_main_block(current_level, state.selected);
-    let list = create_list(current_level, block, state.selected);
-    let help = create_help_paragraph();
+    let mut block = create_main_block(current_level, state.selected, &state.attributes, state.pending.len());
+    if focused {
+        block = block.border_style(Style::default().fg(theme.accent));
+    }
+    // Room for wrapped attributes inside the borders, highlight symbol and indent.
+    let width = list_area.width.saturating_sub(8).max(10) as usize;
+    let list = create_list(state, page, block, width);
 
     let shadow = Block::default()
         .borders(Borders::NONE)
-        .bg(Color::Rgb(20, 20, 28));
+        .bg(theme.shadow);
     let shadow_rect = Rect {
-        x: 2,
-        y: 2,
+        x: main_area.x + 2,
+        y: main_area.y + 2,
         width: main_area.width.saturating_sub(4),
         height: main_area.height.saturating_sub(4),
     };
     f.render_widget(shadow, shadow_rect);
     f.render_stateful_widget(list, list_area, &mut state.list_state);
-    f.render_widget(help, help_area);
 
-    state.scrollbar_state = state.scrollbar_state.content_length(state.items_len);
+    state.scrollbar_state = state.scrollbar_state.content_length(state.get_current_level().total.max(state.items_len));
 
     let scrollbar = Scrollbar::default()
         .orientation(ratatui::widgets::ScrollbarOrientation::VerticalRight)
@@ -68,61 +223,402 @@ pub fn draw_ui(f: &mut Frame, state: &mut TuiState) {
         &mut state.scrollbar_state,
     );
 
-    if state.show_info_popup {
-        if let Some((ref attrs, child_count)) = state.info_popup_data {
-            let area = centered_rect(60, 50, f.size());
-            f.render_widget(ratatui::widgets::Clear, area);
-
-            let mut lines = vec![
-                Line::from(vec![
-                    Span::styled("Children count: ", Style::default().fg(Color::Cyan)),
-                    Span::styled(
-                        child_count.to_string(),
-                        Style::default()
-                            .fg(Color::Yellow)
-                            .add_modifier(Modifier::BOLD),
-                    ),
-                ]),
-                Line::from(""),
-                Line::from(Span::styled(
-                    "Attributes:",
+    if state.show_info_popup
+        && let Some(info) = &state.info_popup_data
+    {
+        let area = centered_rect(60, 50, main_area);
+        f.render_widget(ratatui::widgets::Clear, area);
+
+        let detail = |label: &'static str, value: String| {
+            Line::from(vec![
+                Span::styled(label, Style::default().fg(theme.accent)),
+                Span::styled(
+                    value,
                     Style::default()
-                        .fg(Color::Cyan)
-                        .add_modifier(Modifier::UNDERLINED),
-                )),
-            ];
+                        .fg(theme.count)
+                        .add_modifier(Modifier::BOLD),
+                ),
+            ])
+        };
+        let mut lines = vec![
+            detail("Children count: ", info.child_count.to_string()),
+            detail("Descendants: ", info.descendant_count.to_string()),
+            detail("Position: ", format!("line {}, column {} (offset {})", info.line, info.column, info.offset)),
+            detail("Size: ", match info.size {
+                size @ ..1024 => format!("{} bytes", size),
+                size => format!("{} ({} bytes)", format_bytes(size as u64), size),
+            }),
+            Line::from(""),
+            Line::from(Span::styled(
+                "Attributes:",
+                Style::default()
+                    .fg(theme.accent)
+                    .add_modifier(Modifier::UNDERLINED),
+            )),
+        ];
 
-            if attrs.is_empty() {
-                lines.push(Line::from(Span::styled(
-                    "  (none)",
-                    Style::default().fg(Color::DarkGray),
-                )));
-            } else {
-                for (key, val) in attrs {
-                    lines.push(Line::from(vec![
-                        Span::raw("  "),
-                        Span::styled(*key, Style::default().fg(Color::Magenta)),
-                        Span::raw(" = "),
-                        Span::styled(*val, Style::default().fg(Color::Green)),
-                    ]));
-                }
+        if info.attributes.is_empty() {
+            lines.push(Line::from(Span::styled(
+                "  (none)",
+                Style::default().fg(theme.muted),
+            )));
+        } else {
+            for (key, val) in &info.attributes {
+                lines.push(Line::from(vec![
+                    Span::raw("  "),
+                    Span::styled(*key, Style::default().fg(theme.tag)),
+                    Span::raw(" = "),
+                    Span::styled(*val, Style::default().fg(theme.value)),
+                ]));
             }
+        }
+
+        let block = Block::default()
+            .title(" Element Details ")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.foreground))
+            .bg(theme.popup);
+
+        let paragraph = Paragraph::new(lines)
+            .block(block)
+            .wrap(ratatui::widgets::Wrap { trim: true });
+
+        f.render_widget(paragraph, area);
+    }
 
-            let block = Block::default()
-                .title(" Element Details ")
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::White))
-                .bg(Color::Rgb(40, 40, 50));
+    if let Some(cardinality) = &state.cardinality {
+        draw_cardinality_popup(f, main_area, cardinality);
+    }
+}
 
-            let paragraph = Paragraph::new(lines)
-                .block(block)
-                .wrap(ratatui::widgets::Wrap { trim: true });
+fn draw_source(f: &mut Frame, area: Rect, view: &SourceView, xml: &str, focused: bool) {
+    let theme = theme::current();
+    let lines = view.visible_lines(xml);
+    let last = (view.scroll + lines.len()).min(view.line_count());
+    let title = format!(" Source  lines {}-{} of {} ", (view.scroll + 1).min(last), last, view.line_count());
+    let mut block = Block::default()
+        .title(Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.border))
+        .bg(theme.background);
+    if focused {
+        block = block.border_style(Style::default().fg(theme.accent));
+    }
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
 
-            f.render_widget(paragraph, area);
+fn draw_reading(f: &mut Frame, area: Rect, view: &mut ReadingView, focused: bool) {
+    let theme = theme::current();
+    let page = area.height.saturating_sub(2) as usize;
+    // Leave the right border and a column of margin free.
+    view.fit(page, area.width.saturating_sub(4) as usize);
+    let last = (view.scroll + page).min(view.line_count());
+    let section = view.section().map(|title| format!("  {}", title)).unwrap_or_default();
+    let markers = if view.markers { "i to hide tags" } else { "i for tags" };
+    let title = format!(
+        " <{}>{}  lines {}-{} of {}  {}, ]s/[s for sections, Enter for contents, r to close ",
+        view.tag,
+        section,
+        (view.scroll + 1).min(last),
+        last,
+        view.line_count(),
+        markers
+    );
+    let mut block = Block::default()
+        .title(Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.border))
+        .padding(ratatui::widgets::Padding::left(1))
+        .bg(theme.background);
+    if focused {
+        block = block.border_style(Style::default().fg(theme.accent));
+    }
+    f.render_widget(Paragraph::new(view.visible_lines()).block(block), area);
+    let Some(selected) = view.contents else {
+        return;
+    };
+    let area = centered_rect(60, 70, area);
+    f.render_widget(ratatui::widgets::Clear, area);
+    let items: Vec<ListItem> = view
+        .entries()
+        .into_iter()
+        .map(|(level, title)| ListItem::new(format!("{}{}", "  ".repeat(level.saturating_sub(1)), title)))
+        .collect();
+    let block = Block::default()
+        .title(Span::styled(" Contents  Enter to go, Backspace to close ", Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.foreground))
+        .bg(theme.popup);
+    let list = List::new(items)
+        .block(block)
+        .style(Style::default().fg(theme.foreground))
+        .highlight_symbol("→ ")
+        .highlight_style(theme.highlight);
+    let mut list_state = ratatui::widgets::ListState::default().with_selected(Some(selected));
+    f.render_stateful_widget(list, area, &mut list_state);
+}
+
+fn draw_hexdump(f: &mut Frame, area: Rect, view: &mut HexView, bytes: &[u8]) {
+    let theme = theme::current();
+    let area = centered_rect(90, 80, area);
+    f.render_widget(ratatui::widgets::Clear, area);
+    // Offset, three columns and one ASCII column per byte, plus borders and bars.
+    let row_width = if area.width >= 16 * 4 + 14 { 16 } else { 8 };
+    view.fit(area.height.saturating_sub(2) as usize, row_width, bytes.len());
+    let title = format!(" Hexdump  offset {} (0x{:x})  x to close ", view.offset, view.offset);
+    let block = Block::default()
+        .title(Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.foreground))
+        .bg(theme.popup);
+    f.render_widget(Paragraph::new(view.lines(bytes)).block(block), area);
+}
+
+fn draw_text(f: &mut Frame, area: Rect, view: &mut TextView) {
+    let theme = theme::current();
+    let area = centered_rect(90, 80, area);
+    f.render_widget(ratatui::widgets::Clear, area);
+    let page = area.height.saturating_sub(2) as usize;
+    let (title, lines) = match view.hex.as_mut() {
+        Some(hex) => {
+            let row_width = if area.width >= 16 * 4 + 14 { 16 } else { 8 };
+            hex.fit(page, row_width, view.text.len());
+            let title = format!(" <{}> text  {} bytes  x for text, o to close ", view.tag, view.text.len());
+            (title, hex.lines(view.text.as_bytes()))
+        }
+        None => {
+            view.fit(page, area.width.saturating_sub(2) as usize);
+            let last = (view.scroll + page).min(view.line_count());
+            let title = format!(
+                " <{}> text  lines {}-{} of {}  x for hex, o to close ",
+                view.tag,
+                (view.scroll + 1).min(last),
+                last,
+                view.line_count()
+            );
+            (title, view.visible_lines())
         }
+    };
+    let block = Block::default()
+        .title(Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.foreground))
+        .bg(theme.popup);
+    f.render_widget(Paragraph::new(lines).style(Style::default().fg(theme.foreground)).block(block), area);
+}
+
+fn draw_tree(f: &mut Frame, area: Rect, tree: &mut TreeView, xml: &str, attributes: &AttributeView, focused: bool) {
+    let theme = theme::current();
+    let attr_style = Style::default().fg(theme.attribute);
+    let mut items: Vec<ListItem> = tree
+        .rows
+        .iter()
+        .map(|row| {
+            let marker = match (row.expandable, row.expanded) {
+                (true, true) => "▾ ",
+                (true, false) => "▸ ",
+                (false, _) => "  ",
+            };
+            if let Some(run) = row.text {
+                return ListItem::new(Line::from(vec![
+                    Span::raw("  ".repeat(row.depth)),
+                    Span::styled(marker, Style::default().fg(theme.muted)),
+                    Span::styled(format!("\"{}\"", flat_text(run, TEXT_PREVIEW)), Style::default().fg(theme.text).add_modifier(Modifier::ITALIC)),
+                ]));
+            }
+            let mut spans = vec![
+                Span::raw("  ".repeat(row.depth)),
+                Span::styled(marker, Style::default().fg(theme.muted)),
+                Span::styled(row.node.tag, Style::default().fg(theme.tag).add_modifier(Modifier::BOLD)),
+            ];
+            if let Some(summary) = summarize_attributes(&shown_attributes(xml, &row.node, attributes)) {
+                spans.push(Span::styled(summary, attr_style));
+            }
+            if let Some(text) = text_preview(xml, &row.node) {
+                spans.push(Span::raw("  "));
+                spans.push(Span::styled(text, Style::default().fg(theme.text).add_modifier(Modifier::ITALIC)));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    if items.is_empty() {
+        items.push(ListItem::new(Span::styled("(No children)", Style::default().fg(theme.muted).add_modifier(Modifier::ITALIC))));
+    }
+    let position = tree.list_state.selected().map_or(0, |i| i + 1);
+    let title = format!("Tree  [{}/{} rows]", position, tree.rows.len());
+    let mut block = Block::default()
+        .title(Line::from(vec![
+            Span::styled(" XML Tree Navigator ", theme.title),
+            Span::raw("  "),
+            Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+        ]))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.border))
+        .bg(theme.background);
+    if focused {
+        block = block.border_style(Style::default().fg(theme.accent));
+    }
+    let list = List::new(items)
+        .block(block)
+        .highlight_symbol("→ ")
+        .highlight_style(theme.highlight)
+        .bg(theme.background);
+    f.render_stateful_widget(list, area, &mut tree.list_state);
+}
+
+fn draw_table(f: &mut Frame, area: Rect, table: &mut TableView, focused: bool) {
+    let theme = theme::current();
+    table.poll(false);
+    // Borders, header and highlight symbol.
+    let visible = table.fit(area.height.saturating_sub(3) as usize, area.width.saturating_sub(4) as usize);
+    let header_style = Style::default().fg(theme.tag).add_modifier(Modifier::BOLD);
+    let cursor = table.order.get(table.column).copied();
+    let visible: Vec<usize> = visible.into_iter().map(|position| table.order[position]).collect();
+    let header = Row::new(visible.iter().map(|&i| {
+        let column = &table.columns[i];
+        // Sort keys after the first are numbered.
+        let arrow = match table.sort.iter().position(|&(sorted, _)| sorted == i) {
+            Some(k) => {
+                let arrow = if table.sort[k].1 { "▼" } else { "▲" };
+                match k {
+                    0 => format!(" {}", arrow),
+                    _ => format!(" {}{}", arrow, k + 1),
+                }
+            }
+            None => String::new(),
+        };
+        let mut style = if Some(i) == cursor { header_style.add_modifier(Modifier::UNDERLINED) } else { header_style };
+        if table.filters.iter().any(|filter| filter.column == i) {
+            style = style.add_modifier(Modifier::ITALIC);
+        }
+        Cell::from(format!("{}{}", column.name, arrow)).style(style)
+    }));
+    let rows = table.rows[table.top.min(table.rows.len())..]
+        .iter()
+        .take(area.height as usize)
+        .map(|&r| Row::new(visible.iter().map(|&i| Cell::from(clip(&table.cells[r][i], table.columns[i].width)))));
+    let widths: Vec<Constraint> = visible.iter().map(|&i| Constraint::Length(table.columns[i].width as u16)).collect();
+    let mut more = String::new();
+    if visible.len() < table.order.len() {
+        let last = table.order.iter().position(|&i| Some(&i) == visible.last()).unwrap_or(0);
+        more.push_str(&format!("  columns {}–{} of {}", table.first_column + 1, last + 1, table.order.len()));
+    }
+    if table.order.len() < table.columns.len() {
+        more.push_str(&format!("  {} hidden", table.columns.len() - table.order.len()));
+    }
+    if table.frozen {
+        more.push_str(&format!("  {} frozen", table.columns[table.order[0]].name));
+    }
+    if !table.filters.is_empty() {
+        let filters: Vec<String> = table
+            .filters
+            .iter()
+            .map(|filter| format!("{} {}", table.columns[filter.column].name, filter.expr))
+            .collect();
+        more.push_str(&format!("  where {}", filters.join(", ")));
+    }
+    if table.is_busy() {
+        more.push_str("  sorting…");
+    }
+    let position = match table.rows.len() {
+        0 => "0".to_string(),
+        n => format!("{}/{}", table.selected + 1, thousands(n)),
+    };
+    let position = if table.rows.len() < table.nodes.len() {
+        format!("{} of {}", position, thousands(table.nodes.len()))
+    } else {
+        position
+    };
+    let title = format!("<{}> table  [{}]{}", table.tag, position, more);
+    let mut block = Block::default()
+        .title(Line::from(vec![
+            Span::styled(" XML Tree Navigator ", theme.title),
+            Span::raw("  "),
+            Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+        ]))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.border))
+        .bg(theme.background);
+    if focused {
+        block = block.border_style(Style::default().fg(theme.accent));
+    }
+    let widget = Table::new(rows, widths)
+        .header(header)
+        .column_spacing(GAP as u16)
+        .block(block)
+        .highlight_symbol("→ ")
+        .highlight_style(theme.highlight)
+        .bg(theme.background);
+    let mut state = TableState::default().with_selected(Some(table.selected - table.top));
+    f.render_stateful_widget(widget, area, &mut state);
+}
+
+/// `text` cut to `width` characters, ending in "…" when cut.
+fn clip(text: &str, width: usize) -> String {
+    match text.char_indices().nth(width.saturating_sub(1)) {
+        Some((cut, _)) if text.chars().count() > width => format!("{}…", &text[..cut]),
+        _ => text.to_string(),
     }
 }
 
+fn draw_diagnostics(f: &mut Frame, area: Rect, panel: &mut DiagnosticsPanel) {
+    let theme = theme::current();
+    let title = format!(" Diagnostics  {}  (Enter to jump, ! to close) ", panel.summary());
+    let block = Block::default()
+        .title(Span::styled(title, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .bg(theme.background);
+    let list = List::new(panel.items())
+        .block(block)
+        .highlight_symbol("→ ")
+        .highlight_style(theme.highlight_dim);
+    f.render_stateful_widget(list, area, &mut panel.list_state);
+}
+
+fn draw_cardinality_popup(f: &mut Frame, main_area: Rect, c: &Cardinality) {
+    let theme = theme::current();
+    let area = centered_rect(60, 50, main_area);
+    f.render_widget(ratatui::widgets::Clear, area);
+
+    let bar_width = area.width.saturating_sub(24) as u64;
+    let bucket = |label: &'static str, n: u64| {
+        let bar = (n * bar_width).checked_div(c.parents).unwrap_or(0);
+        Line::from(vec![
+            Span::styled(format!("  {:<5}", label), Style::default().fg(theme.accent)),
+            Span::styled(format!("{:>8} ", n), Style::default().fg(theme.count).add_modifier(Modifier::BOLD)),
+            Span::styled("█".repeat(bar as usize), Style::default().fg(theme.value)),
+        ])
+    };
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled(format!("<{}>", c.child), Style::default().fg(theme.tag).add_modifier(Modifier::BOLD)),
+            Span::raw(" per "),
+            Span::styled(format!("<{}>", c.parent), Style::default().fg(theme.tag).add_modifier(Modifier::BOLD)),
+            Span::styled(format!("  ({} parents)", c.parents), Style::default().fg(theme.muted)),
+        ]),
+        Line::from(""),
+        bucket("0", c.zero),
+        bucket("1", c.one),
+        bucket("N", c.many),
+        Line::from(""),
+    ];
+    let verdict = match (c.zero, c.many) {
+        (0, 0) => "always exactly once".to_string(),
+        (0, _) => format!("always present, up to {}", c.max),
+        (_, 0) => "optional, at most once".to_string(),
+        _ => format!("optional, up to {}", c.max),
+    };
+    lines.push(Line::from(Span::styled(verdict, Style::default().fg(theme.mark))));
+
+    let block = Block::default()
+        .title(" Cardinality ")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.foreground))
+        .bg(theme.popup);
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
         .direction(Direction::Vertical)
@@ -143,106 +639,301 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         .split(popup_layout[1])[1]
 }
 
-fn create_main_block<'a>(current: &Level<'a>, selected_index: usize) -> Block<'a> {
-    let n_children = current.children.len();
+fn create_main_block<'a>(current: &Level<'a>, selected_index: usize, attributes: &AttributeView, pending: usize) -> Block<'a> {
+    let theme = theme::current();
+    let n_children = current.len();
     let current_pos = if n_children > 0 {
         selected_index + 1
     } else {
         0
     };
 
-    let title = match &current.tag {
-        Some(t) => format!("<{}>  [{}/{}]", t, current_pos, n_children),
-        None => format!("Root element  [{}/{}]", current_pos, n_children),
+    // Long lists are shown a page at a time.
+    let page = current.page(selected_index);
+    let position = if current.total_rows() > page.len().max(1) {
+        format!("{} in {}–{} of {}", thousands(current_pos), thousands(page.start + 1), thousands(page.end), thousands(current.total_rows()))
+    } else {
+        format!("{}/{}", current_pos, n_children)
+    };
+    let mut title = match (&current.label, &current.tag) {
+        (Some(label), _) => format!("{}  [{}]", label, position),
+        (None, Some(t)) => format!("<{}>  [{}]", t, position),
+        (None, None) => format!("Root element  [{}]", position),
     };
+    if let Some(view) = &current.view {
+        if view.sort != SortKey::Document {
+            title.push_str(&format!("  by {}", view.sort.describe()));
+        }
+        if !view.filter.is_empty() {
+            title.push_str(&format!("  matching \"{}\" of {}", view.filter, thousands(current.children.len())));
+        }
+        if view.grouped {
+            title.push_str("  grouped by tag");
+        }
+    }
+    if !current.marked.is_empty() {
+        title.push_str(&format!("  {} marked", current.marked.len()));
+    }
+    if pending > 0 {
+        title.push_str(&format!("  {} pending", pending));
+    }
+    match attributes.display {
+        AttributeDisplay::Hidden => title.push_str("  attributes hidden"),
+        _ if !attributes.filter.is_empty() => title.push_str(&format!("  attributes: {}", attributes.filter.join(", "))),
+        _ => {}
+    }
     Block::default()
         .title(Line::from(vec![
             Span::styled(
                 " XML Tree Navigator ",
-                Style::default()
-                    .fg(Color::White)
-                    .bg(Color::Blue)
-                    .add_modifier(Modifier::BOLD),
+                theme.title,
             ),
             Span::raw("  "),
             Span::styled(
                 title,
                 Style::default()
-                    .fg(Color::Cyan)
+                    .fg(theme.accent)
                     .add_modifier(Modifier::BOLD),
             ),
         ]))
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(Color::Gray))
-        .bg(Color::Rgb(30, 30, 40))
+        .border_style(Style::default().fg(theme.border))
+        .bg(theme.background)
 }
 
-fn create_list<'a>(current: &Level<'a>, block: Block<'a>, _selected_index: usize) -> List<'a> {
-    let mut items: Vec<ListItem> = current
-        .children
-        .iter()
-        .map(|node| {
-            let mut spans = vec![Span::styled(
-                node.tag,
-                Style::default()
-                    .fg(Color::Magenta)
-                    .add_modifier(Modifier::BOLD),
-            )];
-
-            let trimmed_attrs = node.attributes_raw.replace('\n', " ");
-            let trimmed_attrs = trimmed_attrs.trim();
-            if !trimmed_attrs.is_empty() {
-                let display = if trimmed_attrs.len() > 40 {
-                    format!(" {}...", &trimmed_attrs[..40])
-                } else {
-                    format!(" {}", trimmed_attrs)
-                };
-
-                spans.push(Span::styled(display, Style::default().fg(Color::DarkGray)));
-            }
+/// `n` with commas between groups of three digits.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
 
-            if let Some(text) = node.text {
-                spans.push(Span::raw("  "));
-                spans.push(Span::styled(
-                    text,
-                    Style::default()
-                        .fg(Color::Cyan)
-                        .add_modifier(Modifier::ITALIC),
-                ));
+fn create_list<'a>(state: &TuiState<'a>, page: Range<usize>, block: Block<'a>, width: usize) -> List<'a> {
+    let theme = theme::current();
+    let current = state.get_current_level();
+    let mut items: Vec<ListItem> = page
+        .filter_map(|row| {
+            if let Some((first, count)) = current.group_at(row) {
+                return Some(group_item(current, first, count));
             }
-
-            ListItem::new(Line::from(spans))
+            let i = current.child_index(row)?;
+            Some(child_item(state, current, i, current.node(row)?, width))
         })
         .collect();
 
     if items.is_empty() {
         items.push(ListItem::new(Span::styled(
-            "(No children)",
+            if current.view.is_some() { "(No matching children)" } else { "(No children)" },
             Style::default()
-                .fg(Color::DarkGray)
+                .fg(theme.muted)
                 .add_modifier(Modifier::ITALIC),
         )));
     }
 
     List::new(items)
         .block(block)
-        .highlight_symbol(if current.children.is_empty() {
+        .highlight_symbol(if current.is_empty() {
             ""
         } else {
             "→ "
         })
         .highlight_style(
+            theme.highlight,
+        )
+        .bg(theme.background)
+}
+
+/// The row of a group of `count` children tagged like child `first`.
+fn group_item<'a>(current: &Level<'a>, first: usize, count: usize) -> ListItem<'a> {
+    let theme = theme::current();
+    let expanded = current.is_expanded(first);
+    ListItem::new(Line::from(vec![
+        Span::styled(if expanded { "▾ " } else { "▸ " }, Style::default().fg(theme.muted)),
+        Span::styled(
+            current.children[first].tag,
             Style::default()
-                .bg(Color::Yellow)
-                .fg(Color::Black)
+                .fg(theme.tag)
                 .add_modifier(Modifier::BOLD),
-        )
-        .bg(Color::Rgb(30, 30, 40))
+        ),
+        Span::styled(format!("  ×{}", thousands(count)), Style::default().fg(theme.count)),
+    ]))
+}
+
+/// The symbol and color marking an element that differs from the other
+/// document, when comparing.
+fn diff_symbol(diff: &DiffMarks, offset: usize) -> Option<(&'static str, Color)> {
+    let theme = theme::current();
+    Some(match diff.mark(offset)? {
+        Mark::OnlyHere if diff.new => ("+ ", theme.mark),
+        Mark::OnlyHere => ("- ", theme.error),
+        Mark::Changed => ("~ ", theme.warning),
+        Mark::ChangedInside => ("· ", theme.muted),
+    })
+}
+
+/// The row of child `i` of `current`, indented when it is listed under its
+/// group.
+fn child_item<'a>(state: &TuiState<'a>, current: &Level<'a>, i: usize, node: &Node<'a>, width: usize) -> ListItem<'a> {
+    let theme = theme::current();
+    let xml = state.explorer.xml();
+    let attributes = &state.attributes;
+    let attr_style = Style::default().fg(theme.attribute);
+    let mut spans = Vec::new();
+    if current.view.as_ref().is_some_and(|view| view.grouped) && current.is_expanded(i) {
+        spans.push(Span::raw("  "));
+    }
+    if let Some((symbol, color)) = state.diff.as_ref().and_then(|diff| diff_symbol(diff, node.offset)) {
+        spans.push(Span::styled(symbol, Style::default().fg(color).add_modifier(Modifier::BOLD)));
+    }
+    if current.marked.contains(&i) {
+        spans.push(Span::styled(
+            "* ",
+            Style::default()
+                .fg(theme.mark)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
+    let pending = state.pending.get(&node.offset).map(|(_, pending)| *pending);
+    let mut tag_style = Style::default()
+        .fg(theme.tag)
+        .add_modifier(Modifier::BOLD);
+    match pending {
+        Some(Pending::Delete) => {
+            spans.push(Span::styled("✗ ", Style::default().fg(theme.error).add_modifier(Modifier::BOLD)));
+            tag_style = tag_style.fg(theme.error).add_modifier(Modifier::CROSSED_OUT);
+        }
+        Some(Pending::Duplicate(copies)) => {
+            spans.push(Span::styled(format!("+{} ", copies), Style::default().fg(theme.warning).add_modifier(Modifier::BOLD)));
+        }
+        None => {}
+    }
+    spans.push(Span::styled(node.tag, tag_style));
+    if let Some(label) = state.profile.and_then(|profile| profile.label(xml, node)) {
+        spans.push(Span::styled(format!("  {}", label), Style::default().fg(theme.text)));
+    }
+
+    let shown = shown_attributes(xml, node, attributes);
+    let mut extra_lines = Vec::new();
+    if attributes.display == AttributeDisplay::Full {
+        extra_lines = wrap_attributes(&shown, width)
+            .into_iter()
+            .map(|line| Line::from(Span::styled(format!("    {}", line), attr_style)))
+            .collect();
+    } else {
+        if let Some(summary) = summarize_attributes(&shown) {
+            spans.push(Span::styled(summary, attr_style));
+        }
+    }
+
+    if let Some(text) = text_preview(xml, node) {
+        spans.push(Span::raw("  "));
+        spans.push(Span::styled(
+            text,
+            Style::default()
+                .fg(theme.text)
+                .add_modifier(Modifier::ITALIC),
+        ));
+    }
+
+    let mut lines = vec![Line::from(spans)];
+    lines.extend(extra_lines);
+    ListItem::new(lines)
+}
+
+/// The text shown beside an element: its own text, or for mixed content
+/// the start of all of it as read.
+fn text_preview<'a>(xml: &str, node: &Node<'a>) -> Option<Cow<'a, str>> {
+    if node.mixed {
+        return Some(Cow::Owned(flat_text(&xml[node.span.clone()], TEXT_PREVIEW)));
+    }
+    node.text.map(Cow::Borrowed)
+}
+
+/// The attributes of `node` the view shows, as `name="value"` pieces (or the
+/// raw attribute text when nothing is filtered).
+fn shown_attributes(xml: &str, node: &Node, attributes: &AttributeView) -> Vec<String> {
+    match attributes.display {
+        AttributeDisplay::Hidden => Vec::new(),
+        AttributeDisplay::Summary if attributes.filter.is_empty() => {
+            vec![node.attributes_raw.replace('\n', " ").trim().to_string()]
+        }
+        _ => extract_attributes(xml, node.offset)
+            .into_iter()
+            .filter(|(name, _)| attributes.shows(name))
+            .map(|(name, value)| format!("{}=\"{}\"", name, value))
+            .collect(),
+    }
+}
+
+/// One-line attribute summary, cut after 40 characters.
+fn summarize_attributes(shown: &[String]) -> Option<String> {
+    let summary = shown.join(" ");
+    if summary.is_empty() {
+        return None;
+    }
+    Some(match summary.char_indices().nth(40) {
+        Some((cut, _)) => format!(" {}...", &summary[..cut]),
+        None => format!(" {}", summary),
+    })
+}
+
+/// Packs `name="value"` pieces into lines of at most `width` characters,
+/// breaking pieces that do not fit on a line of their own.
+fn wrap_attributes(pieces: &[String], width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for piece in pieces {
+        let len = piece.chars().count();
+        if !current.is_empty() && current.chars().count() + 1 + len > width {
+            lines.push(std::mem::take(&mut current));
+        }
+        if len <= width {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(piece);
+            continue;
+        }
+        let chars: Vec<char> = piece.chars().collect();
+        for chunk in chars.chunks(width) {
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            current = chunk.iter().collect();
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// One line naming the open documents, numbered, with the active one highlighted.
+fn create_tab_bar(panes: &PaneManager) -> Paragraph<'static> {
+    let theme = theme::current();
+    let mut spans = Vec::new();
+    for (i, tab) in panes.tabs.iter().enumerate() {
+        let label = format!(" {}:{} ", i + 1, tab.name);
+        spans.push(if i == panes.active_tab {
+            Span::styled(label, Style::default().fg(theme.background).bg(theme.accent).add_modifier(Modifier::BOLD))
+        } else {
+            Span::styled(label, Style::default().fg(theme.muted))
+        });
+        spans.push(Span::raw(" "));
+    }
+    Paragraph::new(Line::from(spans))
 }
 
 fn create_help_paragraph() -> Paragraph<'static> {
+    let theme = theme::current();
     let key_style = Style::default()
-        .fg(Color::Cyan)
+        .fg(theme.accent)
         .add_modifier(Modifier::BOLD);
     let help_spans = vec![
         Span::raw("Use "),
@@ -251,9 +942,51 @@ fn create_help_paragraph() -> Paragraph<'static> {
         Span::styled("Enter/→", key_style),
         Span::raw(" to go in, "),
         Span::styled("Backspace/←", key_style),
-        Span::raw(" to go up, "),
+        Span::raw(" to go up ("),
+        Span::styled("1-9", key_style),
+        Span::raw(" levels, "),
+        Span::styled("g", key_style),
+        Span::raw(" to the root), "),
         Span::styled("Space", key_style),
         Span::raw(" to show details, "),
+        Span::styled("c", key_style),
+        Span::raw(" for cardinality, "),
+        Span::styled("v", key_style),
+        Span::raw(" for source, "),
+        Span::styled("!", key_style),
+        Span::raw(" for problems, "),
+        Span::styled("x", key_style),
+        Span::raw(" for hexdump, "),
+        Span::styled("o", key_style),
+        Span::raw(" for full text, "),
+        Span::styled("r", key_style),
+        Span::raw(" for reading, "),
+        Span::styled("T", key_style),
+        Span::raw(" for tree, "),
+        Span::styled("t", key_style),
+        Span::raw(" for table (s/S// to sort/filter columns, </>/-/f/C/E to move/hide/freeze/choose columns, CSV), "),
+        Span::styled("G", key_style),
+        Span::raw(" to group by tag, "),
+        Span::styled("a/A", key_style),
+        Span::raw(" for attributes/filter, "),
+        Span::styled("s/S//", key_style),
+        Span::raw(" to sort/sort by attribute/filter, "),
+        Span::styled("m/*/u", key_style),
+        Span::raw(" to mark/regex-select/unmark, "),
+        Span::styled("=", key_style),
+        Span::raw(" to edit, "),
+        Span::styled("d/p/:w", key_style),
+        Span::raw(" to delete/duplicate/save, "),
+        Span::styled("e", key_style),
+        Span::raw(" to export, "),
+        Span::styled("yp/yt/yx", key_style),
+        Span::raw(" to copy path/text/XML, "),
+        Span::styled("w/W", key_style),
+        Span::raw(" to switch/flip panes, "),
+        Span::styled("Tab", key_style),
+        Span::raw(" to switch documents, "),
+        Span::styled(":", key_style),
+        Span::raw(" to go to an offset or line, "),
         Span::styled("q", key_style),
         Span::raw(" to quit."),
     ];
@@ -261,3 +994,40 @@ fn create_help_paragraph() -> Paragraph<'static> {
 
     Paragraph::new(help_line).block(Block::default().borders(Borders::NONE))
 }
+
+fn create_prompt_paragraph(prompt: &Prompt) -> Paragraph<'static> {
+    let theme = theme::current();
+    let label = match prompt.kind {
+        PromptKind::SelectRegex => "Select regex: ",
+        PromptKind::ExportFile => "Export subtree to file: ",
+        PromptKind::Edit => "Edit (text, or @name=value): ",
+        PromptKind::AttributeFilter => "Show attributes (names, empty for all): ",
+        PromptKind::FilterChildren => "Filter children (~ for fuzzy, empty for all): ",
+        PromptKind::SortAttribute => "Sort by attribute (empty for document order): ",
+        PromptKind::TableColumns => "Show columns (names in order, empty for all): ",
+        PromptKind::ExportTable => "Export table to CSV file: ",
+        PromptKind::ColumnFilter => "Filter column (text, =text, >n, a..b, ! to negate; empty for all): ",
+        PromptKind::Command => ":",
+    };
+    let line = Line::from(vec![
+        Span::styled(
+            label,
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(prompt.input.clone()),
+        Span::styled("█", Style::default().fg(theme.border)),
+    ]);
+    Paragraph::new(line)
+}
+
+fn create_message_paragraph(message: &str) -> Paragraph<'static> {
+    let theme = theme::current();
+    let line = Line::from(Span::styled(
+        message.to_string(),
+        Style::default().fg(theme.warning),
+    ))
+    .alignment(Alignment::Center);
+    Paragraph::new(line)
+}
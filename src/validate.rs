@@ -0,0 +1,189 @@
+//! Record-level validation (`xmz validate --records`): each record a query
+//! selects is checked on its own, against a schema and against rules that
+//! every record must satisfy, spread over several threads.
+//!
+//! The records are found in one pass over the document; checking them is
+//! what takes the time on large dumps, and records are independent of each
+//! other, so workers take them in batches. Only the first failures (in
+//! document order) are kept, with the counts of valid and invalid records.
+
+use crate::check::{Diagnostic, Severity, locate, write_diagnostic};
+use crate::json::Json;
+use crate::query::{Predicate, Query, select};
+use crate::xsd::Xsd;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Records a worker takes at a time.
+const BATCH: usize = 256;
+
+/// A predicate every record must satisfy, e.g. `price>0 and @id`.
+pub struct Rule {
+    pub source: String,
+    predicate: Predicate,
+}
+
+impl Rule {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        Ok(Rule {
+            source: source.to_string(),
+            predicate: Predicate::parse(source)?,
+        })
+    }
+}
+
+pub struct RecordOptions<'a> {
+    pub query: &'a Query,
+    pub schema: Option<&'a Xsd>,
+    pub rules: &'a [Rule],
+    /// Threads to validate with.
+    pub jobs: usize,
+    /// Invalid records to report in full.
+    pub max_failures: usize,
+}
+
+/// An invalid record: its number among the records, from 1, and its
+/// problems, located in the whole document.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub record: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub records: usize,
+    pub invalid: usize,
+    /// The first `max_failures` invalid records.
+    pub failures: Vec<Failure>,
+}
+
+/// The problems of one record, offsets relative to the record.
+fn check_record(record: &str, options: &RecordOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = options.schema.map_or_else(Vec::new, |schema| schema.validate_record(record));
+    let name = record[1..].split(|c: char| c.is_whitespace() || c == '>' || c == '/').next().unwrap_or("");
+    for rule in options.rules {
+        if !rule.predicate.eval(record) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                code: "rule",
+                offset: 0,
+                line: 0,
+                column: 0,
+                path: format!("/{}", name),
+                message: format!("the record does not satisfy '{}'", rule.source),
+            });
+        }
+    }
+    diagnostics.sort_by_key(|d| d.offset);
+    locate(record, &mut diagnostics);
+    diagnostics
+}
+
+/// Validates the records of `xml` that `options.query` selects.
+pub fn validate_records(xml: &str, options: &RecordOptions) -> Summary {
+    let records: Vec<Range<usize>> = select(xml, options.query);
+    let next = AtomicUsize::new(0);
+    let invalid = AtomicUsize::new(0);
+    let failures: Mutex<Vec<Failure>> = Mutex::new(Vec::new());
+    let worker = || {
+        // Batches are taken in order, so each worker's first failures are
+        // enough to find the first overall.
+        let mut found: Vec<Failure> = Vec::new();
+        loop {
+            let from = next.fetch_add(BATCH, Ordering::Relaxed);
+            if from >= records.len() {
+                break;
+            }
+            for (i, range) in records.iter().enumerate().skip(from).take(BATCH) {
+                let diagnostics: Vec<Diagnostic> = check_record(&xml[range.clone()], options).into_iter().filter(|d| d.severity == Severity::Error).collect();
+                if diagnostics.is_empty() {
+                    continue;
+                }
+                invalid.fetch_add(1, Ordering::Relaxed);
+                if found.len() < options.max_failures {
+                    found.push(Failure { record: i + 1, diagnostics });
+                }
+            }
+        }
+        failures.lock().unwrap_or_else(|err| err.into_inner()).extend(found);
+    };
+    let jobs = options.jobs.clamp(1, records.len().div_ceil(BATCH).max(1));
+    std::thread::scope(|scope| {
+        for _ in 1..jobs {
+            scope.spawn(worker);
+        }
+        worker();
+    });
+    let mut failures = failures.into_inner().unwrap_or_else(|err| err.into_inner());
+    failures.sort_by_key(|failure| failure.record);
+    failures.truncate(options.max_failures);
+    // Positions within each record become positions in the document, from
+    // where the records start, found in one pass.
+    let mut starts: Vec<Diagnostic> = failures
+        .iter()
+        .map(|failure| Diagnostic {
+            severity: Severity::Info,
+            code: "record",
+            offset: records[failure.record - 1].start,
+            line: 0,
+            column: 0,
+            path: String::new(),
+            message: String::new(),
+        })
+        .collect();
+    locate(xml, &mut starts);
+    for (failure, start) in failures.iter_mut().zip(&starts) {
+        for diagnostic in &mut failure.diagnostics {
+            if diagnostic.line == 1 {
+                diagnostic.column += start.column - 1;
+            }
+            diagnostic.line += start.line - 1;
+            diagnostic.offset += start.offset;
+        }
+    }
+    Summary {
+        records: records.len(),
+        invalid: invalid.into_inner(),
+        failures,
+    }
+}
+
+/// Prints the failures shown, each diagnostic prefixed with its record,
+/// and a line with the counts.
+pub fn write_summary<W: Write>(out: &mut W, file: &str, summary: &Summary) -> io::Result<()> {
+    for failure in &summary.failures {
+        for diagnostic in &failure.diagnostics {
+            let mut diagnostic = diagnostic.clone();
+            diagnostic.message = format!("record {}: {}", failure.record, diagnostic.message);
+            write_diagnostic(out, file, &diagnostic)?;
+        }
+    }
+    if summary.invalid > summary.failures.len() {
+        writeln!(out, "{}: ... and {} more invalid record(s)", file, summary.invalid - summary.failures.len())?;
+    }
+    writeln!(out, "{}: {} record(s), {} valid, {} invalid", file, summary.records, summary.records - summary.invalid, summary.invalid)
+}
+
+/// The JSON report: `{"file", "records", "valid", "invalid", "failures": [...]}`.
+pub fn summary_to_json(file: &str, summary: &Summary) -> Json {
+    let failures = summary
+        .failures
+        .iter()
+        .map(|failure| {
+            Json::Object(vec![
+                ("record".to_string(), Json::Number(failure.record as f64)),
+                ("diagnostics".to_string(), Json::Array(failure.diagnostics.iter().map(Diagnostic::to_json).collect())),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("file".to_string(), Json::String(file.to_string())),
+        ("records".to_string(), Json::Number(summary.records as f64)),
+        ("valid".to_string(), Json::Number((summary.records - summary.invalid) as f64)),
+        ("invalid".to_string(), Json::Number(summary.invalid as f64)),
+        ("failures".to_string(), Json::Array(failures)),
+    ])
+}
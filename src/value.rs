@@ -0,0 +1,243 @@
+//! Parsing of locale-formatted numbers and dates, shared by the analysis commands.
+//!
+//! Real dumps rarely use clean machine formats: `1.234,56` is a perfectly
+//! normal German price and `31.12.2023` a normal date. A `ValueParser`
+//! bundles the number convention and the accepted date formats so every
+//! command interprets values the same way.
+
+/// How decimal and thousands separators are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    /// Characters accepted (and ignored) as thousands separators.
+    pub grouping: &'static [char],
+}
+
+/// Built-in locale names accepted by `NumberFormat::for_locale`.
+pub const LOCALES: &[&str] = &["c", "en", "de", "fr", "ch"];
+
+impl NumberFormat {
+    /// Plain machine format: `.` decimal separator, no grouping.
+    pub const C: NumberFormat = NumberFormat {
+        decimal: '.',
+        grouping: &[],
+    };
+
+    /// Looks up a locale by name (`en`, `de`, `fr`, `ch`, ...) or language tag (`de-AT`).
+    pub fn for_locale(name: &str) -> Option<Self> {
+        let lang = name.split(['-', '_']).next().unwrap_or(name).to_ascii_lowercase();
+        let format = match lang.as_str() {
+            "c" | "posix" => Self::C,
+            "en" | "ja" | "zh" | "ko" | "th" | "he" | "hi" => NumberFormat {
+                decimal: '.',
+                grouping: &[','],
+            },
+            "de" | "nl" | "it" | "es" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "sl" | "hr" => NumberFormat {
+                decimal: ',',
+                grouping: &['.', '\''],
+            },
+            "fr" | "ru" | "uk" | "sv" | "cs" | "pl" | "fi" | "nb" | "no" | "sk" | "hu" | "bg" => NumberFormat {
+                decimal: ',',
+                grouping: &[' ', '\u{a0}', '\u{202f}'],
+            },
+            "ch" | "rm" => NumberFormat {
+                decimal: '.',
+                grouping: &['\'', '\u{2019}'],
+            },
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Parses `text` as a number, ignoring surrounding whitespace, grouping
+    /// separators and a trailing `%`. Grouping separators may only appear
+    /// before the decimal separator, after a first group of one to three
+    /// digits and between groups of exactly three, so `12.50` read with a
+    /// `.` separator is not a number rather than 1250.
+    pub fn parse(&self, text: &str) -> Option<f64> {
+        let text = text.trim();
+        let text = text.strip_suffix('%').unwrap_or(text).trim_end();
+        if text.is_empty() {
+            return None;
+        }
+        let (integer, fraction) = match text.find(self.decimal) {
+            Some(at) => (&text[..at], Some(&text[at + self.decimal.len_utf8()..])),
+            None => (text, None),
+        };
+        let mut normalized = String::with_capacity(text.len());
+        if integer.contains(self.grouping) {
+            let digits = integer.strip_prefix(['-', '+']).unwrap_or(integer);
+            normalized.push_str(&integer[..integer.len() - digits.len()]);
+            for (i, group) in digits.split(self.grouping).enumerate() {
+                let size = if i == 0 { 1..=3 } else { 3..=3 };
+                if !size.contains(&group.len()) || !group.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                normalized.push_str(group);
+            }
+        } else {
+            normalized.push_str(integer);
+        }
+        if let Some(fraction) = fraction {
+            if fraction.contains(self.grouping) {
+                return None;
+            }
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        if !normalized.bytes().any(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        normalized.parse().ok()
+    }
+}
+
+/// A `strftime`-style date pattern supporting `%Y %y %m %d %H %M %S %b %%`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateFormat(String);
+
+/// Formats tried when no explicit date format is configured.
+pub const ISO_DATE_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%SZ", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d"];
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+impl DateFormat {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c == '%' && !matches!(chars.next(), Some('Y' | 'y' | 'm' | 'd' | 'H' | 'M' | 'S' | 'b' | '%')) {
+                return Err(format!("unsupported directive in date format '{}'", pattern));
+            }
+        }
+        Ok(Self(pattern.to_string()))
+    }
+
+    /// Parses `text` into seconds since the Unix epoch (UTC).
+    pub fn parse(&self, text: &str) -> Option<i64> {
+        let text = text.trim();
+        let (mut year, mut month, mut day) = (1970i64, 1u32, 1u32);
+        let (mut hour, mut minute, mut second) = (0u32, 0u32, 0u32);
+        let mut rest = text;
+        let mut pattern = self.0.chars();
+        while let Some(c) = pattern.next() {
+            if c != '%' {
+                rest = rest.strip_prefix(c)?;
+                continue;
+            }
+            let directive = pattern.next()?;
+            match directive {
+                '%' => rest = rest.strip_prefix('%')?,
+                'b' => {
+                    let word: String = rest.chars().take(3).collect::<String>().to_ascii_lowercase();
+                    month = MONTHS.iter().position(|m| *m == word)? as u32 + 1;
+                    rest = &rest[rest.char_indices().nth(3).map_or(rest.len(), |(i, _)| i)..];
+                    // Accept full month names too.
+                    rest = rest.trim_start_matches(|c: char| c.is_alphabetic());
+                }
+                _ => {
+                    let max_len = if directive == 'Y' { 4 } else { 2 };
+                    let len = rest.bytes().take(max_len).take_while(u8::is_ascii_digit).count();
+                    if len == 0 {
+                        return None;
+                    }
+                    let n: u32 = rest[..len].parse().ok()?;
+                    rest = &rest[len..];
+                    match directive {
+                        'Y' => year = n as i64,
+                        'y' => year = if n < 70 { 2000 + n as i64 } else { 1900 + n as i64 },
+                        'm' => month = n,
+                        'd' => day = n,
+                        'H' => hour = n,
+                        'M' => minute = n,
+                        _ => second = n,
+                    }
+                }
+            }
+        }
+        if !rest.is_empty() || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        Some(days_from_civil(year, month, day) * 86_400 + (hour * 3600 + minute * 60 + second) as i64)
+    }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`.
+pub fn format_timestamp(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let time = secs.rem_euclid(86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    if time == 0 {
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    } else {
+        format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+    }
+}
+
+/// The number and date conventions used to interpret text values.
+#[derive(Debug, Clone)]
+pub struct ValueParser {
+    pub number: NumberFormat,
+    pub dates: Vec<DateFormat>,
+}
+
+impl Default for ValueParser {
+    fn default() -> Self {
+        Self {
+            number: NumberFormat::C,
+            dates: ISO_DATE_FORMATS.iter().map(|f| DateFormat(f.to_string())).collect(),
+        }
+    }
+}
+
+impl ValueParser {
+    /// Builds a parser from an optional locale name and explicit date formats
+    /// (ISO 8601 formats are used when none are given).
+    pub fn new(locale: Option<&str>, date_formats: &[String]) -> Result<Self, String> {
+        let mut parser = Self::default();
+        if let Some(locale) = locale {
+            parser.number = NumberFormat::for_locale(locale)
+                .ok_or_else(|| format!("unknown locale '{}' (known: {})", locale, LOCALES.join(", ")))?;
+        }
+        if !date_formats.is_empty() {
+            parser.dates = date_formats.iter().map(|f| DateFormat::new(f)).collect::<Result<_, _>>()?;
+        }
+        Ok(parser)
+    }
+
+    pub fn number(&self, text: &str) -> Option<f64> {
+        self.number.parse(text)
+    }
+
+    /// Parses a date with the first matching format, as seconds since the epoch.
+    pub fn date(&self, text: &str) -> Option<i64> {
+        self.dates.iter().find_map(|f| f.parse(text))
+    }
+}
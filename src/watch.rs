@@ -0,0 +1,159 @@
+//! Watching a directory for files that appear, are rewritten or go away,
+//! e.g. a drop folder fed by another process.
+//!
+//! On Linux the watcher uses inotify and only reports a new file once its
+//! writer has closed it (or it was moved into place). Elsewhere, or if
+//! inotify is unavailable, the directory is rescanned and modification times
+//! compared.
+//!
+//! A single file is watched through its directory, so that editors that save
+//! by writing a new file and renaming it over the old one are noticed too.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub struct DirWatcher {
+    dir: PathBuf,
+    #[cfg(target_os = "linux")]
+    inotify: Option<inotify::Inotify>,
+    /// Modification times from the last scan, for polling.
+    seen: HashMap<PathBuf, SystemTime>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let mut watcher = Self {
+            dir: dir.to_path_buf(),
+            #[cfg(target_os = "linux")]
+            inotify: inotify::Inotify::watch(dir).ok(),
+            seen: HashMap::new(),
+        };
+        watcher.seen = watcher.scan()?;
+        Ok(watcher)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Regular files in the directory with their modification times.
+    pub fn scan(&self) -> io::Result<HashMap<PathBuf, SystemTime>> {
+        let mut files = HashMap::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                files.insert(entry.path(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Files created, rewritten or removed since the last call; never blocks.
+    pub fn changes(&mut self) -> io::Result<Vec<PathBuf>> {
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = &self.inotify {
+            let mut changed = inotify.read_names()?;
+            changed.sort();
+            changed.dedup();
+            return Ok(changed.into_iter().map(|name| self.dir.join(name)).collect());
+        }
+        let current = self.scan()?;
+        let mut changed: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, modified)| self.seen.get(*path) != Some(*modified))
+            .map(|(path, _)| path.clone())
+            .chain(self.seen.keys().filter(|path| !current.contains_key(*path)).cloned())
+            .collect();
+        changed.sort();
+        self.seen = current;
+        Ok(changed)
+    }
+}
+
+/// Watches one file, e.g. the document open in the navigator.
+pub struct FileWatcher {
+    dir: DirWatcher,
+    path: PathBuf,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = DirWatcher::new(dir)?;
+        let path = dir.dir().join(name);
+        Ok(Self { dir, path })
+    }
+
+    /// Whether the file was rewritten or removed since the last call; never blocks.
+    pub fn changed(&mut self) -> io::Result<bool> {
+        Ok(self.dir.changes()?.contains(&self.path))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::{CString, OsString};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::Path;
+
+    pub struct Inotify {
+        fd: OwnedFd,
+    }
+
+    impl Inotify {
+        /// Watches `dir` for files closed after writing, moved in or out, or deleted.
+        pub fn watch(dir: &Path) -> io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let path = CString::new(dir.as_os_str().as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+            if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { fd })
+        }
+
+        /// Names of the files reported since the last call.
+        pub fn read_names(&self) -> io::Result<Vec<OsString>> {
+            const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+            let mut names = Vec::new();
+            let mut buf = [0u8; 16 * 1024];
+            loop {
+                let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n < 0 {
+                    let err = io::Error::last_os_error();
+                    return match err.kind() {
+                        io::ErrorKind::WouldBlock => Ok(names),
+                        io::ErrorKind::Interrupted => continue,
+                        _ => Err(err),
+                    };
+                }
+                let mut events = &buf[..n as usize];
+                while events.len() >= HEADER {
+                    let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(events.as_ptr().cast()) };
+                    let len = event.len as usize;
+                    let Some(name) = events.get(HEADER..HEADER + len) else {
+                        break;
+                    };
+                    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(len)];
+                    if !name.is_empty() && event.mask & libc::IN_ISDIR == 0 {
+                        names.push(OsString::from_vec(name.to_vec()));
+                    }
+                    events = &events[HEADER + len..];
+                }
+            }
+        }
+    }
+}
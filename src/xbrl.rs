@@ -0,0 +1,281 @@
+//! Facts of XBRL instance documents (`xmz xbrl`). A fact names its context
+//! and unit by id (`contextRef`, `unitRef`), and contexts and units may come
+//! anywhere in the filing, so facts are collected in one pass and resolved
+//! at the end: each row has the concept and value with the entity, period,
+//! dimensions and unit spelled out, ready for a spreadsheet or dataframe.
+
+use crate::agg::push_csv_line;
+use crate::json::Json;
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans, text_content, token_text};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Output formats of `xmz xbrl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            _ => Err(format!("unknown format '{}' (expected csv or ndjson)", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    /// The entity identifier, e.g. a CIK or LEI.
+    pub entity: String,
+    /// Start and end of a duration, or the date of an instant as the end;
+    /// neither for `forever`.
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// Dimension and member pairs from the segment or scenario.
+    pub dimensions: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fact {
+    /// The element name as written, e.g. `us-gaap:Revenues`.
+    pub concept: String,
+    /// The text content; empty for a nil fact.
+    pub value: String,
+    pub context_id: String,
+    pub context: Option<Context>,
+    /// The unit's measures, e.g. `iso4217:USD` or `iso4217:USD/xbrli:shares`.
+    pub unit: Option<String>,
+    pub decimals: Option<String>,
+}
+
+/// Which part of a context or unit the current text belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Identifier,
+    Start,
+    /// `endDate`, or `instant`.
+    End,
+    Member(String),
+    Measure,
+    Other,
+}
+
+struct Collector<'a> {
+    xml: &'a str,
+    contexts: HashMap<String, Context>,
+    units: HashMap<String, String>,
+    facts: Vec<(Fact, Option<String>)>,
+    /// The context or unit being read, with its id.
+    context: Option<(String, Context)>,
+    unit: Option<(String, Vec<String>, Vec<String>)>,
+    in_denominator: bool,
+    /// Open elements below the root.
+    open: Vec<Field>,
+    /// The fact being read, with the offset its content starts at and its depth.
+    fact: Option<(Fact, Option<String>, usize, usize)>,
+}
+
+impl<'a> Collector<'a> {
+    fn start(&mut self, name: &'a str, attrs: &'a str, end: usize) {
+        let local = local_name(name);
+        let attr = |key: &str| attribute_pairs(attrs).into_iter().find(|(name, _)| *name == key).map(|(_, value)| decode_entities(value).into_owned());
+        let field = match local {
+            "context" if self.context.is_none() => {
+                self.context = Some((attr("id").unwrap_or_default(), Context::default()));
+                Field::Other
+            }
+            "unit" if self.unit.is_none() && self.context.is_none() => {
+                self.unit = Some((attr("id").unwrap_or_default(), Vec::new(), Vec::new()));
+                Field::Other
+            }
+            _ if self.context.is_some() => match local {
+                "identifier" => Field::Identifier,
+                "startDate" => Field::Start,
+                "endDate" | "instant" => Field::End,
+                "explicitMember" | "typedMember" => Field::Member(attr("dimension").unwrap_or_default()),
+                _ => Field::Other,
+            },
+            _ if self.unit.is_some() => {
+                if local == "unitDenominator" {
+                    self.in_denominator = true;
+                }
+                if local == "measure" { Field::Measure } else { Field::Other }
+            }
+            _ => {
+                if self.fact.is_none()
+                    && let Some(context_id) = attr("contextRef")
+                {
+                    let nil = attribute_pairs(attrs).iter().any(|&(key, value)| local_name(key) == "nil" && value == "true");
+                    let fact = Fact {
+                        concept: name.to_string(),
+                        value: String::new(),
+                        context_id,
+                        context: None,
+                        unit: None,
+                        decimals: attr("decimals"),
+                    };
+                    // A nil fact has no content to read.
+                    let start = if nil { usize::MAX } else { end };
+                    self.fact = Some((fact, attr("unitRef"), start, self.open.len()));
+                }
+                Field::Other
+            }
+        };
+        self.open.push(field);
+    }
+
+    fn end(&mut self, name: &str, start: usize) {
+        if self.open.pop().is_none() {
+            return;
+        }
+        if let Some((_, _, _, depth)) = &self.fact
+            && *depth == self.open.len()
+            && let Some((mut fact, unit, content, _)) = self.fact.take()
+        {
+            if content != usize::MAX {
+                fact.value = text_content(&self.xml[content.min(start)..start]).trim().to_string();
+            }
+            self.facts.push((fact, unit));
+        }
+        match local_name(name) {
+            "context" => {
+                if let Some((id, context)) = self.context.take() {
+                    self.contexts.insert(id, context);
+                }
+            }
+            "unit" if self.context.is_none() => {
+                if let Some((id, numerators, denominators)) = self.unit.take() {
+                    let mut unit = numerators.join("*");
+                    if !denominators.is_empty() {
+                        unit.push('/');
+                        unit.push_str(&denominators.join("*"));
+                    }
+                    self.units.insert(id, unit);
+                }
+                self.in_denominator = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = text.to_string();
+        if let Some((_, context)) = &mut self.context {
+            match self.open.iter().rev().find(|field| **field != Field::Other) {
+                Some(Field::Identifier) => context.entity = text,
+                Some(Field::Start) => context.start = Some(text),
+                Some(Field::End) => context.end = Some(text),
+                Some(Field::Member(dimension)) => context.dimensions.push((dimension.clone(), text)),
+                _ => {}
+            }
+        } else if let Some((_, numerators, denominators)) = &mut self.unit
+            && self.open.last() == Some(&Field::Measure)
+        {
+            if self.in_denominator { denominators.push(text) } else { numerators.push(text) }
+        }
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+/// The facts of the XBRL instance `xml`, in document order, with their
+/// contexts and units resolved. A reference to a missing context or unit
+/// leaves it `None`.
+pub fn facts(xml: &str) -> Vec<Fact> {
+    let mut collector = Collector {
+        xml,
+        contexts: HashMap::new(),
+        units: HashMap::new(),
+        facts: Vec::new(),
+        context: None,
+        unit: None,
+        in_denominator: false,
+        open: Vec::new(),
+        fact: None,
+    };
+    stream_xml_spans(xml, |token, span| {
+        match token {
+            Token::StartTag(name, attrs) => collector.start(name, attrs, span.end),
+            Token::EndTag(name) => collector.end(name.trim_end(), span.start),
+            Token::Text(_) | Token::CData(_) => collector.text(&token_text(&token).unwrap_or_default()),
+        }
+        Continue(())
+    });
+    let Collector { contexts, units, facts, .. } = collector;
+    facts
+        .into_iter()
+        .map(|(mut fact, unit)| {
+            fact.context = contexts.get(&fact.context_id).cloned();
+            fact.unit = unit.map(|id| units.get(&id).cloned().unwrap_or(id));
+            fact
+        })
+        .collect()
+}
+
+const COLUMNS: [&str; 9] = ["concept", "value", "unit", "decimals", "entity", "start", "end", "dimensions", "context"];
+
+impl Fact {
+    /// The values of `COLUMNS`, empty where absent.
+    fn row(&self) -> [String; 9] {
+        let context = self.context.clone().unwrap_or_default();
+        let dimensions: Vec<String> = context.dimensions.iter().map(|(dimension, member)| format!("{}={}", dimension, member)).collect();
+        [
+            self.concept.clone(),
+            self.value.clone(),
+            self.unit.clone().unwrap_or_default(),
+            self.decimals.clone().unwrap_or_default(),
+            context.entity,
+            context.start.unwrap_or_default(),
+            context.end.unwrap_or_default(),
+            dimensions.join(";"),
+            self.context_id.clone(),
+        ]
+    }
+
+    pub fn to_json(&self) -> Json {
+        let text = |value: &Option<String>| value.clone().map_or(Json::Null, Json::String);
+        let context = self.context.as_ref();
+        Json::Object(vec![
+            ("concept".to_string(), Json::String(self.concept.clone())),
+            ("value".to_string(), Json::String(self.value.clone())),
+            ("unit".to_string(), text(&self.unit)),
+            ("decimals".to_string(), text(&self.decimals)),
+            ("entity".to_string(), context.map_or(Json::Null, |context| Json::String(context.entity.clone()))),
+            ("start".to_string(), context.map_or(Json::Null, |context| text(&context.start))),
+            ("end".to_string(), context.map_or(Json::Null, |context| text(&context.end))),
+            (
+                "dimensions".to_string(),
+                Json::Object(context.map_or(Vec::new(), |context| context.dimensions.iter().map(|(dimension, member)| (dimension.clone(), Json::String(member.clone()))).collect())),
+            ),
+            ("context".to_string(), Json::String(self.context_id.clone())),
+        ])
+    }
+}
+
+/// Writes `facts` as CSV with a header line, or as one JSON object per line.
+pub fn write_facts<W: Write>(out: &mut W, facts: &[Fact], format: Format) -> io::Result<()> {
+    match format {
+        Format::Csv => {
+            let mut csv = String::new();
+            push_csv_line(&mut csv, COLUMNS.into_iter());
+            out.write_all(csv.as_bytes())?;
+            for fact in facts {
+                csv.clear();
+                push_csv_line(&mut csv, fact.row().iter().map(String::as_str));
+                out.write_all(csv.as_bytes())?;
+            }
+        }
+        Format::Ndjson => {
+            for fact in facts {
+                writeln!(out, "{}", fact.to_json())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,7 @@
-use crate::parser::{Break, Continue, Token, extract_attributes, stream_xml};
+use crate::index::{Index, NONE};
+use crate::parser::{Break, Continue, Token, extract_attributes, flat_text, stream_xml, stream_xml_spans, text_content};
+use memchr::memmem;
+use std::ops::Range;
 
 /// A node in the XML tree.
 /// Represents an element with its tag name, text content, and attributes.
@@ -6,19 +9,40 @@ use crate::parser::{Break, Continue, Token, extract_attributes, stream_xml};
 #[derive(Debug, Clone)]
 pub struct Node<'a> {
     pub tag: &'a str,
+    /// The first run of text directly inside the element, trimmed.
     pub text: Option<&'a str>,
+    /// Whether the element has both text and child elements, like a
+    /// paragraph with inline markup; `content` lists them in order and
+    /// `text_content` reads them as one text.
+    pub mixed: bool,
     pub offset: usize,
     pub attributes_raw: &'a str,
+    /// Byte range of the whole element, from its '<' to the end of its end tag.
+    pub span: Range<usize>,
+}
+
+/// A piece of an element's content, in document order.
+#[derive(Debug, Clone)]
+pub enum Content<'a> {
+    /// A run of text as written, with its entities, CDATA sections and
+    /// comments; `parser::text_content` reads it.
+    Text(&'a str),
+    Element(Node<'a>),
 }
 
 /// Cache entry: (parent_offset, children_nodes)
 type CacheEntry<'a> = (usize, Vec<Node<'a>>);
 
+/// Number of children seen so far per tag name.
+type SiblingCounts<'a> = Vec<(&'a str, usize)>;
+
 /// Handles navigation and data access for the XML document.
 /// Wraps the raw XML string and provides caching for children lookups.
+/// With an `Index`, navigation no longer re-streams the document.
 pub struct XmlExplorer<'a> {
     xml: &'a str,
     cache: Vec<CacheEntry<'a>>,
+    index: Option<Index>,
 }
 
 impl<'a> XmlExplorer<'a> {
@@ -26,11 +50,37 @@ impl<'a> XmlExplorer<'a> {
         Self {
             xml,
             cache: Vec::new(),
+            index: None,
         }
     }
 
+    /// Creates an explorer that navigates through a prebuilt index.
+    pub fn with_index(xml: &'a str, index: Index) -> Self {
+        let mut explorer = Self::new(xml);
+        explorer.index = Some(index);
+        explorer
+    }
+
+    pub fn index(&self) -> Option<&Index> {
+        self.index.as_ref()
+    }
+
+    /// Builds the index on first use; later lookups go through it.
+    pub fn ensure_index(&mut self) -> &Index {
+        self.cache.clear();
+        self.index.get_or_insert_with(|| Index::build(self.xml))
+    }
+
+    /// Returns the underlying document.
+    pub fn xml(&self) -> &'a str {
+        self.xml
+    }
+
     /// Returns the root node of the document.
     pub fn root(&self) -> Option<Node<'a>> {
+        if let Some(index) = &self.index {
+            return (!index.is_empty()).then(|| self.indexed_node(index, 0));
+        }
         let mut root = None;
         stream_xml(self.xml, |token| {
             if let Token::StartTag(name, attrs) = token {
@@ -40,8 +90,10 @@ impl<'a> XmlExplorer<'a> {
                 root = Some(Node {
                     tag: name,
                     text: None,
+                    mixed: false,
                     offset,
                     attributes_raw: attrs,
+                    span: offset..root_end(self.xml, name, offset),
                 });
                 return Break(());
             }
@@ -61,20 +113,296 @@ impl<'a> XmlExplorer<'a> {
         }
 
         // Not in cache, parse
-        let children = self.parse_children(parent.offset, Some(parent.tag));
+        let children = match &self.index {
+            Some(index) => match index.id_at(parent.offset) {
+                Some(id) => index.children(id).map(|child| self.indexed_node(index, child)).collect(),
+                None => Vec::new(),
+            },
+            None => self.parse_children(parent.offset, Some(parent.tag)),
+        };
         self.cache.push((parent.offset, children.clone()));
         children
     }
 
+    /// The first `max` children of `parent`, and where to continue with
+    /// `more_children` if there are more. Unlike `children`, the work done
+    /// does not depend on how many children the element has.
+    pub fn children_window(&self, parent: &Node<'a>, max: usize) -> (Vec<Node<'a>>, Option<usize>) {
+        match &self.index {
+            Some(index) => match index.id_at(parent.offset) {
+                Some(id) => self.indexed_window(index, index.first_child[id as usize], max),
+                None => (Vec::new(), None),
+            },
+            None => self.parse_children_window(parent.offset, false, Some(parent.tag), 0, max),
+        }
+    }
+
+    /// Continues a `children_window` listing from the child starting at `resume`.
+    pub fn more_children(&self, resume: usize, max: usize) -> (Vec<Node<'a>>, Option<usize>) {
+        match &self.index {
+            Some(index) => match index.id_at(resume) {
+                Some(id) => self.indexed_window(index, id, max),
+                None => (Vec::new(), None),
+            },
+            None => self.parse_children_window(resume, true, None, 0, max),
+        }
+    }
+
+    /// Number of child elements of `parent`, counted without building nodes.
+    pub fn child_count(&self, parent: &Node<'a>) -> usize {
+        match &self.index {
+            Some(index) => index.id_at(parent.offset).map_or(0, |id| index.children(id).count()),
+            None => self.count_children(parent.offset, parent.tag),
+        }
+    }
+
+    /// Number of elements nested in `node` at any depth.
+    pub fn descendant_count(&self, node: &Node<'a>) -> usize {
+        if let Some(index) = &self.index
+            && let Some(id) = index.id_at(node.offset)
+        {
+            return index.descendant_count(id);
+        }
+        let mut count = 0usize;
+        stream_xml(self.xml.get(node.span.clone()).unwrap_or(""), |token| {
+            if let Token::StartTag(..) = token {
+                count += 1;
+            }
+            Continue(())
+        });
+        count.saturating_sub(1)
+    }
+
+    /// Up to `len` children of `parent` from the `start`th on (0-based);
+    /// children before `start` are skipped without being built.
+    pub fn children_range(&self, parent: &Node<'a>, start: usize, len: usize) -> Vec<Node<'a>> {
+        match &self.index {
+            Some(index) => match index.id_at(parent.offset) {
+                Some(id) => index.children(id).skip(start).take(len).map(|child| self.indexed_node(index, child)).collect(),
+                None => Vec::new(),
+            },
+            None => self.parse_children_window(parent.offset, false, Some(parent.tag), start, len).0,
+        }
+    }
+
+    /// Up to `max` siblings from element `first` on, and the start of the next one.
+    fn indexed_window(&self, index: &Index, first: u32, max: usize) -> (Vec<Node<'a>>, Option<usize>) {
+        let mut children = Vec::new();
+        let mut next = first;
+        while next != NONE && children.len() < max {
+            children.push(self.indexed_node(index, next));
+            next = index.next_sibling[next as usize];
+        }
+        (children, (next != NONE).then(|| index.start[next as usize] as usize))
+    }
+
     /// Extracts parsed attributes (key-value pairs) for the node.
     pub fn attributes(&self, node: &Node<'a>) -> Vec<(&'a str, &'a str)> {
         extract_attributes(self.xml, node.offset)
     }
 
+    /// Returns the exact source text of the element, including all descendants.
+    pub fn raw(&self, node: &Node<'a>) -> &'a str {
+        &self.xml[node.span.clone()]
+    }
+
+    /// XPath-like location of the node, e.g. `/lib/book[2]/title[1]`, where
+    /// positions count earlier siblings of the same name.
+    pub fn path(&self, node: &Node<'a>) -> String {
+        // Open elements above the scan position, with counts of their children by name.
+        let mut open: Vec<(&str, usize, SiblingCounts)> = Vec::new();
+        let mut top = SiblingCounts::new();
+        let mut path = String::new();
+        stream_xml_spans(self.xml, |token, span| {
+            match token {
+                Token::StartTag(name, _) => {
+                    let siblings = open.last_mut().map_or(&mut top, |(_, _, children)| children);
+                    let position = match siblings.iter_mut().find(|(n, _)| *n == name) {
+                        Some((_, count)) => {
+                            *count += 1;
+                            *count
+                        }
+                        None => {
+                            siblings.push((name, 1));
+                            1
+                        }
+                    };
+                    open.push((name, position, Vec::new()));
+                    if span.start == node.offset {
+                        for (depth, (name, position, _)) in open.iter().enumerate() {
+                            path.push('/');
+                            path.push_str(name);
+                            if depth > 0 {
+                                path.push_str(&format!("[{}]", position));
+                            }
+                        }
+                        return Break(());
+                    }
+                }
+                Token::EndTag(_) => {
+                    open.pop();
+                }
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            Continue(())
+        });
+        path
+    }
+
+    /// All text inside the element as read, with whitespace collapsed and
+    /// the text of neighbouring elements separated (see `parser::flat_text`).
+    pub fn text_content(&self, node: &Node<'a>) -> String {
+        flat_text(self.raw(node), usize::MAX)
+    }
+
+    /// The element's text runs and child elements in document order. Runs
+    /// of whitespace between elements are left out.
+    pub fn content(&mut self, node: &Node<'a>) -> Vec<Content<'a>> {
+        let raw = self.raw(node);
+        let mut inner = None;
+        stream_xml_spans(raw, |token, span| {
+            if let Token::StartTag(..) = token {
+                inner = Some(span.end..raw.rfind("</").filter(|&end| end >= span.end).unwrap_or(span.end));
+            }
+            Break(())
+        });
+        let Some(inner) = inner else {
+            return Vec::new();
+        };
+        let (xml, base) = (self.xml, node.span.start);
+        let mut content = Vec::new();
+        let mut at = base + inner.start;
+        let text = |from: usize, to: usize, content: &mut Vec<Content<'a>>| {
+            let run = &xml[from..to];
+            if !text_content(run).trim().is_empty() {
+                content.push(Content::Text(run));
+            }
+        };
+        for child in self.children(node) {
+            text(at, child.span.start, &mut content);
+            at = child.span.end;
+            content.push(Content::Element(child));
+        }
+        text(at, (base + inner.end).max(at), &mut content);
+        content
+    }
+
+    /// Builds the node for the element starting at `offset` (which must point at its '<').
+    pub fn node_at(&self, offset: usize) -> Option<Node<'a>> {
+        if let Some(index) = &self.index
+            && let Some(id) = index.id_at(offset)
+        {
+            return Some(self.indexed_node(index, id));
+        }
+        let slice = self.xml.get(offset..)?;
+        let mut node: Option<Node<'a>> = None;
+        let mut depth = 0;
+        let mut has_children = false;
+        stream_xml_spans(slice, |token, span| {
+            match token {
+                Token::StartTag(name, attrs) => {
+                    if depth == 0 {
+                        node = Some(Node {
+                            tag: name,
+                            text: None,
+                            mixed: false,
+                            offset,
+                            attributes_raw: attrs,
+                            span: offset..self.xml.len(),
+                        });
+                    } else if depth == 1
+                        && let Some(node) = node.as_mut()
+                    {
+                        has_children = true;
+                        node.mixed = node.text.is_some();
+                    }
+                    depth += 1;
+                }
+                Token::EndTag(_) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if let Some(node) = node.as_mut() {
+                            node.span.end = offset + span.end;
+                        }
+                        return Break(());
+                    }
+                }
+                Token::Text(txt) | Token::CData(txt) => {
+                    if depth == 1
+                        && let Some(node) = node.as_mut()
+                        && node.text.is_none()
+                    {
+                        node.text = Some(txt);
+                        node.mixed = has_children;
+                    }
+                }
+            }
+            Continue(())
+        });
+        node
+    }
+
+    /// Materializes element `id` of `index` without scanning its content.
+    fn indexed_node(&self, index: &Index, id: u32) -> Node<'a> {
+        let i = id as usize;
+        let start = index.start[i] as usize;
+        let end = index.end[i] as usize;
+        let (tag, attributes_raw) = start_tag_parts(&self.xml[start..end]);
+        let text = (index.text_start[i] != u64::MAX).then(|| {
+            let text_start = index.text_start[i] as usize;
+            &self.xml[text_start..text_start + index.text_len[i] as usize]
+        });
+        Node {
+            tag,
+            text,
+            mixed: text.is_some() && index.first_child[i] != NONE,
+            offset: start,
+            attributes_raw,
+            span: start..end,
+        }
+    }
+
     /// Internal parsing logic to find direct children
     fn parse_children(&self, offset: usize, parent_tag: Option<&str>) -> Vec<Node<'a>> {
+        self.parse_children_window(offset, false, parent_tag, 0, usize::MAX).0
+    }
+
+    /// Counts the direct children of the element whose start tag is at `offset`.
+    fn count_children(&self, offset: usize, parent_tag: &str) -> usize {
+        let mut count = 0;
+        let mut depth = 0usize;
+        let mut inside = false;
+        stream_xml(self.xml.get(offset..).unwrap_or(""), |token| {
+            match token {
+                Token::StartTag(name, _) if !inside => inside = name == parent_tag,
+                Token::StartTag(..) => {
+                    if depth == 0 {
+                        count += 1;
+                    }
+                    depth += 1;
+                }
+                Token::EndTag(_) if inside => {
+                    if depth == 0 {
+                        return Break(());
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+            Continue(())
+        });
+        count
+    }
+
+    /// Parses at most `max` direct children, from the parent's start tag at
+    /// `offset` or, with `resume`, from a child's start tag at `offset`. The
+    /// first `skip` children are passed over without being built.
+    /// Also returns the start of the next child if the limit cut the list short.
+    fn parse_children_window(&self, offset: usize, resume: bool, parent_tag: Option<&str>, skip: usize, max: usize) -> (Vec<Node<'a>>, Option<usize>) {
         let mut children = Vec::new();
-        let mut depth = 0;
+        let mut more = None;
+        let mut depth = 0usize;
+        let mut skipped = 0;
 
         // Slice from the offset. We expect this to start with '<'
         let slice = if offset < self.xml.len() {
@@ -83,17 +411,18 @@ impl<'a> XmlExplorer<'a> {
             ""
         };
 
-        let mut inside = false;
-        let mut parent_matched = false;
+        let mut inside = resume;
+        let mut parent_matched = resume;
 
         // Current child being built
         let mut last_tag: Option<&'a str> = None;
         let mut last_tag_offset: usize = 0;
         let mut last_attrs: &'a str = "";
         let mut last_text: Option<&'a str> = None;
+        let mut last_has_children = false;
         let mut collecting_text = false;
 
-        stream_xml(slice, |token| {
+        stream_xml_spans(slice, |token, span| {
             match token {
                 Token::StartTag(name, attrs) => {
                     if !inside {
@@ -109,39 +438,49 @@ impl<'a> XmlExplorer<'a> {
                         }
                     } else {
                         // Inside parent
-                        if depth == 0 {
+                        if depth == 0 && skipped < skip {
+                            skipped += 1;
+                        } else if depth == 0 {
+                            if children.len() == max {
+                                more = Some(offset + span.start);
+                                return Break(());
+                            }
                             last_tag = Some(name);
                             // Subtract 1 to point to '<'
                             last_tag_offset = bytes_offset(self.xml, name).saturating_sub(1);
                             last_attrs = attrs;
                             last_text = None;
+                            last_has_children = false;
                             collecting_text = true;
+                        } else if depth == 1 {
+                            last_has_children = true;
                         }
                         depth += 1;
                     }
                 }
-                Token::EndTag(name) => {
+                Token::EndTag(_) => {
                     if inside {
-                        if depth > 0 {
-                            depth -= 1;
-                        }
-                        if depth == 0 && Some(name) == parent_tag && parent_matched {
+                        // At depth 0 this is the parent's own end tag.
+                        if depth == 0 && parent_matched {
                             return Break(());
                         }
+                        depth = depth.saturating_sub(1);
                         if depth == 0 && collecting_text {
                             if let Some(tag) = last_tag.take() {
                                 children.push(Node {
                                     tag,
+                                    mixed: last_has_children && last_text.is_some(),
                                     text: last_text.take(),
                                     offset: last_tag_offset,
                                     attributes_raw: last_attrs,
+                                    span: last_tag_offset..offset + span.end,
                                 });
                             }
                             collecting_text = false;
                         }
                     }
                 }
-                Token::Text(txt) => {
+                Token::Text(txt) | Token::CData(txt) => {
                     if collecting_text && depth == 1 && last_text.is_none() {
                         let t = txt.trim();
                         if !t.is_empty() {
@@ -153,7 +492,7 @@ impl<'a> XmlExplorer<'a> {
             Continue(())
         });
 
-        children
+        (children, more)
     }
 }
 
@@ -166,3 +505,52 @@ fn bytes_offset(base: &str, slice: &str) -> usize {
         slice_start - base_start
     }
 }
+
+/// Splits the start tag at the beginning of `element` into its name and raw attributes.
+fn start_tag_parts(element: &str) -> (&str, &str) {
+    let mut parts = ("", "");
+    stream_xml(element, |token| {
+        if let Token::StartTag(name, attrs) = token {
+            parts = (name, attrs);
+        }
+        Break(())
+    });
+    parts
+}
+
+/// Finds the end of the root element by searching backwards for its end tag,
+/// which avoids streaming the whole document.
+fn root_end(xml: &str, tag: &str, offset: usize) -> usize {
+    let closing = format!("</{}", tag);
+    match memmem::rfind(&xml.as_bytes()[offset..], closing.as_bytes()) {
+        Some(rel) => {
+            let start = offset + rel;
+            memchr::memchr(b'>', &xml.as_bytes()[start..]).map_or(xml.len(), |r| start + r + 1)
+        }
+        None => element_end(xml, offset),
+    }
+}
+
+/// Returns the offset just past the end tag matching the start tag at `offset`.
+pub fn element_end(xml: &str, offset: usize) -> usize {
+    let Some(slice) = xml.get(offset..) else {
+        return xml.len();
+    };
+    let mut depth = 0usize;
+    let mut end = xml.len();
+    stream_xml_spans(slice, |token, span| {
+        match token {
+            Token::StartTag(..) => depth += 1,
+            Token::EndTag(_) => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    end = offset + span.end;
+                    return Break(());
+                }
+            }
+            Token::Text(_) | Token::CData(_) => {}
+        }
+        Continue(())
+    });
+    end
+}
@@ -0,0 +1,1589 @@
+//! Validation against an XML Schema (`xmz validate --xsd`), for the subset of
+//! XSD 1.0 that hand-written schemas mostly use: global and local elements,
+//! named and anonymous types, `sequence`, `choice` and `all` with occurrence
+//! constraints, groups, attributes and attribute groups, simple and complex
+//! content derivation, and simple types restricted by facets.
+//!
+//! Elements and attributes are matched by local name, without checking their
+//! namespace. Identity constraints (`key`, `unique`), substitution groups,
+//! `xsi:type` and the namespaces of wildcards are ignored, and occurrence
+//! bounds above `MAX_OCCURS` are treated as unbounded. The document is checked
+//! in a single pass over the tokens, reporting each problem as a
+//! `check::Diagnostic`.
+
+use crate::check::{Diagnostic, Severity, locate};
+use crate::dtd::{AttributeDefault, AttributeType, ContentSpec, Dtd, Model};
+use crate::parser::{Continue, Token, attribute_pairs, decode_entities, stream_xml_spans, text_content};
+use crate::regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+const XSD_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema";
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+/// Occurrence bounds are expanded into automaton states up to this count.
+const MAX_OCCURS: u32 = 256;
+
+fn local(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+fn prefix(name: &str) -> &str {
+    name.split_once(':').map_or("", |(prefix, _)| prefix)
+}
+
+/// An element of a schema document.
+struct Node {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<usize>,
+    /// Index of the schema document it comes from.
+    file: usize,
+}
+
+impl Node {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// The schema documents read so far, as one tree per document.
+#[derive(Default)]
+struct Documents {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    /// Prefixes bound to the XML Schema namespace in each document.
+    xsd_prefixes: Vec<Vec<String>>,
+    loaded: Vec<std::path::PathBuf>,
+}
+
+impl Documents {
+    fn read(&mut self, path: &Path) -> Result<(), String> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.loaded.contains(&canonical) {
+            return Ok(());
+        }
+        self.loaded.push(canonical);
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let root = self.add(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let includes: Vec<(String, String)> = self.nodes[root]
+            .children
+            .iter()
+            .map(|&child| &self.nodes[child])
+            .filter(|node| matches!(node.name.as_str(), "include" | "import" | "redefine"))
+            .filter_map(|node| Some((node.name.clone(), node.attr("schemaLocation")?.to_string())))
+            .collect();
+        for (kind, location) in includes {
+            if location.contains("://") {
+                // Remote imports are left out; what they declare is reported if used.
+                if kind == "import" {
+                    continue;
+                }
+                return Err(format!("{}: cannot {} remote schema {}", path.display(), kind, location));
+            }
+            self.read(&path.parent().unwrap_or(Path::new("")).join(location))?;
+        }
+        Ok(())
+    }
+
+    /// Adds the tree of one schema document, returning its root.
+    fn add(&mut self, text: &str) -> Result<usize, String> {
+        let file = self.roots.len();
+        let first = self.nodes.len();
+        let mut open: Vec<usize> = Vec::new();
+        let nodes = &mut self.nodes;
+        stream_xml_spans(text, |token, _| {
+            match token {
+                Token::StartTag(name, attrs) => {
+                    let id = nodes.len();
+                    nodes.push(Node {
+                        name: local(name).to_string(),
+                        attrs: attribute_pairs(attrs).into_iter().map(|(key, value)| (key.to_string(), decode_entities(value).into_owned())).collect(),
+                        children: Vec::new(),
+                        file,
+                    });
+                    if let Some(&parent) = open.last() {
+                        nodes[parent].children.push(id);
+                    }
+                    open.push(id);
+                }
+                Token::EndTag(_) => {
+                    open.pop();
+                }
+                Token::Text(_) | Token::CData(_) => {}
+            }
+            Continue(())
+        });
+        let root = self.nodes.get(first).ok_or("not an XML document")?;
+        if root.name != "schema" {
+            return Err(format!("not an XML Schema (the root element is <{}>)", root.name));
+        }
+        let prefixes = root
+            .attrs
+            .iter()
+            .filter(|(_, value)| value == XSD_NAMESPACE)
+            .filter_map(|(key, _)| match key.as_str() {
+                "xmlns" => Some(String::new()),
+                key => key.strip_prefix("xmlns:").map(str::to_string),
+            })
+            .collect();
+        self.roots.push(first);
+        self.xsd_prefixes.push(prefixes);
+        Ok(first)
+    }
+}
+
+/// Built-in simple types, grouped by how their values are checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Builtin {
+    String,
+    NormalizedString,
+    Token,
+    Boolean,
+    Decimal,
+    Float,
+    Integer(Option<i128>, Option<i128>),
+    Date,
+    DateTime,
+    Time,
+    GYear,
+    GYearMonth,
+    Duration,
+    HexBinary,
+    Base64Binary,
+    Name,
+    NcName,
+    QName,
+    NmToken,
+    Language,
+    /// Types whose values are not checked (`anySimpleType`, `anyURI`, `gDay`...).
+    Any,
+}
+
+impl Builtin {
+    fn named(name: &str) -> Option<Self> {
+        let integer = |min: i128, max: i128| Builtin::Integer(Some(min), Some(max));
+        Some(match name {
+            "string" => Builtin::String,
+            "normalizedString" => Builtin::NormalizedString,
+            "token" => Builtin::Token,
+            "boolean" => Builtin::Boolean,
+            "decimal" => Builtin::Decimal,
+            "float" | "double" => Builtin::Float,
+            "integer" => Builtin::Integer(None, None),
+            "nonNegativeInteger" => Builtin::Integer(Some(0), None),
+            "positiveInteger" => Builtin::Integer(Some(1), None),
+            "nonPositiveInteger" => Builtin::Integer(None, Some(0)),
+            "negativeInteger" => Builtin::Integer(None, Some(-1)),
+            "long" => integer(i64::MIN.into(), i64::MAX.into()),
+            "int" => integer(i32::MIN.into(), i32::MAX.into()),
+            "short" => integer(i16::MIN.into(), i16::MAX.into()),
+            "byte" => integer(i8::MIN.into(), i8::MAX.into()),
+            "unsignedLong" => integer(0, u64::MAX.into()),
+            "unsignedInt" => integer(0, u32::MAX.into()),
+            "unsignedShort" => integer(0, u16::MAX.into()),
+            "unsignedByte" => integer(0, u8::MAX.into()),
+            "date" => Builtin::Date,
+            "dateTime" => Builtin::DateTime,
+            "time" => Builtin::Time,
+            "gYear" => Builtin::GYear,
+            "gYearMonth" => Builtin::GYearMonth,
+            "duration" => Builtin::Duration,
+            "hexBinary" => Builtin::HexBinary,
+            "base64Binary" => Builtin::Base64Binary,
+            "Name" => Builtin::Name,
+            "NCName" | "ID" | "IDREF" | "ENTITY" => Builtin::NcName,
+            "QName" | "NOTATION" => Builtin::QName,
+            "NMTOKEN" => Builtin::NmToken,
+            "language" => Builtin::Language,
+            "anySimpleType" | "anyURI" | "gMonth" | "gDay" | "gMonthDay" => Builtin::Any,
+            _ => return None,
+        })
+    }
+
+    fn is_valid(self, value: &str) -> bool {
+        match self {
+            Builtin::String | Builtin::NormalizedString | Builtin::Token | Builtin::Any => true,
+            Builtin::Boolean => matches!(value, "true" | "false" | "1" | "0"),
+            Builtin::Decimal => is_decimal(value),
+            Builtin::Float => {
+                matches!(value, "INF" | "+INF" | "-INF" | "NaN")
+                    || (value.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E')) && value.parse::<f64>().is_ok())
+            }
+            Builtin::Integer(min, max) => {
+                let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+                !digits.is_empty()
+                    && digits.bytes().all(|b| b.is_ascii_digit())
+                    && value.strip_prefix('+').unwrap_or(value).parse::<i128>().is_ok_and(|n| min.is_none_or(|min| n >= min) && max.is_none_or(|max| n <= max))
+            }
+            Builtin::Date => date(value).is_some_and(timezone),
+            Builtin::DateTime => value.split_once('T').is_some_and(|(day, time)| date(day) == Some("") && clock(time).is_some_and(timezone)),
+            Builtin::Time => clock(value).is_some_and(timezone),
+            Builtin::GYear => year(value).is_some_and(timezone),
+            Builtin::GYearMonth => year(value).and_then(|rest| two_digits(rest.strip_prefix('-')?, 1, 12)).is_some_and(timezone),
+            Builtin::Duration => is_duration(value),
+            Builtin::HexBinary => value.len().is_multiple_of(2) && value.bytes().all(|b| b.is_ascii_hexdigit()),
+            Builtin::Base64Binary => {
+                let compact: Vec<u8> = value.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+                let data = compact.iter().take_while(|&&b| b != b'=').count();
+                compact.len().is_multiple_of(4)
+                    && compact.len() - data <= 2
+                    && compact[data..].iter().all(|&b| b == b'=')
+                    && compact[..data].iter().all(|&b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+            }
+            Builtin::Name => is_name(value, true),
+            Builtin::NcName => is_name(value, false),
+            Builtin::QName => match value.split_once(':') {
+                Some((prefix, local)) => is_name(prefix, false) && is_name(local, false),
+                None => is_name(value, false),
+            },
+            Builtin::NmToken => !value.is_empty() && value.chars().all(|c| is_name_char(c) || c == ':'),
+            Builtin::Language => {
+                let mut parts = value.split('-');
+                parts.next().is_some_and(|first| (1..=8).contains(&first.len()) && first.bytes().all(|b| b.is_ascii_alphabetic()))
+                    && parts.all(|part| (1..=8).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_alphanumeric()))
+            }
+        }
+    }
+}
+
+fn is_decimal(value: &str) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    whole.len() + fraction.len() > 0 && whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit())
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '\u{b7}')
+}
+
+fn is_name(value: &str, colons: bool) -> bool {
+    let mut chars = value.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || (colons && c == ':')) && chars.all(|c| is_name_char(c) || (colons && c == ':'))
+}
+
+/// Parses two digits between `min` and `max`, returning what follows.
+fn two_digits(s: &str, min: u32, max: u32) -> Option<&str> {
+    let digits = s.get(..2)?;
+    let n: u32 = digits.bytes().all(|b| b.is_ascii_digit()).then(|| digits.parse().ok())??;
+    (min..=max).contains(&n).then(|| &s[2..])
+}
+
+/// Parses a year of four or more digits, optionally negative.
+fn year(s: &str) -> Option<&str> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let len = digits.bytes().take_while(u8::is_ascii_digit).count();
+    (len >= 4).then(|| &digits[len..])
+}
+
+/// Parses `YYYY-MM-DD`, returning what follows.
+fn date(s: &str) -> Option<&str> {
+    let rest = year(s)?;
+    let year: i64 = s[..s.len() - rest.len()].parse().ok()?;
+    let rest = rest.strip_prefix('-')?;
+    let month: u32 = rest.get(..2)?.parse().ok()?;
+    let rest = two_digits(rest, 1, 12)?.strip_prefix('-')?;
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    two_digits(rest, 1, days)
+}
+
+/// Parses `hh:mm:ss` with optional fractional seconds, returning what follows.
+fn clock(s: &str) -> Option<&str> {
+    let rest = two_digits(s, 0, 24)?.strip_prefix(':')?;
+    let rest = two_digits(rest, 0, 59)?.strip_prefix(':')?;
+    let rest = two_digits(rest, 0, 60)?;
+    match rest.strip_prefix('.') {
+        Some(fraction) => {
+            let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            (len > 0).then(|| &fraction[len..])
+        }
+        None => Some(rest),
+    }
+}
+
+/// Whether `s` is empty or a timezone: `Z`, `+hh:mm` or `-hh:mm`.
+fn timezone(s: &str) -> bool {
+    match s.strip_prefix(['+', '-']) {
+        Some(offset) => two_digits(offset, 0, 14).and_then(|rest| rest.strip_prefix(':')).and_then(|rest| two_digits(rest, 0, 59)) == Some(""),
+        None => s.is_empty() || s == "Z",
+    }
+}
+
+/// `PnYnMnDTnHnMnS`, with at least one part.
+fn is_duration(s: &str) -> bool {
+    let Some(rest) = s.strip_prefix('-').unwrap_or(s).strip_prefix('P') else {
+        return false;
+    };
+    let (day, time) = match rest.split_once('T') {
+        Some((_, "")) => return false,
+        Some((day, time)) => (day, time),
+        None => (rest, ""),
+    };
+    let parts = |mut s: &str, units: &[char]| -> Option<usize> {
+        let mut count = 0;
+        let mut units = units.iter();
+        while !s.is_empty() {
+            let len = s.bytes().take_while(|b| b.is_ascii_digit() || *b == b'.').count();
+            let unit = s[len..].chars().next()?;
+            let number = &s[..len];
+            if number.is_empty() || (number.contains('.') && unit != 'S') || !is_decimal(number) {
+                return None;
+            }
+            units.by_ref().find(|&&u| u == unit)?;
+            s = &s[len + 1..];
+            count += 1;
+        }
+        Some(count)
+    };
+    match (parts(day, &['Y', 'M', 'D']), parts(time, &['H', 'M', 'S'])) {
+        (Some(days), Some(times)) => days + times > 0,
+        _ => false,
+    }
+}
+
+/// Compares two numbers, or two other values (dates) as text.
+fn compare(a: &str, b: &str) -> std::cmp::Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+#[derive(Default)]
+struct Facets {
+    enumeration: Vec<String>,
+    /// Any of the patterns may match.
+    patterns: Vec<(String, Regex)>,
+    min_inclusive: Option<String>,
+    max_inclusive: Option<String>,
+    min_exclusive: Option<String>,
+    max_exclusive: Option<String>,
+    length: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    total_digits: Option<usize>,
+    fraction_digits: Option<usize>,
+}
+
+impl Facets {
+    /// Checks `value`, whose length is `len` (characters, or items of a list).
+    fn check(&self, value: &str, len: usize) -> Result<(), String> {
+        use std::cmp::Ordering::{Greater, Less};
+        if !self.enumeration.is_empty() && !self.enumeration.iter().any(|allowed| allowed == value) {
+            let allowed: Vec<String> = self.enumeration.iter().map(|allowed| format!("'{}'", allowed)).collect();
+            return Err(format!("'{}' is not one of {}", value, allowed.join(", ")));
+        }
+        if !self.patterns.is_empty() && !self.patterns.iter().any(|(_, regex)| regex.is_match(value)) {
+            let patterns: Vec<&str> = self.patterns.iter().map(|(pattern, _)| pattern.as_str()).collect();
+            return Err(format!("'{}' does not match {}", value, patterns.join(" or ")));
+        }
+        let bounds = [
+            (&self.min_inclusive, Less, "less than"),
+            (&self.max_inclusive, Greater, "greater than"),
+        ];
+        for (bound, wrong, relation) in bounds {
+            if let Some(bound) = bound
+                && compare(value, bound) == wrong
+            {
+                return Err(format!("'{}' is {} {}", value, relation, bound));
+            }
+        }
+        let bounds = [
+            (&self.min_exclusive, Greater, "greater than"),
+            (&self.max_exclusive, Less, "less than"),
+        ];
+        for (bound, right, relation) in bounds {
+            if let Some(bound) = bound
+                && compare(value, bound) != right
+            {
+                return Err(format!("'{}' is not {} {}", value, relation, bound));
+            }
+        }
+        if self.length.is_some_and(|length| len != length) || self.min_length.is_some_and(|min| len < min) || self.max_length.is_some_and(|max| len > max) {
+            let limits = match (self.length, self.min_length, self.max_length) {
+                (Some(length), _, _) => format!("exactly {}", length),
+                (_, Some(min), Some(max)) => format!("between {} and {}", min, max),
+                (_, Some(min), None) => format!("at least {}", min),
+                (_, None, max) => format!("at most {}", max.unwrap_or(0)),
+            };
+            return Err(format!("'{}' has length {}, expected {}", value, len, limits));
+        }
+        let digits = value.trim_start_matches(['+', '-']);
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let fraction = fraction.trim_end_matches('0');
+        if let Some(total) = self.total_digits
+            && whole.trim_start_matches('0').len() + fraction.len() > total
+        {
+            return Err(format!("'{}' has more than {} digits", value, total));
+        }
+        if let Some(max) = self.fraction_digits
+            && fraction.len() > max
+        {
+            return Err(format!("'{}' has more than {} fraction digits", value, max));
+        }
+        Ok(())
+    }
+}
+
+enum Variety {
+    Builtin(Builtin),
+    /// Values of the base type, further restricted by the facets.
+    Restriction(usize),
+    List(usize),
+    Union(Vec<usize>),
+}
+
+struct Simple {
+    /// The name shown in messages, e.g. `xs:int` or `ColorType`.
+    name: String,
+    variety: Variety,
+    facets: Facets,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TypeRef {
+    Simple(usize),
+    Complex(usize),
+}
+
+/// `xs:anyType`, which allows any attributes and content.
+const ANY_TYPE: usize = 0;
+
+struct ElementDecl {
+    name: String,
+    ty: TypeRef,
+    nillable: bool,
+    fixed: Option<String>,
+}
+
+#[derive(Clone)]
+struct Attribute {
+    name: String,
+    ty: usize,
+    required: bool,
+    fixed: Option<String>,
+    /// Declared `ID` in a DTD, so no two elements may share its value.
+    id: bool,
+}
+
+#[derive(Clone)]
+enum Particle {
+    Element(usize),
+    Any,
+    Sequence(Vec<Particle>),
+    Choice(Vec<Particle>),
+    Repeat(Box<Particle>, u32, Option<u32>),
+}
+
+#[derive(Clone)]
+enum Content {
+    Empty,
+    Simple(usize),
+    Elements(Particle),
+    /// `xs:all`: each member at most once, the required ones at least once.
+    All(Vec<(usize, bool)>),
+    Any,
+}
+
+#[derive(Clone)]
+struct Complex {
+    content: Content,
+    mixed: bool,
+    attributes: Vec<Attribute>,
+    any_attribute: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Label {
+    Element(usize),
+    Any,
+}
+
+/// The content model of a complex type as a nondeterministic automaton over
+/// child elements. State 0 is the start.
+#[derive(Default)]
+struct Automaton {
+    epsilon: Vec<Vec<usize>>,
+    edges: Vec<Vec<(Label, usize)>>,
+    accept: usize,
+}
+
+impl Automaton {
+    fn new(particle: &Particle) -> Self {
+        let mut automaton = Automaton::default();
+        let start = automaton.state();
+        automaton.accept = automaton.build(particle, start);
+        automaton
+    }
+
+    fn state(&mut self) -> usize {
+        self.epsilon.push(Vec::new());
+        self.edges.push(Vec::new());
+        self.epsilon.len() - 1
+    }
+
+    /// Adds the states matching `particle` from state `from`, returning the state it ends in.
+    fn build(&mut self, particle: &Particle, from: usize) -> usize {
+        match particle {
+            Particle::Element(decl) => self.edge(from, Label::Element(*decl)),
+            Particle::Any => self.edge(from, Label::Any),
+            Particle::Sequence(items) => items.iter().fold(from, |at, item| self.build(item, at)),
+            Particle::Choice(items) => {
+                let end = self.state();
+                for item in items {
+                    let start = self.state();
+                    self.epsilon[from].push(start);
+                    let item_end = self.build(item, start);
+                    self.epsilon[item_end].push(end);
+                }
+                end
+            }
+            Particle::Repeat(item, min, max) => {
+                let mut at = from;
+                for _ in 0..*min {
+                    at = self.build(item, at);
+                }
+                match max {
+                    None => {
+                        let again = self.state();
+                        self.epsilon[at].push(again);
+                        let item_end = self.build(item, again);
+                        self.epsilon[item_end].push(again);
+                        let end = self.state();
+                        self.epsilon[again].push(end);
+                        end
+                    }
+                    Some(max) => {
+                        for _ in *min..*max {
+                            let item_end = self.build(item, at);
+                            let end = self.state();
+                            self.epsilon[at].push(end);
+                            self.epsilon[item_end].push(end);
+                            at = end;
+                        }
+                        at
+                    }
+                }
+            }
+        }
+    }
+
+    fn edge(&mut self, from: usize, label: Label) -> usize {
+        let to = self.state();
+        self.edges[from].push((label, to));
+        to
+    }
+
+    /// Adds the states reachable without consuming an element.
+    fn close(&self, states: &mut Vec<usize>) {
+        let mut i = 0;
+        while i < states.len() {
+            for &next in &self.epsilon[states[i]] {
+                if !states.contains(&next) {
+                    states.push(next);
+                }
+            }
+            i += 1;
+        }
+    }
+
+    fn start(&self) -> Vec<usize> {
+        let mut states = vec![0];
+        self.close(&mut states);
+        states
+    }
+
+    /// The states after a child matching `matches` in `states`, and the label
+    /// it matched, preferring a declared element over a wildcard.
+    fn step(&self, states: &[usize], matches: impl Fn(Label) -> bool) -> Option<(Vec<usize>, Label)> {
+        let mut next = Vec::new();
+        let mut found = None;
+        for &state in states {
+            for &(label, to) in &self.edges[state] {
+                if matches(label) {
+                    if !next.contains(&to) {
+                        next.push(to);
+                    }
+                    if !matches!(found, Some(Label::Element(_))) {
+                        found = Some(label);
+                    }
+                }
+            }
+        }
+        let label = found?;
+        self.close(&mut next);
+        Some((next, label))
+    }
+}
+
+/// A compiled schema.
+pub struct Xsd {
+    simple: Vec<Simple>,
+    complex: Vec<Complex>,
+    automata: Vec<Option<Automaton>>,
+    decls: Vec<ElementDecl>,
+    globals: HashMap<String, usize>,
+    /// The name the root element must have, for a DTD.
+    root: Option<String>,
+    /// Parts of the schema that were left out, e.g. unsupported patterns.
+    pub warnings: Vec<String>,
+}
+
+/// Compiles the schema trees into `Xsd`, resolving references on demand.
+struct Compiler<'d> {
+    documents: &'d Documents,
+    /// Top-level definitions by kind and name.
+    definitions: HashMap<(&'d str, &'d str), usize>,
+    types: HashMap<&'d str, TypeRef>,
+    builtins: HashMap<&'d str, usize>,
+    xsd: Xsd,
+}
+
+impl<'d> Compiler<'d> {
+    fn node(&self, id: usize) -> &'d Node {
+        &self.documents.nodes[id]
+    }
+
+    fn is_builtin(&self, node: &Node, qname: &str) -> bool {
+        self.documents.xsd_prefixes[node.file].iter().any(|p| p == prefix(qname))
+    }
+
+    fn definition(&self, kind: &'static str, qname: &str) -> Result<&'d Node, String> {
+        match self.definitions.get(&(kind, local(qname))) {
+            Some(&id) => Ok(self.node(id)),
+            None => {
+                let kind = if kind == "attributeGroup" { "attribute group" } else { kind };
+                Err(format!("{} '{}' is not defined", kind, qname))
+            }
+        }
+    }
+
+    fn children(&self, node: &'d Node) -> impl Iterator<Item = &'d Node> + use<'d> {
+        let nodes = &self.documents.nodes;
+        node.children.iter().map(move |&id| &nodes[id]).filter(|child| child.name != "annotation")
+    }
+
+    fn builtin(&mut self, name: &'d str) -> Result<usize, String> {
+        if let Some(&id) = self.builtins.get(name) {
+            return Ok(id);
+        }
+        let variety = match name {
+            "NMTOKENS" => Variety::List(self.builtin("NMTOKEN")?),
+            "IDREFS" => Variety::List(self.builtin("IDREF")?),
+            "ENTITIES" => Variety::List(self.builtin("ENTITY")?),
+            name => Variety::Builtin(Builtin::named(name).ok_or_else(|| format!("built-in type xs:{} is not supported", name))?),
+        };
+        let id = self.push_simple(format!("xs:{}", name), variety, Facets::default());
+        self.builtins.insert(name, id);
+        Ok(id)
+    }
+
+    fn push_simple(&mut self, name: String, variety: Variety, facets: Facets) -> usize {
+        self.xsd.simple.push(Simple { name, variety, facets });
+        self.xsd.simple.len() - 1
+    }
+
+    /// The type `qname` refers to from `node`.
+    fn type_named(&mut self, node: &'d Node, qname: &'d str) -> Result<TypeRef, String> {
+        if self.is_builtin(node, qname) {
+            return match local(qname) {
+                "anyType" => Ok(TypeRef::Complex(ANY_TYPE)),
+                name => Ok(TypeRef::Simple(self.builtin(name)?)),
+            };
+        }
+        let name = local(qname);
+        if let Some(&ty) = self.types.get(name) {
+            return Ok(ty);
+        }
+        let definition = self.definition("type", qname)?;
+        if definition.name == "complexType" {
+            // Registered before compiling, for types that contain themselves.
+            let id = self.xsd.complex.len();
+            self.xsd.complex.push(empty_complex());
+            self.types.insert(name, TypeRef::Complex(id));
+            self.xsd.complex[id] = self.complex(definition)?;
+            Ok(TypeRef::Complex(id))
+        } else {
+            let id = self.simple(definition, name.to_string())?;
+            self.types.insert(name, TypeRef::Simple(id));
+            Ok(TypeRef::Simple(id))
+        }
+    }
+
+    fn simple_named(&mut self, node: &'d Node, qname: &'d str) -> Result<usize, String> {
+        match self.type_named(node, qname)? {
+            TypeRef::Simple(id) => Ok(id),
+            TypeRef::Complex(_) => Err(format!("'{}' is not a simple type", qname)),
+        }
+    }
+
+    /// Compiles an `xs:simpleType`.
+    fn simple(&mut self, node: &'d Node, name: String) -> Result<usize, String> {
+        let Some(body) = self.children(node).next() else {
+            return Err(format!("simple type '{}' has no definition", name));
+        };
+        match body.name.as_str() {
+            "restriction" => {
+                let base = match body.attr("base") {
+                    Some(base) => self.simple_named(body, base)?,
+                    None => match self.children(body).find(|child| child.name == "simpleType") {
+                        Some(inline) => self.simple(inline, name.clone())?,
+                        None => return Err(format!("restriction in '{}' has no base", name)),
+                    },
+                };
+                let facets = self.facets(body, &name)?;
+                Ok(self.push_simple(name, Variety::Restriction(base), facets))
+            }
+            "list" => {
+                let item = match (body.attr("itemType"), self.children(body).find(|child| child.name == "simpleType")) {
+                    (Some(item), _) => self.simple_named(body, item)?,
+                    (None, Some(inline)) => self.simple(inline, format!("item of {}", name))?,
+                    (None, None) => return Err(format!("list '{}' has no item type", name)),
+                };
+                Ok(self.push_simple(name, Variety::List(item), Facets::default()))
+            }
+            "union" => {
+                let mut members = Vec::new();
+                for member in body.attr("memberTypes").unwrap_or("").split_whitespace() {
+                    members.push(self.simple_named(body, member)?);
+                }
+                for inline in self.children(body).filter(|child| child.name == "simpleType") {
+                    members.push(self.simple(inline, format!("member of {}", name))?);
+                }
+                Ok(self.push_simple(name, Variety::Union(members), Facets::default()))
+            }
+            other => Err(format!("xs:{} in simple type '{}' is not supported", other, name)),
+        }
+    }
+
+    fn facets(&mut self, restriction: &'d Node, name: &str) -> Result<Facets, String> {
+        let mut facets = Facets::default();
+        for facet in self.children(restriction) {
+            let value = || facet.attr("value").map(str::to_string).ok_or_else(|| format!("xs:{} in '{}' has no value", facet.name, name));
+            let count = || value()?.parse::<usize>().map_err(|_| format!("xs:{} in '{}' is not a number", facet.name, name));
+            match facet.name.as_str() {
+                "enumeration" => facets.enumeration.push(value()?),
+                "pattern" => match pattern(&value()?) {
+                    Ok(regex) => facets.patterns.push((value()?, regex)),
+                    Err(err) => self.xsd.warnings.push(format!("pattern in '{}' is not checked: {}", name, err)),
+                },
+                "minInclusive" => facets.min_inclusive = Some(value()?),
+                "maxInclusive" => facets.max_inclusive = Some(value()?),
+                "minExclusive" => facets.min_exclusive = Some(value()?),
+                "maxExclusive" => facets.max_exclusive = Some(value()?),
+                "length" => facets.length = Some(count()?),
+                "minLength" => facets.min_length = Some(count()?),
+                "maxLength" => facets.max_length = Some(count()?),
+                "totalDigits" => facets.total_digits = Some(count()?),
+                "fractionDigits" => facets.fraction_digits = Some(count()?),
+                // Attributes of simple content are restricted alongside.
+                "whiteSpace" | "simpleType" | "attribute" | "attributeGroup" | "anyAttribute" => {}
+                other => return Err(format!("facet xs:{} in '{}' is not supported", other, name)),
+            }
+        }
+        Ok(facets)
+    }
+
+    /// Compiles an `xs:complexType`.
+    fn complex(&mut self, node: &'d Node) -> Result<Complex, String> {
+        let mut complex = empty_complex();
+        complex.mixed = node.attr("mixed") == Some("true");
+        for child in self.children(node) {
+            match child.name.as_str() {
+                "simpleContent" | "complexContent" => {
+                    let derivation = self.children(child).next().ok_or("empty content derivation")?;
+                    let base = derivation.attr("base").ok_or("content derivation without a base")?;
+                    let extension = derivation.name == "extension";
+                    match self.type_named(derivation, base)? {
+                        TypeRef::Simple(base) => complex.content = Content::Simple(base),
+                        TypeRef::Complex(base) => {
+                            let base = self.xsd.complex[base].clone();
+                            complex.attributes = base.attributes;
+                            complex.any_attribute = base.any_attribute;
+                            if child.name == "simpleContent" || extension {
+                                complex.content = base.content;
+                            }
+                            complex.mixed |= base.mixed && extension;
+                        }
+                    }
+                    if child.name == "complexContent" && child.attr("mixed") == Some("true") {
+                        complex.mixed = true;
+                    }
+                    if child.name == "simpleContent" && !extension {
+                        let Content::Simple(base) = complex.content else {
+                            return Err(format!("simple content restricts '{}', which has no simple content", base));
+                        };
+                        let name = self.xsd.simple[base].name.clone();
+                        let facets = self.facets(derivation, &name)?;
+                        complex.content = Content::Simple(self.push_simple(name, Variety::Restriction(base), facets));
+                    }
+                    self.members(derivation, &mut complex)?;
+                }
+                _ => self.member(child, &mut complex)?,
+            }
+        }
+        Ok(complex)
+    }
+
+    /// Adds the particles and attributes declared under `node` to `complex`.
+    fn members(&mut self, node: &'d Node, complex: &mut Complex) -> Result<(), String> {
+        for child in self.children(node) {
+            self.member(child, complex)?;
+        }
+        Ok(())
+    }
+
+    fn member(&mut self, node: &'d Node, complex: &mut Complex) -> Result<(), String> {
+        match node.name.as_str() {
+            "sequence" | "choice" | "group" => {
+                let particle = self.particle(node)?;
+                complex.content = match std::mem::replace(&mut complex.content, Content::Empty) {
+                    Content::Elements(base) => Content::Elements(Particle::Sequence(vec![base, particle])),
+                    _ => Content::Elements(particle),
+                };
+            }
+            "all" => {
+                let optional = node.attr("minOccurs") == Some("0");
+                let mut members = Vec::new();
+                for element in self.children(node) {
+                    if element.name != "element" {
+                        return Err(format!("xs:{} is not allowed in xs:all", element.name));
+                    }
+                    members.push((self.element(element)?, !optional && element.attr("minOccurs") != Some("0")));
+                }
+                complex.content = Content::All(members);
+            }
+            "attribute" => {
+                let attribute = self.attribute(node)?;
+                complex.attributes.retain(|existing| existing.name != attribute.name);
+                if node.attr("use") != Some("prohibited") {
+                    complex.attributes.push(attribute);
+                }
+            }
+            "attributeGroup" => {
+                let group = self.definition("attributeGroup", node.attr("ref").ok_or("attribute group without a ref")?)?;
+                self.members(group, complex)?;
+            }
+            "anyAttribute" => complex.any_attribute = true,
+            // Identity constraints and assertions are not checked.
+            "key" | "keyref" | "unique" | "assert" => {}
+            other => return Err(format!("xs:{} in a complex type is not supported", other)),
+        }
+        Ok(())
+    }
+
+    fn attribute(&mut self, node: &'d Node) -> Result<Attribute, String> {
+        let required = node.attr("use") == Some("required");
+        let fixed = node.attr("fixed").map(str::to_string);
+        let (declaration, name) = match node.attr("ref") {
+            // The attributes of the xml: namespace, declared in a schema rarely at hand.
+            Some(qname) if prefix(qname) == "xml" => return Ok(Attribute { name: local(qname).to_string(), ty: self.builtin("string")?, required, fixed, id: false }),
+            Some(qname) => (self.definition("attribute", qname)?, local(qname)),
+            None => (node, node.attr("name").ok_or("attribute without a name")?),
+        };
+        let ty = match (declaration.attr("type"), self.children(declaration).find(|child| child.name == "simpleType")) {
+            (Some(ty), _) => self.simple_named(declaration, ty)?,
+            (None, Some(inline)) => self.simple(inline, format!("@{}", name))?,
+            (None, None) => self.builtin("anySimpleType")?,
+        };
+        Ok(Attribute {
+            name: name.to_string(),
+            ty,
+            required,
+            fixed: fixed.or_else(|| declaration.attr("fixed").map(str::to_string)),
+            id: false,
+        })
+    }
+
+    fn particle(&mut self, node: &'d Node) -> Result<Particle, String> {
+        let particle = match node.name.as_str() {
+            "element" => Particle::Element(self.element(node)?),
+            "any" => Particle::Any,
+            "sequence" | "choice" => {
+                let mut items = Vec::new();
+                for child in self.children(node) {
+                    items.push(self.particle(child)?);
+                }
+                if node.name == "sequence" { Particle::Sequence(items) } else { Particle::Choice(items) }
+            }
+            "group" => {
+                let group = self.definition("group", node.attr("ref").ok_or("group without a ref")?)?;
+                let model = self.children(group).next().ok_or_else(|| format!("group '{}' is empty", group.attr("name").unwrap_or("")))?;
+                if model.name == "all" {
+                    return Err("groups of xs:all are not supported".to_string());
+                }
+                self.particle(model)?
+            }
+            other => return Err(format!("xs:{} in a content model is not supported", other)),
+        };
+        let occurs = |name: &str, default: u32| -> Result<Option<u32>, String> {
+            match node.attr(name) {
+                None => Ok(Some(default)),
+                Some("unbounded") => Ok(None),
+                Some(value) => value.parse::<u32>().map(|n| (n <= MAX_OCCURS).then_some(n)).map_err(|_| format!("invalid {} '{}'", name, value)),
+            }
+        };
+        let min = occurs("minOccurs", 1)?.unwrap_or(MAX_OCCURS);
+        let max = occurs("maxOccurs", 1)?;
+        Ok(match (min, max) {
+            (1, Some(1)) => particle,
+            (min, max) => Particle::Repeat(Box::new(particle), min, max),
+        })
+    }
+
+    /// Compiles an `xs:element` declaration or reference.
+    fn element(&mut self, node: &'d Node) -> Result<usize, String> {
+        if let Some(qname) = node.attr("ref") {
+            return self.global(local(qname));
+        }
+        self.declare(node, false)
+    }
+
+    fn global(&mut self, name: &str) -> Result<usize, String> {
+        if let Some(&id) = self.xsd.globals.get(name) {
+            return Ok(id);
+        }
+        let node = self.definition("element", name)?;
+        self.declare(node, true)
+    }
+
+    fn declare(&mut self, node: &'d Node, global: bool) -> Result<usize, String> {
+        let name = node.attr("name").ok_or("element without a name")?;
+        let id = self.xsd.decls.len();
+        self.xsd.decls.push(ElementDecl {
+            name: name.to_string(),
+            ty: TypeRef::Complex(ANY_TYPE),
+            nillable: node.attr("nillable") == Some("true"),
+            fixed: node.attr("fixed").map(str::to_string),
+        });
+        if global {
+            // Registered before compiling, for elements that contain themselves.
+            self.xsd.globals.insert(name.to_string(), id);
+        }
+        let inline = self.children(node).find(|child| matches!(child.name.as_str(), "complexType" | "simpleType"));
+        self.xsd.decls[id].ty = match (node.attr("type"), inline) {
+            (Some(ty), _) => self.type_named(node, ty)?,
+            (None, Some(inline)) if inline.name == "simpleType" => TypeRef::Simple(self.simple(inline, name.to_string())?),
+            (None, Some(inline)) => {
+                let id = self.xsd.complex.len();
+                self.xsd.complex.push(empty_complex());
+                self.xsd.complex[id] = self.complex(inline)?;
+                TypeRef::Complex(id)
+            }
+            (None, None) => TypeRef::Complex(ANY_TYPE),
+        };
+        Ok(id)
+    }
+}
+
+fn empty_complex() -> Complex {
+    Complex {
+        content: Content::Empty,
+        mixed: false,
+        attributes: Vec::new(),
+        any_attribute: false,
+    }
+}
+
+/// The particle for a DTD content model, collecting names without a declaration.
+fn dtd_particle(model: &Model, globals: &HashMap<String, usize>, undeclared: &mut Vec<String>) -> Particle {
+    match model {
+        Model::Name(name) => match globals.get(local(name)) {
+            Some(&decl) => Particle::Element(decl),
+            None => {
+                undeclared.push(name.clone());
+                Particle::Choice(Vec::new())
+            }
+        },
+        Model::Sequence(items) => Particle::Sequence(items.iter().map(|item| dtd_particle(item, globals, undeclared)).collect()),
+        Model::Choice(items) => Particle::Choice(items.iter().map(|item| dtd_particle(item, globals, undeclared)).collect()),
+        Model::Repeat(item, kind) => {
+            let (min, max) = match kind {
+                '?' => (0, Some(1)),
+                '+' => (1, None),
+                _ => (0, None),
+            };
+            Particle::Repeat(Box::new(dtd_particle(item, globals, undeclared)), min, max)
+        }
+    }
+}
+
+/// Compiles an XSD pattern, which must match the whole value and has no anchors.
+fn pattern(pattern: &str) -> Result<Regex, String> {
+    let mut translated = String::from("^(?:");
+    let mut chars = pattern.chars();
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().ok_or("trailing '\\'")?;
+                if matches!(escaped, 'p' | 'P' | 'i' | 'I' | 'c' | 'C') {
+                    return Err(format!("\\{} is not supported", escaped));
+                }
+                translated.push('\\');
+                translated.push(escaped);
+                continue;
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '^' | '$' if !in_class => translated.push('\\'),
+            _ => {}
+        }
+        translated.push(c);
+    }
+    translated.push_str(")$");
+    Regex::new(&translated)
+}
+
+impl Xsd {
+    /// Reads the schema at `path`, with the schemas it includes or imports
+    /// from local files.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut documents = Documents::default();
+        documents.read(path)?;
+        Self::compile(&documents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Compiles a schema given as text; it may not include other schemas.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut documents = Documents::default();
+        documents.add(text)?;
+        Self::compile(&documents)
+    }
+
+    fn compile(documents: &Documents) -> Result<Self, String> {
+        let mut definitions = HashMap::new();
+        for &root in &documents.roots {
+            for &id in &documents.nodes[root].children {
+                let node = &documents.nodes[id];
+                let kind = match node.name.as_str() {
+                    "complexType" | "simpleType" => "type",
+                    "element" | "attribute" | "group" | "attributeGroup" => node.name.as_str(),
+                    _ => continue,
+                };
+                if let Some(name) = node.attr("name") {
+                    definitions.entry((kind, name)).or_insert(id);
+                }
+            }
+        }
+        let mut compiler = Compiler {
+            documents,
+            definitions,
+            types: HashMap::new(),
+            builtins: HashMap::new(),
+            xsd: Xsd {
+                simple: Vec::new(),
+                complex: vec![Complex {
+                    content: Content::Any,
+                    mixed: true,
+                    attributes: Vec::new(),
+                    any_attribute: true,
+                }],
+                automata: Vec::new(),
+                decls: Vec::new(),
+                globals: HashMap::new(),
+                root: None,
+                warnings: Vec::new(),
+            },
+        };
+        let mut globals: Vec<&str> = compiler.definitions.keys().filter(|(kind, _)| *kind == "element").map(|&(_, name)| name).collect();
+        globals.sort_by_key(|name| compiler.definitions[&("element", *name)]);
+        if globals.is_empty() {
+            return Err("the schema declares no global element".to_string());
+        }
+        for name in globals {
+            compiler.global(name)?;
+        }
+        Ok(compiler.xsd.finish())
+    }
+
+    /// Builds the automata of the content models once all types are known.
+    fn finish(mut self) -> Self {
+        self.automata = self
+            .complex
+            .iter()
+            .map(|complex| match &complex.content {
+                Content::Elements(particle) => Some(Automaton::new(particle)),
+                _ => None,
+            })
+            .collect();
+        self
+    }
+
+    /// The schema a DTD describes: every element type declared in it is
+    /// global, and the root element must be the one the DOCTYPE names.
+    pub fn from_dtd(dtd: &Dtd) -> Self {
+        let mut xsd = Xsd {
+            simple: Vec::new(),
+            complex: Vec::new(),
+            automata: Vec::new(),
+            decls: Vec::new(),
+            globals: HashMap::new(),
+            root: Some(local(&dtd.root).to_string()),
+            warnings: Vec::new(),
+        };
+        let mut simple = |name: &str, variety: Variety, enumeration: Vec<String>| {
+            xsd.simple.push(Simple {
+                name: name.to_string(),
+                variety,
+                facets: Facets { enumeration, ..Facets::default() },
+            });
+            xsd.simple.len() - 1
+        };
+        let cdata = simple("CDATA", Variety::Builtin(Builtin::String), Vec::new());
+        let id = simple("ID", Variety::Builtin(Builtin::NcName), Vec::new());
+        let ids = simple("IDREFS", Variety::List(id), Vec::new());
+        let nmtoken = simple("NMTOKEN", Variety::Builtin(Builtin::NmToken), Vec::new());
+        let nmtokens = simple("NMTOKENS", Variety::List(nmtoken), Vec::new());
+        let mut attribute_types = Vec::new();
+        for def in &dtd.attributes {
+            attribute_types.push(match &def.kind {
+                AttributeType::Cdata => cdata,
+                AttributeType::Id | AttributeType::IdRef | AttributeType::Entity => id,
+                AttributeType::IdRefs | AttributeType::Entities => ids,
+                AttributeType::NmToken => nmtoken,
+                AttributeType::NmTokens => nmtokens,
+                AttributeType::Enumeration(values) => simple("enumeration", Variety::Builtin(Builtin::Token), values.clone()),
+            });
+        }
+
+        for (name, _) in &dtd.elements {
+            xsd.globals.insert(local(name).to_string(), xsd.decls.len());
+            xsd.decls.push(ElementDecl {
+                name: local(name).to_string(),
+                ty: TypeRef::Complex(0),
+                nillable: false,
+                fixed: None,
+            });
+        }
+        for (i, (name, spec)) in dtd.elements.iter().enumerate() {
+            let mut undeclared = Vec::new();
+            let mut particle = |model: &Model| dtd_particle(model, &xsd.globals, &mut undeclared);
+            let (content, mixed) = match spec {
+                ContentSpec::Empty => (Content::Empty, false),
+                ContentSpec::Any => (Content::Any, true),
+                ContentSpec::Mixed(names) if names.is_empty() => (Content::Simple(cdata), false),
+                ContentSpec::Mixed(names) => {
+                    let choice = Model::Choice(names.iter().map(|name| Model::Name(name.clone())).collect());
+                    (Content::Elements(particle(&Model::Repeat(Box::new(choice), '*'))), true)
+                }
+                ContentSpec::Children(model) => (Content::Elements(particle(model)), false),
+            };
+            for child in undeclared {
+                xsd.warnings.push(format!("<{}> may contain <{}>, which is not declared", name, child));
+            }
+            let attributes = dtd
+                .attributes
+                .iter()
+                .zip(&attribute_types)
+                .filter(|(def, _)| def.element == *name)
+                .map(|(def, &ty)| Attribute {
+                    name: local(&def.name).to_string(),
+                    ty,
+                    required: def.default == AttributeDefault::Required,
+                    fixed: match &def.default {
+                        AttributeDefault::Fixed(value) => Some(value.clone()),
+                        _ => None,
+                    },
+                    id: def.kind == AttributeType::Id,
+                })
+                .collect();
+            xsd.decls[i].ty = TypeRef::Complex(xsd.complex.len());
+            xsd.complex.push(Complex {
+                content,
+                mixed,
+                attributes,
+                any_attribute: false,
+            });
+        }
+        xsd.finish()
+    }
+
+    /// Checks `value` against simple type `id`.
+    fn check_value(&self, id: usize, value: &str) -> Result<(), String> {
+        let simple = &self.simple[id];
+        let value = match self.whitespace(id) {
+            Builtin::String => std::borrow::Cow::Borrowed(value),
+            Builtin::NormalizedString => value.replace(['\t', '\n', '\r'], " ").into(),
+            _ => value.split_whitespace().collect::<Vec<_>>().join(" ").into(),
+        };
+        let mut len = value.chars().count();
+        match &simple.variety {
+            Variety::Builtin(builtin) => {
+                if !builtin.is_valid(&value) {
+                    return Err(format!("'{}' is not a valid {}", value, simple.name));
+                }
+            }
+            Variety::Restriction(base) => {
+                self.check_value(*base, &value)?;
+                if self.is_list(*base) {
+                    len = value.split_whitespace().count();
+                }
+            }
+            Variety::List(item) => {
+                for item_value in value.split_whitespace() {
+                    self.check_value(*item, item_value)?;
+                }
+            }
+            Variety::Union(members) => {
+                if !members.iter().any(|&member| self.check_value(member, &value).is_ok()) {
+                    return Err(format!("'{}' is not a valid {}", value, simple.name));
+                }
+            }
+        }
+        simple.facets.check(&value, len)
+    }
+
+    /// The built-in type whose whitespace handling `id` inherits.
+    fn whitespace(&self, id: usize) -> Builtin {
+        match &self.simple[id].variety {
+            Variety::Builtin(builtin) => *builtin,
+            Variety::Restriction(base) => self.whitespace(*base),
+            Variety::List(_) | Variety::Union(_) => Builtin::Token,
+        }
+    }
+
+    fn is_list(&self, id: usize) -> bool {
+        match &self.simple[id].variety {
+            Variety::List(_) => true,
+            Variety::Restriction(base) => self.is_list(*base),
+            _ => false,
+        }
+    }
+
+    /// Describes the children allowed in `states`, e.g. `<a>, <b> or the end of <list>`.
+    fn expected(&self, automaton: &Automaton, states: &[usize], parent: &str) -> String {
+        let mut names: Vec<String> = Vec::new();
+        for &state in states {
+            for &(label, _) in &automaton.edges[state] {
+                let name = match label {
+                    Label::Element(decl) => format!("<{}>", self.decls[decl].name),
+                    Label::Any => "any element".to_string(),
+                };
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        if states.contains(&automaton.accept) {
+            names.push(format!("the end of <{}>", parent));
+        }
+        match names.split_last() {
+            None => "nothing".to_string(),
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        }
+    }
+
+    /// Validates `xml`, returning the problems in document order.
+    pub fn validate(&self, xml: &str) -> Vec<Diagnostic> {
+        self.run(xml, false)
+    }
+
+    /// Validates one record of a larger document, an element on its own.
+    /// It is checked against the global declaration of its name, or else
+    /// the first local one, as records are often declared inside their
+    /// container. Offsets are within `record`.
+    pub fn validate_record(&self, record: &str) -> Vec<Diagnostic> {
+        self.run(record, true)
+    }
+
+    fn run(&self, xml: &str, record: bool) -> Vec<Diagnostic> {
+        let mut validator = Validator {
+            xsd: self,
+            xml,
+            record,
+            diagnostics: Vec::new(),
+            open: Vec::new(),
+            xsi_prefixes: Vec::new(),
+            ids: HashSet::new(),
+        };
+        stream_xml_spans(xml, |token, span| {
+            match token {
+                Token::StartTag(name, attrs) => validator.start(name, attrs, span.start, span.end),
+                Token::EndTag(_) => validator.end(span.start),
+                Token::Text(_) | Token::CData(_) => validator.text(span.start),
+            }
+            Continue(())
+        });
+        let mut diagnostics = validator.diagnostics;
+        diagnostics.sort_by_key(|d| d.offset);
+        locate(xml, &mut diagnostics);
+        diagnostics
+    }
+}
+
+/// An open element of the document being validated.
+struct Frame<'a> {
+    name: &'a str,
+    /// Its declaration; `None` when it is not declared and its content is not checked.
+    decl: Option<usize>,
+    /// Automaton states after the children so far.
+    states: Vec<usize>,
+    /// Occurrences of each member of an `xs:all`.
+    seen: Vec<u32>,
+    content_start: usize,
+    has_children: bool,
+    has_text: bool,
+    nil: bool,
+}
+
+struct Validator<'x, 'a> {
+    xsd: &'x Xsd,
+    xml: &'a str,
+    /// Whether the outermost element is a record rather than the root.
+    record: bool,
+    diagnostics: Vec<Diagnostic>,
+    open: Vec<Frame<'a>>,
+    /// Prefixes bound to the schema instance namespace, with the depth of the
+    /// element binding them.
+    xsi_prefixes: Vec<(usize, &'a str)>,
+    /// Values of the `ID` attributes seen so far.
+    ids: HashSet<String>,
+}
+
+impl<'a> Validator<'_, 'a> {
+    fn report(&mut self, code: &'static str, offset: usize, message: String) {
+        let mut path = String::new();
+        for frame in &self.open {
+            path.push('/');
+            path.push_str(frame.name);
+        }
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            code,
+            offset,
+            line: 0,
+            column: 0,
+            path,
+            message,
+        });
+    }
+
+    fn start(&mut self, name: &'a str, attrs: &'a str, offset: usize, content_start: usize) {
+        let local = local(name);
+        let decl = if self.open.is_empty() && self.record {
+            let decl = self.xsd.globals.get(local).copied().or_else(|| self.xsd.decls.iter().position(|decl| decl.name == local));
+            if decl.is_none() {
+                self.report("undeclared-element", offset, format!("the schema declares no element <{}>", local));
+            }
+            decl
+        } else if self.open.is_empty() {
+            let decl = self.xsd.globals.get(local).copied();
+            if let Some(root) = self.xsd.root.as_deref().filter(|&root| root != local) {
+                self.report("wrong-root", offset, format!("the DOCTYPE declares <{}> as the root element, not <{}>", root, local));
+            } else if decl.is_none() {
+                self.report("undeclared-element", offset, format!("the schema declares no global element <{}>", local));
+            }
+            decl
+        } else {
+            self.child(local, offset)
+        };
+        let mut frame = Frame {
+            name,
+            decl,
+            states: Vec::new(),
+            seen: Vec::new(),
+            content_start,
+            has_children: false,
+            has_text: false,
+            nil: false,
+        };
+        if let Some(TypeRef::Complex(id)) = decl.map(|decl| self.xsd.decls[decl].ty) {
+            match (&self.xsd.automata[id], &self.xsd.complex[id].content) {
+                (Some(automaton), _) => frame.states = automaton.start(),
+                (None, Content::All(members)) => frame.seen = vec![0; members.len()],
+                _ => {}
+            }
+        }
+        self.open.push(frame);
+        for (name, value) in attribute_pairs(attrs) {
+            if let Some(prefix) = name.strip_prefix("xmlns:")
+                && value == XSI_NAMESPACE
+            {
+                self.xsi_prefixes.push((self.open.len(), prefix));
+            }
+        }
+        if let Some(decl) = decl {
+            self.attributes(decl, attrs, offset);
+        }
+    }
+
+    /// Finds the declaration of child `name` of the innermost open element,
+    /// reporting it if the content model does not allow it there.
+    fn child(&mut self, name: &str, offset: usize) -> Option<usize> {
+        let xsd = self.xsd;
+        let parent = self.open.last_mut()?;
+        let parent_decl = parent.decl?;
+        parent.has_children = true;
+        let parent_name = parent.name;
+        let ty = match xsd.decls[parent_decl].ty {
+            TypeRef::Complex(id) => id,
+            TypeRef::Simple(_) => {
+                self.report("unexpected-element", offset, format!("<{}> is not allowed: <{}> has a simple type", name, parent_name));
+                return None;
+            }
+        };
+        let problem = match (&xsd.automata[ty], &xsd.complex[ty].content) {
+            (Some(automaton), _) => match automaton.step(&parent.states, |label| match label {
+                Label::Element(decl) => xsd.decls[decl].name == name,
+                Label::Any => true,
+            }) {
+                Some((states, label)) => {
+                    parent.states = states;
+                    return match label {
+                        Label::Element(decl) => Some(decl),
+                        Label::Any => xsd.globals.get(name).copied(),
+                    };
+                }
+                None => format!("<{}> is not allowed here; expected {}", name, xsd.expected(automaton, &parent.states, parent_name)),
+            },
+            (None, Content::All(members)) => match members.iter().position(|&(decl, _)| xsd.decls[decl].name == name) {
+                Some(i) => {
+                    parent.seen[i] += 1;
+                    if parent.seen[i] == 1 {
+                        return Some(members[i].0);
+                    }
+                    format!("<{}> appears more than once in <{}>", name, parent_name)
+                }
+                None => format!("<{}> is not allowed in <{}>", name, parent_name),
+            },
+            (None, Content::Any) => return xsd.globals.get(name).copied(),
+            (None, _) => format!("<{}> is not allowed: <{}> has no child elements", name, parent_name),
+        };
+        self.report("unexpected-element", offset, problem);
+        None
+    }
+
+    fn attributes(&mut self, decl: usize, attrs: &'a str, offset: usize) {
+        let xsd = self.xsd;
+        let element = &xsd.decls[decl];
+        let (declared, any): (&[Attribute], bool) = match element.ty {
+            TypeRef::Complex(id) => (&xsd.complex[id].attributes, xsd.complex[id].any_attribute),
+            TypeRef::Simple(_) => (&[], false),
+        };
+        let mut present = Vec::new();
+        for (name, raw) in attribute_pairs(attrs) {
+            if name == "xmlns" || name.starts_with("xmlns:") {
+                continue;
+            }
+            let value = decode_entities(raw);
+            if self.xsi_prefixes.iter().any(|&(_, xsi)| xsi == prefix(name)) {
+                if local(name) == "nil" && value == "true" {
+                    if !element.nillable {
+                        self.report("not-nillable", offset, format!("<{}> is not nillable", element.name));
+                    }
+                    if let Some(frame) = self.open.last_mut() {
+                        frame.nil = true;
+                    }
+                }
+                continue;
+            }
+            match declared.iter().find(|attribute| attribute.name == local(name)) {
+                Some(attribute) => {
+                    present.push(local(name));
+                    if let Err(err) = xsd.check_value(attribute.ty, &value) {
+                        self.report("invalid-value", offset, format!("attribute '{}': {}", name, err));
+                    } else if let Some(fixed) = &attribute.fixed
+                        && value != fixed.as_str()
+                    {
+                        self.report("invalid-value", offset, format!("attribute '{}' must be '{}'", name, fixed));
+                    } else if attribute.id && !self.ids.insert(value.trim().to_string()) {
+                        self.report("duplicate-id", offset, format!("ID '{}' is already defined", value.trim()));
+                    }
+                }
+                None if any => {}
+                None => self.report("undeclared-attribute", offset, format!("<{}> has no attribute '{}' in the schema", element.name, name)),
+            }
+        }
+        for attribute in declared {
+            if attribute.required && !present.contains(&attribute.name.as_str()) {
+                self.report("missing-attribute", offset, format!("<{}> lacks required attribute '{}'", element.name, attribute.name));
+            }
+        }
+    }
+
+    fn text(&mut self, offset: usize) {
+        let xsd = self.xsd;
+        let Some(frame) = self.open.last_mut() else {
+            return;
+        };
+        let Some(decl) = frame.decl.filter(|_| !frame.has_text) else {
+            return;
+        };
+        frame.has_text = true;
+        let name = frame.name;
+        if let TypeRef::Complex(id) = xsd.decls[decl].ty {
+            let complex = &xsd.complex[id];
+            match complex.content {
+                Content::Empty if !complex.mixed => self.report("text-not-allowed", offset, format!("<{}> must be empty", name)),
+                Content::Simple(_) | Content::Any => {}
+                _ if !complex.mixed => self.report("text-not-allowed", offset, format!("<{}> may only contain elements", name)),
+                _ => {}
+            }
+        }
+    }
+
+    fn end(&mut self, offset: usize) {
+        if let Some((code, at, message)) = self.content_problem(offset) {
+            self.report(code, at, message);
+        }
+        self.open.pop();
+        let depth = self.open.len();
+        self.xsi_prefixes.retain(|&(bound, _)| bound <= depth);
+    }
+
+    /// What is wrong with the content of the innermost open element, which
+    /// ends at `offset`.
+    fn content_problem(&self, offset: usize) -> Option<(&'static str, usize, String)> {
+        let xsd = self.xsd;
+        let frame = self.open.last()?;
+        let element = &xsd.decls[frame.decl?];
+        if frame.nil {
+            return (frame.has_children || frame.has_text).then(|| ("invalid-value", offset, format!("<{}> is nil but not empty", element.name)));
+        }
+        let (complex, simple) = match element.ty {
+            TypeRef::Simple(id) => (None, Some(id)),
+            TypeRef::Complex(id) => match &xsd.complex[id].content {
+                Content::Simple(simple) => (Some(id), Some(*simple)),
+                _ => (Some(id), None),
+            },
+        };
+        if let Some(simple) = simple {
+            if frame.has_children {
+                return None;
+            }
+            let at = frame.content_start.min(offset);
+            let value = text_content(&self.xml[at..offset]);
+            return match xsd.check_value(simple, &value) {
+                Err(err) => Some(("invalid-value", at, err)),
+                Ok(()) => match &element.fixed {
+                    Some(fixed) if value != *fixed => Some(("invalid-value", at, format!("<{}> must contain '{}'", element.name, fixed))),
+                    _ => None,
+                },
+            };
+        }
+        let id = complex?;
+        match (&xsd.automata[id], &xsd.complex[id].content) {
+            (Some(automaton), _) if !frame.states.contains(&automaton.accept) => {
+                let expected = xsd.expected(automaton, &frame.states, frame.name);
+                Some(("missing-element", offset, format!("<{}> ends too early; expected {}", element.name, expected)))
+            }
+            (None, Content::All(members)) => {
+                let missing: Vec<String> = members
+                    .iter()
+                    .zip(&frame.seen)
+                    .filter(|&(&(_, required), &seen)| required && seen == 0)
+                    .map(|(&(decl, _), _)| format!("<{}>", xsd.decls[decl].name))
+                    .collect();
+                (!missing.is_empty()).then(|| ("missing-element", offset, format!("<{}> lacks {}", element.name, missing.join(", "))))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Snapshot tests over `tests/corpus`, small samples of formats met in the
+//! wild. For each sample, the stats report, the schema outline and drafts,
+//! and the element tree as navigated in the TUI are compared with the files in
+//! `tests/snapshots`, as is the DocBook sample in the reading view. The RSS
+//! sample is also read through a `Handler`, the catalog sample, which uses an
+//! ID twice, is validated against its DTD, and locale-formatted numbers are
+//! checked. After an intended change in output, rewrite the snapshots with
+//! `XMZ_BLESS=1 cargo test --test corpus` and review the diff.
+
+use std::fmt::Write as _;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use xmz::dom::Document;
+use xmz::dtd::Dtd;
+use xmz::parser::{Continue, Handler, parse_with_handler};
+use xmz::schema::{Draft, Schema};
+use xmz::value::NumberFormat;
+use xmz::xml::{Node, XmlExplorer};
+use xmz::xsd::Xsd;
+
+fn corpus(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus").join(name)
+}
+
+/// Compares `actual` with `tests/snapshots/<name>`, or writes it there when
+/// `XMZ_BLESS` is set.
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(name);
+    if std::env::var_os("XMZ_BLESS").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {} (run with XMZ_BLESS=1 to create it)", path.display(), err));
+    if expected != actual {
+        let mut diff = String::new();
+        let (expected, actual): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+        for i in 0..expected.len().max(actual.len()) {
+            match (expected.get(i), actual.get(i)) {
+                (Some(e), Some(a)) if e == a => {}
+                (e, a) => {
+                    if let Some(e) = e {
+                        writeln!(diff, "{:>4} - {}", i + 1, e).unwrap();
+                    }
+                    if let Some(a) = a {
+                        writeln!(diff, "{:>4} + {}", i + 1, a).unwrap();
+                    }
+                }
+            }
+        }
+        panic!("{} differs (run with XMZ_BLESS=1 to accept):\n{}", path.display(), diff);
+    }
+}
+
+/// Drops colors and the figures that depend on timing from a stats report.
+fn normalize_stats(report: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = report.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c.is_ascii_alphabetic());
+        } else {
+            plain.push(c);
+        }
+    }
+    let mut out = String::new();
+    for line in plain.lines() {
+        if line.starts_with("Processing speed: ") {
+            out.push_str("Processing speed: [speed]");
+        } else if let Some(at) = line.find(" tags in ").filter(|_| line.starts_with("Processed ")) {
+            out.push_str(&line[..at]);
+            out.push_str(" tags in [elapsed]");
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn stats(name: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_xmz"))
+        .args(["--progress", "none", "stats"])
+        .arg(corpus(name))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "xmz stats {}: {}", name, String::from_utf8_lossy(&output.stderr));
+    normalize_stats(&String::from_utf8(output.stdout).unwrap())
+}
+
+fn outline(schema: &Schema) -> String {
+    let mut out = Vec::new();
+    schema.write_outline(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn draft(schema: &Schema, draft: Draft) -> String {
+    let mut out = Vec::new();
+    schema.write_draft(&mut out, draft).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Every element, indented by depth, as the navigator lists it: tag,
+/// attributes, text and byte span.
+fn children(explorer: &mut XmlExplorer) -> String {
+    fn walk<'a>(explorer: &mut XmlExplorer<'a>, node: &Node<'a>, depth: usize, out: &mut String) {
+        write!(out, "{}<{}>", "  ".repeat(depth), node.tag).unwrap();
+        for (name, value) in explorer.attributes(node) {
+            write!(out, " @{}={:?}", name, value).unwrap();
+        }
+        if let Some(text) = node.text {
+            write!(out, " {:?}", text).unwrap();
+        }
+        writeln!(out, " {}..{}", node.span.start, node.span.end).unwrap();
+        for child in explorer.children(node) {
+            walk(explorer, &child, depth + 1, out);
+        }
+    }
+    let mut out = String::new();
+    if let Some(root) = explorer.root() {
+        walk(explorer, &root, 0, &mut out);
+    }
+    out
+}
+
+fn check(name: &str) {
+    let xml = std::fs::read_to_string(corpus(name)).unwrap();
+    assert_snapshot(&format!("{}.stats", name), &stats(name));
+    let schema = Schema::infer(&xml);
+    assert_snapshot(&format!("{}.outline", name), &outline(&schema));
+    let xsd = draft(&schema, Draft::Xsd);
+    let problems = Xsd::parse(&xsd).unwrap().validate(&xml);
+    assert!(problems.is_empty(), "{}: not valid against its own draft: {:?}", name, problems);
+    assert_snapshot(&format!("{}.xsd", name), &xsd);
+    assert_snapshot(&format!("{}.rng", name), &draft(&schema, Draft::RelaxNg));
+    let streamed = children(&mut XmlExplorer::new(&xml));
+    let mut indexed = XmlExplorer::new(&xml);
+    indexed.ensure_index();
+    assert_eq!(streamed, children(&mut indexed), "{}: navigating with and without an index differs", name);
+    assert_snapshot(&format!("{}.children", name), &streamed);
+    let dom = Document::parse(&xml);
+    let elements = dom.descendants(dom.root()).filter(|&id| dom.name(id).is_some()).count();
+    assert_eq!(elements, streamed.lines().count(), "{}: the DOM has other elements than the navigator", name);
+    let written = dom.to_xml();
+    assert_eq!(Document::parse(&written).to_xml(), written, "{}: the DOM does not read back what it writes", name);
+}
+
+#[test]
+fn rss() {
+    check("feed.rss");
+}
+
+#[test]
+fn svg() {
+    check("drawing.svg");
+}
+
+#[test]
+fn soap() {
+    check("envelope.xml");
+}
+
+#[test]
+fn maven_pom() {
+    check("pom.xml");
+}
+
+#[test]
+fn gpx() {
+    check("track.gpx");
+}
+
+#[test]
+fn ooxml_part() {
+    check("document.xml");
+}
+
+#[test]
+fn docbook() {
+    check("book.xml");
+}
+
+#[test]
+fn docbook_reading() {
+    let script = std::env::temp_dir().join(format!("xmz-reading-{}.txt", std::process::id()));
+    std::fs::write(&script, "reading\nsnapshot\nmarkers\nnext-section\nenter\nsnapshot\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_xmz"))
+        .args(["--progress", "none", "tui"])
+        .arg(corpus("book.xml"))
+        .arg("--script")
+        .arg(&script)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&script).unwrap();
+    assert!(output.status.success(), "xmz tui book.xml: {}", String::from_utf8_lossy(&output.stderr));
+    assert_snapshot("book.xml.reading", &String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn kml() {
+    check("places.kml");
+}
+
+#[test]
+fn handler_cdata() {
+    // Without a `cdata` method, CDATA content arrives as text.
+    struct Texts(Vec<String>);
+    impl<'a> Handler<'a> for Texts {
+        fn text(&mut self, text: &'a str) -> ControlFlow<()> {
+            self.0.push(text.to_string());
+            Continue(())
+        }
+    }
+    let xml = std::fs::read_to_string(corpus("feed.rss")).unwrap();
+    let mut texts = Texts(Vec::new());
+    parse_with_handler(&xml, &mut texts);
+    assert!(texts.0.iter().any(|text| text == "<p>Why we stopped building <b>trees</b> for every file.</p>"), "no CDATA text in {:?}", texts.0);
+}
+
+#[test]
+fn locale_numbers() {
+    let de = NumberFormat::for_locale("de").unwrap();
+    assert_eq!(de.parse("1.234,56"), Some(1234.56));
+    assert_eq!(de.parse("-12.345.678"), Some(-12345678.0));
+    // Misplaced grouping separators make a value invalid, not larger.
+    assert_eq!(de.parse("12.50"), None);
+    assert_eq!(de.parse("1.2.3"), None);
+    assert_eq!(de.parse("1,5.000"), None);
+    let en = NumberFormat::for_locale("en").unwrap();
+    assert_eq!(en.parse("1,234.5"), Some(1234.5));
+    assert_eq!(en.parse("1.500,000"), None);
+}
+
+#[test]
+fn dtd_duplicate_id() {
+    let xml = std::fs::read_to_string(corpus("catalog.xml")).unwrap();
+    let dtd = Dtd::parse(&xml).unwrap().unwrap();
+    let problems = Xsd::from_dtd(&dtd).validate(&xml);
+    let found: Vec<_> = problems.iter().map(|d| (d.code, d.line, d.message.as_str())).collect();
+    assert_eq!(found, [("duplicate-id", 15, "ID 'b1' is already defined")]);
+}
@@ -0,0 +1,375 @@
+//! The tokenizer behind xmz, usable on its own in embedded and sandboxed
+//! code: it needs only `core`, does no I/O and never allocates. Tokens
+//! borrow the input, and attributes and entities are decoded on demand.
+//!
+//! Streaming from readers, limits, and conveniences that allocate (attribute
+//! vectors, decoded text) live in `xmz::parser`, on top of this crate.
+//!
+//! Build without the default `std` feature for `no_std` targets, e.g.
+//! `cargo check -p xmz-core --no-default-features --target thumbv7em-none-eabihf`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::ops::{ControlFlow, Range};
+use memchr::{memchr, memmem};
+
+pub use core::ops::ControlFlow::{Break, Continue};
+
+#[derive(Debug)]
+pub enum Token<'a> {
+    StartTag(&'a str, &'a str), // name, attributes
+    EndTag(&'a str),
+    Text(&'a str),
+    /// The content of a CDATA section, as written; its span is the whole
+    /// section.
+    CData(&'a str),
+}
+
+/// Tokenizes a complete document, passing each token with the byte range it
+/// occupies in `xml`. A self-closing tag yields a `StartTag` spanning the
+/// whole tag followed by an `EndTag` with an empty range at its end.
+pub fn tokenize_all<'a, F>(xml: &'a str, mut on_token: F)
+where
+    F: FnMut(Token<'a>, Range<usize>) -> ControlFlow<()>,
+{
+    let _ = tokenize(xml, true, &mut on_token);
+}
+
+/// Tokenizes `xml`. Unless `last` is set, stops before a token that may continue
+/// past the end of `xml` and returns how many bytes were fully consumed, so
+/// that input arriving in pieces can be fed as it comes.
+pub fn tokenize<'a, F>(xml: &'a str, last: bool, on_token: &mut F) -> ControlFlow<(), usize>
+where
+    F: FnMut(Token<'a>, Range<usize>) -> ControlFlow<()>,
+{
+    let bytes = xml.as_bytes();
+    let len = bytes.len();
+    let mut pos = 0;
+
+    while pos < len {
+        // Fast skip whitespace using memchr
+        while pos < len {
+            let b = unsafe { *bytes.get_unchecked(pos) };
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            pos += 1;
+        }
+
+        if pos >= len {
+            break;
+        }
+
+        let current_byte = unsafe { *bytes.get_unchecked(pos) };
+        if current_byte == b'<' {
+            // Too short to tell which kind of markup this is.
+            if !last && (pos + 4 > len || b"<![CDATA[".starts_with(&bytes[pos..])) {
+                return Continue(pos);
+            }
+            if pos + 1 < len && unsafe { *bytes.get_unchecked(pos + 1) } == b'/' {
+                let start = pos + 2;
+                if let Some(rel) = memchr(b'>', &bytes[start..]) {
+                    let end_pos = start + rel;
+                    let name = unsafe { xml.get_unchecked(start..end_pos) };
+                    if on_token(Token::EndTag(name), pos..end_pos + 1).is_break() {
+                        return Break(());
+                    }
+                    pos = end_pos + 1;
+                } else if !last {
+                    return Continue(pos);
+                } else {
+                    break;
+                }
+            } else if pos + 1 < len && unsafe { *bytes.get_unchecked(pos + 1) } == b'?' {
+                // Processing instruction or XML declaration: not an element.
+                match memmem::find(&bytes[pos + 2..], b"?>") {
+                    Some(rel) => pos += 2 + rel + 2,
+                    None if !last => return Continue(pos),
+                    None => pos = len,
+                }
+            } else if bytes[pos..].starts_with(b"<!--") {
+                // Comments may contain '>', so look for the real terminator.
+                match memmem::find(&bytes[pos + 4..], b"-->") {
+                    Some(rel) => pos += 4 + rel + 3,
+                    None if !last => return Continue(pos),
+                    None => pos = len,
+                }
+            } else if bytes[pos..].starts_with(b"<![CDATA[") {
+                // Likewise for CDATA sections, whose content may hold '<'.
+                match memmem::find(&bytes[pos + 9..], b"]]>") {
+                    Some(rel) => {
+                        let content = unsafe { xml.get_unchecked(pos + 9..pos + 9 + rel) };
+                        if on_token(Token::CData(content), pos..pos + 9 + rel + 3).is_break() {
+                            return Break(());
+                        }
+                        pos += 9 + rel + 3;
+                    }
+                    None if !last => return Continue(pos),
+                    None => pos = len,
+                }
+            } else if bytes[pos..].starts_with(b"<!DOCTYPE") {
+                // The internal subset holds declarations with their own '>'.
+                match doctype_len(&bytes[pos..]) {
+                    Some(doctype) => pos += doctype,
+                    None if !last => return Continue(pos),
+                    None => pos = len,
+                }
+            } else if pos + 3 < len && unsafe { *bytes.get_unchecked(pos + 1) } == b'!' {
+                let mut end_pos = pos + 2;
+                while end_pos < len && unsafe { *bytes.get_unchecked(end_pos) } != b'>' {
+                    end_pos += 1;
+                }
+                if end_pos >= len && !last {
+                    return Continue(pos);
+                }
+                pos = if end_pos < len { end_pos + 1 } else { len };
+            } else {
+                let start = pos + 1;
+                if let Some(rel) = memchr(b'>', &bytes[start..]) {
+                    let end_pos = start + rel;
+                    let is_self_closing = end_pos > start && unsafe { *bytes.get_unchecked(end_pos - 1) } == b'/';
+                    let mut name_end = start;
+                    while name_end < end_pos {
+                        let byte = unsafe { *bytes.get_unchecked(name_end) };
+                        if byte <= b' ' || byte == b'/' {
+                            break;
+                        }
+                        name_end += 1;
+                    }
+                    let name = unsafe { xml.get_unchecked(start..name_end) };
+
+                    let attrs_start = name_end;
+                    let attrs_end = if is_self_closing { end_pos - 1 } else { end_pos };
+                    let attrs = unsafe { xml.get_unchecked(attrs_start..attrs_end) };
+
+                    if on_token(Token::StartTag(name, attrs), pos..end_pos + 1).is_break() {
+                        return Break(());
+                    }
+                    if is_self_closing && on_token(Token::EndTag(name), end_pos + 1..end_pos + 1).is_break() {
+                        return Break(());
+                    }
+                    pos = end_pos + 1;
+                } else if !last {
+                    return Continue(pos);
+                } else {
+                    break;
+                }
+            }
+        } else {
+            let start = pos;
+            let end_pos = match memchr(b'<', &bytes[start..]) {
+                Some(rel) => start + rel,
+                // The text may continue in the next chunk.
+                None if !last => return Continue(start),
+                None => len,
+            };
+            if end_pos > start {
+                let mut t_start = start;
+                let mut t_end = end_pos;
+                while t_start < t_end && unsafe { *bytes.get_unchecked(t_start) }.is_ascii_whitespace() {
+                    t_start += 1;
+                }
+                while t_end > t_start && unsafe { *bytes.get_unchecked(t_end - 1) }.is_ascii_whitespace() {
+                    t_end -= 1;
+                }
+                if t_end > t_start {
+                    let text = unsafe { xml.get_unchecked(t_start..t_end) };
+                    if on_token(Token::Text(text), t_start..t_end).is_break() {
+                        return Break(());
+                    }
+                }
+            }
+            pos = end_pos;
+        }
+    }
+    Continue(pos.min(len))
+}
+
+/// A visitor for `parse_with_handler`: a method per kind of node, each a
+/// no-op by default, so a handler implements only those it needs. Every
+/// `&'a str` borrows the input. Returning `Break` from any method stops the
+/// parse.
+pub trait Handler<'a> {
+    /// An element starts. A self-closing element also gets `end_element`.
+    fn start_element(&mut self, name: &'a str, attributes: Attributes<'a>) -> ControlFlow<()> {
+        let _ = (name, attributes);
+        Continue(())
+    }
+
+    fn end_element(&mut self, name: &'a str) -> ControlFlow<()> {
+        let _ = name;
+        Continue(())
+    }
+
+    /// Text between markup, trimmed, with its entities as written.
+    fn text(&mut self, text: &'a str) -> ControlFlow<()> {
+        let _ = text;
+        Continue(())
+    }
+
+    /// The content of a CDATA section, as written. Unless overridden it is
+    /// passed on to `text`, for handlers that take both alike.
+    fn cdata(&mut self, cdata: &'a str) -> ControlFlow<()> {
+        self.text(cdata)
+    }
+
+    /// What is between `<!--` and `-->`.
+    fn comment(&mut self, comment: &'a str) -> ControlFlow<()> {
+        let _ = comment;
+        Continue(())
+    }
+
+    /// A processing instruction `<?target data?>`; the XML declaration is
+    /// not one.
+    fn pi(&mut self, target: &'a str, data: &'a str) -> ControlFlow<()> {
+        let _ = (target, data);
+        Continue(())
+    }
+}
+
+/// Parses a complete document, calling `handler` for each node in document
+/// order. The DOCTYPE is skipped.
+pub fn parse_with_handler<'a, H: Handler<'a>>(xml: &'a str, handler: &mut H) {
+    // Comments and processing instructions are not tokens; they are found
+    // in the gaps the tokenizer leaves between tokens.
+    let mut from = 0;
+    let flow = tokenize(xml, true, &mut |token, span: Range<usize>| {
+        skipped_markup(xml, from..span.start, handler)?;
+        from = span.end;
+        match token {
+            Token::StartTag(name, attrs) => handler.start_element(name, attributes(attrs)),
+            Token::EndTag(name) => handler.end_element(name),
+            Token::Text(text) => handler.text(text),
+            Token::CData(cdata) => handler.cdata(cdata),
+        }
+    });
+    if flow.is_continue() {
+        let _ = skipped_markup(xml, from..xml.len(), handler);
+    }
+}
+
+/// Passes the comments and processing instructions in `gap`, which holds
+/// only whitespace and markup the tokenizer skips, to `handler`.
+fn skipped_markup<'a, H: Handler<'a>>(xml: &'a str, gap: Range<usize>, handler: &mut H) -> ControlFlow<()> {
+    let bytes = xml.as_bytes();
+    let mut pos = gap.start;
+    while let Some(rel) = memchr(b'<', &bytes[pos..gap.end]) {
+        let at = pos + rel;
+        let rest = &bytes[at..gap.end];
+        pos = if rest.starts_with(b"<!--") {
+            let end = memmem::find(&rest[4..], b"-->").map_or(gap.end, |rel| at + 4 + rel);
+            handler.comment(&xml[at + 4..end])?;
+            (end + 3).min(gap.end)
+        } else if rest.starts_with(b"<?") {
+            let end = memmem::find(&rest[2..], b"?>").map_or(gap.end, |rel| at + 2 + rel);
+            let body = &xml[at + 2..end];
+            let (target, data) = body.split_at(body.find(|c: char| c.is_ascii_whitespace()).unwrap_or(body.len()));
+            if target != "xml" {
+                handler.pi(target, data.trim_start())?;
+            }
+            (end + 2).min(gap.end)
+        } else if rest.starts_with(b"<!DOCTYPE") {
+            doctype_len(rest).map_or(gap.end, |len| at + len)
+        } else if rest.starts_with(b"<![CDATA[") {
+            memmem::find(&rest[9..], b"]]>").map_or(gap.end, |rel| at + 9 + rel + 3)
+        } else {
+            memchr(b'>', rest).map_or(gap.end, |rel| at + rel + 1)
+        };
+    }
+    Continue(())
+}
+
+/// Length of the DOCTYPE declaration at the start of `bytes`, up to the `>`
+/// after its internal subset, skipping quoted literals, comments and
+/// processing instructions; `None` if it does not end in `bytes`.
+pub fn doctype_len(bytes: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    let mut in_subset = false;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            quote @ (b'"' | b'\'') => pos += 1 + memchr(quote, &bytes[pos + 1..])?,
+            b'<' if in_subset && bytes[pos..].starts_with(b"<!--") => pos += 4 + memmem::find(&bytes[pos + 4..], b"-->")? + 2,
+            b'<' if in_subset && bytes[pos..].starts_with(b"<?") => pos += 2 + memmem::find(&bytes[pos + 2..], b"?>")? + 1,
+            b'[' => in_subset = true,
+            b']' => in_subset = false,
+            b'>' if !in_subset => return Some(pos + 1),
+            _ => {}
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// The name and raw value of each attribute in a raw attribute string (as
+/// found in `Token::StartTag`), in order. Values keep their entities.
+pub fn attributes(attrs: &str) -> Attributes<'_> {
+    Attributes { attrs, pos: 0 }
+}
+
+#[derive(Debug, Clone)]
+pub struct Attributes<'a> {
+    attrs: &'a str,
+    pos: usize,
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let attrs = self.attrs;
+        let bytes = attrs.as_bytes();
+        let mut pos = self.pos;
+        while pos < bytes.len() {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let key_start = pos;
+            while pos < bytes.len() && bytes[pos] != b'=' && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let key = &attrs[key_start..pos];
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= bytes.len() || bytes[pos] != b'=' {
+                if pos == key_start {
+                    pos += 1;
+                }
+                continue;
+            }
+            pos += 1;
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && (bytes[pos] == b'"' || bytes[pos] == b'\'') {
+                let quote = bytes[pos];
+                let value_start = pos + 1;
+                let value_end = memchr(quote, &bytes[value_start..]).map_or(bytes.len(), |r| value_start + r);
+                self.pos = value_end + 1;
+                return Some((key, &attrs[value_start..value_end]));
+            }
+        }
+        self.pos = pos;
+        None
+    }
+}
+
+/// The character an entity or character reference stands for, given what is
+/// between `&` and `;` (`amp`, `#38`, `#x26`); `None` for other entities.
+pub fn decode_entity(name: &str) -> Option<char> {
+    let c = match name {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        "apos" => '\'',
+        name => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => name.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some(c)
+}